@@ -0,0 +1,60 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn main() {
+    // Embed the git hash so that bug reports can be matched to a commit.
+    let git_hash = git(&["rev-parse", "--short", "HEAD"]).unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=ERDB_GIT_HASH={}", git_hash);
+
+    // HEAD only changes on a checkout, a commit moves the branch it points at.
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]).map(PathBuf::from) {
+        let head = git_dir.join("HEAD");
+        let mut watched = vec![head.clone(), git_dir.join("packed-refs")];
+        if let Some(branch) = fs::read_to_string(&head)
+            .ok()
+            .and_then(|head| head.trim().strip_prefix("ref: ").map(str::to_owned))
+        {
+            watched.push(git_dir.join(branch));
+        }
+        // A missing file would rerun the build script on every build
+        for path in watched.iter().filter(|path| path.exists()) {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+
+    // The probe-rs version the binary is built with, from the lock file.
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_else(|_| ".".to_owned());
+    let lock_file = Path::new(&manifest_dir).join("Cargo.lock");
+    let probe_rs_version = fs::read_to_string(&lock_file)
+        .ok()
+        .and_then(|lock| locked_version(&lock, "probe-rs"))
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=ERDB_PROBE_RS_VERSION={}", probe_rs_version);
+    if lock_file.exists() {
+        println!("cargo:rerun-if-changed={}", lock_file.display());
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_owned())
+}
+
+// The version of the `[[package]]` with the name in a Cargo.lock.
+fn locked_version(lock: &str, name: &str) -> Option<String> {
+    let name_line = format!("name = \"{}\"", name);
+    let mut lines = lock.lines().map(str::trim);
+    lines.find(|line| *line == name_line)?;
+    lines
+        .next()?
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')
+        .map(str::to_owned)
+}
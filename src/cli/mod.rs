@@ -5,6 +5,13 @@ use super::{
     },
     debugger::DebugHandler,
 };
+use crate::debugger::breakpoints::{BreakpointState, SavedBreakpoint};
+use crate::debugger::memory_usage::MemoryUsage;
+use crate::debugger::stepping::InstructionStep;
+use crate::debugger::symbols::ModuleInfo;
+use crate::debugger::test_run::TestEvent;
+use crate::debugger::watch_break::WatchBreak;
+use crate::debugger::watchpoints::{format_value, Watchpoint};
 use crate::debugger::StackFrame;
 use crate::debugger::Variable;
 use anyhow::{anyhow, Result};
@@ -38,6 +45,11 @@ pub fn debug_mode(opt: super::Opt) -> Result<()> {
     debugger_th.join().expect("oops! the child thread panicked");
     reader_th.join().expect("oops! the child thread panicked");
 
+    // Commands piped in from a script end with the result of the tests
+    if let Some(exit_code) = cli.test_exit_code {
+        std::process::exit(exit_code);
+    }
+
     Ok(())
 }
 
@@ -60,6 +72,22 @@ fn command_reader(sender: Sender<Command>, receiver: Receiver<bool>) -> Result<(
                     continue;
                 }
 
+                // `break commands <id>` reads the commands one per line until `end`
+                let line = match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+                    ["break" | "breakpoints", "commands", id] => {
+                        match read_breakpoint_commands(&mut rl) {
+                            Some(commands) => {
+                                format!("break commands {} {}", id, commands.join("; "))
+                            }
+                            None => {
+                                println!("Cancelled");
+                                continue;
+                            }
+                        }
+                    }
+                    _ => line,
+                };
+
                 let request = match cmd_parser.parse_command(line.as_ref()) {
                     Ok(cmd) => cmd,
                     Err(err) => {
@@ -92,10 +120,25 @@ fn command_reader(sender: Sender<Command>, receiver: Receiver<bool>) -> Result<(
     }
 }
 
+/// Returns None if the input is interrupted.
+fn read_breakpoint_commands(rl: &mut Editor<()>) -> Option<Vec<String>> {
+    println!("Enter the commands, one per line, end with `end`. A last `continue` resumes the core");
+    let mut commands = vec![];
+    loop {
+        match rl.readline("> ").ok()?.trim() {
+            "end" => return Some(commands),
+            "" => (),
+            command => commands.push(command.to_owned()),
+        }
+    }
+}
+
 struct Cli {
     debug_sender: Sender<DebugRequest>,
     receiver: Receiver<Command>,
     cli_sender: Sender<bool>,
+    // The exit code of the process, set when a test run finishes.
+    test_exit_code: Option<i32>,
 }
 
 impl Cli {
@@ -108,6 +151,7 @@ impl Cli {
             debug_sender: debug_sender,
             receiver: receiver,
             cli_sender: cli_sender,
+            test_exit_code: None,
         }
     }
 
@@ -141,13 +185,56 @@ impl Cli {
             DebugEvent::Halted {
                 pc,
                 reason,
-                hit_breakpoint_ids: _,
-            } => self.handle_halted_event(pc, reason),
+                hit_breakpoint_ids,
+                description,
+                resume: _,
+            } => self.handle_halted_event(pc, reason, hit_breakpoint_ids, description),
+            DebugEvent::Output {
+                category: _,
+                output,
+            } => self.handle_output_event(output),
+            DebugEvent::Breakpoint {
+                reason: _,
+                breakpoint: _,
+            } => (),
+            DebugEvent::Progress { title, done, total } => {
+                if total > 0 && (done == total || done % (total / 4).max(1) == 0) {
+                    println!("{}: {}%", title, (done as u64 * 100) / total as u64);
+                }
+            }
+            DebugEvent::Test { event } => {
+                if let TestEvent::Finished { exit_code, .. } = event {
+                    self.test_exit_code = Some(exit_code);
+                }
+                println!("{}", event);
+            }
+            DebugEvent::Reset { pc, halted } => match halted {
+                true => println!("Target reset detected, halted at {:#010x}", pc),
+                false => println!("Target reset detected, resumed from {:#010x}", pc),
+            },
         };
     }
 
-    fn handle_halted_event(&self, pc: u32, reason: HaltReason) {
+    fn handle_halted_event(
+        &self,
+        pc: u32,
+        reason: HaltReason,
+        hit_breakpoint_ids: Option<Vec<u32>>,
+        description: Option<String>,
+    ) {
         println!("Core halted at pc: {:#010x}, reason: {:?}", pc, reason);
+        if let Some(description) = description {
+            println!("{}", description);
+        }
+        if let Some(ids) = hit_breakpoint_ids {
+            for id in ids {
+                println!("Hit breakpoint {:#010x}", id);
+            }
+        }
+    }
+
+    fn handle_output_event(&self, output: String) {
+        println!("{}", output);
     }
 
     fn handle_response(&mut self, response: DebugResponse) -> Result<bool> {
@@ -158,34 +245,205 @@ impl Cli {
             DebugResponse::Attach => self.handle_attach_response(),
             DebugResponse::Status { status, pc } => self.handle_status_response(status, pc),
             DebugResponse::Continue => self.handle_continue_response(),
+            DebugResponse::ContinueFor { millis } => println!("Running for {} ms", millis),
+            DebugResponse::Finish { function } => self.handle_finish_response(function),
             DebugResponse::Step => self.handle_step_response(),
+            DebugResponse::History { entries } => {
+                for entry in &entries {
+                    println!("{}", entry);
+                }
+            }
+            DebugResponse::StopStats { stats } => println!("{}", stats),
+            DebugResponse::SetLogLevel { filter } => println!("Log filter: {}", filter),
+            DebugResponse::LogTail { lines } => {
+                for line in &lines {
+                    println!("{}", line);
+                }
+            }
+            DebugResponse::Breakpoints {
+                breakpoints,
+                disabled,
+            } => self.handle_breakpoints_response(breakpoints, disabled),
+            DebugResponse::VerifyBreakpoints { report } => {
+                println!("Breakpoints:");
+                for entry in &report {
+                    println!("    {}", entry);
+                }
+            }
+            DebugResponse::SaveBreakpoints => println!("Breakpoints saved"),
+            DebugResponse::CoreDump { size } => println!("Core dump written, {} bytes of memory", size),
+            DebugResponse::SnapshotState { caveat } => println!("Snapshot taken\nNote: {}", caveat),
+            DebugResponse::RestoreState { caveat } => println!("Snapshot restored\nNote: {}", caveat),
+            DebugResponse::Snapshots { snapshots } => {
+                println!("Snapshots:");
+                for (name, pc, size) in &snapshots {
+                    match pc {
+                        Some(pc) => println!("\t{}: pc {:#010x}, {} bytes", name, pc, size),
+                        None => println!("\t{}: {} bytes", name, size),
+                    };
+                }
+            }
+            DebugResponse::LoadBreakpoints { loaded, disabled } => {
+                println!("Loaded {} breakpoints", loaded);
+                for location in &disabled {
+                    println!("\tDisabled, no longer resolves: {}", location);
+                }
+            }
+            DebugResponse::IgnoreBreakpoint => println!("Ignore count set"),
+            DebugResponse::BreakpointCommands => println!("Breakpoint commands set"),
+            DebugResponse::Until => self.handle_step_response(),
+            DebugResponse::StepInstruction { steps } => self.handle_step_instruction_response(steps),
             DebugResponse::Halt => self.handle_halt_response(),
             DebugResponse::SetBinary => self.handle_set_binary_response(),
+            DebugResponse::SetSymbolFiles => self.handle_set_symbol_files_response(),
+            DebugResponse::Modules { modules } => self.handle_modules_response(modules),
+            DebugResponse::MemoryUsage { memory_usage } => {
+                self.handle_memory_usage_response(memory_usage)
+            }
             DebugResponse::Flash => self.handle_flash_response(),
             DebugResponse::Reset => self.handle_reset_response(),
             DebugResponse::Read { address, value } => self.handle_read_response(address, value),
+            DebugResponse::WriteWord { value } => println!("Memory written: {:#010x}", value),
+            DebugResponse::WriteRegister { value } => println!("Register written: {:#010x}", value),
             DebugResponse::StackTrace { stack_trace } => {
                 self.handle_stack_trace_response(stack_trace)
             }
             DebugResponse::SetProbeNumber => self.handle_set_probe_number_response(),
-            DebugResponse::SetChip => self.handle_set_chip_response(),
+            DebugResponse::SetForceClaim => println!("Force claim set"),
+            DebugResponse::SetChip { chip } => self.handle_set_chip_response(chip),
+            DebugResponse::SetPolling => println!("Polling set"),
+            DebugResponse::SetHeapOptions => println!("Heap options set"),
+            DebugResponse::SetStepBreakpoints => println!("Breakpoints during step set"),
+            DebugResponse::SetCatchFunctions => println!("Catch functions set"),
+            DebugResponse::SetExceptionFilters => println!("Exception filters set"),
+            DebugResponse::SetInstructionTrace => println!("Instruction trace set"),
+            DebugResponse::SetLiveFlashReads => println!("Live flash reads set"),
+            DebugResponse::SetRegionAnnotations => println!("Region annotations set"),
+            DebugResponse::SetTestRun => println!("Test run set"),
+            DebugResponse::SetPeripheralWrites => println!("Peripheral writes set"),
+            DebugResponse::SetCommandLine => println!("Semihosting command line set"),
+            DebugResponse::SetCoreClock => println!("Core clock set"),
+            DebugResponse::SetDisconnectAction => println!("Disconnect action set"),
+            DebugResponse::SetHaltOnReset => println!("Halt on reset set"),
+            DebugResponse::SetCheckFlashLayout => println!("Flash layout check set"),
+            DebugResponse::SetProbeTimeout => println!("Probe timeout set"),
+            DebugResponse::StepBack { pc } => println!("Stepped back to {:#010x}", pc),
+            DebugResponse::HeapStats { stats } => println!("{}", stats),
+            DebugResponse::ExceptionInfo { status } => println!("{}", status),
+            DebugResponse::Intrusiveness { report } => println!("{}", report),
+            DebugResponse::SelfTest { report } => println!("{}", report),
+            DebugResponse::CalibrateClock { .. }
+            | DebugResponse::TargetInfo { .. }
+            | DebugResponse::ReadFlash { .. }
+            | DebugResponse::ReloadConfig { .. }
+            | DebugResponse::BreakpointLocations { .. }
+            | DebugResponse::Disassemble { .. }
+            | DebugResponse::ReadMemory { .. }
+            | DebugResponse::WriteMemory { .. }
+            | DebugResponse::Restart { .. }
+            | DebugResponse::RestartFrame { .. }
+            | DebugResponse::GotoTargets { .. }
+            | DebugResponse::Completions { .. }
+            | DebugResponse::Goto { .. }
+            | DebugResponse::Source { .. }
+            | DebugResponse::TraceConfigure { .. }
+            | DebugResponse::TraceDisable
+            | DebugResponse::TraceRead { .. } => {
+                println!("{}", response.text())
+            }
+            DebugResponse::SetFormat => println!("Format set"),
+            DebugResponse::Formats { formats } => {
+                println!("Formats:");
+                for (path, format) in &formats {
+                    println!("\t{}: {}", path, format);
+                }
+            }
+            DebugResponse::ClearFormats => println!("Formats cleared"),
+            DebugResponse::SaveFormats => println!("Formats saved"),
+            DebugResponse::LoadFormats { loaded } => println!("Loaded {} formats", loaded),
+            DebugResponse::VectorTableInfo { info } => println!("{}", info),
+            DebugResponse::SectionMap { map } => println!("{}", map),
+            DebugResponse::LoadedSources { sources } => {
+                for path in sources {
+                    println!("{}", path.display());
+                }
+            }
+            DebugResponse::SetCoreDump => println!("Core dump set"),
             DebugResponse::Variable { variable } => self.handle_variable_response(variable),
             DebugResponse::Variables { variables } => self.handle_variables_response(variables),
-            DebugResponse::Registers { registers } => self.handle_registers_response(registers),
+            DebugResponse::ExportStopState { path, report } => match path {
+                Some(path) => println!("Stop state written to {:?}, {} bytes", path, report.len()),
+                None => println!("{}", report),
+            },
+            DebugResponse::Evaluate { variable }
+            | DebugResponse::SetVariable { variable }
+            | DebugResponse::SetExpression { variable } => {
+                println!("{}", variable.value_to_string())
+            }
+            DebugResponse::Registers { registers, regions } => {
+                self.handle_registers_response(registers, regions)
+            }
             DebugResponse::SetBreakpoint => self.handle_set_breakpoint_response(),
             DebugResponse::SetBreakpoints { breakpoints } => {
                 self.handle_set_breakpoints_response(breakpoints)
             }
             DebugResponse::ClearBreakpoint => self.handle_clear_breakpoint_response(),
             DebugResponse::ClearAllBreakpoints => self.handle_clear_all_breakpoints_response(),
+            DebugResponse::SetFunctionBreakpoints { breakpoints }
+            | DebugResponse::SetInstructionBreakpoints { breakpoints } => {
+                for breakpoint in breakpoints {
+                    match (breakpoint.id, breakpoint.message) {
+                        (Some(id), Some(message)) => {
+                            println!("Breakpoint {:#010x} set, {}", id, message)
+                        }
+                        (Some(id), None) => println!("Breakpoint {:#010x} set", id),
+                        (None, message) => {
+                            println!("Breakpoint not set: {}", message.unwrap_or_default())
+                        }
+                    }
+                }
+            }
+            DebugResponse::SetWatchpoint { watchpoint } => {
+                self.handle_set_watchpoint_response(watchpoint)
+            }
+            DebugResponse::Watchpoints { watchpoints } => {
+                self.handle_watchpoints_response(watchpoints)
+            }
+            DebugResponse::DeleteWatchpoint => self.handle_delete_watchpoint_response(),
+            DebugResponse::SetWatchBreak { watch } => match watch.value {
+                Some(value) => println!(
+                    "Watch {} set on `{}`, value: {}",
+                    watch.id, watch.expression, value
+                ),
+                None => println!("Watch {} set on `{}`", watch.id, watch.expression),
+            },
+            DebugResponse::WatchBreaks {
+                watches,
+                sample_interval_ms,
+            } => self.handle_watch_breaks_response(watches, sample_interval_ms),
+            DebugResponse::DeleteWatchBreak => println!("Watch deleted"),
+            DebugResponse::SetWatchSampling { warning } => match warning {
+                Some(warning) => println!("Watch sampling on: {}", warning),
+                None => println!("Watch sampling off"),
+            },
+            DebugResponse::DataBreakpointInfo { description, .. } => println!("{}", description),
+            DebugResponse::SetDataBreakpoints { breakpoints } => {
+                for breakpoint in breakpoints {
+                    match (breakpoint.id, breakpoint.message) {
+                        (Some(id), _) => println!("Watchpoint {} set", id),
+                        (None, message) => println!("Not set: {}", message.unwrap_or_default()),
+                    }
+                }
+            }
             DebugResponse::Code { pc, instructions } => self.handle_code_response(pc, instructions),
             DebugResponse::Stack {
                 stack_pointer,
                 stack,
-            } => self.handle_stack_response(stack_pointer, stack),
+                regions,
+            } => self.handle_stack_response(stack_pointer, stack, regions),
             DebugResponse::Error { message } => self.handle_error_response(message),
             DebugResponse::SetCWD => self.handle_set_cwd_response(),
-            DebugResponse::DAPStackFrames { stack_frames: _ } => {
+            DebugResponse::DAPStackFrames { .. } => {
                 error!("Unreachable");
                 return Err(anyhow!("Unreachable"));
             }
@@ -217,10 +475,64 @@ impl Cli {
         println!("Core is running");
     }
 
+    fn handle_finish_response(&self, function: String) {
+        println!("Run till exit from `{}`", function);
+    }
+
     fn handle_step_response(&self) {
         return ();
     }
 
+    fn handle_step_instruction_response(&self, steps: Vec<InstructionStep>) {
+        for step in &steps {
+            println!("{:#010x}: {}", step.pc, step.instruction);
+            for (name, old, new) in &step.changed_registers {
+                println!("\t{}: {:#010x} -> {:#010x}", name, old, new);
+            }
+        }
+    }
+
+    fn handle_breakpoints_response(
+        &self,
+        breakpoints: Vec<(u32, Breakpoint, BreakpointState)>,
+        disabled: Vec<SavedBreakpoint>,
+    ) {
+        println!("Breakpoints:");
+        for (address, breakpoint, state) in &breakpoints {
+            let location = match (&breakpoint.source, breakpoint.line) {
+                (Some(source), Some(line)) => format!(
+                    " {}:{}",
+                    source.name.clone().unwrap_or_default(),
+                    line
+                ),
+                _ => "".to_owned(),
+            };
+            println!(
+                "\t{:#010x}{}, hits: {}, ignore count: {}",
+                address, location, state.hit_count, state.ignore_count
+            );
+            if let Some(condition) = &state.condition {
+                println!("\t\tif {}", condition);
+            }
+            if let Some(hit_condition) = &state.hit_condition {
+                println!(
+                    "\t\thit condition {}, {} hits counted",
+                    hit_condition,
+                    hit_condition.hits()
+                );
+            }
+            if let Some(log_message) = &state.log_message {
+                println!("\t\tlog \"{}\"", log_message);
+            }
+            for command in &state.commands {
+                println!("\t\t> {}", command);
+            }
+        }
+        for saved in &disabled {
+            println!("\t{} (disabled)", saved.location());
+        }
+    }
+
     fn handle_halt_response(&self) {
         return ();
     }
@@ -229,6 +541,39 @@ impl Cli {
         println!("Binary file path set ");
     }
 
+    fn handle_set_symbol_files_response(&self) {
+        println!("Symbols loaded");
+    }
+
+    fn handle_modules_response(&self, modules: Vec<ModuleInfo>) {
+        println!("Modules:");
+        for module in modules {
+            match module.address_range {
+                Some(range) => println!(
+                    "\t{}: {:?} [{:#010x}, {:#010x})",
+                    module.id, module.path, range.start, range.end
+                ),
+                None => println!("\t{}: {:?} < no .text section >", module.id, module.path),
+            };
+            if let Some(range) = module.flash_range {
+                println!("\t   flash [{:#010x}, {:#010x})", range.start, range.end);
+            }
+            if let Some(build_id) = module.build_id {
+                println!("\t   build id {}", build_id);
+            }
+            if !module.has_debug_info {
+                println!("\t   < no debug information >");
+            }
+        }
+    }
+
+    fn handle_memory_usage_response(&self, memory_usage: Option<MemoryUsage>) {
+        match memory_usage {
+            Some(memory_usage) => println!("{}", memory_usage.summary()),
+            None => println!("No binary set"),
+        };
+    }
+
     fn handle_flash_response(&self) {
         println!("Flash successful");
     }
@@ -304,8 +649,8 @@ impl Cli {
         println!("Probe number set ");
     }
 
-    fn handle_set_chip_response(&self) {
-        println!("Chip set");
+    fn handle_set_chip_response(&self, chip: String) {
+        println!("Chip set to {}", chip);
     }
 
     fn handle_variable_response(&self, variable: Variable) {
@@ -345,10 +690,10 @@ impl Cli {
         }
     }
 
-    fn handle_registers_response(&self, registers: Vec<(String, u32)>) {
+    fn handle_registers_response(&self, registers: Vec<(String, u32)>, regions: Vec<String>) {
         println!("Registers:");
-        for (name, value) in &registers {
-            println!("\t{}: {:#010x}", name, value)
+        for ((name, value), region) in registers.iter().zip(&regions) {
+            println!("\t{}: {:#010x}{}", name, value, region)
         }
     }
 
@@ -368,6 +713,55 @@ impl Cli {
         println!("All hardware breakpoints cleared");
     }
 
+    fn handle_set_watchpoint_response(&self, watchpoint: Watchpoint) {
+        println!(
+            "Watchpoint {} set on `{}` at {:#010x}, value: {}",
+            watchpoint.id,
+            watchpoint.name,
+            watchpoint.address,
+            format_value(&watchpoint.value, watchpoint.endianness)
+        );
+    }
+
+    fn handle_watchpoints_response(&self, watchpoints: Vec<Watchpoint>) {
+        println!("Watchpoints:");
+        for watchpoint in &watchpoints {
+            println!(
+                "\t{}: `{}` at {:#010x}, {} bytes, {:?}",
+                watchpoint.id, watchpoint.name, watchpoint.address, watchpoint.size, watchpoint.kind
+            );
+        }
+    }
+
+    fn handle_delete_watchpoint_response(&self) {
+        println!("Watchpoint deleted");
+    }
+
+    fn handle_watch_breaks_response(
+        &self,
+        watches: Vec<WatchBreak>,
+        sample_interval_ms: Option<u64>,
+    ) {
+        match sample_interval_ms {
+            Some(ms) => println!("Watches, sampled every {} ms:", ms),
+            None => println!("Watches:"),
+        }
+        for watch in &watches {
+            println!(
+                "\t{}: `{}` = {}",
+                watch.id,
+                watch.expression,
+                watch.value.as_deref().unwrap_or("<not evaluated>")
+            );
+            for change in &watch.history {
+                println!(
+                    "\t\t{:#010x}: {} \u{2192} {}",
+                    change.pc, change.old, change.new
+                );
+            }
+        }
+    }
+
     fn handle_code_response(&self, pc: u32, instructions: Vec<(u32, String)>) {
         println!("Assembly Code");
         for (address, asm) in instructions {
@@ -379,13 +773,14 @@ impl Cli {
         }
     }
 
-    fn handle_stack_response(&self, stack_pointer: u32, stack: Vec<u32>) {
+    fn handle_stack_response(&self, stack_pointer: u32, stack: Vec<u32>, regions: Vec<String>) {
         println!("Current stack value:");
         for i in 0..stack.len() {
             println!(
-                "\t{:#010x}: {:#010x}",
+                "\t{:#010x}: {:#010x}{}",
                 stack_pointer as usize + i * 4,
-                stack[i]
+                stack[i],
+                regions[i]
             );
         }
     }
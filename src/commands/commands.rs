@@ -1,9 +1,22 @@
 use super::{debug_request::DebugRequest, Command};
+use crate::debugger::config::SymbolFile;
+use crate::debugger::core_dump::DumpRegions;
+use crate::debugger::formats::ValueFormat;
+use crate::debugger::semihosting::CommandLine;
+use crate::debugger::trap_catch::ExceptionFilter;
+use crate::debugger::watchpoints::WatchKind;
+use crate::debugger::write_policy::PeripheralWrites;
 
 use anyhow::{anyhow, Result};
 
 use std::path::PathBuf;
 
+/// The levels of the variables exported by `export` when no depth is given.
+const DEFAULT_EXPORT_DEPTH: u32 = 4;
+
+/// The lines printed by `log tail` when no count is given.
+const DEFAULT_LOG_TAIL: usize = 50;
+
 struct CommandInfo {
     pub name: &'static str,
     pub description: &'static str,
@@ -51,6 +64,22 @@ impl Commands {
                     description: "Prints the current code",
                     parser: |_args| Ok(DebugRequest::Code),
                 },
+                CommandInfo {
+                    name: "disassemble",
+                    description: "Disassemble instructions from an address: `disassemble <address> [<count>]`",
+                    parser: |args| match args.get(0) {
+                        Some(address) => Ok(DebugRequest::Disassemble {
+                            memory_reference: address.to_string(),
+                            offset: 0,
+                            instruction_offset: 0,
+                            instruction_count: match args.get(1) {
+                                Some(count) => parse_u32_from_str(count)? as usize,
+                                None => 16,
+                            },
+                        }),
+                        None => Err(anyhow!("Requires an address as a argument")),
+                    },
+                },
                 CommandInfo {
                     name: "clear-all-breakpoints",
                     description: "Removes all hardware breakpoints",
@@ -86,6 +115,127 @@ impl Commands {
                         Err(anyhow!("Requires a string as a argument"))
                     },
                 },
+                CommandInfo {
+                    name: "break",
+                    description: "Manage breakpoints: `break list`, `break verify`, `break ignore <id> <count>`, `break commands <id> [<command>; ...]`, `break save <path>` or `break load <path>`",
+                    parser: parse_break_command,
+                },
+                CommandInfo {
+                    name: "breakpoints",
+                    description: "Same as `break`",
+                    parser: parse_break_command,
+                },
+                CommandInfo {
+                    name: "breakpoint-locations",
+                    description: "List the lines of a source file where a breakpoint can go: `breakpoint-locations <file> <line> [<end line>]`",
+                    parser: |args| match (args.get(0), args.get(1)) {
+                        (Some(file), Some(line)) => Ok(DebugRequest::BreakpointLocations {
+                            source_file: file.to_string(),
+                            line: parse_u32_from_str(line)? as u64,
+                            column: None,
+                            end_line: match args.get(2) {
+                                Some(end_line) => Some(parse_u32_from_str(end_line)? as u64),
+                                None => None,
+                            },
+                            end_column: None,
+                        }),
+                        _ => Err(anyhow!("Requires a file and a line as arguments")),
+                    },
+                },
+                CommandInfo {
+                    name: "coredump",
+                    description: "Dump the registers and RAM to a file: `coredump <path> [flash | <address>:<size>...]`",
+                    parser: |args| {
+                        let path = match args.get(0) {
+                            Some(val) => PathBuf::from(val),
+                            None => return Err(anyhow!("Requires a file path as a argument")),
+                        };
+                        Ok(DebugRequest::CoreDump {
+                            path,
+                            regions: parse_dump_regions(&args[1..])?,
+                        })
+                    },
+                },
+                CommandInfo {
+                    name: "read-flash",
+                    description: "Read the flash to a file, continuing an interrupted read: `read-flash <path> [<address>:<size>]`",
+                    parser: |args| {
+                        let path = match args.get(0) {
+                            Some(val) => PathBuf::from(val),
+                            None => return Err(anyhow!("Requires a file path as a argument")),
+                        };
+                        let region = match parse_dump_regions(&args[1..])? {
+                            DumpRegions::Ranges(ranges) if ranges.len() == 1 => {
+                                Some(ranges[0].clone())
+                            }
+                            DumpRegions::Ram => None,
+                            _ => return Err(anyhow!("Expected a single <address>:<size>")),
+                        };
+                        Ok(DebugRequest::ReadFlash { path, region })
+                    },
+                },
+                CommandInfo {
+                    name: "snapshot",
+                    description: "Snapshot the registers and RAM: `snapshot save <name> [flash | <address>:<size>...]`, `snapshot restore <name>` or `snapshot list`",
+                    parser: |args| match (args.get(0), args.get(1)) {
+                        (Some(&"save"), Some(name)) => Ok(DebugRequest::SnapshotState {
+                            name: name.to_string(),
+                            regions: parse_dump_regions(&args[2..])?,
+                        }),
+                        (Some(&"restore"), Some(name)) => Ok(DebugRequest::RestoreState {
+                            name: name.to_string(),
+                        }),
+                        (Some(&"list"), _) => Ok(DebugRequest::Snapshots),
+                        _ => Err(anyhow!("Expected one of save <name>, restore <name> or list")),
+                    },
+                },
+                CommandInfo {
+                    name: "watchpoint",
+                    description: "Set a DWT watchpoint: `watchpoint <expr> [read|write|rw]`, `watchpoint list` or `watchpoint delete <id>`",
+                    parser: |args| {
+                        if args.len() > 0 {
+                            return match args[0] {
+                                "list" => Ok(DebugRequest::Watchpoints),
+                                "delete" => match args.get(1) {
+                                    Some(id) => Ok(DebugRequest::DeleteWatchpoint {
+                                        id: parse_u32_from_str(id)?,
+                                    }),
+                                    None => Err(anyhow!("Requires a watchpoint id as a argument")),
+                                },
+                                expression => Ok(DebugRequest::SetWatchpoint {
+                                    expression: expression.to_string(),
+                                    kind: match args.get(1) {
+                                        Some(kind) => kind.parse::<WatchKind>()?,
+                                        None => WatchKind::Write,
+                                    },
+                                }),
+                            };
+                        }
+                        Err(anyhow!("Requires a expression as a argument"))
+                    },
+                },
+                CommandInfo {
+                    name: "watch",
+                    description: "Halt when an expression changes: `watch break <expr>`, `watch list`, `watch delete <id>` or `watch sample <ms>|off`",
+                    parser: |args| match (args.get(0), args.get(1)) {
+                        (Some(&"break"), Some(_)) => Ok(DebugRequest::SetWatchBreak {
+                            expression: args[1..].join(" "),
+                        }),
+                        (Some(&"list"), _) => Ok(DebugRequest::WatchBreaks),
+                        (Some(&"delete"), Some(id)) => Ok(DebugRequest::DeleteWatchBreak {
+                            id: parse_u32_from_str(id)?,
+                        }),
+                        (Some(&"sample"), Some(&"off")) => {
+                            Ok(DebugRequest::SetWatchSampling { interval_ms: None })
+                        }
+                        (Some(&"sample"), Some(ms)) => Ok(DebugRequest::SetWatchSampling {
+                            interval_ms: Some(ms.parse::<u64>()?),
+                        }),
+                        _ => Err(anyhow!(
+                            "Expected one of break <expr>, list, delete <id> or sample <ms>|off"
+                        )),
+                    },
+                },
                 CommandInfo {
                     name: "registers",
                     description: "Print all register values",
@@ -107,6 +257,41 @@ impl Commands {
                     description: "Print all local variables",
                     parser: |_args| Ok(DebugRequest::Variables),
                 },
+                CommandInfo {
+                    name: "export",
+                    description: "Write the stack, variables and registers of the stop as JSON: `export [<file>] [<depth>]`, printed without a file",
+                    parser: |args| {
+                        let (path, depth) = match args {
+                            [] => (None, None),
+                            [arg] => match parse_u32_from_str(arg) {
+                                Ok(depth) => (None, Some(depth)),
+                                Err(_) => (Some(PathBuf::from(arg)), None),
+                            },
+                            [path, depth, ..] => {
+                                (Some(PathBuf::from(path)), Some(parse_u32_from_str(depth)?))
+                            }
+                        };
+                        Ok(DebugRequest::ExportStopState {
+                            path,
+                            depth: depth.unwrap_or(DEFAULT_EXPORT_DEPTH) as usize,
+                        })
+                    },
+                },
+                CommandInfo {
+                    name: "format",
+                    description: "Show a variable path in a format for the session: `format cfg.flags hex|bin|dec|char|default`, `format list`, `format clear`, `format save <file>` or `format load <file>`",
+                    parser: parse_format_command,
+                },
+                CommandInfo {
+                    name: "reload",
+                    description: "Read configuration files again without restarting the session: `reload svd|sourcemap|formatters|all`",
+                    parser: |args| match args.get(0) {
+                        Some(what) => Ok(DebugRequest::ReloadConfig {
+                            what: what.parse()?,
+                        }),
+                        None => Err(anyhow!("Requires svd, sourcemap, formatters or all as a argument")),
+                    },
+                },
                 CommandInfo {
                     name: "set-chip",
                     description: "Set chip model being used",
@@ -118,6 +303,102 @@ impl Commands {
                         Err(anyhow!("Requires a string as a argument"))
                     },
                 },
+                CommandInfo {
+                    name: "set-core-dump",
+                    description: "Debug a core dump instead of the target, `set-core-dump none` goes back to the probe",
+                    parser: |args| match args.get(0) {
+                        Some(&"none") => Ok(DebugRequest::SetCoreDump { path: None }),
+                        Some(path) => Ok(DebugRequest::SetCoreDump {
+                            path: Some(PathBuf::from(path)),
+                        }),
+                        None => Err(anyhow!("Requires a file path as a argument")),
+                    },
+                },
+                CommandInfo {
+                    name: "set-poll-interval",
+                    description: "Set how often the status of a running target is polled, in milliseconds",
+                    parser: |args| match args.get(0) {
+                        Some(interval) => Ok(DebugRequest::SetPolling {
+                            status_interval_ms: Some(parse_u32_from_str(interval)? as u64),
+                            low_power: None,
+                        }),
+                        None => Err(anyhow!("Requires a interval in milliseconds as a argument")),
+                    },
+                },
+                CommandInfo {
+                    name: "low-power",
+                    description: "Stop all probe traffic while the target runs: `low-power true|false`",
+                    parser: |args| match args.get(0) {
+                        Some(low_power) => Ok(DebugRequest::SetPolling {
+                            status_interval_ms: None,
+                            low_power: Some(parse_bool(low_power)?),
+                        }),
+                        None => Err(anyhow!("Requires a boolean as a argument")),
+                    },
+                },
+                CommandInfo {
+                    name: "set-heap-symbol",
+                    description: "Set the name of the allocator static used by `info heap`",
+                    parser: |args| match args.get(0) {
+                        Some(symbol) => Ok(DebugRequest::SetHeapOptions {
+                            symbol: Some(symbol.to_string()),
+                            stats_on_stop: None,
+                        }),
+                        None => Err(anyhow!("Requires a symbol name as a argument")),
+                    },
+                },
+                CommandInfo {
+                    name: "heap-on-stop",
+                    description: "Print the heap statistics every time the target stops: `heap-on-stop true|false`",
+                    parser: |args| match args.get(0) {
+                        Some(stats_on_stop) => Ok(DebugRequest::SetHeapOptions {
+                            symbol: None,
+                            stats_on_stop: Some(parse_bool(stats_on_stop)?),
+                        }),
+                        None => Err(anyhow!("Requires a boolean as a argument")),
+                    },
+                },
+                CommandInfo {
+                    name: "breakpoints-during-step",
+                    description: "Stop at or defer breakpoints hit in interrupt handlers while stepping: `breakpoints-during-step stop|defer`",
+                    parser: |args| match args.get(0) {
+                        Some(mode) => Ok(DebugRequest::SetStepBreakpoints {
+                            mode: mode.parse()?,
+                        }),
+                        None => Err(anyhow!("Requires stop or defer as a argument")),
+                    },
+                },
+                CommandInfo {
+                    name: "catch-functions",
+                    description: "Stop when the target reaches these functions, in addition to the panic and abort defaults: `catch-functions <name>...`",
+                    parser: |args| {
+                        Ok(DebugRequest::SetCatchFunctions {
+                            functions: args.iter().map(|arg| arg.to_string()).collect(),
+                        })
+                    },
+                },
+                CommandInfo {
+                    name: "catch-exceptions",
+                    description: "Stop at these faults and panics instead of the panic and hard fault defaults: `catch-exceptions [hardfault|busfault|usagefault|memmanage|panic]...`",
+                    parser: |args| {
+                        Ok(DebugRequest::SetExceptionFilters {
+                            filters: args
+                                .iter()
+                                .map(|arg| arg.parse())
+                                .collect::<Result<Vec<ExceptionFilter>>>()?,
+                        })
+                    },
+                },
+                CommandInfo {
+                    name: "probe-timeout",
+                    description: "Fail requests whose probe operations hang for longer than twice this many ms, 0 disables it",
+                    parser: |args| match args.get(0) {
+                        Some(timeout) => Ok(DebugRequest::SetProbeTimeout {
+                            timeout_ms: parse_u32_from_str(timeout)? as u64,
+                        }),
+                        None => Err(anyhow!("Requires a timeout in ms as a argument")),
+                    },
+                },
                 CommandInfo {
                     name: "set-probe-number",
                     description: "Set the probe number to use",
@@ -129,6 +410,16 @@ impl Commands {
                         Err(anyhow!("Requires a boolean as a argument"))
                     },
                 },
+                CommandInfo {
+                    name: "force-claim",
+                    description: "Wait for a probe held by another process to be released when attaching: `force-claim true|false`",
+                    parser: |args| match args.get(0) {
+                        Some(enabled) => Ok(DebugRequest::SetForceClaim {
+                            enabled: parse_bool(enabled)?,
+                        }),
+                        None => Err(anyhow!("Requires a boolean as a argument")),
+                    },
+                },
                 CommandInfo {
                     name: "stack-trace",
                     description: "Print stack trace",
@@ -152,6 +443,18 @@ impl Commands {
                         Err(anyhow!("Requires a boolean as a argument"))
                     },
                 },
+                CommandInfo {
+                    name: "write",
+                    description: "Write a 32 bit word to memory, confirming writes to peripheral registers: `write <address> <value>`",
+                    parser: |args| match args {
+                        [address, value] => Ok(DebugRequest::WriteWord {
+                            address: parse_u32_from_str(address)?,
+                            value: parse_u32_from_str(value)?,
+                            confirmed: true,
+                        }),
+                        _ => Err(anyhow!("Requires an address and a value as arguments")),
+                    },
+                },
                 CommandInfo {
                     name: "reset",
                     description: "Reset or reset and halt the core",
@@ -180,6 +483,20 @@ impl Commands {
                         })
                     },
                 },
+                CommandInfo {
+                    name: "restart",
+                    description: "Reset the core, flashing the binary first if it changed on disk",
+                    parser: |args| {
+                        let mut reset_and_halt = false;
+                        if args.len() > 0 {
+                            reset_and_halt = parse_bool(args[0])?;
+                        }
+
+                        Ok(DebugRequest::Restart {
+                            reset_and_halt: reset_and_halt,
+                        })
+                    },
+                },
                 CommandInfo {
                     name: "step",
                     description: "Step one assembly instruction",
@@ -197,8 +514,190 @@ impl Commands {
                 },
                 CommandInfo {
                     name: "continue",
-                    description: "Continue the program",
-                    parser: |_args| Ok(DebugRequest::Continue),
+                    description: "Continue the program, `continue 50ms` halts it again after 50 ms",
+                    parser: |args| match args.get(0) {
+                        Some(time) => Ok(DebugRequest::ContinueFor {
+                            millis: parse_millis(time)?,
+                        }),
+                        None => Ok(DebugRequest::Continue),
+                    },
+                },
+                CommandInfo {
+                    name: "finish",
+                    description: "Continue until the current function returns and print the returned value",
+                    parser: |_args| Ok(DebugRequest::Finish),
+                },
+                CommandInfo {
+                    name: "until",
+                    description: "Continue until a line greater than the current one is reached in the current frame",
+                    parser: |_args| Ok(DebugRequest::Until),
+                },
+                CommandInfo {
+                    name: "stepi",
+                    description: "Step n machine instructions, default 1",
+                    parser: |args| {
+                        Ok(DebugRequest::StepInstruction {
+                            count: parse_step_count(args)?,
+                            over_calls: false,
+                        })
+                    },
+                },
+                CommandInfo {
+                    name: "nexti",
+                    description: "Step n machine instructions stepping over calls, default 1",
+                    parser: |args| {
+                        Ok(DebugRequest::StepInstruction {
+                            count: parse_step_count(args)?,
+                            over_calls: true,
+                        })
+                    },
+                },
+                CommandInfo {
+                    name: "step-back",
+                    description: "Undo the register changes of the last stepped instruction, memory writes are not undone",
+                    parser: |_args| Ok(DebugRequest::StepBack),
+                },
+                CommandInfo {
+                    name: "restart-frame",
+                    description: "Run the current function again from the end of its prologue, memory writes are not undone",
+                    parser: |_args| Ok(DebugRequest::RestartFrame { frame_id: None }),
+                },
+                CommandInfo {
+                    name: "goto-targets",
+                    description: "List the addresses where a line of a source file starts: `goto-targets <file> <line>`",
+                    parser: |args| match (args.get(0), args.get(1)) {
+                        (Some(file), Some(line)) => Ok(DebugRequest::GotoTargets {
+                            source_file: file.to_string(),
+                            line: parse_u32_from_str(line)? as u64,
+                        }),
+                        _ => Err(anyhow!("Requires a file and a line as arguments")),
+                    },
+                },
+                CommandInfo {
+                    name: "goto",
+                    description: "Set the pc to an address without running anything: `goto <address>`",
+                    parser: |args| match args.get(0) {
+                        Some(address) => Ok(DebugRequest::Goto {
+                            address: parse_u32_from_str(address)?,
+                        }),
+                        None => Err(anyhow!("Requires an address as argument")),
+                    },
+                },
+                CommandInfo {
+                    name: "instruction-trace",
+                    description: "Record the last n stepped instructions for `step-back`, 0 disables it",
+                    parser: |args| match args.get(0) {
+                        Some(length) => Ok(DebugRequest::SetInstructionTrace {
+                            length: parse_u32_from_str(length)? as usize,
+                        }),
+                        None => Err(anyhow!("Requires a number of instructions as a argument")),
+                    },
+                },
+                CommandInfo {
+                    name: "live-flash-reads",
+                    description: "Read variables in flash from the target instead of the binary: `live-flash-reads true|false`",
+                    parser: |args| match args.get(0) {
+                        Some(enabled) => Ok(DebugRequest::SetLiveFlashReads {
+                            enabled: parse_bool(enabled)?,
+                        }),
+                        None => Err(anyhow!("Requires a boolean as a argument")),
+                    },
+                },
+                CommandInfo {
+                    name: "regions",
+                    description: "Show the memory region of addresses, e.g. `0x20001f00 [ram]`: `regions true|false`",
+                    parser: |args| match args.get(0) {
+                        Some(enabled) => Ok(DebugRequest::SetRegionAnnotations {
+                            enabled: parse_bool(enabled)?,
+                        }),
+                        None => Err(anyhow!("Requires a boolean as a argument")),
+                    },
+                },
+                CommandInfo {
+                    name: "test-run",
+                    description: "Report the defmt-test results in the output of the firmware and answer its semihosting console calls: `test-run true|false`",
+                    parser: |args| match args.get(0) {
+                        Some(enabled) => Ok(DebugRequest::SetTestRun {
+                            enabled: parse_bool(enabled)?,
+                        }),
+                        None => Err(anyhow!("Requires a boolean as a argument")),
+                    },
+                },
+                CommandInfo {
+                    name: "peripheral-writes",
+                    description: "Require a confirmation for writes to peripheral registers, or allow or deny them all: `peripheral-writes confirm|allow|deny`",
+                    parser: |args| match args.get(0) {
+                        Some(policy) => Ok(DebugRequest::SetPeripheralWrites {
+                            policy: policy.parse::<PeripheralWrites>()?,
+                        }),
+                        None => Err(anyhow!("Requires confirm, allow or deny as a argument")),
+                    },
+                },
+                CommandInfo {
+                    name: "semihosting-args",
+                    description: "Set the command line answered to the SYS_GET_CMDLINE semihosting call, `NAME=value` words before the arguments are the environment",
+                    parser: |args| {
+                        let mut command_line = CommandLine::default();
+                        let mut words = args.iter().peekable();
+                        while let Some((name, value)) =
+                            words.peek().and_then(|word| word.split_once('='))
+                        {
+                            command_line.env.insert(name.to_owned(), value.to_owned());
+                            words.next();
+                        }
+                        command_line.args = words.map(|word| word.to_string()).collect();
+                        Ok(DebugRequest::SetCommandLine { command_line })
+                    },
+                },
+                CommandInfo {
+                    name: "core-clock",
+                    description: "Set the core clock used to convert cycles to time: `core-clock <Hz>`",
+                    parser: |args| match args.get(0) {
+                        Some(hz) => Ok(DebugRequest::SetCoreClock {
+                            hz: parse_u32_from_str(hz)?,
+                        }),
+                        None => Err(anyhow!("Requires the clock in Hz as a argument")),
+                    },
+                },
+                CommandInfo {
+                    name: "calibrate-clock",
+                    description: "Measure the core clock with the DWT cycle counter over a run of the core: `calibrate-clock [ms]`",
+                    parser: |args| match args.get(0) {
+                        Some(ms) => Ok(DebugRequest::CalibrateClock {
+                            interval_ms: Some(ms.parse()?),
+                        }),
+                        None => Ok(DebugRequest::CalibrateClock { interval_ms: None }),
+                    },
+                },
+                CommandInfo {
+                    name: "on-disconnect",
+                    description: "What to do with the core when the session ends, after removing the breakpoints: `on-disconnect leave|resume|reset`",
+                    parser: |args| match args.get(0) {
+                        Some(action) => Ok(DebugRequest::SetDisconnectAction {
+                            action: action.parse()?,
+                        }),
+                        None => Err(anyhow!("Requires leave, resume or reset as a argument")),
+                    },
+                },
+                CommandInfo {
+                    name: "check-flash-layout",
+                    description: "Refuse to flash a binary that doesn't fit the memory map of the chip, turn it off for layouts the chip description doesn't know: `check-flash-layout true|false`",
+                    parser: |args| match args.get(0) {
+                        Some(enabled) => Ok(DebugRequest::SetCheckFlashLayout {
+                            enabled: parse_bool(enabled)?,
+                        }),
+                        None => Err(anyhow!("Requires a boolean as a argument")),
+                    },
+                },
+                CommandInfo {
+                    name: "halt-on-reset",
+                    description: "Keep the core halted after a watchdog or software reset of the target, or resume it: `halt-on-reset true|false`",
+                    parser: |args| match args.get(0) {
+                        Some(enabled) => Ok(DebugRequest::SetHaltOnReset {
+                            enabled: parse_bool(enabled)?,
+                        }),
+                        None => Err(anyhow!("Requires a boolean as a argument")),
+                    },
                 },
                 CommandInfo {
                     name: "halt",
@@ -216,6 +715,93 @@ impl Commands {
                         Err(anyhow!("Requires a path as a argument"))
                     },
                 },
+                CommandInfo {
+                    name: "load-symbols",
+                    description:
+                        "Load the symbols from one or more binary files, given as `path[@base-address]`",
+                    parser: |args| {
+                        if args.len() > 0 {
+                            let mut symbol_files = vec![];
+                            for arg in args {
+                                symbol_files.push(parse_symbol_file(arg)?);
+                            }
+                            return Ok(DebugRequest::SetSymbolFiles {
+                                symbol_files: symbol_files,
+                            });
+                        }
+                        Err(anyhow!("Requires a path as a argument"))
+                    },
+                },
+                CommandInfo {
+                    name: "history",
+                    description: "Print the timestamped events of the session",
+                    parser: |_args| Ok(DebugRequest::History),
+                },
+                CommandInfo {
+                    name: "log",
+                    description: "Set the log level at runtime: `log <level>` for all modules or `log <module> <level>`, e.g. `log probe_rs debug`. `log tail [lines]` prints the last lines logged",
+                    parser: |args| match args {
+                        ["tail"] => Ok(DebugRequest::LogTail {
+                            lines: DEFAULT_LOG_TAIL,
+                        }),
+                        ["tail", lines] => Ok(DebugRequest::LogTail {
+                            lines: parse_u32_from_str(lines)? as usize,
+                        }),
+                        [level] => Ok(DebugRequest::SetLogLevel {
+                            module: None,
+                            level: level.to_string(),
+                        }),
+                        [module, level] => Ok(DebugRequest::SetLogLevel {
+                            module: Some(module.to_string()),
+                            level: level.to_string(),
+                        }),
+                        _ => Err(anyhow!("Expected a level, a module and a level, or tail")),
+                    },
+                },
+                CommandInfo {
+                    name: "info",
+                    description: "Print information about the session: `info history`, `info heap`, `info vectors`, `info sections`, `info sources`, `info faults` or `info target`",
+                    parser: |args| match args.get(0) {
+                        Some(&"history") => Ok(DebugRequest::History),
+                        Some(&"heap") => Ok(DebugRequest::HeapStats),
+                        Some(&"vectors") => Ok(DebugRequest::VectorTableInfo),
+                        Some(&"sections") => Ok(DebugRequest::SectionMap),
+                        Some(&"sources") => Ok(DebugRequest::LoadedSources),
+                        Some(&"faults") => Ok(DebugRequest::ExceptionInfo),
+                        Some(&"target") => Ok(DebugRequest::TargetInfo),
+                        _ => Err(anyhow!(
+                            "Expected history, heap, vectors, sections, sources, faults or target"
+                        )),
+                    },
+                },
+                CommandInfo {
+                    name: "stats",
+                    description: "Print statistics of the session: `stats stops` for the stops by reason and by breakpoint, and the time halted and running",
+                    parser: |args| match args.get(0) {
+                        Some(&"stops") => Ok(DebugRequest::StopStats),
+                        _ => Err(anyhow!("Expected stops")),
+                    },
+                },
+                CommandInfo {
+                    name: "intrusiveness",
+                    description: "Print the time the target spent in halts the debugger resumed by itself and the probe operations done while it ran",
+                    parser: |_args| Ok(DebugRequest::Intrusiveness),
+                },
+                CommandInfo {
+                    name: "selftest",
+                    description: "Check the probe and target connection step by step, halts a running core for the memory steps",
+                    parser: |_args| Ok(DebugRequest::SelfTest),
+                },
+                CommandInfo {
+                    name: "modules",
+                    description: "List the loaded symbol files and their address ranges",
+                    parser: |_args| Ok(DebugRequest::Modules),
+                },
+                CommandInfo {
+                    name: "memory-usage",
+                    description: "Print how much of the flash and RAM the binary occupies",
+                    parser: |_args| Ok(DebugRequest::MemoryUsage),
+                },
                 CommandInfo {
                     name: "cycle",
                     description: "Print the value of the cycle counter",
@@ -223,14 +809,36 @@ impl Commands {
                 },
                 CommandInfo {
                     name: "trace",
-                    description:
-                        "Trace cycle counter at breakpoint instructions until `bkpt_end` is reached",
-                    parser: |_args| Ok(DebugRequest::Trace),
+                    description: "Trace cycle counter at breakpoint instructions until `bkpt_end` is reached, or the branches with the MTB: `trace configure <buffer address> <size>`, `trace show [<n>]` or `trace off`",
+                    parser: |args| match (args.get(0), args.get(1), args.get(2)) {
+                        (None, _, _) => Ok(DebugRequest::Trace),
+                        (Some(&"configure"), Some(address), Some(size)) => {
+                            Ok(DebugRequest::TraceConfigure {
+                                buffer_address: parse_u32_from_str(address)?,
+                                size: parse_u32_from_str(size)?,
+                            })
+                        }
+                        (Some(&"show"), count, _) => Ok(DebugRequest::TraceRead {
+                            count: match count {
+                                Some(count) => Some(parse_u32_from_str(count)? as usize),
+                                None => None,
+                            },
+                        }),
+                        (Some(&"off"), _, _) => Ok(DebugRequest::TraceDisable),
+                        _ => Err(anyhow!(
+                            "Expected no arguments, configure <buffer address> <size>, show [<n>] or off"
+                        )),
+                    },
                 },
             ],
         }
     }
 
+    /// The names of all the commands.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.commands.iter().map(|command| command.name).collect()
+    }
+
     pub fn parse_command(&self, line: &str) -> Result<Command> {
         let mut command_parts = line.split_whitespace();
         if let Some(command) = command_parts.next() {
@@ -267,7 +875,7 @@ impl Commands {
     }
 }
 
-fn parse_u32_from_str(s: &str) -> Result<u32> {
+pub fn parse_u32_from_str(s: &str) -> Result<u32> {
     if s.starts_with("0x") {
         let without_prefix = s.trim_start_matches("0x");
         return Ok(u32::from_str_radix(without_prefix, 16)?);
@@ -276,6 +884,19 @@ fn parse_u32_from_str(s: &str) -> Result<u32> {
     };
 }
 
+fn parse_symbol_file(s: &str) -> Result<SymbolFile> {
+    match s.rsplit_once('@') {
+        Some((path, base_address)) => Ok(SymbolFile {
+            path: PathBuf::from(path),
+            base_address: parse_u32_from_str(base_address)?,
+        }),
+        None => Ok(SymbolFile {
+            path: PathBuf::from(s),
+            base_address: 0,
+        }),
+    }
+}
+
 fn parse_bool(s: &str) -> Result<bool> {
     match s {
         "true" => Ok(true),
@@ -283,3 +904,112 @@ fn parse_bool(s: &str) -> Result<bool> {
         _ => Err(anyhow!("Expected a boolean argument")),
     }
 }
+
+fn parse_step_count(args: &[&str]) -> Result<u32> {
+    match args.get(0) {
+        Some(count) => match parse_u32_from_str(count)? {
+            0 => Err(anyhow!("The step count must be at least 1")),
+            count => Ok(count),
+        },
+        None => Ok(1),
+    }
+}
+
+/// A time in milliseconds, `50`, `50ms` or `2s`.
+fn parse_millis(time: &str) -> Result<u64> {
+    let (number, scale) = match time.strip_suffix("ms") {
+        Some(number) => (number, 1),
+        None => match time.strip_suffix('s') {
+            Some(number) => (number, 1000),
+            None => (time, 1),
+        },
+    };
+    match number.parse::<u64>() {
+        Ok(number) => Ok(number * scale),
+        Err(_) => Err(anyhow!("Expected a time like 50ms or 2s, got {}", time)),
+    }
+}
+
+fn parse_dump_regions(args: &[&str]) -> Result<DumpRegions> {
+    match args {
+        [] => Ok(DumpRegions::Ram),
+        ["flash"] => Ok(DumpRegions::RamAndFlash),
+        ranges => {
+            let mut parsed = vec![];
+            for range in ranges {
+                let (address, size) = match range.split_once(':') {
+                    Some(val) => val,
+                    None => return Err(anyhow!("Expected <address>:<size>, got {}", range)),
+                };
+                let address = parse_u32_from_str(address)?;
+                parsed.push(address..address + parse_u32_from_str(size)?);
+            }
+            Ok(DumpRegions::Ranges(parsed))
+        }
+    }
+}
+
+fn parse_format_command(args: &[&str]) -> Result<DebugRequest> {
+    match args {
+        ["list"] => Ok(DebugRequest::Formats),
+        ["clear"] => Ok(DebugRequest::ClearFormats),
+        ["save", path] => Ok(DebugRequest::SaveFormats {
+            path: PathBuf::from(path),
+        }),
+        ["load", path] => Ok(DebugRequest::LoadFormats {
+            path: PathBuf::from(path),
+        }),
+        [path, "default"] => Ok(DebugRequest::SetFormat {
+            path: path.to_string(),
+            format: None,
+        }),
+        [path, format] => Ok(DebugRequest::SetFormat {
+            path: path.to_string(),
+            format: Some(format.parse::<ValueFormat>()?),
+        }),
+        _ => Err(anyhow!(
+            "Expected a variable path and a format, list, clear, save <file> or load <file>"
+        )),
+    }
+}
+
+fn parse_break_command(args: &[&str]) -> Result<DebugRequest> {
+    match args.get(0) {
+        Some(&"list") => Ok(DebugRequest::Breakpoints),
+        Some(&"verify") => Ok(DebugRequest::VerifyBreakpoints),
+        Some(&"save") => match args.get(1) {
+            Some(path) => Ok(DebugRequest::SaveBreakpoints {
+                path: PathBuf::from(path),
+            }),
+            None => Err(anyhow!("Requires a file path as a argument")),
+        },
+        Some(&"load") => match args.get(1) {
+            Some(path) => Ok(DebugRequest::LoadBreakpoints {
+                path: PathBuf::from(path),
+            }),
+            None => Err(anyhow!("Requires a file path as a argument")),
+        },
+        Some(&"ignore") => match (args.get(1), args.get(2)) {
+            (Some(id), Some(count)) => Ok(DebugRequest::IgnoreBreakpoint {
+                id: parse_u32_from_str(id)?,
+                count: parse_u32_from_str(count)?,
+            }),
+            _ => Err(anyhow!("Requires a breakpoint id and a count as arguments")),
+        },
+        // Without commands the CLI reads them one per line, see `cli`
+        Some(&"commands") => match args.get(1) {
+            Some(id) => Ok(DebugRequest::BreakpointCommands {
+                id: parse_u32_from_str(id)?,
+                commands: args[2..]
+                    .join(" ")
+                    .split(';')
+                    .map(str::trim)
+                    .filter(|command| !command.is_empty())
+                    .map(str::to_owned)
+                    .collect(),
+            }),
+            None => Err(anyhow!("Requires a breakpoint id as a argument")),
+        },
+        _ => Err(anyhow!("Expected one of list, verify, ignore, commands, save or load")),
+    }
+}
@@ -1,5 +1,8 @@
+use debugserver_types::Breakpoint;
 use probe_rs::HaltReason;
 
+use crate::debugger::test_run::TestEvent;
+
 
 //use debugserver_types::{
 //    SourceBreakpoint,
@@ -8,7 +11,14 @@ use probe_rs::HaltReason;
 
 #[derive(Debug, Clone)]
 pub enum DebugEvent {
-    Halted { pc: u32, reason: HaltReason, hit_breakpoint_ids: Option<Vec<u32>> },
+    // `resume` is the resume count of the core when it halted, see `resumes`.
+    Halted { pc: u32, reason: HaltReason, hit_breakpoint_ids: Option<Vec<u32>>, description: Option<String>, resume: u64 },
+    Output { category: String, output: String },
+    Breakpoint { reason: String, breakpoint: Breakpoint },
+    Progress { title: String, done: u32, total: u32 },
+    Test { event: TestEvent },
+    // A reset the target did by itself, the core is halted at the reset vector if `halted`.
+    Reset { pc: u32, halted: bool },
 }
 
 
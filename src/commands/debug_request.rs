@@ -1,8 +1,27 @@
+use std::ops::Range;
 use std::path::PathBuf;
 
+use crate::debugger::config::{DisconnectAction, ReloadTarget, StepBreakpoints, SymbolFile};
+use crate::debugger::core_dump::DumpRegions;
+use crate::debugger::formats::ValueFormat;
+use crate::debugger::frame_label::StackFrameFormat;
+use crate::debugger::function_breakpoints::FunctionBreakpoint;
+use crate::debugger::instruction_breakpoints::InstructionBreakpoint;
+use crate::debugger::semihosting::CommandLine;
+use crate::debugger::trap_catch::ExceptionFilter;
+use crate::debugger::watchpoints::{DataBreakpoint, WatchKind};
+use crate::debugger::write_policy::PeripheralWrites;
+
 use debugserver_types::{Source, SourceBreakpoint};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+/// A request to the debugger thread.
+///
+/// The JSON form is what the `erdbCommand` custom DAP request takes: externally tagged with the
+/// variant and field names as written here, e.g. `{"Read": {"address": 536870912, "byte_size": 4}}`
+/// or `"Status"`. Variants and optional fields can be added, renaming or removing one is a
+/// breaking change for the extensions using it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DebugRequest {
     Attach {
         reset: bool,
@@ -11,32 +30,233 @@ pub enum DebugRequest {
     Status,
     Exit,
     Continue,
+    ContinueFor {
+        millis: u64,
+    },
+    Finish,
+    History,
+    StopStats,
+    // The level of a module and the modules in it, of all the modules if `module` is None.
+    SetLogLevel {
+        module: Option<String>,
+        level: String,
+    },
+    LogTail {
+        lines: usize,
+    },
+    Breakpoints,
+    VerifyBreakpoints,
+    CoreDump {
+        path: PathBuf,
+        regions: DumpRegions,
+    },
+    // Continues an interrupted readback to the same path, the first flash region if None.
+    ReadFlash {
+        path: PathBuf,
+        region: Option<Range<u32>>,
+    },
+    SnapshotState {
+        name: String,
+        regions: DumpRegions,
+    },
+    RestoreState {
+        name: String,
+    },
+    Snapshots,
+    SaveBreakpoints {
+        path: PathBuf,
+    },
+    LoadBreakpoints {
+        path: PathBuf,
+    },
+    BreakpointCommands {
+        id: u32,
+        commands: Vec<String>,
+    },
+    IgnoreBreakpoint {
+        id: u32,
+        count: u32,
+    },
+    Until,
+    StepInstruction {
+        count: u32,
+        over_calls: bool,
+    },
     Step,
     Halt,
     SetBinary {
         path: PathBuf,
     },
+    SetSymbolFiles {
+        symbol_files: Vec<SymbolFile>,
+    },
+    Modules,
+    MemoryUsage,
     Flash {
         reset_and_halt: bool,
     },
     Reset {
         reset_and_halt: bool,
     },
+    // Flashes the program again only if it changed on disk, then resets.
+    Restart {
+        reset_and_halt: bool,
+    },
     Read {
         address: u32,
         byte_size: usize,
     },
+    WriteWord {
+        address: u32,
+        value: u32,
+        // Confirms a write to a peripheral register, see `write_policy`.
+        #[serde(default)]
+        confirmed: bool,
+    },
+    WriteRegister {
+        name: String,
+        value: u32,
+    },
     StackTrace,
     SetProbeNumber {
         number: usize,
     },
+    SetForceClaim {
+        enabled: bool,
+    },
+    SetPolling {
+        status_interval_ms: Option<u64>,
+        low_power: Option<bool>,
+    },
+    SetHeapOptions {
+        symbol: Option<String>,
+        stats_on_stop: Option<bool>,
+    },
+    SetStepBreakpoints {
+        mode: StepBreakpoints,
+    },
+    SetCatchFunctions {
+        functions: Vec<String>,
+    },
+    SetExceptionFilters {
+        filters: Vec<ExceptionFilter>,
+    },
+    SetInstructionTrace {
+        length: usize,
+    },
+    StepBack,
+    RestartFrame {
+        // The innermost frame if None.
+        frame_id: Option<i64>,
+    },
+    GotoTargets {
+        source_file: String,
+        line: u64,
+    },
+    Goto {
+        address: u32,
+    },
+    SetLiveFlashReads {
+        enabled: bool,
+    },
+    SetRegionAnnotations {
+        enabled: bool,
+    },
+    // Parse the output of the firmware for test results, see `test_run`.
+    SetTestRun {
+        enabled: bool,
+    },
+    SetPeripheralWrites {
+        policy: PeripheralWrites,
+    },
+    SetCommandLine {
+        command_line: CommandLine,
+    },
+    SetCoreClock {
+        hz: u32,
+    },
+    SetDisconnectAction {
+        action: DisconnectAction,
+    },
+    SetHaltOnReset {
+        enabled: bool,
+    },
+    SetCheckFlashLayout {
+        enabled: bool,
+    },
+    SetProbeTimeout {
+        timeout_ms: u64,
+    },
+    HeapStats,
+    VectorTableInfo,
+    SectionMap,
+    LoadedSources,
+    ExceptionInfo,
+    Intrusiveness,
+    SelfTest,
+    CalibrateClock {
+        // The default interval if None.
+        interval_ms: Option<u64>,
+    },
+    TargetInfo,
+    SetFormat {
+        path: String,
+        // None removes the override of the path.
+        format: Option<ValueFormat>,
+    },
+    Formats,
+    ClearFormats,
+    SaveFormats {
+        path: PathBuf,
+    },
+    LoadFormats {
+        path: PathBuf,
+    },
+    ReloadConfig {
+        what: ReloadTarget,
+    },
     SetChip {
         chip: String,
     },
+    SetCoreDump {
+        path: Option<PathBuf>,
+    },
     Variable {
         name: String,
     },
     Variables,
+    ExportStopState {
+        // Returned in the response instead of written if None.
+        path: Option<PathBuf>,
+        depth: usize,
+    },
+    SetVariable {
+        variables_reference: i64,
+        name: String,
+        // Typed by the user, `!` before it confirms a write to a peripheral register.
+        value: String,
+    },
+    SetExpression {
+        expression: String,
+        // The innermost frame if None.
+        frame_id: Option<i64>,
+        // Typed by the user, `!` before it confirms a write to a peripheral register.
+        value: String,
+    },
+    Evaluate {
+        expression: String,
+        // The innermost frame if None.
+        frame_id: Option<i64>,
+        // Only look in the variables already evaluated for the stop, without reading the target.
+        #[serde(default)]
+        cached: bool,
+    },
+    // Completes the last part of a member path, e.g. `cfg.fl`.
+    Completions {
+        text: String,
+        // The innermost frame if None.
+        frame_id: Option<i64>,
+    },
     Registers,
     SetBreakpoint {
         address: u32,
@@ -47,16 +267,90 @@ pub enum DebugRequest {
         source_breakpoints: Vec<SourceBreakpoint>,
         source: Option<Source>,
     },
+    BreakpointLocations {
+        source_file: String,
+        line: u64,
+        column: Option<u64>,
+        end_line: Option<u64>,
+        end_column: Option<u64>,
+    },
     ClearBreakpoint {
         address: u32,
     },
     ClearAllBreakpoints,
+    SetFunctionBreakpoints {
+        breakpoints: Vec<FunctionBreakpoint>,
+    },
+    SetInstructionBreakpoints {
+        breakpoints: Vec<InstructionBreakpoint>,
+    },
+    SetWatchpoint {
+        expression: String,
+        kind: WatchKind,
+    },
+    Watchpoints,
+    DeleteWatchpoint {
+        id: u32,
+    },
+    SetWatchBreak {
+        expression: String,
+    },
+    WatchBreaks,
+    DeleteWatchBreak {
+        id: u32,
+    },
+    SetWatchSampling {
+        // Off if None or 0.
+        interval_ms: Option<u64>,
+    },
+    DataBreakpointInfo {
+        // An expression like for `watch` if None.
+        variables_reference: Option<i64>,
+        name: String,
+    },
+    // Replaces all the data breakpoints.
+    SetDataBreakpoints {
+        breakpoints: Vec<DataBreakpoint>,
+    },
     Code,
+    Disassemble {
+        memory_reference: String,
+        // In bytes from the memory reference.
+        offset: i64,
+        // In instructions from the offset, negative to start before it.
+        instruction_offset: i64,
+        instruction_count: usize,
+    },
+    // Reads what is readable of the range, stopping at the first byte that isn't.
+    ReadMemory {
+        memory_reference: String,
+        // In bytes from the memory reference.
+        offset: i64,
+        count: usize,
+    },
+    // Flash is never written, see `Flash`.
+    WriteMemory {
+        memory_reference: String,
+        // In bytes from the memory reference.
+        offset: i64,
+        data: Vec<u8>,
+        // Write up to the first byte that can't be written instead of failing.
+        allow_partial: bool,
+    },
+    // The content of a source reference handed out in the stack frames.
+    Source {
+        source_reference: i64,
+    },
     Stack,
     SetCWD {
         cwd: String,
     },
-    DAPStackFrames,
+    // The frames from `start_frame`, all of them if `levels` is None, named as `format` asks.
+    DAPStackFrames {
+        start_frame: usize,
+        levels: Option<usize>,
+        format: Option<StackFrameFormat>,
+    },
     DAPScopes {
         frame_id: i64,
     },
@@ -65,4 +359,13 @@ pub enum DebugRequest {
     },
     CycleCounter,
     Trace,
+    TraceConfigure {
+        buffer_address: u32,
+        size: u32,
+    },
+    TraceDisable,
+    TraceRead {
+        // The most recent branches, all of them if None.
+        count: Option<usize>,
+    },
 }
@@ -1,53 +1,317 @@
+use crate::debugger::breakpoints::{BreakpointState, SavedBreakpoint, VerifyReport};
+use crate::debugger::core_clock::CoreClock;
+use crate::debugger::disassemble::DisassembledInstruction;
+use crate::debugger::fault_status::FaultStatus;
+use crate::debugger::flash_readback::ReadbackReport;
+use crate::debugger::formats::ValueFormat;
+use crate::debugger::heap::HeapStats;
+use crate::debugger::history::{HistoryEntry, StopStats};
+use crate::debugger::intrusiveness::IntrusivenessReport;
+use crate::debugger::memory_usage::MemoryUsage;
+use crate::debugger::section_map::SectionMap;
+use crate::debugger::self_test::SelfTestReport;
+use crate::debugger::session_summary::SessionSummary;
+use crate::debugger::stepping::InstructionStep;
+use crate::debugger::symbols::ModuleInfo;
+use crate::debugger::trace_buffer::{TraceBuffer, TracedBranch};
+use crate::debugger::vector_table::VectorTableInfo;
+use crate::debugger::watch_break::WatchBreak;
+use crate::debugger::watchpoints::{DataBreakpointResult, WatchKind, Watchpoint};
 use crate::debugger::StackFrame;
 use crate::debugger::Variable;
 use debugserver_types::Breakpoint;
 use probe_rs::CoreStatus;
+use serde::{Serialize, Serializer};
+use std::path::PathBuf;
 
-#[derive(Debug, Clone)]
+/// A response from the debugger thread.
+///
+/// Serialized in the same form as `DebugRequest` for the `erdbCommand` custom DAP request. The
+/// variants holding values decoded by rust-debug have no JSON form and fail to serialize.
+/// `Deserialize` is not derived: responses only go out to the client, and the probe-rs and
+/// rust-debug types several variants hold can't be deserialized.
+#[derive(Debug, Clone, Serialize)]
 pub enum DebugResponse {
     Attach,
     Status {
+        #[serde(serialize_with = "serialize_debug")]
         status: CoreStatus,
         pc: Option<u32>,
     },
     Exit,
     Continue,
+    ContinueFor {
+        millis: u64,
+    },
+    Finish {
+        function: String,
+    },
     Step,
+    History {
+        entries: Vec<HistoryEntry>,
+    },
+    StopStats {
+        stats: StopStats,
+    },
+    SetLogLevel {
+        filter: String,
+    },
+    LogTail {
+        lines: Vec<String>,
+    },
+    Breakpoints {
+        breakpoints: Vec<(u32, Breakpoint, BreakpointState)>,
+        disabled: Vec<SavedBreakpoint>,
+    },
+    SaveBreakpoints,
+    VerifyBreakpoints {
+        report: Vec<VerifyReport>,
+    },
+    CoreDump {
+        size: u32,
+    },
+    ReadFlash {
+        report: ReadbackReport,
+    },
+    SnapshotState {
+        caveat: String,
+    },
+    RestoreState {
+        caveat: String,
+    },
+    Snapshots {
+        snapshots: Vec<(String, Option<u32>, u32)>,
+    },
+    LoadBreakpoints {
+        loaded: usize,
+        disabled: Vec<String>,
+    },
+    IgnoreBreakpoint,
+    BreakpointCommands,
+    Until,
+    StepInstruction {
+        steps: Vec<InstructionStep>,
+    },
     Halt,
     SetBinary,
+    SetSymbolFiles,
+    Modules {
+        modules: Vec<ModuleInfo>,
+    },
+    MemoryUsage {
+        memory_usage: Option<MemoryUsage>,
+    },
     Flash,
     Reset,
+    Restart {
+        flashed: bool,
+    },
     Read {
         address: u32,
         value: Vec<u8>,
     },
+    WriteWord {
+        value: u32,
+    },
+    WriteRegister {
+        value: u32,
+    },
+    #[serde(skip_serializing)]
     StackTrace {
         stack_trace: Vec<StackFrame>,
     },
     SetProbeNumber,
-    SetChip,
+    SetForceClaim,
+    SetChip {
+        chip: String,
+    },
+    SetPolling,
+    SetHeapOptions,
+    SetStepBreakpoints,
+    SetCatchFunctions,
+    SetExceptionFilters,
+    SetInstructionTrace,
+    SetLiveFlashReads,
+    SetRegionAnnotations,
+    SetTestRun,
+    SetPeripheralWrites,
+    SetCommandLine,
+    SetCoreClock,
+    SetDisconnectAction,
+    SetHaltOnReset,
+    SetCheckFlashLayout,
+    SetProbeTimeout,
+    StepBack {
+        pc: u32,
+    },
+    RestartFrame {
+        function: String,
+        pc: u32,
+    },
+    GotoTargets {
+        // The addresses and the functions they are in.
+        targets: Vec<(u32, Option<String>)>,
+    },
+    Goto {
+        pc: u32,
+        // Set when the target is outside the function of the current frame.
+        warning: Option<String>,
+    },
+    HeapStats {
+        stats: HeapStats,
+    },
+    VectorTableInfo {
+        info: VectorTableInfo,
+    },
+    SectionMap {
+        map: SectionMap,
+    },
+    LoadedSources {
+        sources: Vec<PathBuf>,
+    },
+    ExceptionInfo {
+        status: FaultStatus,
+    },
+    Intrusiveness {
+        report: IntrusivenessReport,
+    },
+    SelfTest {
+        report: SelfTestReport,
+    },
+    CalibrateClock {
+        clock: CoreClock,
+        // Why the clock set by the user was kept.
+        warning: Option<String>,
+    },
+    #[serde(skip_serializing)]
+    TargetInfo {
+        summary: SessionSummary,
+    },
+    SetFormat,
+    Formats {
+        formats: Vec<(String, ValueFormat)>,
+    },
+    ClearFormats,
+    SaveFormats,
+    LoadFormats {
+        loaded: usize,
+    },
+    ReloadConfig {
+        // What was read again, e.g. `formatters: 3 formats from 1 files`.
+        reloaded: Vec<String>,
+    },
+    SetCoreDump,
+    #[serde(skip_serializing)]
     Variable {
         variable: Variable,
     },
+    #[serde(skip_serializing)]
     Variables {
         variables: Vec<Variable>,
     },
+    ExportStopState {
+        path: Option<PathBuf>,
+        report: String,
+    },
+    #[serde(skip_serializing)]
+    Evaluate {
+        variable: Variable,
+    },
+    Completions {
+        // The names replacing the last part of the path.
+        names: Vec<String>,
+    },
+    #[serde(skip_serializing)]
+    SetVariable {
+        variable: Variable,
+    },
+    #[serde(skip_serializing)]
+    SetExpression {
+        variable: Variable,
+    },
     Registers {
         registers: Vec<(String, u32)>,
+        // The region suffix of each value, empty if the annotations are disabled.
+        regions: Vec<String>,
     },
     SetBreakpoint,
     SetBreakpoints {
         breakpoints: Vec<Breakpoint>,
     },
+    BreakpointLocations {
+        // The lines and columns, None is the whole line.
+        locations: Vec<(u64, Option<u64>)>,
+    },
     ClearBreakpoint,
     ClearAllBreakpoints,
+    SetFunctionBreakpoints {
+        breakpoints: Vec<Breakpoint>,
+    },
+    SetInstructionBreakpoints {
+        breakpoints: Vec<Breakpoint>,
+    },
+    SetWatchpoint {
+        watchpoint: Watchpoint,
+    },
+    Watchpoints {
+        watchpoints: Vec<Watchpoint>,
+    },
+    DeleteWatchpoint,
+    SetWatchBreak {
+        watch: WatchBreak,
+    },
+    WatchBreaks {
+        watches: Vec<WatchBreak>,
+        sample_interval_ms: Option<u64>,
+    },
+    DeleteWatchBreak,
+    SetWatchSampling {
+        // Describes the halts the sampling adds, None if it is off.
+        warning: Option<String>,
+    },
+    DataBreakpointInfo {
+        // None if the variable can't be watched, the description says why.
+        data_id: Option<String>,
+        description: String,
+        access_types: Vec<WatchKind>,
+    },
+    SetDataBreakpoints {
+        breakpoints: Vec<DataBreakpointResult>,
+    },
     Code {
         pc: u32,
         instructions: Vec<(u32, String)>,
     },
+    Disassemble {
+        instructions: Vec<DisassembledInstruction>,
+    },
+    ReadMemory {
+        address: u32,
+        data: Vec<u8>,
+        // The bytes after the data that could not be read.
+        unreadable_bytes: usize,
+    },
+    WriteMemory {
+        address: u32,
+        bytes_written: usize,
+    },
+    Source {
+        content: String,
+        // None for plain source.
+        mime_type: Option<String>,
+    },
     Stack {
         stack_pointer: u32,
         stack: Vec<u32>,
+        // The region suffix of each word, empty if the annotations are disabled.
+        regions: Vec<String>,
+    },
+    TraceConfigure {
+        buffer: TraceBuffer,
+    },
+    TraceDisable,
+    TraceRead {
+        branches: Vec<TracedBranch>,
     },
     Error {
         message: String,
@@ -55,11 +319,192 @@ pub enum DebugResponse {
     SetCWD,
     DAPStackFrames {
         stack_frames: Vec<debugserver_types::StackFrame>,
+        total_frames: usize,
     },
     DAPScopes {
         scopes: Vec<debugserver_types::Scope>,
     },
+    #[serde(skip_serializing)]
     DAPVariables {
         variables: Vec<Variable>,
     },
 }
+
+impl DebugResponse {
+    /// The response as text for the debug console and the breakpoint commands, the responses
+    /// without a text form are shown as their debug output.
+    pub fn text(&self) -> String {
+        match self {
+            DebugResponse::Status { status, pc } => match (status.is_halted(), pc) {
+                (true, Some(pc)) => format!("Halted at {:#010x}", pc),
+                _ => format!("{:?}", status),
+            },
+            DebugResponse::Continue => "Running".to_owned(),
+            DebugResponse::Halt => "Halted".to_owned(),
+            DebugResponse::Reset => "Reset".to_owned(),
+            DebugResponse::Flash => "Flashed".to_owned(),
+            DebugResponse::Restart { flashed } => match flashed {
+                true => "Flashed and reset".to_owned(),
+                false => "Reset, the program is unchanged".to_owned(),
+            },
+            DebugResponse::StopStats { stats } => stats.to_string(),
+            DebugResponse::Completions { names } => names.join("\n"),
+            DebugResponse::RestartFrame { function, pc } => {
+                format!("Restarted `{}` at {:#010x}", function, pc)
+            }
+            DebugResponse::GotoTargets { targets } => match targets.is_empty() {
+                true => "No code for the line".to_owned(),
+                false => targets
+                    .iter()
+                    .map(|(address, function)| match function {
+                        Some(function) => format!("{:#010x} in `{}`", address, function),
+                        None => format!("{:#010x}", address),
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            },
+            DebugResponse::Goto { pc, warning } => match warning {
+                Some(warning) => format!("Jumped to {:#010x}\nWarning: {}", pc, warning),
+                None => format!("Jumped to {:#010x}", pc),
+            },
+            DebugResponse::StackTrace { stack_trace } => stack_trace
+                .iter()
+                .map(|frame| {
+                    format!(
+                        "#{} {:#010x} in {} at {}:{}",
+                        frame.state.index,
+                        frame.state.pc.address(),
+                        frame.name,
+                        frame.source.file.as_deref().unwrap_or("<unknown>"),
+                        match frame.source.line {
+                            Some(line) => line.to_string(),
+                            None => "?".to_owned(),
+                        }
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            DebugResponse::Variable { variable }
+            | DebugResponse::Evaluate { variable }
+            | DebugResponse::SetVariable { variable }
+            | DebugResponse::SetExpression { variable } => match &variable.name {
+                Some(name) => format!("{} = {}", name, variable.value_to_string()),
+                None => variable.value_to_string(),
+            },
+            DebugResponse::Variables { variables } => variables
+                .iter()
+                .map(|variable| {
+                    format!(
+                        "{} = {}",
+                        variable.name.as_deref().unwrap_or("<unknown>"),
+                        variable.value_to_string()
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            DebugResponse::Registers { registers, regions } => registers
+                .iter()
+                .zip(regions)
+                .map(|((name, value), region)| format!("{}: {:#010x}{}", name, value, region))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            DebugResponse::HeapStats { stats } => stats.to_string(),
+            DebugResponse::VectorTableInfo { info } => info.to_string(),
+            DebugResponse::SectionMap { map } => map.to_string(),
+            DebugResponse::SetLogLevel { filter } => format!("Log filter: {}", filter),
+            DebugResponse::LogTail { lines } => lines.join("\n"),
+            DebugResponse::LoadedSources { sources } => sources
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<String>>()
+                .join("\n"),
+            DebugResponse::ExceptionInfo { status } => status.to_string(),
+            DebugResponse::Intrusiveness { report } => report.to_string(),
+            DebugResponse::SelfTest { report } => report.to_string(),
+            DebugResponse::CalibrateClock { clock, warning } => match warning {
+                Some(warning) => format!("{}, keeping {}", warning, clock),
+                None => format!("Core clock: {}", clock),
+            },
+            DebugResponse::TargetInfo { summary } => summary.to_string(),
+            DebugResponse::ReadFlash { report } => report.to_string(),
+            DebugResponse::ExportStopState { path, report } => match path {
+                Some(path) => format!("Stop state written to {:?}, {} bytes", path, report.len()),
+                None => report.clone(),
+            },
+            DebugResponse::Formats { formats } => formats
+                .iter()
+                .map(|(path, format)| format!("{}: {}", path, format))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            DebugResponse::ReloadConfig { reloaded } => format!("Reloaded {}", reloaded.join(", ")),
+            DebugResponse::VerifyBreakpoints { report } => report
+                .iter()
+                .map(|entry| entry.to_string())
+                .collect::<Vec<String>>()
+                .join("\n"),
+            DebugResponse::Disassemble { instructions } => instructions
+                .iter()
+                .map(|insn| {
+                    format!(
+                        "{}: {:<12} {}",
+                        insn.address,
+                        insn.instruction_bytes.as_deref().unwrap_or(""),
+                        insn.instruction
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            DebugResponse::ReadMemory {
+                address,
+                data,
+                unreadable_bytes,
+            } => {
+                let mut lines: Vec<String> = data
+                    .chunks(16)
+                    .enumerate()
+                    .map(|(i, row)| {
+                        let bytes: Vec<String> = row.iter().map(|b| format!("{:02x}", b)).collect();
+                        format!("{:#010x}: {}", address + i as u32 * 16, bytes.join(" "))
+                    })
+                    .collect();
+                if *unreadable_bytes > 0 {
+                    lines.push(format!(
+                        "{} bytes from {:#010x} could not be read",
+                        unreadable_bytes,
+                        address + data.len() as u32
+                    ));
+                }
+                lines.join("\n")
+            }
+            DebugResponse::WriteMemory {
+                address,
+                bytes_written,
+            } => format!("Wrote {} bytes at {:#010x}", bytes_written, address),
+            DebugResponse::Source { content, .. } => content.clone(),
+            DebugResponse::BreakpointLocations { locations } => locations
+                .iter()
+                .map(|(line, column)| match column {
+                    Some(column) => format!("{}:{}", line, column),
+                    None => line.to_string(),
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            DebugResponse::TraceConfigure { buffer } => format!("Tracing with the {}", buffer),
+            DebugResponse::TraceDisable => "Trace disabled".to_owned(),
+            DebugResponse::TraceRead { branches } => match branches.is_empty() {
+                true => "No branches traced".to_owned(),
+                false => branches
+                    .iter()
+                    .map(|branch| branch.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            },
+            _ => format!("{:?}", self),
+        }
+    }
+}
+
+// probe-rs types without a JSON form are serialized as their debug output.
+fn serialize_debug<T: std::fmt::Debug, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:?}", value))
+}
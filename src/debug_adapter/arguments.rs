@@ -0,0 +1,343 @@
+//! Lenient request arguments
+//!
+//! Newer clients send fields that the types in `debugserver_types` don't model. The arguments of
+//! the requests are parsed into these structs instead, which only hold the fields the adapter
+//! uses. Unknown fields are ignored and missing optional fields default to `None`.
+use crate::debugger::frame_label::StackFrameFormat;
+use crate::debugger::function_breakpoints::FunctionBreakpoint;
+use crate::debugger::instruction_breakpoints::InstructionBreakpoint;
+use crate::debugger::watchpoints::DataBreakpoint;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct InitializeArguments {
+    pub client_name: Option<String>,
+}
+
+/// Only requests are cancelled, progress reports end with their operation.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CancelArguments {
+    pub request_id: Option<i64>,
+    pub progress_id: Option<String>,
+}
+
+/// Sets the level of `module`, of all the modules without one, or returns the last `tail` lines.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ErdbLogArguments {
+    pub module: Option<String>,
+    pub level: Option<String>,
+    pub tail: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StackTraceArguments {
+    pub thread_id: i64,
+    pub start_frame: Option<i64>,
+    /// All the frames if missing or 0.
+    pub levels: Option<i64>,
+    pub format: Option<StackFrameFormat>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScopesArguments {
+    pub frame_id: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestartFrameArguments {
+    pub frame_id: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VariablesArguments {
+    pub variables_reference: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetVariableArguments {
+    pub variables_reference: i64,
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetExpressionArguments {
+    pub expression: String,
+    pub value: String,
+    #[serde(default)]
+    pub frame_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataBreakpointInfoArguments {
+    #[serde(default)]
+    pub variables_reference: Option<i64>,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetDataBreakpointsArguments {
+    pub breakpoints: Vec<DataBreakpoint>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetFunctionBreakpointsArguments {
+    pub breakpoints: Vec<FunctionBreakpoint>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetInstructionBreakpointsArguments {
+    pub breakpoints: Vec<InstructionBreakpoint>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetExceptionBreakpointsArguments {
+    pub filters: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionsArguments {
+    pub text: String,
+    // Of the cursor, the first character is column 1.
+    pub column: u64,
+    #[serde(default)]
+    pub frame_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluateArguments {
+    pub expression: String,
+    #[serde(default)]
+    pub frame_id: Option<i64>,
+    #[serde(default)]
+    pub context: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Source {
+    pub name: Option<String>,
+    pub path: Option<String>,
+    pub source_reference: Option<i64>,
+}
+
+impl Source {
+    pub fn to_dap(&self) -> debugserver_types::Source {
+        debugserver_types::Source {
+            name: self.name.clone(),
+            path: self.path.clone(),
+            source_reference: self.source_reference,
+            presentation_hint: None,
+            origin: None,
+            sources: None,
+            adapter_data: None,
+            checksums: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceBreakpoint {
+    pub line: i64,
+    #[serde(default)]
+    pub column: Option<i64>,
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub hit_condition: Option<String>,
+    #[serde(default)]
+    pub log_message: Option<String>,
+    /// Monitor commands run when the breakpoint stops the core, an extension of the DAP type.
+    #[serde(default)]
+    pub commands: Option<Vec<String>>,
+}
+
+impl SourceBreakpoint {
+    pub fn to_dap(&self) -> debugserver_types::SourceBreakpoint {
+        debugserver_types::SourceBreakpoint {
+            line: self.line,
+            column: self.column,
+            condition: self.condition.clone(),
+            hit_condition: self.hit_condition.clone(),
+            log_message: self.log_message.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DisassembleArguments {
+    pub memory_reference: String,
+    #[serde(default)]
+    pub offset: Option<i64>,
+    #[serde(default)]
+    pub instruction_offset: Option<i64>,
+    pub instruction_count: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadMemoryArguments {
+    pub memory_reference: String,
+    #[serde(default)]
+    pub offset: Option<i64>,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WriteMemoryArguments {
+    pub memory_reference: String,
+    #[serde(default)]
+    pub offset: Option<i64>,
+    #[serde(default)]
+    pub allow_partial: Option<bool>,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceArguments {
+    // Deprecated in favour of `source.sourceReference`.
+    pub source_reference: i64,
+    #[serde(default)]
+    pub source: Option<Source>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BreakpointLocationsArguments {
+    pub source: Source,
+    pub line: u64,
+    #[serde(default)]
+    pub column: Option<u64>,
+    #[serde(default)]
+    pub end_line: Option<u64>,
+    #[serde(default)]
+    pub end_column: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GotoTargetsArguments {
+    pub source: Source,
+    pub line: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GotoArguments {
+    pub target_id: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetBreakpointsArguments {
+    pub source: Source,
+    #[serde(default)]
+    pub breakpoints: Option<Vec<SourceBreakpoint>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{from_value, json};
+
+    #[test]
+    fn unknown_fields_are_ignored() {
+        let arguments: EvaluateArguments = from_value(json!({
+            "expression": "x",
+            "frameId": 3,
+            "context": "hover",
+            "format": { "hex": true },
+            "line": 12,
+        }))
+        .unwrap();
+        assert_eq!(arguments.expression, "x");
+        assert_eq!(arguments.frame_id, Some(3));
+        assert_eq!(arguments.context.as_deref(), Some("hover"));
+
+        let arguments: InitializeArguments = from_value(json!({
+            "clientID": "vscode",
+            "clientName": "Visual Studio Code",
+            "supportsArgsCanBeInterpretedByShell": true,
+        }))
+        .unwrap();
+        assert_eq!(arguments.client_name.as_deref(), Some("Visual Studio Code"));
+    }
+
+    #[test]
+    fn missing_optional_fields_are_none() {
+        let arguments: CancelArguments = from_value(json!({})).unwrap();
+        assert_eq!(arguments.request_id, None);
+        assert_eq!(arguments.progress_id, None);
+
+        let arguments: SetBreakpointsArguments =
+            from_value(json!({ "source": { "path": "src/main.rs" } })).unwrap();
+        assert_eq!(arguments.source.path.as_deref(), Some("src/main.rs"));
+        assert_eq!(arguments.source.source_reference, None);
+        assert!(arguments.breakpoints.is_none());
+
+        let arguments: ReadMemoryArguments =
+            from_value(json!({ "memoryReference": "0x20000000", "count": 4 })).unwrap();
+        assert_eq!(arguments.offset, None);
+
+        let breakpoint: SourceBreakpoint = from_value(json!({ "line": 7 })).unwrap();
+        let dap = breakpoint.to_dap();
+        assert_eq!(dap.line, 7);
+        assert!(dap.condition.is_none() && dap.log_message.is_none());
+    }
+
+    #[test]
+    fn missing_required_fields_fail() {
+        assert!(from_value::<ReadMemoryArguments>(json!({ "count": 4 })).is_err());
+        assert!(from_value::<StackTraceArguments>(json!({})).is_err());
+    }
+
+    #[test]
+    fn wrong_typed_optional_fields_fail() {
+        let error = from_value::<EvaluateArguments>(json!({
+            "expression": "x",
+            "frameId": "3",
+        }))
+        .unwrap_err();
+        assert!(error.to_string().contains("invalid type"), "{}", error);
+
+        assert!(from_value::<CancelArguments>(json!({ "requestId": true })).is_err());
+        assert!(from_value::<SetBreakpointsArguments>(json!({
+            "source": { "path": "src/main.rs" },
+            "breakpoints": { "line": 7 },
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn null_optional_fields_are_none() {
+        let arguments: WriteMemoryArguments = from_value(json!({
+            "memoryReference": "0x20000000",
+            "offset": null,
+            "allowPartial": null,
+            "data": "AAAA",
+        }))
+        .unwrap();
+        assert_eq!(arguments.offset, None);
+        assert_eq!(arguments.allow_partial, None);
+    }
+}
@@ -1,18 +1,41 @@
+mod arguments;
+mod base64;
+mod output_locations;
+mod persistent_sessions;
+mod repl_assignment;
+
+use std::collections::{BTreeMap, VecDeque};
 use std::path::PathBuf;
 
 use std::net::{SocketAddr, TcpListener};
 
-use crossbeam_channel::{unbounded, Receiver, Sender};
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
 
 use log::{debug, error, info, trace, warn};
 
+use arguments::{
+    BreakpointLocationsArguments, CancelArguments, CompletionsArguments,
+    DataBreakpointInfoArguments, DisassembleArguments, ErdbLogArguments, EvaluateArguments,
+    GotoArguments, GotoTargetsArguments, InitializeArguments, ReadMemoryArguments,
+    RestartFrameArguments, ScopesArguments, SetBreakpointsArguments, SetDataBreakpointsArguments,
+    SetExceptionBreakpointsArguments, SetExpressionArguments, SetFunctionBreakpointsArguments,
+    SetInstructionBreakpointsArguments, SetVariableArguments, SourceArguments, StackTraceArguments,
+    VariablesArguments, WriteMemoryArguments,
+};
+use output_locations::{OutputLocation, OutputLocations, DEFAULT_LOCATION_PATTERN};
+use persistent_sessions::{
+    is_connection_lost, ConnectionClosed, DebuggerLink, ParkedSessions, SessionKey,
+    DEFAULT_GRACE_PERIOD,
+};
+use repl_assignment::Assignment;
 use debugserver_types::{
-    Breakpoint, Capabilities, ContinueResponseBody, DisconnectArguments, EvaluateResponseBody,
-    Event, InitializeRequestArguments, InitializedEvent, ProtocolMessage, Request, Response,
-    SetBreakpointsArguments, SetBreakpointsResponseBody, StackTraceResponseBody, Thread,
+    Breakpoint, Capabilities, ContinueResponseBody, EvaluateResponseBody, Event,
+    ExceptionBreakpointsFilter, InitializedEvent, Request, Response, SetBreakpointsResponseBody, StackTraceResponseBody, Thread,
     ThreadsResponseBody,
 };
 
@@ -20,17 +43,28 @@ use std::io::{BufRead, BufReader, Read, Write};
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use serde_json::{from_slice, from_value, json, to_vec};
+use serde_json::{from_slice, from_value, json, to_vec, Value};
 
 use super::{
     commands::{
-        debug_event::DebugEvent, debug_request::DebugRequest, debug_response::DebugResponse,
-        Command,
+        commands::Commands, debug_event::DebugEvent, debug_request::DebugRequest,
+        debug_response::DebugResponse, Command,
+    },
+    debugger::{
+        cancel::{CancelToken, DISCONNECT_TIMEOUT},
+        config::SymbolFile,
+        reset_catch::RESET_DESCRIPTION,
+        resumes::ResumeCount,
+        semihosting::CommandLine,
+        symbols::ModuleInfo,
+        test_run::TestEvent,
+        trap_catch::ExceptionFilter,
+        write_policy::PeripheralWrites,
+        DebugHandler, VariableLocation,
     },
-    debugger::DebugHandler,
 };
 
-use probe_rs::HaltReason;
+use probe_rs::{CoreStatus, HaltReason};
 
 pub fn start_tcp_server(port: u16) -> Result<()> {
     info!("Starting debug-adapter server on port: {}", port);
@@ -38,39 +72,137 @@ pub fn start_tcp_server(port: u16) -> Result<()> {
     let addr = SocketAddr::from(([127, 0, 0, 1], port));
     let listener = TcpListener::bind(addr)?;
 
+    // The debugger of every running session, used to shut them all down on Ctrl-C.
+    let sessions: Sessions = Arc::new(Mutex::new(vec![]));
+    {
+        let sessions = sessions.clone();
+        ctrlc::set_handler(move || shutdown_sessions(&sessions))?;
+    }
+
+    // The sessions whose client went away, waiting for it to reconnect.
+    let parked = ParkedSessions::default();
+
+    let mut session_id = 0;
     loop {
         let (socket, addr) = listener.accept()?;
         socket.set_nonblocking(true)?;
-        info!("Accepted connection from {}", addr);
+        session_id += 1;
+        info!("Session {}: accepted connection from {}", session_id, addr);
 
         let reader = BufReader::new(socket.try_clone()?);
         let writer = socket;
 
-        start_debugger_and_adapter(reader, writer)?;
+        // Every connection is a separate session with its own debugger and probe.
+        let sessions = sessions.clone();
+        let parked = parked.clone();
+        let id = session_id;
+        thread::Builder::new()
+            .name(format!("session-{}", id))
+            .spawn(move || {
+                match start_debugger_and_adapter(reader, writer, id, sessions, parked) {
+                    Ok(_) => (),
+                    Err(err) => warn!("Session {} stoped because of error: {:?}", id, err),
+                };
+                info!("Session {} ended", id);
+            })?;
+    }
+}
+
+type Sessions = Arc<Mutex<Vec<(usize, Sender<DebugRequest>, CancelToken)>>>;
+
+fn shutdown_sessions(sessions: &Sessions) {
+    info!("Shutting down all sessions");
+    for (id, sender, cancel) in sessions.lock().unwrap().iter() {
+        cancel.cancel();
+        if sender.send(DebugRequest::Exit).is_err() {
+            warn!("Session {} has already stopped", id);
+        }
+    }
+
+    // Give the debuggers time to clear their breakpoints and release the probes
+    let start = Instant::now();
+    while !sessions.lock().unwrap().is_empty() && start.elapsed() < Duration::from_secs(2) {
+        thread::sleep(Duration::from_millis(50));
     }
+    std::process::exit(0);
 }
 
-fn start_debugger_and_adapter<R: Read, W: Write>(reader: BufReader<R>, writer: W) -> Result<()> {
+fn start_debugger_and_adapter<R: Read, W: Write>(
+    reader: BufReader<R>,
+    writer: W,
+    id: usize,
+    sessions: Sessions,
+    parked: ParkedSessions,
+) -> Result<()> {
     let (debugger_sender, debug_adapter_receiver): (Sender<Command>, Receiver<Command>) =
         unbounded();
     let (debug_adapter_sender, debugger_receiver): (Sender<DebugRequest>, Receiver<DebugRequest>) =
         unbounded();
 
+    let mut debugger = DebugHandler::new_default();
+    let cancel = debugger.cancel_token();
+    let resumes = debugger.resume_count();
+    sessions
+        .lock()
+        .unwrap()
+        .push((id, debug_adapter_sender.clone(), cancel.clone()));
+
     let debugger_th = thread::spawn(move || {
-        let mut debugger = DebugHandler::new_default();
         match debugger.run(debugger_sender, debugger_receiver) {
             Ok(_) => (),
             Err(err) => warn!("DebugThread stoped because of error: {:?}", err),
         };
-        info!("DebugThread stoped");
+        sessions
+            .lock()
+            .unwrap()
+            .retain(|(session_id, _, _)| *session_id != id);
+        info!("Session {}: DebugThread stoped", id);
     });
 
-    let mut da = DebugAdapter::new(reader, writer, debug_adapter_sender, debug_adapter_receiver);
-    match da.run() {
-        Ok(_) => (),
-        Err(err) => warn!("DebugAdapterThread stoped because of error: {:?}", err),
+    let mut da = DebugAdapter::new(
+        reader,
+        writer,
+        DebuggerLink {
+            id,
+            sender: debug_adapter_sender,
+            receiver: debug_adapter_receiver,
+            cancel,
+            resumes,
+        },
+        parked.clone(),
+    );
+    let connection_lost = match da.run() {
+        Ok(_) => false,
+        Err(err) if is_connection_lost(&err) => true,
+        Err(err) => {
+            warn!(
+                "Session {}: DebugAdapterThread stoped because of error: {:?}",
+                id, err
+            );
+            false
+        }
+    };
+    info!("Session {}: DebugAdapterThread stoped", id);
+
+    // Keep the debugger for the client to reconnect to, else the dropped channels end it
+    match (connection_lost, da.persistent_session.take()) {
+        (true, Some((key, grace_period))) => {
+            let link = da.into_link();
+            let debugger_id = link.id;
+            info!(
+                "Session {}: connection lost, keeping the debugger for {} ms",
+                id,
+                grace_period.as_millis()
+            );
+            parked.park(key, link, grace_period);
+            if let Some(link) = parked.wait(debugger_id) {
+                info!("Session {}: no client reconnected, ending the debugger", id);
+                link.cancel.cancel();
+                let _ = link.sender.send(DebugRequest::Exit);
+            }
+        }
+        _ => drop(da),
     };
-    info!("DebugAdapterThread stoped");
     debugger_th.join().expect("oops! the child thread panicked");
 
     Ok(())
@@ -82,22 +214,159 @@ pub struct DebugAdapter<R: Read, W: Write> {
     writer: W,
     sender: Sender<DebugRequest>,
     receiver: Receiver<Command>,
+    // Cancelled as soon as a disconnect arrives, also while a request is in flight.
+    cancel: CancelToken,
+    // The halts reported before the last resume of the core are dropped.
+    resumes: ResumeCount,
+    // Read while waiting for a response, handled after it.
+    pending: VecDeque<DebugAdapterMessage>,
+    // The seq of the request waiting for a response from the debugger.
+    in_flight: Option<i64>,
+    // The requests cancelled before they were answered, answered with `cancelled`.
+    cancelled: Vec<i64>,
+    client_capabilities: Option<serde_json::Value>,
+    output_locations: OutputLocations,
+    coalesce_stopped_events: bool,
+    // Launched without debugging, breakpoints are not set.
+    no_debug: bool,
+    // Whether the core was halted after the reset at launch or attach, a restart does the same.
+    halt_after_reset: bool,
+    // The id of the debugger the channels go to, another session's after a reconnect.
+    debugger_id: usize,
+    parked: ParkedSessions,
+    // Kept alive for a grace period if the connection drops, see `persistent_sessions`.
+    persistent_session: Option<(SessionKey, Duration)>,
 }
 
+// From the Cargo.lock, "unknown" if the build had none.
+const PROBE_RS_VERSION: &str = env!("ERDB_PROBE_RS_VERSION");
+
+// The requests this adapter supports that are not part of DAP
+const CUSTOM_REQUESTS: &[&str] = &["erdbInfo", "erdbCommand", "erdbLog"];
+
+// The events this adapter sends that are not part of DAP
+const CUSTOM_EVENTS: &[&str] = &["erdbTest"];
+
+// How often the DAP input is checked while waiting for a response.
+const RESPONSE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 impl<R: Read, W: Write> DebugAdapter<R, W> {
     pub fn new(
         reader: BufReader<R>,
         writer: W,
-        sender: Sender<DebugRequest>,
-        receiver: Receiver<Command>,
+        link: DebuggerLink,
+        parked: ParkedSessions,
     ) -> DebugAdapter<R, W> {
         DebugAdapter {
             seq: 0,
             reader: reader,
             writer: writer,
-            sender: sender,
-            receiver: receiver,
+            sender: link.sender,
+            receiver: link.receiver,
+            cancel: link.cancel,
+            resumes: link.resumes,
+            pending: VecDeque::new(),
+            in_flight: None,
+            cancelled: vec![],
+            client_capabilities: None,
+            output_locations: OutputLocations::new(DEFAULT_LOCATION_PATTERN).unwrap(),
+            coalesce_stopped_events: true,
+            no_debug: false,
+            halt_after_reset: false,
+            debugger_id: link.id,
+            parked,
+            persistent_session: None,
+        }
+    }
+
+    /// The channels to the debugger, for parking it.
+    fn into_link(self) -> DebuggerLink {
+        DebuggerLink {
+            id: self.debugger_id,
+            sender: self.sender,
+            receiver: self.receiver,
+            cancel: self.cancel,
+            resumes: self.resumes,
+        }
+    }
+
+    /// Sets the session to be kept for a reconnect, and takes over a kept session with the same
+    /// key. Returns true if one was taken over, it is already attached.
+    fn persist_session(
+        &mut self,
+        key: SessionKey,
+        enabled: Option<bool>,
+        grace_period_ms: Option<u64>,
+    ) -> Result<bool> {
+        if enabled.unwrap_or(false) {
+            let grace_period = grace_period_ms
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_GRACE_PERIOD);
+            self.persistent_session = Some((key.clone(), grace_period));
+        }
+
+        let link = match self.parked.reclaim(&key) {
+            Some(val) => val,
+            None => return Ok(false),
+        };
+        info!("Reconnected to the session of debugger {}", link.id);
+
+        // The debugger started for this connection is not needed
+        self.cancel.cancel();
+        self.sender.send(DebugRequest::Exit)?;
+        let _ = self.retrieve_exit_response(DISCONNECT_TIMEOUT)?;
+
+        self.sender = link.sender;
+        self.receiver = link.receiver;
+        self.cancel = link.cancel;
+        self.resumes = link.resumes;
+        self.debugger_id = link.id;
+
+        // The events while no client was connected were for the old one
+        let stale = self.receiver.try_iter().count();
+        debug!("Dropped {} events of the lost connection", stale);
+
+        Ok(true)
+    }
+
+    /// Reports the breakpoints and the halted core of a session taken over after a reconnect.
+    fn report_session_state(&mut self) -> Result<()> {
+        self.send_output_event(
+            "console",
+            "Reconnected to the running debug session, the program was not flashed again",
+        )?;
+
+        self.sender.send(DebugRequest::Breakpoints)?;
+        let breakpoints = match self.retrieve_response()? {
+            DebugResponse::Breakpoints { breakpoints, .. } => breakpoints,
+            _ => {
+                error!("Unreachable");
+                return Err(anyhow!("Unreachable"));
+            }
+        };
+        for (_, breakpoint, _) in breakpoints {
+            self.handle_event_command(DebugEvent::Breakpoint {
+                reason: "new".to_owned(),
+                breakpoint,
+            })?;
         }
+
+        self.sender.send(DebugRequest::Status)?;
+        if let DebugResponse::Status {
+            status: CoreStatus::Halted(reason),
+            pc: Some(pc),
+        } = self.retrieve_response()?
+        {
+            self.handle_event_command(DebugEvent::Halted {
+                pc,
+                reason,
+                hit_breakpoint_ids: None,
+                description: Some("Halted before the reconnect".to_owned()),
+                resume: self.resumes.current(),
+            })?;
+        }
+
+        Ok(())
     }
 
     fn init(&mut self) -> Result<()> {
@@ -109,6 +378,7 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                         res = val;
                         break;
                     }
+                    Err(err) if err.is::<ConnectionClosed>() => return Err(err),
                     Err(_) => continue,
                 };
             }
@@ -116,16 +386,52 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
         };
 
         let request = verify_init_msg(message)?;
+        self.client_capabilities = request.arguments.clone();
 
         let capabilities = Capabilities {
             supports_configuration_done_request: Some(true), // Supports config after init request
-            //            supports_data_breakpoints:              Some(true),
-            //        supportsCancelRequest:                  Some(true),
+            supports_step_back: Some(true), // Over the instructions in the instruction trace
+            supports_set_variable: Some(true),
+            supports_conditional_breakpoints: Some(true),
+            supports_hit_conditional_breakpoints: Some(true),
+            supports_log_points: Some(true),
+            supports_function_breakpoints: Some(true),
+            supports_data_breakpoints: Some(true),
+            supports_exception_info_request: Some(true),
+            supports_delayed_stack_trace_loading: Some(true),
+            supports_value_formatting_options: Some(true), // Parameters in the frame names
+            supports_cancel_request: Some(true),
+            exception_breakpoint_filters: Some(
+                ExceptionFilter::ALL
+                    .iter()
+                    .map(|filter| ExceptionBreakpointsFilter {
+                        filter: filter.name().to_owned(),
+                        label: filter.label().to_owned(),
+                        default: Some(filter.default_enabled()),
+                    })
+                    .collect(),
+            ),
             ..Default::default()
         };
 
+        let mut body = json!(capabilities);
+        body["supportsSteppingGranularity"] = json!(true);
+        body["supportsSetExpression"] = json!(true);
+        body["supportsInstructionBreakpoints"] = json!(true);
+        body["supportsBreakpointLocationsRequest"] = json!(true);
+        body["supportsDisassembleRequest"] = json!(true);
+        body["supportsReadMemoryRequest"] = json!(true);
+        body["supportsWriteMemoryRequest"] = json!(true);
+        body["supportsRestartRequest"] = json!(true);
+        body["supportsRestartFrame"] = json!(true);
+        body["supportsGotoTargetsRequest"] = json!(true);
+        body["supportsCompletionsRequest"] = json!(true);
+        body["supportsModulesRequest"] = json!(true);
+        body["supportsLoadedSourcesRequest"] = json!(true);
+        body["completionTriggerCharacters"] = json!(["."]);
+
         let resp = Response {
-            body: Some(json!(capabilities)),
+            body: Some(body),
             command: request.command.clone(),
             message: None,
             request_seq: request.seq,
@@ -147,23 +453,142 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
             self.seq,
         )?;
 
+        // Include the versions in every debug log
+        let info = format!("erdbInfo: {}", self.erdb_info());
+        self.send_output_event("console", &info)?;
+
         Ok(())
     }
 
+    fn erdb_info(&self) -> serde_json::Value {
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "gitHash": env!("ERDB_GIT_HASH"),
+            "probeRsVersion": PROBE_RS_VERSION,
+            "customRequests": CUSTOM_REQUESTS,
+            "customEvents": CUSTOM_EVENTS,
+            "monitorCommands": Commands::new().names(),
+            "clientCapabilities": self.client_capabilities,
+        })
+    }
+
+    fn handle_erdb_info_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let response = Response {
+            body: Some(self.erdb_info()),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    /// Runs a `DebugRequest` sent as JSON by an editor extension, see `DebugRequest` for the form.
+    fn handle_erdb_command_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let debug_request: DebugRequest = match &request.arguments {
+            Some(arguments) => from_value(arguments.clone())?,
+            None => return Err(anyhow!("erdbCommand requires a DebugRequest as arguments")),
+        };
+        if !erdb_command_allowed(&debug_request) {
+            return Err(anyhow!(
+                "{:?} can't be run through erdbCommand",
+                debug_request
+            ));
+        }
+
+        // Send the DebugRequest
+        self.sender.send(debug_request)?;
+
+        // Get DebugResponse
+        let debug_response = self.retrieve_response()?;
+
+        let response = Response {
+            body: Some(serde_json::to_value(&debug_response)?),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    /// Adjusts the log filter or returns the last lines logged, without restarting the server.
+    fn handle_erdb_log_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: ErdbLogArguments = get_arguments(request)?;
+        debug!("args: {:?}", args);
+
+        // Send log DebugRequest
+        let debug_request = match (args.tail, args.level) {
+            (Some(lines), _) => DebugRequest::LogTail { lines },
+            (None, Some(level)) => DebugRequest::SetLogLevel {
+                module: args.module,
+                level,
+            },
+            (None, None) => return Err(anyhow!("erdbLog requires a level or tail")),
+        };
+        self.sender.send(debug_request)?;
+
+        // Get log DebugResponse
+        let body = match self.retrieve_response()? {
+            DebugResponse::SetLogLevel { filter } => json!({ "filter": filter }),
+            DebugResponse::LogTail { lines } => json!({ "lines": lines }),
+            ack => {
+                error!("Unreachable: {:?}", ack);
+                return Err(anyhow!("Unreachable"));
+            }
+        };
+
+        let response = Response {
+            body: Some(body),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
     pub fn run(&mut self) -> Result<()> {
         self.init()?;
         loop {
-            // Check for events
-            match self.receiver.try_recv() {
-                Ok(Command::Event(event)) => self.handle_event_command(event)?,
-                Ok(_) => error!("Unreachable"),
-                Err(_) => (),
-            };
+            // Check for events, a batch of them is handled at once so stale stops can be dropped
+            let mut events = vec![];
+            for command in self.receiver.try_iter() {
+                match command {
+                    Command::Event(event) => events.push(event),
+                    _ => error!("Unreachable"),
+                };
+            }
+            if self.coalesce_stopped_events {
+                events = coalesce_stopped_events(events, self.resumes.current());
+            }
+            for event in events {
+                self.handle_event_command(event)?;
+            }
 
-            // Check for DAP messages
-            let message = match read_dap_msg(&mut self.reader) {
-                Ok(val) => val,
-                Err(_err) => continue,
+            // Check for DAP messages, the ones read while waiting for a response first
+            let message = match self.pending.pop_front() {
+                Some(val) => val,
+                None => match read_dap_msg(&mut self.reader) {
+                    Ok(val) => val,
+                    Err(err) if err.is::<ConnectionClosed>() => return Err(err),
+                    Err(_err) => continue,
+                },
             };
 
             let exit = self.handle_dap_message(message)?;
@@ -190,36 +615,75 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
     }
 
     fn handle_dap_request(&mut self, request: Request) -> Result<bool> {
+        // Cancelled while it was waiting behind another request
+        if let Some(index) = self.cancelled.iter().position(|seq| *seq == request.seq) {
+            self.cancelled.remove(index);
+            return self.send_cancelled_response(&request);
+        }
+
+        self.in_flight = Some(request.seq);
         let result = match request.command.as_ref() {
             "launch" => self.handle_launch_dap_request(&request),
             "attach" => self.handle_attach_dap_request(&request),
             "setBreakpoints" => self.handle_set_breakpoints_dap_request(&request),
+            "breakpointLocations" => self.handle_breakpoint_locations_dap_request(&request),
+            "disassemble" => self.handle_disassemble_dap_request(&request),
+            "readMemory" => self.handle_read_memory_dap_request(&request),
+            "writeMemory" => self.handle_write_memory_dap_request(&request),
+            "setFunctionBreakpoints" => self.handle_set_function_breakpoints_dap_request(&request),
+            "setInstructionBreakpoints" => {
+                self.handle_set_instruction_breakpoints_dap_request(&request)
+            }
             "threads" => self.handle_threads_dap_request(&request),
-            //          //  "setDataBreakpoints"        => Ok(()), // TODO
-            //          //  "setExceptionBreakpoints"   => Ok(()), // TODO
+            "dataBreakpointInfo" => self.handle_data_breakpoint_info_dap_request(&request),
+            "setDataBreakpoints" => self.handle_set_data_breakpoints_dap_request(&request),
+            "setExceptionBreakpoints" => self.handle_set_exception_breakpoints_dap_request(&request),
             "configurationDone" => self.handle_configuration_done_dap_request(&request),
             "pause" => self.handle_pause_dap_request(&request),
             "stackTrace" => self.handle_stack_trace_dap_request(&request),
             "disconnect" => self.handle_disconnect_dap_request(&request),
+            "restart" => self.handle_restart_dap_request(&request),
+            "restartFrame" => self.handle_restart_frame_dap_request(&request),
+            "gotoTargets" => self.handle_goto_targets_dap_request(&request),
+            "goto" => self.handle_goto_dap_request(&request),
             "continue" => self.handle_continue_dap_request(&request),
             "scopes" => self.handle_scopes_dap_request(&request),
-            "source" => {
-                error!("Unimpleemted");
-                Ok(false) // NOTE: Return Error maybe
-            }
+            "source" => self.handle_source_dap_request(&request),
             "variables" => self.handle_variables_dap_request(&request),
+            "setVariable" => self.handle_set_variable_dap_request(&request),
+            "setExpression" => self.handle_set_expression_dap_request(&request),
             "next" => self.handle_next_dap_request(&request),
-            "stepIn" => self.handle_next_dap_request(&request), // TODO
-            "stepOut" => self.handle_next_dap_request(&request), // TODO
+            // stepIn acts as next, except that an instruction step enters calls
+            "stepIn" => self.handle_next_dap_request(&request),
+            "stepOut" => self.handle_step_out_dap_request(&request),
+            "stepBack" => self.handle_step_back_dap_request(&request),
             "evaluate" => self.handle_evaluate_dap_request(&request),
+            "completions" => self.handle_completions_dap_request(&request),
+            "modules" => self.handle_modules_dap_request(&request),
+            "loadedSources" => self.handle_loaded_sources_dap_request(&request),
+            "exceptionInfo" => self.handle_exception_info_dap_request(&request),
+            "erdbInfo" => self.handle_erdb_info_dap_request(&request),
+            "erdbCommand" => self.handle_erdb_command_dap_request(&request),
+            "erdbLog" => self.handle_erdb_log_dap_request(&request),
+            "cancel" => self.handle_cancel_dap_request(&request),
             _ => {
                 error!("command: {}", request.command);
                 Ok(false) // NOTE: Return Error maybe
             }
         };
+        self.in_flight = None;
+        self.cancel.clear_request();
+        let cancelled = match self.cancelled.iter().position(|seq| *seq == request.seq) {
+            Some(index) => {
+                self.cancelled.remove(index);
+                true
+            }
+            None => false,
+        };
 
         match result {
             Ok(v) => Ok(v),
+            Err(_) if cancelled => self.send_cancelled_response(&request),
             Err(err) => {
                 warn!("Error when handeling DAP message: {}", err.to_string());
                 let response = Response {
@@ -246,17 +710,23 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                 pc: _,
                 reason,
                 hit_breakpoint_ids,
+                description: step_description,
+                resume: _,
             } => {
                 let (reason_str, description) = match reason {
                     HaltReason::Breakpoint => (
                         "breakpoint".to_owned(),
-                        Some("Target stopped due to breakpoint.".to_owned()),
+                        Some(breakpoint_description(hit_breakpoint_ids.as_deref())),
                     ),
+                    HaltReason::Step => ("step".to_owned(), None),
+                    HaltReason::Exception => ("exception".to_owned(), None),
+                    HaltReason::Request => ("pause".to_owned(), None),
+                    HaltReason::Watchpoint => ("data breakpoint".to_owned(), None),
                     _ => (format!("{:?}", reason), None),
                 };
                 let body = StoppedEventBody {
                     reason: reason_str,
-                    description: description,
+                    description: step_description.or(description),
                     thread_id: Some(0),
                     preserve_focus_hint: None,
                     text: None,
@@ -275,24 +745,206 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                     self.seq,
                 )?;
             }
+            DebugEvent::Output { category, output } => {
+                self.send_output_event(&category, &output)?;
+            }
+            DebugEvent::Progress { title, done, total } => {
+                let (event, body) = if done == 0 {
+                    ("progressStart", json!({ "progressId": title, "title": title, "percentage": 0 }))
+                } else if done >= total {
+                    ("progressEnd", json!({ "progressId": title }))
+                } else {
+                    (
+                        "progressUpdate",
+                        json!({ "progressId": title, "percentage": (done as u64 * 100) / total as u64 }),
+                    )
+                };
+                self.seq = send_data(
+                    &mut self.writer,
+                    &to_vec(&Event {
+                        body: Some(body),
+                        event: event.to_owned(),
+                        seq: self.seq,
+                        type_: "event".to_owned(),
+                    })?,
+                    self.seq,
+                )?;
+            }
+            DebugEvent::Breakpoint { reason, breakpoint } => {
+                self.seq = send_data(
+                    &mut self.writer,
+                    &to_vec(&Event {
+                        body: Some(json!({
+                            "reason": reason,
+                            "breakpoint": breakpoint,
+                        })),
+                        event: "breakpoint".to_owned(),
+                        seq: self.seq,
+                        type_: "event".to_owned(),
+                    })?,
+                    self.seq,
+                )?;
+            }
+            DebugEvent::Reset { pc, halted } => {
+                let action = match halted {
+                    true => "halted at",
+                    false => "resumed from",
+                };
+                self.send_output_event(
+                    "important",
+                    &format!("A {}, {} {:#010x}", RESET_DESCRIPTION, action, pc),
+                )?;
+                let (event, body) = match halted {
+                    true => (
+                        "stopped",
+                        json!(StoppedEventBody {
+                            reason: "entry".to_owned(),
+                            description: Some(RESET_DESCRIPTION.to_owned()),
+                            thread_id: Some(0),
+                            preserve_focus_hint: None,
+                            text: None,
+                            all_threads_stopped: Some(true),
+                            hit_breakpoint_ids: None,
+                        }),
+                    ),
+                    false => (
+                        "continued",
+                        json!({ "threadId": 0, "allThreadsContinued": true }),
+                    ),
+                };
+                self.seq = send_data(
+                    &mut self.writer,
+                    &to_vec(&Event {
+                        body: Some(body),
+                        event: event.to_owned(),
+                        seq: self.seq,
+                        type_: "event".to_owned(),
+                    })?,
+                    self.seq,
+                )?;
+            }
+            DebugEvent::Test { event } => {
+                let exit_code = match &event {
+                    TestEvent::Finished { exit_code, .. } => Some(*exit_code),
+                    _ => None,
+                };
+                self.seq = send_data(
+                    &mut self.writer,
+                    &to_vec(&Event {
+                        body: Some(json!(event)),
+                        event: "erdbTest".to_owned(),
+                        seq: self.seq,
+                        type_: "event".to_owned(),
+                    })?,
+                    self.seq,
+                )?;
+                // The exit code of the session is the result of the tests
+                if let Some(exit_code) = exit_code {
+                    self.seq = send_data(
+                        &mut self.writer,
+                        &to_vec(&Event {
+                            body: Some(json!({ "exitCode": exit_code })),
+                            event: "exited".to_owned(),
+                            seq: self.seq,
+                            type_: "event".to_owned(),
+                        })?,
+                        self.seq,
+                    )?;
+                }
+            }
         };
 
         Ok(())
     }
 
-    fn handle_launch_dap_request(&mut self, _request: &Request) -> Result<bool> {
-        error!("Unimplemented");
-        Ok(false) // NOTE: return error maybe
+    fn send_output_event(&mut self, category: &str, output: &str) -> Result<()> {
+        let lines = self
+            .output_locations
+            .lines(category, &format!("{}\n", output));
+
+        // Lines without a location are sent together, as they arrived.
+        let mut plain = String::new();
+        for line in lines {
+            match &line.location {
+                Some(location) => {
+                    if !plain.is_empty() {
+                        self.send_output_line(category, &std::mem::take(&mut plain), None)?;
+                    }
+                    self.send_output_line(category, &line.text, Some(location))?;
+                }
+                None => plain.push_str(&line.text),
+            }
+        }
+        if !plain.is_empty() {
+            self.send_output_line(category, &plain, None)?;
+        }
+
+        Ok(())
     }
 
-    fn handle_attach_dap_request(&mut self, request: &Request) -> Result<bool> {
-        let args: AttachRequestArguments = get_arguments(&request)?;
-        debug!("attach args: {:#?}", args);
-        info!("program: {:?}", args.program);
+    fn send_output_line(
+        &mut self,
+        category: &str,
+        output: &str,
+        location: Option<&OutputLocation>,
+    ) -> Result<()> {
+        let mut body = json!({
+            "category": category,
+            "output": output,
+        });
+        if let Some(location) = location {
+            body["source"] = json!({
+                "name": PathBuf::from(&location.path)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string()),
+                "path": location.path,
+            });
+            body["line"] = json!(location.line);
+            if let Some(column) = location.column {
+                body["column"] = json!(column);
+            }
+        }
+
+        self.seq = send_data(
+            &mut self.writer,
+            &to_vec(&Event {
+                body: Some(body),
+                event: "output".to_owned(),
+                seq: self.seq,
+                type_: "event".to_owned(),
+            })?,
+            self.seq,
+        )?;
+
+        Ok(())
+    }
+
+    fn handle_launch_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: LaunchRequestArguments = get_arguments(&request)?;
+        debug!("launch args: {:#?}", args);
+
+        // Take over the session of a client that reloaded instead of flashing again
+        let key = SessionKey::new(
+            None,
+            Some(args.chip.clone()),
+            Some(PathBuf::from(&args.program)),
+        );
+        if self.persist_session(
+            key,
+            args.persistent_session,
+            args.persistent_session_grace_ms,
+        )? {
+            self.no_debug = args.no_debug.unwrap_or(false);
+            self.halt_after_reset = !self.no_debug && args.halt_after_reset.unwrap_or(false);
+            self.output_locations
+                .set_cwd(args.cwd.as_ref().map(PathBuf::from));
+            return self.respond_reconnected(request);
+        }
 
-        // Set binary path
-        let path = PathBuf::from(args.program);
-        self.sender.send(DebugRequest::SetBinary { path: path })?;
+        // Set binary path, launch always flashes it
+        self.sender.send(DebugRequest::SetBinary {
+            path: PathBuf::from(&args.program),
+        })?;
 
         // Get DebugResponse
         let _ack = self.retrieve_response()?;
@@ -302,24 +954,340 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
             chip: args.chip.clone(),
         })?;
 
-        // Get DebugResponse
+        // Get DebugResponse, the chip name is resolved against the probe-rs registry
+        if let DebugResponse::SetChip { chip } = self.retrieve_response()? {
+            info!("Chip set to {}", chip);
+        }
+
+        self.output_locations
+            .set_cwd(args.cwd.as_ref().map(PathBuf::from));
+        if let Some(cwd) = args.cwd {
+            // Set cwd
+            self.sender.send(DebugRequest::SetCWD { cwd })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Answered to the SYS_GET_CMDLINE semihosting call of the firmware
+        if args.args.is_some() || args.env.is_some() {
+            self.sender.send(DebugRequest::SetCommandLine {
+                command_line: CommandLine {
+                    args: args.args.unwrap_or_default(),
+                    env: args.env.unwrap_or_default(),
+                },
+            })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Report the test results in the output of the firmware
+        if let Some(enabled) = args.test_run {
+            self.sender.send(DebugRequest::SetTestRun { enabled })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Flash a binary with a layout the chip description doesn't know
+        if let Some(enabled) = args.check_flash_layout {
+            self.sender
+                .send(DebugRequest::SetCheckFlashLayout { enabled })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Without debugging the program is flashed and run, no breakpoints are set
+        self.no_debug = args.no_debug.unwrap_or(false);
+
+        // Flash to chip, the core is halted after the reset or left running
+        self.halt_after_reset = !self.no_debug && args.halt_after_reset.unwrap_or(false);
+        self.sender.send(DebugRequest::Flash {
+            reset_and_halt: self.halt_after_reset,
+        })?;
+
+        // Get Flash DebugResponse
         let _ack = self.retrieve_response()?;
 
-        match args.cwd {
-            Some(cwd) => {
-                // Set cwd
-                self.sender.send(DebugRequest::SetCWD { cwd: cwd })?;
+        let response = Response {
+            body: None,
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        self.send_module_events()?;
+
+        Ok(false)
+    }
+
+    fn handle_attach_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: AttachRequestArguments = get_arguments(&request)?;
+        debug!("attach args: {:#?}", args);
+        info!("program: {:?}", args.program);
+
+        // Take over the session of a client that reloaded instead of attaching again
+        let key = SessionKey::new(
+            args.probe,
+            args.chip.clone(),
+            args.program.as_ref().map(PathBuf::from),
+        );
+        if args.core_dump.is_none()
+            && self.persist_session(
+                key,
+                args.persistent_session,
+                args.persistent_session_grace_ms,
+            )?
+        {
+            if let Some(coalesce) = args.coalesce_stopped_events {
+                self.coalesce_stopped_events = coalesce;
+            }
+            self.halt_after_reset = args.halt_after_reset.unwrap_or(false);
+            if let Some(pattern) = &args.output_location_pattern {
+                self.output_locations = OutputLocations::new(pattern)?;
+            }
+            self.output_locations
+                .set_cwd(args.cwd.as_ref().map(PathBuf::from));
+            return self.respond_reconnected(request);
+        }
+
+        // Set binary path, without one only raw target inspection is available
+        match args.program {
+            Some(program) => {
+                let path = PathBuf::from(program);
+                self.sender.send(DebugRequest::SetBinary { path: path })?;
 
                 // Get DebugResponse
                 let _ack = self.retrieve_response()?;
             }
-            None => (),
+            None => info!("No program set, attaching without a binary"),
         };
 
-        // Flash and attach or just attach to the core
-        match args.flash {
-            Some(true) => {
-                // Flash to chip
+        // Set the symbol files if they differ from the program
+        let mut symbol_files = vec![];
+        if let Some(path) = args.symbol_file {
+            symbol_files.push(SymbolFile {
+                path: PathBuf::from(path),
+                base_address: 0,
+            });
+        }
+        for symbol_file in args.symbol_files.unwrap_or_default() {
+            symbol_files.push(SymbolFile {
+                path: PathBuf::from(symbol_file.path),
+                base_address: symbol_file.base_address.unwrap_or(0),
+            });
+        }
+        if !symbol_files.is_empty() {
+            self.sender.send(DebugRequest::SetSymbolFiles {
+                symbol_files: symbol_files,
+            })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // A core dump replaces the chip and probe
+        self.sender.send(DebugRequest::SetCoreDump {
+            path: args.core_dump.as_ref().map(PathBuf::from),
+        })?;
+
+        // Get DebugResponse
+        let _ack = self.retrieve_response()?;
+
+        // Select the probe, needed when the server has several boards connected
+        if let Some(number) = args.probe {
+            self.sender.send(DebugRequest::SetProbeNumber { number })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Wait for a probe held by another process to be released
+        if let Some(enabled) = args.force_claim {
+            self.sender.send(DebugRequest::SetForceClaim { enabled })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Set how the running target is polled
+        if args.status_poll_interval_ms.is_some() || args.low_power_mode.is_some() {
+            self.sender.send(DebugRequest::SetPolling {
+                status_interval_ms: args.status_poll_interval_ms,
+                low_power: args.low_power_mode,
+            })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Set where the heap statistics are read from
+        if args.heap_symbol.is_some() || args.heap_stats_on_stop.is_some() {
+            self.sender.send(DebugRequest::SetHeapOptions {
+                symbol: args.heap_symbol.clone(),
+                stats_on_stop: args.heap_stats_on_stop,
+            })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Set what happens to breakpoints in interrupt handlers while stepping
+        if let Some(mode) = &args.breakpoints_during_step {
+            self.sender.send(DebugRequest::SetStepBreakpoints {
+                mode: mode.parse()?,
+            })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Set the functions to stop in besides the defaults
+        if let Some(functions) = &args.catch_functions {
+            self.sender.send(DebugRequest::SetCatchFunctions {
+                functions: functions.clone(),
+            })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Set the deadline of the probe operations
+        if let Some(timeout_ms) = args.probe_timeout_ms {
+            self.sender
+                .send(DebugRequest::SetProbeTimeout { timeout_ms })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Set the core clock for converting cycles to time
+        if let Some(hz) = args.core_clock_hz {
+            self.sender.send(DebugRequest::SetCoreClock { hz })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Set what is done with the core on disconnect
+        if let Some(action) = &args.disconnect_action {
+            self.sender.send(DebugRequest::SetDisconnectAction {
+                action: action.parse()?,
+            })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Resume after a reset the target did by itself instead of halting
+        if let Some(enabled) = args.halt_on_reset {
+            self.sender.send(DebugRequest::SetHaltOnReset { enabled })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Flash a binary with a layout the chip description doesn't know
+        if let Some(enabled) = args.check_flash_layout {
+            self.sender
+                .send(DebugRequest::SetCheckFlashLayout { enabled })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Record stepped instructions for stepBack
+        if let Some(length) = args.instruction_trace {
+            self.sender
+                .send(DebugRequest::SetInstructionTrace { length })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Read flash from the target, for programs that write their own flash
+        if let Some(enabled) = args.live_flash_reads {
+            self.sender
+                .send(DebugRequest::SetLiveFlashReads { enabled })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Show the memory region of addresses
+        if let Some(enabled) = args.region_annotations {
+            self.sender
+                .send(DebugRequest::SetRegionAnnotations { enabled })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Report the test results in the output of the firmware
+        if let Some(enabled) = args.test_run {
+            self.sender.send(DebugRequest::SetTestRun { enabled })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Writes to peripheral registers need a confirmation unless allowed or denied
+        if let Some(policy) = args.peripheral_writes {
+            self.sender
+                .send(DebugRequest::SetPeripheralWrites { policy })?;
+
+            // Get DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Set chip
+        match (&args.chip, &args.core_dump) {
+            (Some(chip), _) => {
+                self.sender.send(DebugRequest::SetChip { chip: chip.clone() })?;
+
+                // Get DebugResponse, the chip name is resolved against the probe-rs registry
+                if let DebugResponse::SetChip { chip } = self.retrieve_response()? {
+                    info!("Chip set to {}", chip);
+                }
+            }
+            (None, Some(_)) => (),
+            (None, None) => return Err(anyhow!("Attach requires a chip or a core dump")),
+        };
+
+        if let Some(coalesce) = args.coalesce_stopped_events {
+            self.coalesce_stopped_events = coalesce;
+        }
+        self.halt_after_reset = args.halt_after_reset.unwrap_or(false);
+
+        // Link output lines that start with a source location
+        if let Some(pattern) = &args.output_location_pattern {
+            self.output_locations = OutputLocations::new(pattern)?;
+        }
+        self.output_locations
+            .set_cwd(args.cwd.as_ref().map(PathBuf::from));
+
+        match args.cwd {
+            Some(cwd) => {
+                // Set cwd
+                self.sender.send(DebugRequest::SetCWD { cwd: cwd })?;
+
+                // Get DebugResponse
+                let _ack = self.retrieve_response()?;
+            }
+            None => (),
+        };
+
+        // Flash and attach or just attach to the core
+        match args.flash {
+            Some(true) if args.core_dump.is_none() => {
+                // Flash to chip
                 self.sender.send(DebugRequest::Flash {
                     reset_and_halt: match args.halt_after_reset {
                         Some(val) => val,
@@ -348,8 +1316,28 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
             }
         };
 
+        // Restore a saved set of breakpoints
+        if let Some(path) = args.breakpoint_file {
+            self.sender.send(DebugRequest::LoadBreakpoints {
+                path: PathBuf::from(path),
+            })?;
+
+            // Get LoadBreakpoints DebugResponse
+            let _ack = self.retrieve_response()?;
+        }
+
+        // Get the memory usage of the binary for tooling
+        self.sender.send(DebugRequest::MemoryUsage)?;
+        let memory_usage = match self.retrieve_response()? {
+            DebugResponse::MemoryUsage { memory_usage } => memory_usage,
+            _ => {
+                error!("Unreachable");
+                return Err(anyhow!("Unreachable"));
+            }
+        };
+
         let response = Response {
-            body: None,
+            body: Some(json!({ "memoryUsage": memory_usage })),
             command: request.command.clone(),
             message: None,
             request_seq: request.seq,
@@ -360,6 +1348,267 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
 
         self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
 
+        self.send_module_events()?;
+
+        Ok(false)
+    }
+
+    /// Answers the launch or attach that took over a kept session, then reports its state.
+    fn respond_reconnected(&mut self, request: &Request) -> Result<bool> {
+        let response = Response {
+            body: Some(json!({ "reconnected": true })),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        self.send_module_events()?;
+        self.report_session_state()?;
+
+        Ok(false)
+    }
+
+    /// Resets the core in the same session, the breakpoints are kept. The program is flashed again
+    /// only if it changed on disk.
+    fn handle_restart_dap_request(&mut self, request: &Request) -> Result<bool> {
+        self.sender.send(DebugRequest::Restart {
+            reset_and_halt: self.halt_after_reset,
+        })?;
+        match self.retrieve_response()? {
+            DebugResponse::Restart { flashed } => info!("Restarted, flashed: {}", flashed),
+            ack => {
+                error!("Unreachable: {:?}", ack);
+                return Err(anyhow!("Unreachable"));
+            }
+        };
+
+        let response = Response {
+            body: None,
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        // A halted core is reported by the debugger with a stopped event
+        if !self.halt_after_reset {
+            self.seq = send_data(
+                &mut self.writer,
+                &to_vec(&Event {
+                    body: Some(json!({ "threadId": 0, "allThreadsContinued": true })),
+                    event: "continued".to_owned(),
+                    seq: self.seq,
+                    type_: "event".to_owned(),
+                })?,
+                self.seq,
+            )?;
+        }
+
+        Ok(false)
+    }
+
+    fn handle_restart_frame_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: RestartFrameArguments = get_arguments(request)?;
+        debug!("args: {:?}", args);
+
+        self.sender.send(DebugRequest::RestartFrame {
+            frame_id: Some(args.frame_id),
+        })?;
+        let pc = match self.retrieve_response()? {
+            DebugResponse::RestartFrame { function, pc } => {
+                info!("Restarted `{}`", function);
+                pc
+            }
+            ack => {
+                error!("Unreachable: {:?}", ack);
+                return Err(anyhow!("Unreachable"));
+            }
+        };
+
+        let response = Response {
+            body: None,
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        // The core stays halted, the client refreshes the stack on the stopped event
+        let body = StoppedEventBody {
+            reason: "restart".to_owned(),
+            description: Some(format!("Restarted at {:#010x}", pc)),
+            thread_id: Some(0),
+            preserve_focus_hint: None,
+            text: None,
+            all_threads_stopped: Some(true),
+            hit_breakpoint_ids: None,
+        };
+        self.seq = send_data(
+            &mut self.writer,
+            &to_vec(&Event {
+                body: Some(json!(body)),
+                event: "stopped".to_owned(),
+                seq: self.seq,
+                type_: "event".to_owned(),
+            })?,
+            self.seq,
+        )?;
+
+        Ok(false)
+    }
+
+    fn handle_goto_targets_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: GotoTargetsArguments = get_arguments(request)?;
+        debug!("args: {:?}", args);
+        let line = args.line;
+
+        let targets = match args.source.path {
+            Some(path) if !self.no_debug => {
+                self.sender.send(DebugRequest::GotoTargets {
+                    source_file: path,
+                    line,
+                })?;
+                match self.retrieve_response()? {
+                    DebugResponse::GotoTargets { targets } => targets,
+                    ack => {
+                        error!("Unreachable: {:?}", ack);
+                        return Err(anyhow!("Unreachable"));
+                    }
+                }
+            }
+            _ => vec![],
+        };
+
+        // The address is the id, goto needs nothing else
+        let targets: Vec<serde_json::Value> = targets
+            .iter()
+            .map(|(address, function)| {
+                let label = match function {
+                    Some(function) => format!("{:#010x} in {}", address, function),
+                    None => format!("{:#010x}", address),
+                };
+                json!({
+                    "id": address,
+                    "label": label,
+                    "line": line,
+                    "instructionPointerReference": format!("{:#010x}", address),
+                })
+            })
+            .collect();
+
+        let response = Response {
+            body: Some(json!({ "targets": targets })),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    fn handle_goto_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: GotoArguments = get_arguments(request)?;
+        debug!("args: {:?}", args);
+
+        self.sender.send(DebugRequest::Goto {
+            address: args.target_id as u32,
+        })?;
+        let (pc, warning) = match self.retrieve_response()? {
+            DebugResponse::Goto { pc, warning } => (pc, warning),
+            ack => {
+                error!("Unreachable: {:?}", ack);
+                return Err(anyhow!("Unreachable"));
+            }
+        };
+
+        let response = Response {
+            body: None,
+            command: request.command.clone(),
+            message: warning.clone(),
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        // Clients don't show the message of a successful response
+        if let Some(warning) = warning {
+            self.send_output_event("important", &format!("Warning: {}", warning))?;
+        }
+
+        // The core stays halted, the client refreshes the stack on the stopped event
+        let body = StoppedEventBody {
+            reason: "goto".to_owned(),
+            description: Some(format!("Jumped to {:#010x}", pc)),
+            thread_id: Some(0),
+            preserve_focus_hint: None,
+            text: None,
+            all_threads_stopped: Some(true),
+            hit_breakpoint_ids: None,
+        };
+        self.seq = send_data(
+            &mut self.writer,
+            &to_vec(&Event {
+                body: Some(json!(body)),
+                event: "stopped".to_owned(),
+                seq: self.seq,
+                type_: "event".to_owned(),
+            })?,
+            self.seq,
+        )?;
+
+        Ok(false)
+    }
+
+    fn handle_set_exception_breakpoints_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: SetExceptionBreakpointsArguments = get_arguments(request)?;
+        debug!("args: {:?}", args);
+
+        let filters = match self.no_debug {
+            true => vec![],
+            false => args
+                .filters
+                .iter()
+                .map(|filter| filter.parse())
+                .collect::<Result<Vec<ExceptionFilter>>>()?,
+        };
+        self.sender.send(DebugRequest::SetExceptionFilters { filters })?;
+
+        // Get DebugResponse
+        let _ack = self.retrieve_response()?;
+
+        let response = Response {
+            body: None,
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
         Ok(false)
     }
 
@@ -379,16 +1628,873 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
         Ok(false)
     }
 
-    fn handle_threads_dap_request(&mut self, request: &Request) -> Result<bool> {
-        let body = ThreadsResponseBody {
-            threads: vec![Thread {
-                id: 0,
-                name: "Main Thread".to_string(),
-            }],
-        };
+    fn handle_threads_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let body = ThreadsResponseBody {
+            threads: vec![Thread {
+                id: 0,
+                name: "Main Thread".to_string(),
+            }],
+        };
+
+        let response = Response {
+            body: Some(json!(body)),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    /// The request itself is cancelled when the cancel is read, see `read_pending_message`, by
+    /// the time it is handled the request has been answered.
+    fn handle_cancel_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: CancelArguments = get_arguments(&request)?;
+        debug!("args: {:?}", args);
+
+        let response = Response {
+            body: None,
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    fn send_cancelled_response(&mut self, request: &Request) -> Result<bool> {
+        let response = Response {
+            body: None,
+            command: request.command.clone(),
+            message: Some("cancelled".to_owned()),
+            request_seq: request.seq,
+            seq: self.seq,
+            success: false,
+            type_: "response".to_string(),
+        };
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    fn handle_pause_dap_request(&mut self, request: &Request) -> Result<bool> {
+        // Send halt DebugRequest
+        self.sender.send(DebugRequest::Halt)?;
+
+        // Get halt DebugResponse
+        let _ack = self.retrieve_response()?;
+
+        let response = Response {
+            body: None,
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    fn handle_stack_trace_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: StackTraceArguments = get_arguments(&request)?;
+        debug!("args: {:?}", args);
+
+        // Get DAP stack frames, only the frames of the page are evaluated
+        self.sender.send(DebugRequest::DAPStackFrames {
+            start_frame: args.start_frame.unwrap_or(0).max(0) as usize,
+            levels: match args.levels {
+                Some(levels) if levels > 0 => Some(levels as usize),
+                _ => None,
+            },
+            format: args.format,
+        })?;
+
+        // Get stack trace DebugResponse
+        let ack = self.retrieve_response()?;
+        let (stack_frames, total_frames) = match ack {
+            DebugResponse::DAPStackFrames {
+                stack_frames,
+                total_frames,
+            } => (stack_frames, total_frames),
+            _ => {
+                error!("Unreachable");
+                return Err(anyhow!("Unreachable"));
+            }
+        };
+
+        let body = StackTraceResponseBody {
+            stack_frames: stack_frames,
+            total_frames: Some(total_frames as i64),
+        };
+
+        let response = Response {
+            body: Some(json!(body)),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    fn handle_scopes_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: ScopesArguments = get_arguments(&request)?;
+        debug!("args: {:?}", args);
+
+        // Get stack trace
+        self.sender.send(DebugRequest::DAPScopes {
+            frame_id: args.frame_id,
+        })?;
+
+        // Get stack trace DebugResponse
+        let ack = self.retrieve_response()?;
+        let scopes = match ack {
+            DebugResponse::DAPScopes { scopes } => scopes,
+            _ => {
+                error!("Unreachable");
+                vec![]
+            }
+        };
+
+        let body = debugserver_types::ScopesResponseBody { scopes: scopes };
+
+        let response = Response {
+            body: Some(json!(body)),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    fn handle_variables_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: VariablesArguments = get_arguments(&request)?;
+        debug!("args: {:?}", args);
+
+        // Get stack trace
+        self.sender.send(DebugRequest::DAPVariables {
+            id: args.variables_reference,
+        })?;
+
+        // Get stack trace DebugResponse
+        let ack = self.retrieve_response()?;
+        let vars = match ack {
+            DebugResponse::DAPVariables { variables } => variables,
+            _ => {
+                error!("Unreachable");
+                return Err(anyhow!("Unreachable"));
+            }
+        };
+
+        // Parse variables
+        let mut variables = vec![];
+
+        for var in &vars {
+            let (indexed_variables, named_variables) = var.get_num_diff_children();
+            let mut variable = json!(debugserver_types::Variable {
+                evaluate_name: None, //Option<String>,
+                indexed_variables: Some(indexed_variables),
+                name: match &var.name {
+                    Some(name) => name.clone(),
+                    None => "<unknown>".to_string(),
+                },
+                named_variables: Some(named_variables),
+                presentation_hint: match var.read_only {
+                    true => Some(debugserver_types::VariablePresentationHint {
+                        attributes: Some(vec!["readOnly".to_owned()]),
+                        kind: None,
+                        visibility: None,
+                    }),
+                    false => None,
+                },
+                type_: Some(var.type_.clone()),
+                value: var.value_to_string(),
+                variables_reference: var.id, // i64,
+            });
+            // Opens the variable in the memory view
+            if let Some(VariableLocation::Memory { address, .. }) = var.location {
+                variable["memoryReference"] = json!(format!("{:#010x}", address));
+            }
+            variables.push(variable);
+        }
+
+        let response = Response {
+            body: Some(json!({ "variables": variables })),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    fn handle_continue_dap_request(&mut self, request: &Request) -> Result<bool> {
+        // Send continue DebugRequest
+        self.sender.send(DebugRequest::Continue)?;
+
+        // Get Continue DebugResponse
+        let _ack = self.retrieve_response()?;
+
+        let body = ContinueResponseBody {
+            all_threads_continued: Some(true),
+        };
+
+        let response = Response {
+            body: Some(json!(body)),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    fn handle_disconnect_dap_request(&mut self, request: &Request) -> Result<bool> {
+        debug!("args: {:?}", request.arguments);
+
+        // Stop the long operations first, then the debugger removes its breakpoints and does the
+        // disconnect action before the disconnect is answered.
+        self.cancel.cancel();
+        self.sender.send(DebugRequest::Exit)?;
+        if !self.retrieve_exit_response(DISCONNECT_TIMEOUT)? {
+            warn!(
+                "The debugger did not finish in {}s, disconnecting without it",
+                DISCONNECT_TIMEOUT.as_secs()
+            );
+            self.send_output_event(
+                "console",
+                "Warning: the debugger did not clean up in time, breakpoints may be left on the target",
+            )?;
+        }
+
+        let response = Response {
+            body: None,
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(true)
+    }
+
+    fn handle_next_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let granularity = request
+            .arguments
+            .as_ref()
+            .and_then(|args| args.get("granularity"))
+            .and_then(|granularity| granularity.as_str());
+
+        // Send Step DebugRequest
+        match granularity {
+            Some("instruction") => self.sender.send(DebugRequest::StepInstruction {
+                count: 1,
+                over_calls: request.command == "next",
+            })?,
+            _ => self.sender.send(DebugRequest::Step)?,
+        };
+
+        // Get Step DebugResponse
+        let _ack = self.retrieve_response()?;
+
+        let response = Response {
+            body: None,
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    fn handle_step_out_dap_request(&mut self, request: &Request) -> Result<bool> {
+        // Send Finish DebugRequest
+        self.sender.send(DebugRequest::Finish)?;
+
+        // Get Finish DebugResponse
+        let _ack = self.retrieve_response()?;
+
+        let response = Response {
+            body: None,
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    fn handle_step_back_dap_request(&mut self, request: &Request) -> Result<bool> {
+        // Send StepBack DebugRequest
+        self.sender.send(DebugRequest::StepBack)?;
+
+        // Get StepBack DebugResponse
+        let _ack = self.retrieve_response()?;
+
+        let response = Response {
+            body: None,
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    fn handle_set_variable_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: SetVariableArguments = get_arguments(&request)?;
+        debug!("args: {:?}", args);
+
+        self.sender.send(DebugRequest::SetVariable {
+            variables_reference: args.variables_reference,
+            name: args.name,
+            value: args.value,
+        })?;
+
+        let variable = match self.retrieve_response()? {
+            DebugResponse::SetVariable { variable } => variable,
+            ack => {
+                error!("Unreachable: {:?}", ack);
+                return Err(anyhow!("Unreachable"));
+            }
+        };
+
+        let response = Response {
+            body: Some(json!({
+                "value": variable.value_to_string(),
+                "type": variable.type_,
+            })),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        // A write through a pointer or to a register can change other shown values
+        self.invalidate("variables")?;
+
+        Ok(false)
+    }
+
+    fn handle_data_breakpoint_info_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: DataBreakpointInfoArguments = get_arguments(&request)?;
+        debug!("args: {:?}", args);
+
+        self.sender.send(DebugRequest::DataBreakpointInfo {
+            variables_reference: args.variables_reference,
+            name: args.name,
+        })?;
+
+        let body = match self.retrieve_response()? {
+            DebugResponse::DataBreakpointInfo {
+                data_id,
+                description,
+                access_types,
+            } => json!({
+                "dataId": data_id,
+                "description": description,
+                "accessTypes": access_types,
+                "canPersist": false,
+            }),
+            ack => {
+                error!("Unreachable: {:?}", ack);
+                return Err(anyhow!("Unreachable"));
+            }
+        };
+
+        let response = Response {
+            body: Some(body),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    fn handle_set_data_breakpoints_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: SetDataBreakpointsArguments = get_arguments(&request)?;
+        debug!("args: {:?}", args);
+
+        self.sender.send(DebugRequest::SetDataBreakpoints {
+            breakpoints: args.breakpoints,
+        })?;
+
+        let results = match self.retrieve_response()? {
+            DebugResponse::SetDataBreakpoints { breakpoints } => breakpoints,
+            ack => {
+                error!("Unreachable: {:?}", ack);
+                return Err(anyhow!("Unreachable"));
+            }
+        };
+
+        // Out of comparators is reported on the breakpoint, not as a failed request
+        let breakpoints: Vec<Value> = results
+            .into_iter()
+            .map(|result| {
+                json!({
+                    "id": result.id,
+                    "verified": result.id.is_some(),
+                    "message": result.message,
+                })
+            })
+            .collect();
+
+        let response = Response {
+            body: Some(json!({ "breakpoints": breakpoints })),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    fn handle_set_function_breakpoints_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: SetFunctionBreakpointsArguments = get_arguments(&request)?;
+        debug!("args: {:?}", args);
+
+        let breakpoints = match self.no_debug {
+            true => args
+                .breakpoints
+                .iter()
+                .map(|_| Breakpoint {
+                    id: None,
+                    verified: false,
+                    message: Some("Launched without debugging".to_owned()),
+                    source: None,
+                    line: None,
+                    column: None,
+                    end_line: None,
+                    end_column: None,
+                })
+                .collect(),
+            false => {
+                self.sender.send(DebugRequest::SetFunctionBreakpoints {
+                    breakpoints: args.breakpoints,
+                })?;
+                match self.retrieve_response()? {
+                    DebugResponse::SetFunctionBreakpoints { breakpoints } => breakpoints,
+                    ack => {
+                        error!("Unreachable: {:?}", ack);
+                        return Err(anyhow!("Unreachable"));
+                    }
+                }
+            }
+        };
+
+        let response = Response {
+            body: Some(json!(SetBreakpointsResponseBody { breakpoints })),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    fn handle_set_instruction_breakpoints_dap_request(
+        &mut self,
+        request: &Request,
+    ) -> Result<bool> {
+        let args: SetInstructionBreakpointsArguments = get_arguments(&request)?;
+        debug!("args: {:?}", args);
+
+        let breakpoints = match self.no_debug {
+            true => args
+                .breakpoints
+                .iter()
+                .map(|_| Breakpoint {
+                    id: None,
+                    verified: false,
+                    message: Some("Launched without debugging".to_owned()),
+                    source: None,
+                    line: None,
+                    column: None,
+                    end_line: None,
+                    end_column: None,
+                })
+                .collect(),
+            false => {
+                self.sender.send(DebugRequest::SetInstructionBreakpoints {
+                    breakpoints: args.breakpoints,
+                })?;
+                match self.retrieve_response()? {
+                    DebugResponse::SetInstructionBreakpoints { breakpoints } => breakpoints,
+                    ack => {
+                        error!("Unreachable: {:?}", ack);
+                        return Err(anyhow!("Unreachable"));
+                    }
+                }
+            }
+        };
+
+        // The resolved address goes back as the instruction reference of the breakpoint
+        let breakpoints: Vec<serde_json::Value> = breakpoints
+            .iter()
+            .map(|breakpoint| {
+                let mut value = json!(breakpoint);
+                if let Some(id) = breakpoint.id {
+                    value["instructionReference"] = json!(format!("{:#010x}", id));
+                }
+                value
+            })
+            .collect();
+
+        let response = Response {
+            body: Some(json!({ "breakpoints": breakpoints })),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    fn handle_set_expression_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: SetExpressionArguments = get_arguments(&request)?;
+        debug!("args: {:?}", args);
+
+        self.sender.send(DebugRequest::SetExpression {
+            expression: args.expression,
+            frame_id: args.frame_id,
+            value: args.value,
+        })?;
+
+        let variable = match self.retrieve_response()? {
+            DebugResponse::SetExpression { variable } => variable,
+            ack => {
+                error!("Unreachable: {:?}", ack);
+                return Err(anyhow!("Unreachable"));
+            }
+        };
+
+        let (indexed_variables, named_variables) = variable.get_num_diff_children();
+        let response = Response {
+            body: Some(json!({
+                "value": variable.value_to_string(),
+                "type": variable.type_,
+                "variablesReference": variable.id,
+                "indexedVariables": indexed_variables,
+                "namedVariables": named_variables,
+            })),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        // The Variables pane shows the same values
+        self.invalidate("variables")?;
+
+        Ok(false)
+    }
+
+    fn handle_evaluate_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: EvaluateArguments = get_arguments(&request)?;
+        debug!("args: {:?}", args);
+
+        if args.context.as_deref() == Some("hover") {
+            return self.handle_hover(request, &args);
+        }
+
+        // Expressions starting with `!` are monitor commands, e.g. `!load-symbols <path>`
+        let repl = args.context.as_deref() == Some("repl");
+        let assignment = match repl {
+            true => Assignment::parse(&args.expression)?,
+            false => None,
+        };
+        let command = match args.expression.strip_prefix('!') {
+            Some(command) => Some(command),
+            None if repl => repl_command(&args.expression),
+            None => None,
+        };
+        let body = match (command, assignment) {
+            (Some(command), _) => text_result(self.handle_monitor_command(command)?),
+            (None, Some(assignment)) => text_result(self.handle_assignment(assignment)?),
+            (None, None) => self.evaluate_variable(&args.expression, args.frame_id, false)?,
+        };
+
+        let response = Response {
+            body: Some(json!(body)),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    /// Completes the word before the cursor: a monitor command after `!`, else a variable, a field
+    /// after a `.` or a static. A lone word also completes the debug console keywords.
+    fn handle_completions_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: CompletionsArguments = get_arguments(request)?;
+        debug!("args: {:?}", args);
+
+        let cursor = (args.column as usize).saturating_sub(1);
+        let before: String = args.text.chars().take(cursor).collect();
+        let line = before.trim_start();
+        let mut targets = vec![];
+        let partial = match line.strip_prefix('!') {
+            Some(command) if !command.contains(char::is_whitespace) => {
+                let mut names = Commands::new().names();
+                names.push("bt");
+                for name in names.into_iter().filter(|name| name.starts_with(command)) {
+                    targets.push((name.to_owned(), "keyword"));
+                }
+                command.to_owned()
+            }
+            Some(_) => String::new(),
+            None => {
+                let word_start = before
+                    .rfind(|c: char| !(c.is_alphanumeric() || "_.:[]".contains(c)))
+                    .map(|index| index + 1)
+                    .unwrap_or(0);
+                let word = &before[word_start..];
+                if line == word && !word.contains('.') {
+                    for keyword in REPL_KEYWORDS.iter().filter(|k| k.starts_with(word)) {
+                        targets.push((keyword.to_string(), "keyword"));
+                    }
+                }
+                if !self.no_debug {
+                    self.sender.send(DebugRequest::Completions {
+                        text: word.to_owned(),
+                        frame_id: args.frame_id,
+                    })?;
+                    let kind = match word.contains('.') {
+                        true => "property",
+                        false => "variable",
+                    };
+                    match self.retrieve_response()? {
+                        DebugResponse::Completions { names } => {
+                            targets.extend(names.into_iter().map(|name| (name, kind)))
+                        }
+                        ack => {
+                            error!("Unreachable: {:?}", ack);
+                            return Err(anyhow!("Unreachable"));
+                        }
+                    };
+                }
+                word.rsplit('.').next().unwrap_or_default().to_owned()
+            }
+        };
+
+        // The name replaces the part of the word typed so far
+        let length = partial.encode_utf16().count() as u64;
+        let targets: Vec<serde_json::Value> = targets
+            .into_iter()
+            .map(|(label, kind)| {
+                json!({
+                    "label": label,
+                    "type": kind,
+                    "start": args.column.saturating_sub(length),
+                    "length": length,
+                })
+            })
+            .collect();
+
+        let response = Response {
+            body: Some(json!({ "targets": targets })),
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success: true,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    /// Hovers are frequent and their failures are not errors, they fail without a message.
+    fn handle_hover(&mut self, request: &Request, args: &EvaluateArguments) -> Result<bool> {
+        let expression = args.expression.trim();
+        let result = match is_member_path(expression) {
+            true => self.evaluate_variable(expression, args.frame_id, true),
+            false => Err(anyhow!("Not a variable path")),
+        };
+        let (body, success) = match result {
+            Ok(body) => (Some(json!(body)), true),
+            Err(err) => {
+                debug!("No hover for `{}`: {}", expression, err);
+                (None, false)
+            }
+        };
+
+        let response = Response {
+            body,
+            command: request.command.clone(),
+            message: None,
+            request_seq: request.seq,
+            seq: self.seq,
+            success,
+            type_: "response".to_string(),
+        };
+
+        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+
+        Ok(false)
+    }
+
+    fn evaluate_variable(
+        &mut self,
+        expression: &str,
+        frame_id: Option<i64>,
+        cached: bool,
+    ) -> Result<EvaluateResponseBody> {
+        self.sender.send(DebugRequest::Evaluate {
+            expression: expression.to_owned(),
+            frame_id,
+            cached,
+        })?;
+
+        let variable = match self.retrieve_response()? {
+            DebugResponse::Evaluate { variable } => variable,
+            ack => {
+                error!("Unreachable: {:?}", ack);
+                return Err(anyhow!("Unreachable"));
+            }
+        };
+
+        // Structured values are expanded through the variables request like in the scopes
+        let (indexed_variables, named_variables) = variable.get_num_diff_children();
+        Ok(EvaluateResponseBody {
+            result: variable.value_to_string(),
+            variables_reference: variable.id as f64,
+            type_: Some(variable.type_.clone()),
+            indexed_variables: Some(indexed_variables as f64),
+            named_variables: Some(named_variables as f64),
+            presentation_hint: None,
+        })
+    }
+
+    fn retrieve_modules(&mut self) -> Result<Vec<ModuleInfo>> {
+        // Send Modules DebugRequest
+        self.sender.send(DebugRequest::Modules)?;
+
+        // Get Modules DebugResponse
+        match self.retrieve_response()? {
+            DebugResponse::Modules { modules } => Ok(modules),
+            ack => {
+                error!("Unreachable: {:?}", ack);
+                Err(anyhow!("Unreachable"))
+            }
+        }
+    }
+
+    /// Fills the modules view of the client, which shows if the right firmware is loaded.
+    fn send_module_events(&mut self) -> Result<()> {
+        for module in self.retrieve_modules()? {
+            self.seq = send_data(
+                &mut self.writer,
+                &to_vec(&Event {
+                    body: Some(json!({ "reason": "new", "module": module_json(&module) })),
+                    event: "module".to_owned(),
+                    seq: self.seq,
+                    type_: "event".to_owned(),
+                })?,
+                self.seq,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn handle_modules_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let modules: Vec<serde_json::Value> =
+            self.retrieve_modules()?.iter().map(module_json).collect();
 
         let response = Response {
-            body: Some(json!(body)),
+            body: Some(json!({
+                "totalModules": modules.len(),
+                "modules": modules,
+            })),
             command: request.command.clone(),
             message: None,
             request_seq: request.seq,
@@ -402,15 +2508,32 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
         Ok(false)
     }
 
-    fn handle_pause_dap_request(&mut self, request: &Request) -> Result<bool> {
-        // Send halt DebugRequest
-        self.sender.send(DebugRequest::Halt)?;
+    fn handle_loaded_sources_dap_request(&mut self, request: &Request) -> Result<bool> {
+        // Send LoadedSources DebugRequest
+        self.sender.send(DebugRequest::LoadedSources)?;
 
-        // Get halt DebugResponse
-        let _ack = self.retrieve_response()?;
+        // Get LoadedSources DebugResponse
+        let sources = match self.retrieve_response()? {
+            DebugResponse::LoadedSources { sources } => sources,
+            ack => {
+                error!("Unreachable: {:?}", ack);
+                return Err(anyhow!("Unreachable"));
+            }
+        };
+
+        let sources: Vec<serde_json::Value> = sources
+            .iter()
+            .map(|path| {
+                let name = match path.file_name() {
+                    Some(val) => val.to_string_lossy().to_string(),
+                    None => path.display().to_string(),
+                };
+                json!({ "name": name, "path": path.display().to_string() })
+            })
+            .collect();
 
         let response = Response {
-            body: None,
+            body: Some(json!({ "sources": sources })),
             command: request.command.clone(),
             message: None,
             request_seq: request.seq,
@@ -418,36 +2541,39 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
             success: true,
             type_: "response".to_string(),
         };
+
         self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
 
         Ok(false)
     }
 
-    fn handle_stack_trace_dap_request(&mut self, request: &Request) -> Result<bool> {
-        let args: debugserver_types::StackTraceArguments = get_arguments(&request)?;
-        debug!("args: {:?}", args);
+    fn handle_exception_info_dap_request(&mut self, request: &Request) -> Result<bool> {
+        // Send ExceptionInfo DebugRequest
+        self.sender.send(DebugRequest::ExceptionInfo)?;
 
-        // Get DAP stack frames
-        self.sender.send(DebugRequest::DAPStackFrames)?;
-
-        // Get stack trace DebugResponse
-        let ack = self.retrieve_response()?;
-        let stack_frames = match ack {
-            DebugResponse::DAPStackFrames { stack_frames } => stack_frames,
-            _ => {
-                error!("Unreachable");
+        // Get ExceptionInfo DebugResponse
+        let status = match self.retrieve_response()? {
+            DebugResponse::ExceptionInfo { status } => status,
+            ack => {
+                error!("Unreachable: {:?}", ack);
                 return Err(anyhow!("Unreachable"));
             }
         };
 
-        let total_frames = stack_frames.len() as i64;
-        let body = StackTraceResponseBody {
-            stack_frames: stack_frames,
-            total_frames: Some(total_frames),
+        let exception = match &status.exception {
+            Some(val) => val.clone(),
+            None => "Thread mode".to_owned(),
         };
-
         let response = Response {
-            body: Some(json!(body)),
+            body: Some(json!({
+                "exceptionId": exception,
+                "description": status.summary(),
+                "breakMode": "always",
+                "details": {
+                    "message": status.to_string(),
+                    "typeName": exception,
+                },
+            })),
             command: request.command.clone(),
             message: None,
             request_seq: request.seq,
@@ -461,87 +2587,117 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
         Ok(false)
     }
 
-    fn handle_scopes_dap_request(&mut self, request: &Request) -> Result<bool> {
-        let args: debugserver_types::ScopesArguments = get_arguments(&request)?;
-        debug!("args: {:?}", args);
-
-        // Get stack trace
-        self.sender.send(DebugRequest::DAPScopes {
-            frame_id: args.frame_id,
-        })?;
+    fn handle_assignment(&mut self, assignment: Assignment) -> Result<String> {
+        self.sender.send(assignment.request())?;
 
-        // Get stack trace DebugResponse
-        let ack = self.retrieve_response()?;
-        let scopes = match ack {
-            DebugResponse::DAPScopes { scopes } => scopes,
-            _ => {
-                error!("Unreachable");
-                vec![]
+        let value = match self.retrieve_response()? {
+            DebugResponse::WriteRegister { value } => value,
+            DebugResponse::WriteWord { value } => value,
+            ack => {
+                error!("Unreachable: {:?}", ack);
+                return Err(anyhow!("Unreachable"));
             }
         };
 
-        let body = debugserver_types::ScopesResponseBody { scopes: scopes };
-
-        let response = Response {
-            body: Some(json!(body)),
-            command: request.command.clone(),
-            message: None,
-            request_seq: request.seq,
-            seq: self.seq,
-            success: true,
-            type_: "response".to_string(),
-        };
-
-        self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
+        // The shown registers, variables and stack are stale
+        self.invalidate("all")?;
 
-        Ok(false)
+        Ok(format!("{:#010x}", value))
     }
 
-    fn handle_variables_dap_request(&mut self, request: &Request) -> Result<bool> {
-        let args: debugserver_types::VariablesArguments = get_arguments(&request)?;
-        debug!("args: {:?}", args);
+    fn invalidate(&mut self, area: &str) -> Result<()> {
+        self.seq = send_data(
+            &mut self.writer,
+            &to_vec(&Event {
+                body: Some(json!({ "areas": [area] })),
+                event: "invalidated".to_owned(),
+                seq: self.seq,
+                type_: "event".to_owned(),
+            })?,
+            self.seq,
+        )?;
+        Ok(())
+    }
 
-        // Get stack trace
-        self.sender.send(DebugRequest::DAPVariables {
-            id: args.variables_reference,
-        })?;
+    fn handle_monitor_command(&mut self, command: &str) -> Result<String> {
+        let command = command.trim();
+        let (name, args) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+        let name = match name {
+            "bt" => "stack-trace",
+            _ => name,
+        };
+        let commands = Commands::new();
+        if !commands.names().contains(&name) {
+            return Err(anyhow!(
+                "Unknown command `{}`, the commands are: bt, {}",
+                name,
+                commands.names().join(", ")
+            ));
+        }
 
-        // Get stack trace DebugResponse
-        let ack = self.retrieve_response()?;
-        let vars = match ack {
-            DebugResponse::DAPVariables { variables } => variables,
+        let request = match commands.parse_command(&format!("{} {}", name, args))? {
+            Command::Request(req) => req,
             _ => {
                 error!("Unreachable");
                 return Err(anyhow!("Unreachable"));
             }
         };
 
-        // Parse variables
-        let mut variables = vec![];
+        // Send monitor DebugRequest
+        self.sender.send(request)?;
 
-        for var in &vars {
-            let (indexed_variables, named_variables) = var.get_num_diff_children();
-            variables.push(debugserver_types::Variable {
-                evaluate_name: None, //Option<String>,
-                indexed_variables: Some(indexed_variables),
-                name: match &var.name {
-                    Some(name) => name.clone(),
-                    None => "<unknown>".to_string(),
-                },
-                named_variables: Some(named_variables),
-                presentation_hint: None,
-                type_: Some(var.type_.clone()),
-                value: var.value_to_string(),
-                variables_reference: var.id, // i64,
-            });
+        // Get monitor DebugResponse
+        let ack = self.retrieve_response()?;
+
+        match ack {
+            DebugResponse::SetFormat
+            | DebugResponse::ClearFormats
+            | DebugResponse::LoadFormats { .. }
+            | DebugResponse::ReloadConfig { .. } => {
+                // The shown variables are formatted again
+                self.invalidate("variables")?;
+                Ok(ack.text())
+            }
+            _ => Ok(ack.text()),
         }
+    }
+
+    fn handle_breakpoint_locations_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: BreakpointLocationsArguments = get_arguments(request)?;
+        debug!("args: {:?}", args);
 
-        let body = debugserver_types::VariablesResponseBody {
-            variables: variables,
+        let locations = match args.source.path {
+            Some(path) if !self.no_debug => {
+                self.sender.send(DebugRequest::BreakpointLocations {
+                    source_file: path,
+                    line: args.line,
+                    column: args.column,
+                    end_line: args.end_line,
+                    end_column: args.end_column,
+                })?;
+                match self.retrieve_response()? {
+                    DebugResponse::BreakpointLocations { locations } => locations,
+                    ack => {
+                        error!("Unreachable: {:?}", ack);
+                        return Err(anyhow!("Unreachable"));
+                    }
+                }
+            }
+            _ => vec![],
         };
 
+        let breakpoints: Vec<serde_json::Value> = locations
+            .iter()
+            .map(|(line, column)| match column {
+                Some(column) => json!({ "line": line, "column": column }),
+                None => json!({ "line": line }),
+            })
+            .collect();
+
         let response = Response {
-            body: Some(json!(body)),
+            body: Some(json!({ "breakpoints": breakpoints })),
             command: request.command.clone(),
             message: None,
             request_seq: request.seq,
@@ -555,19 +2711,26 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
         Ok(false)
     }
 
-    fn handle_continue_dap_request(&mut self, request: &Request) -> Result<bool> {
-        // Send continue DebugRequest
-        self.sender.send(DebugRequest::Continue)?;
-
-        // Get Continue DebugResponse
-        let _ack = self.retrieve_response()?;
+    fn handle_disassemble_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: DisassembleArguments = get_arguments(request)?;
+        debug!("args: {:?}", args);
 
-        let body = ContinueResponseBody {
-            all_threads_continued: Some(true),
+        self.sender.send(DebugRequest::Disassemble {
+            memory_reference: args.memory_reference,
+            offset: args.offset.unwrap_or(0),
+            instruction_offset: args.instruction_offset.unwrap_or(0),
+            instruction_count: args.instruction_count,
+        })?;
+        let instructions = match self.retrieve_response()? {
+            DebugResponse::Disassemble { instructions } => instructions,
+            ack => {
+                error!("Unreachable: {:?}", ack);
+                return Err(anyhow!("Unreachable"));
+            }
         };
 
         let response = Response {
-            body: Some(json!(body)),
+            body: Some(json!({ "instructions": instructions })),
             command: request.command.clone(),
             message: None,
             request_seq: request.seq,
@@ -581,19 +2744,33 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
         Ok(false)
     }
 
-    fn handle_disconnect_dap_request(&mut self, request: &Request) -> Result<bool> {
-        let args: DisconnectArguments = get_arguments(&request)?;
+    fn handle_read_memory_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: ReadMemoryArguments = get_arguments(request)?;
         debug!("args: {:?}", args);
-        // TODO: Stop the debuggee, if conditions are meet
 
-        // Send Exit DebugRequest
-        self.sender.send(DebugRequest::Exit)?;
-
-        // Get Exit DebugResponse
-        let _ack = self.retrieve_response()?;
+        self.sender.send(DebugRequest::ReadMemory {
+            memory_reference: args.memory_reference,
+            offset: args.offset.unwrap_or(0),
+            count: args.count,
+        })?;
+        let body = match self.retrieve_response()? {
+            DebugResponse::ReadMemory {
+                address,
+                data,
+                unreadable_bytes,
+            } => json!({
+                "address": format!("{:#010x}", address),
+                "unreadableBytes": unreadable_bytes,
+                "data": base64::encode(&data),
+            }),
+            ack => {
+                error!("Unreachable: {:?}", ack);
+                return Err(anyhow!("Unreachable"));
+            }
+        };
 
         let response = Response {
-            body: None,
+            body: Some(body),
             command: request.command.clone(),
             message: None,
             request_seq: request.seq,
@@ -604,18 +2781,32 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
 
         self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
 
-        Ok(true)
+        Ok(false)
     }
 
-    fn handle_next_dap_request(&mut self, request: &Request) -> Result<bool> {
-        // Send Step DebugRequest
-        self.sender.send(DebugRequest::Step)?;
+    fn handle_source_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: SourceArguments = get_arguments(request)?;
+        debug!("args: {:?}", args);
 
-        // Get Step DebugResponse
-        let _ack = self.retrieve_response()?;
+        let source_reference = match args.source.and_then(|source| source.source_reference) {
+            Some(val) if val > 0 => val,
+            _ => args.source_reference,
+        };
+        self.sender
+            .send(DebugRequest::Source { source_reference })?;
+        let body = match self.retrieve_response()? {
+            DebugResponse::Source { content, mime_type } => match mime_type {
+                Some(mime_type) => json!({ "content": content, "mimeType": mime_type }),
+                None => json!({ "content": content }),
+            },
+            ack => {
+                error!("Unreachable: {:?}", ack);
+                return Err(anyhow!("Unreachable"));
+            }
+        };
 
         let response = Response {
-            body: None,
+            body: Some(body),
             command: request.command.clone(),
             message: None,
             request_seq: request.seq,
@@ -629,18 +2820,29 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
         Ok(false)
     }
 
-    fn handle_evaluate_dap_request(&mut self, request: &Request) -> Result<bool> {
-        let body = EvaluateResponseBody {
-            result: "This feature is not yet implemented".to_owned(),
-            variables_reference: 0.0,
-            type_: None,
-            indexed_variables: None,
-            named_variables: None,
-            presentation_hint: None,
+    fn handle_write_memory_dap_request(&mut self, request: &Request) -> Result<bool> {
+        let args: WriteMemoryArguments = get_arguments(request)?;
+        debug!("args: {:?}", args);
+
+        self.sender.send(DebugRequest::WriteMemory {
+            memory_reference: args.memory_reference,
+            offset: args.offset.unwrap_or(0),
+            data: base64::decode(&args.data)?,
+            allow_partial: args.allow_partial.unwrap_or(false),
+        })?;
+        let (address, bytes_written) = match self.retrieve_response()? {
+            DebugResponse::WriteMemory {
+                address,
+                bytes_written,
+            } => (address, bytes_written),
+            ack => {
+                error!("Unreachable: {:?}", ack);
+                return Err(anyhow!("Unreachable"));
+            }
         };
 
         let response = Response {
-            body: Some(json!(body)),
+            body: Some(json!({ "bytesWritten": bytes_written })),
             command: request.command.clone(),
             message: None,
             request_seq: request.seq,
@@ -651,6 +2853,24 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
 
         self.seq = send_data(&mut self.writer, &to_vec(&response)?, self.seq)?;
 
+        // The memory views showing the range read it again
+        if bytes_written > 0 {
+            self.seq = send_data(
+                &mut self.writer,
+                &to_vec(&Event {
+                    body: Some(json!({
+                        "memoryReference": format!("{:#010x}", address),
+                        "offset": 0,
+                        "count": bytes_written,
+                    })),
+                    event: "memory".to_owned(),
+                    seq: self.seq,
+                    type_: "event".to_owned(),
+                })?,
+                self.seq,
+            )?;
+        }
+
         Ok(false)
     }
 
@@ -658,29 +2878,57 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
         let args: SetBreakpointsArguments = get_arguments(request)?;
         debug!("args: {:#?}", args);
 
-        let source_breakpoints = match args.breakpoints {
-            Some(bkpts) => bkpts,
-            None => vec![],
-        };
+        let requested = args.breakpoints.unwrap_or_default();
+        let source_breakpoints: Vec<debugserver_types::SourceBreakpoint> =
+            requested.iter().map(|bkpt| bkpt.to_dap()).collect();
 
         let breakpoints: Vec<Breakpoint> = match args.source.path.clone() {
+            Some(_) if self.no_debug => source_breakpoints
+                .iter()
+                .map(|bkpt| Breakpoint {
+                    id: None,
+                    verified: false,
+                    message: Some("Launched without debugging".to_owned()),
+                    source: Some(args.source.to_dap()),
+                    line: Some(bkpt.line),
+                    column: bkpt.column,
+                    end_line: None,
+                    end_column: None,
+                })
+                .collect(),
             Some(path) => {
                 // Send SetBreakpoints DebugRequest
                 self.sender.send(DebugRequest::SetBreakpoints {
                     source_file: path,
                     source_breakpoints: source_breakpoints,
-                    source: Some(args.source.clone()),
+                    source: Some(args.source.to_dap()),
                 })?;
 
                 // Get SetBreakpoints DebugResponse
                 let ack = self.retrieve_response()?;
-                let breakpoints = match ack {
+                let mut breakpoints = match ack {
                     DebugResponse::SetBreakpoints { breakpoints } => breakpoints,
                     _ => {
                         error!("Unreachable: {:#?}", ack);
                         vec![]
                     }
                 };
+
+                // The command lists of the `commands` extension field
+                for (breakpoint, bkpt) in breakpoints.iter_mut().zip(&requested) {
+                    if let (true, Some(id), Some(commands)) =
+                        (breakpoint.verified, breakpoint.id, &bkpt.commands)
+                    {
+                        self.sender.send(DebugRequest::BreakpointCommands {
+                            id: id as u32,
+                            commands: commands.clone(),
+                        })?;
+                        if let Err(err) = self.retrieve_response() {
+                            warn!("Breakpoint commands not set: {}", err);
+                            breakpoint.message = Some(format!("Commands not set: {}", err));
+                        }
+                    }
+                }
                 breakpoints
             }
             None => vec![],
@@ -708,7 +2956,16 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
     fn retrieve_response(&mut self) -> Result<DebugResponse> {
         // Get DebugResponse
         loop {
-            let command = self.receiver.recv()?;
+            let command = match self.receiver.recv_timeout(RESPONSE_POLL_INTERVAL) {
+                Ok(val) => val,
+                Err(RecvTimeoutError::Timeout) => {
+                    self.read_pending_message();
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(anyhow!("The debugger has stopped"))
+                }
+            };
             match command {
                 Command::Response(response) => {
                     if let DebugResponse::Error { message } = response {
@@ -724,6 +2981,72 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
             };
         }
     }
+
+    /// Reads a DAP message that arrived during a request, a disconnect cancels the request and a
+    /// cancel cancels the request it names, in flight or waiting in `pending`.
+    fn read_pending_message(&mut self) {
+        let message = match read_dap_msg(&mut self.reader) {
+            Ok(val) => val,
+            Err(_err) => return,
+        };
+        if let DebugAdapterMessage::Request(request) = &message {
+            match request.command.as_str() {
+                "disconnect" => self.cancel.cancel(),
+                "cancel" => {
+                    if let Ok(CancelArguments {
+                        request_id: Some(id),
+                        ..
+                    }) = get_arguments(request)
+                    {
+                        self.cancel_request(id);
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.pending.push_back(message);
+    }
+
+    fn cancel_request(&mut self, id: i64) {
+        let waiting = self.pending.iter().any(|message| match message {
+            DebugAdapterMessage::Request(request) => request.seq == id,
+            _ => false,
+        });
+        if self.in_flight == Some(id) {
+            // Stops the operation at its next check
+            self.cancel.cancel_request();
+        } else if !waiting {
+            return;
+        }
+        if !self.cancelled.contains(&id) {
+            self.cancelled.push(id);
+        }
+    }
+
+    /// Waits for the debugger to handle the exit request, false if it did not in time.
+    fn retrieve_exit_response(&mut self, timeout: Duration) -> Result<bool> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self
+                .receiver
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(Command::Response(DebugResponse::Exit)) => return Ok(true),
+                // The answers to the requests cancelled by the disconnect
+                Ok(Command::Response(response)) => {
+                    debug!("Dropped on disconnect: {:?}", response)
+                }
+                Ok(Command::Event(event)) => self.handle_event_command(event)?,
+                Ok(_) => {
+                    error!("Unreachable");
+                    return Err(anyhow!("Unreachable"));
+                }
+                Err(RecvTimeoutError::Timeout) => return Ok(false),
+                // The debugger has already stopped
+                Err(RecvTimeoutError::Disconnected) => return Ok(true),
+            }
+        }
+    }
 }
 
 fn verify_init_msg(message: DebugAdapterMessage) -> Result<Request> {
@@ -736,7 +3059,7 @@ fn verify_init_msg(message: DebugAdapterMessage) -> Result<Request> {
                 ));
             }
 
-            let arguments: InitializeRequestArguments = get_arguments(&req)?;
+            let arguments: InitializeArguments = get_arguments(&req)?;
             debug!(
                 "Initialization request from client '{}'",
                 arguments.client_name.unwrap_or("<unknown>".to_owned())
@@ -751,7 +3074,9 @@ fn verify_init_msg(message: DebugAdapterMessage) -> Result<Request> {
 fn read_dap_msg<R: Read>(reader: &mut BufReader<R>) -> Result<DebugAdapterMessage, anyhow::Error> {
     let mut header = String::new();
 
-    reader.read_line(&mut header)?;
+    if reader.read_line(&mut header)? == 0 {
+        return Err(ConnectionClosed.into());
+    }
     trace!("< {}", header.trim_end());
 
     // we should read an empty line here
@@ -765,19 +3090,39 @@ fn read_dap_msg<R: Read>(reader: &mut BufReader<R>) -> Result<DebugAdapterMessag
     let _bytes_read = reader.read(&mut content)?;
 
     // Extract protocol message
-    let protocol_msg: ProtocolMessage = from_slice(&content)?;
-
-    let msg = match protocol_msg.type_.as_ref() {
-        "request" => DebugAdapterMessage::Request(from_slice(&content)?),
-        "response" => DebugAdapterMessage::Response(from_slice(&content)?),
-        "event" => DebugAdapterMessage::Event(from_slice(&content)?),
-        other => return Err(anyhow!("Unknown message type: {}", other)),
+    // Parse into a value first so fields the DAP types don't know about can't fail the message
+    let content: Value = from_slice(&content)?;
+
+    let msg = match content.get("type").and_then(Value::as_str) {
+        Some("request") => DebugAdapterMessage::Request(parse_request(&content)?),
+        Some("response") => DebugAdapterMessage::Response(from_value(content)?),
+        Some("event") => DebugAdapterMessage::Event(from_value(content)?),
+        other => return Err(anyhow!("Unknown message type: {:?}", other)),
     };
 
     trace!("< {:#?}", msg);
     Ok(msg)
 }
 
+/// Only the fields every request has are required, the arguments are parsed by each request.
+fn parse_request(content: &Value) -> Result<Request> {
+    let command = match content.get("command").and_then(Value::as_str) {
+        Some(val) => val.to_owned(),
+        None => return Err(anyhow!("Request without a command: {}", content)),
+    };
+    let seq = match content.get("seq").and_then(Value::as_i64) {
+        Some(val) => val,
+        None => return Err(anyhow!("Request without a seq: {}", content)),
+    };
+
+    Ok(Request {
+        arguments: content.get("arguments").cloned(),
+        command,
+        seq,
+        type_: "request".to_owned(),
+    })
+}
+
 fn get_content_len(header: &str) -> Option<usize> {
     let mut parts = header.trim_end().split_ascii_whitespace();
 
@@ -793,15 +3138,14 @@ pub enum DebugAdapterMessage {
     Event(Event),
 }
 
+/// Parses the arguments of a request, missing arguments parse as an empty object so requests
+/// with only optional arguments don't fail.
 pub fn get_arguments<T: DeserializeOwned>(req: &Request) -> Result<T> {
     let value = match req.arguments.as_ref() {
-        Some(val) => val,
-        None => {
-            error!("Expacted arguments");
-            return Err(anyhow!("Expected arguments"));
-        }
+        Some(val) => val.to_owned(),
+        None => json!({}),
     };
-    from_value(value.to_owned()).map_err(|e| e.into())
+    from_value(value).map_err(|e| anyhow!("Invalid arguments for the {} request: {}", req.command, e))
 }
 
 pub fn send_data<W: Write>(writer: &mut W, raw_data: &[u8], seq: i64) -> Result<i64> {
@@ -834,12 +3178,70 @@ pub struct StoppedEventBody {
 
 #[derive(Deserialize, Debug, Default)]
 struct AttachRequestArguments {
-    program: String,
-    chip: String,
+    program: Option<String>,
+    #[serde(rename = "symbolFile")]
+    symbol_file: Option<String>,
+    #[serde(rename = "symbolFiles")]
+    symbol_files: Option<Vec<SymbolFileArgument>>,
+    chip: Option<String>,
+    #[serde(rename = "coreDump")]
+    core_dump: Option<String>,
+    probe: Option<usize>,
+    #[serde(rename = "forceClaim")]
+    force_claim: Option<bool>,
+    #[serde(rename = "statusPollIntervalMs")]
+    status_poll_interval_ms: Option<u64>,
+    #[serde(rename = "lowPowerMode")]
+    low_power_mode: Option<bool>,
+    #[serde(rename = "heapSymbol")]
+    heap_symbol: Option<String>,
+    #[serde(rename = "heapStatsOnStop")]
+    heap_stats_on_stop: Option<bool>,
+    #[serde(rename = "breakpointsDuringStep")]
+    breakpoints_during_step: Option<String>,
+    #[serde(rename = "outputLocationPattern")]
+    output_location_pattern: Option<String>,
+    #[serde(rename = "catchFunctions")]
+    catch_functions: Option<Vec<String>>,
+    #[serde(rename = "instructionTrace")]
+    instruction_trace: Option<usize>,
+    #[serde(rename = "probeTimeoutMs")]
+    probe_timeout_ms: Option<u64>,
+    #[serde(rename = "coreClockHz")]
+    core_clock_hz: Option<u32>,
+    #[serde(rename = "disconnectAction")]
+    disconnect_action: Option<String>,
+    #[serde(rename = "haltOnReset")]
+    halt_on_reset: Option<bool>,
+    #[serde(rename = "checkFlashLayout")]
+    check_flash_layout: Option<bool>,
+    #[serde(rename = "liveFlashReads")]
+    live_flash_reads: Option<bool>,
+    #[serde(rename = "regionAnnotations")]
+    region_annotations: Option<bool>,
+    #[serde(rename = "testRun")]
+    test_run: Option<bool>,
+    #[serde(rename = "peripheralWrites")]
+    peripheral_writes: Option<PeripheralWrites>,
+    #[serde(rename = "coalesceStoppedEvents")]
+    coalesce_stopped_events: Option<bool>,
     cwd: Option<String>,
     reset: Option<bool>,
     halt_after_reset: Option<bool>,
     flash: Option<bool>,
+    #[serde(rename = "breakpointFile")]
+    breakpoint_file: Option<String>,
+    #[serde(rename = "persistentSession")]
+    persistent_session: Option<bool>,
+    #[serde(rename = "persistentSessionGraceMs")]
+    persistent_session_grace_ms: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct SymbolFileArgument {
+    path: String,
+    base_address: Option<u32>,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -847,7 +3249,343 @@ struct LaunchRequestArguments {
     program: String,
     chip: String,
     cwd: Option<String>,
-    reset: Option<bool>,
+    #[serde(rename = "noDebug")]
     no_debug: Option<bool>,
     halt_after_reset: Option<bool>,
+    // The command line of semihosting firmware.
+    args: Option<Vec<String>>,
+    env: Option<BTreeMap<String, String>>,
+    // Report the defmt-test results of the firmware, see `test_run`.
+    #[serde(rename = "testRun")]
+    test_run: Option<bool>,
+    #[serde(rename = "checkFlashLayout")]
+    check_flash_layout: Option<bool>,
+    #[serde(rename = "persistentSession")]
+    persistent_session: Option<bool>,
+    #[serde(rename = "persistentSessionGraceMs")]
+    persistent_session_grace_ms: Option<u64>,
+}
+
+/// Keeps only the last stop of a batch of events, the earlier ones were followed by a resume and
+/// no longer describe the target. A stop reported before the core last resumed, `resume` being
+/// the resume count of the core now, is dropped as well. The other events are kept in order.
+fn coalesce_stopped_events(events: Vec<DebugEvent>, resume: u64) -> Vec<DebugEvent> {
+    let last_halt = events.iter().rposition(|event| match event {
+        DebugEvent::Halted { resume: halted, .. } => *halted >= resume,
+        _ => false,
+    });
+
+    let total = events.len();
+    let events: Vec<DebugEvent> = events
+        .into_iter()
+        .enumerate()
+        .filter(|(i, event)| Some(*i) == last_halt || !matches!(event, DebugEvent::Halted { .. }))
+        .map(|(_, event)| event)
+        .collect();
+    if events.len() < total {
+        debug!("Dropped {} stale stopped events", total - events.len());
+    }
+    events
+}
+
+/// The requests extensions can run with `erdbCommand`. Requests that change the session, control
+/// execution or change state, like the breakpoints, the data breakpoints, the snapshots, the watch
+/// sampling and the trace buffer, are left out, and so are requests writing files to paths the
+/// caller picks.
+fn erdb_command_allowed(request: &DebugRequest) -> bool {
+    matches!(
+        request,
+        DebugRequest::Status
+            | DebugRequest::History
+            | DebugRequest::StopStats
+            | DebugRequest::Breakpoints
+            | DebugRequest::VerifyBreakpoints
+            | DebugRequest::Snapshots
+            | DebugRequest::Modules
+            | DebugRequest::MemoryUsage
+            | DebugRequest::Read { .. }
+            | DebugRequest::HeapStats
+            | DebugRequest::VectorTableInfo
+            | DebugRequest::SectionMap
+            | DebugRequest::LoadedSources
+            | DebugRequest::ExceptionInfo
+            | DebugRequest::TargetInfo
+            | DebugRequest::Intrusiveness
+            | DebugRequest::Formats
+            | DebugRequest::ExportStopState { path: None, .. }
+            | DebugRequest::Registers
+            | DebugRequest::Watchpoints
+            | DebugRequest::WatchBreaks
+            | DebugRequest::Code
+            | DebugRequest::Disassemble { .. }
+            | DebugRequest::Stack
+            | DebugRequest::CycleCounter
+            | DebugRequest::BreakpointLocations { .. }
+            | DebugRequest::TraceRead { .. }
+    )
+}
+
+/// Debug console input starting with these is run as a monitor command instead of evaluated.
+const REPL_KEYWORDS: [&str; 6] = ["reset", "halt", "continue", "flash", "status", "bt"];
+
+/// The monitor command in debug console input, `monitor <command>` or one of the keywords.
+fn repl_command(expression: &str) -> Option<&str> {
+    let expression = expression.trim();
+    if let Some(command) = expression.strip_prefix("monitor") {
+        if command.is_empty() || command.starts_with(char::is_whitespace) {
+            return Some(command);
+        }
+    }
+    let keyword = expression.split_whitespace().next()?;
+    match REPL_KEYWORDS.contains(&keyword) {
+        true => Some(expression),
+        false => None,
+    }
+}
+
+/// A plain identifier or a member path like `a.b.c`, array elements are members named by index.
+fn is_member_path(expression: &str) -> bool {
+    let mut parts = expression.split('.');
+    let first = parts.next().unwrap_or_default();
+    let is_identifier =
+        |part: &str| !part.is_empty() && part.chars().all(|c| c.is_alphanumeric() || c == '_');
+    is_identifier(first)
+        && !first.starts_with(|c: char| c.is_ascii_digit())
+        && parts.all(is_identifier)
+}
+
+/// A DAP module, the build id is shown as its version.
+fn module_json(module: &ModuleInfo) -> Value {
+    // The flashed sections show where the firmware is, `.text` if none of them is in flash
+    let address_range = module
+        .flash_range
+        .as_ref()
+        .or(module.address_range.as_ref())
+        .map(|range| format!("{:#010x}-{:#010x}", range.start, range.end));
+    json!({
+        "id": module.id,
+        "name": match module.path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => module.path.display().to_string(),
+        },
+        "path": module.path.display().to_string(),
+        "symbolStatus": match module.has_debug_info {
+            true => "Symbols loaded.",
+            false => "No debug information, only the ELF symbols.",
+        },
+        "symbolFilePath": module.path.display().to_string(),
+        "version": module.build_id,
+        "addressRange": address_range,
+    })
+}
+
+fn text_result(result: String) -> EvaluateResponseBody {
+    EvaluateResponseBody {
+        result,
+        variables_reference: 0.0,
+        type_: None,
+        indexed_variables: None,
+        named_variables: None,
+        presentation_hint: None,
+    }
+}
+
+/// Names all the breakpoints hit, several breakpoints can share an address.
+fn breakpoint_description(hit_breakpoint_ids: Option<&[u32]>) -> String {
+    match hit_breakpoint_ids {
+        Some(ids) if !ids.is_empty() => format!(
+            "Target stopped due to breakpoint{} {}.",
+            if ids.len() > 1 { "s" } else { "" },
+            ids.iter()
+                .map(|id| format!("{:#010x}", id))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        _ => "Target stopped due to breakpoint.".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The JSON form of every request `erdbCommand` runs.
+    const ALLOWED: &[&str] = &[
+        r#""Status""#,
+        r#""History""#,
+        r#""StopStats""#,
+        r#""Breakpoints""#,
+        r#""VerifyBreakpoints""#,
+        r#""Snapshots""#,
+        r#""Modules""#,
+        r#""MemoryUsage""#,
+        r#"{"Read": {"address": 536870912, "byte_size": 4}}"#,
+        r#""HeapStats""#,
+        r#""VectorTableInfo""#,
+        r#""SectionMap""#,
+        r#""LoadedSources""#,
+        r#""ExceptionInfo""#,
+        r#""TargetInfo""#,
+        r#""Intrusiveness""#,
+        r#""Formats""#,
+        r#"{"ExportStopState": {"path": null, "depth": 4}}"#,
+        r#""Registers""#,
+        r#""Watchpoints""#,
+        r#""WatchBreaks""#,
+        r#""Code""#,
+        r#"{"Disassemble": {"memory_reference": "0x08000000", "offset": 0, "instruction_offset": -2, "instruction_count": 10}}"#,
+        r#""Stack""#,
+        r#""CycleCounter""#,
+        r#"{"BreakpointLocations": {"source_file": "src/main.rs", "line": 12, "column": null, "end_line": null, "end_column": null}}"#,
+        r#"{"TraceRead": {"count": 16}}"#,
+    ];
+
+    fn halted(pc: u32, resume: u64) -> DebugEvent {
+        DebugEvent::Halted {
+            pc,
+            reason: HaltReason::Breakpoint,
+            hit_breakpoint_ids: None,
+            description: None,
+            resume,
+        }
+    }
+
+    fn output(text: &str) -> DebugEvent {
+        DebugEvent::Output {
+            category: "console".to_owned(),
+            output: text.to_owned(),
+        }
+    }
+
+    // The pc of a stop or the text of an output.
+    fn summary(events: &[DebugEvent]) -> Vec<String> {
+        events
+            .iter()
+            .map(|event| match event {
+                DebugEvent::Halted { pc, .. } => format!("halted {:#x}", pc),
+                DebugEvent::Output { output, .. } => output.clone(),
+                _ => "other".to_owned(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_stop_storm_keeps_the_last_stop() {
+        // A 1 kHz breakpoint storm, the target resumed after every stop
+        let mut events = vec![];
+        for i in 0..100 {
+            events.push(halted(0x0800_0000 + i, i as u64));
+            if i % 10 == 0 {
+                events.push(output(&format!("log {}", i)));
+            }
+        }
+        let events = coalesce_stopped_events(events, 99);
+
+        let mut expected: Vec<String> = (0..100)
+            .step_by(10)
+            .map(|i| format!("log {}", i))
+            .collect();
+        expected.push("halted 0x8000063".to_owned());
+        assert_eq!(summary(&events), expected);
+    }
+
+    #[test]
+    fn a_stop_before_the_last_resume_is_dropped() {
+        let events = vec![halted(0x10, 3), output("after"), halted(0x20, 4)];
+        assert_eq!(
+            summary(&coalesce_stopped_events(events.clone(), 5)),
+            ["after"]
+        );
+        assert_eq!(
+            summary(&coalesce_stopped_events(events, 4)),
+            ["after", "halted 0x20"]
+        );
+    }
+
+    #[test]
+    fn events_without_stops_are_kept() {
+        let events = vec![output("a"), output("b")];
+        assert_eq!(summary(&coalesce_stopped_events(events, 0)), ["a", "b"]);
+    }
+
+    #[test]
+    fn requests_with_unknown_fields_parse() {
+        let content = json!({
+            "seq": 4,
+            "type": "request",
+            "command": "evaluate",
+            "arguments": { "expression": "x", "frameId": 1, "format": { "hex": true } },
+            "__sessionId": "b1c2",
+        });
+        let request = parse_request(&content).unwrap();
+        assert_eq!(request.command, "evaluate");
+        assert_eq!(request.seq, 4);
+
+        let arguments: EvaluateArguments = get_arguments(&request).unwrap();
+        assert_eq!(arguments.frame_id, Some(1));
+    }
+
+    #[test]
+    fn requests_without_arguments_use_the_defaults() {
+        let request = parse_request(&json!({ "seq": 1, "command": "initialize" })).unwrap();
+        assert!(request.arguments.is_none());
+        let arguments: InitializeArguments = get_arguments(&request).unwrap();
+        assert_eq!(arguments.client_name, None);
+
+        // Required fields still have to be there
+        let request = parse_request(&json!({ "seq": 2, "command": "stackTrace" })).unwrap();
+        let error = get_arguments::<StackTraceArguments>(&request).unwrap_err();
+        assert!(error.to_string().contains("the stackTrace request"), "{}", error);
+    }
+
+    #[test]
+    fn wrong_typed_arguments_name_the_request() {
+        let request = parse_request(&json!({
+            "seq": 3,
+            "command": "readMemory",
+            "arguments": { "memoryReference": "0x20000000", "count": 4, "offset": "8" },
+        }))
+        .unwrap();
+        let error = get_arguments::<ReadMemoryArguments>(&request).unwrap_err();
+        assert!(error.to_string().contains("the readMemory request"), "{}", error);
+    }
+
+    #[test]
+    fn requests_without_a_command_or_seq_fail() {
+        assert!(parse_request(&json!({ "seq": 1 })).is_err());
+        assert!(parse_request(&json!({ "command": "threads" })).is_err());
+        assert!(parse_request(&json!({ "seq": "1", "command": "threads" })).is_err());
+    }
+
+    #[test]
+    fn erdb_command_requests_round_trip() {
+        for json in ALLOWED {
+            let value: Value = serde_json::from_str(json).unwrap();
+            let request: DebugRequest = from_value(value.clone()).unwrap();
+            assert!(erdb_command_allowed(&request), "{} is not allowed", json);
+            assert_eq!(serde_json::to_value(&request).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn erdb_command_refuses_stateful_requests() {
+        let refused = [
+            r#""Exit""#,
+            r#""Continue""#,
+            r#"{"SaveBreakpoints": {"path": "/tmp/breakpoints.json"}}"#,
+            r#"{"CoreDump": {"path": "/tmp/core", "regions": "ram"}}"#,
+            r#"{"ReadFlash": {"path": "/tmp/flash.bin", "region": null}}"#,
+            r#"{"ExportStopState": {"path": "/tmp/stop.json", "depth": 4}}"#,
+            r#"{"DeleteWatchpoint": {"id": 1}}"#,
+            r#"{"DeleteWatchBreak": {"id": 1}}"#,
+            r#"{"SnapshotState": {"name": "boot", "regions": "ram"}}"#,
+            r#"{"SetWatchSampling": {"interval_ms": 100}}"#,
+            r#"{"TraceConfigure": {"buffer_address": 536870912, "size": 1024}}"#,
+            r#""TraceDisable""#,
+        ];
+        for json in refused {
+            let request: DebugRequest = serde_json::from_str(json).unwrap();
+            assert!(!erdb_command_allowed(&request), "{} is allowed", json);
+        }
+    }
 }
@@ -0,0 +1,92 @@
+//! Source locations of output lines
+//!
+//! Target logs often start their lines with the location that printed them, e.g.
+//! `src/main.rs:42: starting`. The location is extracted with a regex with the named groups
+//! `path`, `line` and optionally `column`, so the client can link the line to the source.
+//! Output can arrive in chunks that split lines, so the unfinished end of a chunk is kept per
+//! category until the rest of the line arrives.
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_LOCATION_PATTERN: &str =
+    r"^(?P<path>[^\s:]+\.rs):(?P<line>\d+)(?::(?P<column>\d+))?";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputLocation {
+    pub path: String,
+    pub line: i64,
+    pub column: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputLine {
+    pub text: String,
+    pub location: Option<OutputLocation>,
+}
+
+pub struct OutputLocations {
+    pattern: Regex,
+    cwd: Option<PathBuf>,
+    partial: HashMap<String, String>,
+}
+
+impl OutputLocations {
+    pub fn new(pattern: &str) -> Result<OutputLocations> {
+        let pattern = Regex::new(pattern)?;
+        if !pattern.capture_names().any(|name| name == Some("path"))
+            || !pattern.capture_names().any(|name| name == Some("line"))
+        {
+            return Err(anyhow!(
+                "The output location pattern needs the named groups `path` and `line`"
+            ));
+        }
+        Ok(OutputLocations {
+            pattern,
+            cwd: None,
+            partial: HashMap::new(),
+        })
+    }
+
+    /// Relative paths in the output are resolved against `cwd`.
+    pub fn set_cwd(&mut self, cwd: Option<PathBuf>) {
+        self.cwd = cwd;
+    }
+
+    /// Returns the lines completed by `chunk`, the unfinished end is kept for the next chunk.
+    pub fn lines(&mut self, category: &str, chunk: &str) -> Vec<OutputLine> {
+        let buffer = self.partial.entry(category.to_owned()).or_default();
+        buffer.push_str(chunk);
+
+        let end = match buffer.rfind('\n') {
+            Some(val) => val + 1,
+            None => return vec![],
+        };
+        let complete: String = buffer.drain(..end).collect();
+
+        complete
+            .split_inclusive('\n')
+            .map(|text| OutputLine {
+                location: self.location(text.trim_end()),
+                text: text.to_owned(),
+            })
+            .collect()
+    }
+
+    fn location(&self, line: &str) -> Option<OutputLocation> {
+        let captures = self.pattern.captures(line)?;
+        let path = Path::new(captures.name("path")?.as_str());
+        let path = match &self.cwd {
+            Some(cwd) if path.is_relative() => cwd.join(path),
+            _ => path.to_path_buf(),
+        };
+        Some(OutputLocation {
+            path: path.to_string_lossy().to_string(),
+            line: captures.name("line")?.as_str().parse().ok()?,
+            column: captures
+                .name("column")
+                .and_then(|column| column.as_str().parse().ok()),
+        })
+    }
+}
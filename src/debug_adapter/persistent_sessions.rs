@@ -0,0 +1,129 @@
+//! Debug sessions kept alive for a reconnecting client
+//!
+//! Reloading the editor window drops the connection without a disconnect request. With
+//! `persistentSession` set in the attach or launch arguments the debugger is parked instead of
+//! ended: it keeps the probe, the breakpoints and the halted core for a grace period. A new
+//! connection attaching to the same probe, chip and build of the program takes it over without
+//! flashing, and the debugger is ended as usual if no client does within the grace period.
+use crossbeam_channel::{Receiver, Sender};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::commands::{debug_request::DebugRequest, Command};
+use crate::debugger::cancel::CancelToken;
+use crate::debugger::resumes::ResumeCount;
+use crate::debugger::symbols::build_id;
+
+/// How long a parked session waits for its client unless `persistentSessionGraceMs` is set.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(60);
+const RECLAIM_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The connection was closed by the client.
+#[derive(Debug)]
+pub struct ConnectionClosed;
+
+impl fmt::Display for ConnectionClosed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The connection was closed by the client")
+    }
+}
+
+impl std::error::Error for ConnectionClosed {}
+
+/// Returns true if the error is the client going away rather than a failed request.
+pub fn is_connection_lost(err: &anyhow::Error) -> bool {
+    if err.is::<ConnectionClosed>() {
+        return true;
+    }
+    match err.downcast_ref::<std::io::Error>() {
+        Some(err) => matches!(
+            err.kind(),
+            std::io::ErrorKind::BrokenPipe
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+        ),
+        None => false,
+    }
+}
+
+/// What a reconnecting client must attach to, to be given the session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionKey {
+    probe: Option<usize>,
+    chip: Option<String>,
+    program: Option<PathBuf>,
+    // A rebuilt program is flashed by a new session.
+    build_id: Option<String>,
+}
+
+impl SessionKey {
+    pub fn new(probe: Option<usize>, chip: Option<String>, program: Option<PathBuf>) -> SessionKey {
+        let build_id = program
+            .as_ref()
+            .and_then(|path| build_id(path).ok().flatten());
+        SessionKey {
+            probe,
+            chip,
+            program,
+            build_id,
+        }
+    }
+}
+
+/// The adapter ends of the channels to a debugger.
+pub struct DebuggerLink {
+    pub id: usize,
+    pub sender: Sender<DebugRequest>,
+    pub receiver: Receiver<Command>,
+    pub cancel: CancelToken,
+    pub resumes: ResumeCount,
+}
+
+struct ParkedSession {
+    key: SessionKey,
+    link: DebuggerLink,
+    deadline: Instant,
+}
+
+/// The debuggers waiting for their client to reconnect, shared by all the connections.
+#[derive(Clone, Default)]
+pub struct ParkedSessions {
+    parked: Arc<Mutex<Vec<ParkedSession>>>,
+}
+
+impl ParkedSessions {
+    pub fn park(&self, key: SessionKey, link: DebuggerLink, grace_period: Duration) {
+        self.parked.lock().unwrap().push(ParkedSession {
+            key,
+            link,
+            deadline: Instant::now() + grace_period,
+        });
+    }
+
+    /// Takes the session matching the key, if it is still waiting.
+    pub fn reclaim(&self, key: &SessionKey) -> Option<DebuggerLink> {
+        let mut parked = self.parked.lock().unwrap();
+        let index = parked
+            .iter()
+            .position(|session| &session.key == key && session.deadline > Instant::now())?;
+        Some(parked.remove(index).link)
+    }
+
+    /// Waits until the session of the debugger is reclaimed or its grace period has passed. The
+    /// link is returned in the latter case, to end the debugger.
+    pub fn wait(&self, id: usize) -> Option<DebuggerLink> {
+        loop {
+            {
+                let mut parked = self.parked.lock().unwrap();
+                let index = parked.iter().position(|session| session.link.id == id)?;
+                if parked[index].deadline <= Instant::now() {
+                    return Some(parked.remove(index).link);
+                }
+            }
+            thread::sleep(RECLAIM_POLL_INTERVAL);
+        }
+    }
+}
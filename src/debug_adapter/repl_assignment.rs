@@ -0,0 +1,115 @@
+//! Assignments typed in the Debug Console
+//!
+//! In the repl context `pc = 0x0800_0100`, `r0 = 5` and `mem[0x2000_0000] = 0xdead_beef` write a
+//! core register or a 32 bit word of memory. Only a single `=` at the top level makes an
+//! assignment: `==`, `!=`, `<=`, `>=` and anything inside brackets are not. Watch and hover
+//! expressions are never parsed as assignments. A `!` before the value, `mem[0x4000_0000] = !1`,
+//! confirms a write to a peripheral register.
+use anyhow::{anyhow, Result};
+
+use crate::commands::commands::parse_u32_from_str;
+use crate::commands::debug_request::DebugRequest;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Assignment {
+    Register { name: String, value: u32 },
+    Memory {
+        address: u32,
+        value: u32,
+        confirmed: bool,
+    },
+}
+
+impl Assignment {
+    /// Returns `None` if the expression is not an assignment.
+    pub fn parse(expression: &str) -> Result<Option<Assignment>> {
+        let (target, value) = match split_assignment(expression) {
+            Some(val) => val,
+            None => return Ok(None),
+        };
+        let (value, confirmed) = match value.strip_prefix('!') {
+            Some(value) => (value.trim_start(), true),
+            None => (value, false),
+        };
+        let value = parse_value(value)?;
+
+        if let Some(address) = target
+            .strip_prefix("mem[")
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            return Ok(Some(Assignment::Memory {
+                address: parse_value(address)?,
+                value,
+                confirmed,
+            }));
+        }
+
+        let mut chars = target.chars();
+        let is_name = chars.next().map_or(false, |c| c.is_ascii_alphabetic())
+            && chars.all(|c| c.is_ascii_alphanumeric());
+        match is_name {
+            true => Ok(Some(Assignment::Register {
+                name: target.to_owned(),
+                value,
+            })),
+            false => Err(anyhow!(
+                "Can only assign to a register, e.g. `r0 = 5`, or to `mem[address]`"
+            )),
+        }
+    }
+
+    pub fn request(&self) -> DebugRequest {
+        match self {
+            Assignment::Register { name, value } => DebugRequest::WriteRegister {
+                name: name.clone(),
+                value: *value,
+            },
+            Assignment::Memory {
+                address,
+                value,
+                confirmed,
+            } => DebugRequest::WriteWord {
+                address: *address,
+                value: *value,
+                confirmed: *confirmed,
+            },
+        }
+    }
+}
+
+/// Splits at the only top level `=` that is not part of a comparison.
+fn split_assignment(expression: &str) -> Option<(&str, &str)> {
+    let bytes = expression.as_bytes();
+    let mut depth = 0;
+    let mut found = None;
+    for (i, c) in bytes.iter().enumerate() {
+        match c {
+            b'[' | b'(' => depth += 1,
+            b']' | b')' => depth -= 1,
+            b'=' if depth == 0 => {
+                let previous = i.checked_sub(1).map(|j| bytes[j]);
+                let next = bytes.get(i + 1).copied();
+                if matches!(previous, Some(b'=' | b'!' | b'<' | b'>')) || next == Some(b'=') {
+                    continue;
+                }
+                if found.is_some() {
+                    return None;
+                }
+                found = Some(i);
+            }
+            _ => (),
+        };
+    }
+
+    let i = found?;
+    Some((expression[..i].trim(), expression[i + 1..].trim()))
+}
+
+/// A decimal or `0x` hex number, `_` separators and a leading `-` are allowed.
+fn parse_value(value: &str) -> Result<u32> {
+    let value = value.replace('_', "");
+    match value.strip_prefix('-') {
+        Some(negative) => Ok((parse_u32_from_str(negative)? as i32).wrapping_neg() as u32),
+        None => parse_u32_from_str(&value),
+    }
+}
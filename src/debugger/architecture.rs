@@ -0,0 +1,324 @@
+//! What differs between the core architectures
+//!
+//! Most of the debugger only talks to probe-rs, which hides the architecture. The rest goes
+//! through a `CoreArchitecture`, selected from the core when attaching: the disassembler, the
+//! register numbers the DWARF unwinder uses, the software breakpoint instructions, and the
+//! exception and trap state. The Cortex-M debug blocks, the FPB, the DWT and the vector catch of
+//! DEMCR, only exist on ARM, the features built on them fail on RISC-V with an explanation.
+//!
+//! RISC-V hardware breakpoints use the trigger module through probe-rs. The unwinder keeps 16
+//! registers per frame, on RISC-V that is x0-x15, so the saved registers s2-s11 of the callers
+//! are unknown.
+use super::finish::ReturnRegisters;
+use super::values::Endianness;
+use anyhow::{anyhow, Result};
+use capstone::prelude::*;
+use capstone::Capstone;
+use probe_rs::MemoryInterface;
+
+// The DCRSR register selector of xPSR, its low 9 bits are IPSR.
+const XPSR_REGISTER: u16 = 0x10;
+const IPSR_MASK: u32 = 0x1ff;
+// The DCRSR register selector of the first single precision floating point register.
+const FP_S0_REGISTER: u16 = 0x40;
+
+// The probe-rs addresses of x0-x31, the DWARF numbers are 0-31.
+const RISCV_GPR_BASE: u16 = 0x1000;
+const RISCV_GPRS: u16 = 32;
+// a0 and a1, the integer return value registers.
+const RISCV_A0: u16 = RISCV_GPR_BASE + 10;
+// The CSRs of the last trap taken in machine mode.
+const MEPC: u16 = 0x341;
+const MCAUSE: u16 = 0x342;
+const MTVAL: u16 = 0x343;
+const MCAUSE_INTERRUPT: u32 = 1 << 31;
+
+const EBREAK: u32 = 0x0010_0073;
+const C_EBREAK: u16 = 0x9002;
+
+pub trait CoreArchitecture: Sync {
+    fn name(&self) -> &'static str;
+
+    fn capstone(&self) -> Result<Capstone>;
+
+    /// Whether the disassembled instruction calls a function.
+    fn is_call(&self, mnemonic: &str) -> bool;
+
+    /// The DWARF number of a core register, by its probe-rs address. The unwinder indexes the
+    /// registers by it. None for registers DWARF doesn't number.
+    fn dwarf_register(&self, address: u16) -> Option<u16>;
+
+    /// The length of the software breakpoint instruction at the address, None if there is none.
+    fn breakpoint_instruction(
+        &self,
+        core: &mut probe_rs::Core,
+        address: u32,
+    ) -> Result<Option<u32>>;
+
+    /// The active exception number if the core is halted in an exception handler.
+    fn active_exception(&self, core: &mut probe_rs::Core) -> Result<Option<u32>>;
+
+    /// Describes the last trap from the trap registers, None if the architecture has none.
+    fn describe_trap(&self, core: &mut probe_rs::Core) -> Result<Option<String>>;
+
+    /// Reads the registers a function returns its value in.
+    fn return_registers(
+        &self,
+        core: &mut probe_rs::Core,
+        hard_float: bool,
+        endianness: Endianness,
+    ) -> Result<ReturnRegisters>;
+
+    /// The FPB, the DWT and the vector catch of a Cortex-M.
+    fn has_cortex_m_debug(&self) -> bool;
+}
+
+pub struct Arm;
+
+impl CoreArchitecture for Arm {
+    fn name(&self) -> &'static str {
+        "ARM"
+    }
+
+    fn capstone(&self) -> Result<Capstone> {
+        Capstone::new()
+            .arm()
+            .mode(arch::arm::ArchMode::Thumb)
+            .build()
+            .map_err(|err| anyhow!("Failed to create the disassembler: {}", err))
+    }
+
+    fn is_call(&self, mnemonic: &str) -> bool {
+        matches!(mnemonic, "bl" | "blx")
+    }
+
+    fn dwarf_register(&self, address: u16) -> Option<u16> {
+        // r0-r15 have the same numbers
+        Some(address)
+    }
+
+    fn breakpoint_instruction(
+        &self,
+        core: &mut probe_rs::Core,
+        address: u32,
+    ) -> Result<Option<u32>> {
+        let mut code = [0u8; 2];
+        core.read_8(address, &mut code)?;
+        // 0b1011_1110 is the high byte of BKPT #NR
+        match code[1] == 0b1011_1110 {
+            true => Ok(Some(2)),
+            false => Ok(None),
+        }
+    }
+
+    fn active_exception(&self, core: &mut probe_rs::Core) -> Result<Option<u32>> {
+        let xpsr = core.read_core_reg(XPSR_REGISTER)?;
+        match xpsr & IPSR_MASK {
+            0 => Ok(None),
+            exception => Ok(Some(exception)),
+        }
+    }
+
+    fn describe_trap(&self, _core: &mut probe_rs::Core) -> Result<Option<String>> {
+        // The faults are decoded by the trap catches from the fault status registers
+        Ok(None)
+    }
+
+    fn return_registers(
+        &self,
+        core: &mut probe_rs::Core,
+        hard_float: bool,
+        endianness: Endianness,
+    ) -> Result<ReturnRegisters> {
+        Ok(ReturnRegisters {
+            names: ["r0", "r1"],
+            r0: core.read_core_reg(0u16)?,
+            r1: core.read_core_reg(1u16)?,
+            s0: core.read_core_reg(FP_S0_REGISTER).ok(),
+            s1: core.read_core_reg(FP_S0_REGISTER + 1).ok(),
+            hard_float,
+            endianness,
+        })
+    }
+
+    fn has_cortex_m_debug(&self) -> bool {
+        true
+    }
+}
+
+pub struct Riscv;
+
+impl CoreArchitecture for Riscv {
+    fn name(&self) -> &'static str {
+        "RISC-V"
+    }
+
+    fn capstone(&self) -> Result<Capstone> {
+        Capstone::new()
+            .riscv()
+            .mode(arch::riscv::ArchMode::RiscV32)
+            .extra_mode([arch::riscv::ArchExtraMode::RiscVC].iter().copied())
+            .build()
+            .map_err(|err| anyhow!("Failed to create the disassembler: {}", err))
+    }
+
+    fn is_call(&self, mnemonic: &str) -> bool {
+        // A jump without link is printed as `j` or `jr`
+        matches!(mnemonic, "jal" | "jalr" | "c.jal" | "c.jalr" | "call")
+    }
+
+    fn dwarf_register(&self, address: u16) -> Option<u16> {
+        match address.checked_sub(RISCV_GPR_BASE) {
+            Some(number) if number < RISCV_GPRS => Some(number),
+            // The pc has no DWARF number, it is kept under its CSR address
+            _ => Some(address),
+        }
+    }
+
+    fn breakpoint_instruction(
+        &self,
+        core: &mut probe_rs::Core,
+        address: u32,
+    ) -> Result<Option<u32>> {
+        let mut code = [0u8; 4];
+        core.read_8(address, &mut code)?;
+        if u16::from_le_bytes([code[0], code[1]]) == C_EBREAK {
+            return Ok(Some(2));
+        }
+        match u32::from_le_bytes(code) == EBREAK {
+            true => Ok(Some(4)),
+            false => Ok(None),
+        }
+    }
+
+    fn active_exception(&self, _core: &mut probe_rs::Core) -> Result<Option<u32>> {
+        // mcause keeps the last trap after the handler returns, it can't tell if one is active
+        Ok(None)
+    }
+
+    fn describe_trap(&self, core: &mut probe_rs::Core) -> Result<Option<String>> {
+        let mcause = core.read_core_reg(MCAUSE)?;
+        let mepc = core.read_core_reg(MEPC)?;
+        if mcause & MCAUSE_INTERRUPT != 0 {
+            return Ok(Some(format!(
+                "interrupt {} at {:#010x}",
+                mcause & !MCAUSE_INTERRUPT,
+                mepc
+            )));
+        }
+        let cause = match trap_name(mcause) {
+            Some(val) => val.to_owned(),
+            None => format!("exception {}", mcause),
+        };
+        let mtval = core.read_core_reg(MTVAL)?;
+        Ok(Some(match mcause {
+            // mtval holds the faulting address
+            4..=7 | 12 | 13 | 15 => format!("{} of {:#010x} at {:#010x}", cause, mtval, mepc),
+            _ => format!("{} at {:#010x}", cause, mepc),
+        }))
+    }
+
+    fn return_registers(
+        &self,
+        core: &mut probe_rs::Core,
+        _hard_float: bool,
+        endianness: Endianness,
+    ) -> Result<ReturnRegisters> {
+        // The floating point registers aren't read, floats are shown from a0 and a1
+        Ok(ReturnRegisters {
+            names: ["a0", "a1"],
+            r0: core.read_core_reg(RISCV_A0)?,
+            r1: core.read_core_reg(RISCV_A0 + 1)?,
+            s0: None,
+            s1: None,
+            hard_float: false,
+            endianness,
+        })
+    }
+
+    fn has_cortex_m_debug(&self) -> bool {
+        false
+    }
+}
+
+/// The architecture of the core.
+pub fn of(core: &probe_rs::Core) -> &'static dyn CoreArchitecture {
+    match core.architecture() {
+        probe_rs::Architecture::Riscv => &Riscv,
+        probe_rs::Architecture::Arm => &Arm,
+    }
+}
+
+/// Fails features built on the Cortex-M debug blocks on other cores.
+pub fn require_cortex_m(architecture: &dyn CoreArchitecture, feature: &str) -> Result<()> {
+    match architecture.has_cortex_m_debug() {
+        true => Ok(()),
+        false => Err(anyhow!(
+            "{} needs the debug blocks of a Cortex-M, they don't exist on {}",
+            feature,
+            architecture.name()
+        )),
+    }
+}
+
+/// The standard exception causes of mcause.
+fn trap_name(mcause: u32) -> Option<&'static str> {
+    Some(match mcause {
+        0 => "instruction address misaligned",
+        1 => "instruction access fault",
+        2 => "illegal instruction",
+        3 => "breakpoint",
+        4 => "load address misaligned",
+        5 => "load access fault",
+        6 => "store address misaligned",
+        7 => "store access fault",
+        8 => "environment call from U-mode",
+        11 => "environment call from M-mode",
+        12 => "instruction page fault",
+        13 => "load page fault",
+        15 => "store page fault",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_are_recognized_per_architecture() {
+        assert!(Arm.is_call("bl"));
+        assert!(Arm.is_call("blx"));
+        assert!(!Arm.is_call("b"));
+        assert!(!Arm.is_call("jal"));
+
+        assert!(Riscv.is_call("jal"));
+        assert!(Riscv.is_call("c.jalr"));
+        assert!(!Riscv.is_call("j"));
+        assert!(!Riscv.is_call("bl"));
+    }
+
+    #[test]
+    fn dwarf_register_numbers() {
+        assert_eq!(Arm.dwarf_register(13), Some(13));
+        assert_eq!(Riscv.dwarf_register(RISCV_GPR_BASE), Some(0));
+        assert_eq!(Riscv.dwarf_register(RISCV_A0), Some(10));
+        assert_eq!(Riscv.dwarf_register(RISCV_GPR_BASE + 31), Some(31));
+        // The pc CSR is kept under its own address
+        assert_eq!(Riscv.dwarf_register(0x7b1), Some(0x7b1));
+    }
+
+    #[test]
+    fn only_arm_has_the_cortex_m_debug_blocks() {
+        assert!(require_cortex_m(&Arm, "Watchpoints").is_ok());
+        let err = require_cortex_m(&Riscv, "Watchpoints").unwrap_err();
+        assert!(err.to_string().contains("RISC-V"));
+    }
+
+    #[test]
+    fn standard_trap_names() {
+        assert_eq!(trap_name(2), Some("illegal instruction"));
+        assert_eq!(trap_name(7), Some("store access fault"));
+        assert_eq!(trap_name(10), None);
+    }
+}
@@ -0,0 +1,99 @@
+//! The addresses the Flash Patch and Breakpoint unit can match
+//!
+//! Revision 1 of the FPB, in ARMv6-M and ARMv7-M cores, only compares addresses in the code
+//! region below 0x2000_0000. A comparator planted for an address above it is accepted but never
+//! matches, so those breakpoints are reported unverified instead of silently never hitting.
+//! Revision 2, in ARMv8-M cores, compares the whole address space.
+use std::fmt;
+
+const FP_CTRL: u32 = 0xe000_2000;
+const FP_CTRL_REV_SHIFT: u32 = 28;
+
+/// The end of the code region, the addresses revision 1 compares.
+const CODE_REGION_END: u32 = 0x2000_0000;
+
+pub const UNSUPPORTED_ADDRESS: &str = "address not supported by this core's breakpoint unit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakpointUnit {
+    FpbV1,
+    FpbV2,
+    /// Not read yet, a core dump or not a Cortex-M core, no address is refused.
+    Unknown,
+}
+
+impl Default for BreakpointUnit {
+    fn default() -> BreakpointUnit {
+        BreakpointUnit::Unknown
+    }
+}
+
+impl BreakpointUnit {
+    /// Reads the revision from `FP_CTRL`, an unreadable register leaves it unknown.
+    pub fn read(core: &mut probe_rs::Core) -> BreakpointUnit {
+        match core.read_word_32(FP_CTRL) {
+            Ok(ctrl) => match ctrl >> FP_CTRL_REV_SHIFT {
+                0 => BreakpointUnit::FpbV1,
+                1 => BreakpointUnit::FpbV2,
+                _ => BreakpointUnit::Unknown,
+            },
+            Err(_) => BreakpointUnit::Unknown,
+        }
+    }
+
+    pub fn supports(self, address: u32) -> bool {
+        match self {
+            BreakpointUnit::FpbV1 => address < CODE_REGION_END,
+            BreakpointUnit::FpbV2 | BreakpointUnit::Unknown => true,
+        }
+    }
+
+    /// Fails with the reason shown on the unverified breakpoint.
+    pub fn check(self, address: u32) -> Result<u32, String> {
+        match self.supports(address) {
+            true => Ok(address),
+            false => Err(format!("{} ({:#010x})", UNSUPPORTED_ADDRESS, address)),
+        }
+    }
+}
+
+impl fmt::Display for BreakpointUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BreakpointUnit::FpbV1 => write!(
+                f,
+                "FPB revision 1, breakpoints below {:#010x}",
+                CODE_REGION_END
+            ),
+            BreakpointUnit::FpbV2 => write!(f, "FPB revision 2"),
+            BreakpointUnit::Unknown => write!(f, "unknown breakpoint unit"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fpb_v1_only_matches_the_code_region() {
+        let unit = BreakpointUnit::FpbV1;
+        assert_eq!(unit.check(0x0800_0100), Ok(0x0800_0100));
+        assert_eq!(unit.check(CODE_REGION_END - 2), Ok(CODE_REGION_END - 2));
+
+        // Code run from RAM
+        let err = unit.check(CODE_REGION_END).unwrap_err();
+        assert_eq!(err, format!("{} (0x20000000)", UNSUPPORTED_ADDRESS));
+        assert!(unit.check(0x2000_0400).is_err());
+        assert!(unit.check(0xffff_fffe).is_err());
+    }
+
+    #[test]
+    fn fpb_v2_and_unknown_units_match_everything() {
+        for unit in [BreakpointUnit::FpbV2, BreakpointUnit::Unknown] {
+            for address in [0x0800_0100, CODE_REGION_END, 0x2000_0400, 0xffff_fffe] {
+                assert_eq!(unit.check(address), Ok(address), "{}", unit);
+            }
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use super::conditions::{Condition, HitCondition};
+use super::log_points::LogMessage;
+use super::watchpoints::WatchKind;
+use anyhow::{anyhow, Context, Result};
+use debugserver_types::Breakpoint;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Debugger side state of a breakpoint that the DAP `Breakpoint` type has no room for.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BreakpointState {
+    pub hit_count: u32,
+    pub ignore_count: u32,
+    /// Monitor commands run when the breakpoint stops the core, see `break commands`.
+    pub commands: Vec<String>,
+    /// Checked on every hit, the core is resumed if it is false.
+    pub condition: Option<Condition>,
+    /// Checked after the condition, it counts the hits since the breakpoint was set.
+    pub hit_condition: Option<HitCondition>,
+    /// Printed instead of stopping, see `log_points`.
+    pub log_message: Option<LogMessage>,
+}
+
+impl BreakpointState {
+    /// Counts a hit, returns true if the hit should be ignored and the core resumed.
+    pub fn hit(&mut self) -> bool {
+        self.hit_count += 1;
+        if self.ignore_count > 0 {
+            self.ignore_count -= 1;
+            return true;
+        }
+        false
+    }
+
+    /// Replaces the options the breakpoint was set with. A new hit condition counts from zero,
+    /// also when the breakpoint had the same one before.
+    pub fn set_options(
+        &mut self,
+        condition: Option<Condition>,
+        hit_condition: Option<HitCondition>,
+        log_message: Option<LogMessage>,
+    ) {
+        self.condition = condition;
+        self.hit_condition = hit_condition;
+        self.log_message = log_message;
+    }
+
+    /// Counts a hit towards the hit condition, returns false if the core should be resumed.
+    pub fn hit_condition_met(&mut self) -> bool {
+        match &mut self.hit_condition {
+            Some(hit_condition) => hit_condition.hit(),
+            None => true,
+        }
+    }
+
+    pub fn message(&self) -> Option<String> {
+        match self.ignore_count {
+            0 => None,
+            count => Some(format!("Ignoring the next {} hits", count)),
+        }
+    }
+
+    /// Copies the state into the message of the DAP breakpoint.
+    pub fn annotate(&self, mut breakpoint: Breakpoint) -> Breakpoint {
+        breakpoint.message = self.message();
+        breakpoint
+    }
+}
+
+/// All the breakpoints at an address, they share one hardware breakpoint unit.
+pub fn breakpoints_at(
+    breakpoints: &HashMap<u32, Breakpoint>,
+    shared_breakpoints: &HashMap<u32, Vec<Breakpoint>>,
+    address: u32,
+) -> Vec<Breakpoint> {
+    let mut found = vec![];
+    if let Some(breakpoint) = breakpoints.get(&address) {
+        found.push(breakpoint.clone());
+        if let Some(shared) = shared_breakpoints.get(&address) {
+            found.extend(shared.iter().cloned());
+        }
+    }
+    found
+}
+
+pub fn breakpoint_ids(breakpoints: &[Breakpoint]) -> Result<Vec<u32>> {
+    let mut ids = vec![];
+    for breakpoint in breakpoints {
+        match breakpoint.id {
+            Some(id) => ids.push(id as u32),
+            None => {
+                error!("Breakpoint id is required");
+                return Err(anyhow!("Breakpoint id is required"));
+            }
+        }
+    }
+    Ok(ids)
+}
+
+/// The conditions of the hit breakpoints by breakpoint id, each one is evaluated on its own.
+pub fn breakpoint_conditions(
+    hit_breakpoints: &[Breakpoint],
+    states: &HashMap<u32, BreakpointState>,
+    pc: u32,
+) -> Vec<(u32, Condition)> {
+    hit_breakpoints
+        .iter()
+        .filter_map(|breakpoint| {
+            let id = breakpoint.id.unwrap_or(pc as i64) as u32;
+            let condition = states.get(&id)?.condition.clone()?;
+            Some((id, condition))
+        })
+        .collect()
+}
+
+/// The hit breakpoints that don't stop the core, besides the ones with an unmet condition.
+#[derive(Debug, Default)]
+pub struct CountedHits {
+    /// Ignored by their ignore count, annotated with the hits left to ignore.
+    pub ignored: Vec<Breakpoint>,
+    /// Whose hit condition is not met yet.
+    pub not_reached: usize,
+    /// The messages of the log points, they are printed instead of stopping.
+    pub log_messages: Vec<LogMessage>,
+}
+
+/// Counts a hit for each breakpoint at the pc whose condition is not in `unmet`.
+pub fn count_hits(
+    hit_breakpoints: &[Breakpoint],
+    states: &mut HashMap<u32, BreakpointState>,
+    unmet: &[u32],
+    pc: u32,
+) -> CountedHits {
+    let mut counted = CountedHits::default();
+    for breakpoint in hit_breakpoints {
+        let id = breakpoint.id.unwrap_or(pc as i64) as u32;
+        if unmet.contains(&id) {
+            continue;
+        }
+        let state = states.entry(id).or_default();
+        if state.hit() {
+            counted.ignored.push(state.annotate(breakpoint.clone()));
+        } else if !state.hit_condition_met() {
+            counted.not_reached += 1;
+        } else if let Some(log_message) = &state.log_message {
+            counted.log_messages.push(log_message.clone());
+        }
+    }
+    counted
+}
+
+/// A breakpoint as stored in a breakpoint file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedBreakpoint {
+    pub address: u32,
+    pub source: Option<String>,
+    pub line: Option<i64>,
+    pub column: Option<i64>,
+    #[serde(default)]
+    pub ignore_count: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hit_condition: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_message: Option<String>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+impl SavedBreakpoint {
+    pub fn new(address: u32, breakpoint: &Breakpoint, state: &BreakpointState) -> SavedBreakpoint {
+        SavedBreakpoint {
+            address,
+            source: breakpoint.source.as_ref().and_then(|s| s.path.clone()),
+            line: breakpoint.line,
+            column: breakpoint.column,
+            ignore_count: state.ignore_count,
+            commands: state.commands.clone(),
+            condition: state
+                .condition
+                .as_ref()
+                .map(|condition| condition.text().to_owned()),
+            hit_condition: state
+                .hit_condition
+                .as_ref()
+                .map(|hit_condition| hit_condition.text().to_owned()),
+            log_message: state
+                .log_message
+                .as_ref()
+                .map(|log_message| log_message.text().to_owned()),
+            enabled: true,
+        }
+    }
+
+    pub fn location(&self) -> String {
+        match (&self.source, self.line) {
+            (Some(source), Some(line)) => format!("{}:{}", source, line),
+            _ => format!("{:#010x}", self.address),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedWatchpoint {
+    pub expression: String,
+    pub kind: WatchKind,
+}
+
+/// The content of a breakpoint file written by `break save`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BreakpointFile {
+    #[serde(default)]
+    pub breakpoints: Vec<SavedBreakpoint>,
+    #[serde(default)]
+    pub watchpoints: Vec<SavedWatchpoint>,
+}
+
+impl BreakpointFile {
+    pub fn read(path: &Path) -> Result<BreakpointFile> {
+        let data = fs::read(path).context(format!("Failed to read breakpoint file {:?}", path))?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+            .context(format!("Failed to write breakpoint file {:?}", path))?;
+        Ok(())
+    }
+}
+
+/// The outcome of resolving a breakpoint again against the current binary.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum VerifyStatus {
+    Bound,
+    Moved { from: u32 },
+    Unresolved { reason: String },
+    Disabled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    pub location: String,
+    pub address: Option<u32>,
+    pub status: VerifyStatus,
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.status, self.address) {
+            (VerifyStatus::Bound, Some(address)) => {
+                write!(f, "{}: bound at {:#010x}", self.location, address)
+            }
+            (VerifyStatus::Moved { from }, Some(address)) => write!(
+                f,
+                "{}: moved from {:#010x} to {:#010x}",
+                self.location, from, address
+            ),
+            (VerifyStatus::Unresolved { reason }, _) => {
+                write!(f, "{}: unresolved, {}", self.location, reason)
+            }
+            _ => write!(f, "{}: disabled", self.location),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::conditions::Value;
+    use super::*;
+
+    const ADDRESS: u32 = 0x0800_0400;
+
+    fn breakpoint(id: u32, line: i64) -> Breakpoint {
+        Breakpoint {
+            id: Some(id as i64),
+            verified: true,
+            message: None,
+            source: None,
+            line: Some(line),
+            column: None,
+            end_line: None,
+            end_column: None,
+        }
+    }
+
+    // Lines 10, 11 and 12 resolve to the same address, the last two share the unit of the first.
+    fn shared_lines() -> (HashMap<u32, Breakpoint>, HashMap<u32, Vec<Breakpoint>>) {
+        let breakpoints = HashMap::from([(ADDRESS, breakpoint(ADDRESS, 10))]);
+        let shared = HashMap::from([(
+            ADDRESS,
+            vec![breakpoint(u32::MAX, 11), breakpoint(u32::MAX - 1, 12)],
+        )]);
+        (breakpoints, shared)
+    }
+
+    // The unmet conditions, as `unmet_conditions` evaluates them against the variable `x`.
+    fn unmet(conditions: Vec<(u32, Condition)>, x: i128) -> Vec<u32> {
+        conditions
+            .into_iter()
+            .filter(|(_, condition)| !condition.evaluate(&mut |_| Ok(Value::Int(x))).unwrap())
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    #[test]
+    fn lines_resolving_to_one_address() {
+        let (breakpoints, shared) = shared_lines();
+        let hit = breakpoints_at(&breakpoints, &shared, ADDRESS);
+        let lines: Vec<_> = hit.iter().map(|breakpoint| breakpoint.line).collect();
+        assert_eq!(lines, [Some(10), Some(11), Some(12)]);
+        assert_eq!(
+            breakpoint_ids(&hit).unwrap(),
+            [ADDRESS, u32::MAX, u32::MAX - 1]
+        );
+
+        assert!(breakpoints_at(&breakpoints, &shared, ADDRESS + 2).is_empty());
+        // Shared breakpoints are only hit through the breakpoint holding the unit
+        assert!(breakpoints_at(&HashMap::new(), &shared, ADDRESS).is_empty());
+    }
+
+    #[test]
+    fn breakpoints_without_an_id() {
+        let mut without_id = breakpoint(ADDRESS, 10);
+        without_id.id = None;
+        assert!(breakpoint_ids(&[breakpoint(ADDRESS, 10), without_id]).is_err());
+        assert!(breakpoint_ids(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn shared_breakpoints_count_their_own_hits() {
+        let (breakpoints, shared) = shared_lines();
+        let hit = breakpoints_at(&breakpoints, &shared, ADDRESS);
+        let mut states = HashMap::new();
+        states
+            .entry(u32::MAX)
+            .or_insert_with(BreakpointState::default)
+            .ignore_count = 1;
+        states
+            .entry(u32::MAX - 1)
+            .or_insert_with(BreakpointState::default)
+            .log_message = Some(LogMessage::parse("reached").unwrap());
+
+        let counted = count_hits(&hit, &mut states, &[], ADDRESS);
+        assert_eq!(counted.ignored.len(), 1);
+        assert_eq!(counted.ignored[0].line, Some(11));
+        assert_eq!(counted.not_reached, 0);
+        assert_eq!(counted.log_messages.len(), 1);
+        for id in [ADDRESS, u32::MAX, u32::MAX - 1] {
+            assert_eq!(states[&id].hit_count, 1);
+        }
+
+        // The ignore count is used up, line 11 stops the core on the next hit
+        let counted = count_hits(&hit, &mut states, &[], ADDRESS);
+        assert!(counted.ignored.is_empty());
+        assert_eq!(counted.log_messages.len(), 1);
+    }
+
+    #[test]
+    fn conditions_are_evaluated_on_every_hit() {
+        let (breakpoints, shared) = shared_lines();
+        let hit = breakpoints_at(&breakpoints, &shared, ADDRESS);
+        let mut states = HashMap::new();
+        let mut state = BreakpointState::default();
+        state.set_options(Some(Condition::parse("x > 2").unwrap()), None, None);
+        states.insert(u32::MAX, state);
+        let mut state = BreakpointState::default();
+        state.set_options(None, Some(HitCondition::parse("2").unwrap()), None);
+        states.insert(ADDRESS, state);
+
+        let conditions = breakpoint_conditions(&hit, &states, ADDRESS);
+        assert_eq!(conditions.len(), 1);
+        assert_eq!(conditions[0].0, u32::MAX);
+
+        // x is 1, the condition of line 11 is unmet and it doesn't count the hit
+        let unmet_ids = unmet(breakpoint_conditions(&hit, &states, ADDRESS), 1);
+        assert_eq!(unmet_ids, [u32::MAX]);
+        let counted = count_hits(&hit, &mut states, &unmet_ids, ADDRESS);
+        assert_eq!(counted.not_reached, 1);
+        assert_eq!(states[&u32::MAX].hit_count, 0);
+        assert_eq!(states[&(u32::MAX - 1)].hit_count, 1);
+
+        // x is 3 on the next hit, the condition is met and line 10 reaches its hit count
+        let unmet_ids = unmet(breakpoint_conditions(&hit, &states, ADDRESS), 3);
+        assert!(unmet_ids.is_empty());
+        let counted = count_hits(&hit, &mut states, &unmet_ids, ADDRESS);
+        assert_eq!(counted.not_reached, 0);
+        assert_eq!(states[&u32::MAX].hit_count, 1);
+        assert_eq!(states[&ADDRESS].hit_count, 2);
+    }
+}
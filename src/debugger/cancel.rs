@@ -0,0 +1,173 @@
+//! Cancelling long operations when the frontend disconnects or cancels the request
+//!
+//! The token is shared by the debugger thread and its frontend. On disconnect the adapter
+//! cancels it before it sends the exit request, so an operation that loops or waits, the chunks
+//! of a core dump, the instructions of `nexti`, the run over a call, the frames of a stack trace
+//! and the clock calibration, stops at its next check with `Cancelled` and puts the target back
+//! the way it found it. The debugger then handles the exit: it removes its breakpoints and
+//! watchpoints and does the disconnect action. The adapter waits at most `DISCONNECT_TIMEOUT`
+//! for that before it answers the disconnect.
+//!
+//! A DAP `cancel` for the request in flight only cancels that request, the debugger is blocked
+//! in it and can't be sent a request, so the adapter sets the token directly and clears it again
+//! once the request is answered.
+//!
+//! Flashing is checked before it starts but not interrupted, an aborted erase leaves the target
+//! without a program.
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long the adapter waits for the debugger to clean up before it answers the disconnect.
+pub const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Returned by an operation stopped by its cancel token.
+#[derive(Debug)]
+pub enum Cancelled {
+    Disconnect,
+    Request,
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cancelled::Disconnect => write!(f, "Cancelled, the frontend disconnected"),
+            Cancelled::Request => write!(f, "Cancelled by the frontend"),
+        }
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    // Only the request in flight, cleared when it is answered.
+    request: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn cancel_request(&self) {
+        self.request.store(true, Ordering::SeqCst);
+    }
+
+    pub fn clear_request(&self) {
+        self.request.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst) || self.request.load(Ordering::SeqCst)
+    }
+
+    /// Fails with `Cancelled` once the token or the request in flight is cancelled.
+    pub fn check(&self) -> Result<()> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return Err(Cancelled::Disconnect.into());
+        }
+        match self.request.load(Ordering::SeqCst) {
+            true => Err(Cancelled::Request.into()),
+            false => Ok(()),
+        }
+    }
+
+    /// Sleeps for the duration, or until the token is cancelled.
+    pub fn sleep(&self, duration: Duration) -> Result<()> {
+        let start = Instant::now();
+        while start.elapsed() < duration {
+            self.check()?;
+            thread::sleep(CHECK_INTERVAL.min(duration.saturating_sub(start.elapsed())));
+        }
+        self.check()
+    }
+
+    /// Like `Core::wait_for_core_halted`, but also returns when the token is cancelled.
+    pub fn wait_for_core_halted(&self, core: &mut probe_rs::Core, timeout: Duration) -> Result<()> {
+        let start = Instant::now();
+        loop {
+            if core.core_halted()? {
+                return Ok(());
+            }
+            self.check()?;
+            if start.elapsed() > timeout {
+                return Err(anyhow!("Timed out waiting for the core to halt"));
+            }
+            thread::sleep(CHECK_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cancelled(result: Result<()>) -> Cancelled {
+        result.unwrap_err().downcast().unwrap()
+    }
+
+    #[test]
+    fn check_after_cancel() {
+        let token = CancelToken::new();
+        assert!(token.check().is_ok());
+        assert!(!token.is_cancelled());
+
+        // A clone is the frontend's side of the token
+        token.clone().cancel();
+        assert!(token.is_cancelled());
+        assert!(matches!(cancelled(token.check()), Cancelled::Disconnect));
+    }
+
+    #[test]
+    fn request_cancel_is_cleared() {
+        let token = CancelToken::new();
+        token.cancel_request();
+        assert!(matches!(cancelled(token.check()), Cancelled::Request));
+
+        // The next request runs again
+        token.clear_request();
+        assert!(!token.is_cancelled());
+        assert!(token.check().is_ok());
+
+        // Clearing the request doesn't undo a disconnect
+        token.cancel();
+        token.cancel_request();
+        token.clear_request();
+        assert!(matches!(cancelled(token.check()), Cancelled::Disconnect));
+    }
+
+    #[test]
+    fn sleep_returns_early_when_cancelled() {
+        let token = CancelToken::new();
+        let frontend = token.clone();
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            frontend.cancel_request();
+        });
+
+        let start = Instant::now();
+        let result = token.sleep(Duration::from_secs(10));
+        canceller.join().unwrap();
+        assert!(matches!(cancelled(result), Cancelled::Request));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn sleep_runs_out() {
+        let token = CancelToken::new();
+        let start = Instant::now();
+        assert!(token.sleep(Duration::from_millis(30)).is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+}
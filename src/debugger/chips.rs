@@ -0,0 +1,136 @@
+//! Resolving the chip names users type into probe-rs target names
+//!
+//! The names in the probe-rs registry are exact, e.g. `STM32F401RETx`, while users type
+//! `stm32f401re` or `nrf52840`. Names are matched case-insensitively, an exact match wins, then
+//! a unique prefix match. When a prefix matches several package variants the one that is a prefix
+//! of all the others is the generic entry and is picked, otherwise the candidates are listed.
+use anyhow::{anyhow, Result};
+
+/// The most candidates listed in the error of an ambiguous name.
+const MAX_LISTED: usize = 16;
+
+/// Returns the registry name of the chip.
+pub fn resolve_chip(name: &str) -> Result<String> {
+    let families = probe_rs::config::families()
+        .map_err(|err| anyhow!("Failed to read the chip registry: {}", err))?;
+    let names: Vec<String> = families
+        .iter()
+        .flat_map(|family| family.variants.iter())
+        .map(|chip| chip.name.to_string())
+        .collect();
+
+    match_chip(name, &names)
+}
+
+fn match_chip(name: &str, names: &[String]) -> Result<String> {
+    let lower = name.to_lowercase();
+    if let Some(exact) = names.iter().find(|chip| chip.to_lowercase() == lower) {
+        return Ok(exact.clone());
+    }
+
+    let candidates: Vec<&String> = names
+        .iter()
+        .filter(|chip| chip.to_lowercase().starts_with(&lower))
+        .collect();
+    match candidates.len() {
+        0 => Err(anyhow!("Unknown chip `{}`", name)),
+        1 => Ok(candidates[0].clone()),
+        _ => {
+            let generic = candidates.iter().find(|generic| {
+                let generic = generic.to_lowercase();
+                candidates
+                    .iter()
+                    .all(|chip| chip.to_lowercase().starts_with(&generic))
+            });
+            match generic {
+                Some(val) => Ok((*val).clone()),
+                None => {
+                    let mut listed: Vec<&str> =
+                        candidates.iter().take(MAX_LISTED).map(|chip| chip.as_str()).collect();
+                    if candidates.len() > MAX_LISTED {
+                        listed.push("...");
+                    }
+                    Err(anyhow!(
+                        "The chip `{}` is ambiguous, it matches: {}",
+                        name,
+                        listed.join(", ")
+                    ))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> Vec<String> {
+        [
+            "STM32F401RBTx",
+            "STM32F401RETx",
+            "STM32F411RETx",
+            "nRF52840_xxAA",
+            "nRF52832_xxAA",
+            "nRF52832_xxAB",
+            "esp32c3",
+            "esp32c3_usb",
+        ]
+        .iter()
+        .map(|name| name.to_string())
+        .collect()
+    }
+
+    #[test]
+    fn exact_name() {
+        assert_eq!(match_chip("STM32F401RETx", &registry()).unwrap(), "STM32F401RETx");
+    }
+
+    #[test]
+    fn name_in_another_case() {
+        assert_eq!(match_chip("stm32f401retx", &registry()).unwrap(), "STM32F401RETx");
+        assert_eq!(match_chip("NRF52840_XXAA", &registry()).unwrap(), "nRF52840_xxAA");
+    }
+
+    #[test]
+    fn unique_prefix() {
+        assert_eq!(match_chip("stm32f401re", &registry()).unwrap(), "STM32F401RETx");
+        assert_eq!(match_chip("nrf52840", &registry()).unwrap(), "nRF52840_xxAA");
+        assert_eq!(match_chip("STM32F411", &registry()).unwrap(), "STM32F411RETx");
+    }
+
+    #[test]
+    fn exact_match_wins_over_prefixes() {
+        // Also a prefix of esp32c3_usb
+        assert_eq!(match_chip("ESP32C3", &registry()).unwrap(), "esp32c3");
+    }
+
+    #[test]
+    fn prefix_of_a_generic_entry() {
+        assert_eq!(match_chip("esp32", &registry()).unwrap(), "esp32c3");
+    }
+
+    #[test]
+    fn ambiguous_prefix_lists_the_candidates() {
+        let error = match_chip("nrf52832", &registry()).unwrap_err().to_string();
+        assert!(error.contains("ambiguous"), "{}", error);
+        assert!(error.contains("nRF52832_xxAA, nRF52832_xxAB"), "{}", error);
+
+        let error = match_chip("stm32f401", &registry()).unwrap_err().to_string();
+        assert!(error.contains("STM32F401RBTx, STM32F401RETx"), "{}", error);
+    }
+
+    #[test]
+    fn ambiguous_list_is_capped() {
+        let names: Vec<String> = (0..20).map(|i| format!("CHIP{:02}x", i)).collect();
+        let error = match_chip("chip", &names).unwrap_err().to_string();
+        assert!(error.contains("CHIP15x, ..."), "{}", error);
+        assert!(!error.contains("CHIP16x"), "{}", error);
+    }
+
+    #[test]
+    fn unknown_name() {
+        let error = match_chip("atsamd21", &registry()).unwrap_err().to_string();
+        assert_eq!(error, "Unknown chip `atsamd21`");
+    }
+}
@@ -0,0 +1,84 @@
+//! Code addresses and the Thumb bit
+//!
+//! Cortex-M only executes Thumb code, which is marked by setting bit 0 of code addresses in
+//! function symbol values, LR and the return addresses saved on the stack. Instructions are
+//! fetched from the address with bit 0 clear, which is what the PC holds and where breakpoints
+//! have to be set. A `CodeAddress` is always the fetch address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CodeAddress(u32);
+
+impl CodeAddress {
+    /// From an address that may have the Thumb bit set, e.g. a function symbol value.
+    pub fn new(address: u32) -> CodeAddress {
+        CodeAddress(address & !1)
+    }
+
+    /// From a return address, e.g. LR or the code location of a caller frame.
+    pub fn from_return_address(address: u32) -> CodeAddress {
+        CodeAddress::new(address)
+    }
+
+    /// The fetch address, use it for breakpoints and PC writes.
+    pub fn address(self) -> u32 {
+        self.0
+    }
+
+    /// The address inside the call instruction that returns to this address.
+    ///
+    /// A return address points after the call, which can be the first instruction of the next
+    /// line, so caller frames are symbolized with the last halfword of the call instead.
+    pub fn call_site(self) -> CodeAddress {
+        CodeAddress(self.0.wrapping_sub(2))
+    }
+
+    /// An EXC_RETURN value, the return address of an exception handler.
+    pub fn is_exception_return(self) -> bool {
+        self.0 >= 0xffff_ff00
+    }
+
+    pub fn offset(self, bytes: u32) -> CodeAddress {
+        CodeAddress(self.0.wrapping_add(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breakpoints_at_function_symbols() {
+        // A Thumb function symbol has bit 0 set, the breakpoint goes on the first instruction
+        assert_eq!(CodeAddress::new(0x0800_0195).address(), 0x0800_0194);
+        // An address from the line table is already even
+        assert_eq!(CodeAddress::new(0x0800_0194).address(), 0x0800_0194);
+    }
+
+    #[test]
+    fn pc_writes_clear_the_thumb_bit() {
+        // Writing an odd value to the PC through the debug interface would fault on resume
+        for address in [0x0800_0200, 0x0800_0201] {
+            assert_eq!(CodeAddress::new(address).address(), 0x0800_0200);
+        }
+        assert_eq!(
+            CodeAddress::new(0x0800_0201).offset(4).address(),
+            0x0800_0204
+        );
+    }
+
+    #[test]
+    fn lr_is_symbolized_at_the_call() {
+        // `bl` at 0x0800_0300 returns to 0x0800_0304, LR holds it with the Thumb bit set
+        for lr in [0x0800_0305, 0x0800_0304] {
+            let return_address = CodeAddress::from_return_address(lr);
+            assert_eq!(return_address.address(), 0x0800_0304);
+            assert_eq!(return_address.call_site().address(), 0x0800_0302);
+        }
+    }
+
+    #[test]
+    fn exception_returns() {
+        assert!(CodeAddress::from_return_address(0xffff_fff9).is_exception_return());
+        assert!(CodeAddress::from_return_address(0xffff_ffe1).is_exception_return());
+        assert!(!CodeAddress::from_return_address(0x0800_0305).is_exception_return());
+    }
+}
@@ -0,0 +1,434 @@
+//! Breakpoint conditions
+//!
+//! A condition is checked when its breakpoint is hit and the core is resumed without a stop if it
+//! is false. The conditions are small expressions over the variables of the innermost frame and
+//! the statics: comparisons like `i == 10` or `ptr != 0` joined with `&&`, `||` and `!`, where
+//! a variable is a path like `cfg.flags` or `buffer[3]`. A variable alone is true if it is not
+//! zero. Values are compared as numbers, so bools are 0 and 1, chars their code point and
+//! pointers their address.
+//!
+//! A hit condition is checked against the number of hits with a true condition: `5` or `== 5`
+//! stops at the fifth, `>= 3` at the third and every one after it and `% 10` at every tenth.
+use anyhow::{anyhow, Result};
+use serde::{Serialize, Serializer};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i128),
+    Float(f64),
+}
+
+impl Value {
+    /// Parses the value of a variable as it is displayed, None if it is not a number.
+    pub fn parse(text: &str) -> Option<Value> {
+        let text = text.trim();
+        match text {
+            "true" => return Some(Value::Int(1)),
+            "false" => return Some(Value::Int(0)),
+            _ => (),
+        }
+        if let Some(c) = text.strip_prefix('\'').and_then(|t| t.strip_suffix('\'')) {
+            let mut chars = c.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(Value::Int(c as i128)),
+                _ => None,
+            };
+        }
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text),
+        };
+        let number = match digits
+            .strip_prefix("0x")
+            .or_else(|| digits.strip_prefix("0X"))
+        {
+            Some(hex) => i128::from_str_radix(&hex.replace('_', ""), 16).ok(),
+            None => digits.replace('_', "").parse::<i128>().ok(),
+        };
+        match number {
+            Some(number) if negative => Some(Value::Int(-number)),
+            Some(number) => Some(Value::Int(number)),
+            None => text.parse::<f64>().ok().map(Value::Float),
+        }
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Value::Int(i) => i as f64,
+            Value::Float(f) => f,
+        }
+    }
+
+    fn is_true(self) -> bool {
+        match self {
+            Value::Int(i) => i != 0,
+            Value::Float(f) => f != 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    fn compare(self, left: Value, right: Value) -> bool {
+        let ordering = match (left, right) {
+            (Value::Int(l), Value::Int(r)) => l.partial_cmp(&r),
+            (l, r) => l.as_f64().partial_cmp(&r.as_f64()),
+        };
+        let ordering = match ordering {
+            Some(val) => val,
+            // NaN compares unequal to everything
+            None => return self == CompareOp::Ne,
+        };
+        match self {
+            CompareOp::Eq => ordering.is_eq(),
+            CompareOp::Ne => ordering.is_ne(),
+            CompareOp::Lt => ordering.is_lt(),
+            CompareOp::Le => ordering.is_le(),
+            CompareOp::Gt => ordering.is_gt(),
+            CompareOp::Ge => ordering.is_ge(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Literal(Value),
+    // Dotted, `buffer[3]` is `buffer.3`.
+    Variable(String),
+    Not(Box<Expr>),
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(String),
+    Char(char),
+    Ident(String),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 14] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "(", ")", ".", "[", "]",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap_or_default();
+        let len = if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            op.len()
+        } else if c.is_ascii_digit() || (c == '-' && rest[1..].starts_with(|c: char| c.is_ascii_digit())) {
+            let len = 1 + rest[1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len() - 1);
+            tokens.push(Token::Number(rest[..len].to_owned()));
+            len
+        } else if c == '\'' {
+            let mut chars = rest[1..].chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), Some('\'')) => {
+                    tokens.push(Token::Char(c));
+                    2 + c.len_utf8()
+                }
+                _ => return Err(anyhow!("Unterminated character literal in `{}`", text)),
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..len].to_owned()));
+            len
+        } else {
+            return Err(anyhow!("Unexpected `{}` in `{}`", c, text));
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        match self.peek() {
+            Some(Token::Op(o)) if *o == op => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.comparison()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.comparison()?));
+        }
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let left = self.unary()?;
+        let op = match self.peek() {
+            Some(Token::Op("==")) => CompareOp::Eq,
+            Some(Token::Op("!=")) => CompareOp::Ne,
+            Some(Token::Op("<")) => CompareOp::Lt,
+            Some(Token::Op("<=")) => CompareOp::Le,
+            Some(Token::Op(">")) => CompareOp::Gt,
+            Some(Token::Op(">=")) => CompareOp::Ge,
+            _ => return Ok(left),
+        };
+        self.position += 1;
+        let right = self.unary()?;
+        Ok(Expr::Compare(Box::new(left), op, Box::new(right)))
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.or()?;
+            if !self.eat(")") {
+                return Err(anyhow!("Expected `)`"));
+            }
+            return Ok(expr);
+        }
+        match self.next() {
+            Some(Token::Number(number)) => match Value::parse(&number) {
+                Some(value) => Ok(Expr::Literal(value)),
+                None => Err(anyhow!("`{}` is not a number", number)),
+            },
+            Some(Token::Char(c)) => Ok(Expr::Literal(Value::Int(c as i128))),
+            Some(Token::Ident(ident)) if ident == "true" => Ok(Expr::Literal(Value::Int(1))),
+            Some(Token::Ident(ident)) if ident == "false" => Ok(Expr::Literal(Value::Int(0))),
+            Some(Token::Ident(ident)) => self.path(ident),
+            Some(Token::Op(op)) => Err(anyhow!("Unexpected `{}`", op)),
+            None => Err(anyhow!("Unexpected end of the condition")),
+        }
+    }
+
+    fn path(&mut self, mut path: String) -> Result<Expr> {
+        loop {
+            if self.eat(".") {
+                match self.next() {
+                    Some(Token::Ident(member)) | Some(Token::Number(member)) => {
+                        path = format!("{}.{}", path, member)
+                    }
+                    _ => return Err(anyhow!("Expected a member name after `{}.`", path)),
+                }
+            } else if self.eat("[") {
+                let index = match self.next() {
+                    Some(Token::Number(index)) => index,
+                    _ => return Err(anyhow!("Expected an index after `{}[`", path)),
+                };
+                if !self.eat("]") {
+                    return Err(anyhow!("Expected `]` after `{}[{}`", path, index));
+                }
+                path = format!("{}.{}", path, index);
+            } else {
+                return Ok(Expr::Variable(path));
+            }
+        }
+    }
+}
+
+/// A parsed breakpoint condition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    text: String,
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+        };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(anyhow!("Unexpected {:?} in condition `{}`", token, text));
+        }
+        Ok(Condition {
+            text: text.trim().to_owned(),
+            expr,
+        })
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The dotted path if the condition is a variable alone.
+    pub fn variable(&self) -> Option<&str> {
+        match &self.expr {
+            Expr::Variable(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Evaluates the condition, `resolve` reads a variable by its dotted path.
+    pub fn evaluate(&self, resolve: &mut dyn FnMut(&str) -> Result<Value>) -> Result<bool> {
+        Ok(evaluate(&self.expr, resolve)?.is_true())
+    }
+}
+
+fn evaluate(expr: &Expr, resolve: &mut dyn FnMut(&str) -> Result<Value>) -> Result<Value> {
+    let truth = |value: bool| Value::Int(value as i128);
+    Ok(match expr {
+        Expr::Literal(value) => *value,
+        Expr::Variable(path) => resolve(path)?,
+        Expr::Not(expr) => truth(!evaluate(expr, resolve)?.is_true()),
+        Expr::Compare(left, op, right) => {
+            let left = evaluate(left, resolve)?;
+            truth(op.compare(left, evaluate(right, resolve)?))
+        }
+        Expr::And(left, right) => {
+            truth(evaluate(left, resolve)?.is_true() && evaluate(right, resolve)?.is_true())
+        }
+        Expr::Or(left, right) => {
+            truth(evaluate(left, resolve)?.is_true() || evaluate(right, resolve)?.is_true())
+        }
+    })
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Serialize for Condition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.text)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HitOp {
+    Compare(CompareOp),
+    Every,
+}
+
+/// A parsed hit condition with its hit counter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HitCondition {
+    text: String,
+    op: HitOp,
+    count: u32,
+    hits: u32,
+}
+
+impl HitCondition {
+    pub fn parse(text: &str) -> Result<HitCondition> {
+        let text = text.trim();
+        let (op, count) = match text.strip_prefix('%') {
+            Some(count) => (HitOp::Every, count),
+            None => {
+                let op = ["==", "!=", ">=", "<=", ">", "<"]
+                    .iter()
+                    .find(|op| text.starts_with(*op));
+                let count = &text[op.map_or(0, |op| op.len())..];
+                let op = match op.copied() {
+                    Some("!=") => CompareOp::Ne,
+                    Some(">=") => CompareOp::Ge,
+                    Some("<=") => CompareOp::Le,
+                    Some(">") => CompareOp::Gt,
+                    Some("<") => CompareOp::Lt,
+                    _ => CompareOp::Eq,
+                };
+                (HitOp::Compare(op), count)
+            }
+        };
+        let count = match count.trim().parse::<u32>() {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(anyhow!(
+                    "Expected a hit count like `5`, `>= 3` or `% 10`, got `{}`",
+                    text
+                ))
+            }
+        };
+        if op == HitOp::Every && count == 0 {
+            return Err(anyhow!("`{}` divides by zero", text));
+        }
+        Ok(HitCondition {
+            text: text.to_owned(),
+            op,
+            count,
+            hits: 0,
+        })
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn hits(&self) -> u32 {
+        self.hits
+    }
+
+    /// Counts a hit, returns true if the core should stop at it.
+    pub fn hit(&mut self) -> bool {
+        self.hits += 1;
+        match self.op {
+            HitOp::Compare(op) => op.compare(
+                Value::Int(self.hits as i128),
+                Value::Int(self.count as i128),
+            ),
+            HitOp::Every => self.hits % self.count == 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.hits = 0;
+    }
+}
+
+impl fmt::Display for HitCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Serialize for HitCondition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.text)
+    }
+}
@@ -1,24 +1,243 @@
-use std::path::PathBuf;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::core_clock::CoreClock;
+use super::formats::{FormatFile, FormatOverrides};
+use super::semihosting::CommandLine;
+use super::trap_catch::ExceptionFilter;
+use super::write_policy::PeripheralWrites;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SymbolFile {
+    pub path: PathBuf,
+    pub base_address: u32,
+}
+
+/// How the debugger polls the target while it runs.
+#[derive(Debug, Clone)]
+pub struct PollingConfig {
+    pub status_interval: Duration,
+    /// No periodic probe traffic while the target runs, self-halts are only noticed on pause.
+    pub low_power: bool,
+}
+
+impl Default for PollingConfig {
+    fn default() -> PollingConfig {
+        PollingConfig {
+            status_interval: Duration::from_millis(400),
+            low_power: false,
+        }
+    }
+}
+
+/// Where the heap statistics are read from and if they are printed on every stop.
+#[derive(Debug, Clone, Default)]
+pub struct HeapConfig {
+    pub symbol: Option<String>,
+    pub stats_on_stop: bool,
+}
+
+/// What to do when a breakpoint in an interrupt handler is hit during a step.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StepBreakpoints {
+    /// Stop at the breakpoint and abandon the step.
+    Stop,
+    /// Report the hit, resume and complete the step.
+    Defer,
+}
+
+impl Default for StepBreakpoints {
+    fn default() -> StepBreakpoints {
+        StepBreakpoints::Stop
+    }
+}
+
+impl FromStr for StepBreakpoints {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stop" => Ok(StepBreakpoints::Stop),
+            "defer" => Ok(StepBreakpoints::Defer),
+            _ => Err(anyhow!("Expected stop or defer")),
+        }
+    }
+}
+
+/// What is done with the target when the frontend disconnects, after the breakpoints and
+/// watchpoints are removed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DisconnectAction {
+    /// Leave the core halted or running.
+    Leave,
+    /// Resume a halted core.
+    Resume,
+    Reset,
+}
+
+impl Default for DisconnectAction {
+    fn default() -> DisconnectAction {
+        DisconnectAction::Leave
+    }
+}
+
+impl FromStr for DisconnectAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "leave" => Ok(DisconnectAction::Leave),
+            "resume" => Ok(DisconnectAction::Resume),
+            "reset" => Ok(DisconnectAction::Reset),
+            _ => Err(anyhow!("Expected leave, resume or reset")),
+        }
+    }
+}
+
+/// The configuration `reload` reads again from its files.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReloadTarget {
+    Svd,
+    SourceMap,
+    /// The format files loaded with `format load`.
+    Formatters,
+    All,
+}
+
+impl FromStr for ReloadTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "svd" => Ok(ReloadTarget::Svd),
+            "sourcemap" => Ok(ReloadTarget::SourceMap),
+            "formatters" => Ok(ReloadTarget::Formatters),
+            "all" => Ok(ReloadTarget::All),
+            _ => Err(anyhow!("Expected svd, sourcemap, formatters or all")),
+        }
+    }
+}
 
 pub struct Config {
     pub elf_file_path:  Option<PathBuf>,
+    pub symbol_files:   Vec<SymbolFile>,
     pub chip:           Option<String>,
+    pub core_dump:      Option<PathBuf>,
     pub work_directory: Option<String>,
     pub probe_num:      usize,
+    /// Wait for a probe held by another process instead of failing at once.
+    pub force_claim:    bool,
+    pub session_log:    Option<PathBuf>,
+    pub polling:        PollingConfig,
+    pub heap:           HeapConfig,
+    pub step_breakpoints: StepBreakpoints,
+    pub catch_functions: Vec<String>,
+    /// Set by the DAP client, None keeps the default catches.
+    pub exception_filters: Option<Vec<ExceptionFilter>>,
+    /// The number of stepped instructions that can be stepped back over, 0 disables it.
+    pub instruction_trace: usize,
+    /// Read flash from the target instead of the binary, for programs that write their own flash.
+    pub live_flash_reads: bool,
+    /// Show the memory region of addresses, e.g. `0x20001f00 [ram]`.
+    pub region_annotations: bool,
+    /// Parse the output of the firmware for test results, see `test_run`.
+    pub test_run: bool,
+    /// The display formats set with `format`, kept for the whole session.
+    pub formats: FormatOverrides,
+    /// The files the formats were loaded from, read again by `reload formatters`.
+    pub format_files: Vec<FormatFile>,
+    pub peripheral_writes: PeripheralWrites,
+    /// Answered to the SYS_GET_CMDLINE semihosting call.
+    pub command_line: CommandLine,
+    /// Set by the user or measured with `calibrate-clock`.
+    pub core_clock: Option<CoreClock>,
+    pub disconnect_action: DisconnectAction,
+    /// Keep the core halted after a reset the target did by itself.
+    pub halt_on_reset: bool,
+    /// Refuse to flash a binary that doesn't fit the memory map of the chip.
+    pub check_flash_layout: bool,
 }
 
 impl Config {
     pub fn new(opt: super::Opt) -> Config {
         Config {
             elf_file_path: opt.elf_file_path,
+            symbol_files: opt
+                .symbol_files
+                .into_iter()
+                .map(|path| SymbolFile {
+                    path,
+                    base_address: 0,
+                })
+                .collect(),
             chip: opt.chip,
+            core_dump: opt.core_dump,
             work_directory: opt.work_directory,
             probe_num: 0,
+            force_claim: opt.force_claim,
+            session_log: opt.session_log,
+            polling: PollingConfig::default(),
+            heap: HeapConfig::default(),
+            step_breakpoints: StepBreakpoints::default(),
+            catch_functions: vec![],
+            exception_filters: None,
+            instruction_trace: 0,
+            live_flash_reads: false,
+            region_annotations: true,
+            test_run: opt.test_run,
+            formats: FormatOverrides::default(),
+            format_files: vec![],
+            peripheral_writes: PeripheralWrites::default(),
+            command_line: CommandLine::default(),
+            core_clock: None,
+            disconnect_action: DisconnectAction::default(),
+            halt_on_reset: true,
+            check_flash_layout: true,
         }
     }
 
+    /// The chip is the only required configuration, without a binary only the raw target
+    /// inspection commands are available and the work directory defaults to the current one.
+    /// A core dump replaces the chip and probe.
     pub fn is_missing_config(&self) -> bool {
-        self.elf_file_path.is_none() || self.chip.is_none() || self.work_directory.is_none()
+        self.chip.is_none() && self.core_dump.is_none()
+    }
+
+    /// Resolves the relative paths against the working directory of the session.
+    pub fn resolve_paths(&mut self, cwd: &str) {
+        if let Some(path) = &mut self.elf_file_path {
+            *path = resolve_path(cwd, path);
+        }
+        for symbol_file in &mut self.symbol_files {
+            symbol_file.path = resolve_path(cwd, &symbol_file.path);
+        }
+        if let Some(path) = &mut self.core_dump {
+            *path = resolve_path(cwd, path);
+        }
+        if let Some(path) = &mut self.session_log {
+            *path = resolve_path(cwd, path);
+        }
+    }
+
+    /// The files to read the symbols from, the flashed binary is used if none are set.
+    pub fn effective_symbol_files(&self) -> Vec<SymbolFile> {
+        if !self.symbol_files.is_empty() {
+            return self.symbol_files.clone();
+        }
+
+        match &self.elf_file_path {
+            Some(path) => vec![SymbolFile {
+                path: path.clone(),
+                base_address: 0,
+            }],
+            None => vec![],
+        }
     }
 
     pub fn missing_config_message(&self) -> String {
@@ -27,17 +246,91 @@ impl Config {
         }
 
         let mut error = "Missing required configurations:".to_owned();
-        if self.elf_file_path.is_none() {
-            error = format!("{}\n\t{}", error, "elf file path");
-        }
         if self.chip.is_none() {
-            error = format!("{}\n\t{}", error, "chip");
-        }
-        if self.work_directory.is_none() {
-            error = format!("{}\n\t{}", error, "work directory");
+            error = format!("{}\n\t{}", error, "chip (or a core dump)");
         }
 
         error
     }
 }
 
+/// Resolves a relative path against the working directory of a session. The process working
+/// directory is never changed, it is shared by all the sessions of the server.
+pub fn resolve_path(cwd: &str, path: &Path) -> PathBuf {
+    match path.is_relative() {
+        true => Path::new(cwd).join(path),
+        false => path.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Opt;
+    use structopt::StructOpt;
+
+    // The configuration a session of the server starts with.
+    fn session_config() -> Config {
+        Config::new(Opt::from_iter([
+            "embedded-rust-debugger",
+            "--elf-file",
+            "target/app.elf",
+            "--symbol-file",
+            "bootloader/boot.elf",
+            "--symbol-file",
+            "/opt/firmware/rom.elf",
+        ]))
+    }
+
+    #[test]
+    fn sessions_resolve_paths_against_their_own_cwd() {
+        let mut first = session_config();
+        let mut second = session_config();
+        first.resolve_paths("/work/first");
+        second.resolve_paths("/work/second");
+
+        assert_eq!(
+            first.elf_file_path.as_deref(),
+            Some(Path::new("/work/first/target/app.elf"))
+        );
+        assert_eq!(
+            second.elf_file_path.as_deref(),
+            Some(Path::new("/work/second/target/app.elf"))
+        );
+
+        let paths = |config: &Config| -> Vec<PathBuf> {
+            config
+                .effective_symbol_files()
+                .into_iter()
+                .map(|symbol_file| symbol_file.path)
+                .collect()
+        };
+        assert_eq!(
+            paths(&first),
+            [
+                PathBuf::from("/work/first/bootloader/boot.elf"),
+                PathBuf::from("/opt/firmware/rom.elf")
+            ]
+        );
+        assert_eq!(
+            paths(&second),
+            [
+                PathBuf::from("/work/second/bootloader/boot.elf"),
+                PathBuf::from("/opt/firmware/rom.elf")
+            ]
+        );
+    }
+
+    #[test]
+    fn the_binary_is_the_symbol_file_of_its_session() {
+        let mut config = Config::new(Opt::from_iter([
+            "embedded-rust-debugger",
+            "--elf-file",
+            "app.elf",
+        ]));
+        config.resolve_paths("/work/first");
+        let symbol_files = config.effective_symbol_files();
+        assert_eq!(symbol_files.len(), 1);
+        assert_eq!(symbol_files[0].path, Path::new("/work/first/app.elf"));
+    }
+}
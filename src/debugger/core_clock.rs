@@ -0,0 +1,186 @@
+//! The core clock, for converting cycle counts to time
+//!
+//! `calibrate-clock` runs the core for an interval timed on the host with the DWT cycle counter
+//! counting, and divides. The host only knows the core ran somewhere between the end of the run
+//! and the start of the halt command and the start of the run and the end of the halt command,
+//! the middle is taken and half the difference is the uncertainty. A longer interval makes it
+//! smaller. The counter stops while the core sleeps, so firmware that waits in WFI reads low.
+//!
+//! The run state, the DWT registers and the cycle counter are restored afterwards, also when the
+//! calibration is cancelled. Without a cycle counter the clock set by the user is kept.
+use anyhow::{anyhow, Result};
+use debugserver_types::Breakpoint;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::cancel::CancelToken;
+use super::continue_fix;
+
+const DEMCR: u32 = 0xe000_edfc;
+const DEMCR_TRCENA: u32 = 1 << 24;
+const DWT_CTRL: u32 = 0xe000_1000;
+const DWT_CTRL_NOCYCCNT: u32 = 1 << 25;
+const DWT_CTRL_CYCCNTENA: u32 = 1;
+const DWT_CYCCNT: u32 = 0xe000_1004;
+const HALT_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub const DEFAULT_CALIBRATION_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The 32 bit counter wraps after 4.29 s at 1 GHz.
+pub const MAX_CALIBRATION_INTERVAL: Duration = Duration::from_secs(4);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ClockSource {
+    User,
+    Calibrated {
+        interval_ms: u64,
+        /// Relative to the measured clock, in parts per million.
+        uncertainty_ppm: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoreClock {
+    pub hz: u32,
+    pub source: ClockSource,
+}
+
+impl CoreClock {
+    pub fn user(hz: u32) -> CoreClock {
+        CoreClock {
+            hz,
+            source: ClockSource::User,
+        }
+    }
+
+    pub fn duration(&self, cycles: u64) -> Duration {
+        Duration::from_nanos(cycles * 1_000_000_000 / self.hz.max(1) as u64)
+    }
+}
+
+impl fmt::Display for CoreClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.3} MHz", self.hz as f64 / 1_000_000.0)?;
+        match self.source {
+            ClockSource::User => write!(f, " (set by the user)"),
+            ClockSource::Calibrated {
+                interval_ms,
+                uncertainty_ppm,
+            } => write!(
+                f,
+                " (calibrated over {} ms, ±{:.2} %)",
+                interval_ms,
+                uncertainty_ppm as f64 / 10_000.0
+            ),
+        }
+    }
+}
+
+/// The DWT state changed by the calibration.
+struct DwtState {
+    demcr: u32,
+    ctrl: u32,
+    cyccnt: u32,
+}
+
+impl DwtState {
+    fn read(core: &mut probe_rs::Core) -> Result<DwtState> {
+        let demcr = core.read_word_32(DEMCR)?;
+        core.write_word_32(DEMCR, demcr | DEMCR_TRCENA)?;
+        let ctrl = core.read_word_32(DWT_CTRL)?;
+        let state = DwtState {
+            demcr,
+            ctrl,
+            cyccnt: core.read_word_32(DWT_CYCCNT)?,
+        };
+        if ctrl & DWT_CTRL_NOCYCCNT != 0 {
+            state.restore(core)?;
+            return Err(anyhow!("The core has no DWT cycle counter"));
+        }
+        Ok(state)
+    }
+
+    fn restore(&self, core: &mut probe_rs::Core) -> Result<()> {
+        core.write_word_32(DWT_CTRL, self.ctrl)?;
+        core.write_word_32(DWT_CYCCNT, self.cyccnt)?;
+        core.write_word_32(DEMCR, self.demcr)?;
+        Ok(())
+    }
+}
+
+/// Measures the core clock, a running core is halted for it and resumed afterwards.
+pub fn calibrate(
+    core: &mut probe_rs::Core,
+    breakpoints: &HashMap<u32, Breakpoint>,
+    interval: Duration,
+    cancel: &CancelToken,
+) -> Result<CoreClock> {
+    if interval > MAX_CALIBRATION_INTERVAL {
+        return Err(anyhow!(
+            "The cycle counter wraps within {} s, use a shorter interval",
+            MAX_CALIBRATION_INTERVAL.as_secs()
+        ));
+    }
+
+    let was_running = !core.core_halted()?;
+    if was_running {
+        core.halt(HALT_TIMEOUT)?;
+    }
+    let dwt = DwtState::read(core)?;
+
+    let measured = measure(core, breakpoints, interval, cancel);
+
+    dwt.restore(core)?;
+    if was_running {
+        core.run()?;
+    }
+    let (cycles, run_time, uncertainty) = measured?;
+
+    let hz = (cycles as f64 / run_time.as_secs_f64()) as u32;
+    Ok(CoreClock {
+        hz,
+        source: ClockSource::Calibrated {
+            interval_ms: interval.as_millis() as u64,
+            uncertainty_ppm: (uncertainty.as_secs_f64() / run_time.as_secs_f64() * 1e6) as u32,
+        },
+    })
+}
+
+/// Runs the halted core for the interval, returns the counted cycles, the estimated run time and
+/// its uncertainty.
+fn measure(
+    core: &mut probe_rs::Core,
+    breakpoints: &HashMap<u32, Breakpoint>,
+    interval: Duration,
+    cancel: &CancelToken,
+) -> Result<(u32, Duration, Duration)> {
+    continue_fix(core, breakpoints)?;
+    core.write_word_32(DWT_CYCCNT, 0)?;
+    let ctrl = core.read_word_32(DWT_CTRL)?;
+    core.write_word_32(DWT_CTRL, ctrl | DWT_CTRL_CYCCNTENA)?;
+
+    let run_sent = Instant::now();
+    core.run()?;
+    let running = Instant::now();
+    if let Err(err) = cancel.sleep(interval) {
+        core.halt(HALT_TIMEOUT)?;
+        return Err(err);
+    }
+    if core.core_halted()? {
+        return Err(anyhow!(
+            "The core stopped during the calibration, remove the breakpoints it runs into"
+        ));
+    }
+    let halt_sent = Instant::now();
+    core.halt(HALT_TIMEOUT)?;
+    let halted = Instant::now();
+
+    let cycles = core.read_word_32(DWT_CYCCNT)?;
+    let shortest = halt_sent - running;
+    let longest = halted - run_sent;
+    Ok((cycles, (shortest + longest) / 2, (longest - shortest) / 2))
+}
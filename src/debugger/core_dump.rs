@@ -0,0 +1,225 @@
+//! Core dump container format
+//!
+//! A core dump file is laid out as:
+//!
+//! | Size     | Content                                   |
+//! |----------|-------------------------------------------|
+//! | 8        | Magic `ERDBCORE`                          |
+//! | 4        | Format version, little endian u32         |
+//! | 4        | Header length `n`, little endian u32      |
+//! | n        | Header, a UTF-8 JSON `CoreDumpHeader`     |
+//! | ...      | The memory of each region, in order       |
+//!
+//! Each region in the header has the offset of its data relative to the end of the header.
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"ERDBCORE";
+const VERSION: u32 = 1;
+
+/// The memory is read from the target in chunks of this size.
+pub const CHUNK_SIZE: u32 = 0x1000;
+
+/// Which memory to include in a core dump.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DumpRegions {
+    Ram,
+    RamAndFlash,
+    Ranges(Vec<Range<u32>>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoreDumpRegion {
+    pub name: String,
+    pub start: u32,
+    pub size: u32,
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoreDumpRegister {
+    pub name: String,
+    /// The DCRSR register selector, the same numbering the DWARF register numbers map to.
+    pub address: u16,
+    pub value: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoreDumpHeader {
+    pub chip: String,
+    pub program: Option<String>,
+    pub build_id: Option<String>,
+    pub registers: Vec<CoreDumpRegister>,
+    pub regions: Vec<CoreDumpRegion>,
+}
+
+/// A core dump loaded into memory.
+#[derive(Debug, Clone)]
+pub struct CoreDump {
+    pub header: CoreDumpHeader,
+    pub data: Vec<u8>,
+}
+
+impl CoreDump {
+    pub fn new(header: CoreDumpHeader) -> CoreDump {
+        CoreDump {
+            header,
+            data: vec![],
+        }
+    }
+
+    /// Appends the memory of a region, the regions must be added in order.
+    pub fn add_region(&mut self, name: &str, start: u32, memory: &[u8]) {
+        self.header.regions.push(CoreDumpRegion {
+            name: name.to_owned(),
+            start,
+            size: memory.len() as u32,
+            offset: self.data.len() as u64,
+        });
+        self.data.extend_from_slice(memory);
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let header = serde_json::to_vec(&self.header)?;
+
+        let mut file =
+            fs::File::create(path).context(format!("Failed to create core dump {:?}", path))?;
+        file.write_all(MAGIC)?;
+        file.write_all(&VERSION.to_le_bytes())?;
+        file.write_all(&(header.len() as u32).to_le_bytes())?;
+        file.write_all(&header)?;
+        file.write_all(&self.data)?;
+
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<CoreDump> {
+        let mut file =
+            fs::File::open(path).context(format!("Failed to open core dump {:?}", path))?;
+
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(anyhow!("{:?} is not a core dump", path));
+        }
+
+        let mut word = [0u8; 4];
+        file.read_exact(&mut word)?;
+        let version = u32::from_le_bytes(word);
+        if version != VERSION {
+            return Err(anyhow!("Unsupported core dump version {}", version));
+        }
+
+        file.read_exact(&mut word)?;
+        let header_len = u32::from_le_bytes(word) as u64;
+        // A corrupt length would otherwise allocate up to 4 GiB
+        let file_len = file.metadata()?.len();
+        if header_len > file_len.saturating_sub(16) {
+            return Err(anyhow!(
+                "The header of {:?} is {} bytes long, longer than the file",
+                path,
+                header_len
+            ));
+        }
+        let mut header = vec![0u8; header_len as usize];
+        file.read_exact(&mut header)?;
+        let header: CoreDumpHeader = serde_json::from_slice(&header)?;
+
+        let mut data = vec![];
+        file.read_to_end(&mut data)?;
+
+        Ok(CoreDump { header, data })
+    }
+
+    /// Reads memory from the dump, `None` if any of the bytes were not captured.
+    pub fn read_memory(&self, address: u32, num_bytes: usize) -> Option<Vec<u8>> {
+        for region in &self.header.regions {
+            let end = region.start as u64 + region.size as u64;
+            if address >= region.start && address as u64 + num_bytes as u64 <= end {
+                let start = (region.offset + (address - region.start) as u64) as usize;
+                return self.data.get(start..start + num_bytes).map(|d| d.to_vec());
+            }
+        }
+        None
+    }
+
+    pub fn register(&self, address: u16) -> Option<u32> {
+        self.header
+            .registers
+            .iter()
+            .find(|register| register.address == address)
+            .map(|register| register.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("erdb-{}-{}.core", name, std::process::id()))
+    }
+
+    fn header() -> CoreDumpHeader {
+        CoreDumpHeader {
+            chip: "STM32F411RETx".to_owned(),
+            program: Some("app.elf".to_owned()),
+            build_id: Some("0123abcd".to_owned()),
+            registers: vec![CoreDumpRegister {
+                name: "pc".to_owned(),
+                address: 15,
+                value: 0x0800_0100,
+            }],
+            regions: vec![],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut dump = CoreDump::new(header());
+        dump.add_region("RAM", 0x2000_0000, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        dump.add_region("FLASH", 0x0800_0000, &[0xaa, 0xbb]);
+
+        let path = temp_path("round-trip");
+        dump.write(&path).unwrap();
+        let read = CoreDump::read(&path);
+        fs::remove_file(&path).unwrap();
+        let read = read.unwrap();
+
+        assert_eq!(read.header.chip, "STM32F411RETx");
+        assert_eq!(read.header.build_id.as_deref(), Some("0123abcd"));
+        assert_eq!(read.register(15), Some(0x0800_0100));
+        assert_eq!(read.register(14), None);
+        assert_eq!(read.header.regions.len(), 2);
+        assert_eq!(read.read_memory(0x2000_0002, 4), Some(vec![3, 4, 5, 6]));
+        assert_eq!(read.read_memory(0x0800_0000, 2), Some(vec![0xaa, 0xbb]));
+        // Partly outside a region or not captured at all
+        assert_eq!(read.read_memory(0x2000_0006, 4), None);
+        assert_eq!(read.read_memory(0x1fff_fffe, 4), None);
+        assert_eq!(read.read_memory(0x3000_0000, 1), None);
+    }
+
+    #[test]
+    fn header_longer_than_the_file() {
+        let path = temp_path("long-header");
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(b"{}");
+        fs::write(&path, &bytes).unwrap();
+        let read = CoreDump::read(&path);
+        fs::remove_file(&path).unwrap();
+
+        let err = read.unwrap_err();
+        assert!(err.to_string().contains("longer than the file"), "{}", err);
+    }
+}
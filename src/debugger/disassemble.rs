@@ -0,0 +1,105 @@
+//! Disassembly for the DAP disassembly view
+//!
+//! The view asks for a number of instructions around a memory reference, often starting before
+//! it with a negative instruction offset. Thumb-2 mixes 16 and 32 bit instructions, so decoding
+//! can't start at an arbitrary halfword: it starts at the function containing the first requested
+//! instruction, or at the requested address if there is none, and the instructions are counted
+//! from there. Bytes that don't decode are shown as `.short` halfwords and decoding continues
+//! after them. Instructions outside the memory that was read are filled with placeholders.
+use anyhow::{anyhow, Result};
+use capstone::Capstone;
+use serde::Serialize;
+
+/// The longest Thumb instruction, for sizing the memory to read.
+pub const MAX_INSTRUCTION_SIZE: u32 = 4;
+/// How far before the requested instructions decoding starts at most.
+pub const MAX_LOOKBACK: u32 = 4096;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DisassembledInstruction {
+    pub address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instruction_bytes: Option<String>,
+    pub instruction: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<debugserver_types::Source>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Decoded {
+    pub address: u32,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+/// Decodes all of the code, the undecodable halfwords included.
+pub fn decode(capstone: &Capstone, code: &[u8], address: u32) -> Result<Vec<Decoded>> {
+    let mut decoded = vec![];
+    let mut offset = 0;
+    while offset + 1 < code.len() {
+        let insns = capstone
+            .disasm_all(&code[offset..], address as u64 + offset as u64)
+            .map_err(|err| anyhow!("Failed to disassemble: {}", err))?;
+        for insn in insns.iter() {
+            offset += insn.bytes().len();
+            decoded.push(Decoded {
+                address: insn.address() as u32,
+                bytes: insn.bytes().to_vec(),
+                text: format!(
+                    "{} {}",
+                    insn.mnemonic().unwrap_or(""),
+                    insn.op_str().unwrap_or("")
+                )
+                .trim()
+                .to_owned(),
+            });
+        }
+        if offset + 1 < code.len() {
+            let halfword = u16::from_le_bytes([code[offset], code[offset + 1]]);
+            decoded.push(Decoded {
+                address: address + offset as u32,
+                bytes: code[offset..offset + 2].to_vec(),
+                text: format!(".short {:#06x}", halfword),
+            });
+            offset += 2;
+        }
+    }
+    Ok(decoded)
+}
+
+/// Picks `count` instructions starting `instruction_offset` instructions from the one containing
+/// `address`. The missing ones are None with their address, assuming halfword instructions.
+pub fn select(
+    decoded: &[Decoded],
+    address: u32,
+    instruction_offset: i64,
+    count: usize,
+) -> Vec<(u32, Option<&Decoded>)> {
+    let index = decoded
+        .iter()
+        .position(|insn| insn.address + insn.bytes.len() as u32 > address)
+        .unwrap_or(decoded.len()) as i64;
+    let (first, end) = match (decoded.first(), decoded.last()) {
+        (Some(first), Some(last)) => (first.address, last.address + last.bytes.len() as u32),
+        _ => (address, address),
+    };
+
+    let start = index + instruction_offset;
+    (start..start + count as i64)
+        .map(|i| match i {
+            i if i < 0 => (first.wrapping_sub((-i * 2) as u32), None),
+            i if i as usize >= decoded.len() => {
+                let after = i - decoded.len() as i64;
+                (end.wrapping_add((after * 2) as u32), None)
+            }
+            i => (decoded[i as usize].address, Some(&decoded[i as usize])),
+        })
+        .collect()
+}
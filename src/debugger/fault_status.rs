@@ -0,0 +1,138 @@
+//! The fault status registers of a Cortex-M, decoded
+//!
+//! A HardFault says little by itself, the cause is in the bits of CFSR and HFSR and the address
+//! in MMFAR or BFAR, if the status says it is valid. The registers are sticky, so they also tell
+//! what went wrong after the handler has run.
+use anyhow::Result;
+use probe_rs::MemoryInterface;
+use serde::Serialize;
+use std::fmt;
+
+use super::stepping::active_exception;
+use super::vector_table::exception_name;
+
+const CFSR: u32 = 0xe000_ed28;
+const HFSR: u32 = 0xe000_ed2c;
+const MMFAR: u32 = 0xe000_ed34;
+const BFAR: u32 = 0xe000_ed38;
+
+const CFSR_DACCVIOL: u32 = 1 << 1;
+const CFSR_MMARVALID: u32 = 1 << 7;
+const CFSR_PRECISERR: u32 = 1 << 9;
+const CFSR_BFARVALID: u32 = 1 << 15;
+
+/// The bits of MMFSR, BFSR and UFSR, the three parts of CFSR.
+const CFSR_BITS: [(u32, &str); 18] = [
+    (1 << 0, "instruction access violation"),
+    (CFSR_DACCVIOL, "data access violation"),
+    (1 << 3, "memory management fault on exception return"),
+    (1 << 4, "memory management fault on exception entry"),
+    (1 << 5, "memory management fault saving the FPU state"),
+    (1 << 8, "instruction bus error"),
+    (CFSR_PRECISERR, "precise data bus error"),
+    (1 << 10, "imprecise data bus error"),
+    (1 << 11, "bus fault on exception return"),
+    (1 << 12, "bus fault on exception entry"),
+    (1 << 13, "bus fault saving the FPU state"),
+    (1 << 16, "undefined instruction"),
+    (1 << 17, "invalid state, the Thumb bit is clear"),
+    (1 << 18, "invalid pc loaded on exception return"),
+    (1 << 19, "no coprocessor, the FPU may be disabled"),
+    (1 << 20, "stack overflow past the stack limit"),
+    (1 << 24, "unaligned access"),
+    (1 << 25, "division by zero"),
+];
+
+const HFSR_BITS: [(u32, &str); 3] = [
+    (1 << 1, "bus fault reading the vector table"),
+    (1 << 30, "escalated to a hard fault"),
+    (1 << 31, "debug event"),
+];
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FaultStatus {
+    /// The active exception, None in thread mode.
+    pub exception: Option<String>,
+    pub cfsr: u32,
+    pub hfsr: u32,
+    /// Only if the status says the address is valid.
+    pub mmfar: Option<u32>,
+    pub bfar: Option<u32>,
+    /// The set status bits, decoded.
+    pub faults: Vec<String>,
+}
+
+impl FaultStatus {
+    pub fn read(core: &mut probe_rs::Core) -> Result<FaultStatus> {
+        let exception = active_exception(core)?.map(exception_name);
+        let cfsr = core.read_word_32(CFSR)?;
+        let hfsr = core.read_word_32(HFSR)?;
+        let mmfar = match cfsr & CFSR_MMARVALID != 0 {
+            true => Some(core.read_word_32(MMFAR)?),
+            false => None,
+        };
+        let bfar = match cfsr & CFSR_BFARVALID != 0 {
+            true => Some(core.read_word_32(BFAR)?),
+            false => None,
+        };
+
+        let mut faults = vec![];
+        for (bit, text) in CFSR_BITS {
+            if cfsr & bit == 0 {
+                continue;
+            }
+            let address = match bit {
+                CFSR_DACCVIOL => mmfar,
+                CFSR_PRECISERR => bfar,
+                _ => None,
+            };
+            faults.push(match address {
+                Some(address) => format!("{} at {:#010x}", text, address),
+                None => text.to_owned(),
+            });
+        }
+        for (bit, text) in HFSR_BITS {
+            if hfsr & bit != 0 {
+                faults.push(text.to_owned());
+            }
+        }
+
+        Ok(FaultStatus {
+            exception,
+            cfsr,
+            hfsr,
+            mmfar,
+            bfar,
+            faults,
+        })
+    }
+
+    /// The decoded faults on one line.
+    pub fn summary(&self) -> String {
+        match self.faults.is_empty() {
+            true => "No fault is recorded in the fault status registers".to_owned(),
+            false => self.faults.join(", "),
+        }
+    }
+}
+
+impl fmt::Display for FaultStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.exception {
+            Some(exception) => write!(f, "Exception: {}", exception)?,
+            None => write!(f, "Exception: none, thread mode")?,
+        }
+        write!(f, "\nCFSR: {:#010x}\nHFSR: {:#010x}", self.cfsr, self.hfsr)?;
+        if let Some(mmfar) = self.mmfar {
+            write!(f, "\nMMFAR: {:#010x}", mmfar)?;
+        }
+        if let Some(bfar) = self.bfar {
+            write!(f, "\nBFAR: {:#010x}", bfar)?;
+        }
+        for fault in &self.faults {
+            write!(f, "\n    {}", fault)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+use super::values::{sign_extend, truncate, Endianness};
+use anyhow::Result;
+use gimli::{AttributeValue, DebuggingInformationEntry, Dwarf, Reader, Unit};
+
+/// The return type of a function, classified by how it is returned.
+#[derive(Debug, Clone)]
+pub enum ReturnType {
+    /// A base type returned in R0/R1, or S0/D0 for floats with the hard float ABI.
+    Base {
+        name: String,
+        encoding: gimli::DwAte,
+        byte_size: u64,
+    },
+    /// Anything else, e.g. structs, which may be returned in registers or through a sret pointer.
+    Other { name: String, byte_size: u64 },
+}
+
+/// The values of the registers a value can be returned in.
+#[derive(Debug, Clone)]
+pub struct ReturnRegisters {
+    /// The names of the two integer return registers on the core.
+    pub names: [&'static str; 2],
+    pub r0: u32,
+    pub r1: u32,
+    pub s0: Option<u32>,
+    pub s1: Option<u32>,
+    pub hard_float: bool,
+    pub endianness: Endianness,
+}
+
+/// Finds the return type of the function containing `pc`, `None` means the function returns `()`.
+pub fn function_return_type<R: Reader<Offset = usize>>(
+    dwarf: &Dwarf<R>,
+    pc: u32,
+) -> Result<Option<ReturnType>> {
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs()? {
+            if entry.tag() != gimli::DW_TAG_subprogram {
+                continue;
+            }
+
+            let mut in_range = false;
+            let mut ranges = dwarf.die_ranges(&unit, entry)?;
+            while let Some(range) = ranges.next()? {
+                if range.begin <= pc as u64 && (pc as u64) < range.end {
+                    in_range = true;
+                }
+            }
+            if !in_range {
+                continue;
+            }
+
+            return match type_attribute(&unit, entry)? {
+                Some(offset) => Ok(Some(classify_type(dwarf, &unit, offset)?)),
+                None => Ok(None),
+            };
+        }
+    }
+
+    Ok(None)
+}
+
+// The type is either on the subprogram or on the declaration it is a concrete instance of.
+fn type_attribute<R: Reader<Offset = usize>>(
+    unit: &Unit<R>,
+    entry: &DebuggingInformationEntry<R>,
+) -> Result<Option<gimli::UnitOffset>> {
+    if let Some(AttributeValue::UnitRef(offset)) = entry.attr_value(gimli::DW_AT_type)? {
+        return Ok(Some(offset));
+    }
+
+    for origin in [gimli::DW_AT_abstract_origin, gimli::DW_AT_specification] {
+        if let Some(AttributeValue::UnitRef(offset)) = entry.attr_value(origin)? {
+            let origin_entry = unit.entry(offset)?;
+            return type_attribute(unit, &origin_entry);
+        }
+    }
+
+    Ok(None)
+}
+
+fn classify_type<R: Reader<Offset = usize>>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    offset: gimli::UnitOffset,
+) -> Result<ReturnType> {
+    let entry = unit.entry(offset)?;
+
+    let name = match entry.attr(gimli::DW_AT_name)? {
+        Some(attr) => dwarf
+            .attr_string(unit, attr.value())?
+            .to_string_lossy()?
+            .to_string(),
+        None => "< unknown >".to_owned(),
+    };
+    let byte_size = match entry.attr_value(gimli::DW_AT_byte_size)? {
+        Some(value) => value.udata_value().unwrap_or(0),
+        None => 0,
+    };
+
+    match (entry.tag(), entry.attr_value(gimli::DW_AT_encoding)?) {
+        (gimli::DW_TAG_base_type, Some(AttributeValue::Encoding(encoding))) => {
+            Ok(ReturnType::Base {
+                name,
+                encoding,
+                byte_size,
+            })
+        }
+        _ => Ok(ReturnType::Other { name, byte_size }),
+    }
+}
+
+/// Decodes the returned value following the AAPCS, types that can't be decoded show the raw
+/// registers instead.
+pub fn format_return_value(return_type: &ReturnType, registers: &ReturnRegisters) -> String {
+    let raw = format!(
+        "{}: {:#010x}, {}: {:#010x}",
+        registers.names[0], registers.r0, registers.names[1], registers.r1
+    );
+    match return_type {
+        ReturnType::Base {
+            name,
+            encoding,
+            byte_size,
+        } => {
+            let low = registers.r0 as u64;
+            let wide = registers.endianness.join_words(registers.r0, registers.r1);
+            let value = match (*encoding, *byte_size) {
+                (gimli::DW_ATE_boolean, _) => format!("{}", low & 0xff != 0),
+                (gimli::DW_ATE_unsigned, 8) => format!("{}", wide),
+                (gimli::DW_ATE_signed, 8) => format!("{}", wide as i64),
+                (gimli::DW_ATE_unsigned, size) => format!("{}", truncate(low, size)),
+                (gimli::DW_ATE_signed, size) if size > 0 && size < 8 => {
+                    format!("{}", sign_extend(low, size))
+                }
+                (gimli::DW_ATE_UTF, _) => match char::from_u32(registers.r0) {
+                    Some(c) => format!("{:?}", c),
+                    None => raw,
+                },
+                (gimli::DW_ATE_float, 4) => {
+                    let bits = match (registers.hard_float, registers.s0) {
+                        (true, Some(s0)) => s0,
+                        _ => registers.r0,
+                    };
+                    format!("{}", f32::from_bits(bits))
+                }
+                (gimli::DW_ATE_float, 8) => {
+                    let bits = match (registers.hard_float, registers.s0, registers.s1) {
+                        (true, Some(s0), Some(s1)) => s0 as u64 | ((s1 as u64) << 32),
+                        _ => wide,
+                    };
+                    format!("{}", f64::from_bits(bits))
+                }
+                _ => raw,
+            };
+            format!("{} = {}", name, value)
+        }
+        ReturnType::Other { name, byte_size } => {
+            // Composite types larger than 4 bytes are returned through a pointer passed in r0/a0.
+            if *byte_size > 4 {
+                format!("{} = < returned in memory at {:#010x} >", name, registers.r0)
+            } else {
+                format!("{} = < {} >", name, raw)
+            }
+        }
+    }
+}
+
+/// An in progress `finish`, completed when the core halts at the return address.
+#[derive(Debug, Clone)]
+pub struct FinishState {
+    pub function: String,
+    pub return_address: u32,
+    /// The stack pointer of the caller once the function has returned, lower in a deeper call.
+    pub caller_stack_pointer: u32,
+    pub return_type: Option<ReturnType>,
+    pub hard_float: bool,
+    pub endianness: Endianness,
+    pub temporary_breakpoint: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registers(names: [&'static str; 2], r0: u32, r1: u32) -> ReturnRegisters {
+        ReturnRegisters {
+            names,
+            r0,
+            r1,
+            s0: None,
+            s1: None,
+            hard_float: false,
+            endianness: Endianness::Little,
+        }
+    }
+
+    fn base(encoding: gimli::DwAte, byte_size: u64) -> ReturnType {
+        ReturnType::Base {
+            name: "t".to_owned(),
+            encoding,
+            byte_size,
+        }
+    }
+
+    #[test]
+    fn integers_are_read_from_the_first_registers() {
+        let registers = registers(["a0", "a1"], 0xffff_fffe, 0xffff_ffff);
+        assert_eq!(
+            format_return_value(&base(gimli::DW_ATE_signed, 4), &registers),
+            "t = -2"
+        );
+        assert_eq!(
+            format_return_value(&base(gimli::DW_ATE_signed, 8), &registers),
+            "t = -2"
+        );
+        assert_eq!(
+            format_return_value(&base(gimli::DW_ATE_unsigned, 1), &registers),
+            "t = 254"
+        );
+    }
+
+    #[test]
+    fn raw_registers_use_the_core_names() {
+        let other = ReturnType::Other {
+            name: "S".to_owned(),
+            byte_size: 4,
+        };
+        assert_eq!(
+            format_return_value(&other, &registers(["a0", "a1"], 1, 2)),
+            "S = < a0: 0x00000001, a1: 0x00000002 >"
+        );
+        assert_eq!(
+            format_return_value(&other, &registers(["r0", "r1"], 1, 2)),
+            "S = < r0: 0x00000001, r1: 0x00000002 >"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! Reading flash-resident values from the binary instead of the target
+//!
+//! The contents of the loaded sections are kept when the symbols are loaded. Flash does not
+//! change while the program runs, so variable reads that fall entirely inside a section linked
+//! into a flash region are served from the file, which makes expanding a large constant table
+//! instant over a slow probe. Programs that write their own flash, e.g. bootloaders, need the
+//! live reads back with `live-flash-reads true`.
+use anyhow::Result;
+use object::{Object, ObjectSection, SectionKind};
+use probe_rs::config::MemoryRegion;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use super::symbols::{DebugInformation, DwarfReader, Symbols};
+use super::Variable;
+use super::VariableLocation;
+
+/// The file contents of a loaded section, at its link address.
+#[derive(Debug, Clone)]
+pub struct ImageSection {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl ImageSection {
+    /// Reads the loaded sections that have contents in the file, `.bss` and friends have none.
+    pub fn read(path: &Path) -> Result<Vec<ImageSection>> {
+        let file = fs::File::open(&path)?;
+        let mmap = unsafe { memmap::Mmap::map(&file)? };
+        let object = object::File::parse(&*mmap)?;
+
+        let mut sections = vec![];
+        for section in object.sections() {
+            match section.kind() {
+                SectionKind::Text
+                | SectionKind::Data
+                | SectionKind::ReadOnlyData
+                | SectionKind::ReadOnlyString => (),
+                _ => continue,
+            };
+            if section.file_range().is_none() || section.size() == 0 {
+                continue;
+            }
+            sections.push(ImageSection {
+                address: section.address() as u32,
+                data: section.data()?.to_vec(),
+            });
+        }
+        Ok(sections)
+    }
+
+    fn find(&self, address: u32, num_bytes: usize) -> Option<&[u8]> {
+        let offset = address.checked_sub(self.address)? as usize;
+        self.data.get(offset..offset.checked_add(num_bytes)?)
+    }
+}
+
+/// The flash contents of the loaded binaries.
+pub struct FlashImage<'a> {
+    symbols: &'a Symbols,
+    flash: Vec<Range<u32>>,
+}
+
+impl<'a> FlashImage<'a> {
+    pub fn new(symbols: &'a Symbols, memory_map: &[MemoryRegion]) -> FlashImage<'a> {
+        let flash = memory_map
+            .iter()
+            .filter_map(|region| match region {
+                MemoryRegion::Nvm(nvm) => Some(nvm.range.clone()),
+                _ => None,
+            })
+            .collect();
+        FlashImage { symbols, flash }
+    }
+
+    /// Returns the file contents of a flash range, `None` if any of it is not in flash or in a
+    /// loaded section.
+    pub fn find(&self, address: u32, num_bytes: usize) -> Option<&'a [u8]> {
+        let end = address as u64 + num_bytes as u64;
+        if !self
+            .flash
+            .iter()
+            .any(|range| range.start <= address && end <= range.end as u64)
+        {
+            return None;
+        }
+
+        let symbols: &'a Symbols = self.symbols;
+        for module in &symbols.modules {
+            let link_address = module.to_link_address(address);
+            for section in &module.image {
+                if let Some(bytes) = section.find(link_address, num_bytes) {
+                    return Some(bytes);
+                }
+            }
+        }
+        None
+    }
+
+    /// The target address and file contents of the start of the lowest section of the main
+    /// binary that is in flash, at most `max` bytes.
+    pub fn first_flash_bytes(&self, max: usize) -> Option<(u32, &'a [u8])> {
+        let symbols: &'a Symbols = self.symbols;
+        let module = symbols.main().ok()?;
+        let section = module
+            .image
+            .iter()
+            .filter(|section| {
+                let address = module.to_target_address(section.address);
+                self.flash.iter().any(|range| range.contains(&address))
+            })
+            .min_by_key(|section| section.address)?;
+
+        let len = section.data.len().min(max);
+        let address = module.to_target_address(section.address);
+        self.find(address, len).map(|bytes| (address, bytes))
+    }
+
+    /// The span of the sections of the module that are in flash, `None` if none of them is.
+    pub fn flash_range(&self, module: &DebugInformation<DwarfReader>) -> Option<Range<u32>> {
+        let sections = module.image.iter().filter_map(|section| {
+            let address = module.to_target_address(section.address);
+            match self.flash.iter().any(|range| range.contains(&address)) {
+                true => Some(address..address.wrapping_add(section.data.len() as u32)),
+                false => None,
+            }
+        });
+        sections.reduce(|span, section| span.start.min(section.start)..span.end.max(section.end))
+    }
+
+    /// Sets `read_only` on the variables whose value was read from the file.
+    pub fn mark_read_only(&self, variables: &mut [Variable]) {
+        for variable in variables {
+            variable.read_only = match variable.location {
+                Some(VariableLocation::Memory { address, byte_size }) => {
+                    self.find(address, byte_size).is_some()
+                }
+                _ => false,
+            };
+            self.mark_read_only(&mut variable.children);
+        }
+    }
+}
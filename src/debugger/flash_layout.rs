@@ -0,0 +1,183 @@
+//! Checking the binary against the memory map of the chip before flashing
+//!
+//! A binary linked for a chip with more flash than the selected one flashes fine until a segment
+//! falls off the end of the flash, and one linked for a different flash base doesn't boot at all.
+//! Every loadable segment is placed by its physical address, where probe-rs writes it, and must
+//! lie inside a single region of the memory map. The check is turned off with
+//! `check_flash_layout` for layouts the chip description doesn't know about.
+use anyhow::{anyhow, Context, Result};
+use object::elf::{FileHeader32, PT_LOAD};
+use object::read::elf::{FileHeader, ProgramHeader};
+use probe_rs::config::MemoryRegion;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+/// A loadable segment, the bytes probe-rs writes to the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadSegment {
+    pub address: u32,
+    pub size: u32,
+}
+
+impl LoadSegment {
+    fn end(&self) -> u64 {
+        self.address as u64 + self.size as u64
+    }
+}
+
+/// Reads the loadable segments with data from the program headers of the binary.
+pub fn load_segments(path: &Path) -> Result<Vec<LoadSegment>> {
+    let file = fs::File::open(path)?;
+    let mmap = unsafe { memmap::Mmap::map(&file)? };
+    let data = &*mmap;
+    let header = FileHeader32::<object::Endianness>::parse(data)
+        .context(format!("Failed to read the ELF header of {:?}", path))?;
+    let endian = header.endian()?;
+
+    let mut segments = vec![];
+    for segment in header.program_headers(endian, data)? {
+        // The .bss has no data to flash
+        if segment.p_type(endian) != PT_LOAD || segment.p_filesz(endian) == 0 {
+            continue;
+        }
+        segments.push(LoadSegment {
+            address: segment.p_paddr(endian),
+            size: segment.p_filesz(endian),
+        });
+    }
+    Ok(segments)
+}
+
+/// Describes every segment that doesn't fit in the memory map, nothing if all of them do or
+/// there is no memory map.
+pub fn layout_errors(segments: &[LoadSegment], memory_map: &[MemoryRegion]) -> Vec<String> {
+    let regions: Vec<(Range<u32>, &str)> = memory_map.iter().map(region_range).collect();
+    segment_errors(segments, &regions)
+}
+
+// The regions are the address ranges of the memory map with their kind.
+fn segment_errors(segments: &[LoadSegment], regions: &[(Range<u32>, &str)]) -> Vec<String> {
+    if regions.is_empty() {
+        return vec![];
+    }
+    let flash_base = regions
+        .iter()
+        .filter(|(_, kind)| *kind == "flash")
+        .map(|(range, _)| range.start)
+        .min();
+
+    let mut errors = vec![];
+    for segment in segments {
+        let span = format!("{:#010x}..{:#010x}", segment.address, segment.end());
+        let region = regions
+            .iter()
+            .find(|(range, _)| range.contains(&segment.address));
+        match region {
+            Some((region, kind)) => {
+                if segment.end() > region.end as u64 {
+                    errors.push(format!(
+                        "the segment at {} ends {} bytes past the {} region {:#010x}..{:#010x}",
+                        span,
+                        segment.end() - region.end as u64,
+                        kind,
+                        region.start,
+                        region.end
+                    ));
+                }
+            }
+            None => match flash_base {
+                Some(base) if segment.address < base => errors.push(format!(
+                    "the segment at {} starts before the flash base {:#010x}",
+                    span, base
+                )),
+                _ => errors.push(format!(
+                    "the segment at {} is outside every region of the memory map",
+                    span
+                )),
+            },
+        }
+    }
+    errors
+}
+
+/// Fails if a segment of the binary doesn't fit in the memory map of the chip.
+pub fn check_layout(path: &Path, chip: &str, memory_map: &[MemoryRegion]) -> Result<()> {
+    let errors = layout_errors(&load_segments(path)?, memory_map);
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "{:?} does not fit the memory map of {}, it was probably linked for another chip:\n    {}\nSet check-flash-layout to false to flash it anyway",
+        path,
+        chip,
+        errors.join("\n    ")
+    ))
+}
+
+fn region_range(region: &MemoryRegion) -> (Range<u32>, &'static str) {
+    match region {
+        MemoryRegion::Ram(ram) => (ram.range.clone(), "ram"),
+        MemoryRegion::Nvm(nvm) => (nvm.range.clone(), "flash"),
+        MemoryRegion::Generic(generic) => (generic.range.clone(), "memory"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 256 KiB of flash and 64 KiB of RAM, like an STM32F401.
+    fn regions() -> Vec<(Range<u32>, &'static str)> {
+        vec![
+            (0x0800_0000..0x0804_0000, "flash"),
+            (0x2000_0000..0x2001_0000, "ram"),
+        ]
+    }
+
+    fn segment(address: u32, size: u32) -> LoadSegment {
+        LoadSegment { address, size }
+    }
+
+    #[test]
+    fn segments_inside_the_regions_fit() {
+        let segments = [segment(0x0800_0000, 0x1000), segment(0x0803_f000, 0x1000)];
+        assert!(segment_errors(&segments, &regions()).is_empty());
+    }
+
+    #[test]
+    fn a_segment_straddling_the_end_of_flash() {
+        // Linked for 1 MiB of flash
+        let errors = segment_errors(&[segment(0x0803_f000, 0x2000)], &regions());
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("ends 4096 bytes past the flash region"));
+    }
+
+    #[test]
+    fn a_segment_in_no_region() {
+        let errors = segment_errors(&[segment(0x0810_0000, 0x100)], &regions());
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("outside every region"));
+
+        let errors = segment_errors(&[segment(0x0000_0000, 0x100)], &regions());
+        assert!(errors[0].contains("starts before the flash base 0x08000000"));
+    }
+
+    #[test]
+    fn a_segment_in_a_ram_only_map() {
+        let ram_only = [(0x2000_0000..0x2001_0000, "ram")];
+        // Code linked to run from RAM
+        assert!(segment_errors(&[segment(0x2000_0000, 0x800)], &ram_only).is_empty());
+
+        let errors = segment_errors(&[segment(0x2000_f000, 0x2000)], &ram_only);
+        assert!(errors[0].contains("past the ram region"));
+        // No flash base to start before
+        let errors = segment_errors(&[segment(0x0800_0000, 0x100)], &ram_only);
+        assert!(errors[0].contains("outside every region"));
+    }
+
+    #[test]
+    fn no_memory_map_checks_nothing() {
+        assert!(segment_errors(&[segment(0, 0x100)], &[]).is_empty());
+    }
+}
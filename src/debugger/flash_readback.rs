@@ -0,0 +1,263 @@
+//! Reading the flash of a device back to a file
+//!
+//! The flash is read in chunks and appended to the file. Next to it, `<path>.partial` records the
+//! region and the CRC of every chunk written so far. If the readback is interrupted, reading the
+//! same region to the same path again checks the chunks in the file against their CRCs and
+//! continues after the last one that matches. The `.partial` file is removed once the whole
+//! region is read.
+//!
+//! The region is checked against the flash regions of the memory map first, a read that faults
+//! inside flash then means the chip is read-protected.
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use probe_rs::MemoryInterface;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use super::core_dump::CHUNK_SIZE;
+
+/// Parts of the errors the probes give for memory the debug port may not read.
+const PROTECTION_ERRORS: [&str; 5] = ["fault", "protect", "lock", "denied", "secure"];
+
+/// CRC-32 as used by zlib and `crc32` on the command line.
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        Crc32 { state: !0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.state ^= *byte as u32;
+            for _ in 0..8 {
+                let mask = (self.state & 1).wrapping_neg();
+                self.state = (self.state >> 1) ^ (0xedb8_8320 & mask);
+            }
+        }
+    }
+
+    pub fn value(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32::new()
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.value()
+}
+
+/// The first line of the `.partial` file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PartialHeader {
+    start: u32,
+    size: u32,
+    chunk_size: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadbackReport {
+    pub path: PathBuf,
+    pub start: u32,
+    pub size: u32,
+    /// The CRC-32 of the whole region.
+    pub crc: u32,
+    /// The bytes that were already in the file from an interrupted readback.
+    pub resumed_from: u32,
+}
+
+impl std::fmt::Display for ReadbackReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Read {} bytes of flash at {:#010x} to {:?}, CRC-32 {:#010x}",
+            self.size, self.start, self.path, self.crc
+        )?;
+        if self.resumed_from > 0 {
+            write!(f, ", resumed after {} bytes", self.resumed_from)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns true if a failed read inside flash is the chip refusing the debug port.
+pub fn is_read_protection_error(err: &anyhow::Error) -> bool {
+    let message = format!("{:#}", err).to_lowercase();
+    PROTECTION_ERRORS.iter().any(|part| message.contains(part))
+}
+
+/// The requested range, or the first flash region, if it is inside a flash region.
+pub fn flash_range(
+    memory_map: &[probe_rs::config::MemoryRegion],
+    requested: Option<Range<u32>>,
+) -> Result<Range<u32>> {
+    let flash: Vec<&Range<u32>> = memory_map
+        .iter()
+        .filter_map(|region| match region {
+            probe_rs::config::MemoryRegion::Nvm(nvm) => Some(&nvm.range),
+            _ => None,
+        })
+        .collect();
+    match requested {
+        None => match flash.first() {
+            Some(range) => Ok((*range).clone()),
+            None => Err(anyhow!("The memory map of the chip has no flash")),
+        },
+        Some(range) if range.start >= range.end => Err(anyhow!(
+            "{:#010x}..{:#010x} is empty",
+            range.start,
+            range.end
+        )),
+        Some(range) => match flash
+            .iter()
+            .any(|region| region.start <= range.start && range.end <= region.end)
+        {
+            true => Ok(range),
+            false => Err(anyhow!(
+                "{:#010x}..{:#010x} is not inside a flash region of the memory map",
+                range.start,
+                range.end
+            )),
+        },
+    }
+}
+
+/// Reads the range to the file, continuing an interrupted readback of the same range.
+/// `progress` is called with the bytes done after every chunk, an error from it stops the
+/// readback with the file left to resume.
+pub fn read_flash(
+    core: &mut probe_rs::Core,
+    path: &Path,
+    range: Range<u32>,
+    progress: &mut dyn FnMut(u32, u32) -> Result<()>,
+) -> Result<ReadbackReport> {
+    let header = PartialHeader {
+        start: range.start,
+        size: range.end - range.start,
+        chunk_size: CHUNK_SIZE,
+    };
+    let partial_path = PathBuf::from(format!("{}.partial", path.display()));
+    let (mut done, mut crc, chunk_crcs) = verified_prefix(path, &partial_path, &header)?;
+    let resumed_from = done;
+    if resumed_from > 0 {
+        info!("Resuming the readback of {:?} after {} bytes", path, done);
+    }
+
+    // Only the verified chunks are kept
+    let mut partial = fs::File::create(&partial_path)
+        .with_context(|| format!("Failed to create {:?}", partial_path))?;
+    writeln!(partial, "{}", serde_json::to_string(&header)?)?;
+    for chunk_crc in &chunk_crcs {
+        writeln!(partial, "{:08x}", chunk_crc)?;
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {:?}", path))?;
+    file.set_len(done as u64)?;
+    file.seek(SeekFrom::End(0))?;
+
+    progress(done, header.size)?;
+    let mut chunk = vec![0u8; CHUNK_SIZE as usize];
+    while done < header.size {
+        let address = range.start + done;
+        let len = (header.size - done).min(CHUNK_SIZE) as usize;
+        if let Err(err) = core.read_8(address, &mut chunk[..len]) {
+            let err = anyhow::Error::from(err);
+            return Err(match is_read_protection_error(&err) {
+                true => anyhow!(
+                    "The chip is read-protected, the flash at {:#010x} can't be read: {:#}",
+                    address,
+                    err
+                ),
+                false => err.context(format!("Failed to read the flash at {:#010x}", address)),
+            });
+        }
+        file.write_all(&chunk[..len])?;
+        file.flush()?;
+        writeln!(partial, "{:08x}", crc32(&chunk[..len]))?;
+        crc.update(&chunk[..len]);
+        done += len as u32;
+        progress(done, header.size)?;
+    }
+
+    drop(partial);
+    fs::remove_file(&partial_path)?;
+    Ok(ReadbackReport {
+        path: path.to_owned(),
+        start: range.start,
+        size: header.size,
+        crc: crc.value(),
+        resumed_from,
+    })
+}
+
+/// The bytes of the file that match the CRCs of an interrupted readback of the same range, with
+/// their rolling CRC and the CRCs of their chunks.
+fn verified_prefix(
+    path: &Path,
+    partial_path: &Path,
+    header: &PartialHeader,
+) -> Result<(u32, Crc32, Vec<u32>)> {
+    let mut crc = Crc32::new();
+    let mut chunk_crcs = vec![];
+    let partial = match fs::File::open(partial_path) {
+        Ok(val) => val,
+        Err(_) => return Ok((0, crc, chunk_crcs)),
+    };
+    let mut lines = BufReader::new(partial).lines();
+    let previous: Option<PartialHeader> = match lines.next() {
+        Some(Ok(line)) => serde_json::from_str(&line).ok(),
+        _ => None,
+    };
+    if previous.as_ref() != Some(header) {
+        warn!(
+            "{:?} is from the readback of another region, starting over",
+            partial_path
+        );
+        return Ok((0, crc, chunk_crcs));
+    }
+    let data = match fs::read(path) {
+        Ok(val) => val,
+        Err(_) => return Ok((0, crc, chunk_crcs)),
+    };
+
+    let mut done = 0;
+    for line in lines {
+        let expected = match line
+            .ok()
+            .and_then(|l| u32::from_str_radix(l.trim(), 16).ok())
+        {
+            Some(val) => val,
+            None => break,
+        };
+        let len = (header.size - done).min(header.chunk_size) as usize;
+        let chunk = match data.get(done as usize..done as usize + len) {
+            Some(val) => val,
+            None => break,
+        };
+        if crc32(chunk) != expected {
+            break;
+        }
+        crc.update(chunk);
+        chunk_crcs.push(expected);
+        done += len as u32;
+    }
+    Ok((done, crc, chunk_crcs))
+}
@@ -0,0 +1,171 @@
+//! Display formats remembered per variable path
+//!
+//! `format cfg.flags hex` shows `cfg.flags` in hex in every stop of the session, the path is the
+//! variable names from the frame down joined with `.`, e.g. `cfg.flags` or `buffer.3`. Only
+//! integer values are reformatted, other values are shown as evaluated. The overrides can be
+//! written to and read from a file to keep them between sessions, `reload formatters` reads the
+//! loaded files again. Time and frequency types are always shown in units, see `time_units`.
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::time_units;
+use super::Variable;
+use super::VariableLocation;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ValueFormat {
+    Hex,
+    Bin,
+    Dec,
+    Char,
+}
+
+impl FromStr for ValueFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<ValueFormat> {
+        match format {
+            "hex" | "x" => Ok(ValueFormat::Hex),
+            "bin" | "b" => Ok(ValueFormat::Bin),
+            "dec" | "d" => Ok(ValueFormat::Dec),
+            "char" | "c" => Ok(ValueFormat::Char),
+            _ => Err(anyhow!(
+                "Unknown format `{}`, expected hex, bin, dec or char",
+                format
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ValueFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValueFormat::Hex => write!(f, "hex"),
+            ValueFormat::Bin => write!(f, "bin"),
+            ValueFormat::Dec => write!(f, "dec"),
+            ValueFormat::Char => write!(f, "char"),
+        }
+    }
+}
+
+impl ValueFormat {
+    /// Returns `None` for values that are not integers.
+    pub fn apply(self, value: &str, byte_size: Option<usize>) -> Option<String> {
+        let number = value.trim().parse::<i128>().ok()?;
+        // Negative values are shown as the two's complement of the width of the variable.
+        let bits = byte_size.unwrap_or(8).clamp(1, 16) * 8;
+        let unsigned = match bits {
+            128 => number as u128,
+            _ => (number as u128) & ((1u128 << bits) - 1),
+        };
+        match self {
+            ValueFormat::Hex => Some(format!("{:#x}", unsigned)),
+            ValueFormat::Bin => Some(format!("{:#b}", unsigned)),
+            ValueFormat::Dec => Some(format!("{}", number)),
+            ValueFormat::Char => match u32::try_from(unsigned).ok().and_then(char::from_u32) {
+                Some(c) => Some(format!("'{}'", c.escape_default())),
+                None => Some(format!("{:#x}", unsigned)),
+            },
+        }
+    }
+}
+
+/// A format file loaded with `format load` and the overrides it had then.
+#[derive(Debug, Clone)]
+pub struct FormatFile {
+    pub path: PathBuf,
+    pub formats: FormatOverrides,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FormatOverrides {
+    overrides: BTreeMap<String, ValueFormat>,
+}
+
+impl FormatOverrides {
+    pub fn set(&mut self, path: String, format: Option<ValueFormat>) {
+        match format {
+            Some(format) => self.overrides.insert(path, format),
+            None => self.overrides.remove(&path),
+        };
+    }
+
+    pub fn clear(&mut self) {
+        self.overrides.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.overrides.is_empty()
+    }
+
+    pub fn list(&self) -> Vec<(String, ValueFormat)> {
+        self.overrides
+            .iter()
+            .map(|(path, format)| (path.clone(), *format))
+            .collect()
+    }
+
+    pub fn read(path: &Path) -> Result<FormatOverrides> {
+        let data = fs::read(path).context(format!("Failed to read format file {:?}", path))?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+            .context(format!("Failed to write format file {:?}", path))?;
+        Ok(())
+    }
+
+    /// Adds the overrides read from a file, replacing those of the same paths.
+    pub fn extend(&mut self, other: FormatOverrides) {
+        self.overrides.extend(other.overrides);
+    }
+
+    /// Removes the overrides added from a file, except the ones set to another format since.
+    pub fn remove_loaded(&mut self, loaded: &FormatOverrides) {
+        for (path, format) in &loaded.overrides {
+            if self.overrides.get(path) == Some(format) {
+                self.overrides.remove(path);
+            }
+        }
+    }
+
+    pub fn apply(&self, variables: &mut [Variable]) {
+        time_units::render(variables);
+        if !self.overrides.is_empty() {
+            self.apply_at("", variables);
+        }
+    }
+
+    fn apply_at(&self, parent: &str, variables: &mut [Variable]) {
+        for variable in variables {
+            let name = match &variable.name {
+                Some(name) => name,
+                None => continue,
+            };
+            let path = match parent {
+                "" => name.clone(),
+                _ => format!("{}.{}", parent, name),
+            };
+
+            if let Some(format) = self.overrides.get(&path) {
+                let byte_size = match variable.location {
+                    Some(VariableLocation::Memory { byte_size, .. }) => Some(byte_size),
+                    Some(VariableLocation::Register { byte_size, .. }) => Some(byte_size),
+                    None => None,
+                };
+                if let Some(value) = format.apply(&variable.value, byte_size) {
+                    variable.value = value;
+                }
+            }
+            self.apply_at(&path, &mut variable.children);
+        }
+    }
+}
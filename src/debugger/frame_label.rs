@@ -0,0 +1,69 @@
+//! The name of a frame in the stack trace, as the client asked for it
+//!
+//! The `format` of a `stackTrace` request selects what goes in the name of a frame next to the
+//! function, e.g. `spi::transfer(buf=0x20000100, len=64)` with `parameters` and
+//! `parameterValues`. The parameters are the evaluated arguments of the frame, so only the
+//! frames of the requested page have them. Without a format the name is the function name.
+use serde::{Deserialize, Serialize};
+
+use super::StackFrame;
+use super::Variable;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct StackFrameFormat {
+    pub parameters: Option<bool>,
+    pub parameter_types: Option<bool>,
+    pub parameter_names: Option<bool>,
+    pub parameter_values: Option<bool>,
+    pub line: Option<bool>,
+    pub module: Option<bool>,
+    /// Everything above.
+    pub include_all: Option<bool>,
+}
+
+impl StackFrameFormat {
+    fn show(&self, option: Option<bool>) -> bool {
+        self.include_all.unwrap_or(false) || option.unwrap_or(false)
+    }
+
+    /// The name of `frame` at `line` in the binary `module`.
+    pub fn label(&self, frame: &StackFrame, line: i64, module: Option<&str>) -> String {
+        let mut label = frame.name.clone();
+        if self.show(self.parameters) {
+            let parameters: Vec<String> = frame
+                .arguments
+                .iter()
+                .map(|argument| self.parameter(argument))
+                .collect();
+            label = format!("{}({})", label, parameters.join(", "));
+        }
+        if self.show(self.line) && line > 0 {
+            label = format!("{} Line: {}", label, line);
+        }
+        if let (true, Some(module)) = (self.show(self.module), module) {
+            label = format!("{} [{}]", label, module);
+        }
+        label
+    }
+
+    fn parameter(&self, argument: &Variable) -> String {
+        let mut parameter = String::new();
+        if self.show(self.parameter_names) {
+            parameter.push_str(argument.name.as_deref().unwrap_or("_"));
+        }
+        if self.show(self.parameter_types) {
+            match parameter.is_empty() {
+                true => parameter.push_str(&argument.type_),
+                false => parameter = format!("{}: {}", parameter, argument.type_),
+            }
+        }
+        if self.show(self.parameter_values) {
+            match parameter.is_empty() {
+                true => parameter = argument.value_to_string(),
+                false => parameter = format!("{}={}", parameter, argument.value_to_string()),
+            }
+        }
+        parameter
+    }
+}
@@ -0,0 +1,84 @@
+//! The recovered state of each frame of the call stack
+//!
+//! The unwinder recovers the registers of a caller frame from the CFI rules of its callees. A
+//! register without a rule, or with the undefined rule, is unknown in the caller, which is
+//! different from a register that keeps the value of the callee. Anything evaluated in a caller
+//! frame has to fail on an unknown register instead of using the value of the callee.
+use anyhow::{anyhow, Result};
+use rust_debug::call_stack::CallFrame;
+
+use super::code_address::CodeAddress;
+use super::Variable;
+use super::VariableLocation;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterValue {
+    Recovered(u32),
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+pub struct FrameState {
+    /// The position of the frame in the call stack, 0 is the innermost frame.
+    pub index: usize,
+    /// Where the frame continues, the return address for the caller frames.
+    pub pc: CodeAddress,
+    /// The canonical frame address, the stack pointer before the call into the frame.
+    pub cfa: Option<u32>,
+    /// The core registers by their DWARF number, r0-r15 on ARM and x0-x15 on RISC-V.
+    pub registers: Vec<RegisterValue>,
+}
+
+impl FrameState {
+    pub fn new(index: usize, call_frame: &CallFrame) -> FrameState {
+        let pc = match index {
+            0 => CodeAddress::new(call_frame.code_location as u32),
+            _ => CodeAddress::from_return_address(call_frame.code_location as u32),
+        };
+        FrameState {
+            index,
+            pc,
+            cfa: call_frame.cfa.map(|cfa| cfa as u32),
+            registers: call_frame
+                .registers
+                .iter()
+                .map(|value| match value {
+                    Some(value) => RegisterValue::Recovered(*value as u32),
+                    None => RegisterValue::Unknown,
+                })
+                .collect(),
+        }
+    }
+
+    /// The address to symbolize the frame at, inside the call instruction for caller frames.
+    pub fn code_address(&self) -> CodeAddress {
+        match self.index {
+            0 => self.pc,
+            _ => self.pc.call_site(),
+        }
+    }
+
+    pub fn register(&self, register: u16) -> Result<u32> {
+        match self.registers.get(register as usize) {
+            Some(RegisterValue::Recovered(value)) => Ok(*value),
+            _ => Err(anyhow!(
+                "r{} is not recovered in frame {}, its value is unknown",
+                register,
+                self.index
+            )),
+        }
+    }
+
+    /// Replaces the values of the variables held in unknown registers, they can't be trusted.
+    pub fn mark_unknown_registers(&self, variables: &mut [Variable]) {
+        for variable in variables {
+            if let Some(VariableLocation::Register { register, .. }) = variable.location {
+                if let Err(err) = self.register(register) {
+                    variable.value = format!("<{}>", err);
+                    variable.children = vec![];
+                }
+            }
+            self.mark_unknown_registers(&mut variable.children);
+        }
+    }
+}
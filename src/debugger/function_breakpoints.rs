@@ -0,0 +1,58 @@
+//! Breakpoints on functions by name
+//!
+//! A DAP function breakpoint names a function like `main`, `HardFault`, `app::init` or a mangled
+//! symbol. The name is matched against the ELF symbols the way `catch` matches them, so a
+//! generic function matches each of its monomorphizations and gets a breakpoint in each. The
+//! breakpoint is set after the prologue, where the arguments can be read, using the line table.
+//!
+//! The instances of one function breakpoint share its id, so a hit in any of them is reported
+//! as the breakpoint the client set.
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::symbols::Symbols;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionBreakpoint {
+    pub name: String,
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub hit_condition: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionLocation {
+    /// The demangled name of the matched function.
+    pub function: String,
+    pub address: u32,
+    /// The path of the source file and the line of the address.
+    pub source: Option<(String, u64)>,
+}
+
+/// The breakpoint locations of the functions matching the name, one per function.
+pub fn resolve_function(symbols: &Symbols, name: &str) -> Vec<FunctionLocation> {
+    let mut functions = symbols.find_symbols(name);
+    functions.retain(|symbol| symbol.is_function);
+    functions.sort_by_key(|symbol| symbol.address);
+    functions.dedup_by_key(|symbol| symbol.address);
+
+    functions
+        .into_iter()
+        .map(|symbol| {
+            let (address, source) = match symbols.skip_prologue(symbol.address, symbol.size) {
+                Ok(val) => val,
+                Err(err) => {
+                    warn!("No line information for `{}`: {}", symbol.demangled, err);
+                    (symbol.address, None)
+                }
+            };
+            FunctionLocation {
+                function: symbol.demangled,
+                address,
+                source,
+            }
+        })
+        .collect()
+}
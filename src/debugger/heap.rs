@@ -0,0 +1,294 @@
+//! Heap statistics of `linked_list_allocator` based allocators
+//!
+//! The layout of the allocator is read from the DWARF type of its static, so the offsets don't
+//! depend on the allocator version. `linked_list_allocator` 0.9 keeps the heap bounds in `Heap`
+//! and 0.10 keeps them in its `HoleList`, both are supported. Wrappers like
+//! `embedded_alloc::Heap` and `alloc_cortex_m::CortexMHeap` are searched for the inner `Heap`.
+use anyhow::{anyhow, Result};
+use gimli::{AttributeValue, Dwarf, Reader, Unit, UnitOffset};
+use rust_debug::call_stack::MemoryAccess;
+use serde::Serialize;
+use std::fmt;
+
+use super::values::Endianness;
+
+/// The names tried when no heap symbol is configured.
+pub const DEFAULT_HEAP_SYMBOLS: &[&str] = &["HEAP", "ALLOCATOR"];
+
+pub const SUPPORTED_ALLOCATORS: &str =
+    "linked_list_allocator 0.9 and 0.10, embedded-alloc and alloc-cortex-m";
+
+// Wrapper types nest the heap, e.g. `Heap { heap: Mutex<RefCell<Heap>> }`.
+const MAX_NESTING: usize = 8;
+
+// Bounds the walk of a corrupted free list.
+const MAX_HOLES: u32 = 10_000;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeapStats {
+    pub symbol: String,
+    pub address: u32,
+    pub total: u32,
+    pub used: u32,
+    pub free: u32,
+    pub largest_free_block: u32,
+    pub free_blocks: u32,
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Heap `{}` at {:#010x}: {} of {} bytes used, {} free in {} blocks, largest free block {} bytes",
+            self.symbol,
+            self.address,
+            self.used,
+            self.total,
+            self.free,
+            self.free_blocks,
+            self.largest_free_block
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+enum HeapBounds {
+    /// A `size` field, linked_list_allocator 0.9.
+    Size { size: u32 },
+    /// `bottom` and `top` pointers, linked_list_allocator 0.10.
+    Top { bottom: u32, top: u32 },
+}
+
+/// Offsets relative to the start of the allocator static.
+#[derive(Debug, Clone)]
+struct HeapLayout {
+    used: u32,
+    bounds: HeapBounds,
+    first_hole: u32,
+    hole_size: u32,
+    hole_next: u32,
+}
+
+struct Member {
+    name: String,
+    offset: u32,
+    type_offset: Option<UnitOffset>,
+}
+
+/// Decodes the free list of the allocator static at `address`.
+pub fn heap_stats<R: Reader<Offset = usize>, M: MemoryAccess>(
+    dwarf: &Dwarf<R>,
+    memory: &mut M,
+    endianness: Endianness,
+    symbol: &str,
+    address: u32,
+) -> Result<HeapStats> {
+    let (unit, type_offset) = match find_variable_type(dwarf, symbol)? {
+        Some(val) => val,
+        None => return Err(anyhow!("No debug information for the heap static `{}`", symbol)),
+    };
+    let layout = match find_heap(dwarf, &unit, type_offset, 0, MAX_NESTING)? {
+        Some(val) => val,
+        None => {
+            return Err(anyhow!(
+                "Unsupported allocator layout for `{}`, supported are {}",
+                symbol,
+                SUPPORTED_ALLOCATORS
+            ))
+        }
+    };
+
+    let mut read_word = |at: u32| -> Result<u32> {
+        match memory.get_address(&at, 4) {
+            Some(bytes) => match endianness.read_u32(&bytes) {
+                Some(val) => Ok(val),
+                None => Err(anyhow!("Failed to decode the word at {:#010x}", at)),
+            },
+            None => Err(anyhow!("Failed to read the heap at {:#010x}", at)),
+        }
+    };
+
+    let used = read_word(address + layout.used)?;
+    let total = match layout.bounds {
+        HeapBounds::Size { size } => read_word(address + size)?,
+        HeapBounds::Top { bottom, top } => {
+            read_word(address + top)?.wrapping_sub(read_word(address + bottom)?)
+        }
+    };
+
+    // The first hole is a dummy with size 0 that only points to the real free list.
+    let mut free = 0;
+    let mut largest_free_block = 0;
+    let mut free_blocks = 0;
+    let mut hole = read_word(address + layout.first_hole + layout.hole_next)?;
+    while hole != 0 {
+        if free_blocks == MAX_HOLES {
+            return Err(anyhow!("The free list has more than {} blocks, it may be corrupted", MAX_HOLES));
+        }
+        let size = read_word(hole + layout.hole_size)?;
+        free += size;
+        largest_free_block = largest_free_block.max(size);
+        free_blocks += 1;
+        hole = read_word(hole + layout.hole_next)?;
+    }
+
+    Ok(HeapStats {
+        symbol: symbol.to_owned(),
+        address,
+        total,
+        used,
+        free,
+        largest_free_block,
+        free_blocks,
+    })
+}
+
+fn find_variable_type<R: Reader<Offset = usize>>(
+    dwarf: &Dwarf<R>,
+    name: &str,
+) -> Result<Option<(Unit<R>, UnitOffset)>> {
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let mut found = None;
+        {
+            let mut entries = unit.entries();
+            while let Some((_, entry)) = entries.next_dfs()? {
+                if entry.tag() != gimli::DW_TAG_variable {
+                    continue;
+                }
+                let entry_name = match entry.attr_value(gimli::DW_AT_name)? {
+                    Some(val) => dwarf.attr_string(&unit, val)?,
+                    None => continue,
+                };
+                if entry_name.to_string_lossy()? != name {
+                    continue;
+                }
+                if let Some(AttributeValue::UnitRef(offset)) = entry.attr_value(gimli::DW_AT_type)? {
+                    found = Some(offset);
+                    break;
+                }
+            }
+        }
+        if let Some(offset) = found {
+            return Ok(Some((unit, offset)));
+        }
+    }
+    Ok(None)
+}
+
+fn struct_members<R: Reader<Offset = usize>>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    offset: UnitOffset,
+) -> Result<Vec<Member>> {
+    let mut tree = unit.entries_tree(Some(offset))?;
+    let root = tree.root()?;
+
+    match root.entry().tag() {
+        gimli::DW_TAG_typedef => {
+            return match root.entry().attr_value(gimli::DW_AT_type)? {
+                Some(AttributeValue::UnitRef(offset)) => struct_members(dwarf, unit, offset),
+                _ => Ok(vec![]),
+            };
+        }
+        gimli::DW_TAG_structure_type => (),
+        _ => return Ok(vec![]),
+    };
+
+    let mut members = vec![];
+    let mut children = root.children();
+    while let Some(child) = children.next()? {
+        let entry = child.entry();
+        if entry.tag() != gimli::DW_TAG_member {
+            continue;
+        }
+        let name = match entry.attr_value(gimli::DW_AT_name)? {
+            Some(val) => dwarf.attr_string(unit, val)?.to_string_lossy()?.to_string(),
+            None => continue,
+        };
+        let offset = entry
+            .attr_value(gimli::DW_AT_data_member_location)?
+            .and_then(|val| val.udata_value())
+            .unwrap_or(0) as u32;
+        let type_offset = match entry.attr_value(gimli::DW_AT_type)? {
+            Some(AttributeValue::UnitRef(val)) => Some(val),
+            _ => None,
+        };
+        members.push(Member {
+            name,
+            offset,
+            type_offset,
+        });
+    }
+    Ok(members)
+}
+
+fn find_member<'a>(members: &'a [Member], name: &str) -> Option<&'a Member> {
+    members.iter().find(|member| member.name == name)
+}
+
+/// Searches the type for a `linked_list_allocator::Heap`, `base` is the offset of the type.
+fn find_heap<R: Reader<Offset = usize>>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    offset: UnitOffset,
+    base: u32,
+    depth: usize,
+) -> Result<Option<HeapLayout>> {
+    let members = struct_members(dwarf, unit, offset)?;
+
+    if let (Some(used), Some(holes)) = (find_member(&members, "used"), find_member(&members, "holes")) {
+        let hole_list = match holes.type_offset {
+            Some(val) => struct_members(dwarf, unit, val)?,
+            None => return Ok(None),
+        };
+        let first = match find_member(&hole_list, "first") {
+            Some(val) => val,
+            None => return Ok(None),
+        };
+        let hole = match first.type_offset {
+            Some(val) => struct_members(dwarf, unit, val)?,
+            None => return Ok(None),
+        };
+        let (hole_size, hole_next) = match (find_member(&hole, "size"), find_member(&hole, "next")) {
+            (Some(size), Some(next)) => (size.offset, next.offset),
+            _ => return Ok(None),
+        };
+
+        let holes_base = base + holes.offset;
+        let bounds = match find_member(&members, "size") {
+            Some(size) => HeapBounds::Size {
+                size: base + size.offset,
+            },
+            _ => match (find_member(&hole_list, "bottom"), find_member(&hole_list, "top")) {
+                (Some(bottom), Some(top)) => HeapBounds::Top {
+                    bottom: holes_base + bottom.offset,
+                    top: holes_base + top.offset,
+                },
+                _ => return Ok(None),
+            },
+        };
+
+        return Ok(Some(HeapLayout {
+            used: base + used.offset,
+            bounds,
+            first_hole: holes_base + first.offset,
+            hole_size,
+            hole_next,
+        }));
+    }
+
+    if depth == 0 {
+        return Ok(None);
+    }
+    for member in &members {
+        if let Some(type_offset) = member.type_offset {
+            if let Some(layout) = find_heap(dwarf, unit, type_offset, base + member.offset, depth - 1)? {
+                return Ok(Some(layout));
+            }
+        }
+    }
+    Ok(None)
+}
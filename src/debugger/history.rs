@@ -0,0 +1,380 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// The oldest events are dropped when the history is full.
+const HISTORY_CAPACITY: usize = 1000;
+
+/// The most breakpoints listed in the stop statistics.
+const TOP_BREAKPOINTS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HistoryKind {
+    Attach,
+    Stop,
+    Resume,
+    Flash,
+    Breakpoint,
+    Fault,
+    Error,
+    Write,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    /// Time since the debugger was started.
+    pub time: Duration,
+    pub kind: HistoryKind,
+    pub description: String,
+    /// How long a stop stayed halted, set when the core resumes.
+    pub duration: Option<Duration>,
+}
+
+impl fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[+{:>10.3}s] {:<10} {}",
+            self.time.as_secs_f64(),
+            format!("{:?}", self.kind).to_lowercase(),
+            self.description
+        )?;
+        match self.duration {
+            Some(duration) => write!(f, " (halted {:.3}s)", duration.as_secs_f64()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A stop the core has not resumed from yet.
+#[derive(Debug, Clone)]
+struct OpenStop {
+    since: Instant,
+    /// The time of its history entry.
+    time: Duration,
+    reason: String,
+    breakpoint_ids: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct StopTotal {
+    count: u64,
+    halted: Duration,
+    longest: Duration,
+}
+
+impl StopTotal {
+    fn add(&mut self, halted: Duration) {
+        self.count += 1;
+        self.halted += halted;
+        self.longest = self.longest.max(halted);
+    }
+}
+
+/// Ring buffer of the significant events of the session, kept across reattaches.
+#[derive(Debug, Clone)]
+pub struct EventHistory {
+    start: Instant,
+    entries: VecDeque<HistoryEntry>,
+    open_stop: Option<OpenStop>,
+    running_since: Option<Instant>,
+    running: Duration,
+    reasons: HashMap<String, StopTotal>,
+    breakpoints: HashMap<u32, StopTotal>,
+}
+
+impl Default for EventHistory {
+    fn default() -> EventHistory {
+        EventHistory::new()
+    }
+}
+
+impl EventHistory {
+    pub fn new() -> EventHistory {
+        EventHistory {
+            start: Instant::now(),
+            entries: VecDeque::with_capacity(HISTORY_CAPACITY),
+            open_stop: None,
+            running_since: None,
+            running: Duration::ZERO,
+            reasons: HashMap::new(),
+            breakpoints: HashMap::new(),
+        }
+    }
+
+    /// A resume ends the halt of the last stop.
+    pub fn record(&mut self, kind: HistoryKind, description: String) {
+        if self.entries.len() == HISTORY_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(HistoryEntry {
+            time: self.start.elapsed(),
+            kind,
+            description,
+            duration: None,
+        });
+        if kind == HistoryKind::Resume {
+            self.close_stop();
+            self.running_since.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// A stop reported to the user, counted for the stop statistics until the core resumes.
+    pub fn record_stop(
+        &mut self,
+        kind: HistoryKind,
+        description: String,
+        reason: &str,
+        breakpoint_ids: &[u32],
+    ) {
+        self.close_stop();
+        if let Some(since) = self.running_since.take() {
+            self.running += since.elapsed();
+        }
+        self.record(kind, description);
+        self.open_stop = Some(OpenStop {
+            since: Instant::now(),
+            time: self
+                .entries
+                .back()
+                .map(|entry| entry.time)
+                .unwrap_or_default(),
+            reason: reason.to_owned(),
+            breakpoint_ids: breakpoint_ids.to_vec(),
+        });
+    }
+
+    fn close_stop(&mut self) {
+        let stop = match self.open_stop.take() {
+            Some(val) => val,
+            None => return,
+        };
+        let halted = stop.since.elapsed();
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .rev()
+            .find(|entry| entry.time == stop.time)
+        {
+            entry.duration = Some(halted);
+        }
+        self.reasons.entry(stop.reason).or_default().add(halted);
+        for id in stop.breakpoint_ids {
+            self.breakpoints.entry(id).or_default().add(halted);
+        }
+    }
+
+    /// The statistics so far, a stop the core is still halted in counts up to now.
+    pub fn stop_stats(&self) -> StopStats {
+        let mut reasons = self.reasons.clone();
+        let mut breakpoints = self.breakpoints.clone();
+        if let Some(stop) = &self.open_stop {
+            let halted = stop.since.elapsed();
+            reasons.entry(stop.reason.clone()).or_default().add(halted);
+            for id in &stop.breakpoint_ids {
+                breakpoints.entry(*id).or_default().add(halted);
+            }
+        }
+        let running = self.running
+            + self
+                .running_since
+                .map(|since| since.elapsed())
+                .unwrap_or_default();
+
+        let mut by_reason: Vec<StopGroup> = reasons
+            .into_iter()
+            .map(|(reason, total)| StopGroup::new(reason, &total))
+            .collect();
+        by_reason.sort_by(|a, b| b.halted.cmp(&a.halted));
+        let mut by_breakpoint: Vec<StopGroup> = breakpoints
+            .into_iter()
+            .map(|(id, total)| StopGroup::new(id.to_string(), &total))
+            .collect();
+        by_breakpoint.sort_by(|a, b| b.count.cmp(&a.count).then(b.halted.cmp(&a.halted)));
+
+        StopStats {
+            elapsed: self.start.elapsed(),
+            halted: by_reason.iter().map(|group| group.halted).sum(),
+            running,
+            stops: by_reason.iter().map(|group| group.count).sum(),
+            by_reason,
+            by_breakpoint,
+        }
+    }
+
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        self.entries.iter().cloned().collect()
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut log = String::new();
+        for entry in &self.entries {
+            log.push_str(&format!("{}\n", entry));
+        }
+        log.push_str(&format!("\n{}\n", self.stop_stats()));
+        fs::write(path, log).context(format!("Failed to write the session log {:?}", path))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StopGroup {
+    /// The stop reason or the breakpoint id.
+    pub name: String,
+    pub count: u64,
+    pub halted: Duration,
+    pub longest: Duration,
+}
+
+impl StopGroup {
+    fn new(name: String, total: &StopTotal) -> StopGroup {
+        StopGroup {
+            name,
+            count: total.count,
+            halted: total.halted,
+            longest: total.longest,
+        }
+    }
+}
+
+/// Where the time of the session went, the time before the first stop or resume is not counted.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StopStats {
+    pub elapsed: Duration,
+    pub halted: Duration,
+    pub running: Duration,
+    pub stops: u64,
+    /// Sorted by the time halted, longest first.
+    pub by_reason: Vec<StopGroup>,
+    /// Sorted by the number of stops, most first.
+    pub by_breakpoint: Vec<StopGroup>,
+}
+
+impl fmt::Display for StopStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} stops in {:.1}s, halted {:.1}s and running {:.1}s",
+            self.stops,
+            self.elapsed.as_secs_f64(),
+            self.halted.as_secs_f64(),
+            self.running.as_secs_f64()
+        )?;
+        if !self.by_reason.is_empty() {
+            write!(f, "\nBy reason:")?;
+        }
+        for group in &self.by_reason {
+            write_group(f, group)?;
+        }
+        if !self.by_breakpoint.is_empty() {
+            write!(f, "\nBy breakpoint:")?;
+        }
+        for group in self.by_breakpoint.iter().take(TOP_BREAKPOINTS) {
+            write_group(f, group)?;
+        }
+        if self.by_breakpoint.len() > TOP_BREAKPOINTS {
+            write!(
+                f,
+                "\n    ... {} more breakpoints",
+                self.by_breakpoint.len() - TOP_BREAKPOINTS
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn write_group(f: &mut fmt::Formatter, group: &StopGroup) -> fmt::Result {
+    write!(
+        f,
+        "\n    {:<12} {:>6} stops, halted {:>8.3}s, longest {:.3}s",
+        group.name,
+        group.count,
+        group.halted.as_secs_f64(),
+        group.longest.as_secs_f64()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    const HALT: Duration = Duration::from_millis(40);
+
+    #[test]
+    fn scripted_session() {
+        let mut history = EventHistory::new();
+        history.record(HistoryKind::Attach, "Attached".to_owned());
+        history.record(HistoryKind::Resume, "Resumed".to_owned());
+        sleep(Duration::from_millis(10));
+
+        history.record_stop(HistoryKind::Stop, "Halted".to_owned(), "breakpoint", &[1]);
+        sleep(HALT);
+        history.record(HistoryKind::Resume, "Resumed".to_owned());
+        // Breakpoint 2 shares the address of breakpoint 1
+        history.record_stop(
+            HistoryKind::Stop,
+            "Halted".to_owned(),
+            "breakpoint",
+            &[1, 2],
+        );
+        sleep(HALT);
+        history.record(HistoryKind::Resume, "Resumed".to_owned());
+        history.record_stop(HistoryKind::Fault, "HardFault".to_owned(), "exception", &[]);
+
+        let stats = history.stop_stats();
+        assert_eq!(stats.stops, 3);
+        assert!(stats.running >= Duration::from_millis(10));
+        assert!(stats.halted >= HALT * 2);
+        assert_eq!(
+            stats.halted,
+            stats.by_reason.iter().map(|group| group.halted).sum()
+        );
+
+        let reasons: Vec<_> = stats
+            .by_reason
+            .iter()
+            .map(|group| (group.name.as_str(), group.count))
+            .collect();
+        assert_eq!(reasons, [("breakpoint", 2), ("exception", 1)]);
+        assert!(stats.by_reason[0].longest >= HALT);
+        assert!(stats.by_reason[0].halted >= HALT * 2);
+
+        let breakpoints: Vec<_> = stats
+            .by_breakpoint
+            .iter()
+            .map(|group| (group.name.as_str(), group.count))
+            .collect();
+        assert_eq!(breakpoints, [("1", 2), ("2", 1)]);
+
+        // The durations are set on the stops the core resumed from
+        let durations: Vec<_> = history
+            .entries()
+            .iter()
+            .filter(|entry| entry.kind != HistoryKind::Resume)
+            .map(|entry| entry.duration.map(|duration| duration >= HALT))
+            .collect();
+        assert_eq!(durations, [None, Some(true), Some(true), None]);
+    }
+
+    #[test]
+    fn open_stops_are_counted_up_to_now() {
+        let mut history = EventHistory::new();
+        history.record_stop(HistoryKind::Stop, "Halted".to_owned(), "step", &[]);
+        sleep(Duration::from_millis(10));
+        let first = history.stop_stats();
+        sleep(Duration::from_millis(10));
+        let second = history.stop_stats();
+        assert_eq!(first.stops, 1);
+        assert_eq!(second.stops, 1);
+        assert!(second.halted > first.halted);
+        assert_eq!(second.running, Duration::ZERO);
+    }
+}
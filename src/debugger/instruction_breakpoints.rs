@@ -0,0 +1,46 @@
+//! Breakpoints on instructions by memory reference
+//!
+//! The disassembly view sets breakpoints with an `instructionReference`, the memory reference of
+//! an instruction as shown in the view, and a byte offset from it. The breakpoint goes on that
+//! address directly, without the line table. Code addresses can come with the Thumb bit set,
+//! which the comparator doesn't take, so it is masked off.
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::commands::commands::parse_u32_from_str;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstructionBreakpoint {
+    pub instruction_reference: String,
+    #[serde(default)]
+    pub offset: Option<i64>,
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub hit_condition: Option<String>,
+}
+
+impl InstructionBreakpoint {
+    /// The address of the instruction, without the Thumb bit.
+    pub fn address(&self) -> Result<u32> {
+        let reference = match parse_u32_from_str(self.instruction_reference.trim()) {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(anyhow!(
+                    "`{}` is not a memory reference",
+                    self.instruction_reference
+                ))
+            }
+        };
+        let address = reference as i64 + self.offset.unwrap_or(0);
+        if address < 0 || address > u32::MAX as i64 {
+            return Err(anyhow!(
+                "{} with offset {} is outside the address space",
+                self.instruction_reference,
+                self.offset.unwrap_or(0)
+            ));
+        }
+        Ok(address as u32 & !1)
+    }
+}
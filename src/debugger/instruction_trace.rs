@@ -0,0 +1,83 @@
+//! Stepping back over the last single-stepped instructions
+//!
+//! Every instruction stepped on its own records the registers it changed in a bounded buffer,
+//! and stepping back writes their old values back. This is not reverse debugging: memory written
+//! by the instructions is not restored, and code that ran freely, after a continue or over a
+//! call, can't be stepped back over.
+use anyhow::{anyhow, Result};
+use std::collections::VecDeque;
+
+use super::stepping::InstructionStep;
+
+#[derive(Debug, Clone)]
+enum TraceEntry {
+    /// An instruction stepped from `from`.
+    Step { from: u32, step: InstructionStep },
+    /// The core ran freely, the registers before it are unknown.
+    Resume(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct InstructionTrace {
+    length: usize,
+    entries: VecDeque<TraceEntry>,
+}
+
+impl InstructionTrace {
+    pub fn new(length: usize) -> InstructionTrace {
+        InstructionTrace {
+            length,
+            entries: VecDeque::with_capacity(length),
+        }
+    }
+
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    pub fn record_step(&mut self, from: u32, step: InstructionStep) {
+        self.push(TraceEntry::Step { from, step });
+    }
+
+    pub fn record_resume(&mut self, reason: &str) {
+        // Only the latest resume matters, nothing before it can be stepped back over
+        self.entries.clear();
+        self.push(TraceEntry::Resume(reason.to_owned()));
+    }
+
+    fn push(&mut self, entry: TraceEntry) {
+        if self.entries.len() == self.length {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Undoes the register changes of the last stepped instruction, returns the pc it was
+    /// stepped from.
+    pub fn step_back(&mut self, core: &mut probe_rs::Core) -> Result<u32> {
+        let (from, step) = match self.entries.back() {
+            Some(TraceEntry::Step { from, step }) => (*from, step.clone()),
+            Some(TraceEntry::Resume(reason)) => {
+                return Err(anyhow!("Can't step back past where the core {}", reason))
+            }
+            None => {
+                return Err(anyhow!(
+                    "Can't step back further, only the last {} instructions are recorded",
+                    self.length
+                ))
+            }
+        };
+
+        let register_file = core.registers();
+        for (name, old, _) in &step.changed_registers {
+            let register = match register_file.registers().find(|r| r.name() == name) {
+                Some(val) => val,
+                None => return Err(anyhow!("Unknown register {}", name)),
+            };
+            core.write_core_reg(register.into(), *old)?;
+        }
+        self.entries.pop_back();
+
+        Ok(from)
+    }
+}
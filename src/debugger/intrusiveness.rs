@@ -0,0 +1,167 @@
+//! How much the debugger disturbs the running target
+//!
+//! Firmware that misses deadlines only with the debugger attached is usually stalled by it. Two
+//! things are accounted while the target runs: the time it spends in halts the debugger resumes
+//! by itself, e.g. ignored breakpoint hits, and the probe operations done while it runs, counted
+//! by origin. A halt is only noticed at the next status poll, so the measured time of a halt
+//! starts at the poll and the halt itself started up to one status interval earlier.
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The most contributors listed in each part of the report.
+const TOP_CONTRIBUTORS: usize = 5;
+
+#[derive(Debug, Clone, Default)]
+struct HaltTotal {
+    count: u64,
+    duration: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct Intrusiveness {
+    started: Instant,
+    halts: HashMap<&'static str, HaltTotal>,
+    accesses: HashMap<String, u64>,
+}
+
+impl Intrusiveness {
+    pub fn new() -> Intrusiveness {
+        Intrusiveness {
+            started: Instant::now(),
+            halts: HashMap::new(),
+            accesses: HashMap::new(),
+        }
+    }
+
+    /// A halt the debugger resumed without reporting it, `detected` is when the poll saw it.
+    pub fn record_halt(&mut self, origin: &'static str, detected: Instant) {
+        let total = self.halts.entry(origin).or_default();
+        total.count += 1;
+        total.duration += detected.elapsed();
+    }
+
+    /// A probe operation done while the target runs.
+    pub fn record_access(&mut self, origin: &str) {
+        *self.accesses.entry(origin.to_owned()).or_default() += 1;
+    }
+
+    pub fn report(&self, status_interval: Duration) -> IntrusivenessReport {
+        let mut halts: Vec<HaltContributor> = self
+            .halts
+            .iter()
+            .map(|(origin, total)| HaltContributor {
+                origin: origin.to_string(),
+                count: total.count,
+                duration: total.duration,
+            })
+            .collect();
+        halts.sort_by(|a, b| b.duration.cmp(&a.duration));
+
+        let mut accesses: Vec<AccessContributor> = self
+            .accesses
+            .iter()
+            .map(|(origin, count)| AccessContributor {
+                origin: origin.clone(),
+                count: *count,
+            })
+            .collect();
+        accesses.sort_by(|a, b| b.count.cmp(&a.count));
+
+        IntrusivenessReport {
+            elapsed: self.started.elapsed(),
+            status_interval,
+            halts,
+            accesses,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HaltContributor {
+    pub origin: String,
+    pub count: u64,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessContributor {
+    pub origin: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IntrusivenessReport {
+    pub elapsed: Duration,
+    pub status_interval: Duration,
+    /// Sorted by the time halted, longest first.
+    pub halts: Vec<HaltContributor>,
+    /// Sorted by the number of operations, most first.
+    pub accesses: Vec<AccessContributor>,
+}
+
+impl IntrusivenessReport {
+    pub fn halted(&self) -> Duration {
+        self.halts.iter().map(|halt| halt.duration).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.halts.is_empty() && self.accesses.is_empty()
+    }
+}
+
+impl fmt::Display for IntrusivenessReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        write!(
+            f,
+            "Debugger intrusiveness over {:.1} s",
+            self.elapsed.as_secs_f64()
+        )?;
+
+        let halt_count: u64 = self.halts.iter().map(|halt| halt.count).sum();
+        write!(
+            f,
+            "\nTransparent halts: {}, halted for at least {:?} ({:.3} %)",
+            halt_count,
+            self.halted(),
+            100.0 * self.halted().as_secs_f64() / seconds
+        )?;
+        if halt_count > 0 {
+            write!(
+                f,
+                ", each up to {:?} longer before it was polled",
+                self.status_interval
+            )?;
+        }
+        for halt in self.halts.iter().take(TOP_CONTRIBUTORS) {
+            write!(
+                f,
+                "\n  {}: {} halts, {:?}",
+                halt.origin, halt.count, halt.duration
+            )?;
+        }
+
+        let access_count: u64 = self.accesses.iter().map(|access| access.count).sum();
+        write!(
+            f,
+            "\nProbe operations while running: {} ({:.1}/s)",
+            access_count,
+            access_count as f64 / seconds
+        )?;
+        for access in self.accesses.iter().take(TOP_CONTRIBUTORS) {
+            write!(
+                f,
+                "\n  {}: {} ({:.1}/s)",
+                access.origin,
+                access.count,
+                access.count as f64 / seconds
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,765 @@
+//! Evaluates the locations rust-debug leaves optimized out, frame bases and entry values
+use super::frame_state::FrameState;
+use super::symbols::Symbols;
+use super::values::{sign_extend, truncate, Endianness};
+use super::{StackFrame, Variable};
+use anyhow::{anyhow, Result};
+use gimli::{
+    AttributeValue, DebuggingInformationEntry, Dwarf, Encoding, EvaluationResult, Expression,
+    Location, Piece, Reader, Unit, UnitOffset, Value,
+};
+use rust_debug::call_stack::MemoryAccess;
+use std::fmt;
+
+pub const OPTIMIZED_OUT: &str = "< OptimizedOut >";
+
+/// A parameter only described by its value at the entry of the function, which the caller frame
+/// doesn't have either.
+#[derive(Debug)]
+pub struct EntryValueUnavailable;
+
+impl fmt::Display for EntryValueUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<value available only at function entry>")
+    }
+}
+
+impl std::error::Error for EntryValueUnavailable {}
+
+/// The frame a location expression is evaluated in.
+pub trait LocationContext {
+    /// A register of the frame by its DWARF number, None if it isn't recovered.
+    fn register(&self, register: u16) -> Option<u32>;
+
+    /// A register at the entry of the function, recovered in the caller frame.
+    fn entry_register(&self, register: u16) -> Option<u32>;
+
+    fn cfa(&self) -> Option<u32>;
+
+    fn read_memory(&mut self, address: u32, size: usize) -> Option<Vec<u8>>;
+}
+
+pub struct LocationEvaluator<R: Reader<Offset = usize>> {
+    pub encoding: Encoding,
+    pub endianness: Endianness,
+    /// The DW_AT_frame_base of the function, DW_OP_fbreg is relative to it.
+    pub frame_base: Option<Expression<R>>,
+}
+
+impl<R: Reader<Offset = usize>> LocationEvaluator<R> {
+    /// Evaluates a location into the `byte_size` bytes of the value, in target byte order.
+    pub fn bytes<C: LocationContext>(
+        &self,
+        expression: Expression<R>,
+        byte_size: usize,
+        context: &mut C,
+    ) -> Result<Vec<u8>> {
+        let pieces = self.evaluate(expression, false, context)?;
+        let single = pieces.len() == 1;
+        let mut bytes = vec![];
+        for piece in pieces {
+            if piece.bit_offset.is_some() {
+                return Err(anyhow!("Bit pieces are not supported"));
+            }
+            let size = match piece.size_in_bits {
+                Some(bits) if bits % 8 == 0 => (bits / 8) as usize,
+                Some(bits) => return Err(anyhow!("A piece of {} bits is not supported", bits)),
+                None if single => byte_size,
+                None => return Err(anyhow!("A piece has no size")),
+            };
+            bytes.extend(self.piece_bytes(piece.location, size, context)?);
+        }
+
+        if bytes.len() < byte_size {
+            return Err(anyhow!(
+                "The location has {} of the {} bytes",
+                bytes.len(),
+                byte_size
+            ));
+        }
+        bytes.truncate(byte_size);
+        Ok(bytes)
+    }
+
+    fn piece_bytes<C: LocationContext>(
+        &self,
+        location: Location<R>,
+        size: usize,
+        context: &mut C,
+    ) -> Result<Vec<u8>> {
+        match location {
+            Location::Register { register } => {
+                if size > 4 {
+                    return Err(anyhow!("{} bytes don't fit in r{}", size, register.0));
+                }
+                match context.register(register.0) {
+                    Some(value) => Ok(self.endianness.write_unsigned(value as u64, size)),
+                    None => Err(anyhow!("r{} is not recovered in the frame", register.0)),
+                }
+            }
+            Location::Address { address } => match context.read_memory(address as u32, size) {
+                Some(bytes) => Ok(bytes),
+                None => Err(anyhow!("Failed to read memory at {:#010x}", address)),
+            },
+            Location::Value { value } => {
+                let value = value.to_u64(u64::MAX)?;
+                Ok(self.endianness.write_unsigned(value, size))
+            }
+            Location::Bytes { value } => Ok(value.to_slice()?.iter().take(size).copied().collect()),
+            Location::Empty => Err(anyhow!("The value is optimized out")),
+            Location::ImplicitPointer { .. } => Err(anyhow!("Implicit pointers are not supported")),
+        }
+    }
+
+    // At the entry the registers are those of the caller frame.
+    fn evaluate<C: LocationContext>(
+        &self,
+        expression: Expression<R>,
+        at_entry: bool,
+        context: &mut C,
+    ) -> Result<Vec<Piece<R>>> {
+        let mut evaluation = expression.evaluation(self.encoding);
+        let mut result = evaluation.evaluate()?;
+        loop {
+            result = match result {
+                EvaluationResult::Complete => return Ok(evaluation.result()),
+                EvaluationResult::RequiresRegister { register, .. } => {
+                    let value = match at_entry {
+                        true => context.entry_register(register.0),
+                        false => context.register(register.0),
+                    };
+                    match (value, at_entry) {
+                        (Some(value), _) => {
+                            evaluation.resume_with_register(Value::Generic(value as u64))?
+                        }
+                        (None, true) => return Err(EntryValueUnavailable.into()),
+                        (None, false) => {
+                            return Err(anyhow!("r{} is not recovered in the frame", register.0))
+                        }
+                    }
+                }
+                EvaluationResult::RequiresFrameBase => {
+                    let frame_base = self.frame_base(context)?;
+                    evaluation.resume_with_frame_base(frame_base as u64)?
+                }
+                EvaluationResult::RequiresCallFrameCfa => match context.cfa() {
+                    Some(cfa) => evaluation.resume_with_call_frame_cfa(cfa as u64)?,
+                    None => return Err(anyhow!("The CFA of the frame is not known")),
+                },
+                EvaluationResult::RequiresMemory { address, size, .. } => {
+                    let value = context
+                        .read_memory(address as u32, size as usize)
+                        .and_then(|bytes| self.endianness.read_unsigned(&bytes));
+                    match value {
+                        Some(value) => evaluation.resume_with_memory(Value::Generic(value))?,
+                        None => return Err(anyhow!("Failed to read memory at {:#010x}", address)),
+                    }
+                }
+                EvaluationResult::RequiresEntryValue(entry) => {
+                    let value = self.entry_value(entry, context)?;
+                    evaluation.resume_with_entry_value(Value::Generic(value as u64))?
+                }
+                EvaluationResult::RequiresRelocatedAddress(address) => {
+                    evaluation.resume_with_relocated_address(address)?
+                }
+                other => return Err(anyhow!("Unsupported location expression: {:?}", other)),
+            };
+        }
+    }
+
+    fn frame_base<C: LocationContext>(&self, context: &mut C) -> Result<u32> {
+        let expression = match &self.frame_base {
+            Some(val) => val.clone(),
+            None => return Err(anyhow!("The function has no frame base")),
+        };
+        // DW_OP_regN as a frame base is the value of the register, not its location.
+        self.single_value(expression, false, context)
+    }
+
+    // The value of the expression, e.g. DW_OP_reg0 or DW_OP_breg13 0, at the entry of the
+    // function.
+    fn entry_value<C: LocationContext>(
+        &self,
+        expression: Expression<R>,
+        context: &mut C,
+    ) -> Result<u32> {
+        self.single_value(expression, true, context)
+    }
+
+    fn single_value<C: LocationContext>(
+        &self,
+        expression: Expression<R>,
+        at_entry: bool,
+        context: &mut C,
+    ) -> Result<u32> {
+        let pieces = self.evaluate(expression, at_entry, context)?;
+        let location = match pieces.into_iter().next() {
+            Some(piece) => piece.location,
+            None => return Err(anyhow!("The expression has no value")),
+        };
+        match (location, at_entry) {
+            (Location::Register { register }, false) => match context.register(register.0) {
+                Some(value) => Ok(value),
+                None => Err(anyhow!("r{} is not recovered in the frame", register.0)),
+            },
+            (Location::Register { register }, true) => match context.entry_register(register.0) {
+                Some(value) => Ok(value),
+                None => Err(EntryValueUnavailable.into()),
+            },
+            (Location::Address { address }, _) => Ok(address as u32),
+            (Location::Value { value }, _) => Ok(value.to_u64(u64::MAX)? as u32),
+            _ => Err(anyhow!("The expression has no value")),
+        }
+    }
+}
+
+/// Formats the bytes of a base type, None for encodings that aren't decoded.
+pub fn format_base_value(
+    encoding: gimli::DwAte,
+    bytes: &[u8],
+    endianness: Endianness,
+) -> Option<String> {
+    let size = bytes.len() as u64;
+    let value = endianness.read_unsigned(bytes)?;
+    Some(match (encoding, size) {
+        (gimli::DW_ATE_boolean, _) => format!("{}", value != 0),
+        (gimli::DW_ATE_unsigned, _) => format!("{}", truncate(value, size)),
+        (gimli::DW_ATE_signed, _) => format!("{}", sign_extend(value, size)),
+        (gimli::DW_ATE_float, 4) => format!("{}", f32::from_bits(value as u32)),
+        (gimli::DW_ATE_float, 8) => format!("{}", f64::from_bits(value)),
+        (gimli::DW_ATE_UTF, 4) => format!("{:?}", char::from_u32(value as u32)?),
+        _ => return None,
+    })
+}
+
+struct FrameContext<'a, M: MemoryAccess> {
+    state: &'a FrameState,
+    caller: Option<&'a FrameState>,
+    memory: &'a mut M,
+}
+
+impl<M: MemoryAccess> LocationContext for FrameContext<'_, M> {
+    fn register(&self, register: u16) -> Option<u32> {
+        self.state.register(register).ok()
+    }
+
+    fn entry_register(&self, register: u16) -> Option<u32> {
+        self.caller?.register(register).ok()
+    }
+
+    fn cfa(&self) -> Option<u32> {
+        self.state.cfa
+    }
+
+    fn read_memory(&mut self, address: u32, size: usize) -> Option<Vec<u8>> {
+        self.memory.get_address(&address, size)
+    }
+}
+
+/// Evaluates the optimized out variables and arguments of base types in the frames again, with
+/// the frame base and the registers the unwinder recovered for the frame and its caller.
+pub fn recover_optimized_out<M: MemoryAccess>(
+    symbols: &Symbols,
+    stack_trace: &mut [StackFrame],
+    memory: &mut M,
+) -> Result<()> {
+    for index in 0..stack_trace.len() {
+        let (frames, callers) = stack_trace.split_at_mut(index + 1);
+        let frame = &mut frames[index];
+        let optimized_out = |variable: &Variable| variable.value == OPTIMIZED_OUT;
+        if !frame.variables.iter().any(optimized_out) && !frame.arguments.iter().any(optimized_out)
+        {
+            continue;
+        }
+
+        let address = frame.state.code_address().address();
+        let module = symbols.module_for_address(address)?;
+        let pc = module.to_link_address(address) as u64;
+        let (unit, offset) = match find_subprogram(&module.dwarf, pc)? {
+            Some(val) => val,
+            None => continue,
+        };
+        let mut context = FrameContext {
+            state: &frame.state,
+            caller: callers.first().map(|caller| &caller.state),
+            memory: &mut *memory,
+        };
+        let recovered = base_values(
+            &module.dwarf,
+            &unit,
+            offset,
+            pc,
+            module.endianness,
+            &mut context,
+        )?;
+
+        for variable in frame
+            .variables
+            .iter_mut()
+            .chain(frame.arguments.iter_mut())
+        {
+            if variable.value != OPTIMIZED_OUT {
+                continue;
+            }
+            let found = recovered
+                .iter()
+                .find(|(name, _, _)| variable.name.as_deref() == Some(name.as_str()));
+            if let Some((_, type_name, value)) = found {
+                variable.value = value.clone();
+                variable.type_ = format!("{}::{}", variable.type_, type_name);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// The name, type name and value of the variables and parameters of base type of the subprogram
+// in scope at the pc, those that can't be evaluated are left out.
+fn base_values<R: Reader<Offset = usize>, C: LocationContext>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    offset: UnitOffset,
+    pc: u64,
+    endianness: Endianness,
+    context: &mut C,
+) -> Result<Vec<(String, String, String)>> {
+    let subprogram = unit.entry(offset)?;
+    let frame_base = match subprogram.attr_value(gimli::DW_AT_frame_base)? {
+        Some(AttributeValue::Exprloc(expression)) => Some(expression),
+        _ => None,
+    };
+    let evaluator = LocationEvaluator {
+        encoding: unit.encoding(),
+        endianness,
+        frame_base,
+    };
+
+    let mut entries = vec![];
+    collect_variables(unit, offset, pc, &mut entries)?;
+    let mut values = vec![];
+    for entry_offset in entries {
+        let entry = unit.entry(entry_offset)?;
+        let name = match entry.attr(gimli::DW_AT_name)? {
+            Some(attr) => dwarf
+                .attr_string(unit, attr.value())?
+                .to_string_lossy()?
+                .to_string(),
+            None => continue,
+        };
+        let expression = match location_at(dwarf, unit, &entry, pc)? {
+            Some(val) => val,
+            None => continue,
+        };
+        let (type_name, encoding, byte_size) = match base_type(dwarf, unit, &entry)? {
+            Some(val) => val,
+            None => continue,
+        };
+        let value = match evaluator.bytes(expression, byte_size, context) {
+            Ok(bytes) => match format_base_value(encoding, &bytes, endianness) {
+                Some(val) => val,
+                None => continue,
+            },
+            Err(err) if err.is::<EntryValueUnavailable>() => err.to_string(),
+            Err(_) => continue,
+        };
+        values.push((name, type_name, value));
+    }
+    Ok(values)
+}
+
+// The innermost subprogram whose ranges contain the pc, inlined functions are not entered.
+fn find_subprogram<R: Reader<Offset = usize>>(
+    dwarf: &Dwarf<R>,
+    pc: u64,
+) -> Result<Option<(Unit<R>, UnitOffset)>> {
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let mut found = None;
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs()? {
+            if entry.tag() == gimli::DW_TAG_subprogram && contains(dwarf, &unit, entry, pc)? {
+                found = Some(entry.offset());
+            }
+        }
+        if let Some(offset) = found {
+            return Ok(Some((unit, offset)));
+        }
+    }
+    Ok(None)
+}
+
+fn contains<R: Reader<Offset = usize>>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    entry: &DebuggingInformationEntry<R>,
+    pc: u64,
+) -> Result<bool> {
+    let mut ranges = dwarf.die_ranges(unit, entry)?;
+    while let Some(range) = ranges.next()? {
+        if range.begin <= pc && pc < range.end {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// The variables and parameters of the subprogram, and of the lexical blocks containing the pc.
+fn collect_variables<R: Reader<Offset = usize>>(
+    unit: &Unit<R>,
+    offset: UnitOffset,
+    pc: u64,
+    variables: &mut Vec<UnitOffset>,
+) -> Result<()> {
+    let mut tree = unit.entries_tree(Some(offset))?;
+    let mut children = tree.root()?.children();
+    while let Some(child) = children.next()? {
+        let entry = child.entry();
+        match entry.tag() {
+            gimli::DW_TAG_variable | gimli::DW_TAG_formal_parameter => {
+                variables.push(entry.offset())
+            }
+            gimli::DW_TAG_lexical_block => {
+                let low = match entry.attr_value(gimli::DW_AT_low_pc)? {
+                    Some(AttributeValue::Addr(low)) => low,
+                    _ => continue,
+                };
+                let high = match entry.attr_value(gimli::DW_AT_high_pc)? {
+                    Some(AttributeValue::Addr(high)) => high,
+                    Some(AttributeValue::Udata(size)) => low + size,
+                    _ => continue,
+                };
+                if low <= pc && pc < high {
+                    collect_variables(unit, entry.offset(), pc, variables)?;
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+// The location expression of the entry, from its location list entry for the pc if it has one.
+fn location_at<R: Reader<Offset = usize>>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    entry: &DebuggingInformationEntry<R>,
+    pc: u64,
+) -> Result<Option<Expression<R>>> {
+    match entry.attr_value(gimli::DW_AT_location)? {
+        Some(AttributeValue::Exprloc(expression)) => Ok(Some(expression)),
+        Some(AttributeValue::LocationListsRef(offset)) => {
+            let mut locations = dwarf.locations(unit, offset)?;
+            while let Some(location) = locations.next()? {
+                if location.range.begin <= pc && pc < location.range.end {
+                    return Ok(Some(location.data));
+                }
+            }
+            Ok(None)
+        }
+        _ => Ok(None),
+    }
+}
+
+// The name, encoding and size of the type of the entry if it is a base type.
+fn base_type<R: Reader<Offset = usize>>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    entry: &DebuggingInformationEntry<R>,
+) -> Result<Option<(String, gimli::DwAte, usize)>> {
+    let offset = match entry.attr_value(gimli::DW_AT_type)? {
+        Some(AttributeValue::UnitRef(offset)) => offset,
+        _ => return Ok(None),
+    };
+    let type_entry = unit.entry(offset)?;
+    if type_entry.tag() != gimli::DW_TAG_base_type {
+        return Ok(None);
+    }
+    let encoding = match type_entry.attr_value(gimli::DW_AT_encoding)? {
+        Some(AttributeValue::Encoding(encoding)) => encoding,
+        _ => return Ok(None),
+    };
+    let byte_size = match type_entry
+        .attr_value(gimli::DW_AT_byte_size)?
+        .and_then(|value| value.udata_value())
+    {
+        Some(val) if val > 0 && val <= 8 => val as usize,
+        _ => return Ok(None),
+    };
+    let name = match type_entry.attr(gimli::DW_AT_name)? {
+        Some(attr) => dwarf
+            .attr_string(unit, attr.value())?
+            .to_string_lossy()?
+            .to_string(),
+        None => return Ok(None),
+    };
+    Ok(Some((name, encoding, byte_size)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gimli::{EndianSlice, Format, LittleEndian};
+    use std::collections::HashMap;
+
+    const ENCODING: Encoding = Encoding {
+        format: Format::Dwarf32,
+        version: 4,
+        address_size: 4,
+    };
+
+    // DWARF operations used by the expressions below.
+    const DW_OP_REG0: u8 = 0x50;
+    const DW_OP_BREG13: u8 = 0x7d;
+    const DW_OP_FBREG: u8 = 0x91;
+    const DW_OP_PIECE: u8 = 0x93;
+    const DW_OP_CALL_FRAME_CFA: u8 = 0x9c;
+    const DW_OP_STACK_VALUE: u8 = 0x9f;
+    const DW_OP_ENTRY_VALUE: u8 = 0xa3;
+
+    #[derive(Default)]
+    struct TestFrame {
+        registers: HashMap<u16, u32>,
+        caller: HashMap<u16, u32>,
+        cfa: Option<u32>,
+        memory: HashMap<u32, u8>,
+    }
+
+    impl LocationContext for TestFrame {
+        fn register(&self, register: u16) -> Option<u32> {
+            self.registers.get(&register).copied()
+        }
+
+        fn entry_register(&self, register: u16) -> Option<u32> {
+            self.caller.get(&register).copied()
+        }
+
+        fn cfa(&self) -> Option<u32> {
+            self.cfa
+        }
+
+        fn read_memory(&mut self, address: u32, size: usize) -> Option<Vec<u8>> {
+            (address..address + size as u32)
+                .map(|address| self.memory.get(&address).copied())
+                .collect()
+        }
+    }
+
+    impl TestFrame {
+        fn write(&mut self, address: u32, bytes: &[u8]) {
+            for (offset, byte) in bytes.iter().enumerate() {
+                self.memory.insert(address + offset as u32, *byte);
+            }
+        }
+    }
+
+    fn expression(bytes: &[u8]) -> Expression<EndianSlice<LittleEndian>> {
+        Expression(EndianSlice::new(bytes, LittleEndian))
+    }
+
+    fn evaluator<'a>(
+        frame_base: Option<&'a [u8]>,
+    ) -> LocationEvaluator<EndianSlice<'a, LittleEndian>> {
+        LocationEvaluator {
+            encoding: ENCODING,
+            endianness: Endianness::Little,
+            frame_base: frame_base.map(expression),
+        }
+    }
+
+    #[test]
+    fn fbreg_with_the_cfa_as_frame_base() {
+        let mut frame = TestFrame {
+            cfa: Some(0x2000_0100),
+            ..Default::default()
+        };
+        frame.write(0x2000_00f8, &42u32.to_le_bytes());
+
+        // DW_OP_fbreg -8
+        let bytes = evaluator(Some(&[DW_OP_CALL_FRAME_CFA][..]))
+            .bytes(expression(&[DW_OP_FBREG, 0x78]), 4, &mut frame)
+            .unwrap();
+        assert_eq!(bytes, 42u32.to_le_bytes());
+    }
+
+    #[test]
+    fn fbreg_with_a_register_as_frame_base() {
+        let mut frame = TestFrame::default();
+        frame.registers.insert(7, 0x2000_0040);
+        frame.write(0x2000_0044, &[0x34, 0x12]);
+
+        // The frame base DW_OP_reg7 is the value of r7, DW_OP_fbreg 4
+        let bytes = evaluator(Some(&[DW_OP_REG0 + 7][..]))
+            .bytes(expression(&[DW_OP_FBREG, 0x04]), 2, &mut frame)
+            .unwrap();
+        assert_eq!(bytes, [0x34, 0x12]);
+    }
+
+    #[test]
+    fn fbreg_without_a_frame_base_fails() {
+        let mut frame = TestFrame::default();
+        assert!(evaluator(None)
+            .bytes(expression(&[DW_OP_FBREG, 0x04]), 4, &mut frame)
+            .is_err());
+    }
+
+    #[test]
+    fn entry_value_from_the_caller_registers() {
+        let mut frame = TestFrame::default();
+        frame.caller.insert(4, 7);
+
+        // DW_OP_entry_value(DW_OP_reg4) DW_OP_stack_value
+        let code = [DW_OP_ENTRY_VALUE, 1, DW_OP_REG0 + 4, DW_OP_STACK_VALUE];
+        let bytes = evaluator(None)
+            .bytes(expression(&code), 4, &mut frame)
+            .unwrap();
+        assert_eq!(bytes, 7u32.to_le_bytes());
+    }
+
+    #[test]
+    fn entry_value_of_an_unrecovered_register() {
+        let mut frame = TestFrame::default();
+        // r0 has a value in the frame, but not at the entry
+        frame.registers.insert(0, 1);
+
+        let code = [DW_OP_ENTRY_VALUE, 1, DW_OP_REG0, DW_OP_STACK_VALUE];
+        let err = evaluator(None)
+            .bytes(expression(&code), 4, &mut frame)
+            .unwrap_err();
+        assert!(err.is::<EntryValueUnavailable>());
+        assert_eq!(err.to_string(), "<value available only at function entry>");
+    }
+
+    #[test]
+    fn pieces_across_a_register_and_a_stack_slot() {
+        let mut frame = TestFrame::default();
+        frame.registers.insert(0, 0x1111_2222);
+        frame.registers.insert(13, 0x2000_0000);
+        frame.write(0x2000_0010, &0x3333_4444u32.to_le_bytes());
+
+        // DW_OP_reg0 DW_OP_piece 4 DW_OP_breg13 16 DW_OP_piece 4
+        let code = [DW_OP_REG0, DW_OP_PIECE, 4, DW_OP_BREG13, 0x10, DW_OP_PIECE, 4];
+        let bytes = evaluator(None)
+            .bytes(expression(&code), 8, &mut frame)
+            .unwrap();
+        assert_eq!(
+            Endianness::Little.read_unsigned(&bytes),
+            Some(0x3333_4444_1111_2222)
+        );
+    }
+
+    #[test]
+    fn missing_bytes_fail() {
+        let mut frame = TestFrame::default();
+        frame.registers.insert(0, 1);
+        let code = [DW_OP_REG0, DW_OP_PIECE, 4];
+        assert!(evaluator(None)
+            .bytes(expression(&code), 8, &mut frame)
+            .is_err());
+    }
+
+    #[test]
+    fn formatted_base_values() {
+        let little = Endianness::Little;
+        assert_eq!(
+            format_base_value(gimli::DW_ATE_signed, &[0xfe, 0xff], little).as_deref(),
+            Some("-2")
+        );
+        assert_eq!(
+            format_base_value(gimli::DW_ATE_unsigned, &[0xfe, 0xff], little).as_deref(),
+            Some("65534")
+        );
+        assert_eq!(
+            format_base_value(gimli::DW_ATE_float, &1.5f32.to_le_bytes(), little).as_deref(),
+            Some("1.5")
+        );
+        assert_eq!(
+            format_base_value(gimli::DW_ATE_boolean, &[1], little).as_deref(),
+            Some("true")
+        );
+        assert_eq!(format_base_value(gimli::DW_ATE_float, &[0; 2], little), None);
+    }
+
+    // `scale` in tests/fixtures/locations.rs, built at opt-level 2 and 3 by
+    // tests/fixtures/build-locations.sh. LLVM describes its stack slots relative to the sp
+    // (DW_OP_breg13), so DW_OP_fbreg is only covered by the expressions above.
+    const FIXTURES: [&str; 2] = [
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/locations-o2.elf"),
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/locations-o3.elf"),
+    ];
+    const SP: u32 = 0x2000_0ff0;
+
+    fn fixture_values(path: &str, pc: u64, frame: &mut TestFrame) -> Vec<(String, String, String)> {
+        let (dwarf, _) = crate::read_dwarf(std::path::Path::new(path)).unwrap();
+        let (unit, offset) = find_subprogram(&dwarf, pc).unwrap().unwrap();
+        base_values(&dwarf, &unit, offset, pc, Endianness::Little, frame).unwrap()
+    }
+
+    fn value<'a>(values: &'a [(String, String, String)], name: &str) -> Option<&'a str> {
+        values
+            .iter()
+            .find(|(val, _, _)| val == name)
+            .map(|(_, _, value)| value.as_str())
+    }
+
+    #[test]
+    fn fixture_parameters_in_registers_at_entry() {
+        for path in FIXTURES.iter() {
+            let mut frame = TestFrame::default();
+            frame.registers.insert(0, 3);
+            frame.registers.insert(1, 5);
+            let values = fixture_values(path, 0x200f8, &mut frame);
+            assert_eq!(value(&values, "input"), Some("3"), "{}", path);
+            assert_eq!(value(&values, "factor"), Some("5"), "{}", path);
+            assert_eq!(value(&values, "kept"), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn fixture_entry_values_and_stack_slot() {
+        for path in FIXTURES.iter() {
+            let mut frame = TestFrame::default();
+            frame.registers.insert(0, 0);
+            frame.registers.insert(4, 7);
+            frame.registers.insert(13, SP);
+            frame.caller.insert(0, 3);
+            frame.caller.insert(1, 5);
+            frame.write(SP + 4, &15u32.to_le_bytes());
+            let values = fixture_values(path, 0x2010c, &mut frame);
+            assert_eq!(value(&values, "input"), Some("3"), "{}", path);
+            assert_eq!(value(&values, "factor"), Some("5"), "{}", path);
+            assert_eq!(value(&values, "kept"), Some("15"), "{}", path);
+            // Only the low half of the u64 is described, so it is left out.
+            assert_eq!(value(&values, "offset"), None, "{}", path);
+            assert_eq!(value(&values, "out"), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn fixture_entry_values_without_the_caller() {
+        for path in FIXTURES.iter() {
+            let mut frame = TestFrame::default();
+            frame.registers.insert(13, SP);
+            frame.write(SP + 4, &15u32.to_le_bytes());
+            let values = fixture_values(path, 0x2010c, &mut frame);
+            let unavailable = EntryValueUnavailable.to_string();
+            assert_eq!(value(&values, "input"), Some(unavailable.as_str()), "{}", path);
+            assert_eq!(value(&values, "factor"), Some(unavailable.as_str()), "{}", path);
+            assert_eq!(value(&values, "kept"), Some("15"), "{}", path);
+        }
+    }
+
+    #[test]
+    fn fixture_inner_block() {
+        for path in FIXTURES.iter() {
+            let mut frame = TestFrame::default();
+            frame.registers.insert(0, 22);
+            frame.registers.insert(13, SP);
+            frame.write(SP + 4, &15u32.to_le_bytes());
+            let values = fixture_values(path, 0x20110, &mut frame);
+            assert_eq!(value(&values, "out"), Some("22"), "{}", path);
+            assert_eq!(value(&values, "kept"), Some("15"), "{}", path);
+        }
+    }
+}
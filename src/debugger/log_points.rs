@@ -0,0 +1,103 @@
+//! Logpoints, breakpoints that print a message instead of stopping
+//!
+//! The message of a DAP source breakpoint with a `logMessage` is text with `{expression}`
+//! interpolations, `{{` and `}}` are literal braces. The expressions are the ones of `watch
+//! break`: a variable path is replaced by its value, other expressions by their truth. An
+//! expression that can't be evaluated at the hit is replaced by the error in angle brackets.
+//!
+//! A hit logpoint prints its message as console output and the core is resumed, unless another
+//! breakpoint at the address stops it or a pause request came in while it was halted.
+use anyhow::{anyhow, Result};
+use serde::{Serialize, Serializer};
+use std::fmt;
+
+use super::conditions::Condition;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Expression(Condition),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogMessage {
+    text: String,
+    parts: Vec<Part>,
+}
+
+impl LogMessage {
+    pub fn parse(text: &str) -> Result<LogMessage> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut expression = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => expression.push(c),
+                            None => {
+                                return Err(anyhow!("Missing `}}` in log message `{}`", text))
+                            }
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Expression(Condition::parse(&expression)?));
+                }
+                '}' => return Err(anyhow!("Unmatched `}}` in log message `{}`", text)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+
+        Ok(LogMessage {
+            text: text.to_owned(),
+            parts,
+        })
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Renders the message with the values of the expressions.
+    pub fn render(&self, evaluate: &mut dyn FnMut(&Condition) -> Result<String>) -> String {
+        let mut output = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => output.push_str(text),
+                Part::Expression(expression) => match evaluate(expression) {
+                    Ok(value) => output.push_str(&value),
+                    Err(err) => output.push_str(&format!("<{}>", err)),
+                },
+            }
+        }
+        output
+    }
+}
+
+impl fmt::Display for LogMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Serialize for LogMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.text)
+    }
+}
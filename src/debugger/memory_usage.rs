@@ -0,0 +1,272 @@
+use anyhow::Result;
+use object::elf::{FileHeader32, PT_LOAD};
+use object::read::elf::{FileHeader, ProgramHeader};
+use object::{Object, ObjectSection, ObjectSymbol, SectionKind};
+use probe_rs::config::MemoryRegion;
+use serde::Serialize;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegionUsage {
+    pub name: String,
+    pub start: u32,
+    pub size: u32,
+    pub used: u32,
+    /// The most bytes a section extends past the end of the region.
+    pub past_end: u32,
+}
+
+impl RegionUsage {
+    pub fn overflows(&self) -> bool {
+        self.used > self.size || self.past_end > 0
+    }
+
+    pub fn percent(&self) -> u32 {
+        match self.size {
+            0 => 0,
+            size => ((self.used as u64 * 100) / size as u64) as u32,
+        }
+    }
+}
+
+/// How much of each memory region of the target a binary occupies.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryUsage {
+    pub flash: Vec<RegionUsage>,
+    pub ram: Vec<RegionUsage>,
+    pub stack: Option<u32>,
+}
+
+impl MemoryUsage {
+    pub fn new(path: &Path, memory_map: &[MemoryRegion]) -> Result<MemoryUsage> {
+        let file = fs::File::open(&path)?;
+        let mmap = unsafe { memmap::Mmap::map(&file)? };
+        let object = object::File::parse(&*mmap)?;
+        let segments = load_segments(&*mmap);
+
+        let mut flash = vec![];
+        let mut ram = vec![];
+        for region in memory_map {
+            match region {
+                MemoryRegion::Nvm(nvm) => flash.push(new_region_usage("flash", &nvm.range)),
+                MemoryRegion::Ram(r) => ram.push(new_region_usage("RAM", &r.range)),
+                MemoryRegion::Generic(_) => (),
+            };
+        }
+
+        let mut ram_end = None;
+        for section in object.sections() {
+            let address = section.address() as u32;
+            let size = section.size() as u32;
+            if size == 0 || address == 0 {
+                continue;
+            }
+
+            match section.kind() {
+                SectionKind::Text | SectionKind::ReadOnlyData | SectionKind::ReadOnlyString => {
+                    add_usage(&mut flash, address, size);
+                }
+                SectionKind::Data => {
+                    // Initialized data lives in RAM but its initial values are stored in flash.
+                    if add_usage(&mut ram, address, size) {
+                        add_usage(&mut flash, load_address(&segments, address), size);
+                        ram_end = ram_end.max(Some(address.saturating_add(size)));
+                    } else {
+                        add_usage(&mut flash, address, size);
+                    }
+                }
+                SectionKind::UninitializedData => {
+                    if add_usage(&mut ram, address, size) {
+                        ram_end = ram_end.max(Some(address.saturating_add(size)));
+                    }
+                }
+                _ => (),
+            };
+        }
+
+        // cortex-m-rt exposes the initial stack pointer as `_stack_start`
+        let stack = object
+            .symbols()
+            .find(|symbol| symbol.name() == Ok("_stack_start"))
+            .and_then(|symbol| {
+                let stack_start = symbol.address() as u32;
+                ram_end.map(|end| stack_start.saturating_sub(end))
+            });
+
+        // Regions the image doesn't touch are omitted
+        flash.retain(|region| region.used > 0);
+        ram.retain(|region| region.used > 0);
+
+        Ok(MemoryUsage { flash, ram, stack })
+    }
+
+    pub fn overflowing_regions(&self) -> Vec<&RegionUsage> {
+        self.flash
+            .iter()
+            .chain(self.ram.iter())
+            .filter(|region| region.overflows())
+            .collect()
+    }
+
+    /// One line summary, e.g. "flash: 187 KiB / 512 KiB (36%), RAM static: 41 KiB / 128 KiB (32%)"
+    pub fn summary(&self) -> String {
+        let mut parts = vec![];
+        for region in &self.flash {
+            parts.push(format!(
+                "flash: {} KiB / {} KiB ({}%)",
+                region.used / 1024,
+                region.size / 1024,
+                region.percent()
+            ));
+        }
+        for region in &self.ram {
+            parts.push(format!(
+                "RAM static: {} KiB / {} KiB ({}%)",
+                region.used / 1024,
+                region.size / 1024,
+                region.percent()
+            ));
+        }
+        if let Some(stack) = self.stack {
+            parts.push(format!("stack: {} KiB", stack / 1024));
+        }
+
+        parts.join(", ")
+    }
+}
+
+fn new_region_usage(name: &str, range: &Range<u32>) -> RegionUsage {
+    RegionUsage {
+        name: name.to_owned(),
+        start: range.start,
+        size: range.end - range.start,
+        used: 0,
+        past_end: 0,
+    }
+}
+
+/// Adds a section to the region it starts in, a section that doesn't end in it is recorded as
+/// overflowing the region.
+fn add_usage(regions: &mut Vec<RegionUsage>, address: u32, size: u32) -> bool {
+    for region in regions {
+        if address >= region.start && address - region.start < region.size {
+            region.used = region.used.saturating_add(size);
+            let region_end = region.start as u64 + region.size as u64;
+            let past_end = match address.checked_add(size) {
+                Some(end) => (end as u64).saturating_sub(region_end),
+                // The section wraps around the address space
+                None => address as u64 + size as u64 - region_end,
+            };
+            region.past_end = region.past_end.max(past_end.min(u32::MAX as u64) as u32);
+            return true;
+        }
+    }
+    false
+}
+
+/// A loadable segment of the binary, linked at `address` and stored at `load_address`.
+struct LoadSegment {
+    address: u32,
+    load_address: u32,
+    size: u32,
+}
+
+// Without program headers every section is stored where it is linked.
+fn load_segments(data: &[u8]) -> Vec<LoadSegment> {
+    read_load_segments(data).unwrap_or_default()
+}
+
+fn read_load_segments(data: &[u8]) -> Result<Vec<LoadSegment>> {
+    let header = FileHeader32::<object::Endianness>::parse(data)?;
+    let endian = header.endian()?;
+    Ok(header
+        .program_headers(endian, data)?
+        .iter()
+        .filter(|segment| segment.p_type(endian) == PT_LOAD)
+        .map(|segment| LoadSegment {
+            address: segment.p_vaddr(endian),
+            load_address: segment.p_paddr(endian),
+            size: segment.p_memsz(endian),
+        })
+        .collect())
+}
+
+/// The address the bytes linked at `address` are stored at, the LMA of the section.
+fn load_address(segments: &[LoadSegment], address: u32) -> u32 {
+    segments
+        .iter()
+        .find(|segment| address >= segment.address && address - segment.address < segment.size)
+        .map(|segment| segment.load_address.wrapping_add(address - segment.address))
+        .unwrap_or(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regions() -> Vec<RegionUsage> {
+        vec![
+            new_region_usage("flash", &(0x0800_0000..0x0804_0000)),
+            new_region_usage("flash", &(0x0810_0000..0x0811_0000)),
+        ]
+    }
+
+    #[test]
+    fn a_section_inside_a_region() {
+        let mut regions = regions();
+        assert!(add_usage(&mut regions, 0x0803_f000, 0x1000));
+        assert_eq!(regions[0].used, 0x1000);
+        assert!(!regions[0].overflows());
+    }
+
+    #[test]
+    fn a_section_past_the_end_of_a_region() {
+        let mut regions = regions();
+        assert!(add_usage(&mut regions, 0x0803_ff00, 0x200));
+        assert_eq!(regions[0].past_end, 0x100);
+        // Only 512 bytes of the region are used, it still overflows
+        assert!(regions[0].overflows());
+    }
+
+    #[test]
+    fn a_section_wrapping_the_address_space() {
+        let mut regions = vec![new_region_usage("RAM", &(0xffff_0000..0xffff_ffff))];
+        assert!(add_usage(&mut regions, 0xffff_fff0, 0x20));
+        assert_eq!(regions[0].past_end, 0x11);
+    }
+
+    #[test]
+    fn a_section_outside_the_regions() {
+        let mut regions = regions();
+        assert!(!add_usage(&mut regions, 0x2000_0000, 0x100));
+        assert!(regions.iter().all(|region| region.used == 0));
+    }
+
+    #[test]
+    fn data_is_stored_at_its_load_address() {
+        // .data linked in RAM and stored after .text in the second flash bank
+        let segments = [
+            LoadSegment {
+                address: 0x0810_0000,
+                load_address: 0x0810_0000,
+                size: 0x4000,
+            },
+            LoadSegment {
+                address: 0x2000_0000,
+                load_address: 0x0810_4000,
+                size: 0x400,
+            },
+        ];
+        assert_eq!(load_address(&segments, 0x2000_0100), 0x0810_4100);
+        assert_eq!(load_address(&segments, 0x2000_0400), 0x2000_0400);
+
+        let mut regions = regions();
+        add_usage(&mut regions, load_address(&segments, 0x2000_0000), 0x400);
+        assert_eq!(regions[0].used, 0);
+        assert_eq!(regions[1].used, 0x400);
+    }
+}
@@ -1,44 +1,182 @@
+pub mod architecture;
+pub mod breakpoint_unit;
+pub mod breakpoints;
+pub mod cancel;
+pub mod chips;
+pub mod code_address;
+pub mod conditions;
 pub mod config;
-
-use config::Config;
+pub mod core_clock;
+pub mod core_dump;
+pub mod disassemble;
+pub mod fault_status;
+pub mod finish;
+pub mod flash_image;
+pub mod flash_layout;
+pub mod flash_readback;
+pub mod formats;
+pub mod frame_label;
+pub mod frame_state;
+pub mod function_breakpoints;
+pub mod heap;
+pub mod history;
+pub mod instruction_breakpoints;
+pub mod instruction_trace;
+pub mod intrusiveness;
+pub mod locations;
+pub mod log_points;
+pub mod memory_usage;
+pub mod prefetch;
+pub mod probes;
+pub mod ram_code;
+pub mod regions;
+pub mod reset_catch;
+pub mod resumes;
+pub mod section_map;
+pub mod self_test;
+pub mod semihosting;
+pub mod session_fault;
+pub mod session_summary;
+pub mod source_references;
+pub mod stepping;
+pub mod stop_ids;
+pub mod stop_report;
+pub mod symbols;
+pub mod target;
+pub mod test_run;
+pub mod time_units;
+pub mod timed_run;
+pub mod trace_buffer;
+pub mod trap_catch;
+pub mod values;
+pub mod vector_table;
+pub mod watch_break;
+pub mod watchdog;
+pub mod watchpoints;
+pub mod write_policy;
+
+use architecture::{require_cortex_m, CoreArchitecture};
+use breakpoint_unit::BreakpointUnit;
+use breakpoints::{
+    breakpoint_conditions, breakpoint_ids, breakpoints_at, count_hits, BreakpointFile,
+    BreakpointState, CountedHits, SavedBreakpoint, SavedWatchpoint, VerifyReport, VerifyStatus,
+};
+use cancel::{CancelToken, Cancelled};
+use chips::resolve_chip;
+use code_address::CodeAddress;
+use conditions::{Condition, HitCondition, Value};
+use config::{
+    resolve_path, Config, DisconnectAction, HeapConfig, PollingConfig, ReloadTarget,
+    StepBreakpoints, SymbolFile,
+};
+use core_clock::{calibrate, CoreClock, DEFAULT_CALIBRATION_INTERVAL};
+use core_dump::{CoreDump, CoreDumpHeader, CoreDumpRegister, DumpRegions, CHUNK_SIZE};
+use disassemble::{
+    decode, select, Decoded, DisassembledInstruction, MAX_INSTRUCTION_SIZE, MAX_LOOKBACK,
+};
+use fault_status::FaultStatus;
+use finish::{format_return_value, function_return_type, FinishState};
+use flash_image::FlashImage;
+use flash_layout::check_layout;
+use flash_readback::{flash_range, read_flash};
+use formats::{FormatFile, FormatOverrides};
+use frame_label::StackFrameFormat;
+use frame_state::FrameState;
+use function_breakpoints::{resolve_function, FunctionBreakpoint};
+use heap::{heap_stats, HeapStats, DEFAULT_HEAP_SYMBOLS};
+use history::{EventHistory, HistoryKind};
+use instruction_breakpoints::InstructionBreakpoint;
+use instruction_trace::InstructionTrace;
+use intrusiveness::{Intrusiveness, IntrusivenessReport};
+use locations::recover_optimized_out;
+use log_points::LogMessage;
+use memory_usage::MemoryUsage;
+use prefetch::{read_stack, MemoryCache, RequestedRanges, StopCache};
+use probes::{find_probe, ProbeClaim};
+use ram_code::{main_address, ram_code_ranges, StartupBreakpoints, PENDING_MESSAGE};
+use regions::RegionMap;
+use reset_catch::ResetCatch;
+use resumes::ResumeCount;
+use section_map::SectionMap;
+use self_test::{self_test, SelfTestReport};
+use semihosting::{service_console, CommandLine, ConsoleCall, SEMIHOSTING_BKPT};
+use session_fault::{is_fatal, SessionFault};
+use session_summary::{ProbeDetails, SessionSummary};
+use source_references::{listing, listing_line, SourceContent, SourceReferences};
+use stepping::{
+    abandoned_description, active_exception, breakpoint_in_exception, disassemble_instruction,
+    step_instruction, DeferredHit, InstructionStep, RegisterSnapshot, UntilState,
+};
+use stop_ids::{ScopeKind, StopIds, VariableArena};
+use stop_report::StopReport;
+use symbols::{DwarfReader, Symbols};
+use target::{CoreDumpMemory, Target};
+use test_run::{TestEvent, TestRun};
+use timed_run::TimedRun;
+use trace_buffer::{symbolize_branches, TraceBuffer, FAULT_BRANCHES};
+use trap_catch::{
+    filter_catch_functions, ExceptionFilter, TrapCatches, DEFAULT_CATCH_FUNCTIONS,
+    FILTERED_CATCH_FUNCTIONS,
+};
+use values::{parse_value, Endianness};
+use vector_table::{vector_table_info, ElfVectorTable, VectorTableInfo};
+use watch_break::{full_value, WatchBreaks};
+use watchdog::{is_watched, operation_name, Reopen, Watchdog};
+use watchpoints::{
+    check_range, data_id, format_value, parse_data_id, DataBreakpoint, DataBreakpointResult,
+    WatchKind, Watchpoints,
+};
+use write_policy::{first_peripheral, is_peripheral, PeripheralWrites};
 
 use rust_debug::call_stack::{CallFrame, MemoryAccess};
-use rust_debug::evaluate::evaluate::{get_udata, EvaluatorValue};
+use rust_debug::evaluate::evaluate::{get_udata, EvaluatorValue, ValueInformation, ValuePiece};
 use rust_debug::registers::Registers;
-use rust_debug::source_information::{find_breakpoint_location, SourceInformation};
+use rust_debug::source_information::SourceInformation;
 
 use std::num::NonZeroU64;
 
-use gimli::DebugFrame;
-use gimli::Dwarf;
 use gimli::Reader;
 
 use super::commands::{
-    debug_event::DebugEvent, debug_request::DebugRequest, debug_response::DebugResponse, Command,
+    commands::{parse_u32_from_str, Commands}, debug_event::DebugEvent, debug_request::DebugRequest, debug_response::DebugResponse, Command,
 };
 
 use super::Opt;
-use super::{attach_probe, read_dwarf};
+use super::attach_probe;
+use super::logger;
 use anyhow::{anyhow, Context, Result};
-use capstone::arch::BuildsCapstone;
 use crossbeam_channel::{Receiver, Sender, TryRecvError};
 use debugserver_types::{Breakpoint, SourceBreakpoint};
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
 use probe_rs::flashing::{download_file, Format};
-use probe_rs::{CoreStatus, MemoryInterface};
+use probe_rs::{CoreStatus, HaltReason, MemoryInterface};
 use regex::Regex;
+use serde::Serialize;
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::time::{Duration, Instant};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
 pub struct DebugHandler {
     config: Config,
+    history: EventHistory,
+    watchdog: Watchdog,
+    cancel: CancelToken,
+    resumes: ResumeCount,
+    // The probe error that ended the last session, see `session_fault`.
+    fault: Option<SessionFault>,
 }
 
 impl DebugHandler {
     pub fn new(opt: Opt) -> DebugHandler {
         DebugHandler {
             config: Config::new(opt),
+            history: EventHistory::new(),
+            watchdog: Watchdog::new(),
+            cancel: CancelToken::new(),
+            resumes: ResumeCount::new(),
+            fault: None,
         }
     }
 
@@ -46,14 +184,66 @@ impl DebugHandler {
         DebugHandler {
             config: Config {
                 elf_file_path: None,
+                symbol_files: vec![],
                 chip: None,
+                core_dump: None,
                 work_directory: None,
                 probe_num: 0,
+                force_claim: false,
+                session_log: None,
+                polling: PollingConfig::default(),
+                heap: HeapConfig::default(),
+                step_breakpoints: StepBreakpoints::default(),
+                catch_functions: vec![],
+                exception_filters: None,
+                instruction_trace: 0,
+                live_flash_reads: false,
+                region_annotations: true,
+                test_run: false,
+                formats: FormatOverrides::default(),
+                format_files: vec![],
+                peripheral_writes: PeripheralWrites::default(),
+                command_line: CommandLine::default(),
+                core_clock: None,
+                disconnect_action: DisconnectAction::default(),
+                halt_on_reset: true,
+                check_flash_layout: true,
             },
+            history: EventHistory::new(),
+            watchdog: Watchdog::new(),
+            cancel: CancelToken::new(),
+            resumes: ResumeCount::new(),
+            fault: None,
         }
     }
 
-    pub fn run(
+    /// Cancelled by the frontend on disconnect or for the request in flight, see `cancel`.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Lets the frontend drop the halts reported before the core resumed, see `resumes`.
+    pub fn resume_count(&self) -> ResumeCount {
+        self.resumes.clone()
+    }
+
+    pub fn run(&mut self, sender: Sender<Command>, receiver: Receiver<DebugRequest>) -> Result<()> {
+        self.watchdog.supervise(sender.clone(), receiver.clone());
+        let result = self.handle_requests(sender, receiver);
+        self.watchdog.stop();
+
+        info!("{}", self.history.stop_stats());
+        if let Some(path) = &self.config.session_log {
+            match self.history.write(path) {
+                Ok(_) => info!("Session log written to: {:?}", path),
+                Err(err) => error!("{:?}", err),
+            };
+        }
+
+        result
+    }
+
+    fn handle_requests(
         &mut self,
         mut sender: Sender<Command>,
         mut receiver: Receiver<DebugRequest>,
@@ -62,6 +252,36 @@ impl DebugHandler {
             let request = receiver.recv()?;
             let (exit, response) = match self.handle_request(&mut sender, &mut receiver, request) {
                 Ok(val) => val,
+                Err(err) if err.is::<Reopen>() => {
+                    // The supervisor already failed the request, the session was dropped with the
+                    // probe and the next request reopens it.
+                    sender.send(Command::Event(DebugEvent::Output {
+                        category: "console".to_owned(),
+                        output: format!("{}", err),
+                    }))?;
+                    if self.watchdog.take_exit() {
+                        sender.send(Command::Response(DebugResponse::Exit))?;
+                        return Ok(());
+                    }
+                    continue;
+                }
+                Err(err) if err.is::<SessionFault>() => {
+                    let fault: SessionFault = err.downcast()?;
+                    error!("{}", fault);
+                    self.history.record(HistoryKind::Error, fault.to_string());
+                    // Announced once, the later requests only fail with it
+                    sender.send(Command::Event(DebugEvent::Output {
+                        category: "important".to_owned(),
+                        output: fault.to_string(),
+                    }))?;
+                    if fault.request {
+                        sender.send(Command::Response(DebugResponse::Error {
+                            message: fault.message(),
+                        }))?;
+                    }
+                    self.fault = Some(fault);
+                    continue;
+                }
                 Err(err) => {
                     sender.send(Command::Response(DebugResponse::Error {
                         message: format!("{:?}", err),
@@ -85,24 +305,156 @@ impl DebugHandler {
     ) -> Result<(bool, DebugResponse)> {
         match request {
             DebugRequest::Exit => Ok((true, DebugResponse::Exit)),
+            DebugRequest::History => Ok((
+                false,
+                DebugResponse::History {
+                    entries: self.history.entries(),
+                },
+            )),
+            DebugRequest::StopStats => Ok((
+                false,
+                DebugResponse::StopStats {
+                    stats: self.history.stop_stats(),
+                },
+            )),
+            DebugRequest::SetLogLevel { module, level } => Ok((
+                false,
+                DebugResponse::SetLogLevel {
+                    filter: logger::set_level(module.as_deref(), &level)?,
+                },
+            )),
+            DebugRequest::LogTail { lines } => Ok((
+                false,
+                DebugResponse::LogTail {
+                    lines: logger::tail(lines),
+                },
+            )),
             DebugRequest::SetBinary { path } => {
                 self.config.elf_file_path = Some(path);
                 Ok((false, DebugResponse::SetBinary))
             }
+            DebugRequest::SetSymbolFiles { symbol_files } => {
+                self.config.symbol_files = symbol_files;
+                Ok((false, DebugResponse::SetSymbolFiles))
+            }
             DebugRequest::SetProbeNumber { number } => {
                 self.config.probe_num = number;
                 Ok((false, DebugResponse::SetProbeNumber))
             }
+            DebugRequest::SetForceClaim { enabled } => {
+                self.config.force_claim = enabled;
+                Ok((false, DebugResponse::SetForceClaim))
+            }
             DebugRequest::SetChip { chip } => {
-                self.config.chip = Some(chip);
-                Ok((false, DebugResponse::SetChip))
+                let chip = resolve_chip(&chip)?;
+                info!("Chip set to {}", chip);
+                self.config.chip = Some(chip.clone());
+                Ok((false, DebugResponse::SetChip { chip }))
+            }
+            DebugRequest::SetPolling {
+                status_interval_ms,
+                low_power,
+            } => {
+                if let Some(interval) = status_interval_ms {
+                    self.config.polling.status_interval = Duration::from_millis(interval);
+                }
+                if let Some(low_power) = low_power {
+                    self.config.polling.low_power = low_power;
+                }
+                Ok((false, DebugResponse::SetPolling))
+            }
+            DebugRequest::SetHeapOptions {
+                symbol,
+                stats_on_stop,
+            } => {
+                if symbol.is_some() {
+                    self.config.heap.symbol = symbol;
+                }
+                if let Some(stats_on_stop) = stats_on_stop {
+                    self.config.heap.stats_on_stop = stats_on_stop;
+                }
+                Ok((false, DebugResponse::SetHeapOptions))
+            }
+            DebugRequest::SetStepBreakpoints { mode } => {
+                self.config.step_breakpoints = mode;
+                Ok((false, DebugResponse::SetStepBreakpoints))
+            }
+            DebugRequest::SetCatchFunctions { functions } => {
+                self.config.catch_functions = functions;
+                Ok((false, DebugResponse::SetCatchFunctions))
+            }
+            DebugRequest::SetExceptionFilters { filters } => {
+                self.config.exception_filters = Some(filters);
+                Ok((false, DebugResponse::SetExceptionFilters))
+            }
+            DebugRequest::SetInstructionTrace { length } => {
+                self.config.instruction_trace = length;
+                Ok((false, DebugResponse::SetInstructionTrace))
+            }
+            DebugRequest::SetLiveFlashReads { enabled } => {
+                self.config.live_flash_reads = enabled;
+                Ok((false, DebugResponse::SetLiveFlashReads))
+            }
+            DebugRequest::SetRegionAnnotations { enabled } => {
+                self.config.region_annotations = enabled;
+                Ok((false, DebugResponse::SetRegionAnnotations))
+            }
+            DebugRequest::SetTestRun { enabled } => {
+                self.config.test_run = enabled;
+                Ok((false, DebugResponse::SetTestRun))
+            }
+            DebugRequest::SetPeripheralWrites { policy } => {
+                self.config.peripheral_writes = policy;
+                Ok((false, DebugResponse::SetPeripheralWrites))
+            }
+            DebugRequest::SetCommandLine { command_line } => {
+                self.config.command_line = command_line;
+                Ok((false, DebugResponse::SetCommandLine))
+            }
+            DebugRequest::SetCoreDump { path } => {
+                self.config.core_dump = path;
+                Ok((false, DebugResponse::SetCoreDump))
             }
             DebugRequest::SetCWD { cwd } => {
                 self.config.work_directory = Some(cwd);
                 Ok((false, DebugResponse::SetCWD))
             }
+            DebugRequest::SetProbeTimeout { timeout_ms } => {
+                self.watchdog.set_deadline(probe_timeout(timeout_ms));
+                Ok((false, DebugResponse::SetProbeTimeout))
+            }
+            DebugRequest::SetCoreClock { hz } => {
+                self.config.core_clock = Some(CoreClock::user(hz));
+                Ok((false, DebugResponse::SetCoreClock))
+            }
+            DebugRequest::SetDisconnectAction { action } => {
+                self.config.disconnect_action = action;
+                Ok((false, DebugResponse::SetDisconnectAction))
+            }
+            DebugRequest::SetHaltOnReset { enabled } => {
+                self.config.halt_on_reset = enabled;
+                Ok((false, DebugResponse::SetHaltOnReset))
+            }
+            DebugRequest::SetCheckFlashLayout { enabled } => {
+                self.config.check_flash_layout = enabled;
+                Ok((false, DebugResponse::SetCheckFlashLayout))
+            }
             _ => {
+                // Only a new attach or launch can replace the lost session
+                let fault = self.fault.take();
+                if let Some(fault) = &fault {
+                    if !matches!(
+                        request,
+                        DebugRequest::Attach { .. } | DebugRequest::Flash { .. }
+                    ) {
+                        let message = fault.message();
+                        self.fault = Some(fault.clone());
+                        return Ok((false, DebugResponse::Error { message }));
+                    }
+                }
                 if self.config.is_missing_config() {
+                    // Nothing was attached, the session is as lost as before
+                    self.fault = fault;
                     return Ok((
                         false,
                         DebugResponse::Error {
@@ -111,33 +463,25 @@ impl DebugHandler {
                     ));
                 }
 
-                let new_request = init(
+                let new_request = match init(
                     sender,
                     receiver,
-                    match self.config.elf_file_path.clone() {
-                        Some(val) => val,
-                        None => {
-                            error!("Requires elf file path");
-                            return Err(anyhow!("Requires elf file path"));
-                        }
-                    },
-                    self.config.probe_num,
-                    match self.config.chip.clone() {
-                        Some(val) => val,
-                        None => {
-                            error!("Requires chip");
-                            return Err(anyhow!("Requires chip"));
-                        }
-                    },
-                    match self.config.work_directory.clone() {
-                        Some(val) => val,
-                        None => {
-                            error!("Requires elf file path");
-                            return Err(anyhow!("Requires elf file path"));
-                        }
-                    },
+                    &mut self.config,
+                    &mut self.history,
+                    &self.watchdog,
+                    &self.cancel,
+                    &self.resumes,
                     request,
-                )?;
+                ) {
+                    Ok(val) => val,
+                    Err(err) => {
+                        // The probe is still gone if the attach failed
+                        if !err.is::<SessionFault>() && !err.is::<Reopen>() {
+                            self.fault = fault;
+                        }
+                        return Err(err);
+                    }
+                };
                 self.handle_request(sender, receiver, new_request)
             }
         }
@@ -147,85 +491,374 @@ impl DebugHandler {
 pub fn init(
     sender: &mut Sender<Command>,
     receiver: &mut Receiver<DebugRequest>,
-    file_path: PathBuf,
-    probe_number: usize,
-    chip: String,
-    cwd: String,
+    config: &mut Config,
+    history: &mut EventHistory,
+    watchdog: &Watchdog,
+    cancel: &CancelToken,
+    resumes: &ResumeCount,
     request: DebugRequest,
 ) -> Result<DebugRequest> {
-    let cs = capstone::Capstone::new() // TODO: Set the capstone base on the arch of the chip.
-        .arm()
-        .mode(capstone::arch::arm::ArchMode::Thumb)
-        .build()
-        .expect("Failed to create Capstone object");
-
-    let (owned_dwarf, owned_debug_frame) = read_dwarf(&file_path)?;
-    let debug_info = DebugInformation::new(&owned_dwarf, &owned_debug_frame);
-
-    let mut session = attach_probe(&chip, probe_number)?;
-
-    let (pc_reg, link_reg, sp_reg) = {
-        let core = session.core(0)?;
-        let pc_reg =
-            probe_rs::CoreRegisterAddress::from(core.registers().program_counter()).0 as usize;
-        let link_reg =
-            probe_rs::CoreRegisterAddress::from(core.registers().return_address()).0 as usize;
-        let sp_reg =
-            probe_rs::CoreRegisterAddress::from(core.registers().stack_pointer()).0 as usize;
-        (pc_reg, link_reg, sp_reg)
+    let cwd = match config.work_directory.clone() {
+        Some(val) => val,
+        None => std::env::current_dir()?.display().to_string(),
+    };
+    config.resolve_paths(&cwd);
+
+    // A core dump replaces the probe, only inspection of the captured state is possible.
+    let core_dump = match &config.core_dump {
+        Some(path) => Some(CoreDump::read(path)?),
+        None => None,
+    };
+    let mut symbol_files = config.effective_symbol_files();
+    if let Some(dump) = &core_dump {
+        if let (true, Some(program)) = (symbol_files.is_empty(), &dump.header.program) {
+            symbol_files.push(SymbolFile {
+                path: PathBuf::from(program),
+                base_address: 0,
+            });
+        }
+    }
+
+    // The binary is optional, without it only the raw target inspection commands are available.
+    let symbols = Symbols::load(&symbol_files)?;
+    if symbols.is_empty() {
+        info!("No binary set, source level debugging is disabled");
+    } else if let Some(program) = &config.elf_file_path {
+        symbols.check_program(program)?;
+    }
+
+    let (session, arch, (pc_reg, link_reg, sp_reg), probe_claim, probe_details) = match core_dump {
+        // The ARM register numbers, core dumps are only written from ARM targets.
+        Some(dump) => {
+            history.record(
+                HistoryKind::Attach,
+                format!("Opened core dump of a {}", dump.header.chip),
+            );
+            let arch: &'static dyn CoreArchitecture = &architecture::Arm;
+            (Target::CoreDump(dump), arch, (15, 14, 13), None, None)
+        }
+        None => {
+            let chip = match &config.chip {
+                Some(val) => resolve_chip(val)?,
+                None => {
+                    error!("Requires chip");
+                    return Err(anyhow!("Requires chip"));
+                }
+            };
+            // Other sessions of the server can't use the same probe
+            let (probe_info, probe_id) = find_probe(config.probe_num)?;
+            let probe_claim = ProbeClaim::new(probe_id.clone())?;
+            watchdog.start("attaching to the probe", true);
+            let session = attach_probe(&chip, &probe_info, config.force_claim);
+            if watchdog.finish() {
+                return Err(Reopen.into());
+            }
+            let (mut session, probe_details) = session?;
+
+            let (arch, registers) = {
+                let core = session.core(0)?;
+                let arch = architecture::of(&core);
+                // The unwinder knows the registers by their DWARF numbers
+                let number = |register| {
+                    let address = probe_rs::CoreRegisterAddress::from(register).0;
+                    arch.dwarf_register(address).unwrap_or(address) as usize
+                };
+                let pc_reg = number(core.registers().program_counter());
+                let link_reg = number(core.registers().return_address());
+                let sp_reg = number(core.registers().stack_pointer());
+                (arch, (pc_reg, link_reg, sp_reg))
+            };
+            history.record(
+                HistoryKind::Attach,
+                format!(
+                    "Attached to {} ({}) with probe {}",
+                    chip,
+                    arch.name(),
+                    probe_id
+                ),
+            );
+            (
+                Target::Probe(session),
+                arch,
+                registers,
+                Some(probe_claim),
+                Some(probe_details),
+            )
+        }
     };
     let mut registers = Registers::new();
     registers.program_counter_register = Some(pc_reg);
     registers.link_register = Some(link_reg);
     registers.stack_pointer_register = Some(sp_reg);
+    let running = !matches!(session, Target::CoreDump(_));
+    let regions = RegionMap::new(session.memory_map(), config.region_annotations);
 
     let mut debugger = Debugger {
-        capstone: cs,
-        debug_info,
+        sender: sender.clone(),
+        arch,
+        capstone: arch.capstone()?,
+        program: config.elf_file_path.clone(),
+        program_modified: config.elf_file_path.as_deref().and_then(modified_time),
+        symbol_files: config.symbol_files.clone(),
+        symbols,
         session,
+        _probe_claim: probe_claim,
+        probe: probe_details.clone(),
         breakpoints: HashMap::new(),
-        file_path,
+        breakpoint_unit: BreakpointUnit::default(),
+        shared_breakpoints: HashMap::new(),
+        function_breakpoints: HashMap::new(),
+        instruction_breakpoints: HashMap::new(),
+        trace_buffer: None,
+        source_references: SourceReferences::default(),
+        test_run: match config.test_run {
+            true => Some(TestRun::default()),
+            false => None,
+        },
+        next_shared_id: u32::MAX,
+        breakpoint_states: HashMap::new(),
+        disabled_breakpoints: vec![],
+        startup: StartupBreakpoints::default(),
+        snapshots: HashMap::new(),
+        watchpoints: Watchpoints::new(),
+        data_breakpoints: vec![],
+        watch_breaks: WatchBreaks::new(),
+        pause_requested: false,
+        finish: None,
+        until: None,
+        timed_run: None,
         cwd,
         check_time: Instant::now(),
-        running: true,
+        running,
         registers,
         stack_trace: None,
+        total_frames: 0,
         stack_frames: None,
         scopes: None,
         variables: None,
-        id_gen: IdGen::new(),
+        stop_ids: StopIds::new(),
         trace: false,
+        history: std::mem::take(history),
+        polling: config.polling.clone(),
+        heap: config.heap.clone(),
+        step_breakpoints: config.step_breakpoints,
+        step_abandoned: None,
+        catch_functions: config.catch_functions.clone(),
+        exception_filters: config.exception_filters.clone(),
+        catches: TrapCatches::default(),
+        instruction_trace: match config.instruction_trace {
+            0 => None,
+            length => Some(InstructionTrace::new(length)),
+        },
+        live_flash_reads: config.live_flash_reads,
+        regions,
+        formats: config.formats.clone(),
+        format_files: config.format_files.clone(),
+        peripheral_writes: config.peripheral_writes,
+        command_line: config.command_line.clone(),
+        core_clock: config.core_clock,
+        disconnect_action: config.disconnect_action,
+        halt_on_reset: config.halt_on_reset,
+        reset_catch: ResetCatch::default(),
+        check_flash_layout: config.check_flash_layout,
+        stop_cache: None,
+        requested_ranges: RequestedRanges::default(),
+        watchdog: watchdog.clone(),
+        cancel: cancel.clone(),
+        resumes: resumes.clone(),
+        intrusiveness: Intrusiveness::new(),
     };
 
-    debugger.run(sender, receiver, request)
+    let summary = debugger.session_summary(probe_details, config.core_dump.clone())?;
+    info!("{}", summary);
+    debugger.output("console", &summary.to_string())?;
+
+    if debugger.polling.low_power {
+        debugger.output("console", LOW_POWER_WARNING)?;
+    }
+
+    debugger.report_memory_usage()?;
+    debugger.resolve_catches()?;
+
+    if let Some(dump) = debugger.session.core_dump() {
+        info!("Opened core dump of a {}", dump.header.chip);
+        sender.send(Command::Event(DebugEvent::Halted {
+            pc: dump.register(pc_reg as u16).unwrap_or(0),
+            reason: HaltReason::Request,
+            hit_breakpoint_ids: None,
+            description: None,
+            resume: resumes.current(),
+        }))?;
+    }
+
+    let result = debugger.run(sender, receiver, request);
+
+    if let Ok(DebugRequest::Exit) = result {
+        let report = debugger.intrusiveness_report();
+        if !report.is_empty() {
+            info!("{}", report);
+            debugger.output("console", &report.to_string())?;
+        }
+    }
+
+    // Symbols can be loaded while the debugger is running, keep the config in sync with them.
+    config.elf_file_path = debugger.program;
+    config.symbol_files = debugger.symbol_files;
+    *history = debugger.history;
+    config.polling = debugger.polling;
+    config.heap = debugger.heap;
+    config.step_breakpoints = debugger.step_breakpoints;
+    config.catch_functions = debugger.catch_functions;
+    config.exception_filters = debugger.exception_filters;
+    config.instruction_trace = debugger
+        .instruction_trace
+        .as_ref()
+        .map_or(0, |trace| trace.length());
+    config.live_flash_reads = debugger.live_flash_reads;
+    config.region_annotations = debugger.regions.enabled;
+    config.test_run = debugger.test_run.is_some();
+    config.formats = debugger.formats;
+    config.format_files = debugger.format_files;
+    config.peripheral_writes = debugger.peripheral_writes;
+    config.command_line = debugger.command_line;
+    config.core_clock = debugger.core_clock;
+    config.disconnect_action = debugger.disconnect_action;
+    config.halt_on_reset = debugger.halt_on_reset;
+    config.check_flash_layout = debugger.check_flash_layout;
+
+    result
 }
 
-struct Debugger<'a, R: Reader<Offset = usize>> {
-    debug_info: DebugInformation<'a, R>,
-    session: probe_rs::Session,
+const LOW_POWER_WARNING: &str = "Low power mode: the target is not polled while it runs, breakpoints and other self-halts are only reported after a pause";
+
+// The most bytes of a static without debug information shown by evaluate.
+const MAX_STATIC_BYTES: u32 = 64;
+
+// The most names offered by the completions of the debug console.
+const MAX_COMPLETIONS: usize = 100;
+
+// The start of the binary in flash compared with the target for the session summary.
+const FLASH_CHECK_BYTES: usize = 256;
+
+// The size of the reads and writes of the memory requests, a failure is narrowed down within one.
+const MEMORY_REQUEST_CHUNK: usize = 1024;
+
+const SNAPSHOT_CAVEAT: &str =
+    "Only the core registers and memory are captured, peripheral state is not part of the snapshot";
+
+// The frames evaluated on a stop, the first page of stackTrace asks for about as many.
+const FIRST_STACK_PAGE: usize = 20;
+
+// The most call frames unwound, a corrupted stack can repeat its frames forever.
+const MAX_CALL_FRAMES: usize = 1024;
+
+struct Debugger {
+    sender: Sender<Command>,
+    program: Option<PathBuf>,
+    // When the program was last loaded or flashed, a restart flashes it again if it changed.
+    program_modified: Option<SystemTime>,
+    symbol_files: Vec<SymbolFile>,
+    symbols: Symbols,
+    session: Target,
+    _probe_claim: Option<ProbeClaim>,
+    // None for a core dump.
+    probe: Option<ProbeDetails>,
+    // Selected from the core at attach.
+    arch: &'static dyn CoreArchitecture,
     capstone: capstone::Capstone,
     breakpoints: HashMap<u32, Breakpoint>,
-    file_path: PathBuf,
+    // Read with the session summary, refuses the breakpoints the core can't match.
+    breakpoint_unit: BreakpointUnit,
+    // Further breakpoints at the address of a breakpoint in `breakpoints`, sharing its hardware
+    // breakpoint unit. Their ids count down from the top of the address space, where there is
+    // no code, so they don't collide with the address ids of the other breakpoints.
+    shared_breakpoints: HashMap<u32, Vec<Breakpoint>>,
+    // The planted instances of the function breakpoints, also in the breakpoints.
+    function_breakpoints: HashMap<u32, Breakpoint>,
+    instruction_breakpoints: HashMap<u32, Breakpoint>,
+    trace_buffer: Option<TraceBuffer>,
+    // The generated sources of the frames whose file isn't on disk.
+    source_references: SourceReferences,
+    // The results of the tests since the last reset, None if not in a test run.
+    test_run: Option<TestRun>,
+    next_shared_id: u32,
+    breakpoint_states: HashMap<u32, BreakpointState>,
+    disabled_breakpoints: Vec<SavedBreakpoint>,
+    // The breakpoints in RAM functions wait for the startup code, see `ram_code`.
+    startup: StartupBreakpoints,
+    snapshots: HashMap<String, CoreDump>,
+    watchpoints: Watchpoints,
+    // The ids of the watchpoints set as DAP data breakpoints.
+    data_breakpoints: Vec<u32>,
+    watch_breaks: WatchBreaks,
+    // Set by a pause request while the core was halted but not yet reported.
+    pause_requested: bool,
+    finish: Option<FinishState>,
+    until: Option<UntilState>,
+    timed_run: Option<TimedRun>,
     cwd: String,
     check_time: Instant,
     running: bool,
     registers: Registers,
+    // Only the top frames are evaluated until more are asked for.
     stack_trace: Option<Vec<StackFrame>>,
-    id_gen: IdGen,
+    // The depth of the unwound stack, known before all the frames are evaluated.
+    total_frames: usize,
+    stop_ids: StopIds,
     stack_frames: Option<Vec<debugserver_types::StackFrame>>,
     scopes: Option<HashMap<i64, Vec<debugserver_types::Scope>>>,
     variables: Option<HashMap<i64, Vec<Variable>>>,
     trace: bool,
+    history: EventHistory,
+    polling: PollingConfig,
+    heap: HeapConfig,
+    step_breakpoints: StepBreakpoints,
+    // Why the last step stopped early, reported with the next halted event.
+    step_abandoned: Option<String>,
+    // Caught in addition to the default catch functions.
+    catch_functions: Vec<String>,
+    // Replace the panic and hard fault default catches when set.
+    exception_filters: Option<Vec<ExceptionFilter>>,
+    catches: TrapCatches,
+    instruction_trace: Option<InstructionTrace>,
+    live_flash_reads: bool,
+    regions: RegionMap,
+    formats: FormatOverrides,
+    format_files: Vec<FormatFile>,
+    peripheral_writes: PeripheralWrites,
+    command_line: CommandLine,
+    // For converting cycles to time.
+    core_clock: Option<CoreClock>,
+    disconnect_action: DisconnectAction,
+    // Keep the core halted after a reset the target did by itself, see `reset_catch`.
+    halt_on_reset: bool,
+    reset_catch: ResetCatch,
+    // Refuse binaries that don't fit the memory map, see `flash_layout`.
+    check_flash_layout: bool,
+    // Cleared with the other per-stop state when the core resumes.
+    stop_cache: Option<StopCache>,
+    requested_ranges: RequestedRanges,
+    watchdog: Watchdog,
+    // Checked by the operations that loop or wait, see `cancel`.
+    cancel: CancelToken,
+    // Counted at every resume, a halt is reported with the count, see `resumes`.
+    resumes: ResumeCount,
+    intrusiveness: Intrusiveness,
 }
 
-impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
+impl Debugger {
     pub fn run(
         &mut self,
         sender: &mut Sender<Command>,
         receiver: &mut Receiver<DebugRequest>,
         request: DebugRequest,
     ) -> Result<DebugRequest> {
-        match self.handle_request(request)? {
+        let name = operation_name(&request);
+        let command = match self.handle_watched_request(request) {
+            Err(err) if is_fatal(&err) => return Err(SessionFault::new(&name, &err, true).into()),
+            result => result?,
+        };
+        match command {
             Command::Request(req) => return Ok(req),
             Command::Response(res) => sender.send(Command::Response(res))?,
             _ => {
@@ -238,11 +871,13 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
             match receiver.try_recv() {
                 Ok(request) => {
                     // we have recieved a request (either from CLI or DAP)
-                    match self.handle_request(request) {
+                    let name = operation_name(&request);
+                    match self.handle_watched_request(request) {
                         Ok(Command::Request(req)) => {
-                            let mut core = self.session.core(0)?;
-                            core.clear_all_hw_breakpoints()?;
-                            self.breakpoints = HashMap::new();
+                            match req {
+                                DebugRequest::Exit => self.disconnect()?,
+                                _ => self.clear_target_breakpoints()?,
+                            };
 
                             return Ok(req);
                         }
@@ -251,7 +886,12 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
                             error!("Unimplemented");
                             return Err(anyhow!("Unimplemented"));
                         }
+                        Err(err) if err.is::<Reopen>() => return Err(err),
+                        Err(err) if is_fatal(&err) => {
+                            return Err(SessionFault::new(&name, &err, true).into())
+                        }
                         Err(err) => {
+                            self.history.record(HistoryKind::Error, format!("{}", err));
                             sender.send(Command::Response(DebugResponse::Error {
                                 message: format!("{:?}", err),
                             }))?;
@@ -261,11 +901,26 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
                 Err(err) => {
                     match err {
                         // commands to process, to check if halted
-                        TryRecvError::Empty => self.check_halted(sender)?,
+                        TryRecvError::Empty => {
+                            self.watchdog.start("status poll", false);
+                            let result = match self.until {
+                                Some(_) => self.until_step(sender),
+                                None => self.check_halted(sender),
+                            };
+                            if self.watchdog.finish() {
+                                return Err(Reopen.into());
+                            }
+                            match result {
+                                // The exit request follows the cancel
+                                Err(err) if err.is::<Cancelled>() => (),
+                                Err(err) if is_fatal(&err) => {
+                                    return Err(SessionFault::new("status poll", &err, false).into())
+                                }
+                                result => result?,
+                            }
+                        }
                         TryRecvError::Disconnected => {
-                            let mut core = self.session.core(0)?;
-                            core.clear_all_hw_breakpoints()?;
-                            self.breakpoints = HashMap::new();
+                            self.disconnect()?;
 
                             return Err(anyhow!("{:?}", err));
                         }
@@ -275,18 +930,112 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
         }
     }
 
+    fn clear_target_breakpoints(&mut self) -> Result<()> {
+        if let Target::Probe(session) = &mut self.session {
+            let mut core = session.core(0)?;
+            core.clear_all_hw_breakpoints()?;
+            self.watchpoints.clear(&mut core)?;
+            // A reset after the session would halt the core
+            self.reset_catch.lift(&mut core)?;
+        }
+        self.breakpoints = HashMap::new();
+        self.shared_breakpoints = HashMap::new();
+
+        Ok(())
+    }
+
+    /// Removes the breakpoints and does the disconnect action, the last thing done with the
+    /// target before the session ends.
+    fn disconnect(&mut self) -> Result<()> {
+        self.clear_target_breakpoints()?;
+        let session = match &mut self.session {
+            Target::Probe(session) => session,
+            Target::CoreDump(_) => return Ok(()),
+        };
+        let mut core = session.core(0)?;
+        match self.disconnect_action {
+            DisconnectAction::Leave => (),
+            DisconnectAction::Resume => {
+                if core.core_halted()? {
+                    core.run()?;
+                }
+            }
+            DisconnectAction::Reset => core.reset()?,
+        };
+        info!("Disconnected, the core was left: {:?}", self.disconnect_action);
+
+        Ok(())
+    }
+
     fn clear_temporaries(&mut self) {
+        // Every resume, except the one starting it, ends a timed run
+        self.timed_run = None;
+        self.stop_ids.next_stop();
         self.registers.clear();
         self.stack_trace = None;
         self.stack_frames = None;
         self.scopes = None;
         self.variables = None;
+        self.stop_cache = None;
+    }
+
+    /// The variables are evaluated again on the next request, the stop state stays cached.
+    fn clear_variables(&mut self) {
+        self.stack_trace = None;
+        self.stack_frames = None;
+        self.scopes = None;
+        self.variables = None;
+    }
+
+    /// The core is known to be halted without asking the probe while the stop state is cached.
+    fn is_halted(&mut self) -> Result<bool> {
+        if self.stop_cache.is_some() {
+            return Ok(true);
+        }
+        Ok(self.session.core(0)?.status()?.is_halted())
+    }
+
+    /// Reads the state the first refresh after a stop always needs, see `prefetch`.
+    fn prefetch_stop_state(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let mut core = self.session.core(0)?;
+        let read_ahead = self.requested_ranges.take_coalesced();
+        let cache = StopCache::read(&mut core, &read_ahead)?;
+        drop(core);
+        self.stop_cache = Some(cache);
+
+        if !self.symbols.is_empty() && self.stack_trace.is_none() {
+            self.set_stack_trace(Some(FIRST_STACK_PAGE))?;
+            self.set_stack_frames()?;
+        }
+        debug!("Prefetched the stop state in {:?}", start.elapsed());
+
+        Ok(())
     }
 
     fn check_halted(&mut self, sender: &mut Sender<Command>) -> Result<()> {
-        let delta = Duration::from_millis(400);
-        if self.running && self.check_time.elapsed() > delta {
+        // The timer also runs in low power mode, it reports the halt itself
+        if let Some(timed_run) = &mut self.timed_run {
+            if timed_run.expired() {
+                timed_run.fire();
+                self.session.core(0)?.halt(Duration::from_millis(100))?;
+                return self.process_halt_event(sender);
+            }
+        }
+
+        // Sampling is asked for explicitly, it also runs in low power mode
+        if self.running && self.watch_breaks.sample_due() {
+            self.intrusiveness.record_access("watch sampling");
+            return self.sample_watch_breaks(sender);
+        }
+
+        if self.polling.low_power {
+            return Ok(());
+        }
+
+        if self.running && self.check_time.elapsed() > self.polling.status_interval {
             self.check_time = Instant::now();
+            self.intrusiveness.record_access("status poll");
             self.process_halt_event(sender)?;
         }
 
@@ -297,32 +1046,370 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
         let mut core = self.session.core(0)?;
         let status = core.status()?;
 
-        if let CoreStatus::Halted(reason) = status {
+        if let CoreStatus::Halted(mut reason) = status {
+            // The deadline is for each step of handling the halt, not for all of them
+            self.watchdog.heartbeat();
+            let detected = Instant::now();
             self.running = false;
+            // A pause that came in before the halt was seen keeps the core halted
+            let pause_requested = std::mem::take(&mut self.pause_requested);
 
             let pc = core.read_core_reg(core.registers().program_counter())?;
 
-            let mut hit_breakpoint_ids = vec![];
-            match self.breakpoints.get(&pc) {
-                Some(bkpt) => hit_breakpoint_ids.push(match bkpt.id {
-                    Some(val) => val,
-                    None => {
-                        error!("Breakpoint id is required");
-                        return Err(anyhow!("Breakpoint id is required"));
+            // The target reset itself, the firmware starts over from the reset vector
+            if self.reset_catch.hit(&mut core, reason, pc)? {
+                drop(core);
+                let halt = self.halt_on_reset || pause_requested;
+                return self.target_reset(sender, pc, halt, detected);
+            }
+
+            // The startup code has copied the RAM functions, their breakpoints can be planted
+            if self.startup.main == Some(pc) {
+                drop(core);
+                self.startup_done()?;
+                core = self.session.core(0)?;
+                if !self.breakpoints.contains_key(&pc) {
+                    continue_fix(&mut core, &self.breakpoints)?;
+                    core.run()?;
+                    self.running = true;
+                    self.resumes.resumed();
+                    self.intrusiveness.record_halt("startup breakpoint", detected);
+                    return Ok(());
+                }
+            }
+
+            // Every breakpoint at the address counts the hit, stop unless all of them ignore it.
+            // A breakpoint whose condition is false doesn't count the hit, one whose hit
+            // condition is false counts it without stopping.
+            let hit_breakpoints = breakpoints_at(&self.breakpoints, &self.shared_breakpoints, pc);
+            let mut watch_stop = None;
+            let mut pause_stop = false;
+            if !hit_breakpoints.is_empty() {
+                let conditions =
+                    breakpoint_conditions(&hit_breakpoints, &self.breakpoint_states, pc);
+                let mut unmet = vec![];
+                if !conditions.is_empty() {
+                    drop(core);
+                    unmet = self.unmet_conditions(conditions)?;
+                    core = self.session.core(0)?;
+                }
+
+                let CountedHits {
+                    ignored,
+                    not_reached,
+                    log_messages,
+                } = count_hits(&hit_breakpoints, &mut self.breakpoint_states, &unmet, pc);
+
+                drop(core);
+                for log_message in &log_messages {
+                    let output =
+                        log_message.render(&mut |expression| self.expression_value(expression));
+                    self.output("console", &output)?;
+                }
+
+                let skipped = ignored.len() + unmet.len() + not_reached + log_messages.len();
+                if skipped == hit_breakpoints.len() {
+                    // A changed watch expression keeps the core halted
+                    watch_stop = self.watch_break_changes()?;
+                    pause_stop = watch_stop.is_none() && pause_requested;
+                    core = self.session.core(0)?;
+                    for breakpoint in ignored {
+                        sender.send(Command::Event(DebugEvent::Breakpoint {
+                            reason: "changed".to_owned(),
+                            breakpoint,
+                        }))?;
                     }
-                } as u32),
-                None => (),
+                    if watch_stop.is_none() && !pause_stop {
+                        let evaluated = !unmet.is_empty() || !log_messages.is_empty();
+                        if evaluated || !self.watch_breaks.is_empty() {
+                            // The variables were read for the conditions and messages
+                            self.clear_temporaries();
+                        }
+                        continue_fix(&mut core, &self.breakpoints)?;
+                        core.run()?;
+                        self.running = true;
+                        self.resumes.resumed();
+                        let origin = match log_messages.is_empty() {
+                            true => "ignored breakpoint",
+                            false => "logpoint",
+                        };
+                        self.intrusiveness.record_halt(origin, detected);
+                        return Ok(());
+                    }
+                } else {
+                    core = self.session.core(0)?;
+                }
+            }
+
+            // The command line asked for by semihosting firmware
+            self.watchdog.heartbeat();
+            if let Ok(SEMIHOSTING_BKPT) = read_bkpt(&mut core, pc) {
+                let (serviced, warning) = self.command_line.service(&mut core)?;
+                if serviced {
+                    if let Some(warning) = warning {
+                        warn!("{}", warning);
+                        sender.send(Command::Event(DebugEvent::Output {
+                            category: "console".to_owned(),
+                            output: warning,
+                        }))?;
+                    }
+                    continue_fix(&mut core, &self.breakpoints)?;
+                    core.run()?;
+                    self.running = true;
+                    self.resumes.resumed();
+                    self.intrusiveness.record_halt("semihosting", detected);
+                    return Ok(());
+                }
+
+                // The console of test firmware, the core stays halted after an exit
+                if let Some(test_run) = &mut self.test_run {
+                    let call = service_console(&mut core)?;
+                    let (lines, events) = match &call {
+                        Some(ConsoleCall::Exit { success }) => test_run.exit(*success),
+                        Some(ConsoleCall::Output(output)) => test_run.feed(output),
+                        _ => (vec![], vec![]),
+                    };
+                    if !lines.is_empty() {
+                        sender.send(Command::Event(DebugEvent::Output {
+                            category: "stdout".to_owned(),
+                            output: lines.join("\n"),
+                        }))?;
+                    }
+                    for event in events {
+                        sender.send(Command::Event(DebugEvent::Test { event }))?;
+                    }
+                    match call {
+                        Some(ConsoleCall::Exit { success }) => {
+                            info!("The firmware exited, success: {}", success);
+                        }
+                        Some(_) => {
+                            continue_fix(&mut core, &self.breakpoints)?;
+                            core.run()?;
+                            self.running = true;
+                            self.resumes.resumed();
+                            self.intrusiveness.record_halt("semihosting", detected);
+                            return Ok(());
+                        }
+                        None => (),
+                    };
+                }
+            }
+
+            if let Some(finish) = self.finish.take() {
+                if finish.temporary_breakpoint {
+                    core.clear_hw_breakpoint(finish.return_address)?;
+                }
+                let sp = core.read_core_reg(core.registers().stack_pointer())?;
+
+                if pc == finish.return_address && sp < finish.caller_stack_pointer {
+                    // Returned to the same address in a deeper recursive call, keep going.
+                    core.step()?;
+                    if finish.temporary_breakpoint {
+                        core.set_hw_breakpoint(finish.return_address)?;
+                    }
+                    core.run()?;
+                    self.running = true;
+                    self.resumes.resumed();
+                    self.intrusiveness.record_halt("finish in recursion", detected);
+                    self.finish = Some(finish);
+                    return Ok(());
+                } else if pc == finish.return_address {
+                    let mut output = format!("Run till exit from `{}`", finish.function);
+                    if let Some(return_type) = &finish.return_type {
+                        let registers = self.arch.return_registers(
+                            &mut core,
+                            finish.hard_float,
+                            finish.endianness,
+                        )?;
+                        output = format!(
+                            "{}\nValue returned: {}",
+                            output,
+                            format_return_value(return_type, &registers)
+                        );
+                    }
+                    sender.send(Command::Event(DebugEvent::Output {
+                        category: "console".to_owned(),
+                        output,
+                    }))?;
+                    reason = HaltReason::Step;
+                } else if let Some(exception) =
+                    breakpoint_in_exception(&mut core, &self.breakpoints, pc)?
+                {
+                    let step = format!("finish of `{}`", finish.function);
+                    match self.step_breakpoints {
+                        StepBreakpoints::Defer => {
+                            let hit = DeferredHit {
+                                address: pc,
+                                exception,
+                            };
+                            sender.send(Command::Event(DebugEvent::Output {
+                                category: "console".to_owned(),
+                                output: hit.message(&step),
+                            }))?;
+                            continue_fix(&mut core, &self.breakpoints)?;
+                            if finish.temporary_breakpoint {
+                                core.set_hw_breakpoint(finish.return_address)?;
+                            }
+                            core.run()?;
+                            self.running = true;
+                            self.resumes.resumed();
+                            self.intrusiveness.record_halt("deferred breakpoint", detected);
+                            self.finish = Some(finish);
+                            return Ok(());
+                        }
+                        StepBreakpoints::Stop => {
+                            self.step_abandoned = Some(abandoned_description(&step, exception));
+                        }
+                    }
+                } else {
+                    sender.send(Command::Event(DebugEvent::Output {
+                        category: "console".to_owned(),
+                        output: format!("Finish of `{}` was interrupted", finish.function),
+                    }))?;
+                }
+            }
+
+            let timed_run = self.timed_run.take();
+            let caught = match self.catches.hit(&mut core, pc)? {
+                Some(function) => match self.arch.describe_trap(&mut core)? {
+                    Some(trap) => Some(format!("Caught `{}`, {}", function, trap)),
+                    None => Some(format!("Caught `{}`", function)),
+                },
+                None if matches!(reason, HaltReason::Exception) => {
+                    match self.catches.fault(&mut core)? {
+                        Some(fault) => match TrapCatches::fault_address(&mut core)? {
+                            Some((kind, address)) => Some(format!(
+                                "Caught {}, {} at {}",
+                                fault,
+                                kind,
+                                self.regions.annotate(address)
+                            )),
+                            None => Some(format!("Caught {}", fault)),
+                        },
+                        None => None,
+                    }
+                }
+                None => None,
+            };
+            let mut description = match caught {
+                Some(caught) => {
+                    reason = HaltReason::Exception;
+                    // The branches leading up to the fault, if the MTB traced them
+                    if let Some(trace_buffer) = &self.trace_buffer {
+                        match trace_buffer.read(&mut core) {
+                            Ok(branches) => {
+                                let branches =
+                                    symbolize_branches(&self.symbols, &branches, FAULT_BRANCHES);
+                                let lines: Vec<String> =
+                                    branches.iter().map(|branch| branch.to_string()).collect();
+                                sender.send(Command::Event(DebugEvent::Output {
+                                    category: "console".to_owned(),
+                                    output: format!("Last branches:\n{}", lines.join("\n")),
+                                }))?;
+                            }
+                            Err(err) => warn!("Could not read the trace buffer: {}", err),
+                        }
+                    }
+                    Some(caught)
+                }
+                None => match timed_run {
+                    Some(timed_run) => Some(timed_run.description(&reason)),
+                    None => self.step_abandoned.take(),
+                },
+            };
+
+            let hit_breakpoint_ids = breakpoint_ids(&breakpoints_at(
+                &self.breakpoints,
+                &self.shared_breakpoints,
+                pc,
+            ))?;
+
+            for (watchpoint, old_value) in self.watchpoints.triggered(&mut core)? {
+                sender.send(Command::Event(DebugEvent::Output {
+                    category: "console".to_owned(),
+                    output: format!(
+                        "Watchpoint {} `{}` at {:#010x}: {} -> {}",
+                        watchpoint.id,
+                        watchpoint.name,
+                        watchpoint.address,
+                        format_value(&old_value, watchpoint.endianness),
+                        format_value(&watchpoint.value, watchpoint.endianness)
+                    ),
+                }))?;
+                if self.data_breakpoints.contains(&watchpoint.id) {
+                    reason = HaltReason::Watchpoint;
+                    description = Some(format!(
+                        "Data breakpoint on `{}`: {} -> {}",
+                        watchpoint.name,
+                        format_value(&old_value, watchpoint.endianness),
+                        format_value(&watchpoint.value, watchpoint.endianness)
+                    ));
+                }
+            }
+            drop(core);
+
+            // The watch expressions are evaluated at every stop, also the ones they didn't cause
+            match watch_stop {
+                Some(change) => {
+                    reason = HaltReason::Watchpoint;
+                    description = Some(change);
+                }
+                None => {
+                    if let Some(change) = self.watch_break_changes()? {
+                        if hit_breakpoint_ids.is_empty() {
+                            reason = HaltReason::Watchpoint;
+                        }
+                        description = Some(match description {
+                            Some(description) => format!("{}; {}", description, change),
+                            None => change,
+                        });
+                    }
+                }
+            }
+
+            if pause_stop {
+                reason = HaltReason::Request;
+            }
+
+            let kind = match reason {
+                HaltReason::Exception => HistoryKind::Fault,
+                _ => HistoryKind::Stop,
             };
+            self.history.record_stop(
+                kind,
+                format!("Halted at {:#010x}, reason: {:?}", pc, reason),
+                &format!("{:?}", reason).to_lowercase(),
+                &hit_breakpoint_ids,
+            );
 
             if self.trace {
-                drop(core);
-                self.trace_event(pc)
+                self.trace_event(pc, detected)
             } else {
+                // A command list ending with `continue` resumes without reporting the stop
+                if self.run_breakpoint_commands(&hit_breakpoint_ids)? {
+                    self.intrusiveness.record_halt("breakpoint commands", detected);
+                    return Ok(());
+                }
+
                 sender.send(Command::Event(DebugEvent::Halted {
                     pc: pc,
                     reason: reason,
                     hit_breakpoint_ids: Some(hit_breakpoint_ids),
+                    description,
+                    resume: self.resumes.current(),
                 }))?;
+
+                if let Err(err) = self.prefetch_stop_state() {
+                    warn!("Failed to prefetch the stop state: {}", err);
+                }
+
+                if self.heap.stats_on_stop {
+                    let output = match self.heap_stats() {
+                        Ok(stats) => stats.to_string(),
+                        Err(err) => format!("Heap statistics unavailable: {}", err),
+                    };
+                    self.output("console", &output)?;
+                }
                 Ok(())
             }
         } else {
@@ -330,7 +1417,62 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
         }
     }
 
+    /// Handles the request under the watchdog, see `watchdog`.
+    fn handle_watched_request(&mut self, request: DebugRequest) -> Result<Command> {
+        if !is_watched(&request) {
+            return self.handle_request(request);
+        }
+        let name = operation_name(&request);
+        if self.running
+            && !matches!(
+                request,
+                DebugRequest::History
+                    | DebugRequest::StopStats
+                    | DebugRequest::Intrusiveness
+                    | DebugRequest::SetLogLevel { .. }
+                    | DebugRequest::LogTail { .. }
+            )
+        {
+            self.intrusiveness.record_access(&name);
+        }
+        self.watchdog.start(&name, true);
+        let result = self.handle_request(request);
+        if self.watchdog.finish() {
+            return Err(Reopen.into());
+        }
+        result
+    }
+
     fn handle_request(&mut self, request: DebugRequest) -> Result<Command> {
+        // The catch breakpoints give their units to the user breakpoints while these change
+        let moves_breakpoints = matches!(
+            request,
+            DebugRequest::Attach { .. }
+                | DebugRequest::Reset { .. }
+                | DebugRequest::Flash { .. }
+                | DebugRequest::Restart { .. }
+                | DebugRequest::SetBreakpoint { .. }
+                | DebugRequest::SetBreakpoints { .. }
+                | DebugRequest::SetFunctionBreakpoints { .. }
+                | DebugRequest::SetInstructionBreakpoints { .. }
+                | DebugRequest::ClearBreakpoint { .. }
+                | DebugRequest::ClearAllBreakpoints
+                | DebugRequest::LoadBreakpoints { .. }
+                | DebugRequest::VerifyBreakpoints
+        );
+        if !moves_breakpoints {
+            return self.dispatch_request(request);
+        }
+
+        self.lift_catches()?;
+        self.lift_startup_breakpoint()?;
+        let result = self.dispatch_request(request);
+        self.plant_startup_breakpoint()?;
+        self.plant_catches()?;
+        result
+    }
+
+    fn dispatch_request(&mut self, request: DebugRequest) -> Result<Command> {
         match request {
             DebugRequest::Attach {
                 reset,
@@ -338,7 +1480,36 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
             } => self.attach_command(reset, reset_and_halt),
             DebugRequest::Stack => self.stack_command(),
             DebugRequest::Code => self.code_command(),
+            DebugRequest::Disassemble {
+                memory_reference,
+                offset,
+                instruction_offset,
+                instruction_count,
+            } => self.disassemble_command(
+                &memory_reference,
+                offset,
+                instruction_offset,
+                instruction_count,
+            ),
+            DebugRequest::ReadMemory {
+                memory_reference,
+                offset,
+                count,
+            } => self.read_memory_command(&memory_reference, offset, count),
+            DebugRequest::WriteMemory {
+                memory_reference,
+                offset,
+                data,
+                allow_partial,
+            } => self.write_memory_command(&memory_reference, offset, &data, allow_partial),
+            DebugRequest::Source { source_reference } => self.source_command(source_reference),
             DebugRequest::ClearAllBreakpoints => self.clear_all_breakpoints_command(),
+            DebugRequest::SetFunctionBreakpoints { breakpoints } => {
+                self.set_function_breakpoints_command(breakpoints)
+            }
+            DebugRequest::SetInstructionBreakpoints { breakpoints } => {
+                self.set_instruction_breakpoints_command(breakpoints)
+            }
             DebugRequest::ClearBreakpoint { address } => self.clear_breakpoint_command(address),
             DebugRequest::SetBreakpoint {
                 address,
@@ -347,35 +1518,323 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
             DebugRequest::Registers => self.registers_command(),
             DebugRequest::Variable { name } => self.variable_command(&name),
             DebugRequest::Variables => self.variables_command(),
+            DebugRequest::Evaluate {
+                expression,
+                frame_id,
+                cached,
+            } => self.evaluate_command(&expression, frame_id, cached),
+            DebugRequest::Completions { text, frame_id } => {
+                self.completions_command(&text, frame_id)
+            }
+            DebugRequest::SetVariable {
+                variables_reference,
+                name,
+                value,
+            } => self.set_variable_command(variables_reference, &name, &value),
+            DebugRequest::SetExpression {
+                expression,
+                frame_id,
+                value,
+            } => self.set_expression_command(&expression, frame_id, &value),
+            DebugRequest::ExportStopState { path, depth } => self.export_stop_state_command(path, depth),
             DebugRequest::StackTrace => self.stack_trace_command(),
             DebugRequest::Read { address, byte_size } => self.read_command(address, byte_size),
+            DebugRequest::WriteWord {
+                address,
+                value,
+                confirmed,
+            } => self.write_word_command(address, value, confirmed),
+            DebugRequest::WriteRegister { name, value } => self.write_register_command(&name, value),
             DebugRequest::Reset {
                 reset_and_halt: rah,
             } => self.reset_command(rah),
             DebugRequest::Flash {
                 reset_and_halt: rah,
             } => self.flash_command(rah),
+            DebugRequest::Restart { reset_and_halt } => self.restart_command(reset_and_halt),
             DebugRequest::Halt => self.halt_command(),
             DebugRequest::Status => self.status_command(),
             DebugRequest::Continue => self.continue_command(),
+            DebugRequest::ContinueFor { millis } => self.continue_for_command(millis),
+            DebugRequest::Finish => self.finish_command(),
+            DebugRequest::SetPolling {
+                status_interval_ms,
+                low_power,
+            } => self.set_polling_command(status_interval_ms, low_power),
+            DebugRequest::SetHeapOptions {
+                symbol,
+                stats_on_stop,
+            } => {
+                if symbol.is_some() {
+                    self.heap.symbol = symbol;
+                }
+                if let Some(stats_on_stop) = stats_on_stop {
+                    self.heap.stats_on_stop = stats_on_stop;
+                }
+                Ok(Command::Response(DebugResponse::SetHeapOptions))
+            }
+            DebugRequest::SetStepBreakpoints { mode } => {
+                self.step_breakpoints = mode;
+                Ok(Command::Response(DebugResponse::SetStepBreakpoints))
+            }
+            DebugRequest::SetCatchFunctions { functions } => {
+                self.catch_functions = functions;
+                self.resolve_catches()?;
+                Ok(Command::Response(DebugResponse::SetCatchFunctions))
+            }
+            DebugRequest::SetExceptionFilters { filters } => {
+                self.exception_filters = Some(filters);
+                self.resolve_catches()?;
+                Ok(Command::Response(DebugResponse::SetExceptionFilters))
+            }
+            DebugRequest::SetInstructionTrace { length } => {
+                self.instruction_trace = match length {
+                    0 => None,
+                    length => Some(InstructionTrace::new(length)),
+                };
+                Ok(Command::Response(DebugResponse::SetInstructionTrace))
+            }
+            DebugRequest::StepBack => self.step_back_command(),
+            DebugRequest::RestartFrame { frame_id } => self.restart_frame_command(frame_id),
+            DebugRequest::GotoTargets { source_file, line } => {
+                self.goto_targets_command(&source_file, line)
+            }
+            DebugRequest::Goto { address } => self.goto_command(address),
+            DebugRequest::SetLiveFlashReads { enabled } => {
+                self.live_flash_reads = enabled;
+                Ok(Command::Response(DebugResponse::SetLiveFlashReads))
+            }
+            DebugRequest::SetRegionAnnotations { enabled } => {
+                self.regions.enabled = enabled;
+                Ok(Command::Response(DebugResponse::SetRegionAnnotations))
+            }
+            DebugRequest::SetTestRun { enabled } => {
+                self.test_run = match enabled {
+                    true => Some(TestRun::default()),
+                    false => None,
+                };
+                Ok(Command::Response(DebugResponse::SetTestRun))
+            }
+            DebugRequest::SetPeripheralWrites { policy } => {
+                self.peripheral_writes = policy;
+                Ok(Command::Response(DebugResponse::SetPeripheralWrites))
+            }
+            DebugRequest::SetCommandLine { command_line } => {
+                self.command_line = command_line;
+                Ok(Command::Response(DebugResponse::SetCommandLine))
+            }
+            DebugRequest::SetProbeTimeout { timeout_ms } => {
+                self.watchdog.set_deadline(probe_timeout(timeout_ms));
+                Ok(Command::Response(DebugResponse::SetProbeTimeout))
+            }
+            DebugRequest::HeapStats => Ok(Command::Response(DebugResponse::HeapStats {
+                stats: self.heap_stats()?,
+            })),
+            DebugRequest::VectorTableInfo => Ok(Command::Response(DebugResponse::VectorTableInfo {
+                info: self.vector_table_info()?,
+            })),
+            DebugRequest::SectionMap => Ok(Command::Response(DebugResponse::SectionMap {
+                map: self.section_map()?,
+            })),
+            DebugRequest::LoadedSources => Ok(Command::Response(DebugResponse::LoadedSources {
+                sources: self.symbols.source_files(&self.cwd),
+            })),
+            DebugRequest::ExceptionInfo => Ok(Command::Response(DebugResponse::ExceptionInfo {
+                status: self.fault_status()?,
+            })),
+            DebugRequest::History => Ok(Command::Response(DebugResponse::History {
+                entries: self.history.entries(),
+            })),
+            DebugRequest::StopStats => Ok(Command::Response(DebugResponse::StopStats {
+                stats: self.history.stop_stats(),
+            })),
+            DebugRequest::SetLogLevel { module, level } => {
+                Ok(Command::Response(DebugResponse::SetLogLevel {
+                    filter: logger::set_level(module.as_deref(), &level)?,
+                }))
+            }
+            DebugRequest::LogTail { lines } => Ok(Command::Response(DebugResponse::LogTail {
+                lines: logger::tail(lines),
+            })),
+            DebugRequest::Intrusiveness => Ok(Command::Response(DebugResponse::Intrusiveness {
+                report: self.intrusiveness_report(),
+            })),
+            DebugRequest::SelfTest => Ok(Command::Response(DebugResponse::SelfTest {
+                report: self.self_test_command()?,
+            })),
+            DebugRequest::CalibrateClock { interval_ms } => {
+                self.calibrate_clock_command(interval_ms)
+            }
+            DebugRequest::TargetInfo => Ok(Command::Response(DebugResponse::TargetInfo {
+                summary: self.session_summary(self.probe.clone(), None)?,
+            })),
+            DebugRequest::SetCoreClock { hz } => {
+                self.core_clock = Some(CoreClock::user(hz));
+                Ok(Command::Response(DebugResponse::SetCoreClock))
+            }
+            DebugRequest::SetDisconnectAction { action } => {
+                self.disconnect_action = action;
+                Ok(Command::Response(DebugResponse::SetDisconnectAction))
+            }
+            DebugRequest::SetHaltOnReset { enabled } => {
+                self.halt_on_reset = enabled;
+                Ok(Command::Response(DebugResponse::SetHaltOnReset))
+            }
+            DebugRequest::SetCheckFlashLayout { enabled } => {
+                self.check_flash_layout = enabled;
+                Ok(Command::Response(DebugResponse::SetCheckFlashLayout))
+            }
+            DebugRequest::SetFormat { path, format } => {
+                self.formats.set(path, format);
+                self.clear_variables();
+                Ok(Command::Response(DebugResponse::SetFormat))
+            }
+            DebugRequest::Formats => Ok(Command::Response(DebugResponse::Formats {
+                formats: self.formats.list(),
+            })),
+            DebugRequest::ClearFormats => {
+                self.formats.clear();
+                self.format_files.clear();
+                self.clear_variables();
+                Ok(Command::Response(DebugResponse::ClearFormats))
+            }
+            DebugRequest::SaveFormats { path } => {
+                let path = resolve_path(&self.cwd, &path);
+                self.formats.write(&path)?;
+                info!("Formats saved to: {:?}", path);
+                Ok(Command::Response(DebugResponse::SaveFormats))
+            }
+            DebugRequest::LoadFormats { path } => {
+                let path = resolve_path(&self.cwd, &path);
+                let formats = FormatOverrides::read(&path)?;
+                let loaded = formats.list().len();
+                self.formats.extend(formats.clone());
+                self.format_files.retain(|file| file.path != path);
+                self.format_files.push(FormatFile { path, formats });
+                self.clear_variables();
+                Ok(Command::Response(DebugResponse::LoadFormats { loaded }))
+            }
+            DebugRequest::ReloadConfig { what } => self.reload_config_command(what),
+            DebugRequest::Breakpoints => self.breakpoints_command(),
+            DebugRequest::VerifyBreakpoints => Ok(Command::Response(DebugResponse::VerifyBreakpoints {
+                report: self.verify_breakpoints()?,
+            })),
+            DebugRequest::CoreDump { path, regions } => self.core_dump_command(path, regions),
+            DebugRequest::ReadFlash { path, region } => self.read_flash_command(path, region),
+            DebugRequest::SnapshotState { name, regions } => {
+                self.snapshot_state_command(name, regions)
+            }
+            DebugRequest::RestoreState { name } => self.restore_state_command(name),
+            DebugRequest::Snapshots => self.snapshots_command(),
+            DebugRequest::SaveBreakpoints { path } => self.save_breakpoints_command(path),
+            DebugRequest::LoadBreakpoints { path } => self.load_breakpoints_command(path),
+            DebugRequest::BreakpointCommands { id, commands } => {
+                self.breakpoint_commands_command(id, commands)
+            }
+            DebugRequest::IgnoreBreakpoint { id, count } => {
+                self.ignore_breakpoint_command(id, count)
+            }
+            DebugRequest::Until => self.until_command(),
+            DebugRequest::StepInstruction { count, over_calls } => {
+                self.step_instruction_command(count, over_calls)
+            }
             DebugRequest::Step => self.step_command(),
             DebugRequest::SetBreakpoints {
                 source_file,
                 source_breakpoints,
                 source,
             } => self.set_breakpoints_command(source_file, source_breakpoints, source),
-            DebugRequest::DAPStackFrames => self.dap_stack_frames(),
+            DebugRequest::BreakpointLocations {
+                source_file,
+                line,
+                column,
+                end_line,
+                end_column,
+            } => {
+                self.breakpoint_locations_command(&source_file, line, column, end_line, end_column)
+            }
+            DebugRequest::DAPStackFrames {
+                start_frame,
+                levels,
+                format,
+            } => self.dap_stack_frames(start_frame, levels, format),
             DebugRequest::DAPScopes { frame_id } => self.dap_scopes(frame_id),
             DebugRequest::DAPVariables { id } => self.dap_variables(id),
 
+            DebugRequest::SetBinary { path } => self.set_binary_command(path),
+            DebugRequest::SetSymbolFiles { symbol_files } => {
+                self.set_symbol_files_command(symbol_files)
+            }
+            DebugRequest::Modules => {
+                let image = FlashImage::new(&self.symbols, self.session.memory_map());
+                Ok(Command::Response(DebugResponse::Modules {
+                    modules: self.symbols.module_infos(&image),
+                }))
+            }
+            DebugRequest::SetWatchpoint { expression, kind } => {
+                self.set_watchpoint_command(&expression, kind)
+            }
+            DebugRequest::Watchpoints => Ok(Command::Response(DebugResponse::Watchpoints {
+                watchpoints: self.watchpoints.watchpoints.clone(),
+            })),
+            DebugRequest::DeleteWatchpoint { id } => {
+                let mut core = self.session.core(0)?;
+                self.watchpoints.remove(&mut core, id)?;
+                Ok(Command::Response(DebugResponse::DeleteWatchpoint))
+            }
+            DebugRequest::SetWatchBreak { expression } => self.set_watch_break_command(&expression),
+            DebugRequest::WatchBreaks => Ok(Command::Response(DebugResponse::WatchBreaks {
+                watches: self.watch_breaks.watches.clone(),
+                sample_interval_ms: self
+                    .watch_breaks
+                    .sample_interval
+                    .map(|interval| interval.as_millis() as u64),
+            })),
+            DebugRequest::DeleteWatchBreak { id } => {
+                if !self.watch_breaks.remove(id) {
+                    return Err(anyhow!("No watch with id {}", id));
+                }
+                Ok(Command::Response(DebugResponse::DeleteWatchBreak))
+            }
+            DebugRequest::SetWatchSampling { interval_ms } => {
+                self.set_watch_sampling_command(interval_ms)
+            }
+            DebugRequest::DataBreakpointInfo {
+                variables_reference,
+                name,
+            } => self.data_breakpoint_info_command(variables_reference, &name),
+            DebugRequest::SetDataBreakpoints { breakpoints } => {
+                self.set_data_breakpoints_command(breakpoints)
+            }
+            DebugRequest::MemoryUsage => Ok(Command::Response(DebugResponse::MemoryUsage {
+                memory_usage: self.memory_usage()?,
+            })),
+
             DebugRequest::CycleCounter => self.cycle_counter_command(),
             DebugRequest::Trace => self.trace_command(),
+            DebugRequest::TraceConfigure {
+                buffer_address,
+                size,
+            } => self.trace_configure_command(buffer_address, size),
+            DebugRequest::TraceDisable => self.trace_disable_command(),
+            DebugRequest::TraceRead { count } => self.trace_read_command(count),
 
             _ => Ok(Command::Request(request)),
         }
     }
 
     fn attach_command(&mut self, reset: bool, reset_and_halt: bool) -> Result<Command> {
+        if self.session.core_dump().is_some() {
+            return Ok(Command::Response(DebugResponse::Attach));
+        }
+
+        if reset || reset_and_halt {
+            self.catches.rearm();
+            self.reset_hit_conditions();
+            self.enter_startup()?;
+            self.trace_resume("was reset");
+        }
+
         if reset_and_halt {
             self.clear_temporaries();
             let mut core = self.session.core(0)?;
@@ -391,39 +1850,73 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
     }
 
     fn stack_command(&mut self) -> Result<Command> {
+        if let Some(dump) = self.session.core_dump() {
+            let (sf, sp) = match (dump.register(7), dump.register(13)) {
+                (Some(sf), Some(sp)) => (sf, sp),
+                _ => return Err(anyhow!("The core dump has no stack registers")),
+            };
+            let stack = match dump.read_memory(sp, sf.saturating_sub(sp) as usize) {
+                Some(memory) => self.endianness().read_words(&memory),
+                None => vec![],
+            };
+            return Ok(self.stack_response(sp, stack));
+        }
+
+        if let Some(cache) = &self.stop_cache {
+            let (sp, stack) = (cache.stack_pointer, cache.stack.clone());
+            return Ok(self.stack_response(sp, stack));
+        }
+
         let mut core = self.session.core(0)?;
         let status = core.status()?;
 
         if status.is_halted() {
-            let sp_reg: u16 =
-                probe_rs::CoreRegisterAddress::from(core.registers().stack_pointer()).0;
-
-            let sf = core.read_core_reg(7)?; // reg 7 seams to be the base stack address.
-            let sp = core.read_core_reg(sp_reg)?;
-
-            if sf < sp {
-                // The previous stack pointer is less then current.
-                // This happens when there is no stack.
-                return Ok(Command::Response(DebugResponse::Stack {
-                    stack_pointer: sp,
-                    stack: vec![],
-                }));
-            }
-
-            let length = (((sf - sp) + 4 - 1) / 4) as usize;
-            let mut stack = vec![0u32; length];
-            core.read_32(sp, &mut stack)?;
+            let (sp, stack) = read_stack(&mut core)?;
+            drop(core);
 
-            return Ok(Command::Response(DebugResponse::Stack {
-                stack_pointer: sp,
-                stack: stack,
-            }));
+            return Ok(self.stack_response(sp, stack));
         } else {
             return Err(anyhow!("Core must be halted"));
         }
     }
 
+    fn stack_response(&self, stack_pointer: u32, stack: Vec<u32>) -> Command {
+        let regions = stack
+            .iter()
+            .map(|word| self.regions.suffix(*word))
+            .collect();
+        Command::Response(DebugResponse::Stack {
+            stack_pointer,
+            stack,
+            regions,
+        })
+    }
+
     fn code_command(&mut self) -> Result<Command> {
+        if let Some(dump) = self.session.core_dump() {
+            let pc_val = match dump.register(15) {
+                Some(val) => val,
+                None => return Err(anyhow!("The core dump has no program counter")),
+            };
+            let code = match dump.read_memory(pc_val, 16 * 2) {
+                Some(val) => val,
+                None => return Err(anyhow!("The code at the pc was not captured in the core dump")),
+            };
+            let insns = self
+                .capstone
+                .disasm_all(&code, pc_val as u64)
+                .expect("Failed to disassemble");
+
+            let mut instructions = vec![];
+            for i in insns.iter() {
+                instructions.push((i.address() as u32, i.to_string()));
+            }
+            return Ok(Command::Response(DebugResponse::Code {
+                pc: pc_val,
+                instructions,
+            }));
+        }
+
         let mut core = self.session.core(0)?;
         let status = core.status()?;
 
@@ -455,12 +1948,168 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
         }
     }
 
+    fn disassemble_command(
+        &mut self,
+        memory_reference: &str,
+        offset: i64,
+        instruction_offset: i64,
+        instruction_count: usize,
+    ) -> Result<Command> {
+        let address = match parse_u32_from_str(memory_reference.trim()) {
+            Ok(val) => (val as i64 + offset) as u32,
+            Err(_) => return Err(anyhow!("`{}` is not a memory reference", memory_reference)),
+        };
+
+        // Decoding starts at the function of the first requested instruction
+        let before = (-instruction_offset).max(0) as u32 * MAX_INSTRUCTION_SIZE;
+        let first = address.saturating_sub(before);
+        let start = match self.symbols.find_function(first) {
+            Some(symbol) if first - (symbol.address & !1) <= MAX_LOOKBACK => symbol.address & !1,
+            _ => first & !1,
+        };
+        let after = (instruction_offset + instruction_count as i64).max(0) as u32;
+        let end = address.saturating_add(after * MAX_INSTRUCTION_SIZE);
+        let code = self.read_code(start, (end - start) as usize)?;
+        let decoded = decode(&self.capstone, &code, start)?;
+
+        let mut instructions = vec![];
+        let mut previous_file = None;
+        let mut previous_function = None;
+        for (address, insn) in select(&decoded, address, instruction_offset, instruction_count) {
+            let insn = match insn {
+                Some(val) => val,
+                None => {
+                    instructions.push(DisassembledInstruction {
+                        address: format!("{:#010x}", address),
+                        instruction_bytes: None,
+                        instruction: "<unknown>".to_owned(),
+                        symbol: None,
+                        location: None,
+                        line: None,
+                        column: None,
+                    });
+                    continue;
+                }
+            };
+
+            // The function name and the source file are only given where they change
+            let function = self
+                .symbols
+                .find_function(address)
+                .map(|symbol| symbol.demangled);
+            let symbol = match function != previous_function {
+                true => function.clone(),
+                false => None,
+            };
+            previous_function = function;
+
+            let source_info = match self.symbols.module_for_address(address) {
+                Ok(debug_info) => SourceInformation::get_from_address(
+                    &debug_info.dwarf,
+                    debug_info.to_link_address(address) as u64,
+                    &self.cwd,
+                )
+                .ok(),
+                Err(_) => None,
+            };
+            let (location, line, column) = match source_info {
+                Some(source_info) => {
+                    let path = match (&source_info.directory, &source_info.file) {
+                        (Some(dir), Some(file)) => Some(format!("{}/{}", dir, file)),
+                        _ => None,
+                    };
+                    let location = match path.is_some() && path != previous_file {
+                        true => Some(debugserver_types::Source {
+                            name: source_info.file.clone(),
+                            path: path.clone(),
+                            source_reference: None,
+                            presentation_hint: None,
+                            origin: None,
+                            sources: None,
+                            adapter_data: None,
+                            checksums: None,
+                        }),
+                        false => None,
+                    };
+                    previous_file = path;
+                    (
+                        location,
+                        source_info.line.map(|v| v.get() as i64),
+                        source_info.column.map(|v| v.get() as i64),
+                    )
+                }
+                None => (None, None, None),
+            };
+
+            instructions.push(DisassembledInstruction {
+                address: format!("{:#010x}", address),
+                instruction_bytes: Some(
+                    insn.bytes
+                        .iter()
+                        .map(|byte| format!("{:02x}", byte))
+                        .collect::<Vec<String>>()
+                        .join(" "),
+                ),
+                instruction: insn.text.clone(),
+                symbol,
+                location,
+                line,
+                column,
+            });
+        }
+
+        Ok(Command::Response(DebugResponse::Disassemble {
+            instructions,
+        }))
+    }
+
+    /// Reads code from the binary if it is there, else from the core dump or the target.
+    fn read_code(&mut self, address: u32, num_bytes: usize) -> Result<Vec<u8>> {
+        if !self.live_flash_reads {
+            let image = FlashImage::new(&self.symbols, self.session.memory_map());
+            if let Some(code) = image.find(address, num_bytes) {
+                return Ok(code.to_vec());
+            }
+        }
+        if let Some(dump) = self.session.core_dump() {
+            return match dump.read_memory(address, num_bytes) {
+                Some(code) => Ok(code),
+                None => Err(anyhow!(
+                    "The code at {:#010x} was not captured in the core dump",
+                    address
+                )),
+            };
+        }
+
+        let mut core = self.session.core(0)?;
+        let mut code = vec![0; num_bytes];
+        core.read_8(address, &mut code)?;
+        Ok(code)
+    }
+
+    fn shared_ids(&self) -> Vec<u32> {
+        self.shared_breakpoints
+            .values()
+            .flatten()
+            .filter_map(|breakpoint| breakpoint.id)
+            .map(|id| id as u32)
+            .collect()
+    }
+
     fn clear_all_breakpoints_command(&mut self) -> Result<Command> {
         let mut core = self.session.core(0)?;
         core.clear_all_hw_breakpoints()?;
         self.breakpoints = HashMap::new();
+        self.shared_breakpoints = HashMap::new();
+        self.function_breakpoints = HashMap::new();
+        self.instruction_breakpoints = HashMap::new();
+        self.breakpoint_states = HashMap::new();
+        self.disabled_breakpoints = vec![];
+        self.startup.pending.clear();
 
         info!("All breakpoints cleared");
+        self.history
+            .record(HistoryKind::Breakpoint, "Cleared all breakpoints".to_owned());
 
         Ok(Command::Response(DebugResponse::ClearAllBreakpoints))
     }
@@ -470,8 +2119,25 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
 
         match self.breakpoints.remove(&address) {
             Some(_bkpt) => {
-                core.clear_hw_breakpoint(address)?;
+                self.function_breakpoints.remove(&address);
+                self.instruction_breakpoints.remove(&address);
+                self.breakpoint_states.remove(&address);
+                for shared in self.shared_breakpoints.remove(&address).unwrap_or_default() {
+                    if let Some(id) = shared.id {
+                        self.breakpoint_states.remove(&(id as u32));
+                    }
+                }
+                match self.startup.pending.iter().position(|a| *a == address) {
+                    Some(index) => {
+                        self.startup.pending.remove(index);
+                    }
+                    None => core.clear_hw_breakpoint(address)?,
+                };
                 info!("Breakpoint cleared from: 0x{:08x}", address);
+                self.history.record(
+                    HistoryKind::Breakpoint,
+                    format!("Cleared breakpoint at {:#010x}", address),
+                );
                 Ok(Command::Response(DebugResponse::ClearBreakpoint))
             }
             None => {
@@ -488,22 +2154,26 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
     ) -> Result<Command> {
         let mut core = self.session.core(0)?;
         address = match source_file {
-            Some(path) => find_breakpoint_location(
-                self.debug_info.dwarf,
-                &self.cwd,
-                &path,
-                match NonZeroU64::new(address as u64) {
-                    Some(val) => val,
-                    None => {
-                        error!("Could not convert address to NonZeroU64");
-                        return Err(anyhow!("Could not convert address to NonZeroU64"));
-                    }
-                },
-                None,
-            )?
-            .expect("Could not file location form source file line number")
-                as u32,
-            None => address,
+            Some(path) => self
+                .symbols
+                .find_breakpoint_location(
+                    &self.cwd,
+                    &path,
+                    match NonZeroU64::new(address as u64) {
+                        Some(val) => val,
+                        None => {
+                            error!("Could not convert address to NonZeroU64");
+                            return Err(anyhow!("Could not convert address to NonZeroU64"));
+                        }
+                    },
+                    None,
+                )?
+                .expect("Could not file location form source file line number"),
+            None => CodeAddress::new(address).address(),
+        };
+        let address = match self.breakpoint_unit.check(address) {
+            Ok(address) => address,
+            Err(reason) => return Err(anyhow!(reason)),
         };
 
         let num_bkpt = self.breakpoints.len() as u32;
@@ -525,507 +2195,3860 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
             let _bkpt = self.breakpoints.insert(address, breakpoint);
 
             info!("Breakpoint set at: 0x{:08x}", address);
+            self.history.record(
+                HistoryKind::Breakpoint,
+                format!("Set breakpoint at {:#010x}", address),
+            );
             return Ok(Command::Response(DebugResponse::SetBreakpoint));
         } else {
             return Err(anyhow!("All hardware breakpoints are already set"));
         }
     }
 
-    fn registers_command(&mut self) -> Result<Command> {
-        let mut core = self.session.core(0)?;
-        let register_file = core.registers();
+    fn breakpoints_command(&mut self) -> Result<Command> {
+        let mut breakpoints: Vec<(u32, Breakpoint, BreakpointState)> = vec![];
+        for address in self.breakpoints.keys() {
+            for breakpoint in breakpoints_at(&self.breakpoints, &self.shared_breakpoints, *address) {
+                let id = breakpoint.id.unwrap_or(*address as i64) as u32;
+                let state = self.breakpoint_states.get(&id).cloned().unwrap_or_default();
+                let mut breakpoint = state.annotate(breakpoint);
+                if self.startup.pending.contains(address) {
+                    breakpoint.verified = false;
+                    breakpoint.message = Some(PENDING_MESSAGE.to_owned());
+                }
+                breakpoints.push((*address, breakpoint, state));
+            }
+        }
+        breakpoints.sort_by_key(|(address, _, _)| *address);
 
-        let mut registers = vec![];
-        for register in register_file.registers() {
-            let value = core.read_core_reg(register)?;
+        Ok(Command::Response(DebugResponse::Breakpoints {
+            breakpoints,
+            disabled: self.disabled_breakpoints.clone(),
+        }))
+    }
 
-            registers.push((format!("{}", register.name()), value));
+    /// Resolves every breakpoint again against the current binary, moves the ones whose address
+    /// changed and disables the ones that no longer resolve.
+    fn verify_breakpoints(&mut self) -> Result<Vec<VerifyReport>> {
+        let mut report: Vec<VerifyReport> = self
+            .disabled_breakpoints
+            .iter()
+            .map(|saved| VerifyReport {
+                location: saved.location(),
+                address: None,
+                status: VerifyStatus::Disabled,
+            })
+            .collect();
+
+        if self.breakpoints.is_empty() {
+            return Ok(report);
         }
 
-        Ok(Command::Response(DebugResponse::Registers { registers }))
-    }
+        let mut current = vec![];
+        for address in self.breakpoints.keys() {
+            for breakpoint in breakpoints_at(&self.breakpoints, &self.shared_breakpoints, *address) {
+                current.push((*address, breakpoint));
+            }
+        }
+        current.sort_by_key(|(address, _)| *address);
+        let resolved: Vec<(u32, Breakpoint, Result<u32, String>)> = current
+            .into_iter()
+            .map(|(address, breakpoint)| {
+                let result = self
+                    .resolve_breakpoint(address, &breakpoint)
+                    .and_then(|address| self.breakpoint_unit.check(address));
+                (address, breakpoint, result)
+            })
+            .collect();
 
-    fn variable_command(&mut self, name: &str) -> Result<Command> {
         let mut core = self.session.core(0)?;
-        let status = core.status()?;
-        drop(core);
+        core.clear_all_hw_breakpoints()?;
+        let num_units = core.get_available_breakpoint_units()? as usize;
+        self.breakpoints = HashMap::new();
+        self.shared_breakpoints = HashMap::new();
 
-        match status.is_halted() {
-            true => match &self.stack_trace {
-                Some(stack_trace) => {
-                    if stack_trace.len() < 1 {
-                        return Err(anyhow!("Variable {:?} not found", name));
-                    }
-                    let variable = match stack_trace[0].find_variable(name) {
-                        Some(var) => var.clone(),
-                        None => {
-                            return Ok(Command::Response(DebugResponse::Error {
-                                message: format!("Variable {:?} not found", name),
-                            }))
-                        }
-                    };
+        for (old_address, old_breakpoint, result) in resolved {
+            let old_id = old_breakpoint.id.unwrap_or(old_address as i64) as u32;
+            let state = self.breakpoint_states.remove(&old_id).unwrap_or_default();
+            let saved = SavedBreakpoint::new(old_address, &old_breakpoint, &state);
 
-                    Ok(Command::Response(DebugResponse::Variable { variable }))
+            let address = match result {
+                Ok(address)
+                    if self.breakpoints.contains_key(&address)
+                        || self.breakpoints.len() < num_units =>
+                {
+                    address
                 }
-                None => {
-                    self.set_stack_trace()?;
-                    self.set_stack_frames()?;
-                    self.variable_command(name)
+                result => {
+                    let reason = match result {
+                        Err(reason) => reason,
+                        Ok(_) => "no hardware breakpoint unit left".to_owned(),
+                    };
+                    let mut breakpoint = old_breakpoint;
+                    breakpoint.verified = false;
+                    breakpoint.message = Some(reason.clone());
+                    self.sender.send(Command::Event(DebugEvent::Breakpoint {
+                        reason: "changed".to_owned(),
+                        breakpoint,
+                    }))?;
+
+                    report.push(VerifyReport {
+                        location: saved.location(),
+                        address: None,
+                        status: VerifyStatus::Unresolved { reason },
+                    });
+                    self.disabled_breakpoints.push(SavedBreakpoint {
+                        enabled: false,
+                        ..saved
+                    });
+                    continue;
                 }
-            },
-            false => Err(anyhow!("Core must be halted")),
-        }
-    }
+            };
 
-    fn variables_command(&mut self) -> Result<Command> {
-        let mut core = self.session.core(0)?;
-        let status = core.status()?;
-        drop(core);
+            let mut breakpoint = old_breakpoint.clone();
+            let id = if self.breakpoints.contains_key(&address) {
+                let id = self.next_shared_id;
+                self.next_shared_id -= 1;
+                breakpoint.id = Some(id as i64);
+                self.shared_breakpoints
+                    .entry(address)
+                    .or_default()
+                    .push(breakpoint.clone());
+                id
+            } else {
+                core.set_hw_breakpoint(address)?;
+                breakpoint.id = Some(address as i64);
+                self.breakpoints.insert(address, breakpoint.clone());
+                address
+            };
+            let breakpoint = state.annotate(breakpoint);
+            self.breakpoint_states.insert(id, state);
+
+            // The id of a breakpoint is its address, the client sees a moved breakpoint as a new one
+            if id != old_id {
+                self.sender.send(Command::Event(DebugEvent::Breakpoint {
+                    reason: "removed".to_owned(),
+                    breakpoint: old_breakpoint,
+                }))?;
+                self.sender.send(Command::Event(DebugEvent::Breakpoint {
+                    reason: "new".to_owned(),
+                    breakpoint,
+                }))?;
+            }
 
-        match status.is_halted() {
-            true => match &self.stack_trace {
-                Some(stack_trace) => {
-                    let variables = match stack_trace.len() {
-                        0 => vec![],
-                        _ => stack_trace[0].variables.clone(),
-                    };
+            report.push(VerifyReport {
+                location: saved.location(),
+                address: Some(address),
+                status: if address == old_address {
+                    VerifyStatus::Bound
+                } else {
+                    VerifyStatus::Moved { from: old_address }
+                },
+            });
+        }
 
-                    Ok(Command::Response(DebugResponse::Variables {
-                        variables: variables,
-                    }))
+        Ok(report)
+    }
+
+    /// Finds the address of a breakpoint in the current binary, or why it can't be found.
+    fn resolve_breakpoint(&self, address: u32, breakpoint: &Breakpoint) -> Result<u32, String> {
+        let path = breakpoint.source.as_ref().and_then(|source| source.path.clone());
+        match (path, breakpoint.line) {
+            (Some(path), Some(line)) => {
+                let line = match NonZeroU64::new(line as u64) {
+                    Some(val) => val,
+                    None => return Err(format!("invalid line {}", line)),
+                };
+                let column = breakpoint.column.and_then(|c| NonZeroU64::new(c as u64));
+                match self
+                    .symbols
+                    .find_breakpoint_location(&self.cwd, &path, line, column)
+                {
+                    Ok(Some(address)) => Ok(address),
+                    Ok(None) => match self.symbols.has_source_file(&path) {
+                        Ok(true) => Err("the line has no code".to_owned()),
+                        Ok(false) => Err("the file is not in the binary".to_owned()),
+                        Err(err) => Err(err.to_string()),
+                    },
+                    Err(err) => Err(err.to_string()),
                 }
-                None => {
-                    self.set_stack_trace()?;
-                    self.set_stack_frames()?;
-                    self.variables_command()
+            }
+            _ => {
+                if self.symbols.is_empty()
+                    || self.symbols.modules.iter().any(|m| m.contains(address))
+                {
+                    Ok(address)
+                } else {
+                    Err("the address is not in the code of the binary".to_owned())
                 }
-            },
-            false => Err(anyhow!("Core must be halted")),
+            }
         }
     }
 
-    fn stack_trace_command(&mut self) -> Result<Command> {
-        match &self.stack_trace {
-            Some(stack_trace) => Ok(Command::Response(DebugResponse::StackTrace {
-                stack_trace: stack_trace.clone(),
-            })),
-            None => {
-                self.set_stack_trace()?;
-                self.set_stack_frames()?;
-                self.stack_trace_command()
-            }
+    /// Verifies the breakpoints and prints the report, used after the binary changed.
+    fn report_breakpoints(&mut self) -> Result<()> {
+        let report = self.verify_breakpoints()?;
+        if report.is_empty() {
+            return Ok(());
+        }
+
+        let mut output = "Breakpoints:".to_owned();
+        for entry in &report {
+            output = format!("{}\n    {}", output, entry);
         }
+        self.output("console", &output)
     }
 
-    fn read_command(&mut self, address: u32, byte_size: usize) -> Result<Command> {
-        let mut core = self.session.core(0)?;
-        let mut buff: Vec<u8> = vec![0; byte_size];
-        core.read_8(address, &mut buff)?;
+    fn save_breakpoints_command(&mut self, path: PathBuf) -> Result<Command> {
+        let path = resolve_path(&self.cwd, &path);
+        let mut breakpoints: Vec<SavedBreakpoint> = vec![];
+        for address in self.breakpoints.keys() {
+            for breakpoint in breakpoints_at(&self.breakpoints, &self.shared_breakpoints, *address) {
+                let id = breakpoint.id.unwrap_or(*address as i64) as u32;
+                let state = self.breakpoint_states.get(&id).cloned().unwrap_or_default();
+                breakpoints.push(SavedBreakpoint::new(*address, &breakpoint, &state));
+            }
+        }
+        breakpoints.sort_by_key(|saved| saved.address);
+        breakpoints.extend(self.disabled_breakpoints.iter().cloned());
 
-        Ok(Command::Response(DebugResponse::Read {
-            address: address,
-            value: buff,
-        }))
+        let file = BreakpointFile {
+            breakpoints,
+            watchpoints: self
+                .watchpoints
+                .watchpoints
+                .iter()
+                // The DAP client restores its data breakpoints itself
+                .filter(|watchpoint| !self.data_breakpoints.contains(&watchpoint.id))
+                .map(|watchpoint| SavedWatchpoint {
+                    expression: watchpoint.name.clone(),
+                    kind: watchpoint.kind,
+                })
+                .collect(),
+        };
+        file.write(&path)?;
+        info!("Breakpoints saved to: {:?}", path);
+
+        Ok(Command::Response(DebugResponse::SaveBreakpoints))
     }
 
-    fn reset_command(&mut self, reset_and_halt: bool) -> Result<Command> {
-        if reset_and_halt {
-            self.clear_temporaries();
+    fn load_breakpoints_command(&mut self, path: PathBuf) -> Result<Command> {
+        let path = resolve_path(&self.cwd, &path);
+        let file = BreakpointFile::read(&path)?;
 
-            let mut core = self.session.core(0)?;
-            core.reset_and_halt(std::time::Duration::from_millis(10))
-                .context("Failed to reset and halt the core")?;
-        } else {
-            self.clear_temporaries();
+        let mut core = self.session.core(0)?;
+        let num_units = core.get_available_breakpoint_units()? as usize;
+
+        let mut loaded = 0;
+        let mut disabled = vec![];
+        for mut saved in file.breakpoints {
+            if !saved.enabled {
+                self.disabled_breakpoints.push(saved);
+                continue;
+            }
 
-            let mut core = self.session.core(0)?;
-            core.reset().context("Failed to reset the core")?;
+            // Re-verify the location against the current binary
+            let address = match (&saved.source, saved.line) {
+                (Some(source), Some(line)) => match NonZeroU64::new(line as u64) {
+                    Some(line) => self
+                        .symbols
+                        .find_breakpoint_location(
+                            &self.cwd,
+                            source,
+                            line,
+                            saved.column.and_then(|c| NonZeroU64::new(c as u64)),
+                        )
+                        .unwrap_or(None),
+                    None => None,
+                },
+                _ => {
+                    if self.symbols.is_empty()
+                        || self.symbols.modules.iter().any(|m| m.contains(saved.address))
+                    {
+                        Some(saved.address)
+                    } else {
+                        None
+                    }
+                }
+            };
+            let address = match address {
+                Some(val)
+                    if self.breakpoints.len() < num_units && self.breakpoint_unit.supports(val) =>
+                {
+                    val
+                }
+                _ => {
+                    disabled.push(saved.location());
+                    saved.enabled = false;
+                    self.disabled_breakpoints.push(saved);
+                    continue;
+                }
+            };
+            if self.breakpoints.contains_key(&address) {
+                continue;
+            }
+
+            core.set_hw_breakpoint(address)?;
+            let breakpoint = Breakpoint {
+                id: Some(address as i64),
+                verified: true,
+                message: None,
+                source: saved.source.as_ref().map(|path| debugserver_types::Source {
+                    name: PathBuf::from(path)
+                        .file_name()
+                        .map(|name| name.to_string_lossy().to_string()),
+                    path: Some(path.clone()),
+                    source_reference: None,
+                    presentation_hint: None,
+                    origin: None,
+                    sources: None,
+                    adapter_data: None,
+                    checksums: None,
+                }),
+                line: saved.line,
+                column: saved.column,
+                end_line: None,
+                end_column: None,
+            };
+            let condition = match saved.condition.as_deref().map(Condition::parse) {
+                Some(Ok(condition)) => Some(condition),
+                Some(Err(err)) => {
+                    warn!("Dropped the condition of {}: {}", saved.location(), err);
+                    None
+                }
+                None => None,
+            };
+            let hit_condition = match saved.hit_condition.as_deref().map(HitCondition::parse) {
+                Some(Ok(hit_condition)) => Some(hit_condition),
+                Some(Err(err)) => {
+                    warn!("Dropped the hit condition of {}: {}", saved.location(), err);
+                    None
+                }
+                None => None,
+            };
+            let log_message = match saved.log_message.as_deref().map(LogMessage::parse) {
+                Some(Ok(log_message)) => Some(log_message),
+                Some(Err(err)) => {
+                    warn!("Dropped the log message of {}: {}", saved.location(), err);
+                    None
+                }
+                None => None,
+            };
+            let state = BreakpointState {
+                ignore_count: saved.ignore_count,
+                commands: saved.commands.clone(),
+                condition,
+                hit_condition,
+                log_message,
+                ..Default::default()
+            };
+            let breakpoint = state.annotate(breakpoint);
+            self.breakpoints.insert(address, breakpoint.clone());
+            self.breakpoint_states.insert(address, state);
+            loaded += 1;
+
+            self.sender.send(Command::Event(DebugEvent::Breakpoint {
+                reason: "new".to_owned(),
+                breakpoint,
+            }))?;
         }
+        drop(core);
 
-        self.running = true;
+        for saved in file.watchpoints {
+            if let Err(err) = self.set_watchpoint_command(&saved.expression, saved.kind) {
+                warn!("Failed to set watchpoint `{}`: {}", saved.expression, err);
+                disabled.push(saved.expression);
+            }
+        }
 
-        Ok(Command::Response(DebugResponse::Reset))
+        if !disabled.is_empty() {
+            let message = format!(
+                "These breakpoints no longer resolve and were loaded as disabled: {}",
+                disabled.join(", ")
+            );
+            warn!("{}", message);
+            self.output("console", &message)?;
+        }
+        info!("Loaded {} breakpoints from: {:?}", loaded, path);
+        self.history.record(
+            HistoryKind::Breakpoint,
+            format!("Loaded {} breakpoints from {:?}", loaded, path),
+        );
+
+        Ok(Command::Response(DebugResponse::LoadBreakpoints { loaded, disabled }))
     }
 
-    fn flash_command(&mut self, reset_and_halt: bool) -> Result<Command> {
-        download_file(&mut self.session, &self.file_path, Format::Elf)
-            .context("Failed to flash target")?;
+    fn find_breakpoint(&self, id: u32) -> Result<Breakpoint> {
+        // Breakpoint ids are the breakpoint addresses, except for the shared breakpoints
+        let shared = self
+            .shared_breakpoints
+            .values()
+            .flatten()
+            .find(|breakpoint| breakpoint.id == Some(id as i64));
+        match self.breakpoints.get(&id).or(shared) {
+            Some(val) => Ok(val.clone()),
+            None => Err(anyhow!("No breakpoint with id {:#010x}", id)),
+        }
+    }
 
-        if reset_and_halt {
-            self.clear_temporaries();
+    fn ignore_breakpoint_command(&mut self, id: u32, count: u32) -> Result<Command> {
+        let breakpoint = self.find_breakpoint(id)?;
+
+        let state = self.breakpoint_states.entry(id).or_default();
+        state.ignore_count = count;
+        let breakpoint = state.annotate(breakpoint);
+        info!("Ignoring the next {} hits of breakpoint 0x{:08x}", count, id);
+        self.history.record(
+            HistoryKind::Breakpoint,
+            format!("Ignoring the next {} hits of breakpoint {:#010x}", count, id),
+        );
+
+        self.sender
+            .send(Command::Event(DebugEvent::Breakpoint {
+                        reason: "changed".to_owned(),
+                        breakpoint,
+                    }))?;
 
-            let mut core = self.session.core(0)?;
-            core.reset_and_halt(std::time::Duration::from_millis(10))
-                .context("Failed to reset and halt the core")?;
-        } else {
-            self.clear_temporaries();
+        Ok(Command::Response(DebugResponse::IgnoreBreakpoint))
+    }
 
-            let mut core = self.session.core(0)?;
-            core.reset().context("Failed to reset the core")?;
+    /// Sets the monitor commands run when the breakpoint stops the core, an empty list removes
+    /// them. Only the last command can be `continue`.
+    fn breakpoint_commands_command(&mut self, id: u32, commands: Vec<String>) -> Result<Command> {
+        self.find_breakpoint(id)?;
+        let parser = Commands::new();
+        for (i, command) in commands.iter().enumerate() {
+            if command == "continue" {
+                if i + 1 < commands.len() {
+                    return Err(anyhow!("`continue` can only be the last command"));
+                }
+                continue;
+            }
+            parser.parse_command(command)?;
         }
 
-        self.running = true;
+        info!("Breakpoint 0x{:08x} runs {:?}", id, commands);
+        self.history.record(
+            HistoryKind::Breakpoint,
+            format!("Breakpoint {:#010x} runs {} commands", id, commands.len()),
+        );
+        self.breakpoint_states.entry(id).or_default().commands = commands;
 
-        Ok(Command::Response(DebugResponse::Flash))
+        Ok(Command::Response(DebugResponse::BreakpointCommands))
     }
 
-    fn halt_command(&mut self) -> Result<Command> {
-        let mut core = self.session.core(0)?;
-        let status = core.status()?;
+    /// The hit conditions count again from the restart of the program.
+    fn reset_hit_conditions(&mut self) {
+        for hit_condition in self
+            .breakpoint_states
+            .values_mut()
+            .filter_map(|state| state.hit_condition.as_mut())
+        {
+            hit_condition.reset();
+        }
+    }
 
-        if status.is_halted() {
-            warn!("Core is already halted, status: {:?}", status);
-            return Err(anyhow!("Core is already halted"));
-        } else {
-            let cpu_info = core.halt(Duration::from_millis(100))?;
-            info!("Core halted at pc = 0x{:08x}", cpu_info.pc);
-        };
+    /// Holds back the breakpoints in RAM functions on a reset, until `main` is reached.
+    fn enter_startup(&mut self) -> Result<()> {
+        let ranges = ram_code_ranges(&self.symbols, self.session.memory_map());
+        if ranges.is_empty() || main_address(&self.symbols).is_none() {
+            return Ok(());
+        }
+        self.startup.in_startup = true;
+
+        let held_back: Vec<u32> = self
+            .breakpoints
+            .keys()
+            .filter(|address| ranges.iter().any(|range| range.contains(address)))
+            .filter(|address| !self.startup.pending.contains(address))
+            .cloned()
+            .collect();
+        let mut core = self.session.core(0)?;
+        for address in held_back {
+            core.clear_hw_breakpoint(address)?;
+            self.startup.pending.push(address);
+            for mut breakpoint in breakpoints_at(&self.breakpoints, &self.shared_breakpoints, address) {
+                breakpoint.verified = false;
+                breakpoint.message = Some(PENDING_MESSAGE.to_owned());
+                self.sender.send(Command::Event(DebugEvent::Breakpoint {
+                    reason: "changed".to_owned(),
+                    breakpoint,
+                }))?;
+            }
+        }
+        drop(core);
 
-        Ok(Command::Response(DebugResponse::Halt))
+        // Planted before the reset, the program may reach main before the request returns
+        self.plant_startup_breakpoint()
     }
 
-    fn status_command(&mut self) -> Result<Command> {
+    /// Plants the held back breakpoints once the startup code has run.
+    fn startup_done(&mut self) -> Result<()> {
+        self.lift_startup_breakpoint()?;
+        self.startup.in_startup = false;
+
         let mut core = self.session.core(0)?;
-        let status = core.status()?;
-        let mut pc = None;
+        for address in std::mem::take(&mut self.startup.pending) {
+            core.set_hw_breakpoint(address)?;
+            for breakpoint in breakpoints_at(&self.breakpoints, &self.shared_breakpoints, address) {
+                let id = breakpoint.id.unwrap_or(address as i64) as u32;
+                let breakpoint = match self.breakpoint_states.get(&id) {
+                    Some(state) => state.annotate(breakpoint),
+                    None => breakpoint,
+                };
+                self.sender.send(Command::Event(DebugEvent::Breakpoint {
+                    reason: "changed".to_owned(),
+                    breakpoint,
+                }))?;
+            }
+        }
+        Ok(())
+    }
 
-        if status.is_halted() {
-            pc = Some(core.read_core_reg(core.registers().program_counter())?);
+    /// The temporary breakpoint at `main` that ends the startup.
+    fn plant_startup_breakpoint(&mut self) -> Result<()> {
+        if !self.startup.in_startup || self.startup.main.is_some() {
+            return Ok(());
+        }
+        let main = match main_address(&self.symbols) {
+            Some(val) => val,
+            None => return Ok(()),
+        };
+        if !self.breakpoints.contains_key(&main) {
+            self.session.core(0)?.set_hw_breakpoint(main)?;
         }
+        self.startup.main = Some(main);
+        Ok(())
+    }
 
-        Ok(Command::Response(DebugResponse::Status {
-            status: status,
-            pc: pc,
-        }))
+    fn lift_startup_breakpoint(&mut self) -> Result<()> {
+        if let Some(main) = self.startup.main.take() {
+            if !self.breakpoints.contains_key(&main) {
+                self.session.core(0)?.clear_hw_breakpoint(main)?;
+            }
+        }
+        Ok(())
     }
 
-    fn step_command(&mut self) -> Result<Command> {
-        let mut core = self.session.core(0)?;
-        let status = core.status()?;
+    /// Evaluates the watch expressions at a halt, returns the description of the changes.
+    fn watch_break_changes(&mut self) -> Result<Option<String>> {
+        if self.watch_breaks.is_empty() {
+            return Ok(None);
+        }
+        let pc = {
+            let mut core = self.session.core(0)?;
+            core.read_core_reg(core.registers().program_counter())?
+        };
+        let mut changes = vec![];
+        for index in 0..self.watch_breaks.watches.len() {
+            let condition = self.watch_breaks.watches[index].condition.clone();
+            let value = match self.expression_value(&condition) {
+                Ok(val) => val,
+                Err(err) => {
+                    debug!("Watch `{}` not evaluated: {}", condition, err);
+                    continue;
+                }
+            };
+            if let Some(change) = self.watch_breaks.watches[index].update(value, pc) {
+                changes.push(change);
+            }
+        }
+        match changes.is_empty() {
+            true => Ok(None),
+            false => Ok(Some(changes.join("; "))),
+        }
+    }
 
-        if status.is_halted() {
-            let pc = continue_fix(&mut core, &self.breakpoints)?;
-            self.running = true;
-            info!("Stopped at pc = 0x{:08x}", pc);
+    /// The value of a watch or logpoint expression, see `watch_break`.
+    fn expression_value(&mut self, condition: &Condition) -> Result<String> {
+        match condition.variable() {
+            Some(path) => {
+                let (_, variable) = self.evaluate_variable(path, None, false)?;
+                Ok(full_value(&variable))
+            }
+            None => {
+                let result = condition.evaluate(&mut |path| self.condition_value(path));
+                Ok(result?.to_string())
+            }
+        }
+    }
 
+    /// Halts the running core to evaluate the watch expressions, it is resumed unless one of
+    /// them changed.
+    fn sample_watch_breaks(&mut self, sender: &mut Sender<Command>) -> Result<()> {
+        let mut core = self.session.core(0)?;
+        if core.core_halted()? {
+            // Halted by itself, reported as any other stop
             drop(core);
+            return self.process_halt_event(sender);
+        }
+        core.halt(Duration::from_millis(100))?;
+        let detected = Instant::now();
+        let pc = core.read_core_reg(core.registers().program_counter())?;
+        drop(core);
 
-            self.clear_temporaries();
-            return Ok(Command::Response(DebugResponse::Step));
+        let description = match self.watch_break_changes()? {
+            Some(val) => val,
+            None => {
+                self.clear_temporaries();
+                let mut core = self.session.core(0)?;
+                continue_fix(&mut core, &self.breakpoints)?;
+                core.run()?;
+                self.intrusiveness.record_halt("watch sampling", detected);
+                return Ok(());
+            }
+        };
+
+        self.running = false;
+        self.history.record_stop(
+            HistoryKind::Stop,
+            format!("Halted at {:#010x}, {}", pc, description),
+            "watch break",
+            &[],
+        );
+        sender.send(Command::Event(DebugEvent::Halted {
+            pc,
+            reason: HaltReason::Watchpoint,
+            hit_breakpoint_ids: None,
+            description: Some(description),
+            resume: self.resumes.current(),
+        }))?;
+        if let Err(err) = self.prefetch_stop_state() {
+            warn!("Failed to prefetch the stop state: {}", err);
         }
+        Ok(())
+    }
 
-        Ok(Command::Response(DebugResponse::Error {
-            message: "Can only step when core is halted".to_owned(),
-        }))
+    fn set_watch_break_command(&mut self, expression: &str) -> Result<Command> {
+        let condition = Condition::parse(expression)?;
+        // The value to compare the first evaluation with
+        let value = match self.is_halted()? {
+            true => self.expression_value(&condition).ok(),
+            false => None,
+        };
+        let watch = self.watch_breaks.add(condition);
+        watch.value = value;
+        let watch = watch.clone();
+        info!("Watch {} set on `{}`", watch.id, watch.expression);
+        self.history.record(
+            HistoryKind::Breakpoint,
+            format!("Set watch {} on `{}`", watch.id, watch.expression),
+        );
+
+        Ok(Command::Response(DebugResponse::SetWatchBreak { watch }))
     }
 
-    fn continue_command(&mut self) -> Result<Command> {
-        let mut core = self.session.core(0)?;
-        let mut status = core.status()?;
+    fn set_watch_sampling_command(&mut self, interval_ms: Option<u64>) -> Result<Command> {
+        self.watch_breaks.sample_interval =
+            interval_ms.filter(|ms| *ms > 0).map(Duration::from_millis);
+        let warning = self.watch_breaks.sample_interval.map(|interval| {
+            format!(
+                "The core is halted every {} ms while it runs to evaluate the watch expressions, each sample stops the firmware for the stack unwinding and memory reads",
+                interval.as_millis()
+            )
+        });
+
+        Ok(Command::Response(DebugResponse::SetWatchSampling { warning }))
+    }
 
-        if status.is_halted() {
-            let _pc = continue_fix(&mut core, &self.breakpoints)?;
-            core.run()?;
-            self.running = true;
-            status = core.status()?;
+    /// Evaluates the conditions of the hit breakpoints, returns the ids of the ones that are false.
+    /// A condition that can't be evaluated stops the core like a true one, with the error on the
+    /// console.
+    fn unmet_conditions(&mut self, conditions: Vec<(u32, Condition)>) -> Result<Vec<u32>> {
+        let mut unmet = vec![];
+        for (id, condition) in conditions {
+            let result = condition.evaluate(&mut |path| self.condition_value(path));
+            match result {
+                Ok(true) => (),
+                Ok(false) => unmet.push(id),
+                Err(err) => self.output(
+                    "console",
+                    &format!(
+                        "Breakpoint {:#010x} stopped, its condition `{}` failed: {}",
+                        id, condition, err
+                    ),
+                )?,
+            }
+        }
+        Ok(unmet)
+    }
 
-            drop(core);
+    /// The value of a variable in a breakpoint condition, pointers are compared by address.
+    fn condition_value(&mut self, path: &str) -> Result<Value> {
+        let (_, variable) = self.evaluate_variable(path, None, false)?;
+        if let Some(value) = Value::parse(&variable.value) {
+            return Ok(value);
+        }
+        let pointer = variable.type_.starts_with('*') || variable.type_.starts_with('&');
+        match (pointer, variable.location) {
+            (true, Some(VariableLocation::Memory { address, byte_size })) if byte_size <= 8 => {
+                let mut bytes = vec![0u8; byte_size];
+                self.session.core(0)?.read_8(address, &mut bytes)?;
+                match self.endianness().read_unsigned(&bytes) {
+                    Some(value) => Ok(Value::Int(value as i128)),
+                    None => Err(anyhow!("`{}` has no value", path)),
+                }
+            }
+            (true, Some(VariableLocation::Register { register, .. })) => {
+                let value = self
+                    .session
+                    .core(0)?
+                    .read_core_reg(probe_rs::CoreRegisterAddress(register))?;
+                Ok(Value::Int(value as i128))
+            }
+            _ => Err(anyhow!("`{}` is not a number: {}", path, variable.value)),
+        }
+    }
 
-            self.clear_temporaries();
+    /// Runs the commands of the breakpoints that stopped the core, their output goes to the
+    /// console. Returns whether every list with commands ended with `continue` and the core was
+    /// resumed. A failed command ends its list and leaves the core halted.
+    fn run_breakpoint_commands(&mut self, ids: &[u32]) -> Result<bool> {
+        let lists: Vec<Vec<String>> = ids
+            .iter()
+            .filter_map(|id| self.breakpoint_states.get(id))
+            .filter(|state| !state.commands.is_empty())
+            .map(|state| state.commands.clone())
+            .collect();
+        if lists.is_empty() {
+            return Ok(false);
         }
 
-        info!("Core status: {:?}", status);
+        let parser = Commands::new();
+        let mut resume = true;
+        for commands in lists {
+            for command in &commands {
+                if command == "continue" {
+                    break;
+                }
+                self.watchdog.heartbeat();
+                let output = match parser.parse_command(command) {
+                    Ok(Command::Request(request)) => match self.handle_request(request) {
+                        Ok(Command::Response(response)) => Ok(response.text()),
+                        Ok(_) => Ok(String::new()),
+                        Err(err) => Err(err),
+                    },
+                    Ok(_) => Err(anyhow!("Not a request")),
+                    Err(err) => Err(err),
+                };
+                match output {
+                    Ok(output) => self.output("console", &format!("> {}\n{}", command, output))?,
+                    Err(err) => {
+                        self.output(
+                            "console",
+                            &format!("> {}\nBreakpoint command failed: {}", command, err),
+                        )?;
+                        return Ok(false);
+                    }
+                }
+            }
+            resume &= commands.last().map(String::as_str) == Some("continue");
+        }
 
-        Ok(Command::Response(DebugResponse::Continue))
+        if resume {
+            self.continue_command()?;
+        }
+        Ok(resume)
     }
 
-    fn set_breakpoints_command(
+    fn set_watchpoint_command(&mut self, expression: &str, kind: WatchKind) -> Result<Command> {
+        require_cortex_m(self.arch, "Watchpoints")?;
+        let (address, size) = self.resolve_watch_expression(expression)?;
+        let endianness = self.endianness();
+
+        let mut core = self.session.core(0)?;
+        let watchpoint = self.watchpoints.add(
+            &mut core,
+            expression.to_owned(),
+            address,
+            size,
+            kind,
+            endianness,
+        )?;
+        info!(
+            "Watchpoint {} set at: 0x{:08x}",
+            watchpoint.id, watchpoint.address
+        );
+
+        Ok(Command::Response(DebugResponse::SetWatchpoint { watchpoint }))
+    }
+
+    /// Whether a child of the variables reference, or an expression like for `watch` without a
+    /// reference, can be watched. Register allocated variables have no address to watch.
+    fn data_breakpoint_info_command(
         &mut self,
-        source_file: String,
-        source_breakpoints: Vec<SourceBreakpoint>,
-        source: Option<debugserver_types::Source>,
+        variables_reference: Option<i64>,
+        name: &str,
     ) -> Result<Command> {
-        // Clear all existing breakpoints
+        let location = match variables_reference {
+            Some(reference) => match self.find_child_variable(reference, name)?.location {
+                Some(VariableLocation::Memory { address, byte_size }) => {
+                    Ok((address, byte_size as u32))
+                }
+                Some(VariableLocation::Register { .. }) => Err(anyhow!(
+                    "{} is stored in a register and can't be watched",
+                    name
+                )),
+                None => Err(anyhow!("{} doesn't have a known address", name)),
+            },
+            None => self.resolve_watch_expression(name),
+        };
+
+        let response = match location.and_then(|(address, size)| {
+            check_range(address, size)?;
+            Ok((address, size))
+        }) {
+            Ok((address, size)) => DebugResponse::DataBreakpointInfo {
+                data_id: Some(data_id(address, size, name)),
+                description: format!("{} ({} bytes at {:#010x})", name, size, address),
+                access_types: vec![WatchKind::Read, WatchKind::Write, WatchKind::ReadWrite],
+            },
+            Err(err) => DebugResponse::DataBreakpointInfo {
+                data_id: None,
+                description: err.to_string(),
+                access_types: vec![],
+            },
+        };
+
+        Ok(Command::Response(response))
+    }
+
+    /// Replaces the data breakpoints, the ones that don't get a comparator are reported with the
+    /// reason instead of an id.
+    fn set_data_breakpoints_command(&mut self, breakpoints: Vec<DataBreakpoint>) -> Result<Command> {
+        let endianness = self.endianness();
         let mut core = self.session.core(0)?;
-        core.clear_all_hw_breakpoints()?;
-        self.breakpoints = HashMap::new();
+        for id in std::mem::take(&mut self.data_breakpoints) {
+            // A reset or flash may have cleared it already
+            if self.watchpoints.watchpoints.iter().any(|w| w.id == id) {
+                self.watchpoints.remove(&mut core, id)?;
+            }
+        }
 
-        let mut breakpoints = vec![];
-        for bkpt in source_breakpoints {
-            let breakpoint = match find_breakpoint_location(
-                self.debug_info.dwarf,
-                &self.cwd,
-                &source_file,
-                match NonZeroU64::new(bkpt.line as u64) {
-                    Some(val) => val,
-                    None => {
-                        error!("Could not convert to NonZeroU64");
-                        return Err(anyhow!("Could not convert to NonZeroU64"));
-                    }
-                },
-                bkpt.column.map(|c| NonZeroU64::new(c as u64).unwrap()),
-            )? {
-                Some(address) => {
-                    let mut breakpoint = Breakpoint {
-                        id: Some(address as i64),
-                        verified: true,
+        let mut results = vec![];
+        for breakpoint in breakpoints {
+            let result = require_cortex_m(self.arch, "Data breakpoints")
+                .and_then(|_| parse_data_id(&breakpoint.data_id))
+                .and_then(|(address, size, name)| {
+                    self.watchpoints.add(
+                        &mut core,
+                        name,
+                        address,
+                        size,
+                        breakpoint.access_type.unwrap_or(WatchKind::Write),
+                        endianness,
+                    )
+                });
+            results.push(match result {
+                Ok(watchpoint) => {
+                    self.data_breakpoints.push(watchpoint.id);
+                    DataBreakpointResult {
+                        id: Some(watchpoint.id),
                         message: None,
-                        source: source.clone(),
-                        line: Some(bkpt.line),
-                        column: bkpt.column,
-                        end_line: None,
-                        end_column: None,
-                    };
-
-                    // Set breakpoint
-                    if self.breakpoints.len() < core.get_available_breakpoint_units()? as usize {
-                        self.breakpoints.insert(address as u32, breakpoint.clone());
-                        core.set_hw_breakpoint(address as u32)?;
-                    } else {
-                        breakpoint.verified = false;
                     }
-
-                    breakpoint
                 }
-                None => Breakpoint {
+                Err(err) => DataBreakpointResult {
                     id: None,
-                    verified: false,
-                    message: None,
-                    source: source.clone(),
-                    line: Some(bkpt.line),
-                    column: bkpt.column,
-                    end_line: None,
-                    end_column: None,
+                    message: Some(err.to_string()),
                 },
-            };
-
-            breakpoints.push(breakpoint);
+            });
         }
+        info!("Data breakpoints set: {:?}", self.data_breakpoints);
 
-        Ok(Command::Response(DebugResponse::SetBreakpoints {
-            breakpoints,
+        Ok(Command::Response(DebugResponse::SetDataBreakpoints {
+            breakpoints: results,
         }))
     }
 
-    fn dap_stack_frames(&mut self) -> Result<Command> {
-        match &self.stack_frames {
-            Some(stack_frames) => Ok(Command::Response(DebugResponse::DAPStackFrames {
-                stack_frames: stack_frames.clone(),
-            })),
-            None => {
-                self.set_stack_trace()?;
-                self.set_stack_frames()?;
-                self.dap_stack_frames()
-            }
+    // Resolves a raw address (`0x20000000` or `0x20000000:2`), a local variable path or a static
+    // into an address and a size.
+    fn resolve_watch_expression(&mut self, expression: &str) -> Result<(u32, u32)> {
+        let (address_str, size) = match expression.split_once(':') {
+            Some((address, size)) => (address, parse_u32_from_str(size)?),
+            None => (expression, 4),
+        };
+        if let Ok(address) = parse_u32_from_str(address_str) {
+            return Ok((address, size));
         }
-    }
 
-    fn dap_scopes(&mut self, frame_id: i64) -> Result<Command> {
-        match &self.scopes {
-            Some(scopes) => Ok(Command::Response(DebugResponse::DAPScopes {
-                scopes: match scopes.get(&frame_id) {
-                    Some(val) => val,
-                    None => {
-                        error!("Could not find scope");
-                        return Err(anyhow!("Could not find scope"));
+        let mut core = self.session.core(0)?;
+        let halted = core.status()?.is_halted();
+        drop(core);
+
+        if halted && !self.symbols.is_empty() {
+            self.require_frames(Some(1))?;
+            let variable = self
+                .stack_trace
+                .as_ref()
+                .and_then(|stack_trace| stack_trace.first())
+                .and_then(|frame| frame.find_variable_path(expression));
+            if let Some(variable) = variable {
+                return match &variable.location {
+                    Some(VariableLocation::Memory { address, byte_size }) => {
+                        Ok((*address, *byte_size as u32))
+                    }
+                    Some(VariableLocation::Register { .. }) => Err(anyhow!(
+                        "{} is stored in a register and can't be watched",
+                        expression
+                    )),
+                    None => Err(anyhow!("{} doesn't have a known address", expression)),
+                };
+            }
+        }
+
+        match self
+            .symbols
+            .find_symbols(expression)
+            .iter()
+            .find(|symbol| !symbol.is_function && symbol.size > 0)
+        {
+            Some(symbol) => Ok((symbol.address, symbol.size)),
+            None => Err(anyhow!("Could not find {:?}", expression)),
+        }
+    }
+
+    fn registers_command(&mut self) -> Result<Command> {
+        let registers = if let Some(dump) = self.session.core_dump() {
+            dump.header
+                .registers
+                .iter()
+                .map(|register| (register.name.clone(), register.value))
+                .collect()
+        } else if let Some(cache) = &self.stop_cache {
+            cache.registers.registers.clone()
+        } else {
+            let mut core = self.session.core(0)?;
+            RegisterSnapshot::read(&mut core)?.registers
+        };
+
+        let regions = registers
+            .iter()
+            .map(|(_, value)| self.regions.suffix(*value))
+            .collect();
+        Ok(Command::Response(DebugResponse::Registers {
+            registers,
+            regions,
+        }))
+    }
+
+    fn variable_command(&mut self, name: &str) -> Result<Command> {
+        match self.is_halted()? {
+            true => match &self.stack_trace {
+                Some(stack_trace) => {
+                    if stack_trace.len() < 1 {
+                        return Err(anyhow!("Variable {:?} not found", name));
                     }
+                    let variable = match stack_trace[0].find_variable(name) {
+                        Some(var) => var.clone(),
+                        None => {
+                            return Ok(Command::Response(DebugResponse::Error {
+                                message: format!("Variable {:?} not found", name),
+                            }))
+                        }
+                    };
+
+                    Ok(Command::Response(DebugResponse::Variable { variable }))
                 }
-                .clone(),
+                None => {
+                    self.set_stack_trace(None)?;
+                    self.set_stack_frames()?;
+                    self.variable_command(name)
+                }
+            },
+            false => Err(anyhow!("Core must be halted")),
+        }
+    }
+
+    fn variables_command(&mut self) -> Result<Command> {
+        match self.is_halted()? {
+            true => match &self.stack_trace {
+                Some(stack_trace) => {
+                    let variables = match stack_trace.len() {
+                        0 => vec![],
+                        _ => stack_trace[0].variables.clone(),
+                    };
+
+                    Ok(Command::Response(DebugResponse::Variables {
+                        variables: variables,
+                    }))
+                }
+                None => {
+                    self.set_stack_trace(None)?;
+                    self.set_stack_frames()?;
+                    self.variables_command()
+                }
+            },
+            false => Err(anyhow!("Core must be halted")),
+        }
+    }
+
+    fn export_stop_state_command(&mut self, path: Option<PathBuf>, depth: usize) -> Result<Command> {
+        if !self.is_halted()? {
+            return Err(anyhow!("Core must be halted"));
+        }
+        self.require_frames(None)?;
+        let stack_trace = match &self.stack_trace {
+            Some(val) => val,
+            None => return Err(anyhow!("No stack trace")),
+        };
+
+        let stop_report = StopReport::new(stack_trace, &self.history.entries(), depth);
+        let report = serde_json::to_string_pretty(&stop_report)?;
+        if let Some(path) = &path {
+            let path = resolve_path(&self.cwd, path);
+            std::fs::write(&path, &report)
+                .context(format!("Failed to write the stop state to {:?}", path))?;
+            info!("Stop state exported to: {:?}", path);
+        }
+
+        Ok(Command::Response(DebugResponse::ExportStopState { path, report }))
+    }
+
+    /// The names of the variables, the fields of the variable before the last `.` or the statics
+    /// starting with the last part of the path. The variables are only read when the core is
+    /// halted, a running core completes statics.
+    fn completions_command(&mut self, text: &str, frame_id: Option<i64>) -> Result<Command> {
+        let (path, partial) = match text.rfind('.') {
+            Some(index) => (Some(&text[..index]), &text[index + 1..]),
+            None => (None, text),
+        };
+        if self.variables.is_none() && matches!(self.is_halted(), Ok(true)) {
+            self.set_stack_trace(None)?;
+            self.set_stack_frames()?;
+        }
+        let index = frame_id
+            .and_then(|id| self.stop_ids.frame_index(id))
+            .unwrap_or(0);
+
+        let mut names = vec![];
+        if let Some(variables) = &self.variables {
+            for kind in [ScopeKind::Locals, ScopeKind::Arguments] {
+                let scope = self.stop_ids.scope_reference(index, kind)?;
+                let id = match path {
+                    Some(path) => {
+                        let path = path.replace('[', ".").replace(']', "");
+                        match find_in_scope(variables, scope, &path) {
+                            Some(variable) => variable.id,
+                            None => continue,
+                        }
+                    }
+                    None => scope,
+                };
+                if let Some(children) = variables.get(&id) {
+                    names.extend(children.iter().filter_map(|child| child.name.clone()));
+                }
+            }
+        }
+        if path.is_none() {
+            names.extend(self.symbols.static_names(partial));
+        }
+        names.retain(|name| name.starts_with(partial));
+        names.sort();
+        names.dedup();
+        names.truncate(MAX_COMPLETIONS);
+
+        Ok(Command::Response(DebugResponse::Completions { names }))
+    }
+
+    /// Looks up a variable path in the locals and arguments of the frame, then the statics.
+    fn evaluate_command(
+        &mut self,
+        expression: &str,
+        frame_id: Option<i64>,
+        cached: bool,
+    ) -> Result<Command> {
+        let (_, variable) = self.evaluate_variable(expression, frame_id, cached)?;
+        Ok(Command::Response(DebugResponse::Evaluate { variable }))
+    }
+
+    /// Finds the variable in the scopes of the frame, or else the static, of a member path like
+    /// `a.b.3` or `a.b[3]`. The frame index is None for a static.
+    fn evaluate_variable(
+        &mut self,
+        expression: &str,
+        frame_id: Option<i64>,
+        cached: bool,
+    ) -> Result<(Option<usize>, Variable)> {
+        let expression = expression.trim();
+        // The cached variables are cleared when the core resumes
+        if cached && self.variables.is_none() {
+            return Err(anyhow!("The variables of the stop are not evaluated yet"));
+        }
+        if !cached && !self.is_halted()? {
+            return Err(anyhow!("Core must be halted"));
+        }
+        if self.variables.is_none() {
+            self.set_stack_trace(None)?;
+            self.set_stack_frames()?;
+        }
+
+        let index = match frame_id {
+            Some(id) => match self.stop_ids.frame_index(id) {
+                Some(val) => val,
+                None => return Err(anyhow!("The frame {} is not part of the current stop", id)),
+            },
+            None => 0,
+        };
+        let path = expression.replace('[', ".").replace(']', "");
+        if let Some(variables) = &self.variables {
+            for kind in [ScopeKind::Locals, ScopeKind::Arguments] {
+                let scope = self.stop_ids.scope_reference(index, kind)?;
+                if let Some(variable) = find_in_scope(variables, scope, &path) {
+                    let variable = variable.clone();
+                    self.requested_ranges.record(std::slice::from_ref(&variable));
+                    return Ok((Some(index), variable));
+                }
+            }
+        }
+
+        if cached {
+            return Err(anyhow!("No variable named `{}` in the frame", expression));
+        }
+        let symbol = self
+            .symbols
+            .find_symbols(expression)
+            .into_iter()
+            .find(|symbol| !symbol.is_function);
+        match symbol {
+            Some(symbol) => Ok((
+                None,
+                self.static_variable(expression, symbol.address, symbol.size)?,
+            )),
+            None => Err(anyhow!("No variable or static named `{}`", expression)),
+        }
+    }
+
+    /// Statics have no evaluated type, the value is the content of the symbol.
+    fn static_variable(&mut self, name: &str, address: u32, size: u32) -> Result<Variable> {
+        let byte_size = size.clamp(1, MAX_STATIC_BYTES) as usize;
+        let bytes = match self.session.core_dump() {
+            Some(dump) => match dump.read_memory(address, byte_size) {
+                Some(val) => val,
+                None => {
+                    return Err(anyhow!(
+                        "The static `{}` at {:#010x} was not captured in the core dump",
+                        name,
+                        address
+                    ))
+                }
+            },
+            None => {
+                let mut buff = vec![0; byte_size];
+                self.session.core(0)?.read_8(address, &mut buff)?;
+                buff
+            }
+        };
+
+        let value = match (size, self.endianness().read_unsigned(&bytes)) {
+            (1 | 2 | 4 | 8, Some(value)) => format!("{}", value),
+            _ => {
+                let mut value = bytes
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect::<Vec<String>>()
+                    .join(" ");
+                if size > MAX_STATIC_BYTES {
+                    value.push_str(" ...");
+                }
+                value
+            }
+        };
+        Ok(Variable {
+            id: 0,
+            name: Some(name.to_owned()),
+            value,
+            type_: format!("<static, {} bytes>", size),
+            source: None,
+            kind: VariableKind::Named,
+            children: vec![],
+            location: Some(VariableLocation::Memory {
+                address,
+                byte_size: size as usize,
+            }),
+            read_only: false,
+        })
+    }
+
+    fn stack_trace_command(&mut self) -> Result<Command> {
+        self.require_frames(None)?;
+        match &self.stack_trace {
+            Some(stack_trace) => Ok(Command::Response(DebugResponse::StackTrace {
+                stack_trace: stack_trace.clone(),
             })),
+            None => Err(anyhow!("No stack trace")),
+        }
+    }
+
+    fn read_command(&mut self, address: u32, byte_size: usize) -> Result<Command> {
+        if let Some(dump) = self.session.core_dump() {
+            return match dump.read_memory(address, byte_size) {
+                Some(value) => Ok(Command::Response(DebugResponse::Read { address, value })),
+                None => Err(anyhow!("The memory at {:#010x} was not captured in the core dump", address)),
+            };
+        }
+
+        let mut core = self.session.core(0)?;
+        let mut buff: Vec<u8> = vec![0; byte_size];
+        core.read_8(address, &mut buff)?;
+
+        Ok(Command::Response(DebugResponse::Read {
+            address: address,
+            value: buff,
+        }))
+    }
+
+    fn read_memory_command(
+        &mut self,
+        memory_reference: &str,
+        offset: i64,
+        count: usize,
+    ) -> Result<Command> {
+        let address = match parse_u32_from_str(memory_reference.trim()) {
+            Ok(val) => (val as i64 + offset) as u32,
+            Err(_) => return Err(anyhow!("`{}` is not a memory reference", memory_reference)),
+        };
+        let count = count.min((u32::MAX - address) as usize + 1);
+
+        let mut data = vec![];
+        while data.len() < count {
+            let start = address + data.len() as u32;
+            let len = (count - data.len()).min(MEMORY_REQUEST_CHUNK);
+            if let Some(bytes) = self.try_read_memory(start, len)? {
+                data.extend(bytes);
+                continue;
+            }
+
+            // The chunk runs into unmapped memory, find how much of it is readable
+            let (mut readable, mut unreadable) = (0, len);
+            while unreadable - readable > 1 {
+                let mid = (readable + unreadable) / 2;
+                match self.try_read_memory(start, mid)?.is_some() {
+                    true => readable = mid,
+                    false => unreadable = mid,
+                }
+            }
+            if readable > 0 {
+                if let Some(bytes) = self.try_read_memory(start, readable)? {
+                    data.extend(bytes);
+                }
+            }
+            break;
+        }
+
+        Ok(Command::Response(DebugResponse::ReadMemory {
+            address,
+            unreadable_bytes: count - data.len(),
+            data,
+        }))
+    }
+
+    /// Reads the memory, None if any of it can't be read.
+    fn try_read_memory(&mut self, address: u32, byte_size: usize) -> Result<Option<Vec<u8>>> {
+        if let Some(dump) = self.session.core_dump() {
+            return Ok(dump.read_memory(address, byte_size));
+        }
+
+        let mut core = self.session.core(0)?;
+        let mut buff: Vec<u8> = vec![0; byte_size];
+        match core.read_8(address, &mut buff) {
+            Ok(()) => Ok(Some(buff)),
+            Err(err) => {
+                debug!(
+                    "Failed to read {} bytes at {:#010x}: {}",
+                    byte_size, address, err
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Writes the data, up to the first byte that can't be written if `allow_partial` is set.
+    /// Flash is never written, that takes flashing.
+    fn write_memory_command(
+        &mut self,
+        memory_reference: &str,
+        offset: i64,
+        data: &[u8],
+        allow_partial: bool,
+    ) -> Result<Command> {
+        let address = match parse_u32_from_str(memory_reference.trim()) {
+            Ok(val) => (val as i64 + offset) as u32,
+            Err(_) => return Err(anyhow!("`{}` is not a memory reference", memory_reference)),
+        };
+        if data.is_empty() {
+            return Ok(Command::Response(DebugResponse::WriteMemory {
+                address,
+                bytes_written: 0,
+            }));
+        }
+        let end = address as u64 + data.len() as u64;
+        if end > u32::MAX as u64 + 1 {
+            return Err(anyhow!(
+                "The write at {:#010x} runs past the address space",
+                address
+            ));
+        }
+        for region in self.session.memory_map() {
+            if let probe_rs::config::MemoryRegion::Nvm(nvm) = region {
+                if (address as u64) < nvm.range.end as u64 && end > nvm.range.start as u64 {
+                    return Err(anyhow!(
+                        "{:#010x}..{:#010x} overlaps the flash at {:#010x}..{:#010x}, flash can't be written through memory writes",
+                        address,
+                        end,
+                        nvm.range.start,
+                        nvm.range.end
+                    ));
+                }
+            }
+        }
+        // Writing from the editor is deliberate, only denied peripheral writes are refused
+        if let Some(peripheral) = first_peripheral(address, end) {
+            self.peripheral_writes.check(peripheral, true)?;
+        }
+
+        let mut core = self.session.core(0)?;
+        if !core.status()?.is_halted() {
+            return Err(anyhow!("Core must be halted"));
+        }
+        let mut written = 0;
+        while written < data.len() {
+            let start = address + written as u32;
+            let len = (data.len() - written).min(MEMORY_REQUEST_CHUNK);
+            let chunk = &data[written..written + len];
+            let err = match core.write_8(start, chunk) {
+                Ok(()) => {
+                    written += len;
+                    continue;
+                }
+                Err(err) => err,
+            };
+            if !allow_partial {
+                return Err(anyhow!(
+                    "Failed to write {} bytes at {:#010x}, {} bytes were written before it: {}",
+                    len,
+                    start,
+                    written,
+                    err
+                ));
+            }
+
+            // The chunk runs into memory that can't be written, find how much of it can
+            let (mut writable, mut unwritable) = (0, len);
+            while unwritable - writable > 1 {
+                let mid = (writable + unwritable) / 2;
+                match core.write_8(start, &chunk[..mid]) {
+                    Ok(()) => writable = mid,
+                    Err(_) => unwritable = mid,
+                }
+            }
+            written += writable;
+            break;
+        }
+        drop(core);
+
+        if first_peripheral(address, address as u64 + written as u64).is_some() {
+            self.history.record(
+                HistoryKind::Write,
+                format!(
+                    "Wrote {} bytes to the peripheral registers at {:#010x}",
+                    written, address
+                ),
+            );
+        }
+
+        // Evaluated again with the same ids, the frontend keeps its references
+        self.registers.clear();
+        self.stop_cache = None;
+        self.clear_variables();
+        Ok(Command::Response(DebugResponse::WriteMemory {
+            address,
+            bytes_written: written,
+        }))
+    }
+
+    fn write_word_command(&mut self, address: u32, value: u32, confirmed: bool) -> Result<Command> {
+        self.peripheral_writes.check(address, confirmed)?;
+        let mut core = self.session.core(0)?;
+        if !core.status()?.is_halted() {
+            return Err(anyhow!("Core must be halted"));
+        }
+        core.write_word_32(address, value)?;
+        // Reading a peripheral register can clear its flags or pop a FIFO, it is not read back
+        let value = match is_peripheral(address) {
+            true => value,
+            false => core.read_word_32(address)?,
+        };
+        drop(core);
+
+        if is_peripheral(address) {
+            self.history.record(
+                HistoryKind::Write,
+                format!("Wrote {:#010x} to the peripheral register at {:#010x}", value, address),
+            );
+        }
+
+        self.clear_temporaries();
+        Ok(Command::Response(DebugResponse::WriteWord { value }))
+    }
+
+    /// Writes a variable shown in a scope, `reference` is the scope or the parent variable.
+    fn set_variable_command(&mut self, reference: i64, name: &str, value: &str) -> Result<Command> {
+        if !self.is_halted()? {
+            return Err(anyhow!("Core must be halted"));
+        }
+        let variable = self.find_child_variable(reference, name)?;
+        // The registers scope holds the raw register values
+        let (frame, raw) = match self.stop_ids.reference_frame(reference) {
+            Some(index) => (
+                Some(index),
+                self.stop_ids.scope_reference(index, ScopeKind::Registers)? == reference,
+            ),
+            None => (None, false),
+        };
+        self.write_variable(name, &variable, frame, raw, value)?;
+
+        let variable = self.find_child_variable(reference, name)?;
+        Ok(Command::Response(DebugResponse::SetVariable { variable }))
+    }
+
+    /// Writes the variable or static an evaluate of `expression` finds, e.g. `cfg.flags` or
+    /// `buffer[3]`.
+    fn set_expression_command(
+        &mut self,
+        expression: &str,
+        frame_id: Option<i64>,
+        value: &str,
+    ) -> Result<Command> {
+        if !self.is_halted()? {
+            return Err(anyhow!("Core must be halted"));
+        }
+        let (frame, variable) = self.evaluate_variable(expression, frame_id, false)?;
+        // Statics without debug information are raw memory
+        let raw = frame.is_none();
+        self.write_variable(expression, &variable, frame, raw, value)?;
+
+        let (_, variable) = self.evaluate_variable(expression, frame_id, false)?;
+        Ok(Command::Response(DebugResponse::SetExpression { variable }))
+    }
+
+    /// Parses `value` by the type of the variable and writes it, raw values are parsed as
+    /// unsigned integers of their size. `frame` is None for statics.
+    fn write_variable(
+        &mut self,
+        name: &str,
+        variable: &Variable,
+        frame: Option<usize>,
+        raw: bool,
+        value: &str,
+    ) -> Result<()> {
+        if !variable.children.is_empty() {
+            return Err(anyhow!("{} is not a single value, set its members one by one", name));
+        }
+        if variable.read_only {
+            return Err(anyhow!("{} is in flash and can't be written", name));
+        }
+        let (confirmed, value) = match value.trim().strip_prefix('!') {
+            Some(value) => (true, value),
+            None => (false, value),
+        };
+        let value_type = |byte_size: usize| match raw {
+            true => format!("u{}", byte_size * 8),
+            false => variable
+                .type_
+                .rsplit("::")
+                .next()
+                .unwrap_or_default()
+                .to_owned(),
+        };
+
+        match variable.location {
+            Some(VariableLocation::Memory { address, byte_size }) => {
+                self.peripheral_writes.check(address, confirmed)?;
+                let bits = parse_value(&value_type(byte_size), value, byte_size)?;
+                let bytes = self.endianness().write_unsigned(bits, byte_size);
+                self.session.core(0)?.write_8(address, &bytes)?;
+                if is_peripheral(address) {
+                    self.history.record(
+                        HistoryKind::Write,
+                        format!("Set {} in the peripheral register at {:#010x}", name, address),
+                    );
+                }
+            }
+            Some(VariableLocation::Register { register, byte_size }) => {
+                // The registers of the outer frames are unwound copies, not the core registers
+                if frame != Some(0) {
+                    return Err(anyhow!(
+                        "{} is in a register, it can only be set in the innermost frame",
+                        name
+                    ));
+                }
+                if byte_size > 4 {
+                    return Err(anyhow!("{} is split over registers and can't be set", name));
+                }
+                let bits = parse_value(&value_type(byte_size), value, byte_size)?;
+                self.session
+                    .core(0)?
+                    .write_core_reg(probe_rs::CoreRegisterAddress(register), bits as u32)?;
+                self.trace_resume("had a register written");
+            }
             None => {
-                self.set_stack_trace()?;
-                self.set_stack_frames()?;
-                self.dap_stack_frames()
+                return Err(anyhow!(
+                    "{} is optimized out or has no single location and can't be set",
+                    name
+                ))
             }
+        };
+
+        // Evaluated again with the same ids, the frontend keeps its references
+        self.registers.clear();
+        self.stop_cache = None;
+        self.clear_variables();
+        Ok(())
+    }
+
+    fn find_child_variable(&mut self, reference: i64, name: &str) -> Result<Variable> {
+        if self.variables.is_none() {
+            self.set_stack_trace(None)?;
+            self.set_stack_frames()?;
+        }
+        let variables = match self.variables.as_ref().and_then(|v| v.get(&reference)) {
+            Some(val) => val,
+            None => return Err(anyhow!("The variables are from an earlier stop")),
+        };
+        match variables
+            .iter()
+            .find(|variable| variable.name.as_deref() == Some(name))
+        {
+            Some(variable) => Ok(variable.clone()),
+            None => Err(anyhow!("No variable named {}", name)),
+        }
+    }
+
+    /// Writes a core register by its name, e.g. `R0`, `pc` or `sp`.
+    fn write_register_command(&mut self, name: &str, value: u32) -> Result<Command> {
+        let mut core = self.session.core(0)?;
+        if !core.status()?.is_halted() {
+            return Err(anyhow!("Core must be halted"));
         }
+
+        let register_file = core.registers();
+        let register = match name.to_lowercase().as_str() {
+            "pc" => register_file.program_counter(),
+            "sp" => register_file.stack_pointer(),
+            "lr" => register_file.return_address(),
+            lower => match register_file
+                .registers()
+                .find(|register| register.name().to_lowercase() == lower)
+            {
+                Some(val) => val,
+                None => return Err(anyhow!("Unknown register {}", name)),
+            },
+        };
+        core.write_core_reg(register.into(), value)?;
+        let value = core.read_core_reg(register)?;
+        drop(core);
+
+        // Nothing before the write can be stepped back over
+        self.trace_resume("had a register written");
+        self.clear_temporaries();
+        Ok(Command::Response(DebugResponse::WriteRegister { value }))
+    }
+
+    fn core_dump_command(&mut self, path: PathBuf, regions: DumpRegions) -> Result<Command> {
+        let path = resolve_path(&self.cwd, &path);
+        let dump = self.capture_state(regions, &format!("Writing core dump {:?}", path))?;
+        dump.write(&path)?;
+
+        let size = dump.data.len() as u32;
+        info!("Core dump of {} bytes written to: {:?}", size, path);
+
+        Ok(Command::Response(DebugResponse::CoreDump { size }))
+    }
+
+    fn read_flash_command(&mut self, path: PathBuf, region: Option<Range<u32>>) -> Result<Command> {
+        let path = resolve_path(&self.cwd, &path);
+        let range = flash_range(self.session.memory_map(), region)?;
+
+        let title = format!("Reading flash to {:?}", path);
+        let sender = self.sender.clone();
+        let cancel = self.cancel.clone();
+        let mut progress = |done: u32, total: u32| -> Result<()> {
+            cancel.check()?;
+            sender.send(Command::Event(DebugEvent::Progress {
+                title: title.clone(),
+                done,
+                total,
+            }))?;
+            Ok(())
+        };
+        let mut core = self.session.core(0)?;
+        let report = read_flash(&mut core, &path, range, &mut progress)?;
+        drop(core);
+        info!("{}", report);
+
+        self.history.record(HistoryKind::Write, report.to_string());
+        Ok(Command::Response(DebugResponse::ReadFlash { report }))
+    }
+
+    fn snapshot_state_command(&mut self, name: String, regions: DumpRegions) -> Result<Command> {
+        let snapshot = self.capture_state(regions, &format!("Taking snapshot `{}`", name))?;
+        info!("Snapshot `{}` taken of {} bytes", name, snapshot.data.len());
+        self.snapshots.insert(name, snapshot);
+
+        Ok(Command::Response(DebugResponse::SnapshotState {
+            caveat: SNAPSHOT_CAVEAT.to_owned(),
+        }))
+    }
+
+    fn restore_state_command(&mut self, name: String) -> Result<Command> {
+        let snapshot = match self.snapshots.get(&name) {
+            Some(val) => val,
+            None => return Err(anyhow!("No snapshot named `{}`", name)),
+        };
+
+        let build_id = match &self.program {
+            Some(program) => symbols::build_id(program)?,
+            None => None,
+        };
+        if build_id != snapshot.header.build_id {
+            return Err(anyhow!(
+                "The binary changed since snapshot `{}` was taken, refusing to restore it",
+                name
+            ));
+        }
+
+        let mut core = self.session.core(0)?;
+        let status = core.status()?;
+        if !status.is_halted() {
+            return Err(anyhow!("Core must be halted"));
+        }
+
+        for region in &snapshot.header.regions {
+            let start = region.offset as usize;
+            core.write_8(
+                region.start,
+                &snapshot.data[start..start + region.size as usize],
+            )?;
+        }
+        for register in &snapshot.header.registers {
+            if let Err(err) =
+                core.write_core_reg(probe_rs::CoreRegisterAddress(register.address), register.value)
+            {
+                warn!("Failed to restore register {}: {}", register.name, err);
+            }
+        }
+        drop(core);
+        info!("Snapshot `{}` restored", name);
+
+        // Let the halt check report the restored location
+        self.running = true;
+        self.resumes.resumed();
+        self.trace_resume("was restored to a snapshot");
+        self.clear_temporaries();
+
+        Ok(Command::Response(DebugResponse::RestoreState {
+            caveat: SNAPSHOT_CAVEAT.to_owned(),
+        }))
+    }
+
+    fn snapshots_command(&mut self) -> Result<Command> {
+        let mut snapshots: Vec<(String, Option<u32>, u32)> = self
+            .snapshots
+            .iter()
+            .map(|(name, snapshot)| {
+                (
+                    name.clone(),
+                    snapshot.register(15),
+                    snapshot.data.len() as u32,
+                )
+            })
+            .collect();
+        snapshots.sort();
+
+        Ok(Command::Response(DebugResponse::Snapshots { snapshots }))
+    }
+
+    /// Reads the registers and the memory regions of the halted core, reporting progress
+    /// under `title`.
+    fn capture_state(&mut self, regions: DumpRegions, title: &str) -> Result<CoreDump> {
+        let mut core = self.session.core(0)?;
+        let status = core.status()?;
+        if !status.is_halted() {
+            return Err(anyhow!("Core must be halted"));
+        }
+        let register_file = core.registers();
+        let mut registers = vec![];
+        for register in register_file.registers() {
+            registers.push(CoreDumpRegister {
+                name: format!("{}", register.name()),
+                address: probe_rs::CoreRegisterAddress::from(register).0,
+                value: core.read_core_reg(register)?,
+            });
+        }
+        drop(core);
+
+        let mut ranges = vec![];
+        match regions {
+            DumpRegions::Ranges(requested) => {
+                for range in requested {
+                    ranges.push(("memory".to_owned(), range));
+                }
+            }
+            regions => {
+                for region in self.session.memory_map() {
+                    match region {
+                        probe_rs::config::MemoryRegion::Ram(ram) => {
+                            ranges.push(("RAM".to_owned(), ram.range.clone()))
+                        }
+                        probe_rs::config::MemoryRegion::Nvm(nvm) => {
+                            if let DumpRegions::RamAndFlash = regions {
+                                ranges.push(("flash".to_owned(), nvm.range.clone()));
+                            }
+                        }
+                        probe_rs::config::MemoryRegion::Generic(_) => (),
+                    };
+                }
+            }
+        };
+
+        let build_id = match &self.program {
+            Some(program) => symbols::build_id(program)?,
+            None => None,
+        };
+        let mut dump = CoreDump::new(CoreDumpHeader {
+            chip: self.session.chip_name(),
+            program: self.program.as_ref().map(|p| p.display().to_string()),
+            build_id,
+            registers,
+            regions: vec![],
+        });
+
+        let title = title.to_owned();
+        let total: u32 = ranges.iter().map(|(_, range)| range.end - range.start).sum();
+        let mut done = 0;
+        self.sender.send(Command::Event(DebugEvent::Progress {
+            title: title.clone(),
+            done,
+            total,
+        }))?;
+
+        let mut core = self.session.core(0)?;
+        for (name, range) in ranges {
+            let mut memory = vec![0u8; (range.end - range.start) as usize];
+            for (i, chunk) in memory.chunks_mut(CHUNK_SIZE as usize).enumerate() {
+                self.cancel.check()?;
+                core.read_8(range.start + i as u32 * CHUNK_SIZE, chunk)?;
+                done += chunk.len() as u32;
+                self.sender.send(Command::Event(DebugEvent::Progress {
+                    title: title.clone(),
+                    done,
+                    total,
+                }))?;
+            }
+            dump.add_region(&name, range.start, &memory);
+        }
+
+        Ok(dump)
+    }
+
+    fn reset_command(&mut self, reset_and_halt: bool) -> Result<Command> {
+        self.catches.rearm();
+        self.reset_hit_conditions();
+        self.restart_test_run();
+        self.enter_startup()?;
+        if reset_and_halt {
+            self.clear_temporaries();
+
+            let mut core = self.session.core(0)?;
+            core.reset_and_halt(std::time::Duration::from_millis(10))
+                .context("Failed to reset and halt the core")?;
+        } else {
+            self.clear_temporaries();
+
+            let mut core = self.session.core(0)?;
+            core.reset().context("Failed to reset the core")?;
+        }
+
+        self.running = true;
+
+        self.resumes.resumed();
+        self.trace_resume("was reset");
+        self.history
+            .record(HistoryKind::Resume, "Reset the core".to_owned());
+
+        Ok(Command::Response(DebugResponse::Reset))
+    }
+
+    fn flash_command(&mut self, reset_and_halt: bool) -> Result<Command> {
+        let path = match &self.program {
+            Some(val) => val.clone(),
+            None => return Err(anyhow!(symbols::NO_BINARY_MESSAGE)),
+        };
+        self.report_memory_usage()?;
+        if self.check_flash_layout {
+            check_layout(&path, &self.session.chip_name(), self.session.memory_map())?;
+        }
+        // Too late to cancel once the erase has started
+        self.cancel.check()?;
+        download_file(self.session.session()?, &path, Format::Elf).context("Failed to flash target")?;
+        self.program_modified = modified_time(&path);
+        self.history
+            .record(HistoryKind::Flash, format!("Flashed {:?}", path));
+        self.report_breakpoints()?;
+
+        self.catches.rearm();
+        self.reset_hit_conditions();
+        self.restart_test_run();
+        self.enter_startup()?;
+        if reset_and_halt {
+            self.clear_temporaries();
+
+            let mut core = self.session.core(0)?;
+            core.reset_and_halt(std::time::Duration::from_millis(10))
+                .context("Failed to reset and halt the core")?;
+        } else {
+            self.clear_temporaries();
+
+            let mut core = self.session.core(0)?;
+            core.reset().context("Failed to reset the core")?;
+        }
+
+        self.running = true;
+
+        self.resumes.resumed();
+        self.trace_resume("was flashed");
+
+        Ok(Command::Response(DebugResponse::Flash))
+    }
+
+    /// Resets the core keeping the session, the program is flashed again first if it changed on
+    /// disk. The breakpoints are installed again either way.
+    fn restart_command(&mut self, reset_and_halt: bool) -> Result<Command> {
+        let path = match &self.program {
+            Some(val) => val.clone(),
+            None => return Err(anyhow!(symbols::NO_BINARY_MESSAGE)),
+        };
+        let flashed = modified_time(&path) != self.program_modified;
+        if flashed {
+            info!("{:?} changed on disk, flashing it again", path);
+            if self.symbol_files.is_empty() {
+                self.reload_symbols()?;
+            } else {
+                self.symbols.check_program(&path)?;
+            }
+            self.flash_command(reset_and_halt)?;
+        } else {
+            self.verify_breakpoints()?;
+            self.reset_command(reset_and_halt)?;
+        }
+        let message = match flashed {
+            true => "Restarted with the changed program",
+            false => "Restarted, the program is unchanged",
+        };
+        self.history.record(HistoryKind::Resume, message.to_owned());
+
+        Ok(Command::Response(DebugResponse::Restart { flashed }))
+    }
+
+    /// Starts over after a reset the target did by itself, see `reset_catch`. The core is halted
+    /// at the reset vector and kept there if `halt` is set.
+    fn target_reset(
+        &mut self,
+        sender: &mut Sender<Command>,
+        pc: u32,
+        halt: bool,
+        detected: Instant,
+    ) -> Result<()> {
+        warn!("Target reset detected at pc = 0x{:08x}", pc);
+        let mut core = self.session.core(0)?;
+        if let Some(finish) = self.finish.take() {
+            if finish.temporary_breakpoint {
+                core.clear_hw_breakpoint(finish.return_address)?;
+            }
+        }
+        // The breakpoint units are reset with the core by some resets
+        for address in self.breakpoints.keys() {
+            if !self.startup.pending.contains(address) {
+                core.set_hw_breakpoint(*address)?;
+            }
+        }
+        drop(core);
+
+        self.timed_run = None;
+        self.step_abandoned = None;
+        self.lift_catches()?;
+        self.lift_startup_breakpoint()?;
+        self.catches.rearm();
+        self.reset_hit_conditions();
+        self.restart_test_run();
+        self.enter_startup()?;
+        self.plant_startup_breakpoint()?;
+        self.plant_catches()?;
+        self.clear_temporaries();
+        self.trace_resume("was reset by the target");
+
+        if halt {
+            self.history.record_stop(
+                HistoryKind::Stop,
+                format!("Target reset, halted at {:#010x}", pc),
+                "reset",
+                &[],
+            );
+        } else {
+            let mut core = self.session.core(0)?;
+            continue_fix(&mut core, &self.breakpoints)?;
+            core.run()?;
+            self.running = true;
+            self.resumes.resumed();
+            self.intrusiveness.record_halt("target reset", detected);
+            self.history.record(
+                HistoryKind::Resume,
+                format!("Target reset, resumed from {:#010x}", pc),
+            );
+        }
+        sender.send(Command::Event(DebugEvent::Reset { pc, halted: halt }))?;
+        Ok(())
+    }
+
+    /// The tests run again from the start after a reset.
+    fn restart_test_run(&mut self) {
+        if self.test_run.is_some() {
+            self.test_run = Some(TestRun::default());
+        }
+    }
+
+    fn halt_command(&mut self) -> Result<Command> {
+        if self.until.is_some() {
+            // The core is only halted between the steps of `until`, let the halt check report it.
+            self.cancel_until()?;
+            self.running = true;
+            self.resumes.resumed();
+        } else {
+            let mut core = self.session.core(0)?;
+            let status = core.status()?;
+
+            if status.is_halted() && self.running {
+                // Halted by a breakpoint the halt check hasn't handled, e.g. a logpoint that
+                // would resume it.
+                info!("Core halted before the pause, it is kept halted");
+                self.pause_requested = true;
+            } else if status.is_halted() {
+                warn!("Core is already halted, status: {:?}", status);
+                return Err(anyhow!("Core is already halted"));
+            } else {
+                let cpu_info = core.halt(Duration::from_millis(100))?;
+                info!("Core halted at pc = 0x{:08x}", cpu_info.pc);
+            };
+        }
+
+        // Nothing polls the core in low power mode, report the halt right away
+        if self.polling.low_power {
+            let mut sender = self.sender.clone();
+            self.process_halt_event(&mut sender)?;
+        }
+
+        Ok(Command::Response(DebugResponse::Halt))
+    }
+
+    fn set_polling_command(
+        &mut self,
+        status_interval_ms: Option<u64>,
+        low_power: Option<bool>,
+    ) -> Result<Command> {
+        if let Some(interval) = status_interval_ms {
+            self.polling.status_interval = Duration::from_millis(interval);
+        }
+        if let Some(low_power) = low_power {
+            if low_power && !self.polling.low_power {
+                warn!("{}", LOW_POWER_WARNING);
+                self.output("console", LOW_POWER_WARNING)?;
+            }
+            self.polling.low_power = low_power;
+        }
+
+        Ok(Command::Response(DebugResponse::SetPolling))
+    }
+
+    fn status_command(&mut self) -> Result<Command> {
+        if let Some(dump) = self.session.core_dump() {
+            return Ok(Command::Response(DebugResponse::Status {
+                status: CoreStatus::Halted(HaltReason::Request),
+                pc: dump.register(15),
+            }));
+        }
+
+        let mut core = self.session.core(0)?;
+        let status = core.status()?;
+        let mut pc = None;
+
+        if status.is_halted() {
+            pc = Some(core.read_core_reg(core.registers().program_counter())?);
+        }
+
+        Ok(Command::Response(DebugResponse::Status {
+            status: status,
+            pc: pc,
+        }))
+    }
+
+    fn step_command(&mut self) -> Result<Command> {
+        self.cancel_until()?;
+        let mut core = self.session.core(0)?;
+        let status = core.status()?;
+
+        if status.is_halted() {
+            let snapshot = RegisterSnapshot::read(&mut core)?;
+            let from = core.read_core_reg(core.registers().program_counter())?;
+            let pc = continue_fix(&mut core, &self.breakpoints)?;
+            if let Some(trace) = &mut self.instruction_trace {
+                let (instruction, _, _) = disassemble_instruction(&mut core, &self.capstone, pc)?;
+                trace.record_step(
+                    from,
+                    InstructionStep {
+                        pc,
+                        instruction,
+                        changed_registers: RegisterSnapshot::read(&mut core)?.changed(&snapshot),
+                    },
+                );
+            }
+            self.running = true;
+            self.resumes.resumed();
+            info!("Stopped at pc = 0x{:08x}", pc);
+            self.history
+                .record(HistoryKind::Resume, format!("Stepped to {:#010x}", pc));
+
+            drop(core);
+
+            self.clear_temporaries();
+            return Ok(Command::Response(DebugResponse::Step));
+        }
+
+        Ok(Command::Response(DebugResponse::Error {
+            message: "Can only step when core is halted".to_owned(),
+        }))
+    }
+
+    fn step_instruction_command(&mut self, count: u32, over_calls: bool) -> Result<Command> {
+        self.cancel_until()?;
+        let mut core = self.session.core(0)?;
+        let status = core.status()?;
+
+        if !status.is_halted() {
+            return Err(anyhow!("Core must be halted"));
+        }
+
+        let mut steps = vec![];
+        let mut deferred = vec![];
+        let start_exception = active_exception(&mut core)?;
+        let mut snapshot = RegisterSnapshot::read(&mut core)?;
+        for _ in 0..count {
+            // The steps done so far are reported
+            if self.cancel.is_cancelled() {
+                break;
+            }
+            self.watchdog.heartbeat();
+            let from = core.read_core_reg(core.registers().program_counter())?;
+            let (_, _, is_call) = disassemble_instruction(&mut core, &self.capstone, from)?;
+            let pc = step_instruction(
+                &mut core,
+                &self.capstone,
+                &self.breakpoints,
+                over_calls,
+                match self.step_breakpoints {
+                    StepBreakpoints::Defer => Some(&mut deferred),
+                    StepBreakpoints::Stop => None,
+                },
+                &self.cancel,
+            )?;
+            let (instruction, _, _) = disassemble_instruction(&mut core, &self.capstone, pc)?;
+
+            let new_snapshot = RegisterSnapshot::read(&mut core)?;
+            let step = InstructionStep {
+                pc,
+                instruction,
+                changed_registers: new_snapshot.changed(&snapshot),
+            };
+            snapshot = new_snapshot;
+
+            if let Some(trace) = &mut self.instruction_trace {
+                if over_calls && is_call {
+                    trace.record_resume(&format!("ran over the call at {:#010x}", from));
+                } else {
+                    trace.record_step(from, step.clone());
+                }
+            }
+            steps.push(step);
+
+            // Stop early if a breakpoint was hit while stepping over a call
+            if self.breakpoints.contains_key(&pc) {
+                match breakpoint_in_exception(&mut core, &self.breakpoints, pc)? {
+                    Some(exception) if Some(exception) != start_exception => {
+                        self.step_abandoned = Some(abandoned_description("step", exception));
+                    }
+                    _ => (),
+                }
+                break;
+            }
+        }
+        info!("Stopped at pc = 0x{:08x}", steps.last().map_or(0, |s| s.pc));
+        self.history.record(
+            HistoryKind::Resume,
+            format!("Stepped {} instructions", steps.len()),
+        );
+
+        // Let the halt check report the new location
+        self.running = true;
+        self.resumes.resumed();
+        drop(core);
+
+        for hit in &deferred {
+            self.output("console", &hit.message("step"))?;
+        }
+
+        self.clear_temporaries();
+        Ok(Command::Response(DebugResponse::StepInstruction { steps }))
+    }
+
+    fn step_back_command(&mut self) -> Result<Command> {
+        let trace = match &mut self.instruction_trace {
+            Some(val) => val,
+            None => {
+                return Err(anyhow!(
+                    "Stepping back requires the instruction trace, set its length with `instruction-trace`"
+                ))
+            }
+        };
+        let mut core = self.session.core(0)?;
+        if !core.status()?.is_halted() {
+            return Err(anyhow!("Core must be halted"));
+        }
+        let pc = trace.step_back(&mut core)?;
+        drop(core);
+
+        info!("Stepped back to pc = 0x{:08x}", pc);
+        self.history
+            .record(HistoryKind::Resume, format!("Stepped back to {:#010x}", pc));
+
+        // Let the halt check report the new location
+        self.running = true;
+        self.resumes.resumed();
+        self.clear_temporaries();
+        Ok(Command::Response(DebugResponse::StepBack { pc }))
+    }
+
+    /// Rewinds the frame to the end of the prologue of its function with the registers the
+    /// unwinder recovered for it, the arguments kept in registers may have changed since.
+    fn restart_frame_command(&mut self, frame_id: Option<i64>) -> Result<Command> {
+        if !self.is_halted()? {
+            return Err(anyhow!("Core must be halted"));
+        }
+        let index = match frame_id {
+            Some(id) => match self.stop_ids.frame_index(id) {
+                Some(val) => val,
+                None => return Err(anyhow!("The frame {} is not part of the current stop", id)),
+            },
+            None => 0,
+        };
+        self.require_frames(Some(index + 1))?;
+        let stack_trace = self.stack_trace.as_ref().unwrap();
+        let frame = match stack_trace.get(index) {
+            Some(val) => val,
+            None => return Err(anyhow!("No stack frame {}", index)),
+        };
+        // The state of the interrupted code is in the exception frame, not in the CFI
+        if stack_trace[1..=index]
+            .iter()
+            .any(|caller| caller.state.pc.is_exception_return())
+        {
+            return Err(anyhow!(
+                "`{}` was interrupted by an exception, its registers can't be restored",
+                frame.name
+            ));
+        }
+
+        let pc = frame.state.code_address().address();
+        let function = match self.symbols.find_function(pc) {
+            Some(val) => val,
+            None => return Err(anyhow!("No function at {:#010x}", pc)),
+        };
+        let (body, _) = self.symbols.skip_prologue(function.address, function.size)?;
+        // The stack pointer is only that of the body after the prologue has run
+        if index == 0 && pc < body {
+            return Err(anyhow!("`{}` has not run past its prologue yet", frame.name));
+        }
+        let name = frame.name.clone();
+
+        let mut core = self.session.core(0)?;
+        let register_file = core.registers();
+        let pc_register = probe_rs::CoreRegisterAddress::from(register_file.program_counter());
+        let dwarf_register = |address: probe_rs::CoreRegisterAddress| {
+            self.arch.dwarf_register(address.0).unwrap_or(address.0)
+        };
+        // Without the stack pointer the body would run on the stack of another frame
+        let stack_pointer = probe_rs::CoreRegisterAddress::from(register_file.stack_pointer());
+        frame.state.register(dwarf_register(stack_pointer))?;
+        for register in register_file.registers() {
+            let address = probe_rs::CoreRegisterAddress::from(register);
+            if address.0 == pc_register.0 {
+                continue;
+            }
+            if let Ok(value) = frame.state.register(dwarf_register(address)) {
+                core.write_core_reg(address, value)?;
+            }
+        }
+        core.write_core_reg(pc_register, body)?;
+        drop(core);
+
+        info!("Restarted `{}` at pc = 0x{:08x}", name, body);
+        self.history.record(
+            HistoryKind::Resume,
+            format!("Restarted `{}` at {:#010x}", name, body),
+        );
+
+        // Nothing before the restart can be stepped back over
+        self.trace_resume("restarted a frame");
+        self.clear_temporaries();
+        Ok(Command::Response(DebugResponse::RestartFrame {
+            function: name,
+            pc: body,
+        }))
+    }
+
+    fn goto_targets_command(&mut self, source_file: &str, line: u64) -> Result<Command> {
+        let targets = self
+            .symbols
+            .line_addresses(source_file, line)?
+            .into_iter()
+            .map(|address| {
+                let function = self.symbols.find_function(address);
+                (address, function.map(|symbol| symbol.demangled))
+            })
+            .collect();
+
+        Ok(Command::Response(DebugResponse::GotoTargets { targets }))
+    }
+
+    /// Moves the pc without running anything, the stack and the registers are left as they are.
+    fn goto_command(&mut self, address: u32) -> Result<Command> {
+        let address = CodeAddress::new(address).address();
+        let mut core = self.session.core(0)?;
+        if !core.status()?.is_halted() {
+            return Err(anyhow!("Core must be halted"));
+        }
+        let pc = core.read_core_reg(core.registers().program_counter())?;
+
+        let current = self.symbols.find_function(pc);
+        let target = self.symbols.find_function(address);
+        let warning = match (&current, &target) {
+            (Some(current), Some(target)) if current.address == target.address => None,
+            (Some(current), Some(target)) => Some(format!(
+                "{:#010x} is in `{}`, the frame is still the one of `{}`",
+                address, target.demangled, current.demangled
+            )),
+            (_, None) => Some(format!("{:#010x} is not in a function", address)),
+            (None, Some(target)) => Some(format!(
+                "{:#010x} is in `{}`, the current frame has no function",
+                address, target.demangled
+            )),
+        };
+        core.write_core_reg(core.registers().program_counter().into(), address)?;
+        drop(core);
+
+        if let Some(warning) = &warning {
+            warn!("{}", warning);
+        }
+        info!("Jumped to pc = 0x{:08x}", address);
+        self.history
+            .record(HistoryKind::Resume, format!("Jumped to {:#010x}", address));
+
+        // Nothing before the jump can be stepped back over
+        self.trace_resume("jumped");
+        self.clear_temporaries();
+        Ok(Command::Response(DebugResponse::Goto {
+            pc: address,
+            warning,
+        }))
+    }
+
+    fn trace_resume(&mut self, reason: &str) {
+        if let Some(trace) = &mut self.instruction_trace {
+            trace.record_resume(reason);
+        }
+    }
+
+    fn until_command(&mut self) -> Result<Command> {
+        let mut core = self.session.core(0)?;
+        let status = core.status()?;
+        if !status.is_halted() {
+            return Err(anyhow!("Core must be halted"));
+        }
+        let pc = core.read_core_reg(core.registers().program_counter())?;
+        drop(core);
+
+        // Every line of assembly is one instruction, running until the next line could run a loop
+        if self.symbols.is_assembly(pc).unwrap_or(false) {
+            info!("Stepping over the assembly instruction at {:#010x}", pc);
+            self.step_instruction_command(1, true)?;
+            return Ok(Command::Response(DebugResponse::Until));
+        }
+
+        self.require_frames(Some(1))?;
+        let stack_trace = self.stack_trace.as_ref().unwrap();
+        let frame = match stack_trace.first() {
+            Some(val) => val,
+            None => return Err(anyhow!("No stack frame")),
+        };
+        let start_line = match frame.source.line {
+            Some(val) => val.get(),
+            None => return Err(anyhow!("No line information for the current location")),
+        };
+        let function = frame.name.clone();
+
+        let mut core = self.session.core(0)?;
+        let cfa = match frame.state.cfa {
+            Some(val) => val,
+            None => core.read_core_reg(core.registers().stack_pointer())?,
+        };
+
+        // Breakpoint on the return address as a backstop in case the frame returns while running
+        // over a call.
+        let (return_address, temporary_breakpoint) = match stack_trace.get(1) {
+            Some(caller) => {
+                let return_address = caller.state.pc.address();
+                let temporary_breakpoint = !self.breakpoints.contains_key(&return_address)
+                    && (self.breakpoints.len() as u32) < core.get_available_breakpoint_units()?;
+                if temporary_breakpoint {
+                    core.set_hw_breakpoint(return_address)?;
+                }
+                (return_address, temporary_breakpoint)
+            }
+            None => (0, false),
+        };
+        drop(core);
+
+        info!("Running until a line after {} in `{}`", start_line, function);
+        self.until = Some(UntilState {
+            function,
+            start_line,
+            cfa,
+            return_address,
+            temporary_breakpoint,
+            module: self.symbols.module_index(pc),
+        });
+        self.clear_temporaries();
+        self.trace_resume("ran until a later line");
+
+        Ok(Command::Response(DebugResponse::Until))
+    }
+
+    fn until_step(&mut self, sender: &mut Sender<Command>) -> Result<()> {
+        let until = match &self.until {
+            Some(val) => val.clone(),
+            None => return Ok(()),
+        };
+
+        let mut core = self.session.core(0)?;
+        let mut deferred = vec![];
+        let start_exception = active_exception(&mut core)?;
+        let pc = step_instruction(
+            &mut core,
+            &self.capstone,
+            &self.breakpoints,
+            true,
+            match self.step_breakpoints {
+                StepBreakpoints::Defer => Some(&mut deferred),
+                StepBreakpoints::Stop => None,
+            },
+            &self.cancel,
+        )?;
+        let sp = core.read_core_reg(core.registers().stack_pointer())?;
+        let exception = breakpoint_in_exception(&mut core, &self.breakpoints, pc)?;
+        drop(core);
+
+        for hit in &deferred {
+            self.output("console", &hit.message("until"))?;
+        }
+
+        let reason = if self.breakpoints.contains_key(&pc) {
+            match exception {
+                Some(exception) if Some(exception) != start_exception => {
+                    self.step_abandoned = Some(abandoned_description("until", exception));
+                }
+                _ => (),
+            }
+            HaltReason::Breakpoint
+        } else if sp >= until.cfa || pc == until.return_address {
+            // The frame has returned
+            HaltReason::Step
+        } else if until.left_module(self.symbols.module_index(pc)) {
+            HaltReason::Step
+        } else {
+            let debug_info = self.symbols.module_for_address(pc)?;
+            let source_info = SourceInformation::get_from_address(
+                &debug_info.dwarf,
+                debug_info.to_link_address(pc) as u64,
+                &self.cwd,
+            )?;
+            match source_info.line {
+                Some(line) if line.get() > until.start_line => HaltReason::Step,
+                _ => return Ok(()),
+            }
+        };
+
+        self.cancel_until()?;
+        let hit_breakpoint_ids = breakpoint_ids(&breakpoints_at(
+            &self.breakpoints,
+            &self.shared_breakpoints,
+            pc,
+        ))?;
+        self.history.record_stop(
+            HistoryKind::Stop,
+            format!("Halted at {:#010x} after until, reason: {:?}", pc, reason),
+            &format!("{:?}", reason).to_lowercase(),
+            &hit_breakpoint_ids,
+        );
+        sender.send(Command::Event(DebugEvent::Halted {
+            pc,
+            reason,
+            hit_breakpoint_ids: Some(hit_breakpoint_ids),
+            description: self.step_abandoned.take(),
+            resume: self.resumes.current(),
+        }))?;
+
+        Ok(())
+    }
+
+    fn cancel_until(&mut self) -> Result<()> {
+        if let Some(until) = self.until.take() {
+            if until.temporary_breakpoint {
+                let mut core = self.session.core(0)?;
+                core.clear_hw_breakpoint(until.return_address)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn continue_command(&mut self) -> Result<Command> {
+        self.cancel_until()?;
+        let mut core = self.session.core(0)?;
+        let mut status = core.status()?;
+
+        if status.is_halted() {
+            let pc = continue_fix(&mut core, &self.breakpoints)?;
+            core.run()?;
+            if let Some(trace) = &mut self.instruction_trace {
+                trace.record_resume("continued");
+            }
+            self.running = true;
+            self.resumes.resumed();
+            status = core.status()?;
+            self.history
+                .record(HistoryKind::Resume, format!("Continued from {:#010x}", pc));
+
+            drop(core);
+
+            self.clear_temporaries();
+        }
+
+        info!("Core status: {:?}", status);
+
+        Ok(Command::Response(DebugResponse::Continue))
+    }
+
+    fn continue_for_command(&mut self, millis: u64) -> Result<Command> {
+        if millis == 0 {
+            return Err(anyhow!("The run time must be at least 1 ms"));
+        }
+        if !self.session.core(0)?.status()?.is_halted() {
+            return Err(anyhow!("Core must be halted"));
+        }
+        self.continue_command()?;
+        self.timed_run = Some(TimedRun::new(Duration::from_millis(millis)));
+
+        Ok(Command::Response(DebugResponse::ContinueFor { millis }))
+    }
+
+    fn finish_command(&mut self) -> Result<Command> {
+        self.cancel_until()?;
+        let mut core = self.session.core(0)?;
+        let status = core.status()?;
+        drop(core);
+
+        if !status.is_halted() {
+            return Err(anyhow!("Core must be halted"));
+        }
+
+        // The caller is where finish stops
+        self.require_frames(Some(2))?;
+        let stack_trace = self.stack_trace.as_ref().unwrap();
+        if stack_trace.len() < 2 {
+            return Err(anyhow!("Can't finish the outermost frame"));
+        }
+
+        let pc = stack_trace[0].state.pc.address();
+        let function = stack_trace[0].name.clone();
+        let return_address = stack_trace[1].state.pc.address();
+        // The stack pointer of the caller at the return address, the CFA of the finished frame
+        let caller_stack_pointer = match self.registers.stack_pointer_register {
+            Some(register) => stack_trace[1].state.register(register as u16).ok(),
+            None => None,
+        };
+        let caller_stack_pointer = match caller_stack_pointer.or(stack_trace[0].state.cfa) {
+            Some(val) => val,
+            None => return Err(anyhow!("The stack pointer of the caller is not known")),
+        };
+
+        let module = self.symbols.module_for_address(pc)?;
+        let return_type = function_return_type(&module.dwarf, module.to_link_address(pc))?;
+        let hard_float = module.hard_float;
+        let endianness = module.endianness;
+
+        let mut core = self.session.core(0)?;
+        let temporary_breakpoint = !self.breakpoints.contains_key(&return_address);
+        if temporary_breakpoint {
+            if self.breakpoints.len() as u32 >= core.get_available_breakpoint_units()? {
+                return Err(anyhow!("No hardware breakpoint available for finish"));
+            }
+            core.set_hw_breakpoint(return_address)?;
+        }
+        drop(core);
+
+        self.finish = Some(FinishState {
+            function: function.clone(),
+            return_address,
+            caller_stack_pointer,
+            return_type,
+            hard_float,
+            endianness,
+            temporary_breakpoint,
+        });
+        self.continue_command()?;
+
+        Ok(Command::Response(DebugResponse::Finish { function }))
+    }
+
+    /// Replaces the function breakpoints, the other breakpoints are kept.
+    fn set_function_breakpoints_command(
+        &mut self,
+        function_breakpoints: Vec<FunctionBreakpoint>,
+    ) -> Result<Command> {
+        let _ = self.symbols.main()?;
+
+        let mut core = self.session.core(0)?;
+        for address in self.function_breakpoints.keys() {
+            if self.instruction_breakpoints.contains_key(address) {
+                continue;
+            }
+            if self.breakpoints.remove(address).is_some() {
+                core.clear_hw_breakpoint(*address)?;
+            }
+            self.breakpoint_states.remove(address);
+        }
+        self.function_breakpoints = HashMap::new();
+
+        let mut breakpoints = vec![];
+        for bkpt in function_breakpoints {
+            let unverified = |message: String| Breakpoint {
+                id: None,
+                verified: false,
+                message: Some(message),
+                source: None,
+                line: None,
+                column: None,
+                end_line: None,
+                end_column: None,
+            };
+            let options = parse_breakpoint_options(
+                bkpt.condition.as_deref(),
+                bkpt.hit_condition.as_deref(),
+                None,
+            );
+            let (condition, hit_condition) = match options {
+                Ok((condition, hit_condition, _)) => (condition, hit_condition),
+                Err(err) => {
+                    breakpoints.push(unverified(err.to_string()));
+                    continue;
+                }
+            };
+            let locations = resolve_function(&self.symbols, &bkpt.name);
+            let id = match locations.first() {
+                Some(location) => location.address,
+                None => {
+                    breakpoints.push(unverified(format!("No function named `{}`", bkpt.name)));
+                    continue;
+                }
+            };
+
+            let mut planted = vec![];
+            for location in &locations {
+                let mut breakpoint = Breakpoint {
+                    id: Some(id as i64),
+                    verified: true,
+                    message: None,
+                    source: location.source.as_ref().map(|(path, _)| debugserver_types::Source {
+                        name: PathBuf::from(path)
+                            .file_name()
+                            .map(|name| name.to_string_lossy().to_string()),
+                        path: Some(path.clone()),
+                        source_reference: None,
+                        presentation_hint: None,
+                        origin: None,
+                        sources: None,
+                        adapter_data: None,
+                        checksums: None,
+                    }),
+                    line: location.source.as_ref().map(|(_, line)| *line as i64),
+                    column: None,
+                    end_line: None,
+                    end_column: None,
+                };
+                if self.breakpoints.contains_key(&location.address) {
+                    // Stops there already
+                    planted.push((location, breakpoint));
+                } else if let Err(reason) = self.breakpoint_unit.check(location.address) {
+                    warn!("No breakpoint in `{}`: {}", location.function, reason);
+                } else if self.breakpoints.len() < core.get_available_breakpoint_units()? as usize
+                {
+                    core.set_hw_breakpoint(location.address)?;
+                    self.breakpoints.insert(location.address, breakpoint.clone());
+                    self.function_breakpoints
+                        .insert(location.address, breakpoint.clone());
+                    planted.push((location, breakpoint));
+                }
+            }
+
+            let mut breakpoint = match planted.first() {
+                Some((_, breakpoint)) => breakpoint.clone(),
+                None => {
+                    breakpoints.push(unverified(format!(
+                        "No breakpoint unit left for `{}`",
+                        bkpt.name
+                    )));
+                    continue;
+                }
+            };
+            if locations.len() > 1 {
+                let functions: Vec<&str> = planted
+                    .iter()
+                    .map(|(location, _)| location.function.as_str())
+                    .collect();
+                breakpoint.message = Some(format!(
+                    "Set in {} of {} functions: {}",
+                    planted.len(),
+                    locations.len(),
+                    functions.join(", ")
+                ));
+            }
+            self.breakpoint_states
+                .entry(id)
+                .or_default()
+                .set_options(condition, hit_condition, None);
+            info!(
+                "Function breakpoint on `{}` set in {} functions",
+                bkpt.name,
+                planted.len()
+            );
+            self.history.record(
+                HistoryKind::Breakpoint,
+                format!("Set function breakpoint on `{}`", bkpt.name),
+            );
+            breakpoints.push(breakpoint);
+        }
+
+        Ok(Command::Response(DebugResponse::SetFunctionBreakpoints {
+            breakpoints,
+        }))
+    }
+
+    /// The locations in the lines where a breakpoint can go, the columns bound the first and the
+    /// last line.
+    fn breakpoint_locations_command(
+        &mut self,
+        source_file: &str,
+        line: u64,
+        column: Option<u64>,
+        end_line: Option<u64>,
+        end_column: Option<u64>,
+    ) -> Result<Command> {
+        let end_line = end_line.unwrap_or(line);
+        let locations = self
+            .symbols
+            .breakpoint_locations(source_file, line..=end_line)?
+            .into_iter()
+            .filter(|location| match (column, location) {
+                (Some(column), (l, Some(c))) => *l != line || *c >= column,
+                _ => true,
+            })
+            .filter(|location| match (end_column, location) {
+                (Some(end_column), (l, Some(c))) => *l != end_line || *c <= end_column,
+                _ => true,
+            })
+            .collect();
+
+        Ok(Command::Response(DebugResponse::BreakpointLocations {
+            locations,
+        }))
+    }
+
+    /// Replaces the instruction breakpoints, the other breakpoints are kept.
+    fn set_instruction_breakpoints_command(
+        &mut self,
+        instruction_breakpoints: Vec<InstructionBreakpoint>,
+    ) -> Result<Command> {
+        let mut core = self.session.core(0)?;
+        for address in self.instruction_breakpoints.keys() {
+            if self.function_breakpoints.contains_key(address) {
+                continue;
+            }
+            if self.breakpoints.remove(address).is_some() {
+                core.clear_hw_breakpoint(*address)?;
+            }
+            self.breakpoint_states.remove(address);
+        }
+        self.instruction_breakpoints = HashMap::new();
+
+        let mut breakpoints = vec![];
+        for bkpt in instruction_breakpoints {
+            let unverified = |message: String| Breakpoint {
+                id: None,
+                verified: false,
+                message: Some(message),
+                source: None,
+                line: None,
+                column: None,
+                end_line: None,
+                end_column: None,
+            };
+            let options = parse_breakpoint_options(
+                bkpt.condition.as_deref(),
+                bkpt.hit_condition.as_deref(),
+                None,
+            );
+            let (condition, hit_condition) = match options {
+                Ok((condition, hit_condition, _)) => (condition, hit_condition),
+                Err(err) => {
+                    breakpoints.push(unverified(err.to_string()));
+                    continue;
+                }
+            };
+            let address = match bkpt.address() {
+                Ok(address) => address,
+                Err(err) => {
+                    breakpoints.push(unverified(err.to_string()));
+                    continue;
+                }
+            };
+            if let Err(reason) = self.breakpoint_unit.check(address) {
+                breakpoints.push(unverified(reason));
+                continue;
+            }
+
+            let breakpoint = Breakpoint {
+                id: Some(address as i64),
+                verified: true,
+                message: None,
+                source: None,
+                line: None,
+                column: None,
+                end_line: None,
+                end_column: None,
+            };
+            if !self.breakpoints.contains_key(&address) {
+                if self.breakpoints.len() >= core.get_available_breakpoint_units()? as usize {
+                    breakpoints.push(unverified(format!(
+                        "No breakpoint unit left for {:#010x}",
+                        address
+                    )));
+                    continue;
+                }
+                core.set_hw_breakpoint(address)?;
+                self.breakpoints.insert(address, breakpoint.clone());
+            }
+            self.instruction_breakpoints
+                .insert(address, breakpoint.clone());
+            self.breakpoint_states
+                .entry(address)
+                .or_default()
+                .set_options(condition, hit_condition, None);
+            info!("Instruction breakpoint set at {:#010x}", address);
+            self.history.record(
+                HistoryKind::Breakpoint,
+                format!("Set instruction breakpoint at {:#010x}", address),
+            );
+            breakpoints.push(breakpoint);
+        }
+
+        Ok(Command::Response(
+            DebugResponse::SetInstructionBreakpoints { breakpoints },
+        ))
+    }
+
+    fn set_breakpoints_command(
+        &mut self,
+        source_file: String,
+        source_breakpoints: Vec<SourceBreakpoint>,
+        source: Option<debugserver_types::Source>,
+    ) -> Result<Command> {
+        let _ = self.symbols.main()?;
+        let ram_code = match self.startup.in_startup {
+            true => ram_code_ranges(&self.symbols, self.session.memory_map()),
+            false => vec![],
+        };
+
+        // Clear all existing breakpoints
+        let mut core = self.session.core(0)?;
+        core.clear_all_hw_breakpoints()?;
+        self.breakpoints = HashMap::new();
+        self.shared_breakpoints = HashMap::new();
+        self.startup.pending.clear();
+
+        // The function and instruction breakpoints are only replaced by their own requests
+        for (address, breakpoint) in self
+            .function_breakpoints
+            .iter()
+            .chain(&self.instruction_breakpoints)
+        {
+            if !self.breakpoints.contains_key(address) {
+                core.set_hw_breakpoint(*address)?;
+                self.breakpoints.insert(*address, breakpoint.clone());
+            }
+        }
+
+        let mut breakpoints = vec![];
+        for bkpt in source_breakpoints {
+            let options = parse_breakpoint_options(
+                bkpt.condition.as_deref(),
+                bkpt.hit_condition.as_deref(),
+                bkpt.log_message.as_deref(),
+            );
+            let (condition, hit_condition, log_message) = match options {
+                Ok(val) => val,
+                Err(err) => {
+                    breakpoints.push(Breakpoint {
+                        id: None,
+                        verified: false,
+                        message: Some(err.to_string()),
+                        source: source.clone(),
+                        line: Some(bkpt.line),
+                        column: bkpt.column,
+                        end_line: None,
+                        end_column: None,
+                    });
+                    continue;
+                }
+            };
+
+            let breakpoint = match self.symbols.find_breakpoint_location(
+                &self.cwd,
+                &source_file,
+                match NonZeroU64::new(bkpt.line as u64) {
+                    Some(val) => val,
+                    None => {
+                        error!("Could not convert to NonZeroU64");
+                        return Err(anyhow!("Could not convert to NonZeroU64"));
+                    }
+                },
+                bkpt.column.map(|c| NonZeroU64::new(c as u64).unwrap()),
+            )? {
+                Some(address) => {
+                    let mut breakpoint = Breakpoint {
+                        id: Some(address as i64),
+                        verified: true,
+                        message: None,
+                        source: source.clone(),
+                        line: Some(bkpt.line),
+                        column: bkpt.column,
+                        end_line: None,
+                        end_column: None,
+                    };
+
+                    // Set breakpoint
+                    if self.breakpoints.contains_key(&(address as u32)) {
+                        // Another line resolved to the same address, share its breakpoint unit
+                        let id = self.next_shared_id;
+                        self.next_shared_id -= 1;
+                        breakpoint.id = Some(id as i64);
+                        self.shared_breakpoints
+                            .entry(address as u32)
+                            .or_default()
+                            .push(breakpoint.clone());
+                        self.breakpoint_states.entry(id).or_default().set_options(
+                            condition,
+                            hit_condition,
+                            log_message,
+                        );
+                        if let Some(state) = self.breakpoint_states.get(&id) {
+                            breakpoint = state.annotate(breakpoint);
+                        }
+                        if self.startup.pending.contains(&(address as u32)) {
+                            breakpoint.verified = false;
+                            breakpoint.message = Some(PENDING_MESSAGE.to_owned());
+                        }
+                    } else if let Err(reason) = self.breakpoint_unit.check(address as u32) {
+                        breakpoint.verified = false;
+                        breakpoint.message = Some(reason);
+                    } else if self.breakpoints.len() < core.get_available_breakpoint_units()? as usize {
+                        self.breakpoints.insert(address as u32, breakpoint.clone());
+                        let pending = ram_code.iter().any(|range| range.contains(&(address as u32)));
+                        if pending {
+                            self.startup.pending.push(address as u32);
+                        } else {
+                            core.set_hw_breakpoint(address as u32)?;
+                        }
+                        self.breakpoint_states
+                            .entry(address as u32)
+                            .or_default()
+                            .set_options(condition, hit_condition, log_message);
+                        if let Some(state) = self.breakpoint_states.get(&(address as u32)) {
+                            breakpoint = state.annotate(breakpoint);
+                        }
+                        if pending {
+                            breakpoint.verified = false;
+                            breakpoint.message = Some(PENDING_MESSAGE.to_owned());
+                        }
+                    } else {
+                        breakpoint.verified = false;
+                    }
+
+                    breakpoint
+                }
+                None => Breakpoint {
+                    id: None,
+                    verified: false,
+                    message: None,
+                    source: source.clone(),
+                    line: Some(bkpt.line),
+                    column: bkpt.column,
+                    end_line: None,
+                    end_column: None,
+                },
+            };
+
+            breakpoints.push(breakpoint);
+        }
+
+        self.history.record(
+            HistoryKind::Breakpoint,
+            format!(
+                "Set {} breakpoints in {}",
+                breakpoints.iter().filter(|b| b.verified).count(),
+                source_file
+            ),
+        );
+
+        // Keep the hit and ignore counts of the breakpoints that are still set
+        let ids: Vec<u32> = self
+            .breakpoints
+            .keys()
+            .cloned()
+            .chain(self.shared_ids())
+            .collect();
+        self.breakpoint_states.retain(|id, _| ids.contains(id));
+
+        Ok(Command::Response(DebugResponse::SetBreakpoints {
+            breakpoints,
+        }))
+    }
+
+    /// Evaluates the frames up to the end of the page, the frames below it are only unwound.
+    fn dap_stack_frames(
+        &mut self,
+        start_frame: usize,
+        levels: Option<usize>,
+        format: Option<StackFrameFormat>,
+    ) -> Result<Command> {
+        self.require_frames(levels.map(|levels| start_frame.saturating_add(levels)))?;
+        let stack_frames = match &self.stack_frames {
+            Some(val) => val,
+            None => return Err(anyhow!("No stack trace")),
+        };
+        let start = start_frame.min(stack_frames.len());
+        let end = match levels {
+            Some(levels) => start.saturating_add(levels).min(stack_frames.len()),
+            None => stack_frames.len(),
+        };
+        let mut page = stack_frames[start..end].to_vec();
+        if let (Some(format), Some(stack_trace)) = (format, &self.stack_trace) {
+            for (frame, s) in page.iter_mut().zip(&stack_trace[start..end]) {
+                let module = self
+                    .symbols
+                    .module_for_address(s.state.code_address().address())
+                    .ok()
+                    .and_then(|module| module.path.file_name())
+                    .map(|name| name.to_string_lossy().into_owned());
+                frame.name = format.label(s, frame.line, module.as_deref());
+            }
+        }
+        Ok(Command::Response(DebugResponse::DAPStackFrames {
+            stack_frames: page,
+            total_frames: self.total_frames,
+        }))
+    }
+
+    fn dap_scopes(&mut self, frame_id: i64) -> Result<Command> {
+        match &self.scopes {
+            Some(scopes) => Ok(Command::Response(DebugResponse::DAPScopes {
+                scopes: match scopes.get(&frame_id) {
+                    Some(val) => val,
+                    None => {
+                        error!("Could not find scope");
+                        return Err(anyhow!("Could not find scope"));
+                    }
+                }
+                .clone(),
+            })),
+            None => {
+                self.set_stack_trace(None)?;
+                self.set_stack_frames()?;
+                self.dap_scopes(frame_id)
+            }
+        }
+    }
+
+    fn dap_variables(&mut self, vars_id: i64) -> Result<Command> {
+        match &self.variables {
+            Some(variables) => {
+                let variables = match variables.get(&vars_id) {
+                    Some(val) => val.clone(),
+                    None => {
+                        error!("Missing variables");
+                        return Err(anyhow!("Missing variables"));
+                    }
+                };
+                // Shown by the frontend, read ahead on the next stop
+                self.requested_ranges.record(&variables);
+                Ok(Command::Response(DebugResponse::DAPVariables { variables }))
+            }
+            None => {
+                self.set_stack_trace(None)?;
+                self.set_stack_frames()?;
+                self.dap_variables(vars_id)
+            }
+        }
+    }
+
+    /// Whether the top `depth` frames are evaluated, all the frames if None.
+    fn has_frames(&self, depth: Option<usize>) -> bool {
+        match &self.stack_trace {
+            Some(stack_trace) => {
+                let depth = depth.unwrap_or(self.total_frames).min(self.total_frames);
+                stack_trace.len() >= depth
+            }
+            None => false,
+        }
+    }
+
+    /// Evaluates the stack again if fewer than `depth` frames are, all the frames if None.
+    fn require_frames(&mut self, depth: Option<usize>) -> Result<()> {
+        if !self.has_frames(depth) {
+            self.set_stack_trace(depth)?;
+            self.set_stack_frames()?;
+        }
+        Ok(())
+    }
+
+    /// Unwinds the whole stack, but only evaluates the top `depth` frames, all if None.
+    fn set_stack_trace(&mut self, depth: Option<usize>) -> Result<()> {
+        self.watchdog.heartbeat();
+        if let Some(dump) = self.session.core_dump() {
+            for register in &dump.header.registers {
+                self.registers
+                    .add_register_value(register.address, register.value);
+            }
+
+            let pc = match dump.register(15) {
+                Some(val) => val,
+                None => return Err(anyhow!("The core dump has no program counter")),
+            };
+            let mut memory = CoreDumpMemory { dump };
+            let (stack_trace, total_frames) = unwind_stack(
+                &self.symbols,
+                pc,
+                self.registers.clone(),
+                &mut memory,
+                &self.cwd,
+                depth,
+                &self.cancel,
+            )?;
+            self.total_frames = total_frames;
+            let mut stack_trace = resolve_stack_trace(stack_trace)?;
+            name_assembly_frames(&self.symbols, &mut stack_trace);
+            if let Err(err) = recover_optimized_out(&self.symbols, &mut stack_trace, &mut memory) {
+                warn!("Failed to evaluate the optimized out variables: {}", err);
+            }
+            for frame in &mut stack_trace {
+                self.formats.apply(&mut frame.variables);
+                self.formats.apply(&mut frame.arguments);
+            }
+            self.stack_trace = Some(stack_trace);
+
+            return Ok(());
+        }
+
+        let image = match self.live_flash_reads {
+            true => None,
+            false => Some(FlashImage::new(&self.symbols, self.session.memory_map())),
+        };
+        let core = self.session.core(0)?;
+        let memory = self.stop_cache.as_ref().map(|cache| &cache.memory);
+        let mut my_core = MyCore {
+            core,
+            image,
+            memory,
+        };
+
+        read_and_add_registers(&mut my_core.core, &mut self.registers)?;
+
+        let pc = my_core
+            .core
+            .read_core_reg(my_core.core.registers().program_counter())?;
+        let (stack_trace, total_frames) = unwind_stack(
+            &self.symbols,
+            pc,
+            self.registers.clone(),
+            &mut my_core,
+            &self.cwd,
+            depth,
+            &self.cancel,
+        )?;
+        self.total_frames = total_frames;
+        let mut stack_trace = resolve_stack_trace(stack_trace)?;
+        name_assembly_frames(&self.symbols, &mut stack_trace);
+        if let Err(err) = recover_optimized_out(&self.symbols, &mut stack_trace, &mut my_core) {
+            warn!("Failed to evaluate the optimized out variables: {}", err);
+        }
+        if let Some(image) = &my_core.image {
+            for frame in &mut stack_trace {
+                image.mark_read_only(&mut frame.variables);
+                image.mark_read_only(&mut frame.arguments);
+            }
+        }
+        for frame in &mut stack_trace {
+            self.formats.apply(&mut frame.variables);
+            self.formats.apply(&mut frame.arguments);
+        }
+        self.stack_trace = Some(stack_trace);
+
+        Ok(())
+    }
+
+    fn set_stack_frames(&mut self) -> Result<()> {
+        let mut stack_frames = vec![];
+        let mut scopes = HashMap::new();
+        let mut variables = HashMap::new();
+
+        let mut vars = vec![];
+
+        // The frames whose file isn't on disk get a generated source
+        let mut generated = vec![];
+        let code_addresses: Vec<u32> = self
+            .stack_trace
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|s| s.state.code_address().address())
+            .collect();
+        for address in code_addresses {
+            generated.push(self.generated_source(address)?);
+        }
+
+        for (index, s) in self.stack_trace.as_ref().unwrap().iter().enumerate() {
+            // The caller frames are at their return address, symbolize them at the call
+            let code_address = s.state.code_address();
+            let debug_info = self.symbols.module_for_address(code_address.address())?;
+            let source_info = SourceInformation::get_from_address(
+                &debug_info.dwarf,
+                debug_info.to_link_address(code_address.address()) as u64,
+                &self.cwd,
+            )?;
+            let (source_reference, listing_name, listing_line) = match &generated[index] {
+                Some((reference, Some((name, line)))) => {
+                    (Some(*reference), Some(name.clone()), Some(*line))
+                }
+                Some((reference, None)) => (Some(*reference), None, None),
+                None => (None, None, None),
+            };
+
+            let id = self.stop_ids.frame_id(index)?;
+            {
+                let mut scope = vec![];
+                let source = debugserver_types::Source {
+                    // TODO: Make path os independent?
+                    name: source_info.file.clone(),
+                    path: match &source_info.directory {
+                        Some(dir) => match &source_info.file {
+                            Some(file) => Some(format!("{}/{}", dir, file)),
+                            None => None,
+                        },
+                        None => None,
+                    },
+                    source_reference,
+                    presentation_hint: None,
+                    origin: None,
+                    sources: None,
+                    adapter_data: None,
+                    checksums: None,
+                };
+                {
+                    let (indexed, named) = get_num_diff_children(&s.variables);
+                    let scope_id = self.stop_ids.scope_reference(index, ScopeKind::Locals)?;
+                    scope.push(debugserver_types::Scope {
+                        column: source_info.column.map(|v| v.get() as i64),
+                        end_column: None,
+                        end_line: None,
+                        expensive: false,
+                        indexed_variables: Some(indexed),
+                        named_variables: Some(named),
+                        line: source_info.line.map(|v| v.get() as i64),
+                        name: "locale".to_owned(),
+                        source: Some(source.clone()),
+                        variables_reference: scope_id,
+                    });
+                    let mut locals = s.variables.clone();
+                    self.regions.annotate_variables(&mut locals);
+                    vars.push((index, locals, scope_id));
+                }
+                {
+                    let (indexed, named) = get_num_diff_children(&s.arguments);
+                    let scope_id = self.stop_ids.scope_reference(index, ScopeKind::Arguments)?;
+                    scope.push(debugserver_types::Scope {
+                        column: source_info.column.map(|v| v.get() as i64),
+                        end_column: None,
+                        end_line: None,
+                        expensive: false,
+                        indexed_variables: Some(indexed),
+                        named_variables: Some(named),
+                        line: source_info.line.map(|v| v.get() as i64),
+                        name: "arguments".to_owned(),
+                        source: Some(source),
+                        variables_reference: scope_id,
+                    });
+                    let mut arguments = s.arguments.clone();
+                    self.regions.annotate_variables(&mut arguments);
+                    vars.push((index, arguments, scope_id));
+                }
+                {
+                    let (indexed, named) = get_num_diff_children(&s.registers);
+                    let scope_id = self.stop_ids.scope_reference(index, ScopeKind::Registers)?;
+                    scope.push(debugserver_types::Scope {
+                        column: None,
+                        end_column: None,
+                        end_line: None,
+                        expensive: false,
+                        indexed_variables: Some(indexed),
+                        named_variables: Some(named),
+                        line: None,
+                        name: "registers".to_owned(),
+                        source: None,
+                        variables_reference: scope_id,
+                    });
+                    let mut registers = s.registers.clone();
+                    self.regions.annotate_registers(&mut registers);
+                    vars.push((index, registers, scope_id));
+                }
+                scopes.insert(id, scope);
+            }
+
+            // Create Source object
+            let source = debugserver_types::Source {
+                name: source_info.file.clone(),
+                path: match &source_info.directory {
+                    // TODO: Make path os independent?
+                    Some(dir) => match &source_info.file {
+                        Some(file) => Some(format!("{}/{}", dir, file)),
+                        None => None,
+                    },
+                    None => None,
+                },
+                source_reference,
+                presentation_hint: None,
+                origin: None,
+                sources: None,
+                adapter_data: None,
+                checksums: None,
+            };
+            // A listing has no path, the frame is on the line of its instruction
+            let (source, line, column) = match (listing_name, listing_line) {
+                (Some(name), Some(line)) => (
+                    debugserver_types::Source {
+                        name: Some(name),
+                        path: None,
+                        ..source
+                    },
+                    line,
+                    1,
+                ),
+                _ => (
+                    source,
+                    source_info.line.map_or(1, |v| v.get() as i64),
+                    source_info.column.map_or(1, |v| v.get() as i64),
+                ),
+            };
+
+            // Crate and add StackFrame object
+            stack_frames.push(debugserver_types::StackFrame {
+                id: id,
+                name: s.name.clone(),
+                source: Some(source),
+                line,
+                column,
+                end_column: None,
+                end_line: None,
+                module_id: None,
+                presentation_hint: Some("normal".to_owned()),
+            });
+        }
+        // The references of a frame are handed out in the same order every time
+        let mut arenas = HashMap::new();
+        for (index, vs, sid) in vars {
+            let arena = match arenas.entry(index) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(self.stop_ids.variables(index)?),
+            };
+            set_variables(&mut variables, vs, sid, arena)?;
+        }
+
+        self.stack_frames = Some(stack_frames);
+        self.scopes = Some(scopes);
+        self.variables = Some(variables);
+        Ok(())
+    }
+
+    /// The source reference of a frame whose file isn't on disk, with the name and the line in
+    /// the listing if its function is disassembled. None if the file is there or no source can
+    /// be made.
+    fn generated_source(&mut self, address: u32) -> Result<Option<(i64, Option<(String, i64)>)>> {
+        let debug_info = self.symbols.module_for_address(address)?;
+        let source_info = SourceInformation::get_from_address(
+            &debug_info.dwarf,
+            debug_info.to_link_address(address) as u64,
+            &self.cwd,
+        )?;
+        let path = match (&source_info.directory, &source_info.file) {
+            (Some(dir), Some(file)) => Some(format!("{}/{}", dir, file)),
+            _ => None,
+        };
+        if let Some(path) = &path {
+            if resolve_path(&self.cwd, Path::new(path)).exists() {
+                return Ok(None);
+            }
+            if self.symbols.embedded_source(path)?.is_some() {
+                let reference = self
+                    .source_references
+                    .reference(SourceContent::Embedded { path: path.clone() });
+                return Ok(Some((reference, None)));
+            }
+        }
+
+        let function = match self.symbols.find_function(address) {
+            Some(val) => val,
+            None => return Ok(None),
+        };
+        let start = function.address & !1;
+        // The stack trace is still shown without the listing
+        let instructions = match self.function_instructions(start, function.size) {
+            Ok(val) => val,
+            Err(err) => {
+                warn!("No disassembly of {}: {:#}", function.demangled, err);
+                return Ok(None);
+            }
+        };
+        let reference = self
+            .source_references
+            .reference(SourceContent::Disassembly {
+                address: start,
+                size: function.size,
+                name: function.demangled.clone(),
+            });
+        let name = format!("{}.s", function.demangled);
+        Ok(Some((
+            reference,
+            Some((name, listing_line(&instructions, address))),
+        )))
+    }
+
+    fn function_instructions(&mut self, address: u32, size: u32) -> Result<Vec<Decoded>> {
+        let code = self.read_code(address, size.max(2) as usize)?;
+        decode(&self.capstone, &code, address)
+    }
+
+    fn source_command(&mut self, source_reference: i64) -> Result<Command> {
+        let content = match self.source_references.get(source_reference) {
+            Some(val) => val.clone(),
+            None => return Err(anyhow!("No source with reference {}", source_reference)),
+        };
+        let (content, mime_type) = match content {
+            SourceContent::Embedded { path } => match self.symbols.embedded_source(&path)? {
+                Some(source) => (source, None),
+                None => return Err(anyhow!("The source of {} is no longer embedded", path)),
+            },
+            SourceContent::Disassembly {
+                address,
+                size,
+                name,
+            } => {
+                let instructions = self.function_instructions(address, size)?;
+                (listing(&name, &instructions), Some("text/x-asm".to_owned()))
+            }
+        };
+
+        Ok(Command::Response(DebugResponse::Source {
+            content,
+            mime_type,
+        }))
+    }
+
+    fn set_binary_command(&mut self, path: PathBuf) -> Result<Command> {
+        self.program = Some(resolve_path(&self.cwd, &path));
+        self.program_modified = self.program.as_deref().and_then(modified_time);
+        self.report_memory_usage()?;
+        self.report_section_map()?;
+
+        // Without any explicit symbol files the symbols are read from the flashed binary.
+        if self.symbol_files.is_empty() {
+            self.reload_symbols()?;
+        } else if let Some(program) = &self.program {
+            self.symbols.check_program(program)?;
+        }
+
+        Ok(Command::Response(DebugResponse::SetBinary))
+    }
+
+    fn memory_usage(&self) -> Result<Option<MemoryUsage>> {
+        match &self.program {
+            Some(program) => Ok(Some(MemoryUsage::new(
+                program,
+                self.session.memory_map(),
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    fn report_memory_usage(&mut self) -> Result<()> {
+        let memory_usage = match self.memory_usage()? {
+            Some(val) => val,
+            None => return Ok(()),
+        };
+
+        info!("{}", memory_usage.summary());
+        self.output("console", &memory_usage.summary())?;
+        for region in memory_usage.overflowing_regions() {
+            warn!("The binary overflows the {} region", region.name);
+            if region.used > region.size {
+                self.output(
+                    "important",
+                    &format!(
+                        "Warning: the binary needs {} bytes of {} but the region at {:#010x} is only {} bytes",
+                        region.used, region.name, region.start, region.size
+                    ),
+                )?;
+            }
+            if region.past_end > 0 {
+                self.output(
+                    "important",
+                    &format!(
+                        "Warning: a section ends {} bytes past the {} region at {:#010x}",
+                        region.past_end, region.name, region.start
+                    ),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn section_map(&self) -> Result<SectionMap> {
+        match &self.program {
+            Some(program) => SectionMap::new(program, self.session.memory_map()),
+            None => Err(anyhow!(symbols::NO_BINARY_MESSAGE)),
+        }
+    }
+
+    /// Warns about sections the linker script placed wrong, the full map is `info sections`.
+    fn report_section_map(&mut self) -> Result<()> {
+        let section_map = self.section_map()?;
+        for warning in &section_map.warnings {
+            warn!("{}", warning);
+            self.output("important", &format!("Warning: {}", warning))?;
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the allocator static, the configured symbol or the first of the default names.
+    fn heap_stats(&mut self) -> Result<HeapStats> {
+        let names: Vec<String> = match &self.heap.symbol {
+            Some(symbol) => vec![symbol.clone()],
+            None => DEFAULT_HEAP_SYMBOLS.iter().map(|name| name.to_string()).collect(),
+        };
+        let symbol = match names.iter().find_map(|name| {
+            self.symbols
+                .find_symbols(name)
+                .into_iter()
+                .find(|symbol| !symbol.is_function)
+        }) {
+            Some(val) => val,
+            None => {
+                return Err(anyhow!(
+                    "No heap static named {}, set it with `set-heap-symbol <name>`",
+                    names.join(" or ")
+                ))
+            }
+        };
+
+        // The DWARF name of a static is the last segment of its path
+        let name = match symbol.demangled.rsplit("::").next() {
+            Some(val) => val.to_owned(),
+            None => symbol.name.clone(),
+        };
+        let endianness = self.endianness();
+        let dwarf = &self.symbols.main()?.dwarf;
+        match &mut self.session {
+            Target::Probe(session) => heap_stats(
+                dwarf,
+                &mut MyCore {
+                    core: session.core(0)?,
+                    image: None,
+                    memory: None,
+                },
+                endianness,
+                &name,
+                symbol.address,
+            ),
+            Target::CoreDump(dump) => heap_stats(
+                dwarf,
+                &mut CoreDumpMemory { dump },
+                endianness,
+                &name,
+                symbol.address,
+            ),
+        }
+    }
+
+    /// Only read while halted, the active exception is read from a core register.
+    fn fault_status(&mut self) -> Result<FaultStatus> {
+        require_cortex_m(self.arch, "The fault status")?;
+        let mut core = self.session.core(0)?;
+        if !core.core_halted()? {
+            return Err(anyhow!("Core must be halted"));
+        }
+        FaultStatus::read(&mut core)
+    }
+
+    fn vector_table_info(&mut self) -> Result<VectorTableInfo> {
+        require_cortex_m(self.arch, "The vector table")?;
+        let endianness = self.endianness();
+        let _ = self.symbols.main()?;
+        let elf_tables = ElfVectorTable::read_all(&self.symbols, endianness)?;
+        let symbols = &self.symbols;
+        match &mut self.session {
+            Target::Probe(session) => vector_table_info(
+                &mut MyCore {
+                    core: session.core(0)?,
+                    image: None,
+                    memory: None,
+                },
+                endianness,
+                symbols,
+                &elf_tables,
+            ),
+            Target::CoreDump(dump) => {
+                vector_table_info(&mut CoreDumpMemory { dump }, endianness, symbols, &elf_tables)
+            }
+        }
+    }
+
+    fn intrusiveness_report(&self) -> IntrusivenessReport {
+        self.intrusiveness.report(self.polling.status_interval)
+    }
+
+    fn self_test_command(&mut self) -> Result<SelfTestReport> {
+        let session = match &mut self.session {
+            Target::Probe(session) => session,
+            Target::CoreDump(_) => {
+                return Err(anyhow!("There is no probe in a core dump session"))
+            }
+        };
+        require_cortex_m(self.arch, "The self test")?;
+        let report = self_test(session, self.probe.as_ref());
+        info!("{}", report);
+        match &report.advice {
+            Some(advice) => self
+                .history
+                .record(HistoryKind::Error, format!("Self test: {}", advice)),
+            None => self
+                .history
+                .record(HistoryKind::Attach, "Self test passed".to_owned()),
+        };
+        Ok(report)
+    }
+
+    fn session_summary(
+        &mut self,
+        probe: Option<ProbeDetails>,
+        core_dump: Option<PathBuf>,
+    ) -> Result<SessionSummary> {
+        let binary = self.symbols.main().ok().map(|module| module.path.clone());
+        let build_id = match &binary {
+            Some(path) => symbols::build_id(path)?,
+            None => None,
+        };
+
+        let (core_type, breakpoint_units, matches_flash) = match &mut self.session {
+            Target::Probe(session) => {
+                let core_type = format!("{:?}", session.target().core_type);
+                let image = FlashImage::new(&self.symbols, &session.target().memory_map);
+                let sample = image.first_flash_bytes(FLASH_CHECK_BYTES);
+                let mut core = session.core(0)?;
+                if self.arch.has_cortex_m_debug() {
+                    self.breakpoint_unit = BreakpointUnit::read(&mut core);
+                }
+                let matches_flash = match sample {
+                    Some((address, expected)) => {
+                        let mut flashed = vec![0u8; expected.len()];
+                        core.read_8(address, &mut flashed)?;
+                        Some(flashed == expected)
+                    }
+                    None => None,
+                };
+                (
+                    Some(core_type),
+                    Some((core.get_available_breakpoint_units()?, self.breakpoint_unit)),
+                    matches_flash,
+                )
+            }
+            Target::CoreDump(_) => (None, None, None),
+        };
+
+        Ok(SessionSummary {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            probe,
+            chip: self.session.chip_name(),
+            core_type,
+            breakpoint_units,
+            binary,
+            build_id,
+            matches_flash,
+            core_dump,
+            core_clock: self.core_clock,
+        })
+    }
+
+    /// Measures the core clock and keeps it for converting cycles to time, see `core_clock`.
+    /// The clock set before is kept if it can't be measured.
+    fn calibrate_clock_command(&mut self, interval_ms: Option<u64>) -> Result<Command> {
+        require_cortex_m(self.arch, "The clock calibration")?;
+        let interval = match interval_ms {
+            Some(ms) => Duration::from_millis(ms),
+            None => DEFAULT_CALIBRATION_INTERVAL,
+        };
+        let mut core = self.session.core(0)?;
+        let halted_at = match core.core_halted()? {
+            true => Some(core.read_core_reg(core.registers().program_counter())?),
+            false => None,
+        };
+        let result = calibrate(&mut core, &self.breakpoints, interval, &self.cancel);
+
+        // A halted core ran for the calibration, the state read at the stop is stale
+        if let Some(pc) = halted_at {
+            let new_pc = core.read_core_reg(core.registers().program_counter())?;
+            drop(core);
+            if new_pc != pc {
+                self.clear_temporaries();
+                self.resumes.resumed();
+                self.sender.send(Command::Event(DebugEvent::Halted {
+                    pc: new_pc,
+                    reason: HaltReason::Request,
+                    hit_breakpoint_ids: None,
+                    description: Some("Halted after the clock calibration".to_owned()),
+                    resume: self.resumes.current(),
+                }))?;
+            }
+        }
+
+        let response = match (result, self.core_clock) {
+            (Ok(clock), _) => {
+                info!("Core clock calibrated: {}", clock);
+                self.history
+                    .record(HistoryKind::Attach, format!("Core clock calibrated: {}", clock));
+                self.core_clock = Some(clock);
+                DebugResponse::CalibrateClock {
+                    clock,
+                    warning: None,
+                }
+            }
+            (Err(err), _) if err.is::<Cancelled>() => return Err(err),
+            (Err(err), Some(clock)) => DebugResponse::CalibrateClock {
+                clock,
+                warning: Some(err.to_string()),
+            },
+            (Err(err), None) => return Err(err),
+        };
+
+        Ok(Command::Response(response))
     }
 
-    fn dap_variables(&mut self, vars_id: i64) -> Result<Command> {
-        match &self.variables {
-            Some(variables) => Ok(Command::Response(DebugResponse::DAPVariables {
-                variables: match variables.get(&vars_id) {
-                    Some(val) => val.clone(),
-                    None => {
-                        error!("Missing variables");
-                        return Err(anyhow!("Missing variables"));
-                    }
-                },
-            })),
-            None => {
-                self.set_stack_trace()?;
-                self.set_stack_frames()?;
-                self.dap_stack_frames()
-            }
+    /// The byte order of the target, little endian if no binary is loaded.
+    fn endianness(&self) -> Endianness {
+        match self.symbols.main() {
+            Ok(module) => module.endianness,
+            Err(_) => Endianness::default(),
         }
     }
 
-    pub fn set_variables(
-        &mut self,
-        variables: &mut HashMap<i64, Vec<Variable>>,
-        mut children: Vec<Variable>,
-        id: i64,
-    ) -> Result<()> {
-        for child in &mut children {
-            if child.children.len() > 0 {
-                child.id = self.id_gen.gen();
-                self.set_variables(variables, child.children.clone(), child.id)?;
+    /// Resolves the default and configured catch functions in the current symbols, the exception
+    /// filters replace the panic and hard fault defaults.
+    fn resolve_catches(&mut self) -> Result<()> {
+        self.lift_catches()?;
+        let defaults = DEFAULT_CATCH_FUNCTIONS.iter().map(|function| function.to_string());
+        let functions: Vec<String> = match &self.exception_filters {
+            Some(filters) => defaults
+                .filter(|function| !FILTERED_CATCH_FUNCTIONS.contains(&function.as_str()))
+                .chain(filter_catch_functions(filters))
+                .chain(self.catch_functions.iter().cloned())
+                .collect(),
+            None => defaults.chain(self.catch_functions.iter().cloned()).collect(),
+        };
+        let (mut catches, missing) = TrapCatches::resolve(&self.symbols, &functions);
+        if let Some(filters) = &self.exception_filters {
+            catches.catch_faults(filters);
+        }
+        self.catches = catches;
+
+        // The default functions are often not linked in, only warn about the configured ones
+        if !self.symbols.is_empty() {
+            for function in missing.iter().filter(|f| self.catch_functions.contains(f)) {
+                self.output(
+                    "console",
+                    &format!("Can't catch `{}`, it isn't in the binary", function),
+                )?;
             }
         }
-        variables.insert(id, children.clone());
+        self.plant_catches()
+    }
 
+    fn lift_catches(&mut self) -> Result<()> {
+        if let Target::Probe(session) = &mut self.session {
+            let mut core = session.core(0)?;
+            self.catches.lift(&mut core)?;
+            self.reset_catch.lift(&mut core)?;
+        }
         Ok(())
     }
 
-    fn set_stack_trace(&mut self) -> Result<()> {
-        let core = self.session.core(0)?;
-        let mut my_core = MyCore { core };
-
-        read_and_add_registers(&mut my_core.core, &mut self.registers)?;
-        let stack_trace = rust_debug::call_stack::stack_trace(
-            self.debug_info.dwarf,
-            self.debug_info.debug_frame,
-            self.registers.clone(),
-            &mut my_core,
-            &self.cwd,
-        )?;
-        self.stack_trace = Some(resolve_stack_trace(stack_trace)?);
-
+    fn plant_catches(&mut self) -> Result<()> {
+        let skipped = match &mut self.session {
+            Target::Probe(session) => {
+                let mut core = session.core(0)?;
+                self.reset_catch.plant(&mut core)?;
+                self.catches.plant(&mut core, &self.breakpoints)?
+            }
+            _ => return Ok(()),
+        };
+        if !skipped.is_empty() {
+            self.output(
+                "console",
+                &format!(
+                    "Warning: no free hardware breakpoint to catch {}, the user breakpoints use them",
+                    skipped.join(", ")
+                ),
+            )?;
+        }
         Ok(())
     }
 
-    fn set_stack_frames(&mut self) -> Result<()> {
-        let mut stack_frames = vec![];
-        let mut scopes = HashMap::new();
-        let mut variables = HashMap::new();
+    fn output(&self, category: &str, output: &str) -> Result<()> {
+        self.sender.send(Command::Event(DebugEvent::Output {
+            category: category.to_owned(),
+            output: output.to_owned(),
+        }))?;
+        Ok(())
+    }
 
-        let mut vars = vec![];
+    /// Nothing is swapped unless all the files of a target could be read. The debugger has no
+    /// SVD or source map support, `all` only reloads the formatters.
+    fn reload_config_command(&mut self, what: ReloadTarget) -> Result<Command> {
+        let reloaded = match what {
+            ReloadTarget::Svd => {
+                return Err(anyhow!("No SVD file is used, there is nothing to reload"))
+            }
+            ReloadTarget::SourceMap => {
+                return Err(anyhow!("No source map is used, there is nothing to reload"))
+            }
+            ReloadTarget::Formatters | ReloadTarget::All => {
+                vec![format!("formatters: {}", self.reload_formats()?)]
+            }
+        };
 
-        for s in self.stack_trace.as_ref().unwrap() {
-            let source_info = SourceInformation::get_from_address(
-                self.debug_info.dwarf,
-                s.call_frame.code_location as u64,
-                &self.cwd,
-            )?;
+        info!("Reloaded {}", reloaded.join(", "));
+        Ok(Command::Response(DebugResponse::ReloadConfig { reloaded }))
+    }
 
-            let id = self.id_gen.gen();
-            {
-                let mut scope = vec![];
-                let source = debugserver_types::Source {
-                    // TODO: Make path os independent?
-                    name: source_info.file.clone(),
-                    path: match &source_info.directory {
-                        Some(dir) => match &source_info.file {
-                            Some(file) => Some(format!("{}/{}", dir, file)),
-                            None => None,
-                        },
-                        None => None,
-                    },
-                    source_reference: None,
-                    presentation_hint: None,
-                    origin: None,
-                    sources: None,
-                    adapter_data: None,
-                    checksums: None,
-                };
-                {
-                    let (indexed, named) = get_num_diff_children(&s.variables);
-                    let scope_id = self.id_gen.gen();
-                    scope.push(debugserver_types::Scope {
-                        column: source_info.column.map(|v| v.get() as i64),
-                        end_column: None,
-                        end_line: None,
-                        expensive: false,
-                        indexed_variables: Some(indexed),
-                        named_variables: Some(named),
-                        line: source_info.line.map(|v| v.get() as i64),
-                        name: "locale".to_owned(),
-                        source: Some(source.clone()),
-                        variables_reference: scope_id,
-                    });
-                    vars.push((s.variables.clone(), scope_id));
-                }
-                {
-                    let (indexed, named) = get_num_diff_children(&s.arguments);
-                    let scope_id = self.id_gen.gen();
-                    scope.push(debugserver_types::Scope {
-                        column: source_info.column.map(|v| v.get() as i64),
-                        end_column: None,
-                        end_line: None,
-                        expensive: false,
-                        indexed_variables: Some(indexed),
-                        named_variables: Some(named),
-                        line: source_info.line.map(|v| v.get() as i64),
-                        name: "arguments".to_owned(),
-                        source: Some(source),
-                        variables_reference: scope_id,
-                    });
-                    vars.push((s.arguments.clone(), scope_id));
-                }
-                {
-                    let (indexed, named) = get_num_diff_children(&s.registers);
-                    let scope_id = self.id_gen.gen();
-                    scope.push(debugserver_types::Scope {
-                        column: None,
-                        end_column: None,
-                        end_line: None,
-                        expensive: false,
-                        indexed_variables: Some(indexed),
-                        named_variables: Some(named),
-                        line: None,
-                        name: "registers".to_owned(),
-                        source: None,
-                        variables_reference: scope_id,
-                    });
-                    vars.push((s.registers.clone(), scope_id));
+    fn reload_formats(&mut self) -> Result<String> {
+        let mut read = vec![];
+        for file in &self.format_files {
+            match FormatOverrides::read(&file.path) {
+                Ok(formats) => read.push(formats),
+                Err(err) => {
+                    return Err(anyhow!("{:#}, the previous formats are kept", err));
                 }
-                scopes.insert(id, scope);
             }
+        }
 
-            // Create Source object
-            let source = debugserver_types::Source {
-                name: source_info.file.clone(),
-                path: match &source_info.directory {
-                    // TODO: Make path os independent?
-                    Some(dir) => match &source_info.file {
-                        Some(file) => Some(format!("{}/{}", dir, file)),
-                        None => None,
-                    },
-                    None => None,
-                },
-                source_reference: None,
-                presentation_hint: None,
-                origin: None,
-                sources: None,
-                adapter_data: None,
-                checksums: None,
-            };
-
-            // Crate and add StackFrame object
-            stack_frames.push(debugserver_types::StackFrame {
-                id: id,
-                name: s.name.clone(),
-                source: Some(source),
-                line: match source_info.line {
-                    Some(v) => v.get() as i64,
-                    None => 1,
-                },
-                column: match source_info.column {
-                    Some(v) => v.get() as i64,
-                    None => 1,
-                },
-                end_column: None,
-                end_line: None,
-                module_id: None,
-                presentation_hint: Some("normal".to_owned()),
-            });
+        let mut loaded = 0;
+        for (file, formats) in self.format_files.iter_mut().zip(read) {
+            loaded += formats.list().len();
+            self.formats.remove_loaded(&file.formats);
+            self.formats.extend(formats.clone());
+            file.formats = formats;
         }
-        for (vs, sid) in vars {
-            self.set_variables(&mut variables, vs, sid)?;
+        // The shown variables were formatted with the previous ones
+        self.clear_variables();
+        Ok(format!(
+            "{} formats from {} files",
+            loaded,
+            self.format_files.len()
+        ))
+    }
+
+    fn set_symbol_files_command(&mut self, symbol_files: Vec<SymbolFile>) -> Result<Command> {
+        self.symbol_files = symbol_files
+            .into_iter()
+            .map(|symbol_file| SymbolFile {
+                path: resolve_path(&self.cwd, &symbol_file.path),
+                base_address: symbol_file.base_address,
+            })
+            .collect();
+        self.reload_symbols()?;
+
+        Ok(Command::Response(DebugResponse::SetSymbolFiles))
+    }
+
+    fn reload_symbols(&mut self) -> Result<()> {
+        let symbol_files = if self.symbol_files.is_empty() {
+            match &self.program {
+                Some(path) => vec![SymbolFile {
+                    path: path.clone(),
+                    base_address: 0,
+                }],
+                None => vec![],
+            }
+        } else {
+            self.symbol_files.clone()
+        };
+        let symbols = Symbols::load(&symbol_files)?;
+        if let Some(program) = &self.program {
+            symbols.check_program(program)?;
         }
 
-        self.stack_frames = Some(stack_frames);
-        self.scopes = Some(scopes);
-        self.variables = Some(variables);
+        // Breakpoints and cached values were resolved using the old symbols.
+        self.lift_catches()?;
+        self.symbols = symbols;
+        self.source_references.clear();
+        self.clear_temporaries();
+        self.report_breakpoints()?;
+        self.resolve_catches()?;
+
         Ok(())
     }
 
@@ -1033,7 +6056,16 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
     fn cycle_counter_command(&mut self) -> Result<Command> {
         let mut core = self.session.core(0)?;
         let (pc_val, cycle_counter) = read_cycle_counter(&mut core)?;
-        println!("pc: {:#010x}, cycle counter: {}", pc_val, cycle_counter);
+        match self.core_clock {
+            Some(clock) => println!(
+                "pc: {:#010x}, cycle counter: {} ({:?} at {})",
+                pc_val,
+                cycle_counter,
+                clock.duration(cycle_counter as u64),
+                clock
+            ),
+            None => println!("pc: {:#010x}, cycle counter: {}", pc_val, cycle_counter),
+        }
         drop(core);
         self.status_command()
     }
@@ -1044,7 +6076,50 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
         self.continue_command()
     }
 
-    fn trace_event(&mut self, _pc_val: u32) -> Result<()> {
+    fn trace_configure_command(&mut self, buffer_address: u32, size: u32) -> Result<Command> {
+        let mut core = self.session.core(0)?;
+        let buffer = TraceBuffer::configure(&mut core, buffer_address, size)?;
+        self.trace_buffer = Some(buffer);
+
+        info!("{}", buffer);
+        self.history
+            .record(HistoryKind::Write, format!("Started the trace: {}", buffer));
+
+        Ok(Command::Response(DebugResponse::TraceConfigure { buffer }))
+    }
+
+    fn trace_disable_command(&mut self) -> Result<Command> {
+        if let Some(buffer) = self.trace_buffer.take() {
+            let mut core = self.session.core(0)?;
+            buffer.disable(&mut core)?;
+        }
+
+        Ok(Command::Response(DebugResponse::TraceDisable))
+    }
+
+    fn trace_read_command(&mut self, count: Option<usize>) -> Result<Command> {
+        let buffer = match self.trace_buffer {
+            Some(val) => val,
+            None => {
+                return Err(anyhow!(
+                    "The trace is not configured, see `trace configure`"
+                ))
+            }
+        };
+        if !self.is_halted()? {
+            return Err(anyhow!("Core must be halted"));
+        }
+
+        let mut core = self.session.core(0)?;
+        let branches = buffer.read(&mut core)?;
+        let count = count.unwrap_or(branches.len());
+
+        Ok(Command::Response(DebugResponse::TraceRead {
+            branches: symbolize_branches(&self.symbols, &branches, count),
+        }))
+    }
+
+    fn trace_event(&mut self, _pc_val: u32, detected: Instant) -> Result<()> {
         let mut core = self.session.core(0)?;
         let (pc_val, cycle_counter) = read_cycle_counter(&mut core)?;
         println!("pc: {:#010x}, cycle counter: {}", pc_val, cycle_counter);
@@ -1070,6 +6145,7 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
                     // continue
                     drop(core);
                     self.continue_command()?;
+                    self.intrusiveness.record_halt("trace point", detected);
                     Ok(())
                 }
             }
@@ -1079,6 +6155,27 @@ impl<'a, R: Reader<Offset = usize>> Debugger<'a, R> {
     }
 }
 
+/// The condition, hit condition and log message of a breakpoint, empty ones are None.
+fn parse_breakpoint_options(
+    condition: Option<&str>,
+    hit_condition: Option<&str>,
+    log_message: Option<&str>,
+) -> Result<(Option<Condition>, Option<HitCondition>, Option<LogMessage>)> {
+    let condition = match condition.map(str::trim) {
+        Some("") | None => None,
+        Some(text) => Some(Condition::parse(text)?),
+    };
+    let hit_condition = match hit_condition.map(str::trim) {
+        Some("") | None => None,
+        Some(text) => Some(HitCondition::parse(text)?),
+    };
+    let log_message = match log_message {
+        Some("") | None => None,
+        Some(text) => Some(LogMessage::parse(text)?),
+    };
+    Ok((condition, hit_condition, log_message))
+}
+
 fn read_cycle_counter(core: &mut probe_rs::Core) -> Result<(u32, u32), probe_rs::Error> {
     let mut buff: Vec<u32> = vec![0; 1];
     core.read_32(0xe0001004, &mut buff)?;
@@ -1099,6 +6196,14 @@ fn read_bkpt(core: &mut probe_rs::Core, pc_val: u32) -> Result<u8, probe_rs::Err
     }
 }
 
+/// A timeout of 0 disables the watchdog.
+fn probe_timeout(timeout_ms: u64) -> Option<Duration> {
+    match timeout_ms {
+        0 => None,
+        timeout_ms => Some(Duration::from_millis(timeout_ms)),
+    }
+}
+
 fn continue_fix(
     core: &mut probe_rs::Core,
     breakpoints: &HashMap<u32, Breakpoint>,
@@ -1110,16 +6215,18 @@ fn continue_fix(
                     let pc = core.registers().program_counter();
                     let pc_val = core.read_core_reg(pc)?;
 
-                    match read_bkpt(core, pc_val) {
-                        Ok(_) => {
+                    let length = architecture::of(core)
+                        .breakpoint_instruction(core, pc_val)
+                        .map_err(probe_rs::Error::Other)?;
+                    match length {
+                        Some(length) => {
                             // For now we treat all breakpoints equally
-                            // NOTE: Increment with 2 because bkpt is 2 byte instruction.
-                            let step_pc = pc_val + 0x2; // TODO: Fix for other CPU types.
+                            let step_pc = CodeAddress::new(pc_val).offset(length).address();
                             core.write_core_reg(pc.into(), step_pc)?;
 
                             return Ok(step_pc);
                         }
-                        Err(_) => {
+                        None => {
                             match breakpoints.get(&pc_val) {
                                 Some(_bkpt) => {
                                     core.clear_hw_breakpoint(pc_val)?;
@@ -1143,10 +6250,25 @@ fn continue_fix(
 
 pub struct MyCore<'a> {
     pub core: probe_rs::Core<'a>,
+    // Serves the reads from flash without a probe round trip.
+    pub image: Option<FlashImage<'a>>,
+    // Serves the reads of the memory read ahead on the stop.
+    pub memory: Option<&'a MemoryCache>,
 }
 
 impl MemoryAccess for MyCore<'_> {
     fn get_address(&mut self, address: &u32, num_bytes: usize) -> Option<Vec<u8>> {
+        if let Some(image) = &self.image {
+            if let Some(bytes) = image.find(*address, num_bytes) {
+                return Some(bytes.to_vec());
+            }
+        }
+        if let Some(memory) = self.memory {
+            if let Some(bytes) = memory.find(*address, num_bytes) {
+                return Some(bytes.to_vec());
+            }
+        }
+
         let mut buff = vec![0u8; num_bytes];
         match self.core.read_8(*address, &mut buff) {
             Ok(_) => (),
@@ -1157,48 +6279,102 @@ impl MemoryAccess for MyCore<'_> {
 }
 
 fn read_and_add_registers(core: &mut probe_rs::Core, registers: &mut Registers) -> Result<()> {
+    let architecture = architecture::of(core);
     let register_file = core.registers();
     for register in register_file.registers() {
-        let value = core.read_core_reg(register)?;
-        registers.add_register_value(probe_rs::CoreRegisterAddress::from(register).0, value);
+        let address = probe_rs::CoreRegisterAddress::from(register).0;
+        if let Some(number) = architecture.dwarf_register(address) {
+            let value = core.read_core_reg(register)?;
+            registers.add_register_value(number, value);
+        }
     }
 
     Ok(())
 }
 
-#[derive(Debug, Clone)]
-pub struct DebugInformation<'a, R: Reader<Offset = usize>> {
-    pub dwarf: &'a Dwarf<R>,
-    pub debug_frame: &'a DebugFrame<R>,
-    pub breakpoints: Vec<u32>,
-}
-
-impl<'a, R: Reader<Offset = usize>> DebugInformation<'a, R> {
-    pub fn new(dwarf: &'a Dwarf<R>, debug_frame: &'a DebugFrame<R>) -> DebugInformation<'a, R> {
-        DebugInformation {
-            dwarf,
-            debug_frame,
-            breakpoints: vec![],
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub enum VariableKind {
     Indexed,
     Named,
     Unknown,
 }
 
-#[derive(Debug, Clone)]
+// The ids and the source are left out of the JSON form, it is compared between runs.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Variable {
+    #[serde(skip)]
     pub id: i64,
     pub name: Option<String>,
     pub value: String,
+    #[serde(rename = "type")]
     pub type_: String,
+    #[serde(skip)]
     pub source: Option<SourceInformation>,
     pub kind: VariableKind,
     pub children: Vec<Variable>,
+    pub location: Option<VariableLocation>,
+    // Read from the binary instead of the target, see `flash_image`.
+    pub read_only: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum VariableLocation {
+    Memory { address: u32, byte_size: usize },
+    Register { register: u16, byte_size: usize },
+}
+
+impl VariableLocation {
+    fn from_value_information(info: &ValueInformation) -> Option<VariableLocation> {
+        // Values split into multiple pieces don't have a single location.
+        if info.pieces.len() != 1 {
+            return None;
+        }
+        match &info.pieces[0] {
+            ValuePiece::Memory { address, byte_size } => Some(VariableLocation::Memory {
+                address: *address,
+                byte_size: *byte_size,
+            }),
+            ValuePiece::Register {
+                register,
+                byte_size,
+            } => Some(VariableLocation::Register {
+                register: *register,
+                byte_size: *byte_size,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The memory range spanned by the locations of all the children, if they are all in memory.
+    fn from_children(children: &[Variable]) -> Option<VariableLocation> {
+        let mut start = u32::MAX;
+        let mut end = 0;
+        for child in children {
+            match child.location {
+                Some(VariableLocation::Memory { address, byte_size }) => {
+                    start = start.min(address);
+                    end = end.max(address + byte_size as u32);
+                }
+                _ => return None,
+            };
+        }
+
+        match start < end {
+            true => Some(VariableLocation::Memory {
+                address: start,
+                byte_size: (end - start) as usize,
+            }),
+            false => None,
+        }
+    }
+}
+
+/// The modification time of the file, None if it can't be read.
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
 }
 
 impl Variable {
@@ -1206,6 +6382,12 @@ impl Variable {
         get_num_diff_children(&self.children)
     }
 
+    pub fn find_child(&self, name: &str) -> Option<&Variable> {
+        self.children
+            .iter()
+            .find(|child| child.name.as_deref() == Some(name))
+    }
+
     pub fn value_to_string(&self) -> String {
         self.value_to_string_recursive(true)
     }
@@ -1241,6 +6423,8 @@ impl Variable {
             source: var.source.clone(),
             kind: VariableKind::Unknown,
             children: vec![],
+            location: None,
+            read_only: false,
         };
 
         variable.evaluate(&var.value, &var.source)?;
@@ -1256,9 +6440,10 @@ impl Variable {
         source: &Option<SourceInformation>,
     ) -> Result<()> {
         match value {
-            EvaluatorValue::Value(val, _) => {
+            EvaluatorValue::Value(val, info) => {
                 self.value = format!("{}", val);
                 self.type_ = format!("{}::{}", self.type_, val.get_type());
+                self.location = VariableLocation::from_value_information(info);
             }
             EvaluatorValue::PointerTypeValue(pointer_type) => {
                 match &pointer_type.name {
@@ -1283,6 +6468,8 @@ impl Variable {
                     source: source.clone(),
                     kind: VariableKind::Indexed,
                     children: vec![],
+                    location: None,
+                    read_only: false,
                 };
                 variable.evaluate(
                     &EvaluatorValue::Member(Box::new(variant_value.child.clone())),
@@ -1331,6 +6518,8 @@ impl Variable {
                             source: source.clone(),
                             kind: VariableKind::Named,
                             children: vec![],
+                            location: None,
+                            read_only: false,
                         };
                         self.children.push(variable);
                     }
@@ -1345,6 +6534,8 @@ impl Variable {
                                     source: source.clone(),
                                     kind: VariableKind::Named,
                                     children: vec![],
+                                    location: None,
+                                    read_only: false,
                                 };
                                 variable.evaluate(
                                     &EvaluatorValue::<R>::Value(base_type_value, loc),
@@ -1378,6 +6569,7 @@ impl Variable {
                     ),
                     source,
                 )?;
+                let first_element = self.children.len();
                 for i in 0..array_type_value.values.len() {
                     let mut variable = Variable {
                         id: 0,
@@ -1387,10 +6579,13 @@ impl Variable {
                         source: source.clone(),
                         kind: VariableKind::Indexed,
                         children: vec![],
+                        location: None,
+                        read_only: false,
                     };
                     variable.evaluate(&array_type_value.values[i], source)?;
                     self.children.push(variable);
                 }
+                self.location = VariableLocation::from_children(&self.children[first_element..]);
             }
             EvaluatorValue::Struct(structure_type_value) => {
                 //self.name = Some(structure_type_value.name.clone());
@@ -1401,6 +6596,7 @@ impl Variable {
                 for member in &structure_type_value.members {
                     self.evaluate(member, source)?;
                 }
+                self.location = VariableLocation::from_children(&self.children);
             }
             EvaluatorValue::Enum(enumeration_type_value) => {
                 self.kind = VariableKind::Named;
@@ -1408,7 +6604,8 @@ impl Variable {
                 self.type_ = format!("{}::{}", self.type_, enumeration_type_value.name.clone());
                 self.value = "< OptimizedOut >".to_owned();
                 match &enumeration_type_value.variant {
-                    EvaluatorValue::Value(base_type_value, _) => {
+                    EvaluatorValue::Value(base_type_value, info) => {
+                        self.location = VariableLocation::from_value_information(info);
                         let variant = get_udata(base_type_value.clone())?;
                         for enu in &enumeration_type_value.enumerators {
                             if enu.const_value == variant {
@@ -1465,6 +6662,8 @@ impl Variable {
                     source: source.clone(),
                     kind,
                     children: vec![],
+                    location: None,
+                    read_only: false,
                 };
                 variable.evaluate(&member_value.value, source)?;
                 self.children.push(variable);
@@ -1481,6 +6680,7 @@ impl Variable {
 pub struct StackFrame {
     pub name: String,
     pub call_frame: CallFrame,
+    pub state: FrameState,
     pub source: SourceInformation,
     pub variables: Vec<Variable>,
     pub arguments: Vec<Variable>,
@@ -1489,8 +6689,11 @@ pub struct StackFrame {
 
 impl StackFrame {
     pub fn resolve_stackframe<R: Reader<Offset = usize>>(
+        index: usize,
         frame: &rust_debug::call_stack::StackFrame<R>,
     ) -> Result<StackFrame> {
+        let state = FrameState::new(index, &frame.call_frame);
+
         let mut variables = vec![];
         for var in &frame.variables {
             variables.push(Variable::resolve_varialbe(var)?);
@@ -1506,9 +6709,13 @@ impl StackFrame {
             registers.push(Variable::resolve_varialbe(var)?);
         }
 
+        state.mark_unknown_registers(&mut variables);
+        state.mark_unknown_registers(&mut arguments);
+
         Ok(StackFrame {
             name: frame.name.clone(),
             call_frame: frame.call_frame.clone(),
+            state,
             source: frame.source.clone(),
             variables,
             arguments,
@@ -1516,6 +6723,16 @@ impl StackFrame {
         })
     }
 
+    /// Finds a variable or one of its members from a dotted path, e.g. `config.flags`.
+    pub fn find_variable_path(&self, path: &str) -> Option<&Variable> {
+        let mut parts = path.split('.');
+        let mut variable = self.find_variable(parts.next()?)?;
+        for part in parts {
+            variable = variable.find_child(part)?;
+        }
+        Some(variable)
+    }
+
     pub fn find_variable(&self, name: &str) -> Option<&Variable> {
         for v in &self.variables {
             match &v.name {
@@ -1531,30 +6748,178 @@ impl StackFrame {
     }
 }
 
+/// Unwinds all the call frames, which only needs the CFI, and evaluates the variables of the top
+/// `depth` frames, all of them if None. Returns the number of call frames with the frames.
+fn unwind_stack<M: MemoryAccess>(
+    symbols: &Symbols,
+    pc: u32,
+    registers: Registers,
+    memory: &mut M,
+    cwd: &str,
+    depth: Option<usize>,
+    cancel: &CancelToken,
+) -> Result<(Vec<rust_debug::call_stack::StackFrame<DwarfReader>>, usize)> {
+    let call_frames = unwind_call_frames(symbols, pc, &registers, memory, cancel)?;
+    let total_frames = call_frames.len();
+    let mut stack_frames = vec![];
+    for call_frame in call_frames.into_iter().take(depth.unwrap_or(total_frames)) {
+        // A corrupted stack can have many frames with garbage variables
+        cancel.check()?;
+        // The DWARF of the module knows the frame by its link address
+        let address = call_frame.code_location as u32;
+        let module = symbols.module_for_address(address)?;
+        let mut link_frame = call_frame.clone();
+        link_frame.code_location = module.to_link_address(address).into();
+        let mut stack_frame = rust_debug::call_stack::create_stack_frame(
+            &module.dwarf,
+            link_frame,
+            &registers,
+            memory,
+            cwd,
+        )?;
+        stack_frame.call_frame = call_frame;
+        stack_frames.push(stack_frame);
+    }
+    Ok((stack_frames, total_frames))
+}
+
+/// Unwinds each call frame with the CFI of the module it is in, looked up by its link address.
+fn unwind_call_frames<M: MemoryAccess>(
+    symbols: &Symbols,
+    pc: u32,
+    registers: &Registers,
+    memory: &mut M,
+    cancel: &CancelToken,
+) -> Result<Vec<CallFrame>> {
+    let pc_register = match registers.program_counter_register {
+        Some(register) => register as u16,
+        None => return Err(anyhow!("The program counter register is not known")),
+    };
+
+    let mut call_frames: Vec<CallFrame> = vec![];
+    let mut pc = pc;
+    let mut frame_registers = registers.clone();
+    while call_frames.len() < MAX_CALL_FRAMES {
+        cancel.check()?;
+        let module = symbols.module_for_address(pc)?;
+        let mut link_registers = frame_registers.clone();
+        link_registers.add_register_value(pc_register, module.to_link_address(pc));
+        let mut frames = rust_debug::call_stack::unwind_call_stack(
+            link_registers,
+            memory,
+            &module.debug_frame,
+        )?
+        .into_iter();
+
+        let mut first = match frames.next() {
+            Some(frame) => frame,
+            None => break,
+        };
+        first.code_location = pc.into();
+        call_frames.push(first);
+
+        // The unwinder looks up the callers by their target address, a caller is unwound
+        // correctly only while its callee is in this module and the module isn't rebased.
+        let mut restart = None;
+        for frame in frames {
+            let address = frame.code_location as u32;
+            let same_module = module.base_address == 0
+                && symbols
+                    .module_for_address(address)
+                    .map_or(false, |other| std::ptr::eq(other, module));
+            call_frames.push(frame);
+            if !same_module {
+                restart = Some(address);
+                break;
+            }
+        }
+
+        match restart {
+            Some(address) => {
+                // Unwound again with the CFI of its own module
+                let frame = call_frames.pop().unwrap();
+                frame_registers = caller_registers(registers, &frame);
+                pc = address;
+            }
+            None => return Ok(call_frames),
+        }
+    }
+
+    if call_frames.len() >= MAX_CALL_FRAMES {
+        warn!("Stopped unwinding after {} call frames", MAX_CALL_FRAMES);
+    }
+    Ok(call_frames)
+}
+
+/// The registers of a caller frame to continue unwinding from.
+fn caller_registers(template: &Registers, call_frame: &CallFrame) -> Registers {
+    let mut registers = Registers::new();
+    registers.program_counter_register = template.program_counter_register;
+    registers.link_register = template.link_register;
+    registers.stack_pointer_register = template.stack_pointer_register;
+    for (number, value) in call_frame.registers.iter().enumerate() {
+        if let Some(value) = value {
+            registers.add_register_value(number as u16, *value as u32);
+        }
+    }
+    registers
+}
+
 pub fn resolve_stack_trace<R: Reader<Offset = usize>>(
     stack_frames: Vec<rust_debug::call_stack::StackFrame<R>>,
 ) -> Result<Vec<StackFrame>> {
     let mut stack_trace = vec![];
-    for sf in &stack_frames {
-        stack_trace.push(StackFrame::resolve_stackframe(sf)?);
+    for (index, sf) in stack_frames.iter().enumerate() {
+        stack_trace.push(StackFrame::resolve_stackframe(index, sf)?);
     }
     Ok(stack_trace)
 }
 
-pub struct IdGen {
-    next_id: i64,
+/// Assembly units have no subprogram entries, the frames in them are named by their ELF symbol.
+fn name_assembly_frames(symbols: &Symbols, stack_trace: &mut [StackFrame]) {
+    for frame in stack_trace {
+        let pc = frame.state.code_address().address();
+        if let Some(name) = symbols.assembly_function_name(pc) {
+            frame.name = name;
+        }
+    }
 }
 
-impl IdGen {
-    pub fn new() -> IdGen {
-        IdGen { next_id: 0 }
+
+/// Finds a variable path, e.g. `cfg.flags`, in the variables of a scope.
+fn find_in_scope<'a>(
+    variables: &'a HashMap<i64, Vec<Variable>>,
+    scope: i64,
+    path: &str,
+) -> Option<&'a Variable> {
+    let mut id = scope;
+    let mut found = None;
+    for part in path.split('.') {
+        let variable = variables
+            .get(&id)?
+            .iter()
+            .find(|variable| variable.name.as_deref() == Some(part))?;
+        id = variable.id;
+        found = Some(variable);
     }
+    found
+}
 
-    pub fn gen(&mut self) -> i64 {
-        let id = self.next_id;
-        self.next_id += 1;
-        return id;
+pub fn set_variables(
+    variables: &mut HashMap<i64, Vec<Variable>>,
+    mut children: Vec<Variable>,
+    id: i64,
+    arena: &mut VariableArena,
+) -> Result<()> {
+    for child in &mut children {
+        if child.children.len() > 0 {
+            child.id = arena.allocate()?;
+            set_variables(variables, child.children.clone(), child.id, arena)?;
+        }
     }
+    variables.insert(id, children.clone());
+
+    Ok(())
 }
 
 pub fn get_num_diff_children(children: &Vec<Variable>) -> (i64, i64) {
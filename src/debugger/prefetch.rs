@@ -0,0 +1,140 @@
+//! Target state read right after the core stops
+//!
+//! Every stop is followed by a burst of requests from the frontend for the registers, the stack
+//! trace and the locals of the top frame, each with its own probe traffic. The registers and the
+//! stack are read once while the frontend is still handling the halted event, the stack trace is
+//! unwound into the existing per-stop cache, and both are served from there until the core
+//! resumes.
+//!
+//! The memory of the variables the frontend requested during the previous stop is read ahead as
+//! well, with adjacent ranges coalesced into one transfer, so evaluating the expanded variables
+//! again costs a few transfers instead of one per value.
+use anyhow::Result;
+use log::warn;
+use probe_rs::MemoryInterface;
+use std::ops::Range;
+
+use super::stepping::RegisterSnapshot;
+use super::Variable;
+use super::VariableLocation;
+
+/// Ranges closer than this are read in one transfer.
+const COALESCE_GAP: u32 = 32;
+
+/// The most memory read ahead on a stop, the rest is read when it is evaluated.
+const MAX_READ_AHEAD: u32 = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct StopCache {
+    pub registers: RegisterSnapshot,
+    pub stack_pointer: u32,
+    pub stack: Vec<u32>,
+    pub memory: MemoryCache,
+}
+
+impl StopCache {
+    pub fn read(core: &mut probe_rs::Core, read_ahead: &[Range<u32>]) -> Result<StopCache> {
+        let registers = RegisterSnapshot::read(core)?;
+        let (stack_pointer, stack) = read_stack(core)?;
+
+        Ok(StopCache {
+            registers,
+            stack_pointer,
+            stack,
+            memory: MemoryCache::read(core, read_ahead),
+        })
+    }
+}
+
+/// The memory ranges of the variables requested by the frontend since the last stop.
+#[derive(Debug, Default)]
+pub struct RequestedRanges {
+    ranges: Vec<Range<u32>>,
+}
+
+impl RequestedRanges {
+    pub fn record(&mut self, variables: &[Variable]) {
+        for variable in variables {
+            if let Some(VariableLocation::Memory { address, byte_size }) = variable.location {
+                self.ranges.push(address..address.saturating_add(byte_size as u32));
+            }
+        }
+    }
+
+    /// Takes the recorded ranges, sorted, coalesced and cut at `MAX_READ_AHEAD` bytes.
+    pub fn take_coalesced(&mut self) -> Vec<Range<u32>> {
+        let mut ranges = std::mem::take(&mut self.ranges);
+        ranges.sort_by_key(|range| range.start);
+
+        let mut coalesced: Vec<Range<u32>> = vec![];
+        for range in ranges {
+            if let Some(last) = coalesced.last_mut() {
+                if range.start <= last.end.saturating_add(COALESCE_GAP) {
+                    last.end = last.end.max(range.end);
+                    continue;
+                }
+            }
+            coalesced.push(range);
+        }
+
+        let mut total = 0;
+        coalesced
+            .into_iter()
+            .take_while(|range| {
+                total += range.end - range.start;
+                total <= MAX_READ_AHEAD
+            })
+            .collect()
+    }
+}
+
+/// Memory read ahead on a stop, valid until the core resumes.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryCache {
+    blocks: Vec<(u32, Vec<u8>)>,
+}
+
+impl MemoryCache {
+    /// Ranges that fail to read are left out, they fail again when they are evaluated.
+    pub fn read(core: &mut probe_rs::Core, ranges: &[Range<u32>]) -> MemoryCache {
+        let mut blocks = vec![];
+        for range in ranges {
+            let mut data = vec![0u8; (range.end - range.start) as usize];
+            match core.read_8(range.start, &mut data) {
+                Ok(_) => blocks.push((range.start, data)),
+                Err(err) => warn!(
+                    "Failed to read ahead {:#010x}..{:#010x}: {}",
+                    range.start, range.end, err
+                ),
+            };
+        }
+        MemoryCache { blocks }
+    }
+
+    pub fn find(&self, address: u32, num_bytes: usize) -> Option<&[u8]> {
+        self.blocks.iter().find_map(|(start, data)| {
+            let offset = address.checked_sub(*start)? as usize;
+            data.get(offset..offset.checked_add(num_bytes)?)
+        })
+    }
+}
+
+/// Reads the words between the stack pointer and the frame pointer of a halted core.
+pub fn read_stack(core: &mut probe_rs::Core) -> Result<(u32, Vec<u32>)> {
+    let sp_reg: u16 = probe_rs::CoreRegisterAddress::from(core.registers().stack_pointer()).0;
+
+    let sf = core.read_core_reg(7)?; // reg 7 seams to be the base stack address.
+    let sp = core.read_core_reg(sp_reg)?;
+
+    if sf < sp {
+        // The previous stack pointer is less then current.
+        // This happens when there is no stack.
+        return Ok((sp, vec![]));
+    }
+
+    let length = (((sf - sp) + 4 - 1) / 4) as usize;
+    let mut stack = vec![0u32; length];
+    core.read_32(sp, &mut stack)?;
+
+    Ok((sp, stack))
+}
@@ -0,0 +1,389 @@
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// The probes claimed by the sessions of this process.
+static CLAIMED_PROBES: Mutex<Vec<ProbeId>> = Mutex::new(Vec::new());
+
+/// How long `--force-claim` waits for another process to release the probe.
+const FORCE_CLAIM_TIMEOUT: Duration = Duration::from_secs(10);
+const FORCE_CLAIM_RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Parts of the errors libusb, hidapi and the probe drivers give for a probe opened elsewhere.
+const IN_USE_ERRORS: [&str; 5] = ["busy", "in use", "access denied", "claim", "exclusive"];
+
+/// The host tools that keep a probe open while they run.
+const KNOWN_DEBUGGERS: [&str; 14] = [
+    "probe-rs",
+    "probe-rs-cli",
+    "probe-rs-debugger",
+    "cargo-embed",
+    "cargo-flash",
+    "probe-run",
+    "openocd",
+    "pyocd",
+    "st-util",
+    "stm32_programmer_cli",
+    "jlinkgdbserver",
+    "jlinkgdbserverclexe",
+    "jlinkexe",
+    "erdb",
+];
+
+/// Identifies a probe across listings, the position in the probe list changes when probes are
+/// plugged in or out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeId {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub serial_number: Option<String>,
+    /// The position in the probe list, only used for probes without a serial number.
+    pub position: Option<usize>,
+}
+
+impl ProbeId {
+    pub fn new(info: &probe_rs::DebugProbeInfo, position: usize) -> ProbeId {
+        ProbeId {
+            vendor_id: info.vendor_id,
+            product_id: info.product_id,
+            serial_number: info.serial_number.clone(),
+            position: match info.serial_number {
+                Some(_) => None,
+                None => Some(position),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for ProbeId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vendor_id, self.product_id)?;
+        match (&self.serial_number, self.position) {
+            (Some(serial), _) => write!(f, ":{}", serial),
+            (None, Some(position)) => write!(f, " (probe {})", position),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Finds the probe at `probe_num` in the list of connected probes.
+pub fn find_probe(probe_num: usize) -> Result<(probe_rs::DebugProbeInfo, ProbeId)> {
+    match probe_rs::Probe::list_all().into_iter().nth(probe_num) {
+        Some(info) => {
+            let id = ProbeId::new(&info, probe_num);
+            Ok((info, id))
+        }
+        None => Err(anyhow!("Probe {} not available", probe_num)),
+    }
+}
+
+/// Exclusive use of a probe by one session, released when dropped.
+#[derive(Debug)]
+pub struct ProbeClaim {
+    id: ProbeId,
+}
+
+impl ProbeClaim {
+    pub fn new(id: ProbeId) -> Result<ProbeClaim> {
+        let mut claimed = CLAIMED_PROBES.lock().unwrap();
+        if claimed.contains(&id) {
+            return Err(anyhow!(
+                "Probe {} is already in use by another session, select a different probe with the `probe` attach argument",
+                id
+            ));
+        }
+        claimed.push(id.clone());
+        info!("Claimed probe {}", id);
+
+        Ok(ProbeClaim { id })
+    }
+}
+
+impl Drop for ProbeClaim {
+    fn drop(&mut self) {
+        let mut claimed = CLAIMED_PROBES.lock().unwrap();
+        claimed.retain(|id| *id != self.id);
+        info!("Released probe {}", self.id);
+    }
+}
+
+/// Opens the probe, naming the processes that likely hold it if another process does.
+///
+/// probe-rs can't take a probe from another process, so `force_claim` waits for the probe to be
+/// released instead of failing at once, e.g. while an old session is closing.
+pub fn open_probe(info: &probe_rs::DebugProbeInfo, force_claim: bool) -> Result<probe_rs::Probe> {
+    let start = Instant::now();
+    loop {
+        let err = match info.open() {
+            Ok(probe) => return Ok(probe),
+            Err(err) => anyhow::Error::from(err),
+        };
+        if !is_in_use_error(&err) {
+            return Err(err).context("Failed to open probe");
+        }
+        if force_claim && start.elapsed() < FORCE_CLAIM_TIMEOUT {
+            std::thread::sleep(FORCE_CLAIM_RETRY_INTERVAL);
+            continue;
+        }
+        warn!("Probe in use: {:#}", err);
+        return Err(anyhow!(in_use_message(
+            &SystemProcesses,
+            info.serial_number.as_deref(),
+            force_claim
+        )));
+    }
+}
+
+/// Returns true if the error says the probe is opened by another process.
+pub fn is_in_use_error(err: &anyhow::Error) -> bool {
+    let message = format!("{:#}", err).to_lowercase();
+    IN_USE_ERRORS.iter().any(|part| message.contains(part))
+}
+
+/// The error for a probe held by another process, with the processes that likely hold it.
+pub fn in_use_message(
+    processes: &dyn ProcessList,
+    serial: Option<&str>,
+    force_claim: bool,
+) -> String {
+    let probe = match serial {
+        Some(serial) => format!("Probe {}", serial),
+        None => "The probe".to_owned(),
+    };
+    let hint = match force_claim {
+        true => format!(
+            "it was not released within {} s",
+            FORCE_CLAIM_TIMEOUT.as_secs()
+        ),
+        false => "close it, or use `--force-claim` to wait for it to be released".to_owned(),
+    };
+    match processes.processes() {
+        Ok(processes) => {
+            let culprits = likely_holders(&processes, std::process::id());
+            match culprits.is_empty() {
+                true => format!(
+                    "{} is in use by another process, no known debugger is running, {}",
+                    probe, hint
+                ),
+                false => format!(
+                    "{} is in use by another process, {}. Likely held by: {}",
+                    probe,
+                    hint,
+                    culprits
+                        .iter()
+                        .map(|process| process.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ),
+            }
+        }
+        Err(err) => format!(
+            "{} is in use by another process, {}. The processes could not be listed: {}",
+            probe, hint, err
+        ),
+    }
+}
+
+/// The processes that have a USB device open or are known debuggers, the former first.
+pub fn likely_holders(processes: &[HostProcess], own_pid: u32) -> Vec<HostProcess> {
+    let mut holders: Vec<HostProcess> = processes
+        .iter()
+        .filter(|process| process.pid != own_pid)
+        .filter(|process| process.usb_handle || process.is_known_debugger())
+        .cloned()
+        .collect();
+    holders.sort_by_key(|process| (!process.usb_handle, process.pid));
+    holders
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostProcess {
+    pub pid: u32,
+    pub name: String,
+    /// Has a USB device open, only known where the open files of processes can be read.
+    pub usb_handle: bool,
+}
+
+impl HostProcess {
+    fn is_known_debugger(&self) -> bool {
+        let name = self.name.to_lowercase();
+        let name = name.strip_suffix(".exe").unwrap_or(&name);
+        KNOWN_DEBUGGERS.contains(&name)
+    }
+}
+
+impl std::fmt::Display for HostProcess {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.usb_handle {
+            true => write!(f, "{} (pid {}, has a USB device open)", self.name, self.pid),
+            false => write!(f, "{} (pid {})", self.name, self.pid),
+        }
+    }
+}
+
+/// Lists the processes of the host, replaceable to check the detection without real processes.
+pub trait ProcessList {
+    fn processes(&self) -> Result<Vec<HostProcess>>;
+}
+
+/// The processes of this host.
+pub struct SystemProcesses;
+
+impl ProcessList for SystemProcesses {
+    fn processes(&self) -> Result<Vec<HostProcess>> {
+        list_processes()
+    }
+}
+
+/// Reads `/proc`, the USB devices are the open files under `/dev/bus/usb`. The files of the
+/// processes of other users can't be read without privileges.
+#[cfg(target_os = "linux")]
+fn list_processes() -> Result<Vec<HostProcess>> {
+    let mut processes = vec![];
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid = match entry.file_name().to_string_lossy().parse::<u32>() {
+            Ok(val) => val,
+            Err(_) => continue,
+        };
+        let name = match std::fs::read_to_string(entry.path().join("comm")) {
+            Ok(val) => val.trim().to_owned(),
+            Err(_) => continue,
+        };
+        let usb_handle = match std::fs::read_dir(entry.path().join("fd")) {
+            Ok(fds) => fds.filter_map(|fd| fd.ok()).any(|fd| {
+                std::fs::read_link(fd.path())
+                    .map(|target| target.starts_with("/dev/bus/usb"))
+                    .unwrap_or(false)
+            }),
+            Err(_) => false,
+        };
+        processes.push(HostProcess {
+            pid,
+            name,
+            usb_handle,
+        });
+    }
+    Ok(processes)
+}
+
+/// Lists the processes with `ps`, the open USB devices are not known.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn list_processes() -> Result<Vec<HostProcess>> {
+    let output = std::process::Command::new("ps")
+        .args(&["-A", "-o", "pid=,comm="])
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (pid, command) = line.trim().split_once(char::is_whitespace)?;
+            Some(HostProcess {
+                pid: pid.parse().ok()?,
+                name: command.trim().rsplit('/').next()?.to_owned(),
+                usb_handle: false,
+            })
+        })
+        .collect())
+}
+
+/// Lists the processes with `tasklist`, the open USB devices are not known.
+#[cfg(windows)]
+fn list_processes() -> Result<Vec<HostProcess>> {
+    let output = std::process::Command::new("tasklist")
+        .args(&["/FO", "CSV", "/NH"])
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            // "name.exe","pid","session name","session#","memory"
+            let mut fields = line.split("\",\"").map(|field| field.trim_matches('"'));
+            let name = fields.next()?.to_owned();
+            let pid = fields.next()?.parse().ok()?;
+            Some(HostProcess {
+                pid,
+                name,
+                usb_handle: false,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stlink(serial: Option<&str>, position: usize) -> ProbeId {
+        ProbeId {
+            vendor_id: 0x0483,
+            product_id: 0x374b,
+            serial_number: serial.map(str::to_owned),
+            position: match serial {
+                Some(_) => None,
+                None => Some(position),
+            },
+        }
+    }
+
+    #[test]
+    fn a_probe_is_claimed_by_its_serial_number() {
+        let claim = ProbeClaim::new(stlink(Some("0671FF485550755187121723"), 0)).unwrap();
+        // The same probe listed at another position after a probe was unplugged
+        let err = ProbeClaim::new(stlink(Some("0671FF485550755187121723"), 1)).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("0483:374b:0671FF485550755187121723 is already in use"));
+        // Another probe of the same model
+        let other = ProbeClaim::new(stlink(Some("066DFF495051717867133834"), 0)).unwrap();
+
+        drop(claim);
+        ProbeClaim::new(stlink(Some("0671FF485550755187121723"), 2)).unwrap();
+        drop(other);
+    }
+
+    #[test]
+    fn sessions_share_a_probe_one_at_a_time() {
+        use std::sync::mpsc::channel;
+        use std::thread;
+
+        let jlink = ProbeId {
+            vendor_id: 0x1366,
+            product_id: 0x0105,
+            serial_number: Some("000260112233".to_owned()),
+            position: None,
+        };
+        let (claimed_sender, claimed) = channel();
+        let (release, release_receiver) = channel::<()>();
+
+        // Each session of the server runs on its own thread
+        let first_id = jlink.clone();
+        let first = thread::spawn(move || {
+            let claim = ProbeClaim::new(first_id).unwrap();
+            claimed_sender.send(()).unwrap();
+            release_receiver.recv().unwrap();
+            drop(claim);
+        });
+        claimed.recv().unwrap();
+
+        let second_id = jlink.clone();
+        let second = thread::spawn(move || ProbeClaim::new(second_id).map(drop));
+        let err = second.join().unwrap().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("already in use by another session"));
+
+        release.send(()).unwrap();
+        first.join().unwrap();
+        let second = thread::spawn(move || ProbeClaim::new(jlink).map(drop));
+        second.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn probes_without_serial_numbers_are_told_apart_by_position() {
+        let first = ProbeClaim::new(stlink(None, 5)).unwrap();
+        let second = ProbeClaim::new(stlink(None, 6)).unwrap();
+        assert!(ProbeClaim::new(stlink(None, 5)).is_err());
+        assert_eq!(stlink(None, 5).to_string(), "0483:374b (probe 5)");
+        drop((first, second));
+    }
+}
@@ -0,0 +1,67 @@
+//! Breakpoints in functions that run from RAM
+//!
+//! Functions placed in RAM, e.g. with `#[ramfunc]` in `.data`, only exist there after the reset
+//! handler has copied the initialized RAM sections from flash. A breakpoint in them is held back
+//! from a reset until the program reaches `main`: the debugger stops there with a temporary
+//! breakpoint, plants the held back breakpoints and resumes. Until then they are reported as not
+//! verified with `PENDING_MESSAGE`.
+//!
+//! A section is taken to be copied at startup if the binary has contents for it, it is linked
+//! into a RAM region and it contains a function. Breakpoints in flash are not affected.
+use probe_rs::config::MemoryRegion;
+use std::ops::Range;
+
+use super::symbols::Symbols;
+
+pub const PENDING_MESSAGE: &str =
+    "Pending: the function runs from RAM, the breakpoint is set once the startup code has copied it there";
+
+/// The target address ranges of the functions copied to RAM at startup.
+pub fn ram_code_ranges(symbols: &Symbols, memory_map: &[MemoryRegion]) -> Vec<Range<u32>> {
+    let ram: Vec<&Range<u32>> = memory_map
+        .iter()
+        .filter_map(|region| match region {
+            MemoryRegion::Ram(ram) => Some(&ram.range),
+            _ => None,
+        })
+        .collect();
+
+    let mut ranges = vec![];
+    for module in &symbols.modules {
+        for section in &module.image {
+            let link_range = section.address..section.address + section.data.len() as u32;
+            let start = module.to_target_address(link_range.start);
+            if !ram.iter().any(|range| range.contains(&start)) {
+                continue;
+            }
+            let has_function = module
+                .elf_symbols
+                .iter()
+                .any(|symbol| symbol.is_function && link_range.contains(&symbol.address));
+            if has_function {
+                ranges.push(start..module.to_target_address(link_range.end));
+            }
+        }
+    }
+    ranges
+}
+
+/// The address of the `main` the reset handler calls once RAM is initialized.
+pub fn main_address(symbols: &Symbols) -> Option<u32> {
+    symbols
+        .find_symbols("main")
+        .into_iter()
+        .find(|symbol| symbol.is_function && symbol.name == "main")
+        .map(|symbol| symbol.address)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StartupBreakpoints {
+    /// Set from a reset until `main` is reached, if the program has functions in RAM.
+    pub in_startup: bool,
+    /// The temporary breakpoint at `main`, while it is planted.
+    pub main: Option<u32>,
+    /// The addresses of the breakpoints held back until `main`, they are in the breakpoints
+    /// of the debugger but not planted.
+    pub pending: Vec<u32>,
+}
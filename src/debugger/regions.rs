@@ -0,0 +1,105 @@
+//! Memory region annotations of addresses
+//!
+//! Register values, stack words, hex formatted variables and fault addresses are shown with the
+//! region they fall in, e.g. `0x20001f00 [ram]`, which makes a wild pointer or a wrong linker
+//! script stand out. The regions of the target memory map come first, the rest of the address
+//! space is named after the Cortex-M default map: peripherals, external RAM and devices and the
+//! system region. Core dumps have no memory map, only the default map is used for them. There
+//! is no SVD description of the peripherals, so they are not named.
+//!
+//! The annotations are only added to what is shown, the values the debugger works with are
+//! unchanged. `regions off` disables them.
+use probe_rs::config::MemoryRegion;
+use std::ops::Range;
+
+use super::Variable;
+
+/// The Cortex-M default memory map, for the addresses outside the target memory map.
+const DEFAULT_MAP: [(Range<u64>, &str); 4] = [
+    (0x4000_0000..0x6000_0000, "peripheral"),
+    (0x6000_0000..0xa000_0000, "external"),
+    (0xa000_0000..0xe000_0000, "external"),
+    (0xe000_0000..0x1_0000_0000, "system"),
+];
+
+#[derive(Debug, Clone)]
+pub struct RegionMap {
+    /// Sorted by start address.
+    regions: Vec<(Range<u64>, &'static str)>,
+    pub enabled: bool,
+}
+
+impl RegionMap {
+    pub fn new(memory_map: &[MemoryRegion], enabled: bool) -> RegionMap {
+        let mut regions: Vec<(Range<u64>, &'static str)> = memory_map
+            .iter()
+            .map(|region| match region {
+                MemoryRegion::Ram(ram) => (&ram.range, "ram"),
+                MemoryRegion::Nvm(nvm) => (&nvm.range, "flash"),
+                MemoryRegion::Generic(generic) => (&generic.range, "memory"),
+            })
+            .map(|(range, name)| (range.start as u64..range.end as u64, name))
+            .collect();
+        regions.sort_by_key(|(range, _)| range.start);
+        RegionMap { regions, enabled }
+    }
+
+    /// The name of the region the address is in.
+    pub fn region(&self, address: u32) -> &'static str {
+        let address = address as u64;
+        find(&self.regions, address)
+            .or_else(|| find(&DEFAULT_MAP, address))
+            .unwrap_or("unmapped")
+    }
+
+    /// The address in hex followed by its region, if the annotations are enabled.
+    pub fn annotate(&self, address: u32) -> String {
+        format!("{:#010x}{}", address, self.suffix(address))
+    }
+
+    /// The region of the address to append to a value, empty if the annotations are disabled.
+    pub fn suffix(&self, address: u32) -> String {
+        match self.enabled {
+            true => format!(" [{}]", self.region(address)),
+            false => String::new(),
+        }
+    }
+
+    /// Appends the region to the variables shown in hex.
+    pub fn annotate_variables(&self, variables: &mut [Variable]) {
+        if !self.enabled {
+            return;
+        }
+        for variable in variables {
+            let address = variable
+                .value
+                .strip_prefix("0x")
+                .and_then(|digits| u32::from_str_radix(digits, 16).ok());
+            if let Some(address) = address {
+                variable.value.push_str(&self.suffix(address));
+            }
+            self.annotate_variables(&mut variable.children);
+        }
+    }
+
+    /// Appends the region to the register values.
+    pub fn annotate_registers(&self, registers: &mut [Variable]) {
+        if !self.enabled {
+            return;
+        }
+        for register in registers {
+            if let Ok(value) = register.value.parse::<u32>() {
+                register.value.push_str(&self.suffix(value));
+            }
+        }
+    }
+}
+
+/// Binary search in regions sorted by start address.
+fn find(regions: &[(Range<u64>, &'static str)], address: u64) -> Option<&'static str> {
+    let index = regions.partition_point(|(range, _)| range.start <= address);
+    match index.checked_sub(1).map(|index| &regions[index]) {
+        Some((range, name)) if range.contains(&address) => Some(*name),
+        _ => None,
+    }
+}
@@ -0,0 +1,63 @@
+//! Catching the resets the target does by itself
+//!
+//! A watchdog or a software reset restarts the firmware while the debugger still shows the state
+//! from before it. The reset vector catch of DEMCR halts the core at the reset vector on every
+//! local reset, and the halt is reported as the entry of a new run. The catch is lifted around
+//! the resets the debugger does itself, with the trap catches, so those are not reported twice.
+//!
+//! The debug registers survive a local reset, the catch stays planted. Only Cortex-M cores have
+//! the vector catch.
+use anyhow::Result;
+use probe_rs::{HaltReason, MemoryInterface};
+
+use super::architecture;
+use super::stepping::active_exception;
+use super::vector_table::VTOR;
+
+const DEMCR: u32 = 0xe000_edfc;
+const DEMCR_VC_CORERESET: u32 = 1;
+
+pub const RESET_DESCRIPTION: &str = "target reset detected (watchdog or software reset)";
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResetCatch {
+    planted: bool,
+}
+
+impl ResetCatch {
+    pub fn plant(&mut self, core: &mut probe_rs::Core) -> Result<()> {
+        if !architecture::of(core).has_cortex_m_debug() {
+            return Ok(());
+        }
+        let demcr = core.read_word_32(DEMCR)?;
+        core.write_word_32(DEMCR, demcr | DEMCR_VC_CORERESET)?;
+        self.planted = true;
+        Ok(())
+    }
+
+    /// Also clears a catch left planted by an earlier session.
+    pub fn lift(&mut self, core: &mut probe_rs::Core) -> Result<()> {
+        if !architecture::of(core).has_cortex_m_debug() {
+            return Ok(());
+        }
+        let demcr = core.read_word_32(DEMCR)?;
+        core.write_word_32(DEMCR, demcr & !DEMCR_VC_CORERESET)?;
+        self.planted = false;
+        Ok(())
+    }
+
+    /// Returns true if the core halted at the reset vector because of the catch.
+    pub fn hit(&self, core: &mut probe_rs::Core, reason: HaltReason, pc: u32) -> Result<bool> {
+        // The vector catches halt with the exception reason, the fault catches in a handler
+        if !self.planted
+            || !matches!(reason, HaltReason::Exception)
+            || active_exception(core)?.is_some()
+        {
+            return Ok(false);
+        }
+        let vtor = core.read_word_32(VTOR)?;
+        let reset_vector = core.read_word_32(vtor.wrapping_add(4))?;
+        // The Thumb bit is set in the vector
+        Ok(pc == reset_vector & !1)
+    }
+}
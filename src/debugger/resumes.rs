@@ -0,0 +1,22 @@
+//! The number of times the core was resumed, shared with the adapter to drop stale halts
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default)]
+pub struct ResumeCount {
+    count: Arc<AtomicU64>,
+}
+
+impl ResumeCount {
+    pub fn new() -> ResumeCount {
+        ResumeCount::default()
+    }
+
+    pub fn resumed(&self) {
+        self.count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn current(&self) -> u64 {
+        self.count.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,233 @@
+//! The sections of a binary laid over the memory map of the target
+//!
+//! Every allocated section is placed in the memory region it starts in and checked: sections
+//! must not overlap each other, must end inside their region and writable data must be in RAM.
+//! The last check catches a `.data` or `.bss` linked into flash address space, which only shows
+//! as variables that never change. The start and end symbols cortex-m-rt defines for the data,
+//! the bss, the heap and the stack are listed with them, and the stack is checked to start in
+//! RAM above the bss.
+use anyhow::Result;
+use object::{Object, ObjectSection, ObjectSymbol, SectionKind};
+use probe_rs::config::MemoryRegion;
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// The start and end symbols of the ranges cortex-m-rt lays out.
+const RANGE_SYMBOLS: [(&str, &str); 3] = [
+    ("__sdata", "__edata"),
+    ("__sbss", "__ebss"),
+    ("__sheap", "__eheap"),
+];
+
+/// The symbols without an end.
+const SINGLE_SYMBOLS: [&str; 3] = ["__sidata", "__veneer_base", "_stack_start"];
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SectionInfo {
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+    pub writable: bool,
+    /// The memory region it starts in, None if it is outside the memory map.
+    pub region: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkerSymbol {
+    pub name: String,
+    pub address: u32,
+    /// Up to the matching end symbol, if there is one.
+    pub size: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SectionMap {
+    pub sections: Vec<SectionInfo>,
+    pub symbols: Vec<LinkerSymbol>,
+    pub warnings: Vec<String>,
+}
+
+impl fmt::Display for SectionMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sections:")?;
+        for section in &self.sections {
+            write!(
+                f,
+                "\n    {:<24} {:#010x}..{:#010x} {:>8} bytes",
+                section.name,
+                section.address,
+                section.address as u64 + section.size as u64,
+                section.size
+            )?;
+            if let Some(region) = &section.region {
+                write!(f, " [{}]", region)?;
+            }
+        }
+        if !self.symbols.is_empty() {
+            write!(f, "\nLinker symbols:")?;
+        }
+        for symbol in &self.symbols {
+            write!(f, "\n    {:<24} {:#010x}", symbol.name, symbol.address)?;
+            if let Some(size) = symbol.size {
+                write!(f, " {:>8} bytes", size)?;
+            }
+        }
+        for warning in &self.warnings {
+            write!(f, "\nWarning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+impl SectionMap {
+    /// Reads the sections of the binary, the region checks are skipped without a memory map.
+    pub fn new(path: &Path, memory_map: &[MemoryRegion]) -> Result<SectionMap> {
+        let file = fs::File::open(&path)?;
+        let mmap = unsafe { memmap::Mmap::map(&file)? };
+        let object = object::File::parse(&*mmap)?;
+
+        let mut sections = vec![];
+        let mut warnings = vec![];
+        for section in object.sections() {
+            let writable = match section.kind() {
+                SectionKind::Text | SectionKind::ReadOnlyData | SectionKind::ReadOnlyString => {
+                    false
+                }
+                SectionKind::Data | SectionKind::UninitializedData => true,
+                _ => continue,
+            };
+            let (address, size) = (section.address() as u32, section.size() as u32);
+            if size == 0 {
+                continue;
+            }
+            let name = section.name().unwrap_or("<unnamed>").to_owned();
+            let region = memory_map.iter().find(|region| {
+                region_range(region).0 <= address && address < region_range(region).1
+            });
+
+            if let Some(region) = region {
+                let (start, end, kind) = region_range(region);
+                if address as u64 + size as u64 > end as u64 {
+                    warnings.push(format!(
+                        "{} spills {} bytes out of the {} region at {:#010x}",
+                        name,
+                        address as u64 + size as u64 - end as u64,
+                        kind,
+                        start
+                    ));
+                }
+                if writable && matches!(region, MemoryRegion::Nvm(_)) {
+                    warnings.push(format!(
+                        "{} is writable data but linked into flash at {:#010x}",
+                        name, address
+                    ));
+                }
+            } else if !memory_map.is_empty() {
+                warnings.push(format!(
+                    "{} at {:#010x} is outside the memory map of the target",
+                    name, address
+                ));
+            }
+            sections.push(SectionInfo {
+                name,
+                address,
+                size,
+                writable,
+                region: region.map(|region| region_range(region).2.to_owned()),
+            });
+        }
+
+        sections.sort_by_key(|section| section.address);
+        for pair in sections.windows(2) {
+            let end = pair[0].address as u64 + pair[0].size as u64;
+            if end > pair[1].address as u64 {
+                warnings.push(format!(
+                    "{} and {} overlap at {:#010x}..{:#010x}",
+                    pair[0].name,
+                    pair[1].name,
+                    pair[1].address,
+                    end.min(pair[1].address as u64 + pair[1].size as u64)
+                ));
+            }
+        }
+
+        let address_of = |name: &str| {
+            object
+                .symbols()
+                .find(|symbol| symbol.name() == Ok(name))
+                .map(|symbol| symbol.address() as u32)
+        };
+        let mut symbols = vec![];
+        for (start, end) in RANGE_SYMBOLS.iter() {
+            let end_address = address_of(end);
+            if let Some(address) = address_of(start) {
+                symbols.push(LinkerSymbol {
+                    name: start.to_string(),
+                    address,
+                    size: end_address.map(|end| end.wrapping_sub(address)),
+                });
+            }
+            if let Some(address) = end_address {
+                symbols.push(LinkerSymbol {
+                    name: end.to_string(),
+                    address,
+                    size: None,
+                });
+            }
+        }
+        for name in SINGLE_SYMBOLS.iter() {
+            if let Some(address) = address_of(name) {
+                symbols.push(LinkerSymbol {
+                    name: name.to_string(),
+                    address,
+                    size: None,
+                });
+            }
+        }
+
+        // The stack grows down from `_stack_start` towards the statics
+        if let Some(stack_start) = address_of("_stack_start") {
+            let in_ram = memory_map.iter().any(|region| match region {
+                // The initial stack pointer is one past the end of its region
+                MemoryRegion::Ram(ram) => {
+                    ram.range.start < stack_start && stack_start <= ram.range.end
+                }
+                _ => false,
+            });
+            if !in_ram && !memory_map.is_empty() {
+                warnings.push(format!(
+                    "_stack_start {:#010x} is not in a RAM region",
+                    stack_start
+                ));
+            }
+            if let Some(ebss) = address_of("__ebss") {
+                if stack_start < ebss {
+                    warnings.push(format!(
+                        "_stack_start {:#010x} is below the end of .bss {:#010x}",
+                        stack_start, ebss
+                    ));
+                }
+            }
+        }
+
+        Ok(SectionMap {
+            sections,
+            symbols,
+            warnings,
+        })
+    }
+}
+
+/// The start, end and kind of a memory region.
+fn region_range(region: &MemoryRegion) -> (u32, u32, &'static str) {
+    match region {
+        MemoryRegion::Ram(ram) => (ram.range.start, ram.range.end, "ram"),
+        MemoryRegion::Nvm(nvm) => (nvm.range.start, nvm.range.end, "flash"),
+        MemoryRegion::Generic(generic) => (generic.range.start, generic.range.end, "memory"),
+    }
+}
@@ -0,0 +1,215 @@
+//! A step by step check of the probe and target connection
+//!
+//! `selftest` answers "is the wiring even right?" by going from the probe to the target memory
+//! and stopping at the first step that fails, with advice on what to check. A running core is
+//! halted for the memory steps and resumed at the end, a halted core stays halted, and the
+//! scratch RAM word gets its original value back. The probe is open for the whole session, so
+//! the target voltage is the one measured when it was opened.
+use anyhow::{anyhow, Result};
+use probe_rs::architecture::arm::{DapAccess, DpAddress};
+use probe_rs::config::MemoryRegion;
+use probe_rs::{Probe, Session};
+use serde::Serialize;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::session_summary::ProbeDetails;
+
+const DP_IDR: u8 = 0x0;
+const CPUID: u32 = 0xe000_ed00;
+const SCRATCH_PATTERN: u32 = 0xa5a5_5a5a;
+const ERASED_WORD: u32 = 0xffff_ffff;
+const HALT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Below this the target is taken to be unpowered.
+const MIN_TARGET_VOLTAGE: f32 = 1.0;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfTestStep {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: String,
+    pub micros: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SelfTestReport {
+    pub steps: Vec<SelfTestStep>,
+    /// What to check for the first failed step.
+    pub advice: Option<String>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.advice.is_none()
+    }
+
+    /// Runs the step unless an earlier one failed.
+    fn step(&mut self, name: &'static str, advice: &str, check: impl FnOnce() -> Result<String>) {
+        if self.passed() {
+            self.run_step(name, advice, check);
+        }
+    }
+
+    fn run_step(&mut self, name: &'static str, advice: &str, check: impl FnOnce() -> Result<String>) {
+        let started = Instant::now();
+        let result = check();
+        let micros = started.elapsed().as_micros() as u64;
+        let (passed, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(err) => {
+                self.advice
+                    .get_or_insert_with(|| format!("{} failed: {}", name, advice));
+                (false, err.to_string())
+            }
+        };
+        self.steps.push(SelfTestStep {
+            name,
+            passed,
+            detail,
+            micros,
+        });
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Self test:")?;
+        for step in &self.steps {
+            writeln!(
+                f,
+                "  {:<4}  {:<17} {:>9.1} ms  {}",
+                if step.passed { "pass" } else { "FAIL" },
+                step.name,
+                step.micros as f64 / 1000.0,
+                step.detail
+            )?;
+        }
+        match &self.advice {
+            Some(advice) => write!(f, "{}", advice),
+            None => write!(f, "All steps passed"),
+        }
+    }
+}
+
+pub fn self_test(session: &mut Session, probe: Option<&ProbeDetails>) -> SelfTestReport {
+    let mut report = SelfTestReport::default();
+
+    report.step(
+        "enumerate probe",
+        "check the USB cable and the probe drivers or udev rules",
+        || {
+            let probe = probe.ok_or_else(|| anyhow!("No probe details"))?;
+            match Probe::list_all().iter().any(|info| {
+                info.identifier == probe.name && info.serial_number == probe.serial
+            }) {
+                true => Ok(probe.name.clone()),
+                false => Err(anyhow!("{} is not listed", probe.name)),
+            }
+        },
+    );
+    report.step(
+        "target voltage",
+        "check the target power and the VTref pin of the probe",
+        || match probe.and_then(|probe| probe.target_voltage) {
+            Some(voltage) if voltage < MIN_TARGET_VOLTAGE => {
+                Err(anyhow!("{:.2} V when the probe was opened", voltage))
+            }
+            Some(voltage) => Ok(format!("{:.2} V when the probe was opened", voltage)),
+            None => Ok("not measured by this probe".to_owned()),
+        },
+    );
+    report.step(
+        "read DP IDR",
+        "check SWCLK/SWDIO wiring and target power",
+        || {
+            let idr = session
+                .get_arm_interface()?
+                .read_raw_dp_register(DpAddress::Default, DP_IDR)?;
+            Ok(format!("{:#010x}", idr))
+        },
+    );
+    report.step(
+        "read CPUID",
+        "check that the chip matches the target, the debug port answers but the core doesn't",
+        || match session.core(0)?.read_word_32(CPUID)? {
+            0 | ERASED_WORD => Err(anyhow!("No core at the debug port")),
+            cpuid => Ok(format!("{:#010x}", cpuid)),
+        },
+    );
+
+    let mut resume = false;
+    report.step(
+        "halt core",
+        "the core may be held in reset or in a deep sleep mode, try attaching under reset",
+        || {
+            let mut core = session.core(0)?;
+            if core.core_halted()? {
+                return Ok("already halted, it stays halted".to_owned());
+            }
+            let info = core.halt(HALT_TIMEOUT)?;
+            resume = true;
+            Ok(format!("halted at {:#010x}", info.pc))
+        },
+    );
+
+    let memory_map = session.target().memory_map.clone();
+    report.step(
+        "scratch RAM word",
+        "check that the chip matches the target, the RAM is not where the target places it",
+        || {
+            let address = memory_map
+                .iter()
+                .find_map(|region| match region {
+                    MemoryRegion::Ram(ram) => Some(ram.range.start),
+                    _ => None,
+                })
+                .ok_or_else(|| anyhow!("No RAM in the memory map of the target"))?;
+            let mut core = session.core(0)?;
+            let original = core.read_word_32(address)?;
+            core.write_word_32(address, SCRATCH_PATTERN)?;
+            let read_back = core.read_word_32(address);
+            core.write_word_32(address, original)?;
+            match read_back? {
+                SCRATCH_PATTERN => Ok(format!("{:#010x}", address)),
+                value => Err(anyhow!(
+                    "Wrote {:#010x} to {:#010x} and read back {:#010x}",
+                    SCRATCH_PATTERN,
+                    address,
+                    value
+                )),
+            }
+        },
+    );
+    report.step(
+        "first flash word",
+        "check that the chip matches the target, the flash is not where the target places it",
+        || {
+            let address = memory_map
+                .iter()
+                .find_map(|region| match region {
+                    MemoryRegion::Nvm(nvm) => Some(nvm.range.start),
+                    _ => None,
+                })
+                .ok_or_else(|| anyhow!("No flash in the memory map of the target"))?;
+            match session.core(0)?.read_word_32(address)? {
+                ERASED_WORD => Ok(format!("{:#010x} is erased", address)),
+                value => Ok(format!("{:#010x} = {:#010x}", address, value)),
+            }
+        },
+    );
+
+    // The core is resumed even if a step in between failed
+    if resume {
+        report.run_step(
+            "resume core",
+            "the core was left halted, continue it",
+            || {
+                session.core(0)?.run()?;
+                Ok("running".to_owned())
+            },
+        );
+    }
+
+    report
+}
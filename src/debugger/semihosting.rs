@@ -0,0 +1,177 @@
+//! The command line of semihosting firmware
+//!
+//! Firmware asks for its command line with the SYS_GET_CMDLINE semihosting call, `BKPT 0xAB`
+//! with the operation in R0 and the address of a parameter block in R1. The block holds the
+//! address and the size of a buffer, the line is written to the buffer with a terminating NUL
+//! and its length to the second word of the block.
+//!
+//! The line is the `env` variables as `NAME=value` words followed by the `args`, the convention
+//! of `env(1)`, so the firmware tells the two apart by the `=` of the leading words. Words with
+//! spaces are quoted with `"`. There is no program name in front of the arguments. Only this call
+//! is answered, the core stops at the other semihosting calls like at any breakpoint.
+//!
+//! In a test run the console calls are answered as well, see `test_run`: the `:tt` console is
+//! opened, written to and the firmware exits with SYS_EXIT or SYS_EXIT_EXTENDED. Other files
+//! can't be opened.
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const SEMIHOSTING_BKPT: u8 = 0xab;
+const SYS_OPEN: u32 = 0x01;
+const SYS_WRITEC: u32 = 0x03;
+const SYS_WRITE0: u32 = 0x04;
+const SYS_WRITE: u32 = 0x05;
+const SYS_GET_CMDLINE: u32 = 0x15;
+const SYS_EXIT: u32 = 0x18;
+const SYS_EXIT_EXTENDED: u32 = 0x20;
+const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x20026;
+const CALL_FAILED: u32 = u32::MAX;
+// The console is opened with mode `w` for stdout and `a` for stderr.
+const STDOUT_HANDLE: u32 = 1;
+const STDERR_HANDLE: u32 = 2;
+const OPEN_MODE_APPEND: u32 = 8;
+// Longer SYS_WRITE0 strings are cut.
+const MAX_STRING: u32 = 4096;
+
+/// A console call of test firmware.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConsoleCall {
+    /// The console was opened or another call was answered, nothing to report.
+    Answered,
+    Output(String),
+    Exit {
+        success: bool,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CommandLine {
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+}
+
+impl CommandLine {
+    pub fn line(&self) -> String {
+        self.env
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .chain(self.args.iter().cloned())
+            .map(|word| match word.contains(char::is_whitespace) || word.is_empty() {
+                true => format!("\"{}\"", word),
+                false => word,
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    /// Answers the semihosting call the core halted at, returns `false` if it is not a
+    /// SYS_GET_CMDLINE call. A line too long for the buffer is truncated and the warning returned.
+    pub fn service(&self, core: &mut probe_rs::Core) -> Result<(bool, Option<String>)> {
+        if core.read_core_reg(0u16)? != SYS_GET_CMDLINE {
+            return Ok((false, None));
+        }
+        let block = core.read_core_reg(1u16)?;
+        let buffer = core.read_word_32(block)?;
+        let size = core.read_word_32(block + 4)? as usize;
+
+        let line = self.line();
+        // One byte of the buffer is the NUL
+        let mut length = line.len().min(size.saturating_sub(1));
+        while !line.is_char_boundary(length) {
+            length -= 1;
+        }
+        let warning = match length < line.len() {
+            true => Some(format!(
+                "The semihosting command line is {} bytes, truncated to the {} byte buffer of the target",
+                line.len(),
+                size
+            )),
+            false => None,
+        };
+        if size == 0 {
+            core.write_core_reg(0u16.into(), CALL_FAILED)?;
+            return Ok((true, warning));
+        }
+
+        let mut bytes = line.as_bytes()[..length].to_vec();
+        bytes.push(0);
+        core.write_8(buffer, &bytes)?;
+        core.write_word_32(block + 4, length as u32)?;
+        core.write_core_reg(0u16.into(), 0)?;
+        Ok((true, warning))
+    }
+}
+
+/// Answers the console call the core halted at, None if it is another call. An exit is not
+/// answered, the firmware doesn't expect to run after it.
+pub fn service_console(core: &mut probe_rs::Core) -> Result<Option<ConsoleCall>> {
+    let parameter = core.read_core_reg(1u16)?;
+    let (result, call) = match core.read_core_reg(0u16)? {
+        SYS_OPEN => {
+            let name = core.read_word_32(parameter)?;
+            let mode = core.read_word_32(parameter + 4)?;
+            let length = core.read_word_32(parameter + 8)?;
+            let mut bytes = vec![0u8; length.min(MAX_STRING) as usize];
+            core.read_8(name, &mut bytes)?;
+            let handle = match (bytes.as_slice(), mode) {
+                (b":tt", mode) if mode >= OPEN_MODE_APPEND => STDERR_HANDLE,
+                (b":tt", _) => STDOUT_HANDLE,
+                _ => CALL_FAILED,
+            };
+            (handle, ConsoleCall::Answered)
+        }
+        SYS_WRITEC => {
+            let mut byte = [0u8];
+            core.read_8(parameter, &mut byte)?;
+            (0, output(&byte))
+        }
+        SYS_WRITE0 => {
+            let mut bytes = vec![];
+            let mut chunk = [0u8; 64];
+            while (bytes.len() as u32) < MAX_STRING {
+                core.read_8(parameter + bytes.len() as u32, &mut chunk)?;
+                match chunk.iter().position(|b| *b == 0) {
+                    Some(end) => {
+                        bytes.extend_from_slice(&chunk[..end]);
+                        break;
+                    }
+                    None => bytes.extend_from_slice(&chunk),
+                }
+            }
+            (0, output(&bytes))
+        }
+        SYS_WRITE => {
+            let handle = core.read_word_32(parameter)?;
+            let data = core.read_word_32(parameter + 4)?;
+            let length = core.read_word_32(parameter + 8)?;
+            if handle != STDOUT_HANDLE && handle != STDERR_HANDLE {
+                // None of the bytes are written
+                (length, ConsoleCall::Answered)
+            } else {
+                let mut bytes = vec![0u8; length as usize];
+                core.read_8(data, &mut bytes)?;
+                (0, output(&bytes))
+            }
+        }
+        SYS_EXIT => {
+            return Ok(Some(ConsoleCall::Exit {
+                success: parameter == ADP_STOPPED_APPLICATION_EXIT,
+            }))
+        }
+        SYS_EXIT_EXTENDED => {
+            let reason = core.read_word_32(parameter)?;
+            let code = core.read_word_32(parameter + 4)?;
+            return Ok(Some(ConsoleCall::Exit {
+                success: reason == ADP_STOPPED_APPLICATION_EXIT && code == 0,
+            }));
+        }
+        _ => return Ok(None),
+    };
+    core.write_core_reg(0u16.into(), result)?;
+    Ok(Some(call))
+}
+
+fn output(bytes: &[u8]) -> ConsoleCall {
+    ConsoleCall::Output(String::from_utf8_lossy(bytes).to_string())
+}
@@ -0,0 +1,151 @@
+//! The first fatal error of a session
+//!
+//! When the probe is lost every request fails with whatever error it happens to hit first, which
+//! hides the cause. The first probe error ends the session instead and is kept as its fault. The
+//! requests after it fail with the fault until a new attach or launch recovers the session.
+use chrono::{DateTime, Local};
+use std::fmt;
+
+/// Returned when a probe error ended the session.
+#[derive(Debug, Clone)]
+pub struct SessionFault {
+    pub cause: String,
+    pub operation: String,
+    pub time: DateTime<Local>,
+    /// False if the fault was hit by a status poll, no request waits for it.
+    pub request: bool,
+}
+
+impl SessionFault {
+    pub fn new(operation: &str, err: &anyhow::Error, request: bool) -> SessionFault {
+        SessionFault {
+            cause: format!("{:#}", err),
+            operation: operation.to_owned(),
+            time: Local::now(),
+            request,
+        }
+    }
+
+    /// The error of a request that can't run in the faulted session.
+    pub fn message(&self) -> String {
+        format!(
+            "{}; this request cannot proceed, attach again to recover the session",
+            self
+        )
+    }
+}
+
+impl fmt::Display for SessionFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The probe failed at {} during {}: {}",
+            self.time.format("%H:%M:%S"),
+            self.operation,
+            self.cause
+        )
+    }
+}
+
+impl std::error::Error for SessionFault {}
+
+/// Errors of the probe itself, after them nothing more can be done with the target.
+pub fn is_fatal(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<probe_rs::Error>(),
+            Some(probe_rs::Error::Probe(_))
+        ) || cause.downcast_ref::<probe_rs::DebugProbeError>().is_some()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::DebugHandler;
+    use super::*;
+    use crate::commands::{debug_request::DebugRequest, debug_response::DebugResponse};
+    use anyhow::anyhow;
+    use crossbeam_channel::unbounded;
+
+    fn faulted() -> (DebugHandler, SessionFault) {
+        let fault = SessionFault::new("read", &anyhow!("The probe was disconnected"), true);
+        let mut handler = DebugHandler::new_default();
+        handler.fault = Some(fault.clone());
+        (handler, fault)
+    }
+
+    fn response(handler: &mut DebugHandler, request: DebugRequest) -> DebugResponse {
+        let (mut sender, _) = unbounded();
+        let (_, mut receiver) = unbounded();
+        let (_, response) = handler
+            .handle_request(&mut sender, &mut receiver, request)
+            .unwrap();
+        response
+    }
+
+    fn error_message(response: DebugResponse) -> String {
+        match response {
+            DebugResponse::Error { message } => message,
+            response => panic!("Expected an error, got {:?}", response),
+        }
+    }
+
+    #[test]
+    fn requests_fail_with_the_fault() {
+        let (mut handler, fault) = faulted();
+        let requests = [
+            DebugRequest::Status,
+            DebugRequest::Continue,
+            DebugRequest::Stack,
+            DebugRequest::Read {
+                address: 0x2000_0000,
+                byte_size: 4,
+            },
+            DebugRequest::Reset {
+                reset_and_halt: true,
+            },
+        ];
+        for request in requests {
+            let message = error_message(response(&mut handler, request));
+            assert_eq!(message, fault.message());
+            assert!(handler.fault.is_some());
+        }
+    }
+
+    #[test]
+    fn only_attach_and_flash_reinitialise() {
+        let requests = [
+            DebugRequest::Attach {
+                reset: false,
+                reset_and_halt: false,
+            },
+            DebugRequest::Flash {
+                reset_and_halt: true,
+            },
+        ];
+        for request in requests {
+            let (mut handler, fault) = faulted();
+            // Without a chip the new session stops before the probe is opened
+            let message = error_message(response(&mut handler, request));
+            assert_ne!(message, fault.message());
+            assert_eq!(message, handler.config.missing_config_message());
+            assert!(handler.fault.is_some());
+        }
+    }
+
+    #[test]
+    fn configuration_requests_run_after_a_fault() {
+        let (mut handler, _) = faulted();
+        let response = response(
+            &mut handler,
+            DebugRequest::SetHaltOnReset { enabled: false },
+        );
+        assert!(
+            matches!(response, DebugResponse::SetHaltOnReset),
+            "{:?}",
+            response
+        );
+        assert!(!handler.config.halt_on_reset);
+        assert!(handler.fault.is_some());
+    }
+}
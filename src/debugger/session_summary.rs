@@ -0,0 +1,103 @@
+//! Summary of the session printed when it starts and by `info target`
+//!
+//! Collects the probe, target and binary details in one place so that a copy of the console
+//! output has everything needed to reproduce a problem.
+use std::fmt;
+use std::path::PathBuf;
+
+use super::breakpoint_unit::BreakpointUnit;
+use super::core_clock::CoreClock;
+
+/// The probe as it was opened, before attaching to the target.
+#[derive(Debug, Clone)]
+pub struct ProbeDetails {
+    pub name: String,
+    pub serial: Option<String>,
+    pub protocol: Option<String>,
+    pub speed_khz: u32,
+    /// Measured when the probe was opened, not all probes can.
+    pub target_voltage: Option<f32>,
+}
+
+impl ProbeDetails {
+    pub fn new(info: &probe_rs::DebugProbeInfo, probe: &mut probe_rs::Probe) -> ProbeDetails {
+        ProbeDetails {
+            name: info.identifier.clone(),
+            serial: info.serial_number.clone(),
+            protocol: probe.protocol().map(|protocol| format!("{:?}", protocol)),
+            speed_khz: probe.speed_khz(),
+            target_voltage: probe.get_target_voltage().unwrap_or(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionSummary {
+    pub version: String,
+    /// None for a core dump.
+    pub probe: Option<ProbeDetails>,
+    pub chip: String,
+    pub core_type: Option<String>,
+    pub breakpoint_units: Option<(u32, BreakpointUnit)>,
+    pub binary: Option<PathBuf>,
+    pub build_id: Option<String>,
+    /// Whether the start of the binary in flash matches the target, unknown for core dumps.
+    pub matches_flash: Option<bool>,
+    pub core_dump: Option<PathBuf>,
+    pub core_clock: Option<CoreClock>,
+}
+
+impl fmt::Display for SessionSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "embedded-rust-debugger {}", self.version)?;
+
+        match &self.probe {
+            Some(probe) => {
+                write!(f, "Probe:  {}", probe.name)?;
+                if let Some(serial) = &probe.serial {
+                    write!(f, " (serial {})", serial)?;
+                }
+                match &probe.protocol {
+                    Some(protocol) => write!(f, ", {} at {} kHz", protocol, probe.speed_khz)?,
+                    None => write!(f, ", {} kHz", probe.speed_khz)?,
+                };
+                match probe.target_voltage {
+                    Some(voltage) => writeln!(f, ", target at {:.2} V", voltage)?,
+                    None => writeln!(f)?,
+                };
+            }
+            None => {
+                if let Some(path) = &self.core_dump {
+                    writeln!(f, "Core dump: {}", path.display())?;
+                }
+            }
+        };
+
+        write!(f, "Target: {}", self.chip)?;
+        if let Some(core_type) = &self.core_type {
+            write!(f, ", {} core", core_type)?;
+        }
+        if let Some((units, unit)) = self.breakpoint_units {
+            write!(f, ", {} hardware breakpoints ({})", units, unit)?;
+        }
+        writeln!(f)?;
+        if let Some(clock) = &self.core_clock {
+            writeln!(f, "Core clock: {}", clock)?;
+        }
+
+        match &self.binary {
+            Some(path) => {
+                write!(f, "Binary: {}", path.display())?;
+                if let Some(build_id) = &self.build_id {
+                    write!(f, ", build id {}", build_id)?;
+                }
+                match self.matches_flash {
+                    Some(true) => write!(f, ", matches flash"),
+                    Some(false) => write!(f, ", does NOT match flash"),
+                    None => Ok(()),
+                }
+            }
+            None => write!(f, "Binary: none"),
+        }
+    }
+}
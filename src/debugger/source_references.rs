@@ -0,0 +1,76 @@
+//! Sources for the frames whose file isn't on disk
+//!
+//! A frame in code built elsewhere, e.g. `core` or a crate compiled on CI, points at a path that
+//! doesn't exist locally. Such frames get a `sourceReference` instead, and the editor asks for
+//! the content with the `source` request: the source embedded in the line table if the binary
+//! has it, else a disassembly listing of the function.
+use super::disassemble::Decoded;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceContent {
+    /// The source embedded in the DWARF for the path.
+    Embedded { path: String },
+    /// A listing of the function.
+    Disassembly {
+        address: u32,
+        size: u32,
+        name: String,
+    },
+}
+
+/// The generated sources handed out, a reference is the index plus one as 0 means none in DAP.
+#[derive(Debug, Default)]
+pub struct SourceReferences {
+    sources: Vec<SourceContent>,
+}
+
+impl SourceReferences {
+    /// The reference of the content, the same one every time it is asked for.
+    pub fn reference(&mut self, content: SourceContent) -> i64 {
+        match self.sources.iter().position(|source| *source == content) {
+            Some(index) => index as i64 + 1,
+            None => {
+                self.sources.push(content);
+                self.sources.len() as i64
+            }
+        }
+    }
+
+    pub fn get(&self, reference: i64) -> Option<&SourceContent> {
+        match reference {
+            r if r > 0 => self.sources.get(r as usize - 1),
+            _ => None,
+        }
+    }
+
+    /// The references are only valid for the symbols they were made from.
+    pub fn clear(&mut self) {
+        self.sources.clear();
+    }
+}
+
+/// The listing of a function, one instruction per line after the name.
+pub fn listing(name: &str, instructions: &[Decoded]) -> String {
+    let mut lines = vec![format!("{}:", name)];
+    for insn in instructions {
+        let bytes: Vec<String> = insn.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        lines.push(format!(
+            "    {:#010x}:  {:<12} {}",
+            insn.address,
+            bytes.join(""),
+            insn.text
+        ));
+    }
+    lines.join("\n")
+}
+
+/// The line of the instruction containing the address in the listing, 1 for the name.
+pub fn listing_line(instructions: &[Decoded], address: u32) -> i64 {
+    match instructions
+        .iter()
+        .position(|insn| address < insn.address + insn.bytes.len() as u32)
+    {
+        Some(index) => index as i64 + 2,
+        None => 1,
+    }
+}
@@ -0,0 +1,240 @@
+use anyhow::{anyhow, Result};
+use capstone::Capstone;
+use debugserver_types::Breakpoint;
+use probe_rs::MemoryInterface;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::architecture;
+use super::cancel::CancelToken;
+use super::code_address::CodeAddress;
+use super::continue_fix;
+use super::vector_table::exception_name;
+
+// How long `nexti` waits for a called function to return.
+const STEP_OVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns the active exception number if the core is halted in an exception handler.
+pub fn active_exception(core: &mut probe_rs::Core) -> Result<Option<u32>> {
+    architecture::of(core).active_exception(core)
+}
+
+/// Returns the active exception number if `pc` is a breakpoint in an exception handler.
+pub fn breakpoint_in_exception(
+    core: &mut probe_rs::Core,
+    breakpoints: &HashMap<u32, Breakpoint>,
+    pc: u32,
+) -> Result<Option<u32>> {
+    if !breakpoints.contains_key(&pc) {
+        return Ok(None);
+    }
+    active_exception(core)
+}
+
+/// The stopped event description of a step that ended at a breakpoint in an exception handler.
+pub fn abandoned_description(step: &str, exception: u32) -> String {
+    format!(
+        "The {} was abandoned due to a breakpoint in the {} handler",
+        step,
+        exception_name(exception)
+    )
+}
+
+/// A breakpoint hit in an exception handler that didn't end a step.
+#[derive(Debug, Clone)]
+pub struct DeferredHit {
+    pub address: u32,
+    pub exception: u32,
+}
+
+impl DeferredHit {
+    pub fn message(&self, step: &str) -> String {
+        format!(
+            "Breakpoint at {:#010x} hit in the {} handler during {}, resumed to complete the step",
+            self.address,
+            exception_name(self.exception),
+            step
+        )
+    }
+}
+
+/// The values of all the core registers at one point in time.
+#[derive(Debug, Clone)]
+pub struct RegisterSnapshot {
+    pub registers: Vec<(String, u32)>,
+}
+
+impl RegisterSnapshot {
+    pub fn read(core: &mut probe_rs::Core) -> Result<RegisterSnapshot> {
+        let register_file = core.registers();
+
+        let mut registers = vec![];
+        for register in register_file.registers() {
+            let value = core.read_core_reg(register)?;
+            registers.push((format!("{}", register.name()), value));
+        }
+
+        Ok(RegisterSnapshot { registers })
+    }
+
+    /// Returns the registers that differ from the earlier snapshot as (name, old, new).
+    pub fn changed(&self, earlier: &RegisterSnapshot) -> Vec<(String, u32, u32)> {
+        self.registers
+            .iter()
+            .zip(earlier.registers.iter())
+            .filter(|((_, new), (_, old))| new != old)
+            .map(|((name, new), (_, old))| (name.clone(), *old, *new))
+            .collect()
+    }
+}
+
+/// The result of stepping one machine instruction.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstructionStep {
+    pub pc: u32,
+    pub instruction: String,
+    pub changed_registers: Vec<(String, u32, u32)>,
+}
+
+/// Disassembles the instruction at `address`, returns its text and size in bytes.
+pub fn disassemble_instruction(
+    core: &mut probe_rs::Core,
+    capstone: &Capstone,
+    address: u32,
+) -> Result<(String, u32, bool)> {
+    let mut code = [0u8; 4];
+    core.read_8(address, &mut code)?;
+
+    let insns = match capstone.disasm_count(&code, address as u64, 1) {
+        Ok(val) => val,
+        Err(err) => return Err(anyhow!("Failed to disassemble: {}", err)),
+    };
+    match insns.iter().next() {
+        Some(insn) => {
+            let is_call = match insn.mnemonic() {
+                Some(mnemonic) => architecture::of(core).is_call(mnemonic),
+                None => false,
+            };
+            Ok((insn.to_string(), insn.bytes().len() as u32, is_call))
+        }
+        None => Err(anyhow!("No valid instruction at {:#010x}", address)),
+    }
+}
+
+/// Steps exactly one machine instruction, or over a whole call if `over_calls` is set and the
+/// instruction is a call. Returns the new pc.
+///
+/// With `deferred` the breakpoints hit in exception handlers while running over a call are
+/// collected there and the core is resumed, instead of ending the step at them. A cancelled
+/// `cancel` halts the core in the call and fails the step.
+pub fn step_instruction(
+    core: &mut probe_rs::Core,
+    capstone: &Capstone,
+    breakpoints: &HashMap<u32, Breakpoint>,
+    over_calls: bool,
+    mut deferred: Option<&mut Vec<DeferredHit>>,
+    cancel: &CancelToken,
+) -> Result<u32> {
+    let pc = core.read_core_reg(core.registers().program_counter())?;
+
+    if over_calls {
+        let (_, size, is_call) = disassemble_instruction(core, capstone, pc)?;
+        if is_call {
+            let return_address = CodeAddress::new(pc).offset(size).address();
+            let temporary_breakpoint = !breakpoints.contains_key(&return_address);
+            if temporary_breakpoint {
+                core.set_hw_breakpoint(return_address)?;
+            }
+
+            continue_fix(core, breakpoints)?;
+            core.run()?;
+            let mut result = cancel.wait_for_core_halted(core, STEP_OVER_TIMEOUT);
+            while result.is_ok() {
+                let deferred = match deferred.as_mut() {
+                    Some(val) => val,
+                    None => break,
+                };
+                let pc = core.read_core_reg(core.registers().program_counter())?;
+                if pc == return_address {
+                    break;
+                }
+                let exception = match breakpoint_in_exception(core, breakpoints, pc)? {
+                    Some(val) => val,
+                    None => break,
+                };
+                deferred.push(DeferredHit {
+                    address: pc,
+                    exception,
+                });
+                continue_fix(core, breakpoints)?;
+                core.run()?;
+                result = cancel.wait_for_core_halted(core, STEP_OVER_TIMEOUT);
+            }
+            if result.is_err() {
+                core.halt(STEP_OVER_TIMEOUT)?;
+            }
+
+            if temporary_breakpoint {
+                core.clear_hw_breakpoint(return_address)?;
+            }
+            result?;
+
+            return Ok(core.read_core_reg(core.registers().program_counter())?);
+        }
+    }
+
+    Ok(continue_fix(core, breakpoints)?)
+}
+
+/// An in progress `until`, stepped one instruction at a time between requests so that it can
+/// be interrupted.
+#[derive(Debug, Clone)]
+pub struct UntilState {
+    pub function: String,
+    pub start_line: u64,
+    pub cfa: u32,
+    pub return_address: u32,
+    pub temporary_breakpoint: bool,
+    /// The module the pc was in, see `Symbols::module_index`.
+    pub module: Option<usize>,
+}
+
+impl UntilState {
+    /// Returns true if the pc moved into another module, e.g. a bootloader jumped into the
+    /// application. The lines of two modules can't be compared. Code outside every module, e.g.
+    /// a function run from RAM, is still stepped through.
+    pub fn left_module(&self, module: Option<usize>) -> bool {
+        matches!((self.module, module), (Some(start), Some(module)) if start != module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn until(module: Option<usize>) -> UntilState {
+        UntilState {
+            function: "boot::jump_to_application".to_owned(),
+            start_line: 42,
+            cfa: 0x2000_7ff0,
+            return_address: 0x0800_1235,
+            temporary_breakpoint: false,
+            module,
+        }
+    }
+
+    #[test]
+    fn until_stops_when_the_bootloader_starts_the_application() {
+        assert!(until(Some(0)).left_module(Some(1)));
+        assert!(!until(Some(0)).left_module(Some(0)));
+    }
+
+    #[test]
+    fn until_steps_through_code_outside_the_modules() {
+        assert!(!until(Some(0)).left_module(None));
+        assert!(!until(None).left_module(Some(0)));
+        assert!(!until(None).left_module(None));
+    }
+}
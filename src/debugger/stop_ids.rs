@@ -0,0 +1,98 @@
+//! Deterministic ids of the frames, scopes and variables of a stop
+//!
+//! The ids are derived from the stop generation and the position of the frame in the call stack
+//! instead of a running counter, so evaluating the same stop again, e.g. after a retried
+//! `stackTrace` or a format change, hands out the same ids. A reference left over from a previous
+//! stop belongs to another generation and can't name a frame of the current one.
+//!
+//! Layout of an id: the generation in bits 32 and up, the frame index in bits 20-31, then the
+//! scope kind, or the index of the variable in the arena of the frame, in the low 20 bits.
+use anyhow::{anyhow, Result};
+
+const GENERATION_SHIFT: u32 = 32;
+const FRAME_SHIFT: u32 = 20;
+
+/// Keeps the ids below 2^53, the largest integer a JavaScript frontend holds exactly.
+const MAX_GENERATION: i64 = (1 << (53 - GENERATION_SHIFT)) - 1;
+const MAX_FRAMES: usize = 1 << (GENERATION_SHIFT - FRAME_SHIFT);
+const ARENA_SIZE: i64 = 1 << FRAME_SHIFT;
+
+/// The variable references of a frame start after its scope references.
+const FIRST_VARIABLE: i64 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeKind {
+    Locals = 1,
+    Arguments = 2,
+    Registers = 3,
+}
+
+#[derive(Debug)]
+pub struct StopIds {
+    generation: i64,
+}
+
+impl StopIds {
+    pub fn new() -> StopIds {
+        StopIds { generation: 1 }
+    }
+
+    /// The core resumed, the ids of the previous stop are no longer valid.
+    pub fn next_stop(&mut self) {
+        self.generation = self.generation % MAX_GENERATION + 1;
+    }
+
+    pub fn frame_id(&self, index: usize) -> Result<i64> {
+        if index >= MAX_FRAMES {
+            return Err(anyhow!(
+                "The call stack is deeper than {} frames",
+                MAX_FRAMES
+            ));
+        }
+        Ok((self.generation << GENERATION_SHIFT) | ((index as i64) << FRAME_SHIFT))
+    }
+
+    /// Returns `None` for ids that are not a frame of the current stop.
+    pub fn frame_index(&self, frame_id: i64) -> Option<usize> {
+        if frame_id >> GENERATION_SHIFT != self.generation || frame_id & (ARENA_SIZE - 1) != 0 {
+            return None;
+        }
+        Some(((frame_id >> FRAME_SHIFT) as usize) & (MAX_FRAMES - 1))
+    }
+
+    /// The frame a scope or variable reference of the current stop belongs to.
+    pub fn reference_frame(&self, reference: i64) -> Option<usize> {
+        self.frame_index(reference & !(ARENA_SIZE - 1))
+    }
+
+    pub fn scope_reference(&self, index: usize, kind: ScopeKind) -> Result<i64> {
+        Ok(self.frame_id(index)? | kind as i64)
+    }
+
+    /// The references of the variables with children in the scopes of a frame.
+    pub fn variables(&self, index: usize) -> Result<VariableArena> {
+        let frame_id = self.frame_id(index)?;
+        Ok(VariableArena {
+            next: frame_id + FIRST_VARIABLE,
+            end: frame_id + ARENA_SIZE,
+        })
+    }
+}
+
+/// Hands out variable references in the order the variables are visited.
+#[derive(Debug)]
+pub struct VariableArena {
+    next: i64,
+    end: i64,
+}
+
+impl VariableArena {
+    pub fn allocate(&mut self) -> Result<i64> {
+        if self.next >= self.end {
+            return Err(anyhow!("Too many variables in one frame"));
+        }
+        let id = self.next;
+        self.next += 1;
+        Ok(id)
+    }
+}
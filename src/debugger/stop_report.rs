@@ -0,0 +1,120 @@
+//! The state of a stop as a JSON document
+//!
+//! `export state.json` writes the call stack with the arguments, locals and registers of every
+//! frame, and the recent session events, for bug reports and for comparing two runs. Nothing that
+//! changes between runs of the same program is included, like the variable ids or the event
+//! times, so two exports of the same stop diff clean with standard tools.
+use serde::Serialize;
+
+use super::history::HistoryEntry;
+use super::StackFrame;
+use super::Variable;
+
+/// The most variables in a report, the rest are left out and the report marked truncated.
+const MAX_VARIABLES: usize = 20_000;
+
+/// The most recent session events in a report.
+const RECENT_EVENTS: usize = 20;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StopReport {
+    pub version: String,
+    /// How many levels of the variables are included, 1 is only the top level.
+    pub depth: usize,
+    pub truncated: bool,
+    pub frames: Vec<FrameReport>,
+    pub recent_events: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameReport {
+    pub index: usize,
+    pub function: String,
+    pub pc: u32,
+    pub cfa: Option<u32>,
+    pub file: Option<String>,
+    pub line: Option<u64>,
+    pub arguments: Vec<Variable>,
+    pub locals: Vec<Variable>,
+    pub registers: Vec<Variable>,
+}
+
+impl StopReport {
+    pub fn new(stack_trace: &[StackFrame], events: &[HistoryEntry], depth: usize) -> StopReport {
+        let mut budget = MAX_VARIABLES;
+        let mut truncated = false;
+        let mut copy =
+            |variables: &[Variable]| limit(variables, depth, &mut budget, &mut truncated);
+
+        let frames = stack_trace
+            .iter()
+            .map(|frame| FrameReport {
+                index: frame.state.index,
+                function: frame.name.clone(),
+                pc: frame.state.pc.address(),
+                cfa: frame.state.cfa,
+                file: match (&frame.source.directory, &frame.source.file) {
+                    (Some(directory), Some(file)) => Some(format!("{}/{}", directory, file)),
+                    (None, Some(file)) => Some(file.clone()),
+                    _ => None,
+                },
+                line: frame.source.line.map(|line| line.get()),
+                arguments: copy(&frame.arguments),
+                locals: copy(&frame.variables),
+                registers: copy(&frame.registers),
+            })
+            .collect();
+
+        let recent = events.len().saturating_sub(RECENT_EVENTS);
+        StopReport {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            depth,
+            truncated,
+            frames,
+            recent_events: events[recent..]
+                .iter()
+                .map(|entry| {
+                    format!(
+                        "{}: {}",
+                        format!("{:?}", entry.kind).to_lowercase(),
+                        entry.description
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Copies the variables down to `depth` levels while the budget lasts.
+fn limit(
+    variables: &[Variable],
+    depth: usize,
+    budget: &mut usize,
+    truncated: &mut bool,
+) -> Vec<Variable> {
+    let mut limited = vec![];
+    if depth == 0 {
+        return limited;
+    }
+    for variable in variables {
+        if *budget == 0 {
+            *truncated = true;
+            break;
+        }
+        *budget -= 1;
+        limited.push(Variable {
+            id: variable.id,
+            name: variable.name.clone(),
+            value: variable.value.clone(),
+            type_: variable.type_.clone(),
+            source: None,
+            kind: variable.kind.clone(),
+            children: limit(&variable.children, depth - 1, budget, truncated),
+            location: variable.location.clone(),
+            read_only: variable.read_only,
+        });
+    }
+    limited
+}
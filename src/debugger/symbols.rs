@@ -0,0 +1,1044 @@
+use super::code_address::CodeAddress;
+use super::config::{resolve_path, SymbolFile};
+use super::flash_image::{FlashImage, ImageSection};
+use super::values::Endianness;
+use crate::read_dwarf;
+use rust_debug::source_information::find_breakpoint_location;
+
+use anyhow::{anyhow, Context, Result};
+use gimli::{
+    DebugFrame, Dwarf, EndianRcSlice, FileEntry, LineProgramHeader, LittleEndian, Reader, Unit,
+};
+use log::{info, warn};
+use object::{FileFlags, Object, ObjectSection, ObjectSymbol, SymbolKind};
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::hash::Hasher;
+use std::num::NonZeroU64;
+use std::ops::{Range, RangeInclusive};
+use std::path::{Component, Path, PathBuf};
+
+pub type DwarfReader = EndianRcSlice<LittleEndian>;
+
+pub const NO_BINARY_MESSAGE: &str = "No binary loaded, use `load-symbols <path>` to load one";
+
+#[derive(Debug)]
+pub struct DebugInformation<R: Reader<Offset = usize>> {
+    pub path: PathBuf,
+    pub base_address: u32,
+    pub dwarf: Dwarf<R>,
+    pub debug_frame: DebugFrame<R>,
+    pub text: Option<TextSection>,
+    pub elf_symbols: Vec<ElfSymbol>,
+    pub hard_float: bool,
+    pub endianness: Endianness,
+    pub image: Vec<ImageSection>,
+    pub breakpoints: Vec<u32>,
+    /// False for a binary without DWARF, only its ELF symbols are known.
+    pub has_debug_info: bool,
+    pub build_id: Option<String>,
+    /// The files of the line tables, read once as a large binary has thousands.
+    pub source_files: Vec<PathBuf>,
+}
+
+impl DebugInformation<DwarfReader> {
+    pub fn new(symbol_file: &SymbolFile) -> Result<DebugInformation<DwarfReader>> {
+        let (dwarf, debug_frame) = read_dwarf(&symbol_file.path).context(format!(
+            "Failed to read debug information from {:?}",
+            symbol_file.path
+        ))?;
+        let text = TextSection::read(&symbol_file.path)?;
+        let elf_symbols = ElfSymbol::read(&symbol_file.path)?;
+        let hard_float = is_hard_float(&symbol_file.path)?;
+        let endianness = Endianness::of_elf(&symbol_file.path)?;
+        let image = ImageSection::read(&symbol_file.path)?;
+        let has_debug_info = dwarf.units().next()?.is_some();
+        let build_id = build_id(&symbol_file.path)?;
+        let source_files = source_files(&dwarf)?;
+
+        Ok(DebugInformation {
+            path: symbol_file.path.clone(),
+            base_address: symbol_file.base_address,
+            dwarf,
+            debug_frame,
+            text,
+            elf_symbols,
+            hard_float,
+            endianness,
+            image,
+            breakpoints: vec![],
+            has_debug_info,
+            build_id,
+            source_files,
+        })
+    }
+}
+
+impl<R: Reader<Offset = usize>> DebugInformation<R> {
+    /// Returns true if the address is inside the `.text` section of this module.
+    pub fn contains(&self, address: u32) -> bool {
+        match &self.text {
+            Some(text) => text.range.contains(&address.wrapping_sub(self.base_address)),
+            None => false,
+        }
+    }
+
+    /// Converts a target address into the address space the DWARF of this module uses.
+    pub fn to_link_address(&self, address: u32) -> u32 {
+        address.wrapping_sub(self.base_address)
+    }
+
+    /// Converts an address from the DWARF of this module into a target address.
+    pub fn to_target_address(&self, address: u32) -> u32 {
+        address.wrapping_add(self.base_address)
+    }
+
+    /// Returns true if the line tables of this module name the file, a relative name matches
+    /// the end of the path.
+    pub fn has_file(&self, path: &Path) -> bool {
+        let path = normalize_path(path);
+        self.source_files.iter().any(|file| match file.is_absolute() {
+            true => *file == path,
+            false => path.ends_with(file),
+        })
+    }
+}
+
+// Set in the ELF header flags of binaries using the hard float calling convention.
+const EF_ARM_ABI_FLOAT_HARD: u32 = 0x400;
+
+fn is_hard_float(path: &Path) -> Result<bool> {
+    let file = fs::File::open(&path)?;
+    let mmap = unsafe { memmap::Mmap::map(&file)? };
+    let object = object::File::parse(&*mmap)?;
+
+    match object.flags() {
+        FileFlags::Elf { e_flags, .. } => Ok(e_flags & EF_ARM_ABI_FLOAT_HARD != 0),
+        _ => Ok(false),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ElfSymbol {
+    pub name: String,
+    pub demangled: String,
+    pub address: u32,
+    pub size: u32,
+    pub is_function: bool,
+}
+
+impl ElfSymbol {
+    pub fn read(path: &Path) -> Result<Vec<ElfSymbol>> {
+        let file = fs::File::open(&path)?;
+        let mmap = unsafe { memmap::Mmap::map(&file)? };
+        let object = object::File::parse(&*mmap)?;
+
+        let mut elf_symbols = vec![];
+        for symbol in object.symbols() {
+            let is_function = match symbol.kind() {
+                SymbolKind::Text => true,
+                SymbolKind::Data => false,
+                _ => continue,
+            };
+            let name = match symbol.name() {
+                Ok(val) if !val.is_empty() => val.to_owned(),
+                _ => continue,
+            };
+            // Function symbols have the Thumb bit set
+            let address = if is_function {
+                CodeAddress::new(symbol.address() as u32).address()
+            } else {
+                symbol.address() as u32
+            };
+            elf_symbols.push(ElfSymbol {
+                demangled: demangle(&name),
+                name,
+                address,
+                size: symbol.size() as u32,
+                is_function,
+            });
+        }
+
+        Ok(elf_symbols)
+    }
+
+    /// Matches the raw name, the full demangled path or the last segments of the path.
+    pub fn matches(&self, name: &str) -> bool {
+        self.name == name
+            || self.demangled == name
+            || self.demangled.ends_with(&format!("::{}", name))
+    }
+}
+
+/// The path of a file of the line table, joined with its directory.
+fn file_path<R: Reader<Offset = usize>>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    header: &LineProgramHeader<R>,
+    file: &FileEntry<R>,
+) -> Result<String> {
+    let name = dwarf.attr_string(unit, file.path_name())?;
+    let mut path = PathBuf::from(name.to_string_lossy()?.as_ref());
+    if path.is_relative() {
+        if let Some(directory) = file.directory(header) {
+            let directory = dwarf.attr_string(unit, directory)?;
+            path = PathBuf::from(directory.to_string_lossy()?.as_ref()).join(path);
+        }
+    }
+    if path.is_relative() {
+        if let Some(comp_dir) = &unit.comp_dir {
+            path = PathBuf::from(comp_dir.to_string_lossy()?.as_ref()).join(path);
+        }
+    }
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// The files of all the line tables, without duplicates.
+fn source_files<R: Reader<Offset = usize>>(dwarf: &Dwarf<R>) -> Result<Vec<PathBuf>> {
+    let mut files = BTreeSet::new();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let program = match &unit.line_program {
+            Some(val) => val,
+            None => continue,
+        };
+        let header = program.header();
+        for file in header.file_names() {
+            let path = file_path(dwarf, &unit, header, file)?;
+            files.insert(normalize_path(Path::new(&path)));
+        }
+    }
+    Ok(files.into_iter().collect())
+}
+
+/// Drops the `.` and `..` components, units name the same file both ways.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push(component);
+                }
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// The first address of the line in the assembly units, every line is a statement in them. The
+/// breakpoint goes on the instruction of the line itself, not the next statement after it.
+fn find_assembly_line<R: Reader<Offset = usize>>(
+    dwarf: &Dwarf<R>,
+    path: &str,
+    line: u64,
+) -> Result<Option<u32>> {
+    let mut found: Option<u64> = None;
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        if !is_assembly_unit(&unit)? {
+            continue;
+        }
+        let program = match unit.line_program.clone() {
+            Some(val) => val,
+            None => continue,
+        };
+        let mut rows = program.rows();
+        while let Some((header, row)) = rows.next_row()? {
+            if row.end_sequence() || row.address() == 0 || row.line().map(|l| l.get()) != Some(line)
+            {
+                continue;
+            }
+            let matches = match header.file(row.file_index()) {
+                Some(file) => {
+                    let name = dwarf.attr_string(&unit, file.path_name())?;
+                    Path::new(path).ends_with(name.to_string_lossy()?.as_ref())
+                }
+                None => false,
+            };
+            if matches && found.map_or(true, |address| row.address() < address) {
+                found = Some(row.address());
+            }
+        }
+    }
+    Ok(found.map(|address| address as u32))
+}
+
+/// Returns true if the unit was assembled from an assembly source, e.g. a `.s` file or
+/// `global_asm!`. Assemblers mark them with the MIPS language code, whatever the architecture.
+fn is_assembly_unit<R: Reader<Offset = usize>>(unit: &Unit<R>) -> Result<bool> {
+    let mut entries = unit.entries();
+    let root = match entries.next_dfs()? {
+        Some((_, entry)) => entry,
+        None => return Ok(false),
+    };
+    Ok(matches!(
+        root.attr_value(gimli::DW_AT_language)?,
+        Some(gimli::AttributeValue::Language(
+            gimli::DW_LANG_Mips_Assembler
+        ))
+    ))
+}
+
+/// Demangles legacy Rust symbol names, e.g. `_ZN3app6BUFFER17h0123456789abcdefE` -> `app::BUFFER`.
+/// Names that are not mangled are returned unchanged.
+pub fn demangle(name: &str) -> String {
+    let mut rest = match name.strip_prefix("_ZN") {
+        Some(val) => val,
+        None => return name.to_owned(),
+    };
+
+    let mut segments = vec![];
+    while !rest.starts_with('E') {
+        let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+        let len: usize = match rest[..digits].parse() {
+            Ok(val) => val,
+            Err(_) => return name.to_owned(),
+        };
+        if rest.len() < digits + len {
+            return name.to_owned();
+        }
+        segments.push(&rest[digits..digits + len]);
+        rest = &rest[digits + len..];
+    }
+
+    // The last segment is a hash of the symbol
+    if let Some(last) = segments.last() {
+        if last.len() == 17 && last.starts_with('h') {
+            segments.pop();
+        }
+    }
+
+    segments
+        .iter()
+        .map(|segment| {
+            segment
+                .replace("$LT$", "<")
+                .replace("$GT$", ">")
+                .replace("$RF$", "&")
+                .replace("$u20$", " ")
+                .replace("$C$", ",")
+                .replace("..", "::")
+        })
+        .collect::<Vec<String>>()
+        .join("::")
+}
+
+#[derive(Debug, Clone)]
+pub struct TextSection {
+    pub range: Range<u32>,
+    pub hash: u64,
+}
+
+impl TextSection {
+    pub fn read(path: &Path) -> Result<Option<TextSection>> {
+        let file = fs::File::open(&path)?;
+        let mmap = unsafe { memmap::Mmap::map(&file)? };
+        let object = object::File::parse(&*mmap)?;
+
+        let section = match object.section_by_name(".text") {
+            Some(val) => val,
+            None => return Ok(None),
+        };
+
+        let mut hasher = DefaultHasher::new();
+        hasher.write(section.data()?);
+
+        let start = section.address() as u32;
+        Ok(Some(TextSection {
+            range: start..start + section.size() as u32,
+            hash: hasher.finish(),
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleInfo {
+    pub id: usize,
+    pub path: PathBuf,
+    pub base_address: u32,
+    pub address_range: Option<Range<u32>>,
+    /// The span of the sections of the module that are flashed.
+    pub flash_range: Option<Range<u32>>,
+    pub has_debug_info: bool,
+    pub build_id: Option<String>,
+}
+
+/// All the loaded symbol files, e.g. a bootloader and an application.
+pub struct Symbols {
+    pub modules: Vec<DebugInformation<DwarfReader>>,
+}
+
+impl Symbols {
+    pub fn load(symbol_files: &[SymbolFile]) -> Result<Symbols> {
+        let mut modules = vec![];
+        for symbol_file in symbol_files {
+            let module = DebugInformation::new(symbol_file)?;
+            info!(
+                "Loaded symbols from: {:?} at base address 0x{:08x}",
+                module.path, module.base_address
+            );
+            modules.push(module);
+        }
+
+        Ok(Symbols { modules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+
+    /// Returns the first loaded module, used when there is no address to select a module from.
+    pub fn main(&self) -> Result<&DebugInformation<DwarfReader>> {
+        self.modules.first().ok_or_else(|| anyhow!(NO_BINARY_MESSAGE))
+    }
+
+    /// Returns the module that contains the address, or the first module if none of them does.
+    pub fn module_for_address(&self, address: u32) -> Result<&DebugInformation<DwarfReader>> {
+        match self.modules.iter().find(|m| m.contains(address)) {
+            Some(module) => Ok(module),
+            None => self.main(),
+        }
+    }
+
+    /// The position of the module that contains the address, None if no module does.
+    pub fn module_index(&self, address: u32) -> Option<usize> {
+        self.modules.iter().position(|m| m.contains(address))
+    }
+
+    /// The modules with the source file, e.g. the `src/main.rs` of the application and not the
+    /// one of the bootloader. All the modules if none of them names it, their line tables then
+    /// match the path by its end.
+    pub fn modules_for_file(&self, path: &str) -> Vec<&DebugInformation<DwarfReader>> {
+        let modules: Vec<&DebugInformation<DwarfReader>> = self
+            .modules
+            .iter()
+            .filter(|module| module.has_file(Path::new(path)))
+            .collect();
+        match modules.is_empty() {
+            true => self.modules.iter().collect(),
+            false => modules,
+        }
+    }
+
+    pub fn module_infos(&self, image: &FlashImage) -> Vec<ModuleInfo> {
+        self.modules
+            .iter()
+            .enumerate()
+            .map(|(id, module)| ModuleInfo {
+                id,
+                path: module.path.clone(),
+                base_address: module.base_address,
+                address_range: module.text.as_ref().map(|text| {
+                    module.to_target_address(text.range.start)
+                        ..module.to_target_address(text.range.end)
+                }),
+                flash_range: image.flash_range(module),
+                has_debug_info: module.has_debug_info,
+                build_id: module.build_id.clone(),
+            })
+            .collect()
+    }
+
+    /// Finds the function containing the address, with its target address.
+    pub fn find_function(&self, address: u32) -> Option<ElfSymbol> {
+        for module in &self.modules {
+            let link_address = module.to_link_address(address);
+            let found = module.elf_symbols.iter().find(|symbol| {
+                symbol.is_function
+                    && link_address >= symbol.address
+                    && link_address < symbol.address + symbol.size.max(1)
+            });
+            if let Some(symbol) = found {
+                let mut symbol = symbol.clone();
+                symbol.address = module.to_target_address(symbol.address);
+                return Some(symbol);
+            }
+        }
+        None
+    }
+
+    /// Finds the ELF symbols matching the name in all the modules, with target addresses.
+    pub fn find_symbols(&self, name: &str) -> Vec<ElfSymbol> {
+        let mut found = vec![];
+        for module in &self.modules {
+            for symbol in &module.elf_symbols {
+                if symbol.matches(name) {
+                    let mut symbol = symbol.clone();
+                    symbol.address = module.to_target_address(symbol.address);
+                    found.push(symbol);
+                }
+            }
+        }
+        found
+    }
+
+    /// The names of the statics starting with the prefix, the last segment of the path unless the
+    /// prefix is a path itself. Compiler generated symbols are left out.
+    pub fn static_names(&self, prefix: &str) -> Vec<String> {
+        let mut names = vec![];
+        for module in &self.modules {
+            for symbol in &module.elf_symbols {
+                if symbol.is_function || symbol.name.starts_with('.') || symbol.name.contains('$') {
+                    continue;
+                }
+                let name = match prefix.contains("::") {
+                    true => symbol.demangled.as_str(),
+                    false => match symbol.demangled.rsplit("::").next() {
+                        Some(val) => val,
+                        None => continue,
+                    },
+                };
+                if name.starts_with(prefix) {
+                    names.push(name.to_owned());
+                }
+            }
+        }
+        names
+    }
+
+    /// The source files of all the modules, relative paths are resolved against `cwd`.
+    pub fn source_files(&self, cwd: &str) -> Vec<PathBuf> {
+        let files: BTreeSet<PathBuf> = self
+            .modules
+            .iter()
+            .flat_map(|module| module.source_files.iter())
+            .map(|path| normalize_path(&resolve_path(cwd, path)))
+            .collect();
+        files.into_iter().collect()
+    }
+
+    /// Returns true if any module has line information for the source file.
+    pub fn has_source_file(&self, path: &str) -> Result<bool> {
+        for module in &self.modules {
+            let dwarf = &module.dwarf;
+            let mut units = dwarf.units();
+            while let Some(header) = units.next()? {
+                let unit = dwarf.unit(header)?;
+                let program = match &unit.line_program {
+                    Some(val) => val,
+                    None => continue,
+                };
+                for file in program.header().file_names() {
+                    let name = dwarf.attr_string(&unit, file.path_name())?;
+                    if Path::new(path).ends_with(name.to_string_lossy()?.as_ref()) {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// The source of the file embedded in the line table (DWARF 5 `DW_LNCT_LLVM_source`), if
+    /// any module has it.
+    pub fn embedded_source(&self, path: &str) -> Result<Option<String>> {
+        for module in &self.modules {
+            let dwarf = &module.dwarf;
+            let mut units = dwarf.units();
+            while let Some(header) = units.next()? {
+                let unit = dwarf.unit(header)?;
+                let program = match &unit.line_program {
+                    Some(val) => val,
+                    None => continue,
+                };
+                for file in program.header().file_names() {
+                    let source = match file.source() {
+                        Some(val) => val,
+                        None => continue,
+                    };
+                    let name = dwarf.attr_string(&unit, file.path_name())?;
+                    if Path::new(path).ends_with(name.to_string_lossy()?.as_ref()) {
+                        let source = dwarf.attr_string(&unit, source)?;
+                        return Ok(Some(source.to_string_lossy()?.to_string()));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Resolves a source location using the first module with the file that has code for it.
+    pub fn find_breakpoint_location(
+        &self,
+        cwd: &str,
+        path: &str,
+        line: NonZeroU64,
+        column: Option<NonZeroU64>,
+    ) -> Result<Option<u32>> {
+        if self.is_empty() {
+            return Err(anyhow!(NO_BINARY_MESSAGE));
+        }
+
+        for module in self.modules_for_file(path) {
+            if let Some(address) = find_assembly_line(&module.dwarf, path, line.get())? {
+                return Ok(Some(module.to_target_address(address)));
+            }
+            if let Some(address) = find_breakpoint_location(&module.dwarf, cwd, path, line, column)? {
+                let address = CodeAddress::new(address as u32).address();
+                return Ok(Some(module.to_target_address(address)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns true if the address is in code assembled from an assembly source.
+    pub fn is_assembly(&self, address: u32) -> Result<bool> {
+        let module = self.module_for_address(address)?;
+        let dwarf = &module.dwarf;
+        let address = module.to_link_address(address) as u64;
+
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let mut ranges = dwarf.unit_ranges(&unit)?;
+            while let Some(range) = ranges.next()? {
+                if range.begin <= address && address < range.end {
+                    return is_assembly_unit(&unit);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// The ELF symbol of the function at the address if it is in an assembly unit, those have
+    /// no subprogram entries to name the frames in them by.
+    pub fn assembly_function_name(&self, address: u32) -> Option<String> {
+        if !self.is_assembly(address).unwrap_or(false) {
+            return None;
+        }
+        self.find_function(address).map(|symbol| symbol.demangled)
+    }
+
+    /// The lines and columns in the range where a breakpoint can go: the statement boundaries in
+    /// the line table with code in the program. Code removed by the linker is at address 0.
+    pub fn breakpoint_locations(
+        &self,
+        path: &str,
+        lines: RangeInclusive<u64>,
+    ) -> Result<Vec<(u64, Option<u64>)>> {
+        if self.is_empty() {
+            return Err(anyhow!(NO_BINARY_MESSAGE));
+        }
+
+        let mut locations = vec![];
+        for module in self.modules_for_file(path) {
+            let dwarf = &module.dwarf;
+            let mut units = dwarf.units();
+            while let Some(header) = units.next()? {
+                let unit = dwarf.unit(header)?;
+                let program = match unit.line_program.clone() {
+                    Some(val) => val,
+                    None => continue,
+                };
+                // Every line of assembly is a statement, whatever the assembler marked
+                let assembly = is_assembly_unit(&unit)?;
+                let mut matching_files = HashMap::new();
+                let mut rows = program.rows();
+                while let Some((header, row)) = rows.next_row()? {
+                    if !(row.is_stmt() || assembly) || row.end_sequence() || row.address() == 0 {
+                        continue;
+                    }
+                    let line = match row.line() {
+                        Some(val) if lines.contains(&val.get()) => val.get(),
+                        _ => continue,
+                    };
+                    let matches = match matching_files.get(&row.file_index()) {
+                        Some(val) => *val,
+                        None => {
+                            let matches = match header.file(row.file_index()) {
+                                Some(file) => {
+                                    let name = dwarf.attr_string(&unit, file.path_name())?;
+                                    Path::new(path).ends_with(name.to_string_lossy()?.as_ref())
+                                }
+                                None => false,
+                            };
+                            matching_files.insert(row.file_index(), matches);
+                            matches
+                        }
+                    };
+                    if matches {
+                        let column = match row.column() {
+                            gimli::ColumnType::LeftEdge => None,
+                            gimli::ColumnType::Column(column) => Some(column.get()),
+                        };
+                        locations.push((line, column));
+                    }
+                }
+            }
+        }
+        locations.sort_unstable();
+        locations.dedup();
+        Ok(locations)
+    }
+
+    /// The addresses where the line starts, one for each block of code of the line, e.g. every
+    /// inlined copy. Rows continuing the line of the row before them are not a start.
+    pub fn line_addresses(&self, path: &str, line: u64) -> Result<Vec<u32>> {
+        if self.is_empty() {
+            return Err(anyhow!(NO_BINARY_MESSAGE));
+        }
+
+        let mut addresses = vec![];
+        for module in self.modules_for_file(path) {
+            let dwarf = &module.dwarf;
+            let mut units = dwarf.units();
+            while let Some(header) = units.next()? {
+                let unit = dwarf.unit(header)?;
+                let program = match unit.line_program.clone() {
+                    Some(val) => val,
+                    None => continue,
+                };
+                let assembly = is_assembly_unit(&unit)?;
+                let mut matching_files = HashMap::new();
+                let mut previous = None;
+                let mut rows = program.rows();
+                while let Some((header, row)) = rows.next_row()? {
+                    if row.end_sequence() {
+                        previous = None;
+                        continue;
+                    }
+                    let location = (row.file_index(), row.line().map(|line| line.get()));
+                    let continues = previous == Some(location);
+                    previous = Some(location);
+                    if continues || !(row.is_stmt() || assembly) || row.address() == 0 {
+                        continue;
+                    }
+                    if location.1 != Some(line) {
+                        continue;
+                    }
+                    let matches = match matching_files.get(&row.file_index()) {
+                        Some(val) => *val,
+                        None => {
+                            let matches = match header.file(row.file_index()) {
+                                Some(file) => {
+                                    let name = dwarf.attr_string(&unit, file.path_name())?;
+                                    Path::new(path).ends_with(name.to_string_lossy()?.as_ref())
+                                }
+                                None => false,
+                            };
+                            matching_files.insert(row.file_index(), matches);
+                            matches
+                        }
+                    };
+                    if matches {
+                        let address = CodeAddress::new(row.address() as u32).address();
+                        addresses.push(module.to_target_address(address));
+                    }
+                }
+            }
+        }
+        addresses.sort_unstable();
+        addresses.dedup();
+        Ok(addresses)
+    }
+
+    /// The first address after the prologue of the function and its source location, from the
+    /// line table: the row marked as the end of the prologue, else the first row on another
+    /// line than the entry. The entry itself if the function has a single line.
+    pub fn skip_prologue(&self, address: u32, size: u32) -> Result<(u32, Option<(String, u64)>)> {
+        let module = self.module_for_address(address)?;
+        let dwarf = &module.dwarf;
+        let start = module.to_link_address(address) as u64;
+        let end = start + size.max(1) as u64;
+
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let program = match unit.line_program.clone() {
+                Some(val) => val,
+                None => continue,
+            };
+            let mut rows = program.rows();
+            let mut entry = None;
+            while let Some((header, row)) = rows.next_row()? {
+                if row.end_sequence() || row.address() < start || row.address() >= end {
+                    continue;
+                }
+                let line = match row.line() {
+                    Some(val) => val.get(),
+                    None => continue,
+                };
+                let location = (row.address(), line, row.file_index());
+                let body = match entry {
+                    None => {
+                        entry = Some(location);
+                        row.prologue_end()
+                    }
+                    Some((_, entry_line, _)) => row.prologue_end() || line != entry_line,
+                };
+                if body {
+                    entry = Some(location);
+                    break;
+                }
+            }
+
+            if let Some((body, line, file_index)) = entry {
+                let header = rows.header();
+                let path = match header.file(file_index) {
+                    Some(file) => Some(file_path(dwarf, &unit, header, file)?),
+                    None => None,
+                };
+                let address = module.to_target_address(body as u32);
+                return Ok((address, path.map(|path| (path, line))));
+            }
+        }
+        Ok((address, None))
+    }
+
+    /// Warns if the flashed program does not match any of the symbol files.
+    pub fn check_program(&self, program: &Path) -> Result<()> {
+        let program_text = match TextSection::read(program)? {
+            Some(val) => val,
+            None => return Ok(()),
+        };
+
+        let mut matched = false;
+        for module in &self.modules {
+            if module.path == program {
+                return Ok(());
+            }
+            if let Some(text) = &module.text {
+                if text.hash == program_text.hash {
+                    matched = true;
+                }
+            }
+        }
+
+        if !matched {
+            warn!(
+                "The .text section of {:?} does not match any of the symbol files, the symbols are probably from a different build",
+                program
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Identifies a build of a binary, the GNU build id if it has one, otherwise a hash of `.text`.
+pub fn build_id(path: &Path) -> Result<Option<String>> {
+    let file = fs::File::open(&path)?;
+    let mmap = unsafe { memmap::Mmap::map(&file)? };
+    let object = object::File::parse(&*mmap)?;
+
+    if let Some(id) = object.build_id()? {
+        return Ok(Some(id.iter().map(|b| format!("{:02x}", b)).collect()));
+    }
+    drop(object);
+
+    Ok(TextSection::read(path)?.map(|text| format!("text-{:016x}", text.hash)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gimli::{write, Section};
+    use std::rc::Rc;
+
+    // A module without DWARF sections, the text range and files are what the lookups use.
+    fn module(
+        path: &str,
+        base_address: u32,
+        text: Range<u32>,
+        source_files: &[&str],
+    ) -> DebugInformation<DwarfReader> {
+        let loader = |_| {
+            Ok::<_, gimli::Error>(EndianRcSlice::new(Rc::<[u8]>::from(&[][..]), LittleEndian))
+        };
+        DebugInformation {
+            path: PathBuf::from(path),
+            base_address,
+            dwarf: Dwarf::load(loader).unwrap(),
+            debug_frame: DebugFrame::load(loader).unwrap(),
+            text: Some(TextSection {
+                range: text,
+                hash: 0,
+            }),
+            elf_symbols: vec![],
+            hard_float: false,
+            endianness: Endianness::Little,
+            image: vec![],
+            breakpoints: vec![],
+            has_debug_info: true,
+            build_id: None,
+            source_files: source_files.iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    // A 32 KiB bootloader and an application linked after it, both built with cortex-m-rt.
+    fn bootloader_and_application() -> Symbols {
+        let registry = "/home/user/.cargo/registry/src/cortex-m-rt-0.7.1/src/lib.rs";
+        Symbols {
+            modules: vec![
+                module(
+                    "boot/target/boot",
+                    0,
+                    0x0800_0400..0x0800_3000,
+                    &["/work/boot/src/main.rs", registry],
+                ),
+                module(
+                    "app/target/app",
+                    0,
+                    0x0800_8400..0x0802_0000,
+                    &["/work/app/src/main.rs", "src/sensor.rs", registry],
+                ),
+            ],
+        }
+    }
+
+    // The DWARF of compile units with only a language and an address range.
+    fn units(units: &[(gimli::DwLang, Range<u32>)]) -> Dwarf<DwarfReader> {
+        let encoding = gimli::Encoding {
+            format: gimli::Format::Dwarf32,
+            version: 4,
+            address_size: 4,
+        };
+        let mut dwarf = write::Dwarf::new();
+        for (language, range) in units {
+            let id = dwarf
+                .units
+                .add(write::Unit::new(encoding, write::LineProgram::none()));
+            let unit = dwarf.units.get_mut(id);
+            let root = unit.root();
+            let entry = unit.get_mut(root);
+            entry.set(
+                gimli::DW_AT_language,
+                write::AttributeValue::Language(*language),
+            );
+            entry.set(
+                gimli::DW_AT_low_pc,
+                write::AttributeValue::Address(write::Address::Constant(range.start as u64)),
+            );
+            entry.set(
+                gimli::DW_AT_high_pc,
+                write::AttributeValue::Udata((range.end - range.start) as u64),
+            );
+        }
+        let mut sections = write::Sections::new(write::EndianVec::new(LittleEndian));
+        dwarf.write(&mut sections).unwrap();
+
+        let mut data = HashMap::new();
+        sections
+            .for_each(|id, section| {
+                data.insert(id, section.slice().to_vec());
+                Ok::<_, gimli::Error>(())
+            })
+            .unwrap();
+        Dwarf::load(|id| {
+            let section = data.get(&id).map(Vec::as_slice).unwrap_or(&[]);
+            Ok::<_, gimli::Error>(EndianRcSlice::new(Rc::from(section), LittleEndian))
+        })
+        .unwrap()
+    }
+
+    fn function(name: &str, demangled: &str, address: u32, size: u32) -> ElfSymbol {
+        ElfSymbol {
+            name: name.to_owned(),
+            demangled: demangled.to_owned(),
+            address,
+            size,
+            is_function: true,
+        }
+    }
+
+    fn paths(modules: &[&DebugInformation<DwarfReader>]) -> Vec<String> {
+        modules
+            .iter()
+            .map(|module| module.path.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn addresses_resolve_to_their_module() {
+        let symbols = bootloader_and_application();
+        // The jump to the application and its reset handler
+        assert_eq!(symbols.module_index(0x0800_1234), Some(0));
+        assert_eq!(symbols.module_index(0x0800_8400), Some(1));
+        assert_eq!(
+            symbols.module_for_address(0x0800_9000).unwrap().path,
+            PathBuf::from("app/target/app")
+        );
+        // RAM code is in no module, the first one is used for it
+        assert_eq!(symbols.module_index(0x2000_0000), None);
+        assert_eq!(
+            symbols.module_for_address(0x2000_0000).unwrap().path,
+            PathBuf::from("boot/target/boot")
+        );
+    }
+
+    #[test]
+    fn addresses_resolve_with_the_base_address() {
+        // The same application loaded 0x10000 higher than it is linked
+        let symbols = Symbols {
+            modules: vec![module("app", 0x1_0000, 0x0800_8400..0x0802_0000, &[])],
+        };
+        assert_eq!(symbols.module_index(0x0800_8400), None);
+        assert_eq!(symbols.module_index(0x0801_8400), Some(0));
+        let module = &symbols.modules[0];
+        assert_eq!(module.to_link_address(0x0801_8400), 0x0800_8400);
+        assert_eq!(module.to_target_address(0x0800_8400), 0x0801_8400);
+    }
+
+    #[test]
+    fn files_resolve_to_the_module_that_has_them() {
+        let symbols = bootloader_and_application();
+        assert_eq!(
+            paths(&symbols.modules_for_file("/work/app/src/main.rs")),
+            ["app/target/app"]
+        );
+        assert_eq!(
+            paths(&symbols.modules_for_file("/work/boot/src/./main.rs")),
+            ["boot/target/boot"]
+        );
+        // Relative names in the line table match the end of the path
+        assert_eq!(
+            paths(&symbols.modules_for_file("/work/app/src/sensor.rs")),
+            ["app/target/app"]
+        );
+    }
+
+    #[test]
+    fn shared_and_unknown_files_resolve_to_every_module() {
+        let symbols = bootloader_and_application();
+        let shared = "/home/user/.cargo/registry/src/cortex-m-rt-0.7.1/src/lib.rs";
+        assert_eq!(symbols.modules_for_file(shared).len(), 2);
+        assert_eq!(symbols.modules_for_file("/elsewhere/lib.rs").len(), 2);
+    }
+
+    #[test]
+    fn frames_in_assembly_are_named_by_their_symbol() {
+        // A HardFault trampoline from cortex-m-rt's asm.S, called from the vector table
+        let mut app = module("app", 0, 0x0800_0000..0x0801_0000, &[]);
+        app.dwarf = units(&[
+            (gimli::DW_LANG_Mips_Assembler, 0x0800_0100..0x0800_0110),
+            (gimli::DW_LANG_Rust, 0x0800_0200..0x0800_0300),
+        ]);
+        app.elf_symbols = vec![
+            function("HardFaultTrampoline", "HardFaultTrampoline", 0x0800_0100, 8),
+            function(
+                "_ZN3app4main17h0123456789abcdefE",
+                "app::main",
+                0x0800_0200,
+                0x40,
+            ),
+        ];
+        let symbols = Symbols { modules: vec![app] };
+
+        assert_eq!(
+            symbols.assembly_function_name(0x0800_0104).as_deref(),
+            Some("HardFaultTrampoline")
+        );
+        // The Rust frames keep the name of their subprogram
+        assert_eq!(symbols.assembly_function_name(0x0800_0210), None);
+        // Assembly without a function symbol and code outside any unit
+        assert_eq!(symbols.assembly_function_name(0x0800_010c), None);
+        assert_eq!(symbols.assembly_function_name(0x0800_0400), None);
+    }
+}
@@ -0,0 +1,63 @@
+use super::core_dump::CoreDump;
+use anyhow::{anyhow, Result};
+use probe_rs::config::MemoryRegion;
+use probe_rs::{Core, Session};
+use rust_debug::call_stack::MemoryAccess;
+
+pub const CORE_DUMP_MESSAGE: &str = "Not supported on a core dump";
+
+/// Where the debugger reads the target state from, a live probe session or a core dump.
+pub enum Target {
+    Probe(Session),
+    CoreDump(CoreDump),
+}
+
+impl Target {
+    /// Returns the core of a live target, run control is not possible on a core dump.
+    pub fn core(&mut self, n: usize) -> Result<Core> {
+        match self {
+            Target::Probe(session) => Ok(session.core(n)?),
+            Target::CoreDump(_) => Err(anyhow!(CORE_DUMP_MESSAGE)),
+        }
+    }
+
+    pub fn session(&mut self) -> Result<&mut Session> {
+        match self {
+            Target::Probe(session) => Ok(session),
+            Target::CoreDump(_) => Err(anyhow!(CORE_DUMP_MESSAGE)),
+        }
+    }
+
+    pub fn core_dump(&self) -> Option<&CoreDump> {
+        match self {
+            Target::Probe(_) => None,
+            Target::CoreDump(dump) => Some(dump),
+        }
+    }
+
+    pub fn chip_name(&self) -> String {
+        match self {
+            Target::Probe(session) => session.target().name.clone(),
+            Target::CoreDump(dump) => dump.header.chip.clone(),
+        }
+    }
+
+    /// The memory map of the chip, unknown for core dumps.
+    pub fn memory_map(&self) -> &[MemoryRegion] {
+        match self {
+            Target::Probe(session) => &session.target().memory_map,
+            Target::CoreDump(_) => &[],
+        }
+    }
+}
+
+/// Serves the memory captured in a core dump to the stack unwinding and variable evaluation.
+pub struct CoreDumpMemory<'a> {
+    pub dump: &'a CoreDump,
+}
+
+impl MemoryAccess for CoreDumpMemory<'_> {
+    fn get_address(&mut self, address: &u32, num_bytes: usize) -> Option<Vec<u8>> {
+        self.dump.read_memory(*address, num_bytes)
+    }
+}
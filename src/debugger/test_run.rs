@@ -0,0 +1,232 @@
+//! Results of on-target test runs
+//!
+//! With `testRun` set, the output of the firmware is parsed for the progress records of
+//! `defmt-test`, `(1/3) running `name`...` before each test and `all tests passed!` at the end.
+//! A test passed when the next one starts or the run ends, and failed when the firmware panics
+//! while it runs, with the panic message and location. A semihosting exit ends the run as well,
+//! an exit with a failure fails the running test. The output is taken line by line from the
+//! semihosting console calls, there is no RTT channel to decode `defmt` from.
+//!
+//! The parsing is not tied to a frontend, the events go to the CLI and to the DAP client alike.
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TestLocation {
+    pub path: String,
+    pub line: u32,
+    pub column: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum TestEvent {
+    Started {
+        index: usize,
+        total: usize,
+        name: String,
+    },
+    Passed {
+        name: String,
+    },
+    Failed {
+        // None if the firmware failed outside of a test.
+        name: Option<String>,
+        message: String,
+        location: Option<TestLocation>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Finished {
+        passed: usize,
+        failed: usize,
+        exit_code: i32,
+    },
+}
+
+impl std::fmt::Display for TestEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TestEvent::Started { index, total, name } => {
+                write!(f, "({}/{}) test `{}` ...", index, total, name)
+            }
+            TestEvent::Passed { name } => write!(f, "test `{}` passed", name),
+            TestEvent::Failed {
+                name,
+                message,
+                location,
+            } => {
+                match name {
+                    Some(name) => write!(f, "test `{}` FAILED: {}", name, message)?,
+                    None => write!(f, "FAILED: {}", message)?,
+                };
+                match location {
+                    Some(location) => write!(f, " at {}:{}", location.path, location.line),
+                    None => Ok(()),
+                }
+            }
+            TestEvent::Finished { passed, failed, .. } => write!(
+                f,
+                "test result: {}. {} passed, {} failed",
+                if *failed == 0 { "ok" } else { "FAILED" },
+                passed,
+                failed
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TestRun {
+    // The output after the last complete line.
+    partial: String,
+    current: Option<String>,
+    passed: usize,
+    failed: usize,
+    // The location of a panic whose message is on the next line.
+    panic_location: Option<TestLocation>,
+    finished: bool,
+}
+
+impl TestRun {
+    /// Parses the output of the firmware, returns the complete lines to show and the events. The
+    /// last line is kept until it is complete.
+    pub fn feed(&mut self, output: &str) -> (Vec<String>, Vec<TestEvent>) {
+        self.partial.push_str(output);
+        let mut lines = vec![];
+        let mut events = vec![];
+        while let Some(end) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=end).collect();
+            let line = line.trim_end().to_owned();
+            events.extend(self.line(&line));
+            lines.push(line);
+        }
+        (lines, events)
+    }
+
+    /// Ends the run at a semihosting exit of the firmware, with the incomplete last line.
+    pub fn exit(&mut self, success: bool) -> (Vec<String>, Vec<TestEvent>) {
+        let (mut lines, mut events) = self.feed("\n");
+        lines.retain(|line| !line.is_empty());
+        if self.finished {
+            return (lines, events);
+        }
+        if let Some(location) = self.panic_location.take() {
+            events.push(self.fail("panicked".to_owned(), Some(location)));
+        } else if success {
+            events.extend(self.pass_current());
+        } else {
+            events.push(self.fail("the firmware exited with a failure".to_owned(), None));
+        }
+        events.push(self.finish());
+        (lines, events)
+    }
+
+    fn line(&mut self, line: &str) -> Vec<TestEvent> {
+        if self.finished {
+            return vec![];
+        }
+        if let Some(location) = self.panic_location.take() {
+            return vec![
+                self.fail(line.trim().to_owned(), Some(location)),
+                self.finish(),
+            ];
+        }
+
+        if let Some((index, total, name)) = parse_running(line) {
+            let mut events: Vec<TestEvent> = self.pass_current().into_iter().collect();
+            self.current = Some(name.clone());
+            events.push(TestEvent::Started { index, total, name });
+            return events;
+        }
+        if line.contains("all tests passed") {
+            let mut events: Vec<TestEvent> = self.pass_current().into_iter().collect();
+            events.push(self.finish());
+            return events;
+        }
+        match parse_panic(line) {
+            Some((Some(message), location)) => vec![self.fail(message, location), self.finish()],
+            Some((None, Some(location))) => {
+                self.panic_location = Some(location);
+                vec![]
+            }
+            Some((None, None)) => vec![self.fail(line.trim().to_owned(), None), self.finish()],
+            None => vec![],
+        }
+    }
+
+    fn pass_current(&mut self) -> Option<TestEvent> {
+        let name = self.current.take()?;
+        self.passed += 1;
+        Some(TestEvent::Passed { name })
+    }
+
+    fn fail(&mut self, message: String, location: Option<TestLocation>) -> TestEvent {
+        self.failed += 1;
+        TestEvent::Failed {
+            name: self.current.take(),
+            message,
+            location,
+        }
+    }
+
+    fn finish(&mut self) -> TestEvent {
+        self.finished = true;
+        TestEvent::Finished {
+            passed: self.passed,
+            failed: self.failed,
+            exit_code: match self.failed {
+                0 => 0,
+                _ => 1,
+            },
+        }
+    }
+}
+
+/// Parses `(1/3) running `name`...`, there can be a log prefix in front of it.
+fn parse_running(line: &str) -> Option<(usize, usize, String)> {
+    let start = line.find("running `")?;
+    let name_start = start + "running `".len();
+    let name_end = name_start + line[name_start..].find('`')?;
+    let counter = line[..start].trim_end().strip_suffix(')')?;
+    let counter = &counter[counter.rfind('(')? + 1..];
+    let (index, total) = counter.split_once('/')?;
+    Some((
+        index.trim().parse().ok()?,
+        total.trim().parse().ok()?,
+        line[name_start..name_end].to_owned(),
+    ))
+}
+
+/// Parses `panicked at 'message', src/lib.rs:10:5` and `panicked at src/lib.rs:10:5:`, the
+/// message of the latter is on the next line.
+fn parse_panic(line: &str) -> Option<(Option<String>, Option<TestLocation>)> {
+    let rest = &line[line.find("panicked at ")? + "panicked at ".len()..];
+    match rest.strip_prefix('\'') {
+        Some(quoted) => match quoted.rfind("', ") {
+            Some(end) => Some((
+                Some(quoted[..end].to_owned()),
+                parse_location(&quoted[end + 3..]),
+            )),
+            None => Some((Some(quoted.trim_end_matches('\'').to_owned()), None)),
+        },
+        None => Some((None, parse_location(rest.trim_end_matches(':')))),
+    }
+}
+
+/// Parses `src/lib.rs:10:5` or `src/lib.rs:10`.
+fn parse_location(location: &str) -> Option<TestLocation> {
+    let mut parts = location.trim().rsplitn(3, ':');
+    let last = parts.next()?.parse::<u32>().ok()?;
+    let second = parts.next()?;
+    match (second.parse::<u32>(), parts.next()) {
+        (Ok(line), Some(path)) => Some(TestLocation {
+            path: path.to_owned(),
+            line,
+            column: Some(last),
+        }),
+        _ => Some(TestLocation {
+            path: location.trim().rsplitn(2, ':').nth(1)?.to_owned(),
+            line: last,
+            column: None,
+        }),
+    }
+}
@@ -0,0 +1,137 @@
+//! Time and frequency values shown in units
+//!
+//! The time types of `fugit` and `embedded-time`, and the `Hertz(u32)` like newtypes of the HALs,
+//! are recognized by their type name and shown in units instead of as a struct, e.g.
+//! `483.92 ms @ 1 MHz tick` for a `fugit::Instant<u32, 1, 1000000>`. The tick rate of the `fugit`
+//! types is taken from the const generic parameters in the DWARF type name. Types with a tick
+//! rate that isn't in the name, like the `Instant<Clock>` of `embedded-time`, are shown as raw
+//! ticks with the type name. The raw field is kept as the child of the value.
+use super::Variable;
+
+/// The `embedded-time` and HAL durations, with their length in seconds.
+const DURATIONS: [(&str, f64); 6] = [
+    ("Hours", 3600.0),
+    ("Minutes", 60.0),
+    ("Seconds", 1.0),
+    ("Milliseconds", 1e-3),
+    ("Microseconds", 1e-6),
+    ("Nanoseconds", 1e-9),
+];
+
+/// The `embedded-time` and HAL rates, with their rate in Hz.
+const RATES: [(&str, f64); 6] = [
+    ("Hertz", 1.0),
+    ("Kilohertz", 1e3),
+    ("KiloHertz", 1e3),
+    ("Megahertz", 1e6),
+    ("MegaHertz", 1e6),
+    ("Gigahertz", 1e9),
+];
+
+/// Shows the recognized time and frequency values in units, the children are left as they are.
+pub fn render(variables: &mut [Variable]) {
+    for variable in variables {
+        if let Some(value) = in_units(variable) {
+            variable.value = value;
+        }
+        render(&mut variable.children);
+    }
+}
+
+fn in_units(variable: &Variable) -> Option<String> {
+    // The value of a struct is its type name, e.g. `Duration<u32, 1, 1000>`
+    let (name, parameters) = split_type_name(&variable.value)?;
+    match (name, parameters.as_slice()) {
+        ("Duration", [_, nom, denom]) | ("Instant", [_, nom, denom]) => {
+            let ticks = field(variable, "ticks")?;
+            Some(match tick_period(nom, denom) {
+                Some((period, rate)) => format!(
+                    "{} @ {} tick",
+                    format_duration(ticks as f64 * period),
+                    format_rate(rate)
+                ),
+                None => raw_ticks(ticks, &variable.value),
+            })
+        }
+        ("Rate", [_, nom, denom]) => {
+            let raw = field(variable, "raw")?;
+            Some(match tick_period(nom, denom) {
+                Some((period, _)) => format_rate(raw as f64 * period),
+                None => raw_ticks(raw, &variable.value),
+            })
+        }
+        ("Instant", _) => Some(raw_ticks(field(variable, "ticks")?, &variable.value)),
+        (name, _) => {
+            let value = field(variable, "__0")? as f64;
+            if let Some((_, seconds)) = DURATIONS.iter().find(|(n, _)| *n == name) {
+                return Some(format_duration(value * seconds));
+            }
+            let (_, hertz) = RATES.iter().find(|(n, _)| *n == name)?;
+            Some(format_rate(value * hertz))
+        }
+    }
+}
+
+/// The name without the path and the generic parameters of a type name.
+fn split_type_name(type_name: &str) -> Option<(&str, Vec<&str>)> {
+    let (path, parameters) = match type_name.find('<') {
+        Some(index) => {
+            let parameters = type_name[index + 1..].strip_suffix('>')?;
+            (
+                &type_name[..index],
+                parameters.split(',').map(str::trim).collect(),
+            )
+        }
+        None => (type_name, vec![]),
+    };
+    let name = path.rsplit("::").next()?.trim();
+    match name.is_empty() {
+        true => None,
+        false => Some((name, parameters)),
+    }
+}
+
+/// The integer value of the field, None if it is not an integer, e.g. optimized out.
+fn field(variable: &Variable, name: &str) -> Option<u64> {
+    variable.find_child(name)?.value.trim().parse().ok()
+}
+
+/// The tick period in seconds and the tick rate in Hz of the `NOM / DENOM` fraction.
+fn tick_period(nom: &str, denom: &str) -> Option<(f64, f64)> {
+    let nom = nom.parse::<u64>().ok().filter(|n| *n > 0)? as f64;
+    let denom = denom.parse::<u64>().ok().filter(|d| *d > 0)? as f64;
+    Some((nom / denom, denom / nom))
+}
+
+fn raw_ticks(ticks: u64, type_name: &str) -> String {
+    format!("{} ticks ({})", ticks, type_name)
+}
+
+fn format_duration(seconds: f64) -> String {
+    let (value, unit) = match seconds.abs() {
+        s if s >= 1.0 || s == 0.0 => (seconds, "s"),
+        s if s >= 1e-3 => (seconds * 1e3, "ms"),
+        s if s >= 1e-6 => (seconds * 1e6, "µs"),
+        _ => (seconds * 1e9, "ns"),
+    };
+    format!("{} {}", format_number(value), unit)
+}
+
+fn format_rate(hertz: f64) -> String {
+    let (value, unit) = match hertz.abs() {
+        h if h >= 1e9 => (hertz / 1e9, "GHz"),
+        h if h >= 1e6 => (hertz / 1e6, "MHz"),
+        h if h >= 1e3 => (hertz / 1e3, "kHz"),
+        _ => (hertz, "Hz"),
+    };
+    format!("{} {}", format_number(value), unit)
+}
+
+/// At most two decimals, without trailing zeros.
+fn format_number(value: f64) -> String {
+    let formatted = format!("{:.2}", value);
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_owned()
+}
@@ -0,0 +1,46 @@
+//! Running the core for a fixed time
+//!
+//! `continue 50ms` resumes the core and halts it again when the time is up, unless something
+//! else stopped it first. The timer is on the host and checked with the status polls, so the core
+//! runs for at least the requested time plus the latency of one poll and the halt.
+use probe_rs::HaltReason;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct TimedRun {
+    started: Instant,
+    duration: Duration,
+    // The timer halted the core, it may have stopped on its own in the meantime.
+    fired: bool,
+}
+
+impl TimedRun {
+    pub fn new(duration: Duration) -> TimedRun {
+        TimedRun {
+            started: Instant::now(),
+            duration,
+            fired: false,
+        }
+    }
+
+    pub fn expired(&self) -> bool {
+        !self.fired && self.started.elapsed() >= self.duration
+    }
+
+    pub fn fire(&mut self) {
+        self.fired = true;
+    }
+
+    /// Why the timed run ended, for the halted event.
+    pub fn description(&self, reason: &HaltReason) -> String {
+        let elapsed = self.started.elapsed().as_millis();
+        match (self.fired, reason) {
+            (true, &HaltReason::Request) => format!("Halted by the timer after {} ms", elapsed),
+            _ => format!(
+                "Stopped after {} ms of a {} ms run, before the timer",
+                elapsed,
+                self.duration.as_millis()
+            ),
+        }
+    }
+}
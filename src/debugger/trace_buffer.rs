@@ -0,0 +1,294 @@
+//! Hardware branch trace from the Micro Trace Buffer
+//!
+//! The MTB of a Cortex-M0+ writes a packet for every taken branch, exception entry and exception
+//! return into a circular buffer in SRAM. The buffer is read out after a stop and decoded into the
+//! branch history leading up to it. The buffer is ordinary SRAM, it must be a region the program
+//! doesn't use, aligned to its size.
+//!
+//! The trace components are found by scanning the CoreSight ROM tables. The AP base address
+//! isn't reachable through the core, so the scan starts at the ROM tables where the Cortex-M
+//! core and the vendors put them. An ETB is recognized, but decoding the ETM protocol it
+//! captures is not supported.
+use anyhow::{anyhow, Result};
+use probe_rs::MemoryInterface;
+use serde::Serialize;
+use std::fmt;
+
+use super::symbols::Symbols;
+
+/// The Cortex-M core ROM table, the NXP Kinetis and the Microchip SAM D top level ROM tables.
+const ROM_TABLES: [u32; 3] = [0xe00f_f000, 0xf000_2000, 0x4100_3000];
+const MAX_ROM_TABLE_DEPTH: usize = 4;
+const MAX_ROM_TABLE_ENTRIES: u32 = 960;
+
+const CIDR_PREAMBLE: u32 = 0xb105_000d;
+const CLASS_ROM_TABLE: u32 = 0x1;
+/// The JEP106 continuation and identity code of ARM.
+const ARM_DESIGNER: u32 = 0x43b;
+const PART_MTB: u32 = 0x932;
+const PART_ETB: u32 = 0x907;
+
+const MTB_POSITION: u32 = 0x000;
+const MTB_MASTER: u32 = 0x004;
+const MTB_FLOW: u32 = 0x008;
+const MTB_BASE: u32 = 0x00c;
+const MTB_POSITION_WRAP: u32 = 1 << 2;
+const MTB_MASTER_EN: u32 = 1 << 31;
+const MTB_MIN_SIZE: u32 = 16;
+
+/// The number of branches added to a fault report.
+pub const FAULT_BRANCHES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceHardware {
+    Mtb(u32),
+    Etb(u32),
+}
+
+/// Finds an MTB in the ROM tables, or else an ETB.
+pub fn detect_trace_hardware(core: &mut probe_rs::Core) -> Result<TraceHardware> {
+    let mut found = vec![];
+    for table in ROM_TABLES {
+        scan_rom_table(core, table, MAX_ROM_TABLE_DEPTH, &mut found);
+    }
+    match found.iter().find(|hw| matches!(hw, TraceHardware::Mtb(_))) {
+        Some(mtb) => Ok(*mtb),
+        None => match found.first() {
+            Some(etb) => Ok(*etb),
+            None => Err(anyhow!("No trace hardware detected")),
+        },
+    }
+}
+
+/// Collects the trace components of the ROM table, unreadable entries are skipped.
+fn scan_rom_table(
+    core: &mut probe_rs::Core,
+    table: u32,
+    depth: usize,
+    found: &mut Vec<TraceHardware>,
+) {
+    match component_id(core, table) {
+        Ok(Some((CLASS_ROM_TABLE, _, _))) => (),
+        _ => return,
+    }
+    for index in 0..MAX_ROM_TABLE_ENTRIES {
+        let entry = match core.read_word_32(table + index * 4) {
+            Ok(0) | Err(_) => return,
+            Ok(val) => val,
+        };
+        // Bit 0 is set if the component is present, the offset is signed
+        if entry & 1 == 0 {
+            continue;
+        }
+        let base = table.wrapping_add(entry & 0xffff_f000);
+        match component_id(core, base) {
+            Ok(Some((CLASS_ROM_TABLE, _, _))) if depth > 0 && base != table => {
+                scan_rom_table(core, base, depth - 1, found)
+            }
+            Ok(Some((_, ARM_DESIGNER, PART_MTB))) => found.push(TraceHardware::Mtb(base)),
+            Ok(Some((_, ARM_DESIGNER, PART_ETB))) => found.push(TraceHardware::Etb(base)),
+            _ => (),
+        }
+    }
+}
+
+/// Reads the class, the designer and the part number of a CoreSight component.
+fn component_id(core: &mut probe_rs::Core, base: u32) -> Result<Option<(u32, u32, u32)>> {
+    let mut cidr = [0u32; 4];
+    core.read_32(base + 0xff0, &mut cidr)?;
+    let cidr = bytes_to_word(&cidr);
+    if cidr & !0xf000 != CIDR_PREAMBLE {
+        return Ok(None);
+    }
+
+    let mut pidr = [0u32; 4];
+    core.read_32(base + 0xfe0, &mut pidr)?;
+    let pidr4 = core.read_word_32(base + 0xfd0)?;
+    let pidr = bytes_to_word(&pidr);
+    let part = pidr & 0xfff;
+    let designer = (pidr >> 12) & 0x7f | (pidr4 & 0xf) << 8;
+
+    Ok(Some(((cidr >> 12) & 0xf, designer, part)))
+}
+
+/// The ID registers hold one byte each.
+fn bytes_to_word(registers: &[u32; 4]) -> u32 {
+    registers
+        .iter()
+        .enumerate()
+        .fold(0, |word, (i, register)| word | (register & 0xff) << (i * 8))
+}
+
+/// A configured MTB.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceBuffer {
+    pub mtb_address: u32,
+    pub buffer_address: u32,
+    pub size: u32,
+}
+
+impl TraceBuffer {
+    /// Programs the MTB to trace into the buffer and starts it.
+    pub fn configure(
+        core: &mut probe_rs::Core,
+        buffer_address: u32,
+        size: u32,
+    ) -> Result<TraceBuffer> {
+        let mtb_address = match detect_trace_hardware(core)? {
+            TraceHardware::Mtb(address) => address,
+            TraceHardware::Etb(address) => {
+                return Err(anyhow!(
+                    "Found an ETB at {:#010x}, decoding its ETM trace is not supported",
+                    address
+                ))
+            }
+        };
+        if !size.is_power_of_two() || size < MTB_MIN_SIZE {
+            return Err(anyhow!(
+                "The trace buffer size must be a power of two of at least {} bytes",
+                MTB_MIN_SIZE
+            ));
+        }
+        if buffer_address % size != 0 {
+            return Err(anyhow!(
+                "The trace buffer must be aligned to its size of {} bytes",
+                size
+            ));
+        }
+        let sram_base = core.read_word_32(mtb_address + MTB_BASE)?;
+        let offset = match buffer_address.checked_sub(sram_base) {
+            Some(offset) => offset,
+            None => {
+                return Err(anyhow!(
+                    "The trace buffer must be in the SRAM of the MTB, from {:#010x}",
+                    sram_base
+                ))
+            }
+        };
+
+        // MASK keeps the pointer in a buffer of 2^(MASK + 4) bytes
+        let mask = size.trailing_zeros() - 4;
+        core.write_word_32(mtb_address + MTB_MASTER, 0)?;
+        core.write_word_32(mtb_address + MTB_FLOW, 0)?;
+        core.write_word_32(mtb_address + MTB_POSITION, offset)?;
+        core.write_word_32(mtb_address + MTB_MASTER, MTB_MASTER_EN | mask)?;
+
+        Ok(TraceBuffer {
+            mtb_address,
+            buffer_address,
+            size,
+        })
+    }
+
+    /// Stops the tracing.
+    pub fn disable(&self, core: &mut probe_rs::Core) -> Result<()> {
+        core.write_word_32(self.mtb_address + MTB_MASTER, 0)?;
+        Ok(())
+    }
+
+    /// Reads the branches in the buffer, the oldest first.
+    pub fn read(&self, core: &mut probe_rs::Core) -> Result<Vec<Branch>> {
+        let position = core.read_word_32(self.mtb_address + MTB_POSITION)?;
+        let sram_base = core.read_word_32(self.mtb_address + MTB_BASE)?;
+        let next = sram_base
+            .wrapping_add(position & !0x7)
+            .wrapping_sub(self.buffer_address);
+        let next = next % self.size;
+
+        let mut words = vec![0u32; (self.size / 4) as usize];
+        core.read_32(self.buffer_address, &mut words)?;
+
+        // The packets before the pointer were written last, the ones after it only if it wrapped
+        let split = (next / 4) as usize;
+        let mut packets = vec![];
+        if position & MTB_POSITION_WRAP != 0 {
+            packets.extend_from_slice(&words[split..]);
+        }
+        packets.extend_from_slice(&words[..split]);
+
+        Ok(packets
+            .chunks_exact(2)
+            .map(|packet| Branch {
+                source: packet[0] & !1,
+                destination: packet[1] & !1,
+                exception: packet[0] & 1 != 0,
+                trace_start: packet[1] & 1 != 0,
+            })
+            .collect())
+    }
+}
+
+impl fmt::Display for TraceBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "MTB at {:#010x} tracing into {} bytes at {:#010x}",
+            self.mtb_address, self.size, self.buffer_address
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Branch {
+    pub source: u32,
+    pub destination: u32,
+    /// An exception entry or return, the source is the interrupted instruction.
+    pub exception: bool,
+    /// The first packet after the trace was started.
+    pub trace_start: bool,
+}
+
+/// A branch with the functions of its addresses.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TracedBranch {
+    pub branch: Branch,
+    pub source_function: Option<String>,
+    pub destination_function: Option<String>,
+}
+
+/// Symbolizes the last `count` branches.
+pub fn symbolize_branches(
+    symbols: &Symbols,
+    branches: &[Branch],
+    count: usize,
+) -> Vec<TracedBranch> {
+    let function = |address: u32| {
+        symbols
+            .find_function(address)
+            .map(|symbol| format!("{}+{:#x}", symbol.demangled, address - symbol.address))
+    };
+    branches[branches.len().saturating_sub(count)..]
+        .iter()
+        .map(|branch| TracedBranch {
+            branch: branch.clone(),
+            source_function: function(branch.source),
+            destination_function: function(branch.destination),
+        })
+        .collect()
+}
+
+impl fmt::Display for TracedBranch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let location = |address: u32, function: &Option<String>| match function {
+            Some(function) => format!("{:#010x} {}", address, function),
+            None => format!("{:#010x}", address),
+        };
+        write!(
+            f,
+            "{} -> {}{}{}",
+            location(self.branch.source, &self.source_function),
+            location(self.branch.destination, &self.destination_function),
+            match self.branch.exception {
+                true => " (exception)",
+                false => "",
+            },
+            match self.branch.trace_start {
+                true => " (trace start)",
+                false => "",
+            }
+        )
+    }
+}
@@ -0,0 +1,293 @@
+//! Catching the functions firmware dies in
+//!
+//! Panics, `abort()` and failed asserts all end up in a few well known functions. Breakpoints on
+//! them stop the target where it died instead of in the loop it spins in afterwards. The catch
+//! breakpoints only use the hardware breakpoint units the user breakpoints leave free, and one
+//! unit is always kept for the temporary breakpoints of `finish` and `until`. A catch is disarmed
+//! after it is hit, so continuing doesn't stop in it again, and rearmed on reset.
+//!
+//! The pseudo function `udf` catches stray UDF instructions, and other faults, through the hard
+//! fault vector catch, which doesn't use a breakpoint unit.
+//!
+//! The exception filters of the DAP client replace the panic and `udf` defaults: the fault filters
+//! set the vector catch bits of their faults and the panic filter catches the panic functions.
+use anyhow::{anyhow, Result};
+use debugserver_types::Breakpoint;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use super::architecture;
+use super::stepping::active_exception;
+use super::symbols::Symbols;
+use super::vector_table::exception_name;
+
+/// The functions always caught, the configured ones are added to these.
+pub const DEFAULT_CATCH_FUNCTIONS: &[&str] = &[
+    "rust_begin_unwind",
+    "core::panicking::panic_fmt",
+    "abort",
+    UDF_CATCH,
+];
+
+pub const UDF_CATCH: &str = "udf";
+
+/// The defaults the exception filters replace.
+pub const FILTERED_CATCH_FUNCTIONS: &[&str] =
+    &["rust_begin_unwind", "core::panicking::panic_fmt", UDF_CATCH];
+
+const PANIC_CATCH_FUNCTIONS: &[&str] = &["rust_begin_unwind", "core::panicking::panic"];
+
+const DEMCR: u32 = 0xe000_edfc;
+const DEMCR_VC_HARDERR: u32 = 1 << 10;
+const DEMCR_VC_BUSERR: u32 = 1 << 8;
+// Invalid state, checking and no coprocessor usage faults
+const DEMCR_VC_USAGEERR: u32 = 0b111 << 5;
+const DEMCR_VC_MMERR: u32 = 1 << 4;
+const DEMCR_VC_FAULTS: u32 =
+    DEMCR_VC_HARDERR | DEMCR_VC_BUSERR | DEMCR_VC_USAGEERR | DEMCR_VC_MMERR;
+
+// The exception numbers of HardFault to UsageFault
+const FAULT_EXCEPTIONS: std::ops::RangeInclusive<u32> = 3..=6;
+
+const CFSR: u32 = 0xe000_ed28;
+const CFSR_MMARVALID: u32 = 1 << 7;
+const CFSR_BFARVALID: u32 = 1 << 15;
+const MMFAR: u32 = 0xe000_ed34;
+const BFAR: u32 = 0xe000_ed38;
+
+// Kept free for the temporary breakpoints of finish and until.
+const RESERVED_UNITS: usize = 1;
+
+/// The exception breakpoint filters shown in the Breakpoints pane of the DAP client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExceptionFilter {
+    HardFault,
+    BusFault,
+    UsageFault,
+    MemManage,
+    Panic,
+}
+
+impl ExceptionFilter {
+    pub const ALL: [ExceptionFilter; 5] = [
+        ExceptionFilter::HardFault,
+        ExceptionFilter::BusFault,
+        ExceptionFilter::UsageFault,
+        ExceptionFilter::MemManage,
+        ExceptionFilter::Panic,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ExceptionFilter::HardFault => "hardfault",
+            ExceptionFilter::BusFault => "busfault",
+            ExceptionFilter::UsageFault => "usagefault",
+            ExceptionFilter::MemManage => "memmanage",
+            ExceptionFilter::Panic => "panic",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ExceptionFilter::HardFault => "Hard fault",
+            ExceptionFilter::BusFault => "Bus fault",
+            ExceptionFilter::UsageFault => "Usage fault",
+            ExceptionFilter::MemManage => "Memory management fault",
+            ExceptionFilter::Panic => "Panic",
+        }
+    }
+
+    /// On without exception filters, as the defaults catch them.
+    pub fn default_enabled(self) -> bool {
+        matches!(self, ExceptionFilter::HardFault | ExceptionFilter::Panic)
+    }
+
+    fn vector_catch(self) -> u32 {
+        match self {
+            ExceptionFilter::HardFault => DEMCR_VC_HARDERR,
+            ExceptionFilter::BusFault => DEMCR_VC_BUSERR,
+            ExceptionFilter::UsageFault => DEMCR_VC_USAGEERR,
+            ExceptionFilter::MemManage => DEMCR_VC_MMERR,
+            ExceptionFilter::Panic => 0,
+        }
+    }
+}
+
+impl FromStr for ExceptionFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match ExceptionFilter::ALL.iter().find(|filter| filter.name() == s) {
+            Some(filter) => Ok(*filter),
+            None => Err(anyhow!(
+                "Unknown exception filter {:?}, expected one of hardfault, busfault, usagefault, memmanage or panic",
+                s
+            )),
+        }
+    }
+}
+
+/// The functions to catch for the exception filters, in place of `FILTERED_CATCH_FUNCTIONS`.
+pub fn filter_catch_functions(filters: &[ExceptionFilter]) -> Vec<String> {
+    match filters.contains(&ExceptionFilter::Panic) {
+        true => PANIC_CATCH_FUNCTIONS.iter().map(|f| f.to_string()).collect(),
+        false => vec![],
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CatchPoint {
+    function: String,
+    address: u32,
+    armed: bool,
+    planted: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TrapCatches {
+    catches: Vec<CatchPoint>,
+    // The DEMCR vector catch bits.
+    vector_catch: u32,
+    // The number of catches that didn't fit last time, to only warn when it grows.
+    skipped: usize,
+}
+
+impl TrapCatches {
+    /// Resolves the functions in the binary, returns the catches and the functions not found.
+    pub fn resolve(symbols: &Symbols, functions: &[String]) -> (TrapCatches, Vec<String>) {
+        let mut catches = TrapCatches::default();
+        let mut missing = vec![];
+        for function in functions {
+            if function == UDF_CATCH {
+                catches.vector_catch |= DEMCR_VC_HARDERR;
+                continue;
+            }
+            let found: Vec<u32> = symbols
+                .find_symbols(function)
+                .iter()
+                .filter(|symbol| symbol.is_function)
+                .map(|symbol| symbol.address)
+                .collect();
+            if found.is_empty() {
+                missing.push(function.clone());
+            }
+            for address in found {
+                if catches.catches.iter().all(|c| c.address != address) {
+                    catches.catches.push(CatchPoint {
+                        function: function.clone(),
+                        address,
+                        armed: true,
+                        planted: false,
+                    });
+                }
+            }
+        }
+        (catches, missing)
+    }
+
+    /// Catches the faults of the exception filters.
+    pub fn catch_faults(&mut self, filters: &[ExceptionFilter]) {
+        for filter in filters {
+            self.vector_catch |= filter.vector_catch();
+        }
+    }
+
+    /// Plants the armed catches in the free breakpoint units. Returns the functions that didn't
+    /// fit if more of them were skipped than last time.
+    pub fn plant(
+        &mut self,
+        core: &mut probe_rs::Core,
+        breakpoints: &HashMap<u32, Breakpoint>,
+    ) -> Result<Vec<String>> {
+        let num_units = core.get_available_breakpoint_units()? as usize;
+        let mut free = num_units.saturating_sub(breakpoints.len() + RESERVED_UNITS);
+
+        let mut skipped = vec![];
+        for catch in self.catches.iter_mut() {
+            // A user breakpoint at the same address already stops there
+            if !catch.armed || catch.planted || breakpoints.contains_key(&catch.address) {
+                continue;
+            }
+            if free == 0 {
+                skipped.push(catch.function.clone());
+                continue;
+            }
+            core.set_hw_breakpoint(catch.address)?;
+            catch.planted = true;
+            free -= 1;
+        }
+
+        // Other cores have no vector catch, only the catch functions stop at their traps
+        if architecture::of(core).has_cortex_m_debug() {
+            let demcr = core.read_word_32(DEMCR)?;
+            core.write_word_32(DEMCR, (demcr & !DEMCR_VC_FAULTS) | self.vector_catch)?;
+        }
+
+        let grew = skipped.len() > self.skipped;
+        self.skipped = skipped.len();
+        if grew {
+            Ok(skipped)
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    /// Frees the units of the catches, so the user breakpoints can use them.
+    pub fn lift(&mut self, core: &mut probe_rs::Core) -> Result<()> {
+        for catch in self.catches.iter_mut().filter(|c| c.planted) {
+            core.clear_hw_breakpoint(catch.address)?;
+            catch.planted = false;
+        }
+        Ok(())
+    }
+
+    /// Returns the function caught at `pc` and disarms its catch.
+    pub fn hit(&mut self, core: &mut probe_rs::Core, pc: u32) -> Result<Option<String>> {
+        let catch = match self
+            .catches
+            .iter_mut()
+            .find(|c| c.planted && c.address == pc)
+        {
+            Some(val) => val,
+            None => return Ok(None),
+        };
+        core.clear_hw_breakpoint(catch.address)?;
+        catch.planted = false;
+        catch.armed = false;
+        Ok(Some(catch.function.clone()))
+    }
+
+    /// Returns the name of the fault a vector catch stopped the core at.
+    pub fn fault(&self, core: &mut probe_rs::Core) -> Result<Option<String>> {
+        if self.vector_catch == 0 {
+            return Ok(None);
+        }
+        match active_exception(core)? {
+            Some(exception) if FAULT_EXCEPTIONS.contains(&exception) => {
+                Ok(Some(exception_name(exception)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the kind of access and the address that caused a memory management or bus fault,
+    /// if the fault status register says the fault address register is valid.
+    pub fn fault_address(core: &mut probe_rs::Core) -> Result<Option<(&'static str, u32)>> {
+        let cfsr = core.read_word_32(CFSR)?;
+        if cfsr & CFSR_MMARVALID != 0 {
+            return Ok(Some(("memory management fault", core.read_word_32(MMFAR)?)));
+        }
+        if cfsr & CFSR_BFARVALID != 0 {
+            return Ok(Some(("bus fault", core.read_word_32(BFAR)?)));
+        }
+        Ok(None)
+    }
+
+    pub fn rearm(&mut self) {
+        for catch in self.catches.iter_mut() {
+            catch.armed = true;
+        }
+    }
+}
@@ -0,0 +1,328 @@
+//! Conversion between raw target memory and values
+//!
+//! All multi-byte values read from the target go through here so the byte order of the target,
+//! from the ELF header, and the width of the value, from the DWARF byte size, are handled in one
+//! place.
+use anyhow::{anyhow, Result};
+use object::Object;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+impl Default for Endianness {
+    fn default() -> Endianness {
+        Endianness::Little
+    }
+}
+
+impl Endianness {
+    /// Reads the byte order of the target from the ELF header.
+    pub fn of_elf(path: &Path) -> Result<Endianness> {
+        let file = fs::File::open(&path)?;
+        let mmap = unsafe { memmap::Mmap::map(&file)? };
+        let object = object::File::parse(&*mmap)?;
+
+        if object.is_little_endian() {
+            Ok(Endianness::Little)
+        } else {
+            Ok(Endianness::Big)
+        }
+    }
+
+    /// Reads a unsigned integer, the width is the number of bytes which can be 1 to 8.
+    pub fn read_unsigned(self, bytes: &[u8]) -> Option<u64> {
+        let len = bytes.len();
+        if len == 0 || len > 8 {
+            return None;
+        }
+
+        let mut buffer = [0u8; 8];
+        match self {
+            Endianness::Little => {
+                buffer[..len].copy_from_slice(bytes);
+                Some(u64::from_le_bytes(buffer))
+            }
+            Endianness::Big => {
+                buffer[8 - len..].copy_from_slice(bytes);
+                Some(u64::from_be_bytes(buffer))
+            }
+        }
+    }
+
+    pub fn read_u32(self, bytes: &[u8]) -> Option<u32> {
+        match bytes.len() {
+            4 => self.read_unsigned(bytes).map(|value| value as u32),
+            _ => None,
+        }
+    }
+
+    /// The `byte_size` least significant bytes of a value in target byte order.
+    pub fn write_unsigned(self, value: u64, byte_size: usize) -> Vec<u8> {
+        let size = byte_size.min(8);
+        match self {
+            Endianness::Little => value.to_le_bytes()[..size].to_vec(),
+            Endianness::Big => value.to_be_bytes()[8 - size..].to_vec(),
+        }
+    }
+
+    /// Splits memory into words, trailing bytes that don't fill a word are dropped.
+    pub fn read_words(self, bytes: &[u8]) -> Vec<u32> {
+        bytes
+            .chunks_exact(4)
+            .filter_map(|word| self.read_u32(word))
+            .collect()
+    }
+
+    /// Joins a 64 bit value read as two words, `first` is the word at the lower address.
+    ///
+    /// This is also the layout of a 64 bit value in a register pair, e.g. R0 and R1.
+    pub fn join_words(self, first: u32, second: u32) -> u64 {
+        match self {
+            Endianness::Little => first as u64 | ((second as u64) << 32),
+            Endianness::Big => ((first as u64) << 32) | second as u64,
+        }
+    }
+}
+
+/// Keeps the `byte_size` least significant bytes of a value.
+pub fn truncate(value: u64, byte_size: u64) -> u64 {
+    match byte_size {
+        0 | 8..=u64::MAX => value,
+        size => value & ((1 << (size * 8)) - 1),
+    }
+}
+
+/// Sign extends a two's complement value that is `byte_size` bytes wide.
+pub fn sign_extend(value: u64, byte_size: u64) -> i64 {
+    match byte_size {
+        0 | 8..=u64::MAX => value as i64,
+        size => {
+            let shift = 64 - size * 8;
+            ((value << shift) as i64) >> shift
+        }
+    }
+}
+
+/// Parses a value typed by the user into the bits of a base type, e.g. `u8` or `f32`.
+///
+/// Integers are decimal, or hex and binary with a `0x` or `0b` prefix, and signed values are
+/// sign extended to 64 bits.
+pub fn parse_value(base_type: &str, text: &str, byte_size: usize) -> Result<u64> {
+    let text = text.trim();
+    match base_type {
+        "bool" => match text {
+            "true" | "1" => Ok(1),
+            "false" | "0" => Ok(0),
+            _ => Err(anyhow!("Expected true or false")),
+        },
+        "char" => {
+            let inner = text
+                .strip_prefix('\'')
+                .and_then(|text| text.strip_suffix('\''))
+                .unwrap_or(text);
+            let mut chars = inner.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(c as u64),
+                _ => Err(anyhow!("Expected a single character, e.g. 'a'")),
+            }
+        }
+        "f32" => Ok(text.parse::<f32>()?.to_bits() as u64),
+        "f64" => Ok(text.parse::<f64>()?.to_bits()),
+        _ => {
+            let signed = match base_type.chars().next() {
+                Some('i') => true,
+                Some('u') => false,
+                _ => return Err(anyhow!("Values of type `{}` can't be set", base_type)),
+            };
+            parse_integer(text, signed, byte_size as u64)
+        }
+    }
+}
+
+fn parse_integer(text: &str, signed: bool, byte_size: u64) -> Result<u64> {
+    if byte_size > 8 {
+        return Err(anyhow!("Values wider than 64 bits can't be set"));
+    }
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let magnitude = match (digits.get(..2), digits.get(2..)) {
+        (Some("0x"), Some(hex)) => u64::from_str_radix(hex, 16)?,
+        (Some("0b"), Some(bin)) => u64::from_str_radix(bin, 2)?,
+        _ => digits.parse::<u64>()?,
+    };
+
+    let bits = byte_size.clamp(1, 8) * 8;
+    let out_of_range = || anyhow!("{} doesn't fit in {} bits", text, bits);
+    match (signed, negative) {
+        (false, true) => Err(anyhow!("{} is negative, the type is unsigned", text)),
+        (false, false) => match truncate(magnitude, byte_size) == magnitude {
+            true => Ok(magnitude),
+            false => Err(out_of_range()),
+        },
+        (true, _) => {
+            let limit = 1u64 << (bits - 1);
+            match (negative, magnitude) {
+                (true, m) if m <= limit => Ok((m as i64).wrapping_neg() as u64),
+                (false, m) if m < limit => Ok(m),
+                _ => Err(out_of_range()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LE: Endianness = Endianness::Little;
+    const BE: Endianness = Endianness::Big;
+
+    #[test]
+    fn read_unsigned_of_each_width() {
+        assert_eq!(LE.read_unsigned(&[0x34, 0x12]), Some(0x1234));
+        assert_eq!(BE.read_unsigned(&[0x12, 0x34]), Some(0x1234));
+
+        let bytes = [0x78, 0x56, 0x34, 0x12];
+        assert_eq!(LE.read_unsigned(&bytes), Some(0x1234_5678));
+        assert_eq!(BE.read_unsigned(&bytes), Some(0x7856_3412));
+
+        let bytes = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(LE.read_unsigned(&bytes), Some(0x0807_0605_0403_0201));
+        assert_eq!(BE.read_unsigned(&bytes), Some(0x0102_0304_0506_0708));
+
+        // Odd widths, e.g. a 3 byte field
+        assert_eq!(LE.read_unsigned(&[1, 2, 3]), Some(0x03_0201));
+        assert_eq!(BE.read_unsigned(&[1, 2, 3]), Some(0x01_0203));
+    }
+
+    #[test]
+    fn read_unsigned_rejects_empty_and_wide_buffers() {
+        assert_eq!(LE.read_unsigned(&[]), None);
+        assert_eq!(BE.read_unsigned(&[]), None);
+        assert_eq!(LE.read_unsigned(&[0; 9]), None);
+    }
+
+    #[test]
+    fn read_u32_needs_exactly_four_bytes() {
+        assert_eq!(LE.read_u32(&[0xef, 0xbe, 0xad, 0xde]), Some(0xdead_beef));
+        assert_eq!(BE.read_u32(&[0xde, 0xad, 0xbe, 0xef]), Some(0xdead_beef));
+        assert_eq!(LE.read_u32(&[0xef, 0xbe, 0xad]), None);
+        assert_eq!(BE.read_u32(&[0; 8]), None);
+    }
+
+    #[test]
+    fn write_unsigned_round_trips() {
+        assert_eq!(LE.write_unsigned(0x1234, 2), vec![0x34, 0x12]);
+        assert_eq!(BE.write_unsigned(0x1234, 2), vec![0x12, 0x34]);
+        // Only the least significant bytes are kept
+        assert_eq!(LE.write_unsigned(0x1234_5678, 1), vec![0x78]);
+        assert_eq!(BE.write_unsigned(0x1234_5678, 1), vec![0x78]);
+        assert_eq!(LE.write_unsigned(1, 16).len(), 8);
+
+        for endianness in [LE, BE] {
+            let value = 0x0102_0304_0506_0708;
+            let bytes = endianness.write_unsigned(value, 8);
+            assert_eq!(endianness.read_unsigned(&bytes), Some(value));
+        }
+    }
+
+    #[test]
+    fn read_words_drops_a_short_tail() {
+        let bytes = [1, 0, 0, 0, 2, 0, 0, 0, 3, 0];
+        assert_eq!(LE.read_words(&bytes), vec![1, 2]);
+        assert_eq!(BE.read_words(&bytes), vec![0x0100_0000, 0x0200_0000]);
+        assert!(LE.read_words(&[1, 2, 3]).is_empty());
+    }
+
+    #[test]
+    fn join_words_matches_reading_eight_bytes() {
+        let bytes = [1, 2, 3, 4, 5, 6, 7, 8];
+        for endianness in [LE, BE] {
+            let first = endianness.read_u32(&bytes[..4]).unwrap();
+            let second = endianness.read_u32(&bytes[4..]).unwrap();
+            assert_eq!(
+                Some(endianness.join_words(first, second)),
+                endianness.read_unsigned(&bytes)
+            );
+        }
+    }
+
+    #[test]
+    fn signed_values_from_bytes() {
+        // -2 as an i16 and an i32
+        let value = LE.read_unsigned(&[0xfe, 0xff]).unwrap();
+        assert_eq!(sign_extend(value, 2), -2);
+        let value = BE.read_unsigned(&[0xff, 0xff, 0xff, 0xfe]).unwrap();
+        assert_eq!(sign_extend(value, 4), -2);
+        // i8::MIN and a positive i8
+        assert_eq!(sign_extend(0x80, 1), -128);
+        assert_eq!(sign_extend(0x7f, 1), 127);
+        assert_eq!(sign_extend(u64::MAX, 8), -1);
+    }
+
+    #[test]
+    fn truncate_keeps_the_low_bytes() {
+        assert_eq!(truncate(0x1234_5678, 1), 0x78);
+        assert_eq!(truncate(0x1234_5678, 2), 0x5678);
+        assert_eq!(truncate(u64::MAX, 4), 0xffff_ffff);
+        assert_eq!(truncate(u64::MAX, 8), u64::MAX);
+        assert_eq!(truncate(u64::MAX, 0), u64::MAX);
+    }
+
+    #[test]
+    fn floats_from_bytes() {
+        let bytes = 1.5f32.to_le_bytes();
+        let bits = LE.read_u32(&bytes).unwrap();
+        assert_eq!(f32::from_bits(bits), 1.5);
+
+        let bytes = (-2.25f64).to_be_bytes();
+        let bits = BE.read_unsigned(&bytes).unwrap();
+        assert_eq!(f64::from_bits(bits), -2.25);
+    }
+
+    #[test]
+    fn parse_unsigned_values() {
+        assert_eq!(parse_value("u8", "255", 1).unwrap(), 255);
+        assert_eq!(parse_value("u16", "0x1234", 2).unwrap(), 0x1234);
+        assert_eq!(parse_value("u32", "0b101", 4).unwrap(), 5);
+        assert_eq!(parse_value("u64", " 18446744073709551615 ", 8).unwrap(), u64::MAX);
+        assert!(parse_value("u8", "256", 1).is_err());
+        assert!(parse_value("u16", "-1", 2).is_err());
+    }
+
+    #[test]
+    fn parse_signed_values() {
+        assert_eq!(parse_value("i8", "-128", 1).unwrap(), -128i64 as u64);
+        assert_eq!(parse_value("i8", "127", 1).unwrap(), 127);
+        assert!(parse_value("i8", "128", 1).is_err());
+        assert!(parse_value("i8", "-129", 1).is_err());
+        assert_eq!(parse_value("i32", "-0x10", 4).unwrap(), -16i64 as u64);
+        assert_eq!(
+            parse_value("i64", "-9223372036854775808", 8).unwrap(),
+            i64::MIN as u64
+        );
+        // What is written is the truncated value
+        let bits = parse_value("i16", "-2", 2).unwrap();
+        assert_eq!(LE.write_unsigned(bits, 2), vec![0xfe, 0xff]);
+    }
+
+    #[test]
+    fn parse_other_base_types() {
+        assert_eq!(parse_value("f32", "1.5", 4).unwrap(), 1.5f32.to_bits() as u64);
+        assert_eq!(parse_value("f64", "-2.25", 8).unwrap(), (-2.25f64).to_bits());
+        assert_eq!(parse_value("bool", "true", 1).unwrap(), 1);
+        assert_eq!(parse_value("char", "'a'", 4).unwrap(), 'a' as u64);
+        assert!(parse_value("char", "ab", 4).is_err());
+        assert!(parse_value("u128", "1", 16).is_err());
+        assert!(parse_value("str", "1", 8).is_err());
+    }
+}
@@ -0,0 +1,328 @@
+//! Checks of the interrupt vector table
+//!
+//! The table the core uses is found through VTOR and compared with the `.vector_table` section
+//! of the binary, which catches a VTOR left pointing at a bootloader and tables that don't match
+//! the flashed binary.
+use anyhow::{anyhow, Result};
+use object::{Object, ObjectSection};
+use rust_debug::call_stack::MemoryAccess;
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use super::code_address::CodeAddress;
+use super::symbols::Symbols;
+use super::values::Endianness;
+
+/// Vector Table Offset Register.
+pub const VTOR: u32 = 0xe000_ed08;
+
+// Read when the binary has no vector table, the system exceptions and the first 32 IRQs.
+const DEFAULT_ENTRIES: u32 = 48;
+
+// The encoding of `b .`, the body of the default handlers.
+const INFINITE_LOOP: [u8; 2] = [0xfe, 0xe7];
+
+const DEFAULT_HANDLERS: &[&str] = &["DefaultHandler", "DefaultHandler_"];
+
+const EXCEPTION_NAMES: [&str; 16] = [
+    "Initial SP",
+    "Reset",
+    "NMI",
+    "HardFault",
+    "MemManage",
+    "BusFault",
+    "UsageFault",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "SVCall",
+    "DebugMonitor",
+    "Reserved",
+    "PendSV",
+    "SysTick",
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VectorEntry {
+    pub index: u32,
+    pub value: u32,
+    pub symbol: Option<String>,
+    pub warnings: Vec<String>,
+}
+
+impl VectorEntry {
+    pub fn name(&self) -> String {
+        exception_name(self.index)
+    }
+}
+
+/// The name of an exception number, as in IPSR and the index in the vector table.
+pub fn exception_name(number: u32) -> String {
+    match EXCEPTION_NAMES.get(number as usize) {
+        Some(name) => name.to_string(),
+        None => format!("IRQ{}", number - 16),
+    }
+}
+
+impl fmt::Display for VectorEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>3} {:<12} {:#010x} {}",
+            self.index,
+            self.name(),
+            self.value,
+            self.symbol.as_deref().unwrap_or("")
+        )?;
+        for warning in &self.warnings {
+            write!(f, "\n    Warning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VectorTableInfo {
+    pub vtor: u32,
+    pub entries: Vec<VectorEntry>,
+    pub warnings: Vec<String>,
+}
+
+impl fmt::Display for VectorTableInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Vector table at {:#010x} (VTOR)", self.vtor)?;
+        for warning in &self.warnings {
+            write!(f, "\nWarning: {}", warning)?;
+        }
+        for entry in &self.entries {
+            write!(f, "\n{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// The `.vector_table` section of a binary.
+#[derive(Debug, Clone)]
+pub struct ElfVectorTable {
+    pub address: u32,
+    pub entries: Vec<u32>,
+}
+
+impl ElfVectorTable {
+    pub fn read(path: &Path, endianness: Endianness) -> Result<Option<ElfVectorTable>> {
+        let file = fs::File::open(&path)?;
+        let mmap = unsafe { memmap::Mmap::map(&file)? };
+        let object = object::File::parse(&*mmap)?;
+
+        let section = match object.section_by_name(".vector_table") {
+            Some(val) => val,
+            None => return Ok(None),
+        };
+
+        Ok(Some(ElfVectorTable {
+            address: section.address() as u32,
+            entries: endianness.read_words(section.data()?),
+        }))
+    }
+
+    /// The vector tables of all the modules at their target addresses, e.g. of a bootloader and
+    /// the application it starts.
+    pub fn read_all(symbols: &Symbols, endianness: Endianness) -> Result<Vec<ElfVectorTable>> {
+        let mut tables = vec![];
+        for module in &symbols.modules {
+            if let Some(mut table) = ElfVectorTable::read(&module.path, endianness)? {
+                table.address = module.to_target_address(table.address);
+                tables.push(table);
+            }
+        }
+        Ok(tables)
+    }
+}
+
+/// Reads the active vector table and checks every entry against the symbols and the binary.
+pub fn vector_table_info<M: MemoryAccess>(
+    memory: &mut M,
+    endianness: Endianness,
+    symbols: &Symbols,
+    elf_tables: &[ElfVectorTable],
+) -> Result<VectorTableInfo> {
+    let mut warnings = vec![];
+    let vtor = match read_word(memory, endianness, VTOR) {
+        Some(val) => val,
+        None => match elf_tables.first() {
+            Some(table) => {
+                warnings.push("VTOR could not be read, using the vector table of the binary".to_owned());
+                table.address
+            }
+            None => return Err(anyhow!("Failed to read VTOR")),
+        },
+    };
+    // A bootloader moves VTOR to the table of the application it starts
+    let elf_table = elf_tables
+        .iter()
+        .find(|table| table.address == vtor)
+        .or_else(|| elf_tables.first());
+
+    let num_entries = match elf_table {
+        Some(table) => {
+            if table.address != vtor {
+                warnings.push(format!(
+                    "VTOR points at {:#010x} but the vector table of the binary is at {:#010x}",
+                    vtor, table.address
+                ));
+            }
+            table.entries.len() as u32
+        }
+        None => {
+            warnings.push("The binary has no .vector_table section".to_owned());
+            DEFAULT_ENTRIES
+        }
+    };
+
+    let mut entries = vec![];
+    for index in 0..num_entries {
+        let value = match read_word(memory, endianness, vtor + index * 4) {
+            Some(val) => val,
+            None => return Err(anyhow!("Failed to read the vector table at {:#010x}", vtor + index * 4)),
+        };
+        let mut entry_warnings = vec![];
+
+        if let Some(expected) = elf_table.and_then(|table| table.entries.get(index as usize)) {
+            if *expected != value {
+                entry_warnings.push(format!("the binary has {:#010x}", expected));
+            }
+        }
+
+        // The first entry is the initial stack pointer and unused entries are zero
+        let mut symbol = None;
+        if index > 0 && value != 0 {
+            let address = CodeAddress::new(value).address();
+            symbol = symbols.find_function(address).map(|symbol| symbol.demangled);
+
+            if value & 1 == 0 {
+                entry_warnings.push("the Thumb bit is not set".to_owned());
+            }
+            if !symbols.modules.iter().any(|module| module.contains(address)) {
+                entry_warnings.push("points outside the text section".to_owned());
+            }
+            if let Some(name) = &symbol {
+                if DEFAULT_HANDLERS.contains(&name.as_str()) {
+                    entry_warnings.push("the default handler".to_owned());
+                }
+            }
+            if let Some(code) = memory.get_address(&address, 2) {
+                if code == INFINITE_LOOP {
+                    entry_warnings.push("the handler is an infinite loop".to_owned());
+                }
+            }
+        }
+
+        entries.push(VectorEntry {
+            index,
+            value,
+            symbol,
+            warnings: entry_warnings,
+        });
+    }
+
+    Ok(VectorTableInfo {
+        vtor,
+        entries,
+        warnings,
+    })
+}
+
+fn read_word<M: MemoryAccess>(memory: &mut M, endianness: Endianness, address: u32) -> Option<u32> {
+    memory
+        .get_address(&address, 4)
+        .and_then(|bytes| endianness.read_u32(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct Memory(HashMap<u32, u8>);
+
+    impl Memory {
+        fn write_words(&mut self, address: u32, words: &[u32]) {
+            for (i, word) in words.iter().enumerate() {
+                for (j, byte) in word.to_le_bytes().iter().enumerate() {
+                    self.0.insert(address + (i * 4 + j) as u32, *byte);
+                }
+            }
+        }
+    }
+
+    impl MemoryAccess for Memory {
+        fn get_address(&mut self, address: &u32, num_bytes: usize) -> Option<Vec<u8>> {
+            (0..num_bytes as u32)
+                .map(|offset| self.0.get(&(address + offset)).copied())
+                .collect()
+        }
+    }
+
+    fn bootloader_and_application() -> Vec<ElfVectorTable> {
+        vec![
+            ElfVectorTable {
+                address: 0x0800_0000,
+                entries: vec![0x2000_8000, 0x0800_0401],
+            },
+            ElfVectorTable {
+                address: 0x0800_8000,
+                entries: vec![0x2002_0000, 0x0800_8401],
+            },
+        ]
+    }
+
+    fn info(vtor: u32) -> VectorTableInfo {
+        let mut memory = Memory(HashMap::new());
+        memory.write_words(VTOR, &[vtor]);
+        memory.write_words(0x0800_0000, &[0x2000_8000, 0x0800_0401]);
+        memory.write_words(0x0800_8000, &[0x2002_0000, 0x0800_8401]);
+        let symbols = Symbols { modules: vec![] };
+        vector_table_info(
+            &mut memory,
+            Endianness::Little,
+            &symbols,
+            &bootloader_and_application(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn the_table_of_the_bootloader_before_the_handoff() {
+        let info = info(0x0800_0000);
+        assert!(info.warnings.is_empty(), "{:?}", info.warnings);
+        assert_eq!(info.entries[1].value, 0x0800_0401);
+        assert!(info.entries[1].warnings.iter().all(|w| !w.contains("the binary has")));
+    }
+
+    #[test]
+    fn the_table_of_the_application_after_the_handoff() {
+        let info = info(0x0800_8000);
+        assert!(info.warnings.is_empty(), "{:?}", info.warnings);
+        assert_eq!(info.entries[0].value, 0x2002_0000);
+        assert!(info.entries[1].warnings.iter().all(|w| !w.contains("the binary has")));
+    }
+
+    #[test]
+    fn a_table_of_no_module() {
+        let mut memory = Memory(HashMap::new());
+        memory.write_words(VTOR, &[0x2000_0000]);
+        memory.write_words(0x2000_0000, &[0x2000_8000, 0x2000_0101]);
+        let info = vector_table_info(
+            &mut memory,
+            Endianness::Little,
+            &Symbols { modules: vec![] },
+            &bootloader_and_application(),
+        )
+        .unwrap();
+        assert!(info.warnings[0].contains("VTOR points at 0x20000000"));
+        assert_eq!(info.entries[1].warnings[0], "the binary has 0x08000401");
+    }
+}
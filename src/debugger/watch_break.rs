@@ -0,0 +1,138 @@
+//! Watch expressions that halt the core when their value changes
+//!
+//! `watch break <expr>` covers what the DWT comparators can't watch: values in flash, whole
+//! structs and computed expressions. The expressions are evaluated whenever the core is halted
+//! anyway, at every reported stop and at the halts the debugger resumes by itself, e.g. ignored
+//! breakpoint hits. A change keeps the core halted and is reported as a data breakpoint.
+//!
+//! A variable path or static is compared by its displayed value including all its fields, other
+//! expressions are breakpoint conditions and compared by their truth. An expression that can't
+//! be evaluated at a stop, e.g. a local of another function, keeps its previous value.
+//!
+//! With sampling on the running core is also halted at a fixed interval to evaluate them. Every
+//! sample stops the firmware for the unwinding and the memory reads, milliseconds over most
+//! probes, so it is off by default and its halts are counted in the intrusiveness report.
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::conditions::Condition;
+use super::Variable;
+
+/// The most changes kept per expression.
+const HISTORY_LENGTH: usize = 16;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchChange {
+    pub pc: u32,
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchBreak {
+    pub id: u32,
+    pub expression: String,
+    #[serde(skip)]
+    pub condition: Condition,
+    /// The last evaluated value, None before the first evaluation.
+    pub value: Option<String>,
+    pub history: VecDeque<WatchChange>,
+}
+
+impl WatchBreak {
+    /// Records a newly evaluated value, returns the description of the change if it changed.
+    pub fn update(&mut self, value: String, pc: u32) -> Option<String> {
+        let old = match self.value.replace(value.clone()) {
+            Some(old) if old != value => old,
+            _ => return None,
+        };
+        let description = format!("Watch `{}`: {} \u{2192} {}", self.expression, old, value);
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(WatchChange {
+            pc,
+            old,
+            new: value,
+        });
+        Some(description)
+    }
+}
+
+pub struct WatchBreaks {
+    next_id: u32,
+    pub watches: Vec<WatchBreak>,
+    /// Off by default, see the module documentation.
+    pub sample_interval: Option<Duration>,
+    last_sample: Instant,
+}
+
+impl WatchBreaks {
+    pub fn new() -> WatchBreaks {
+        WatchBreaks {
+            next_id: 1,
+            watches: vec![],
+            sample_interval: None,
+            last_sample: Instant::now(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    pub fn add(&mut self, condition: Condition) -> &mut WatchBreak {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.watches.push(WatchBreak {
+            id,
+            expression: condition.text().to_owned(),
+            condition,
+            value: None,
+            history: VecDeque::new(),
+        });
+        self.watches.last_mut().unwrap()
+    }
+
+    pub fn remove(&mut self, id: u32) -> bool {
+        let len = self.watches.len();
+        self.watches.retain(|watch| watch.id != id);
+        self.watches.len() != len
+    }
+
+    /// True if the running core should be halted for a sample now.
+    pub fn sample_due(&mut self) -> bool {
+        match self.sample_interval {
+            Some(interval) if !self.watches.is_empty() => {
+                if self.last_sample.elapsed() < interval {
+                    return false;
+                }
+                self.last_sample = Instant::now();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The displayed value of a variable with all its fields, so a struct changes with any of them.
+pub fn full_value(variable: &Variable) -> String {
+    if variable.children.is_empty() {
+        return variable.value.clone();
+    }
+    let fields: Vec<String> = variable
+        .children
+        .iter()
+        .map(|child| {
+            format!(
+                "{}: {}",
+                child.name.clone().unwrap_or_default(),
+                full_value(child)
+            )
+        })
+        .collect();
+    format!("{{ {} }}", fields.join(", "))
+}
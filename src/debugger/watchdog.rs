@@ -0,0 +1,258 @@
+//! Deadline on the probe operations of the debugger thread
+//!
+//! A probe-rs call can hang inside a USB transfer, which freezes the debugger thread with it.
+//! The thread records every operation it starts here and a supervisor thread checks them. An
+//! operation running past the deadline marks the session degraded, and past twice the deadline
+//! the request it runs for is failed and the requests queued behind it are failed as they
+//! arrive. When the operation finally returns its response is dropped and the probe is reopened.
+//!
+//! Flashing and core dumps can take longer than any sensible deadline and are not watched.
+use crossbeam_channel::{Receiver, Sender};
+use log::warn;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::commands::{
+    debug_event::DebugEvent, debug_request::DebugRequest, debug_response::DebugResponse, Command,
+};
+
+/// Longer than `nexti` waits for a called function to return.
+pub const DEFAULT_DEADLINE: Duration = Duration::from_secs(10);
+
+const SUPERVISOR_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Returned when a timed out operation returns, dropping the session reopens the probe.
+#[derive(Debug)]
+pub struct Reopen;
+
+impl fmt::Display for Reopen {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "A timed out probe operation returned, the probe is reopened")
+    }
+}
+
+impl std::error::Error for Reopen {}
+
+/// The variant name of the request, the arguments can be long.
+pub fn operation_name(request: &DebugRequest) -> String {
+    format!("{:?}", request)
+        .split(|c: char| !c.is_alphanumeric())
+        .next()
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// Flashing and core dumps are not watched.
+pub fn is_watched(request: &DebugRequest) -> bool {
+    !matches!(
+        request,
+        DebugRequest::Flash { .. } | DebugRequest::CoreDump { .. } | DebugRequest::SnapshotState { .. }
+    )
+}
+
+#[derive(Debug, Clone)]
+struct Operation {
+    name: String,
+    started: Instant,
+    // A frontend waits for the response of a request, not for a status poll.
+    request: bool,
+}
+
+#[derive(Debug)]
+struct WatchdogState {
+    deadline: Option<Duration>,
+    operation: Option<Operation>,
+    degraded: bool,
+    timed_out: bool,
+    exit_requested: bool,
+    stopped: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    state: Arc<Mutex<WatchdogState>>,
+}
+
+impl Watchdog {
+    pub fn new() -> Watchdog {
+        Watchdog {
+            state: Arc::new(Mutex::new(WatchdogState {
+                deadline: Some(DEFAULT_DEADLINE),
+                operation: None,
+                degraded: false,
+                timed_out: false,
+                exit_requested: false,
+                stopped: false,
+            })),
+        }
+    }
+
+    /// `None` disables the watchdog.
+    pub fn set_deadline(&self, deadline: Option<Duration>) {
+        self.state.lock().unwrap().deadline = deadline;
+    }
+
+    /// Records the start of an operation, `request` is set if a frontend waits for its response.
+    pub fn start(&self, name: &str, request: bool) {
+        self.state.lock().unwrap().operation = Some(Operation {
+            name: name.to_owned(),
+            started: Instant::now(),
+            request,
+        });
+    }
+
+    /// Restarts the deadline of the operation, called at each step of an operation that makes
+    /// progress, e.g. each instruction of `nexti`. A timed out operation stays timed out.
+    pub fn heartbeat(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.timed_out {
+            return;
+        }
+        if let Some(operation) = &mut state.operation {
+            operation.started = Instant::now();
+        }
+    }
+
+    /// Records the end of the operation, returns true if it timed out and the probe has to be
+    /// reopened. The response of a timed out request was already sent by the supervisor.
+    pub fn finish(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.operation = None;
+        state.degraded = false;
+        std::mem::replace(&mut state.timed_out, false)
+    }
+
+    /// Returns true if an exit was requested while the debugger thread was stuck.
+    pub fn take_exit(&self) -> bool {
+        std::mem::replace(&mut self.state.lock().unwrap().exit_requested, false)
+    }
+
+    /// Starts the supervisor thread, it runs until `stop` is called.
+    pub fn supervise(&self, sender: Sender<Command>, receiver: Receiver<DebugRequest>) {
+        let state = self.state.clone();
+        thread::spawn(move || loop {
+            thread::sleep(SUPERVISOR_INTERVAL);
+            let mut state = state.lock().unwrap();
+            if state.stopped {
+                return;
+            }
+            if check(&mut state, &sender, &receiver).is_err() {
+                // The frontend is gone
+                return;
+            }
+        });
+    }
+
+    pub fn stop(&self) {
+        self.state.lock().unwrap().stopped = true;
+    }
+}
+
+fn check(
+    state: &mut WatchdogState,
+    sender: &Sender<Command>,
+    receiver: &Receiver<DebugRequest>,
+) -> Result<(), crossbeam_channel::SendError<Command>> {
+    let (operation, deadline) = match (&state.operation, state.deadline) {
+        (Some(operation), Some(deadline)) => (operation.clone(), deadline),
+        _ => return Ok(()),
+    };
+    let elapsed = operation.started.elapsed();
+
+    if elapsed > deadline && !state.degraded {
+        state.degraded = true;
+        warn!("Probe operation `{}` is stuck", operation.name);
+        sender.send(Command::Event(DebugEvent::Output {
+            category: "console".to_owned(),
+            output: format!(
+                "Warning: the probe operation `{}` has not returned after {}s, the session is degraded",
+                operation.name,
+                elapsed.as_secs()
+            ),
+        }))?;
+    }
+
+    if elapsed > deadline * 2 && !state.timed_out {
+        state.timed_out = true;
+        if operation.request {
+            sender.send(Command::Response(DebugResponse::Error {
+                message: format!(
+                    "`{}` timed out after {}s, the probe is reopened when it returns",
+                    operation.name,
+                    elapsed.as_secs()
+                ),
+            }))?;
+        }
+    }
+
+    // Fail the requests queued behind the stuck operation instead of letting them wait for it
+    if state.timed_out {
+        while let Ok(request) = receiver.try_recv() {
+            match request {
+                DebugRequest::Exit => state.exit_requested = true,
+                request => sender.send(Command::Response(DebugResponse::Error {
+                    message: format!(
+                        "{} failed, the debugger is stuck in the probe operation `{}`",
+                        operation_name(&request),
+                        operation.name
+                    ),
+                }))?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam_channel::unbounded;
+
+    // Starts an operation that began `elapsed` ago.
+    fn started(watchdog: &Watchdog, elapsed: Duration) {
+        watchdog.start("Step", true);
+        let mut state = watchdog.state.lock().unwrap();
+        state.operation.as_mut().unwrap().started = Instant::now() - elapsed;
+    }
+
+    fn check_now(watchdog: &Watchdog) -> Vec<Command> {
+        let (sender, commands) = unbounded();
+        let (_requests, receiver) = unbounded();
+        check(&mut watchdog.state.lock().unwrap(), &sender, &receiver).unwrap();
+        commands.try_iter().collect()
+    }
+
+    #[test]
+    fn a_slow_operation_times_out() {
+        let watchdog = Watchdog::new();
+        started(&watchdog, DEFAULT_DEADLINE * 3);
+        let commands = check_now(&watchdog);
+        assert!(matches!(commands[0], Command::Event(DebugEvent::Output { .. })));
+        assert!(matches!(
+            commands[1],
+            Command::Response(DebugResponse::Error { .. })
+        ));
+        assert!(watchdog.finish());
+    }
+
+    #[test]
+    fn a_heartbeat_restarts_the_deadline() {
+        let watchdog = Watchdog::new();
+        started(&watchdog, DEFAULT_DEADLINE * 3);
+        watchdog.heartbeat();
+        assert!(check_now(&watchdog).is_empty());
+        assert!(!watchdog.finish());
+    }
+
+    #[test]
+    fn a_heartbeat_after_the_timeout_keeps_it() {
+        let watchdog = Watchdog::new();
+        started(&watchdog, DEFAULT_DEADLINE * 3);
+        check_now(&watchdog);
+        watchdog.heartbeat();
+        assert!(watchdog.finish());
+    }
+}
@@ -0,0 +1,230 @@
+use super::values::Endianness;
+use anyhow::{anyhow, Result};
+use probe_rs::MemoryInterface;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+// Cortex-M DWT registers
+const DEMCR: u32 = 0xe000_edfc;
+const DEMCR_TRCENA: u32 = 1 << 24;
+const DWT_CTRL: u32 = 0xe000_1000;
+const DWT_COMP0: u32 = 0xe000_1020;
+const DWT_MASK0: u32 = 0xe000_1024;
+const DWT_FUNCTION0: u32 = 0xe000_1028;
+const DWT_COMPARATOR_STRIDE: u32 = 0x10;
+const DWT_FUNCTION_MATCHED: u32 = 1 << 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn function(&self) -> u32 {
+        match self {
+            WatchKind::Read => 0b0101,
+            WatchKind::Write => 0b0110,
+            WatchKind::ReadWrite => 0b0111,
+        }
+    }
+}
+
+impl FromStr for WatchKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(WatchKind::Read),
+            "write" => Ok(WatchKind::Write),
+            "rw" | "readWrite" => Ok(WatchKind::ReadWrite),
+            _ => Err(anyhow!("Expected one of read, write or rw")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Watchpoint {
+    pub id: u32,
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+    pub kind: WatchKind,
+    pub comparator: usize,
+    pub value: Vec<u8>,
+    pub endianness: Endianness,
+}
+
+/// A data breakpoint of the DAP `setDataBreakpoints` request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataBreakpoint {
+    /// As returned by `dataBreakpointInfo`, see `data_id`.
+    pub data_id: String,
+    #[serde(default)]
+    pub access_type: Option<WatchKind>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DataBreakpointResult {
+    /// The id of the watchpoint, None if it couldn't be set.
+    pub id: Option<u32>,
+    pub message: Option<String>,
+}
+
+/// The DWT comparators in use, shared by all the watchpoint frontends.
+pub struct Watchpoints {
+    next_id: u32,
+    pub watchpoints: Vec<Watchpoint>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Watchpoints {
+        Watchpoints {
+            next_id: 1,
+            watchpoints: vec![],
+        }
+    }
+
+    pub fn add(
+        &mut self,
+        core: &mut probe_rs::Core,
+        name: String,
+        address: u32,
+        size: u32,
+        kind: WatchKind,
+        endianness: Endianness,
+    ) -> Result<Watchpoint> {
+        check_range(address, size)?;
+
+        let comparator = match (0..num_comparators(core)?)
+            .find(|c| self.watchpoints.iter().all(|w| w.comparator != *c))
+        {
+            Some(val) => val,
+            None => return Err(anyhow!("All DWT comparators are already in use")),
+        };
+
+        let mut value = vec![0u8; size as usize];
+        core.read_8(address, &mut value)?;
+
+        enable_dwt(core)?;
+        let offset = comparator as u32 * DWT_COMPARATOR_STRIDE;
+        core.write_word_32(DWT_COMP0 + offset, address)?;
+        core.write_word_32(DWT_MASK0 + offset, size.trailing_zeros())?;
+        core.write_word_32(DWT_FUNCTION0 + offset, kind.function())?;
+
+        let watchpoint = Watchpoint {
+            id: self.next_id,
+            name,
+            address,
+            size,
+            kind,
+            comparator,
+            value,
+            endianness,
+        };
+        self.next_id += 1;
+        self.watchpoints.push(watchpoint.clone());
+
+        Ok(watchpoint)
+    }
+
+    pub fn remove(&mut self, core: &mut probe_rs::Core, id: u32) -> Result<Watchpoint> {
+        let index = match self.watchpoints.iter().position(|w| w.id == id) {
+            Some(val) => val,
+            None => return Err(anyhow!("No watchpoint with id {}", id)),
+        };
+        let watchpoint = self.watchpoints.remove(index);
+        clear_comparator(core, watchpoint.comparator)?;
+
+        Ok(watchpoint)
+    }
+
+    pub fn clear(&mut self, core: &mut probe_rs::Core) -> Result<()> {
+        for watchpoint in &self.watchpoints {
+            clear_comparator(core, watchpoint.comparator)?;
+        }
+        self.watchpoints = vec![];
+
+        Ok(())
+    }
+
+    /// Finds the watchpoints whose comparator matched, reads their new values and returns them
+    /// together with the value they had before.
+    pub fn triggered(&mut self, core: &mut probe_rs::Core) -> Result<Vec<(Watchpoint, Vec<u8>)>> {
+        let mut triggered = vec![];
+        for watchpoint in &mut self.watchpoints {
+            let offset = watchpoint.comparator as u32 * DWT_COMPARATOR_STRIDE;
+
+            // The matched bit is cleared when the function register is read.
+            let function = core.read_word_32(DWT_FUNCTION0 + offset)?;
+            if function & DWT_FUNCTION_MATCHED == 0 {
+                continue;
+            }
+
+            let mut value = vec![0u8; watchpoint.size as usize];
+            core.read_8(watchpoint.address, &mut value)?;
+            let old_value = std::mem::replace(&mut watchpoint.value, value);
+            triggered.push((watchpoint.clone(), old_value));
+        }
+
+        Ok(triggered)
+    }
+}
+
+/// A comparator matches a power of two sized range at an address aligned to its size.
+pub fn check_range(address: u32, size: u32) -> Result<()> {
+    if !size.is_power_of_two() || address % size != 0 {
+        return Err(anyhow!(
+            "Watchpoints require a power of two size and an aligned address, got {} bytes at {:#010x}",
+            size,
+            address
+        ));
+    }
+    Ok(())
+}
+
+/// The data id of a data breakpoint, `<address>/<size>/<name>`. The name is kept for the stopped
+/// event, the DAP client only hands the id back.
+pub fn data_id(address: u32, size: u32, name: &str) -> String {
+    format!("{:#010x}/{}/{}", address, size, name)
+}
+
+pub fn parse_data_id(data_id: &str) -> Result<(u32, u32, String)> {
+    let mut parts = data_id.splitn(3, '/');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(address), Some(size), Some(name)) => Ok((
+            u32::from_str_radix(address.trim_start_matches("0x"), 16)?,
+            size.parse()?,
+            name.to_owned(),
+        )),
+        _ => Err(anyhow!("Malformed data id {:?}", data_id)),
+    }
+}
+
+pub fn num_comparators(core: &mut probe_rs::Core) -> Result<usize> {
+    let ctrl = core.read_word_32(DWT_CTRL)?;
+    Ok((ctrl >> 28) as usize)
+}
+
+fn enable_dwt(core: &mut probe_rs::Core) -> Result<()> {
+    let demcr = core.read_word_32(DEMCR)?;
+    core.write_word_32(DEMCR, demcr | DEMCR_TRCENA)?;
+    Ok(())
+}
+
+fn clear_comparator(core: &mut probe_rs::Core, comparator: usize) -> Result<()> {
+    let offset = comparator as u32 * DWT_COMPARATOR_STRIDE;
+    core.write_word_32(DWT_FUNCTION0 + offset, 0)?;
+    Ok(())
+}
+
+/// Formats bytes read from the target as a hex number, e.g. `0x0000002a`.
+pub fn format_value(value: &[u8], endianness: Endianness) -> String {
+    match endianness.read_unsigned(value) {
+        Some(number) => format!("{:#0width$x}", number, width = value.len() * 2 + 2),
+        None => format!("{:02x?}", value),
+    }
+}
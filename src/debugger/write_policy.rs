@@ -0,0 +1,109 @@
+//! Protection against accidental writes to peripheral registers
+//!
+//! A mistyped value written to a peripheral, e.g. a DMA or clock enable register, can do more
+//! damage than one written to RAM. There is no SVD description of the peripherals, so the
+//! peripheral regions of the Cortex-M memory map stand in for them: writes there need to be
+//! confirmed by default, with `!` before the value in the debug console or with the `write`
+//! command, and can be allowed or denied for the whole session.
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// The peripheral region and the system region, which holds the core peripherals.
+const PERIPHERAL_REGIONS: [RangeInclusive<u32>; 2] =
+    [0x4000_0000..=0x5fff_ffff, 0xe000_0000..=0xffff_ffff];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PeripheralWrites {
+    /// Only confirmed writes are done.
+    Confirm,
+    Allow,
+    Deny,
+}
+
+impl Default for PeripheralWrites {
+    fn default() -> PeripheralWrites {
+        PeripheralWrites::Confirm
+    }
+}
+
+impl FromStr for PeripheralWrites {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "confirm" => Ok(PeripheralWrites::Confirm),
+            "allow" => Ok(PeripheralWrites::Allow),
+            "deny" => Ok(PeripheralWrites::Deny),
+            _ => Err(anyhow!("Expected confirm, allow or deny")),
+        }
+    }
+}
+
+pub fn is_peripheral(address: u32) -> bool {
+    PERIPHERAL_REGIONS
+        .iter()
+        .any(|region| region.contains(&address))
+}
+
+/// The first peripheral address in `start..end`, if the range overlaps a peripheral region.
+pub fn first_peripheral(start: u32, end: u64) -> Option<u32> {
+    PERIPHERAL_REGIONS
+        .iter()
+        .filter(|region| (start as u64) <= *region.end() as u64 && end > *region.start() as u64)
+        .map(|region| start.max(*region.start()))
+        .min()
+}
+
+impl PeripheralWrites {
+    /// Fails with how to write anyway if the policy blocks the write.
+    pub fn check(self, address: u32, confirmed: bool) -> Result<()> {
+        if !is_peripheral(address) {
+            return Ok(());
+        }
+        match (self, confirmed) {
+            (PeripheralWrites::Allow, _) | (PeripheralWrites::Confirm, true) => Ok(()),
+            (PeripheralWrites::Confirm, false) => Err(anyhow!(
+                "{:#010x} is a peripheral register, confirm the write with `!` before the value, e.g. `mem[{:#010x}] = !0x1`, or use `!write {:#010x} <value>`",
+                address,
+                address,
+                address
+            )),
+            (PeripheralWrites::Deny, _) => Err(anyhow!(
+                "Writes to peripheral registers are denied, {:#010x} is not written. Change it with `peripheral-writes confirm|allow`",
+                address
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_overlapping_a_peripheral_region() {
+        assert_eq!(first_peripheral(0x2000_0000, 0x2000_0100), None);
+        // Neither edge is a peripheral, the middle of the range is
+        assert_eq!(
+            first_peripheral(0x3fff_fff0, 0x6000_0010),
+            Some(0x4000_0000)
+        );
+        assert_eq!(first_peripheral(0x3fff_fff0, 0x4000_0000), None);
+        assert_eq!(
+            first_peripheral(0x4000_0010, 0x4000_0014),
+            Some(0x4000_0010)
+        );
+        assert_eq!(
+            first_peripheral(0x5fff_fffc, 0x6000_0004),
+            Some(0x5fff_fffc)
+        );
+        assert_eq!(first_peripheral(0x6000_0000, 0xe000_0000), None);
+        assert_eq!(
+            first_peripheral(0xffff_fffc, 0x1_0000_0000),
+            Some(0xffff_fffc)
+        );
+    }
+}
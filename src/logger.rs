@@ -0,0 +1,171 @@
+//! The log of the debugger, adjustable while it runs
+//!
+//! The filter starts from `RUST_LOG` and the verbosity option, and `log <module> <level>` changes
+//! it without restarting the server, which would lose the failing session. The filter is built
+//! with env_logger, so a module also sets the modules in it. The printed lines are also kept in a
+//! ring of the last `BUFFERED_LINES`, `log tail` shows them in the Debug Console. Keeping a line
+//! only moves the string that was printed, the ring is never read unless tailed.
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use env_logger::filter::{Builder, Filter};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+
+/// The lines kept for `log tail`.
+const BUFFERED_LINES: usize = 1000;
+
+struct Directives {
+    /// `RUST_LOG`, the levels set later override it.
+    env: Option<String>,
+    /// The levels by module, None for the modules without a level.
+    levels: Vec<(Option<String>, LevelFilter)>,
+}
+
+impl fmt::Display for Directives {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = vec![];
+        if let Some(env) = &self.env {
+            parts.push(format!("RUST_LOG={}", env));
+        }
+        for (module, level) in &self.levels {
+            match module {
+                Some(module) => parts.push(format!("{}={}", module, level)),
+                None => parts.push(level.to_string()),
+            }
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// The last lines, `next` is the oldest once the ring is full.
+struct Recent {
+    lines: Vec<String>,
+    next: usize,
+}
+
+impl Recent {
+    fn push(&mut self, line: String) {
+        if self.lines.len() < BUFFERED_LINES {
+            self.lines.push(line);
+        } else {
+            self.lines[self.next] = line;
+            self.next = (self.next + 1) % BUFFERED_LINES;
+        }
+    }
+
+    fn last(&self, count: usize) -> Vec<String> {
+        let (newer, older) = self.lines.split_at(self.next);
+        let lines: Vec<&String> = older.iter().chain(newer.iter()).collect();
+        let start = lines.len().saturating_sub(count);
+        lines[start..].iter().map(|line| line.to_string()).collect()
+    }
+}
+
+static DIRECTIVES: Mutex<Directives> = Mutex::new(Directives {
+    env: None,
+    levels: Vec::new(),
+});
+static FILTER: RwLock<Option<Filter>> = RwLock::new(None);
+static RECENT: Mutex<Recent> = Mutex::new(Recent {
+    lines: Vec::new(),
+    next: 0,
+});
+static LOGGER: Logger = Logger;
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match FILTER.read() {
+            Ok(filter) => matches!(&*filter, Some(filter) if filter.enabled(metadata)),
+            Err(_) => false,
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        let matches = match FILTER.read() {
+            Ok(filter) => matches!(&*filter, Some(filter) if filter.matches(record)),
+            Err(_) => false,
+        };
+        if !matches {
+            return;
+        }
+        let line = format!(
+            "{} {}:{} [{}] - {}",
+            Local::now().format("%Y-%m-%dT%H:%M:%S"),
+            record.file().unwrap_or("unknown"),
+            record.line().unwrap_or(0),
+            record.level(),
+            record.args()
+        );
+        eprintln!("{}", line);
+        if let Ok(mut recent) = RECENT.lock() {
+            recent.push(line);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger, probe-rs gets its own level as it logs a lot.
+pub fn init(level: LevelFilter, probe_rs_level: LevelFilter) -> Result<()> {
+    let mut directives = lock_directives()?;
+    directives.env = std::env::var("RUST_LOG").ok();
+    directives.levels = vec![(None, level), (Some("probe_rs".to_owned()), probe_rs_level)];
+    apply(&directives)?;
+    log::set_logger(&LOGGER).map_err(|err| anyhow!("Failed to set the logger: {}", err))
+}
+
+/// Sets the level of a module and the modules in it, the default level if `module` is None.
+/// Returns the new filter.
+pub fn set_level(module: Option<&str>, level: &str) -> Result<String> {
+    let level = match LevelFilter::from_str(level) {
+        Ok(val) => val,
+        Err(_) => {
+            return Err(anyhow!(
+                "Unknown log level `{}`, the levels are off, error, warn, info, debug and trace",
+                level
+            ))
+        }
+    };
+    let mut directives = lock_directives()?;
+    directives
+        .levels
+        .retain(|(name, _)| name.as_deref() != module);
+    directives.levels.push((module.map(str::to_owned), level));
+    apply(&directives)?;
+    Ok(directives.to_string())
+}
+
+/// The last `count` lines logged, oldest first.
+pub fn tail(count: usize) -> Vec<String> {
+    match RECENT.lock() {
+        Ok(recent) => recent.last(count),
+        Err(_) => vec![],
+    }
+}
+
+fn lock_directives() -> Result<std::sync::MutexGuard<'static, Directives>> {
+    DIRECTIVES
+        .lock()
+        .map_err(|_| anyhow!("The log filter is poisoned"))
+}
+
+fn apply(directives: &Directives) -> Result<()> {
+    let mut builder = Builder::new();
+    if let Some(env) = &directives.env {
+        builder.parse(env);
+    }
+    for (module, level) in &directives.levels {
+        builder.filter(module.as_deref(), *level);
+    }
+    let filter = builder.build();
+    log::set_max_level(filter.filter());
+    match FILTER.write() {
+        Ok(mut current) => *current = Some(filter),
+        Err(_) => return Err(anyhow!("The log filter is poisoned")),
+    }
+    Ok(())
+}
@@ -2,13 +2,17 @@ mod cli;
 mod commands;
 mod debug_adapter;
 mod debugger;
+mod logger;
 
 use rust_debug::utils::in_ranges;
 
 use std::path::Path;
 use std::{borrow, fs};
 
-use probe_rs::{Probe, Session};
+use probe_rs::{DebugProbeInfo, Session};
+
+use debugger::probes::open_probe;
+use debugger::session_summary::ProbeDetails;
 
 use object::{Object, ObjectSection};
 
@@ -19,14 +23,11 @@ use std::rc::Rc;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{Context, Result};
 
 use std::str::FromStr;
 
-use chrono::Local;
-use env_logger::*;
 use log::{error, LevelFilter};
-use std::io::Write;
 
 #[derive(Debug)]
 enum Mode {
@@ -66,10 +67,30 @@ pub struct Opt {
     #[structopt(short = "elf", long = "elf-file")]
     elf_file_path: Option<PathBuf>,
 
+    /// Elf file to read the symbols from, if it differs from the flashed one
+    #[structopt(long = "symbol-file")]
+    symbol_files: Vec<PathBuf>,
+
+    /// Core dump to debug instead of a target connected through a probe
+    #[structopt(long = "core-dump")]
+    core_dump: Option<PathBuf>,
+
+    /// Write the event history of the session to this file at exit
+    #[structopt(long = "session-log")]
+    session_log: Option<PathBuf>,
+
     /// Current working directory
     #[structopt(short = "wd", long = "work-directory")]
     work_directory: Option<String>,
 
+    /// Wait for a probe held by another process to be released instead of failing at once
+    #[structopt(long = "force-claim")]
+    force_claim: bool,
+
+    /// Report the defmt-test results of the firmware, the exit code is the result of the tests
+    #[structopt(long = "test-run")]
+    test_run: bool,
+
     /// Type of Chip
     #[structopt(short = "c", long = "chip")]
     chip: Option<String>,
@@ -96,22 +117,7 @@ fn main() -> Result<()> {
         _ => log_level,
     };
 
-    let mut builder = Builder::from_default_env();
-    builder
-        .format(|buf, record| {
-            writeln!(
-                buf,
-                "{} {}:{} [{}] - {}",
-                Local::now().format("%Y-%m-%dT%H:%M:%S"),
-                record.file().unwrap_or("unknown"),
-                record.line().unwrap_or(0),
-                record.level(),
-                record.args()
-            )
-        })
-        .filter(None, log_level)
-        .filter_module("probe_rs", probe_rs_log_level)
-        .init();
+    logger::init(log_level, probe_rs_log_level)?;
 
     match opt.mode {
         Mode::Debug => cli::debug_mode(opt),
@@ -119,22 +125,20 @@ fn main() -> Result<()> {
     }
 }
 
-fn attach_probe(chip: &str, probe_num: usize) -> Result<Session> {
-    // Get a list of all available debug probes.
-    let probes = Probe::list_all();
-
-    // Use the first probe found.
-    let probe = match probes.len() > probe_num {
-        true => probes[probe_num].open().context("Failed to open probe")?,
-        false => return Err(anyhow!("Probe {} not available", probe_num)),
-    };
+fn attach_probe(
+    chip: &str,
+    probe_info: &DebugProbeInfo,
+    force_claim: bool,
+) -> Result<(Session, ProbeDetails)> {
+    let mut probe = open_probe(probe_info, force_claim)?;
+    let details = ProbeDetails::new(probe_info, &mut probe);
 
     // Attach to a chip.
     let session = probe
         .attach_under_reset(chip)
         .context("Failed to attach probe to target")?;
 
-    Ok(session)
+    Ok((session, details))
 }
 
 fn read_dwarf<'a>(
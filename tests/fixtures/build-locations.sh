@@ -0,0 +1,20 @@
+#!/bin/sh
+# Builds the fixtures of the location tests in src/debugger/locations.rs. The program is
+# `no_core` so that only a nightly rustc with the ARM backend is needed, not a core library for
+# the target.
+set -e
+cd "$(dirname "$0")"
+for level in 2 3; do
+    rustc +nightly \
+        --target thumbv7em-none-eabihf \
+        --crate-type bin \
+        -C opt-level=$level \
+        -C debuginfo=2 \
+        -C panic=abort \
+        -C linker=rust-lld \
+        -C link-arg=--unresolved-symbols=ignore-all \
+        -C link-arg=-eReset \
+        --remap-path-prefix "$(pwd)=fixtures" \
+        locations.rs \
+        -o locations-o$level.elf
+done
@@ -0,0 +1,57 @@
+// The fixture of the location tests, built by build-locations.sh.
+#![feature(no_core, lang_items, rustc_attrs)]
+#![no_core]
+#![no_std]
+#![no_main]
+#![allow(internal_features)]
+
+#[lang = "pointee_sized"]
+pub trait PointeeSized {}
+#[lang = "meta_sized"]
+pub trait MetaSized: PointeeSized {}
+#[lang = "sized"]
+pub trait Sized: MetaSized {}
+#[lang = "copy"]
+pub trait Copy {}
+impl Copy for u32 {}
+impl Copy for u64 {}
+#[lang = "add"]
+pub trait Add<Rhs = Self> {
+    type Output;
+    fn add(self, rhs: Rhs) -> Self::Output;
+}
+impl Add for u32 {
+    type Output = u32;
+    fn add(self, rhs: u32) -> u32 { self + rhs }
+}
+#[lang = "mul"]
+pub trait Mul<Rhs = Self> {
+    type Output;
+    fn mul(self, rhs: Rhs) -> Self::Output;
+}
+impl Mul for u32 {
+    type Output = u32;
+    fn mul(self, rhs: u32) -> u32 { self * rhs }
+}
+
+extern "C" {
+    fn sink(value: u32) -> u32;
+    fn touch(value: *mut u32);
+}
+
+// `kept` lives in the stack frame. `input` and `factor` are dead after the first call, the
+// registers they came in are described by DW_OP_entry_value. `offset` is in a register pair,
+// described by DW_OP_piece.
+#[inline(never)]
+#[no_mangle]
+pub extern "C" fn scale(input: u32, factor: u32, offset: u64) -> u32 {
+    let mut kept = input * factor;
+    unsafe { touch(&mut kept) };
+    let out = unsafe { sink(kept) };
+    out + offset as u32
+}
+
+#[no_mangle]
+pub extern "C" fn Reset() -> u32 {
+    scale(3, 5, 0x1_0000_0007)
+}